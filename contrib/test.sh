@@ -2,7 +2,14 @@
 
 set -e
 
-FEATURES="compiler use-serde rand"
+FEATURES="compiler use-serde rand satisfy-no-alloc bip39"
+
+# Features needing a more recent compiler than the MSRV: zeroize needs Rust
+# 1.51 and parallel Rust 1.63
+case "$TOOLCHAIN" in
+    1.41.1|1.47) ;;
+    *) FEATURES="$FEATURES parallel zeroize" ;;
+esac
 
 # Use toolchain if explicitly specified
 if [ -n "$TOOLCHAIN" ]
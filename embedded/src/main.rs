@@ -54,6 +54,27 @@ fn main() -> ! {
 
     // Estimate the satisfaction cost
     assert_eq!(desc.max_satisfaction_weight().unwrap(), 293);
+
+    // Satisfy the descriptor with a signature for its first key
+    let pk = miniscript::bitcoin::PublicKey::from_str(
+        "020e0338c96a8870479f2396c373cc7696ba124e8635d41b0ea581112b67817261",
+    )
+    .unwrap();
+    let sig = miniscript::bitcoin::EcdsaSig {
+        sig: miniscript::bitcoin::secp256k1::ecdsa::Signature::from_str(
+            "3045\
+             0221\
+             00f7c3648c390d87578cd79c8016940aa8e3511c4104cb78daa8fb8e429375efc1\
+             0220\
+             531d75c136272f127a5dc14acc0722301cbddc222262934151f140da345af177",
+        )
+        .unwrap(),
+        hash_ty: miniscript::bitcoin::EcdsaSighashType::All,
+    };
+    let mut txin = miniscript::bitcoin::TxIn::default();
+    desc.satisfy(&mut txin, SingleSig(pk, sig)).unwrap();
+    hprintln!("witness elements {}", txin.witness.len()).unwrap();
+    assert_eq!(txin.witness.len(), 2);
     // end miniscript test
 
     // exit QEMU
@@ -63,6 +84,22 @@ fn main() -> ! {
     loop {}
 }
 
+// A satisfier which knows a single signature
+struct SingleSig(miniscript::bitcoin::PublicKey, miniscript::bitcoin::EcdsaSig);
+
+impl miniscript::Satisfier<miniscript::bitcoin::PublicKey> for SingleSig {
+    fn lookup_ecdsa_sig(
+        &self,
+        pk: &miniscript::bitcoin::PublicKey,
+    ) -> Option<miniscript::bitcoin::EcdsaSig> {
+        if *pk == self.0 {
+            Some(self.1)
+        } else {
+            None
+        }
+    }
+}
+
 // define what happens in an Out Of Memory (OOM) condition
 #[alloc_error_handler]
 fn alloc_error(_layout: Layout) -> ! {
@@ -22,17 +22,21 @@ use core::fmt;
 use core::str::FromStr;
 
 use bitcoin::blockdata::script;
+use bitcoin::hashes::hex::{FromHex, ToHex};
+use bitcoin::util::address::{Payload, WitnessVersion};
 use bitcoin::{Address, Network, Script};
 
 use super::checksum::{desc_checksum, verify_checksum};
+use super::SatisfactionWeight;
 use crate::expression::{self, FromTree};
 use crate::miniscript::context::ScriptContext;
-use crate::policy::{semantic, Liftable};
+use crate::miniscript::limits::RuleSet;
+use crate::policy::{semantic, LiftError, Liftable};
 use crate::prelude::*;
-use crate::util::{varint_len, witness_to_scriptsig};
+use crate::util::witness_to_scriptsig;
 use crate::{
     BareCtx, Error, ForEach, ForEachKey, Miniscript, MiniscriptKey, Satisfier, ToPublicKey,
-    TranslatePk,
+    TranslatePk, Translator,
 };
 
 /// Create a Bare Descriptor. That is descriptor that is
@@ -61,9 +65,20 @@ impl<Pk: MiniscriptKey> Bare<Pk> {
         &self.ms
     }
 
+    /// Get the descriptor without the checksum
+    pub fn to_string_no_checksum(&self) -> String {
+        format!("{}", self.ms)
+    }
+
     /// Checks whether the descriptor is safe.
     pub fn sanity_check(&self) -> Result<(), Error> {
-        self.ms.sanity_check()?;
+        self.sanity_check_with(&RuleSet::Standard)
+    }
+
+    /// Checks whether the descriptor is safe as [`Self::sanity_check`] does,
+    /// checking its resource usage against `rules`
+    pub fn sanity_check_with(&self, rules: &RuleSet) -> Result<(), Error> {
+        self.ms.sanity_check_with(rules)?;
         Ok(())
     }
 
@@ -77,8 +92,16 @@ impl<Pk: MiniscriptKey> Bare<Pk> {
     /// # Errors
     /// When the descriptor is impossible to safisfy (ex: sh(OP_FALSE)).
     pub fn max_satisfaction_weight(&self) -> Result<usize, Error> {
-        let scriptsig_len = self.ms.max_satisfaction_size()?;
-        Ok(4 * (varint_len(scriptsig_len) + scriptsig_len))
+        Ok(self.satisfaction_weight()?.weight())
+    }
+
+    /// Computes an upper bound on the size of a satisfaction, see
+    /// [`Descriptor::satisfaction_weight`](crate::Descriptor::satisfaction_weight).
+    pub fn satisfaction_weight(&self) -> Result<SatisfactionWeight, Error> {
+        Ok(SatisfactionWeight {
+            script_sig_size: self.ms.max_satisfaction_size()?,
+            ..Default::default()
+        })
     }
 }
 
@@ -105,10 +128,7 @@ impl<Pk: MiniscriptKey + ToPublicKey> Bare<Pk> {
     where
         S: Satisfier<Pk>,
     {
-        let ms = self.ms.satisfy(satisfier)?;
-        let script_sig = witness_to_scriptsig(&ms);
-        let witness = vec![];
-        Ok((witness, script_sig))
+        self.satisfaction_with(satisfier, false, &RuleSet::Standard)
     }
 
     /// Returns satisfying, possibly malleable, witness and scriptSig with
@@ -118,7 +138,25 @@ impl<Pk: MiniscriptKey + ToPublicKey> Bare<Pk> {
     where
         S: Satisfier<Pk>,
     {
-        let ms = self.ms.satisfy_malleable(satisfier)?;
+        self.satisfaction_with(satisfier, true, &RuleSet::Standard)
+    }
+
+    // Helper function to get a satisfaction whose witness is checked against
+    // the limits of `rules`
+    pub(crate) fn satisfaction_with<S>(
+        &self,
+        satisfier: S,
+        allow_mall: bool,
+        rules: &RuleSet,
+    ) -> Result<(Vec<Vec<u8>>, Script), Error>
+    where
+        S: Satisfier<Pk>,
+    {
+        let ms = if allow_mall {
+            self.ms.satisfy_malleable_with_rules(satisfier, rules)?
+        } else {
+            self.ms.satisfy_with_rules(satisfier, rules)?
+        };
         let script_sig = witness_to_scriptsig(&ms);
         let witness = vec![];
         Ok((witness, script_sig))
@@ -133,7 +171,7 @@ impl<Pk: MiniscriptKey> fmt::Debug for Bare<Pk> {
 
 impl<Pk: MiniscriptKey> fmt::Display for Bare<Pk> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let desc = format!("{}", self.ms);
+        let desc = self.to_string_no_checksum();
         let checksum = desc_checksum(&desc).map_err(|_| fmt::Error)?;
         write!(f, "{}#{}", &desc, &checksum)
     }
@@ -149,8 +187,16 @@ impl<Pk> FromTree for Bare<Pk>
 where
     Pk: MiniscriptKey + FromStr,
     Pk::Hash: FromStr,
+    Pk::Sha256: FromStr,
+    Pk::Hash256: FromStr,
+    Pk::Ripemd160: FromStr,
+    Pk::Hash160: FromStr,
     <Pk as FromStr>::Err: ToString,
     <<Pk as MiniscriptKey>::Hash as FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Sha256 as FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Hash256 as FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Ripemd160 as FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Hash160 as FromStr>::Err: ToString,
 {
     fn from_tree(top: &expression::Tree) -> Result<Self, Error> {
         let sub = Miniscript::<Pk, BareCtx>::from_tree(top)?;
@@ -163,8 +209,16 @@ impl<Pk> FromStr for Bare<Pk>
 where
     Pk: MiniscriptKey + FromStr,
     Pk::Hash: FromStr,
+    Pk::Sha256: FromStr,
+    Pk::Hash256: FromStr,
+    Pk::Ripemd160: FromStr,
+    Pk::Hash160: FromStr,
     <Pk as FromStr>::Err: ToString,
     <<Pk as MiniscriptKey>::Hash as FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Sha256 as FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Hash256 as FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Ripemd160 as FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Hash160 as FromStr>::Err: ToString,
 {
     type Err = Error;
 
@@ -192,14 +246,11 @@ where
 {
     type Output = Bare<Q>;
 
-    fn translate_pk<Fpk, Fpkh, E>(&self, mut fpk: Fpk, mut fpkh: Fpkh) -> Result<Self::Output, E>
+    fn translate_pk<T, E>(&self, t: &mut T) -> Result<Self::Output, E>
     where
-        Fpk: FnMut(&P) -> Result<Q, E>,
-        Fpkh: FnMut(&P::Hash) -> Result<Q::Hash, E>,
-        Q: MiniscriptKey,
+        T: Translator<P, Q, E> + ?Sized,
     {
-        Ok(Bare::new(self.ms.translate_pk(&mut fpk, &mut fpkh)?)
-            .expect("Translation cannot fail inside Bare"))
+        Ok(Bare::new(self.ms.translate_pk(t)?).expect("Translation cannot fail inside Bare"))
     }
 }
 
@@ -227,6 +278,11 @@ impl<Pk: MiniscriptKey> Pkh<Pk> {
         self.pk
     }
 
+    /// Get the descriptor without the checksum
+    pub fn to_string_no_checksum(&self) -> String {
+        format!("pkh({})", self.pk)
+    }
+
     /// Computes an upper bound on the weight of a satisfying witness to the
     /// transaction.
     ///
@@ -234,7 +290,17 @@ impl<Pk: MiniscriptKey> Pkh<Pk> {
     /// sighash suffix. Includes the weight of the VarInts encoding the
     /// scriptSig and witness stack length.
     pub fn max_satisfaction_weight(&self) -> usize {
-        4 * (1 + 73 + BareCtx::pk_len(&self.pk))
+        self.satisfaction_weight().weight()
+    }
+
+    /// Computes an upper bound on the size of a satisfaction, see
+    /// [`Descriptor::satisfaction_weight`](crate::Descriptor::satisfaction_weight).
+    pub fn satisfaction_weight(&self) -> SatisfactionWeight {
+        SatisfactionWeight {
+            // signature, and the key with its push opcode
+            script_sig_size: 73 + 1 + BareCtx::pk_len(&self.pk),
+            ..Default::default()
+        }
     }
 }
 
@@ -301,7 +367,7 @@ impl<Pk: MiniscriptKey> fmt::Debug for Pkh<Pk> {
 
 impl<Pk: MiniscriptKey> fmt::Display for Pkh<Pk> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let desc = format!("pkh({})", self.pk);
+        let desc = self.to_string_no_checksum();
         let checksum = desc_checksum(&desc).map_err(|_| fmt::Error)?;
         write!(f, "{}#{}", &desc, &checksum)
     }
@@ -317,8 +383,16 @@ impl<Pk> FromTree for Pkh<Pk>
 where
     Pk: MiniscriptKey + FromStr,
     Pk::Hash: FromStr,
+    Pk::Sha256: FromStr,
+    Pk::Hash256: FromStr,
+    Pk::Ripemd160: FromStr,
+    Pk::Hash160: FromStr,
     <Pk as FromStr>::Err: ToString,
     <<Pk as MiniscriptKey>::Hash as FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Sha256 as FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Hash256 as FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Ripemd160 as FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Hash160 as FromStr>::Err: ToString,
 {
     fn from_tree(top: &expression::Tree) -> Result<Self, Error> {
         if top.name == "pkh" && top.args.len() == 1 {
@@ -339,8 +413,16 @@ impl<Pk> FromStr for Pkh<Pk>
 where
     Pk: MiniscriptKey + FromStr,
     Pk::Hash: FromStr,
+    Pk::Sha256: FromStr,
+    Pk::Hash256: FromStr,
+    Pk::Ripemd160: FromStr,
+    Pk::Hash160: FromStr,
     <Pk as FromStr>::Err: ToString,
     <<Pk as MiniscriptKey>::Hash as FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Sha256 as FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Hash256 as FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Ripemd160 as FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Hash160 as FromStr>::Err: ToString,
 {
     type Err = Error;
 
@@ -368,11 +450,328 @@ where
 {
     type Output = Pkh<Q>;
 
-    fn translate_pk<Fpk, Fpkh, E>(&self, mut fpk: Fpk, _fpkh: Fpkh) -> Result<Self::Output, E>
+    fn translate_pk<T, E>(&self, t: &mut T) -> Result<Self::Output, E>
     where
-        Fpk: FnMut(&P) -> Result<Q, E>,
-        Fpkh: FnMut(&P::Hash) -> Result<Q::Hash, E>,
+        T: Translator<P, Q, E> + ?Sized,
     {
-        Ok(Pkh::new(fpk(&self.pk)?))
+        Ok(Pkh::new(t.pk(&self.pk)?))
+    }
+}
+
+/// A raw script descriptor `raw(HEX)`, for outputs whose script pubkey is
+/// not a miniscript.
+///
+/// The spending conditions of the script are unknown, so it can be watched
+/// but not satisfied.
+#[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub struct Raw {
+    /// the script pubkey
+    script: Script,
+}
+
+impl Raw {
+    /// Create a new raw descriptor
+    pub fn new(script: Script) -> Self {
+        Self { script }
+    }
+
+    /// get the inner
+    pub fn into_inner(self) -> Script {
+        self.script
+    }
+
+    /// get the inner
+    pub fn as_inner(&self) -> &Script {
+        &self.script
+    }
+
+    /// Get the descriptor without the checksum
+    pub fn to_string_no_checksum(&self) -> String {
+        format!("raw({})", self.script.to_hex())
+    }
+
+    /// Obtains the corresponding script pubkey for this descriptor.
+    pub fn script_pubkey(&self) -> Script {
+        self.script.clone()
+    }
+
+    /// Obtains the address of the script pubkey, if it has a standard one.
+    pub fn address(&self, network: Network) -> Result<Address, Error> {
+        Address::from_script(&self.script, network).ok_or(Error::BareDescriptorAddr)
+    }
+}
+
+impl fmt::Debug for Raw {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "raw({})", self.script.to_hex())
+    }
+}
+
+impl fmt::Display for Raw {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let desc = self.to_string_no_checksum();
+        let checksum = desc_checksum(&desc).map_err(|_| fmt::Error)?;
+        write!(f, "{}#{}", &desc, &checksum)
+    }
+}
+
+impl<Pk: MiniscriptKey> Liftable<Pk> for Raw {
+    fn lift(&self) -> Result<semantic::Policy<Pk>, Error> {
+        Err(Error::LiftError(LiftError::UnknownScript))
+    }
+}
+
+impl FromTree for Raw {
+    fn from_tree(top: &expression::Tree) -> Result<Self, Error> {
+        if top.name == "raw" && top.args.len() == 1 {
+            let script = expression::terminal(&top.args[0], Vec::<u8>::from_hex)?;
+            Ok(Raw::new(Script::from(script)))
+        } else {
+            Err(Error::Unexpected(format!(
+                "{}({} args) while parsing raw descriptor",
+                top.name,
+                top.args.len(),
+            )))
+        }
+    }
+}
+
+impl FromStr for Raw {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let desc_str = verify_checksum(s)?;
+        let top = expression::Tree::from_str(desc_str)?;
+        Self::from_tree(&top)
+    }
+}
+
+/// An address descriptor `addr(ADDRESS)`, for outputs only known by their
+/// address.
+///
+/// As with [`Raw`], the spending conditions of the output are unknown, so it
+/// can be watched but not satisfied.
+#[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub struct Addr {
+    /// the address
+    address: Address,
+}
+
+impl Addr {
+    /// Create a new address descriptor
+    pub fn new(address: Address) -> Self {
+        Self { address }
+    }
+
+    /// get the inner
+    pub fn into_inner(self) -> Address {
+        self.address
+    }
+
+    /// get the inner
+    pub fn as_inner(&self) -> &Address {
+        &self.address
+    }
+
+    /// Get the descriptor without the checksum
+    pub fn to_string_no_checksum(&self) -> String {
+        format!("addr({})", self.address)
+    }
+
+    /// Obtains the corresponding script pubkey for this descriptor.
+    pub fn script_pubkey(&self) -> Script {
+        self.address.script_pubkey()
+    }
+
+    /// Obtains the address of the descriptor.
+    ///
+    /// # Errors
+    /// When the address is not valid on `network`: testnet addresses are
+    /// accepted for signet and, unless segwit, for regtest.
+    pub fn address(&self, network: Network) -> Result<Address, Error> {
+        if self.address.is_valid_for_network(network) {
+            Ok(self.address.clone())
+        } else {
+            Err(Error::AddrNetworkMismatch(self.address.network, network))
+        }
+    }
+}
+
+impl fmt::Debug for Addr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "addr({})", self.address)
+    }
+}
+
+impl fmt::Display for Addr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let desc = self.to_string_no_checksum();
+        let checksum = desc_checksum(&desc).map_err(|_| fmt::Error)?;
+        write!(f, "{}#{}", &desc, &checksum)
+    }
+}
+
+impl<Pk: MiniscriptKey> Liftable<Pk> for Addr {
+    fn lift(&self) -> Result<semantic::Policy<Pk>, Error> {
+        Err(Error::LiftError(LiftError::UnknownScript))
+    }
+}
+
+impl FromTree for Addr {
+    fn from_tree(top: &expression::Tree) -> Result<Self, Error> {
+        if top.name == "addr" && top.args.len() == 1 {
+            let address = expression::terminal(&top.args[0], Address::from_str)?;
+            Ok(Addr::new(address))
+        } else {
+            Err(Error::Unexpected(format!(
+                "{}({} args) while parsing addr descriptor",
+                top.name,
+                top.args.len(),
+            )))
+        }
+    }
+}
+
+impl FromStr for Addr {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let desc_str = verify_checksum(s)?;
+        let top = expression::Tree::from_str(desc_str)?;
+        Self::from_tree(&top)
+    }
+}
+
+/// A witness program descriptor `wv(VERSION,HEX)`, for segwit outputs of a
+/// version without a descriptor, e.g. the pay-to-anchor output `wv(1,4e73)`.
+///
+/// Only versions 2 to 16, and version 1 programs which are not taproot
+/// outputs, are accepted. As with [`Raw`], the outputs can be watched but
+/// not satisfied.
+#[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub struct Wv {
+    /// the witness version
+    version: WitnessVersion,
+    /// the witness program
+    program: Vec<u8>,
+}
+
+impl Wv {
+    /// Create a new witness program descriptor
+    ///
+    /// # Errors
+    /// When the version is 0, or 1 with a 32 byte program, or the program is
+    /// not 2 to 40 bytes long.
+    pub fn new(version: WitnessVersion, program: Vec<u8>) -> Result<Self, Error> {
+        if program.len() < 2 || program.len() > 40 {
+            return Err(Error::BadDescriptor(format!(
+                "witness program of {} bytes",
+                program.len()
+            )));
+        }
+        match version {
+            WitnessVersion::V0 => Err(Error::BadDescriptor(
+                "segwit v0 outputs must use wpkh or wsh".to_string(),
+            )),
+            WitnessVersion::V1 if program.len() == 32 => Err(Error::BadDescriptor(
+                "taproot outputs must use tr or rawtr".to_string(),
+            )),
+            _ => Ok(Self { version, program }),
+        }
+    }
+
+    /// Infer the descriptor of a witness program `script_pubkey`
+    ///
+    /// # Errors
+    /// When `script_pubkey` is not a witness program, or is one of the
+    /// programs rejected by [`Wv::new`].
+    pub fn from_script_pubkey(script_pubkey: &Script) -> Result<Self, Error> {
+        match Payload::from_script(script_pubkey) {
+            Some(Payload::WitnessProgram { version, program }) => Wv::new(version, program),
+            _ => Err(Error::BadDescriptor(
+                "script_pubkey is not a witness program".to_string(),
+            )),
+        }
+    }
+
+    /// Get the witness version
+    pub fn version(&self) -> WitnessVersion {
+        self.version
+    }
+
+    /// Get the witness program
+    pub fn program(&self) -> &[u8] {
+        &self.program
+    }
+
+    /// Get the descriptor without the checksum
+    pub fn to_string_no_checksum(&self) -> String {
+        format!("wv({},{})", self.version.into_num(), self.program.to_hex())
+    }
+
+    /// Obtains the corresponding script pubkey for this descriptor.
+    pub fn script_pubkey(&self) -> Script {
+        Script::new_witness_program(self.version, &self.program)
+    }
+
+    /// Obtains the address of the witness program.
+    pub fn address(&self, network: Network) -> Address {
+        Address {
+            payload: Payload::WitnessProgram {
+                version: self.version,
+                program: self.program.clone(),
+            },
+            network,
+        }
+    }
+}
+
+impl fmt::Debug for Wv {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.to_string_no_checksum())
+    }
+}
+
+impl fmt::Display for Wv {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let desc = self.to_string_no_checksum();
+        let checksum = desc_checksum(&desc).map_err(|_| fmt::Error)?;
+        write!(f, "{}#{}", &desc, &checksum)
+    }
+}
+
+impl<Pk: MiniscriptKey> Liftable<Pk> for Wv {
+    fn lift(&self) -> Result<semantic::Policy<Pk>, Error> {
+        Err(Error::LiftError(LiftError::UnknownScript))
+    }
+}
+
+impl FromTree for Wv {
+    fn from_tree(top: &expression::Tree) -> Result<Self, Error> {
+        if top.name == "wv" && top.args.len() == 2 {
+            let version = expression::terminal(&top.args[0], |v| {
+                u8::from_str(v)
+                    .map_err(|e| e.to_string())
+                    .and_then(|v| WitnessVersion::from_num(v).map_err(|e| e.to_string()))
+            })?;
+            let program = expression::terminal(&top.args[1], Vec::<u8>::from_hex)?;
+            Wv::new(version, program)
+        } else {
+            Err(Error::Unexpected(format!(
+                "{}({} args) while parsing wv descriptor",
+                top.name,
+                top.args.len(),
+            )))
+        }
+    }
+}
+
+impl FromStr for Wv {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let desc_str = verify_checksum(s)?;
+        let top = expression::Tree::from_str(desc_str)?;
+        Self::from_tree(&top)
     }
 }
@@ -0,0 +1,267 @@
+// Miniscript
+// Written in 2022 by rust-miniscript developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Descriptor Builder
+//!
+//! Programmatic construction of Miniscripts and descriptors from keys,
+//! timelocks and combinators, without going through string parsing or
+//! the policy compiler.
+//!
+
+use sync::Arc;
+
+use super::{Descriptor, TapTree};
+use crate::miniscript::context::{ScriptContext, SigType};
+use crate::miniscript::decode::Terminal;
+use crate::miniscript::types::{Base, Input};
+use crate::miniscript::{Segwitv0, Tap};
+use crate::prelude::*;
+use crate::{errstr, Error, Miniscript, MiniscriptKey};
+
+/// Builder for spending conditions which can be turned into a type-checked
+/// [`Miniscript`] or [`Descriptor`].
+///
+/// Conditions are built from [`key`](DescriptorBuilder::key),
+/// [`older`](DescriptorBuilder::older) and [`after`](DescriptorBuilder::after)
+/// and combined with [`and`](DescriptorBuilder::and),
+/// [`or`](DescriptorBuilder::or) and
+/// [`threshold`](DescriptorBuilder::threshold). Unlike the policy compiler, the
+/// builder does not look for the cheapest script: every combinator maps to a
+/// fixed fragment (`and_v`, `or_i`, `multi`/`multi_a` for thresholds of keys
+/// and `thresh` otherwise), wrapped as needed to typecheck.
+///
+/// ```
+/// use miniscript::bitcoin::PublicKey;
+/// use miniscript::descriptor::DescriptorBuilder;
+/// use std::str::FromStr;
+///
+/// let key = |s: &str| DescriptorBuilder::key(PublicKey::from_str(s).unwrap());
+/// // 2-of-3 multisig, or a recovery key after 1000 blocks
+/// let vault = DescriptorBuilder::threshold(
+///     2,
+///     vec![
+///         key("0231c7d3fc85c148717848033ce276ae2b464a4e2c367ed33886cc428b8af48ff8"),
+///         key("0250863ad64a87ae8a2fe83c1af1a8403cb53f53e486d8511dad8a04887e5b2352"),
+///         key("025476c2e83188368da1ff3e292e7acafcdb3566bb0ad253f62fc70f07aeee6357"),
+///     ],
+/// )
+/// .or(key("0283dfe85a3151d2517290da461fe2815591ef69f2b18a2ce63f01697a8b313145")
+///     .and(DescriptorBuilder::older(1000)));
+///
+/// let desc = vault.wsh().unwrap();
+/// assert!(desc.sanity_check().is_ok());
+/// ```
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Hash)]
+pub struct DescriptorBuilder<Pk: MiniscriptKey> {
+    node: Node<Pk>,
+}
+
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Hash)]
+enum Node<Pk: MiniscriptKey> {
+    Key(Pk),
+    After(u32),
+    Older(u32),
+    And(Box<Node<Pk>>, Box<Node<Pk>>),
+    Or(Box<Node<Pk>>, Box<Node<Pk>>),
+    Threshold(usize, Vec<Node<Pk>>),
+}
+
+impl<Pk: MiniscriptKey> DescriptorBuilder<Pk> {
+    /// A signature with the given key
+    pub fn key(pk: Pk) -> Self {
+        DescriptorBuilder {
+            node: Node::Key(pk),
+        }
+    }
+
+    /// An absolute locktime restriction
+    pub fn after(n: u32) -> Self {
+        DescriptorBuilder {
+            node: Node::After(n),
+        }
+    }
+
+    /// A relative locktime restriction
+    pub fn older(n: u32) -> Self {
+        DescriptorBuilder {
+            node: Node::Older(n),
+        }
+    }
+
+    /// `k` of the given conditions must be satisfied
+    pub fn threshold(k: usize, subs: Vec<DescriptorBuilder<Pk>>) -> Self {
+        DescriptorBuilder {
+            node: Node::Threshold(k, subs.into_iter().map(|sub| sub.node).collect()),
+        }
+    }
+
+    /// Both this and the `other` condition must be satisfied
+    pub fn and(self, other: DescriptorBuilder<Pk>) -> Self {
+        DescriptorBuilder {
+            node: Node::And(Box::new(self.node), Box::new(other.node)),
+        }
+    }
+
+    /// Either this or the `other` condition must be satisfied
+    pub fn or(self, other: DescriptorBuilder<Pk>) -> Self {
+        DescriptorBuilder {
+            node: Node::Or(Box::new(self.node), Box::new(other.node)),
+        }
+    }
+
+    /// Build the Miniscript for these conditions in the script context `Ctx`.
+    ///
+    /// The result is type-checked, but not checked for sanity; the descriptor
+    /// constructors below do that.
+    pub fn to_miniscript<Ctx: ScriptContext>(&self) -> Result<Miniscript<Pk, Ctx>, Error> {
+        self.node.to_miniscript()
+    }
+
+    /// Build a `wsh` descriptor for these conditions
+    pub fn wsh(&self) -> Result<Descriptor<Pk>, Error> {
+        Descriptor::new_wsh(self.to_miniscript::<Segwitv0>()?)
+    }
+
+    /// Build a `sh(wsh)` descriptor for these conditions
+    pub fn sh_wsh(&self) -> Result<Descriptor<Pk>, Error> {
+        Descriptor::new_sh_wsh(self.to_miniscript::<Segwitv0>()?)
+    }
+
+    /// Build a `tr` descriptor with the given internal key and these conditions
+    /// as its only script leaf
+    pub fn tr(&self, internal_key: Pk) -> Result<Descriptor<Pk>, Error> {
+        let leaf = TapTree::Leaf(Arc::new(self.to_miniscript::<Tap>()?));
+        Descriptor::new_tr(internal_key, Some(leaf))
+    }
+}
+
+impl<Pk: MiniscriptKey> Node<Pk> {
+    fn to_miniscript<Ctx: ScriptContext>(&self) -> Result<Miniscript<Pk, Ctx>, Error> {
+        match *self {
+            Node::Key(ref pk) => Miniscript::from_ast(Terminal::Check(Arc::new(
+                Miniscript::from_ast(Terminal::PkK(pk.clone()))?,
+            ))),
+            Node::After(n) => Miniscript::from_ast(Terminal::After(n)),
+            Node::Older(n) => Miniscript::from_ast(Terminal::Older(n)),
+            Node::And(ref left, ref right) => {
+                let left = Miniscript::from_ast(Terminal::Verify(Arc::new(left.to_miniscript()?)))?;
+                Miniscript::from_ast(Terminal::AndV(
+                    Arc::new(left),
+                    Arc::new(right.to_miniscript()?),
+                ))
+            }
+            Node::Or(ref left, ref right) => Miniscript::from_ast(Terminal::OrI(
+                Arc::new(left.to_miniscript()?),
+                Arc::new(right.to_miniscript()?),
+            )),
+            Node::Threshold(k, ref subs) => {
+                if k == 0 || k > subs.len() {
+                    return Err(errstr(
+                        "threshold k must be between 1 and the number of subs",
+                    ));
+                }
+                let keys: Vec<Pk> = subs
+                    .iter()
+                    .filter_map(|sub| match *sub {
+                        Node::Key(ref pk) => Some(pk.clone()),
+                        _ => None,
+                    })
+                    .collect();
+                if keys.len() == subs.len() {
+                    return Miniscript::from_ast(match Ctx::sig_type() {
+                        SigType::Ecdsa => Terminal::Multi(k, keys),
+                        SigType::Schnorr => Terminal::MultiA(k, keys),
+                    });
+                }
+
+                let mut ms_subs = Vec::with_capacity(subs.len());
+                for (i, sub) in subs.iter().enumerate() {
+                    let ms = dissatisfiable_unit(sub.to_miniscript()?)?;
+                    if i == 0 {
+                        ms_subs.push(Arc::new(ms));
+                    } else {
+                        ms_subs.push(Arc::new(Miniscript::from_ast(Terminal::Alt(Arc::new(ms)))?));
+                    }
+                }
+                Miniscript::from_ast(Terminal::Thresh(k, ms_subs))
+            }
+        }
+    }
+}
+
+// Wrap a `B` Miniscript so that it is dissatisfiable and leaves exactly one
+// element on the stack (`du`), as required for the subs of `thresh`.
+fn dissatisfiable_unit<Pk: MiniscriptKey, Ctx: ScriptContext>(
+    ms: Miniscript<Pk, Ctx>,
+) -> Result<Miniscript<Pk, Ctx>, Error> {
+    debug_assert!(ms.ty.corr.base == Base::B);
+    let ms = if ms.ty.corr.dissatisfiable {
+        ms
+    } else if ms.ty.corr.input == Input::Zero {
+        // d:v:X
+        let ms = Miniscript::from_ast(Terminal::Verify(Arc::new(ms)))?;
+        Miniscript::from_ast(Terminal::DupIf(Arc::new(ms)))?
+    } else {
+        // j:X
+        Miniscript::from_ast(Terminal::NonZero(Arc::new(ms)))?
+    };
+    if ms.ty.corr.unit {
+        Ok(ms)
+    } else {
+        // n:X
+        Miniscript::from_ast(Terminal::ZeroNotEqual(Arc::new(ms)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Builder = DescriptorBuilder<String>;
+
+    #[test]
+    fn build_miniscripts() {
+        let key = |s: &str| Builder::key(s.to_string());
+
+        let ms: Miniscript<String, Segwitv0> = Builder::threshold(2, vec![key("A"), key("B")])
+            .or(key("C").and(Builder::older(144)))
+            .to_miniscript()
+            .unwrap();
+        assert_eq!(
+            ms.to_string(),
+            "or_i(multi(2,A,B),and_v(v:pk(C),older(144)))"
+        );
+
+        let ms: Miniscript<String, Tap> = Builder::threshold(2, vec![key("A"), key("B")])
+            .to_miniscript()
+            .unwrap();
+        assert_eq!(ms.to_string(), "multi_a(2,A,B)");
+
+        // Non-key subs are wrapped to be dissatisfiable units
+        let ms: Miniscript<String, Segwitv0> = Builder::threshold(
+            2,
+            vec![key("A"), Builder::after(100), key("B").and(key("C"))],
+        )
+        .to_miniscript()
+        .unwrap();
+        assert_eq!(
+            ms.to_string(),
+            "thresh(2,pk(A),andv:after(100),aj:and_v(v:pk(B),pk(C)))"
+        );
+        assert!(ms.sanity_check().is_ok());
+
+        assert!(Builder::threshold(3, vec![key("A"), key("B")])
+            .wsh()
+            .is_err());
+    }
+}
@@ -1,7 +1,8 @@
 //! Descriptor checksum
 //!
 //! This module contains a re-implementation of the function used by Bitcoin Core to calculate the
-//! checksum of a descriptor
+//! checksum of a descriptor. The checksum is the `#xxxxxxxx` suffix that Bitcoin Core appends to
+//! descriptors it exports and requires on descriptors it imports.
 
 use core::iter::FromIterator;
 
@@ -79,7 +80,7 @@ pub fn desc_checksum(desc: &str) -> Result<String, Error> {
 /// descriptor types. Checks and verifies the checksum
 /// if it is present and returns the descriptor string
 /// without the checksum
-pub(super) fn verify_checksum(s: &str) -> Result<&str, Error> {
+pub fn verify_checksum(s: &str) -> Result<&str, Error> {
     for ch in s.as_bytes() {
         if *ch < 20 || *ch > 127 {
             return Err(Error::Unprintable(*ch));
@@ -99,6 +100,7 @@ pub(super) fn verify_checksum(s: &str) -> Result<&str, Error> {
     }
     Ok(desc_str)
 }
+
 #[cfg(test)]
 mod test {
     use core::str;
@@ -133,6 +135,24 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_verify_checksum() {
+        let desc = "pkh(tpubD6NzVbkrYhZ4XHndKkuB8FifXm8r5FQHwrN6oZuWCz13qb93rtgKvD4PQsqC4HP4yhV3tA2fqr2RbY5mNXfM7RxXUoeABoDtsFUq2zJq6YK/44'/1'/0'/0/*)";
+
+        // No checksum is fine and valid checksums are stripped
+        assert_eq!(verify_checksum(desc).unwrap(), desc);
+        assert_eq!(
+            verify_checksum(&format!("{}#lasegmfs", desc)).unwrap(),
+            desc
+        );
+        assert_eq!(
+            verify_checksum(&format!("{}#lasegmfq", desc))
+                .unwrap_err()
+                .to_string(),
+            "Invalid descriptor: Invalid checksum 'lasegmfq', expected 'lasegmfs'"
+        );
+    }
+
     #[test]
     fn test_desc_checksum_invalid_character() {
         let sparkle_heart = vec![240, 159, 146, 150];
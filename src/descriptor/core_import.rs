@@ -0,0 +1,255 @@
+// Miniscript
+// Written in 2022 by rust-miniscript developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Bitcoin Core descriptor imports
+//!
+//! Conversion of descriptors to and from the requests accepted by the
+//! `importdescriptors` RPC of Bitcoin Core. The RPC takes a JSON array of
+//! requests, which is what a `Vec<ImportDescriptor>` serializes to.
+//!
+
+use core::fmt;
+use core::str::FromStr;
+
+use serde::de::{self, Deserialize, Deserializer, MapAccess, SeqAccess, Visitor};
+use serde::ser::{self, Serialize, SerializeMap, Serializer};
+
+use super::{Descriptor, DescriptorPublicKey};
+use crate::prelude::*;
+
+/// The time from which Bitcoin Core rescans the chain for transactions of an
+/// imported descriptor
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Timestamp {
+    /// Do not rescan, the descriptor is new
+    Now,
+    /// Rescan from the given UNIX epoch time
+    Time(u64),
+}
+
+/// A single request of the `importdescriptors` RPC
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImportDescriptor {
+    /// The descriptor to import
+    pub descriptor: Descriptor<DescriptorPublicKey>,
+    /// Whether the descriptor is used to generate new addresses
+    pub active: bool,
+    /// The inclusive range of derivation indexes to import, for a descriptor
+    /// with wildcards
+    pub range: Option<(u32, u32)>,
+    /// The next derivation index to generate addresses from, for an active
+    /// descriptor with wildcards
+    pub next_index: Option<u32>,
+    /// Time from which to rescan the chain
+    pub timestamp: Timestamp,
+    /// Whether the descriptor is used for change outputs
+    pub internal: bool,
+    /// Label of the imported addresses. Core does not allow it on internal
+    /// descriptors, so such requests fail to serialize and deserialize.
+    pub label: Option<String>,
+}
+
+impl ImportDescriptor {
+    /// Create an inactive, non-internal import request for `descriptor`
+    pub fn new(descriptor: Descriptor<DescriptorPublicKey>, timestamp: Timestamp) -> Self {
+        ImportDescriptor {
+            descriptor,
+            active: false,
+            range: None,
+            next_index: None,
+            timestamp,
+            internal: false,
+            label: None,
+        }
+    }
+}
+
+impl Serialize for Timestamp {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match *self {
+            Timestamp::Now => serializer.serialize_str("now"),
+            Timestamp::Time(t) => serializer.serialize_u64(t),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Timestamp {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Timestamp, D::Error> {
+        struct TimestampVisitor;
+
+        impl<'de> Visitor<'de> for TimestampVisitor {
+            type Value = Timestamp;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a UNIX timestamp or \"now\"")
+            }
+
+            fn visit_u64<E: de::Error>(self, v: u64) -> Result<Timestamp, E> {
+                Ok(Timestamp::Time(v))
+            }
+
+            fn visit_i64<E: de::Error>(self, v: i64) -> Result<Timestamp, E> {
+                if v < 0 {
+                    return Err(E::invalid_value(de::Unexpected::Signed(v), &self));
+                }
+                Ok(Timestamp::Time(v as u64))
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Timestamp, E> {
+                if v == "now" {
+                    Ok(Timestamp::Now)
+                } else {
+                    Err(E::invalid_value(de::Unexpected::Str(v), &self))
+                }
+            }
+        }
+
+        deserializer.deserialize_any(TimestampVisitor)
+    }
+}
+
+// The range of an import, which Core accepts either as the end of the range or
+// as a `[begin, end]` pair
+struct Range((u32, u32));
+
+impl<'de> Deserialize<'de> for Range {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Range, D::Error> {
+        struct RangeVisitor;
+
+        impl<'de> Visitor<'de> for RangeVisitor {
+            type Value = Range;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a range end or a [begin, end] pair")
+            }
+
+            fn visit_u64<E: de::Error>(self, v: u64) -> Result<Range, E> {
+                if v > u32::max_value() as u64 {
+                    return Err(E::invalid_value(de::Unexpected::Unsigned(v), &self));
+                }
+                Ok(Range((0, v as u32)))
+            }
+
+            fn visit_i64<E: de::Error>(self, v: i64) -> Result<Range, E> {
+                if v < 0 || v > u32::max_value() as i64 {
+                    return Err(E::invalid_value(de::Unexpected::Signed(v), &self));
+                }
+                Ok(Range((0, v as u32)))
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Range, A::Error> {
+                let begin = seq
+                    .next_element()?
+                    .ok_or_else(|| de::Error::invalid_length(0, &self))?;
+                let end = seq
+                    .next_element()?
+                    .ok_or_else(|| de::Error::invalid_length(1, &self))?;
+                if seq.next_element::<de::IgnoredAny>()?.is_some() {
+                    return Err(de::Error::invalid_length(3, &self));
+                }
+                if begin > end {
+                    return Err(de::Error::custom("range begin is after its end"));
+                }
+                Ok(Range((begin, end)))
+            }
+        }
+
+        deserializer.deserialize_any(RangeVisitor)
+    }
+}
+
+impl Serialize for ImportDescriptor {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if self.internal && self.label.is_some() {
+            return Err(ser::Error::custom(
+                "internal descriptors cannot have a label",
+            ));
+        }
+        let mut map = serializer.serialize_map(None)?;
+        map.serialize_entry("desc", &self.descriptor.to_string())?;
+        map.serialize_entry("active", &self.active)?;
+        if let Some((begin, end)) = self.range {
+            map.serialize_entry("range", &[begin, end])?;
+        }
+        if let Some(next_index) = self.next_index {
+            map.serialize_entry("next_index", &next_index)?;
+        }
+        map.serialize_entry("timestamp", &self.timestamp)?;
+        map.serialize_entry("internal", &self.internal)?;
+        if let Some(ref label) = self.label {
+            map.serialize_entry("label", label)?;
+        }
+        map.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for ImportDescriptor {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<ImportDescriptor, D::Error> {
+        struct ImportVisitor;
+
+        impl<'de> Visitor<'de> for ImportVisitor {
+            type Value = ImportDescriptor;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("an importdescriptors request")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(
+                self,
+                mut map: A,
+            ) -> Result<ImportDescriptor, A::Error> {
+                let mut descriptor = None;
+                let mut active = None;
+                let mut range = None;
+                let mut next_index = None;
+                let mut timestamp = None;
+                let mut internal = None;
+                let mut label = None;
+                while let Some(key) = map.next_key::<String>()? {
+                    match key.as_str() {
+                        "desc" => {
+                            let desc: String = map.next_value()?;
+                            let desc = Descriptor::from_str(&desc).map_err(de::Error::custom)?;
+                            descriptor = Some(desc);
+                        }
+                        "active" => active = Some(map.next_value()?),
+                        "range" => range = Some(map.next_value::<Range>()?.0),
+                        "next_index" => next_index = Some(map.next_value()?),
+                        "timestamp" => timestamp = Some(map.next_value()?),
+                        "internal" => internal = Some(map.next_value()?),
+                        "label" => label = Some(map.next_value()?),
+                        _ => {
+                            map.next_value::<de::IgnoredAny>()?;
+                        }
+                    }
+                }
+                if internal == Some(true) && label.is_some() {
+                    return Err(de::Error::custom(
+                        "internal descriptors cannot have a label",
+                    ));
+                }
+                Ok(ImportDescriptor {
+                    descriptor: descriptor.ok_or_else(|| de::Error::missing_field("desc"))?,
+                    active: active.unwrap_or(false),
+                    range,
+                    next_index,
+                    timestamp: timestamp.ok_or_else(|| de::Error::missing_field("timestamp"))?,
+                    internal: internal.unwrap_or(false),
+                    label,
+                })
+            }
+        }
+
+        deserializer.deserialize_map(ImportVisitor)
+    }
+}
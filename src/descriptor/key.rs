@@ -1,16 +1,24 @@
-use core::fmt;
+#[cfg(feature = "zeroize")]
+use core::ptr;
 use core::str::FromStr;
+#[cfg(feature = "zeroize")]
+use core::sync::atomic;
+use core::{cmp, fmt, hash};
 #[cfg(feature = "std")]
 use std::error;
 
 use bitcoin::hashes::hex::FromHex;
-use bitcoin::hashes::{hash160, Hash, HashEngine};
-use bitcoin::secp256k1::{Secp256k1, Signing, Verification};
+use bitcoin::hashes::{hash160, ripemd160, sha256, Hash, HashEngine};
+use bitcoin::secp256k1::{self, Secp256k1, Signing, Verification};
 use bitcoin::util::bip32;
+use bitcoin::util::schnorr::{TapTweak, TweakedPublicKey};
+use bitcoin::util::taproot::TapBranchHash;
 use bitcoin::{self, XOnlyPublicKey, XpubIdentifier};
+#[cfg(feature = "zeroize")]
+use zeroize::{Zeroize, ZeroizeOnDrop};
 
 use crate::prelude::*;
-use crate::{MiniscriptKey, ToPublicKey};
+use crate::{hash256, MiniscriptKey, ToPublicKey};
 
 /// The descriptor pubkey, either a single pubkey or an xpub.
 #[derive(Debug, Eq, PartialEq, Clone, Ord, PartialOrd, Hash)]
@@ -22,7 +30,10 @@ pub enum DescriptorPublicKey {
 }
 
 /// The descriptor secret key, either a single private key or an xprv.
-#[derive(Debug)]
+///
+/// Equality, ordering and hashing compare the origin, derivation path and
+/// wildcard of the key, and the fields of its inner private key.
+#[derive(Debug, Clone)]
 pub enum DescriptorSecretKey {
     /// Single private key.
     Single(SinglePriv),
@@ -40,7 +51,7 @@ pub struct SinglePub {
 }
 
 /// A descriptor [`bitcoin::PrivateKey`] with optional origin information.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct SinglePriv {
     /// Origin information (fingerprint and derivation path).
     pub origin: Option<(bip32::Fingerprint, bip32::DerivationPath)>,
@@ -102,9 +113,83 @@ impl fmt::Display for DescriptorSecretKey {
     }
 }
 
+// The fields of a `DescriptorSecretKey`, in the order they are compared and
+// hashed, as `bitcoin::PrivateKey` and `bip32::ExtendedPrivKey` do not
+// implement `Ord` and `Hash`
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash)]
+enum SecretKeyFields<'a> {
+    Single {
+        origin: &'a Option<(bip32::Fingerprint, bip32::DerivationPath)>,
+        key: (bool, bitcoin::Network, &'a secp256k1::SecretKey),
+    },
+    XPrv {
+        origin: &'a Option<(bip32::Fingerprint, bip32::DerivationPath)>,
+        xkey: (
+            bitcoin::Network,
+            u8,
+            bip32::Fingerprint,
+            bip32::ChildNumber,
+            bip32::ChainCode,
+            &'a secp256k1::SecretKey,
+        ),
+        derivation_path: &'a bip32::DerivationPath,
+        wildcard: Wildcard,
+    },
+}
+
+impl DescriptorSecretKey {
+    fn fields(&self) -> SecretKeyFields {
+        match *self {
+            DescriptorSecretKey::Single(ref single) => SecretKeyFields::Single {
+                origin: &single.origin,
+                key: (single.key.compressed, single.key.network, &single.key.inner),
+            },
+            DescriptorSecretKey::XPrv(ref xprv) => SecretKeyFields::XPrv {
+                origin: &xprv.origin,
+                xkey: (
+                    xprv.xkey.network,
+                    xprv.xkey.depth,
+                    xprv.xkey.parent_fingerprint,
+                    xprv.xkey.child_number,
+                    xprv.xkey.chain_code,
+                    &xprv.xkey.private_key,
+                ),
+                derivation_path: &xprv.derivation_path,
+                wildcard: xprv.wildcard,
+            },
+        }
+    }
+}
+
+impl PartialEq for DescriptorSecretKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.fields() == other.fields()
+    }
+}
+
+impl Eq for DescriptorSecretKey {}
+
+impl PartialOrd for DescriptorSecretKey {
+    fn partial_cmp(&self, other: &Self) -> Option<cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for DescriptorSecretKey {
+    fn cmp(&self, other: &Self) -> cmp::Ordering {
+        self.fields().cmp(&other.fields())
+    }
+}
+
+impl hash::Hash for DescriptorSecretKey {
+    fn hash<H: hash::Hasher>(&self, state: &mut H) {
+        self.fields().hash(state)
+    }
+}
+
 /// Trait for "extended key" types like `xpub` and `xprv`. Used internally to generalize parsing and
 /// handling of `bip32::ExtendedPubKey` and `bip32::ExtendedPrivKey`.
-pub trait InnerXKey: fmt::Display + FromStr {
+pub trait InnerXKey: fmt::Display + FromStr<Err = bip32::Error> {
     /// Returns the fingerprint of the key
     fn xkey_fingerprint<C: Signing>(&self, secp: &Secp256k1<C>) -> bip32::Fingerprint;
 
@@ -112,6 +197,11 @@ pub trait InnerXKey: fmt::Display + FromStr {
     ///
     /// `true` for `bip32::ExtendedPrivKey` and `false` for `bip32::ExtendedPubKey`.
     fn can_derive_hardened() -> bool;
+
+    /// Overwrites the secret parts of the key, if any, which is done when a
+    /// [`DescriptorXKey`] is dropped
+    #[cfg(feature = "zeroize")]
+    fn erase(&mut self) {}
 }
 
 impl InnerXKey for bip32::ExtendedPubKey {
@@ -132,6 +222,11 @@ impl InnerXKey for bip32::ExtendedPrivKey {
     fn can_derive_hardened() -> bool {
         true
     }
+
+    #[cfg(feature = "zeroize")]
+    fn erase(&mut self) {
+        erase_xprv(self);
+    }
 }
 
 /// Whether a descriptor has a wildcard in it
@@ -179,11 +274,14 @@ impl DescriptorXKey<bip32::ExtendedPrivKey> {
         let hardened_path = &self.derivation_path[..last_hardened_idx];
         let unhardened_path = &self.derivation_path[last_hardened_idx..];
 
-        let xprv = self
+        #[allow(unused_mut)]
+        let mut xprv = self
             .xkey
             .derive_priv(secp, &hardened_path)
-            .map_err(|_| DescriptorKeyParseError("Unable to derive the hardened steps"))?;
+            .map_err(DescriptorKeyParseError::HardenedDerivation)?;
         let xpub = bip32::ExtendedPubKey::from_priv(secp, &xprv);
+        #[cfg(feature = "zeroize")]
+        erase_xprv(&mut xprv);
 
         let origin = match &self.origin {
             Some((fingerprint, path)) => Some((
@@ -211,21 +309,320 @@ impl DescriptorXKey<bip32::ExtendedPrivKey> {
     }
 }
 
+// The bytes of secp256k1 secret keys are private, so the key is overwritten
+// with a constant key rather than zeroed. The write is volatile so that it is
+// not optimized away as a dead store.
+#[cfg(feature = "zeroize")]
+#[allow(unsafe_code)]
+fn erase_secret_key(key: &mut secp256k1::SecretKey) {
+    // SAFETY: the pointer comes from a mutable reference, so it is valid and
+    // aligned, and the overwritten `SecretKey` has no drop glue
+    unsafe { ptr::write_volatile(key, secp256k1::ONE_KEY) };
+    atomic::compiler_fence(atomic::Ordering::SeqCst);
+}
+
+#[cfg(feature = "zeroize")]
+#[allow(unsafe_code)]
+pub(super) fn erase_xprv(xprv: &mut bip32::ExtendedPrivKey) {
+    erase_secret_key(&mut xprv.private_key);
+    let chain_code = bip32::ChainCode::from(&[0; 32][..]);
+    // SAFETY: as in `erase_secret_key`, for a `ChainCode`
+    unsafe { ptr::write_volatile(&mut xprv.chain_code, chain_code) };
+    atomic::compiler_fence(atomic::Ordering::SeqCst);
+}
+
+/// Overwrites the private key. The origin is kept.
+#[cfg(feature = "zeroize")]
+impl Zeroize for SinglePriv {
+    fn zeroize(&mut self) {
+        erase_secret_key(&mut self.key.inner);
+    }
+}
+
+#[cfg(feature = "zeroize")]
+impl Drop for SinglePriv {
+    fn drop(&mut self) {
+        self.zeroize();
+    }
+}
+
+#[cfg(feature = "zeroize")]
+impl ZeroizeOnDrop for SinglePriv {}
+
+/// Overwrites the private key and chain code of the xprv. The origin,
+/// derivation path and the public metadata of the xprv are kept.
+#[cfg(feature = "zeroize")]
+impl Zeroize for DescriptorXKey<bip32::ExtendedPrivKey> {
+    fn zeroize(&mut self) {
+        erase_xprv(&mut self.xkey);
+    }
+}
+
+/// Extended keys are also used as public keys, so the drop is implemented for
+/// both and only does something for xprvs, see [`InnerXKey::erase`]. With the
+/// `zeroize` feature, fields can thus not be moved out of any `DescriptorXKey`.
+#[cfg(feature = "zeroize")]
+impl<K: InnerXKey> Drop for DescriptorXKey<K> {
+    fn drop(&mut self) {
+        self.xkey.erase();
+    }
+}
+
+#[cfg(feature = "zeroize")]
+impl ZeroizeOnDrop for DescriptorXKey<bip32::ExtendedPrivKey> {}
+
+#[cfg(feature = "zeroize")]
+impl Zeroize for DescriptorSecretKey {
+    fn zeroize(&mut self) {
+        match *self {
+            DescriptorSecretKey::Single(ref mut single) => single.zeroize(),
+            DescriptorSecretKey::XPrv(ref mut xprv) => xprv.zeroize(),
+        }
+    }
+}
+
+// Both kinds of keys scrub themselves when dropped
+#[cfg(feature = "zeroize")]
+impl ZeroizeOnDrop for DescriptorSecretKey {}
+
 /// Descriptor Key parsing errors
-// FIXME: replace with error enums
-#[derive(Debug, PartialEq, Clone, Copy)]
-pub struct DescriptorKeyParseError(&'static str);
+///
+/// Positions are byte offsets into the parsed key string. Errors reported by
+/// [`Descriptor::parse_descriptor`](crate::Descriptor::parse_descriptor), in
+/// [`Error::BadDescriptorKey`](crate::Error::BadDescriptorKey), have their
+/// positions offset to be relative to the descriptor string instead.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum DescriptorKeyParseError {
+    /// The key is empty
+    EmptyKey,
+    /// The key is too short to match any key format
+    KeyTooShort,
+    /// An unprintable character was found
+    UnprintableCharacter {
+        /// Position of the character
+        position: usize,
+    },
+    /// The key origin opened with `[` is not closed
+    UnclosedOrigin,
+    /// More than one `]` closing the key origin
+    MultipleOriginClosures {
+        /// Position of the second `]`
+        position: usize,
+    },
+    /// The master fingerprint of the key origin is not 8 hex characters
+    BadFingerprint {
+        /// Position of the fingerprint
+        position: usize,
+    },
+    /// A step of a derivation path is not a valid child number
+    BadDerivationPath {
+        /// Position of the invalid step
+        position: usize,
+        /// The child number parsing error
+        source: bip32::Error,
+    },
+    /// A wildcard is followed by further derivation steps
+    WildcardNotAtEnd {
+        /// Position of the wildcard
+        position: usize,
+    },
+    /// No key follows the key origin
+    MissingKey,
+    /// The extended key could not be parsed
+    UnparsableXKey {
+        /// Position of the extended key
+        position: usize,
+        /// The extended key parsing error
+        source: bip32::Error,
+    },
+    /// A single public key does not start with `02`, `03` or `04`
+    BadPubKeyPrefix {
+        /// Position of the public key
+        position: usize,
+    },
+    /// A single public key is not 64, 66 or 130 characters long
+    BadPubKeyLength {
+        /// Position of the public key
+        position: usize,
+        /// Length of the public key, in characters
+        length: usize,
+    },
+    /// A single public key could not be parsed
+    UnparsablePubKey {
+        /// Position of the public key
+        position: usize,
+        /// The public key parsing error
+        source: bitcoin::util::key::Error,
+    },
+    /// A single x-only public key could not be parsed
+    UnparsableXOnlyKey {
+        /// Position of the public key
+        position: usize,
+        /// The x-only public key parsing error
+        source: bitcoin::secp256k1::Error,
+    },
+    /// A WIF private key could not be parsed
+    UnparsableWif {
+        /// Position of the private key
+        position: usize,
+        /// The private key parsing error
+        source: bitcoin::util::key::Error,
+    },
+    /// The hardened derivation steps of an extended private key could not be
+    /// applied
+    HardenedDerivation(bip32::Error),
+}
 
 impl fmt::Display for DescriptorKeyParseError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        f.write_str(self.0)
+        match *self {
+            DescriptorKeyParseError::EmptyKey => f.write_str("empty key"),
+            DescriptorKeyParseError::KeyTooShort => {
+                f.write_str("key too short (<66 char), doesn't match any format")
+            }
+            DescriptorKeyParseError::UnprintableCharacter { position } => {
+                write!(f, "unprintable character at position {}", position)
+            }
+            DescriptorKeyParseError::UnclosedOrigin => f.write_str("unclosed '['"),
+            DescriptorKeyParseError::MultipleOriginClosures { position } => {
+                write!(
+                    f,
+                    "multiple ']' in key, the second at position {}",
+                    position
+                )
+            }
+            DescriptorKeyParseError::BadFingerprint { position } => write!(
+                f,
+                "malformed master fingerprint at position {}, expected 8 hex chars",
+                position
+            ),
+            DescriptorKeyParseError::BadDerivationPath {
+                position,
+                ref source,
+            } => write!(
+                f,
+                "invalid derivation step at position {}: {}",
+                position, source
+            ),
+            DescriptorKeyParseError::WildcardNotAtEnd { position } => write!(
+                f,
+                "'*' at position {} may only appear as last element in a derivation path",
+                position
+            ),
+            DescriptorKeyParseError::MissingKey => f.write_str("no key after origin"),
+            DescriptorKeyParseError::UnparsableXKey {
+                position,
+                ref source,
+            } => write!(f, "invalid xkey at position {}: {}", position, source),
+            DescriptorKeyParseError::BadPubKeyPrefix { position } => write!(
+                f,
+                "public key at position {} does not have a 02/03/04 prefix",
+                position
+            ),
+            DescriptorKeyParseError::BadPubKeyLength { position, length } => write!(
+                f,
+                "public key at position {} is {} characters, expected 64/66/130",
+                position, length
+            ),
+            DescriptorKeyParseError::UnparsablePubKey {
+                position,
+                ref source,
+            } => write!(f, "invalid public key at position {}: {}", position, source),
+            DescriptorKeyParseError::UnparsableXOnlyKey {
+                position,
+                ref source,
+            } => write!(
+                f,
+                "invalid x-only public key at position {}: {}",
+                position, source
+            ),
+            DescriptorKeyParseError::UnparsableWif {
+                position,
+                ref source,
+            } => write!(
+                f,
+                "invalid WIF private key at position {}: {}",
+                position, source
+            ),
+            DescriptorKeyParseError::HardenedDerivation(ref e) => {
+                write!(f, "unable to derive the hardened steps: {}", e)
+            }
+        }
+    }
+}
+
+impl DescriptorKeyParseError {
+    // Shift the positions of the error by `offset`, the position of the key
+    // in a descriptor string
+    pub(crate) fn offset_by(self, offset: usize) -> Self {
+        use self::DescriptorKeyParseError::*;
+
+        match self {
+            UnprintableCharacter { position } => UnprintableCharacter {
+                position: position + offset,
+            },
+            MultipleOriginClosures { position } => MultipleOriginClosures {
+                position: position + offset,
+            },
+            BadFingerprint { position } => BadFingerprint {
+                position: position + offset,
+            },
+            BadDerivationPath { position, source } => BadDerivationPath {
+                position: position + offset,
+                source,
+            },
+            WildcardNotAtEnd { position } => WildcardNotAtEnd {
+                position: position + offset,
+            },
+            UnparsableXKey { position, source } => UnparsableXKey {
+                position: position + offset,
+                source,
+            },
+            BadPubKeyPrefix { position } => BadPubKeyPrefix {
+                position: position + offset,
+            },
+            BadPubKeyLength { position, length } => BadPubKeyLength {
+                position: position + offset,
+                length,
+            },
+            UnparsablePubKey { position, source } => UnparsablePubKey {
+                position: position + offset,
+                source,
+            },
+            UnparsableXOnlyKey { position, source } => UnparsableXOnlyKey {
+                position: position + offset,
+                source,
+            },
+            UnparsableWif { position, source } => UnparsableWif {
+                position: position + offset,
+                source,
+            },
+            EmptyKey | KeyTooShort | UnclosedOrigin | MissingKey | HardenedDerivation(_) => self,
+        }
     }
 }
 
 #[cfg(feature = "std")]
 impl error::Error for DescriptorKeyParseError {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
-        None
+        use self::DescriptorKeyParseError::*;
+
+        match self {
+            EmptyKey
+            | KeyTooShort
+            | UnprintableCharacter { .. }
+            | UnclosedOrigin
+            | MultipleOriginClosures { .. }
+            | BadFingerprint { .. }
+            | WildcardNotAtEnd { .. }
+            | MissingKey
+            | BadPubKeyPrefix { .. }
+            | BadPubKeyLength { .. } => None,
+            BadDerivationPath { source, .. } | UnparsableXKey { source, .. } => Some(source),
+            UnparsablePubKey { source, .. } | UnparsableWif { source, .. } => Some(source),
+            UnparsableXOnlyKey { source, .. } => Some(source),
+            HardenedDerivation(e) => Some(e),
+        }
     }
 }
 
@@ -271,6 +668,85 @@ impl DescriptorSecretKey {
 
         Ok(pk)
     }
+
+    /// The fingerprint of the master key associated with this key, computed
+    /// in the same way as [`DescriptorPublicKey::master_fingerprint`].
+    pub fn master_fingerprint<C: Signing>(&self, secp: &Secp256k1<C>) -> bip32::Fingerprint {
+        match *self {
+            DescriptorSecretKey::XPrv(ref xprv) => {
+                if let Some((fingerprint, _)) = xprv.origin {
+                    fingerprint
+                } else {
+                    xprv.xkey.fingerprint(secp)
+                }
+            }
+            DescriptorSecretKey::Single(ref single) => {
+                DescriptorPublicKey::Single(single.to_public(secp)).master_fingerprint()
+            }
+        }
+    }
+
+    /// Full path, from the master key
+    ///
+    /// For wildcard keys this will return the path up to the wildcard, so you
+    /// can get full paths by appending one additional derivation step, according
+    /// to the wildcard type (hardened or normal)
+    pub fn full_derivation_path(&self) -> bip32::DerivationPath {
+        match *self {
+            DescriptorSecretKey::XPrv(ref xprv) => {
+                let origin_path = if let Some((_, ref path)) = xprv.origin {
+                    path.clone()
+                } else {
+                    bip32::DerivationPath::from(vec![])
+                };
+                origin_path.extend(&xprv.derivation_path)
+            }
+            DescriptorSecretKey::Single(ref single) => {
+                if let Some((_, ref path)) = single.origin {
+                    path.clone()
+                } else {
+                    bip32::DerivationPath::from(vec![])
+                }
+            }
+        }
+    }
+
+    /// Whether or not the key has a wildcards
+    pub fn is_deriveable(&self) -> bool {
+        match *self {
+            DescriptorSecretKey::Single(..) => false,
+            DescriptorSecretKey::XPrv(ref xprv) => xprv.wildcard != Wildcard::None,
+        }
+    }
+
+    /// Derives the [`DescriptorSecretKey`] at `index` if this key is an xprv and has a wildcard.
+    ///
+    /// # Returns
+    ///
+    /// - If this key is not an xprv, returns `self`.
+    /// - If this key is an xprv but does not have a wildcard, returns `self`.
+    /// - Otherwise, returns the xprv with the wildcard replaced by `index`, which
+    ///   is a hardened step for hardened wildcards.
+    ///
+    /// # Panics
+    ///
+    /// If `index` ≥ 2^31
+    pub fn derive(mut self, index: u32) -> DescriptorSecretKey {
+        // The xprv is updated in place rather than moved into a new key, so
+        // that no copy of it is left behind
+        if let DescriptorSecretKey::XPrv(ref mut xprv) = self {
+            let child = match xprv.wildcard {
+                Wildcard::None => None,
+                Wildcard::Unhardened => Some(bip32::ChildNumber::from_normal_idx(index).unwrap()),
+                Wildcard::Hardened => Some(bip32::ChildNumber::from_hardened_idx(index).unwrap()),
+            };
+            if let Some(child) = child {
+                xprv.derivation_path = xprv.derivation_path.child(child);
+            }
+            xprv.wildcard = Wildcard::None;
+        }
+        self
+    }
 }
 
 /// Writes the fingerprint of the origin, if there is one.
@@ -304,16 +780,15 @@ impl FromStr for DescriptorPublicKey {
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         // A "raw" public key without any origin is the least we accept.
         if s.len() < 64 {
-            return Err(DescriptorKeyParseError(
-                "Key too short (<66 char), doesn't match any format",
-            ));
+            return Err(DescriptorKeyParseError::KeyTooShort);
         }
 
         let (key_part, origin) = DescriptorXKey::<bip32::ExtendedPubKey>::parse_xkey_origin(s)?;
+        let position = s.len() - key_part.len();
 
         if key_part.contains("pub") {
             let (xpub, derivation_path, wildcard) =
-                DescriptorXKey::<bip32::ExtendedPubKey>::parse_xkey_deriv(key_part)?;
+                DescriptorXKey::<bip32::ExtendedPubKey>::parse_xkey_deriv(key_part, position)?;
 
             Ok(DescriptorPublicKey::XPub(DescriptorXKey {
                 origin,
@@ -324,8 +799,8 @@ impl FromStr for DescriptorPublicKey {
         } else {
             let key = match key_part.len() {
                 64 => {
-                    let x_only_key = XOnlyPublicKey::from_str(key_part).map_err(|_| {
-                        DescriptorKeyParseError("Error while parsing simple xonly key")
+                    let x_only_key = XOnlyPublicKey::from_str(key_part).map_err(|source| {
+                        DescriptorKeyParseError::UnparsableXOnlyKey { position, source }
                     })?;
                     SinglePubKey::XOnly(x_only_key)
                 }
@@ -334,19 +809,15 @@ impl FromStr for DescriptorPublicKey {
                         || &key_part[0..2] == "03"
                         || &key_part[0..2] == "04")
                     {
-                        return Err(DescriptorKeyParseError(
-                            "Only publickeys with prefixes 02/03/04 are allowed",
-                        ));
+                        return Err(DescriptorKeyParseError::BadPubKeyPrefix { position });
                     }
-                    let key = bitcoin::PublicKey::from_str(key_part).map_err(|_| {
-                        DescriptorKeyParseError("Error while parsing simple public key")
+                    let key = bitcoin::PublicKey::from_str(key_part).map_err(|source| {
+                        DescriptorKeyParseError::UnparsablePubKey { position, source }
                     })?;
                     SinglePubKey::FullKey(key)
                 }
-                _ => {
-                    return Err(DescriptorKeyParseError(
-                        "Public keys must be 64/66/130 characters in size",
-                    ))
+                length => {
+                    return Err(DescriptorKeyParseError::BadPubKeyLength { position, length })
                 }
             };
             Ok(DescriptorPublicKey::Single(SinglePub { key, origin }))
@@ -354,6 +825,8 @@ impl FromStr for DescriptorPublicKey {
     }
 }
 
+serde_string_impl_pk!(nopk DescriptorPublicKey, "a descriptor public key");
+
 /// Descriptor key conversion error
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
 pub enum ConversionError {
@@ -449,6 +922,84 @@ impl DescriptorPublicKey {
         }
     }
 
+    /// Moves the derivation path of an xpub into its origin, by deriving the
+    /// xpub along the path.
+    ///
+    /// The returned key has the same master fingerprint and full derivation
+    /// path, and derives the same keys, as `self`. Keys whose derivation path
+    /// is empty or contains hardened steps are returned unchanged.
+    pub(super) fn derive_path<C: Verification>(self, secp: &Secp256k1<C>) -> Self {
+        let xpub = match self {
+            DescriptorPublicKey::XPub(ref xpub)
+                if !xpub.derivation_path.is_master()
+                    && xpub.derivation_path.as_ref().iter().all(|c| c.is_normal()) =>
+            {
+                xpub
+            }
+            _ => return self,
+        };
+        let xkey = xpub
+            .xkey
+            .derive_pub(secp, &xpub.derivation_path)
+            .expect("path without hardened steps is derivable");
+        let origin = match xpub.origin {
+            Some((fingerprint, ref path)) => (fingerprint, path.extend(&xpub.derivation_path)),
+            None => (xpub.xkey.fingerprint(), xpub.derivation_path.clone()),
+        };
+        DescriptorPublicKey::XPub(DescriptorXKey {
+            origin: Some(origin),
+            xkey,
+            derivation_path: bip32::DerivationPath::master(),
+            wildcard: xpub.wildcard,
+        })
+    }
+
+    /// The simplest key deriving the same public keys as `self`, after
+    /// deriving its wildcard at `index` if given.
+    ///
+    /// The returned key has no origin, and xpubs have their derivation path
+    /// moved into the key and the metadata which does not change the derived
+    /// keys (network, depth, parent fingerprint and child number) reset. An
+    /// xpub which ends up with neither a derivation path nor a wildcard is
+    /// replaced by its public key.
+    pub(super) fn normalized<C: Verification>(
+        self,
+        secp: &Secp256k1<C>,
+        index: Option<u32>,
+    ) -> Self {
+        let key = match index {
+            Some(index) => self.derive(index).key,
+            None => self,
+        };
+        match key.derive_path(secp) {
+            DescriptorPublicKey::Single(single) => DescriptorPublicKey::Single(SinglePub {
+                origin: None,
+                key: single.key,
+            }),
+            DescriptorPublicKey::XPub(ref xpub)
+                if xpub.wildcard == Wildcard::None && xpub.derivation_path.is_master() =>
+            {
+                DescriptorPublicKey::Single(SinglePub {
+                    origin: None,
+                    key: SinglePubKey::FullKey(bitcoin::PublicKey::new(xpub.xkey.public_key)),
+                })
+            }
+            DescriptorPublicKey::XPub(xpub) => DescriptorPublicKey::XPub(DescriptorXKey {
+                origin: None,
+                xkey: bip32::ExtendedPubKey {
+                    network: bitcoin::Network::Bitcoin,
+                    depth: 0,
+                    parent_fingerprint: Default::default(),
+                    child_number: bip32::ChildNumber::from_normal_idx(0).expect("0 is normal"),
+                    public_key: xpub.xkey.public_key,
+                    chain_code: xpub.xkey.chain_code,
+                },
+                derivation_path: xpub.derivation_path.clone(),
+                wildcard: xpub.wildcard,
+            }),
+        }
+    }
+
     /// Derives the [`DescriptorPublicKey`] at `index` if this key is an xpub and has a wildcard.
     ///
     /// # Returns
@@ -468,16 +1019,16 @@ impl DescriptorPublicKey {
             DescriptorPublicKey::Single(_) => self,
             DescriptorPublicKey::XPub(xpub) => {
                 let derivation_path = match xpub.wildcard {
-                    Wildcard::None => xpub.derivation_path,
+                    Wildcard::None => xpub.derivation_path.clone(),
                     Wildcard::Unhardened => xpub
                         .derivation_path
-                        .into_child(bip32::ChildNumber::from_normal_idx(index).unwrap()),
+                        .child(bip32::ChildNumber::from_normal_idx(index).unwrap()),
                     Wildcard::Hardened => xpub
                         .derivation_path
-                        .into_child(bip32::ChildNumber::from_hardened_idx(index).unwrap()),
+                        .child(bip32::ChildNumber::from_hardened_idx(index).unwrap()),
                 };
                 DescriptorPublicKey::XPub(DescriptorXKey {
-                    origin: xpub.origin,
+                    origin: xpub.origin.clone(),
                     xkey: xpub.xkey,
                     derivation_path,
                     wildcard: Wildcard::None,
@@ -499,8 +1050,8 @@ impl DescriptorPublicKey {
     ///
     /// To ensure there are no wildcards, call `.derive(0)` or similar;
     /// to avoid hardened derivation steps, start from a `DescriptorSecretKey`
-    /// and call `to_public`, or call `TranslatePk2::translate_pk2` with
-    /// some function which has access to secret key data.
+    /// and call `to_public`, or translate the keys with a
+    /// [`Translator`](crate::Translator) which has access to secret key data.
     pub fn derive_public_key<C: Verification>(
         &self,
         secp: &Secp256k1<C>,
@@ -523,6 +1074,49 @@ impl DescriptorPublicKey {
             },
         }
     }
+
+    /// Computes the x-only public key corresponding to this descriptor key,
+    /// along with the parity of its y-coordinate.
+    ///
+    /// For full keys and BIP32 derivations the parity is that of the actual
+    /// derived key. A raw x-only key carries no parity, so as in BIP340 it is
+    /// taken to have an even y-coordinate.
+    ///
+    /// Errors in the same cases as [`DescriptorPublicKey::derive_public_key`].
+    pub fn to_x_only_with_parity<C: Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+    ) -> Result<(XOnlyPublicKey, secp256k1::Parity), ConversionError> {
+        if let DescriptorPublicKey::Single(SinglePub {
+            key: SinglePubKey::XOnly(xpk),
+            ..
+        }) = *self
+        {
+            return Ok((xpk, secp256k1::Parity::Even));
+        }
+        let pk = self.derive_public_key(secp)?;
+        let parity = if pk.inner.serialize()[0] == 0x03 {
+            secp256k1::Parity::Odd
+        } else {
+            secp256k1::Parity::Even
+        };
+        Ok((XOnlyPublicKey::from(pk.inner), parity))
+    }
+
+    /// Computes the taproot output key committing to this descriptor key as
+    /// the internal key and to the script tree with root `merkle_root`, along
+    /// with the parity of the output key needed for script spend control
+    /// blocks.
+    ///
+    /// Errors in the same cases as [`DescriptorPublicKey::derive_public_key`].
+    pub fn to_tap_output_key<C: Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+        merkle_root: Option<TapBranchHash>,
+    ) -> Result<(TweakedPublicKey, secp256k1::Parity), ConversionError> {
+        let (internal_key, _) = self.to_x_only_with_parity(secp)?;
+        Ok(internal_key.tap_tweak(secp, merkle_root))
+    }
 }
 
 impl FromStr for DescriptorSecretKey {
@@ -530,17 +1124,15 @@ impl FromStr for DescriptorSecretKey {
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         let (key_part, origin) = DescriptorXKey::<bip32::ExtendedPubKey>::parse_xkey_origin(s)?;
+        let position = s.len() - key_part.len();
 
         if key_part.len() <= 52 {
             let sk = bitcoin::PrivateKey::from_str(key_part)
-                .map_err(|_| DescriptorKeyParseError("Error while parsing a WIF private key"))?;
-            Ok(DescriptorSecretKey::Single(SinglePriv {
-                key: sk,
-                origin: None,
-            }))
+                .map_err(|source| DescriptorKeyParseError::UnparsableWif { position, source })?;
+            Ok(DescriptorSecretKey::Single(SinglePriv { key: sk, origin }))
         } else {
             let (xprv, derivation_path, wildcard) =
-                DescriptorXKey::<bip32::ExtendedPrivKey>::parse_xkey_deriv(key_part)?;
+                DescriptorXKey::<bip32::ExtendedPrivKey>::parse_xkey_deriv(key_part, position)?;
             Ok(DescriptorSecretKey::XPrv(DescriptorXKey {
                 origin,
                 xkey: xprv,
@@ -551,97 +1143,106 @@ impl FromStr for DescriptorSecretKey {
     }
 }
 
+// Parse a derivation step found at `position` in the parsed key string
+fn parse_child_number(
+    step: &str,
+    position: usize,
+) -> Result<bip32::ChildNumber, DescriptorKeyParseError> {
+    bip32::ChildNumber::from_str(step)
+        .map_err(|source| DescriptorKeyParseError::BadDerivationPath { position, source })
+}
+
+// Parse the derivation steps of a key origin, the first one found at
+// `position` in the parsed key string
+fn parse_derivation_path<'a, I: Iterator<Item = &'a str>>(
+    steps: I,
+    mut position: usize,
+) -> Result<bip32::DerivationPath, DescriptorKeyParseError> {
+    let mut path = Vec::new();
+    for step in steps {
+        path.push(parse_child_number(step, position)?);
+        position += step.len() + 1;
+    }
+    Ok(path.into())
+}
+
 impl<K: InnerXKey> DescriptorXKey<K> {
     fn parse_xkey_origin(
         s: &str,
     ) -> Result<(&str, Option<bip32::KeySource>), DescriptorKeyParseError> {
-        for ch in s.as_bytes() {
+        for (position, ch) in s.as_bytes().iter().enumerate() {
             if *ch < 20 || *ch > 127 {
-                return Err(DescriptorKeyParseError(
-                    "Encountered an unprintable character",
-                ));
+                return Err(DescriptorKeyParseError::UnprintableCharacter { position });
             }
         }
 
         if s.is_empty() {
-            return Err(DescriptorKeyParseError("Empty key"));
+            return Err(DescriptorKeyParseError::EmptyKey);
         }
-        let mut parts = s[1..].split(']');
 
         if let Some('[') = s.chars().next() {
-            let mut raw_origin = parts
-                .next()
-                .ok_or(DescriptorKeyParseError("Unclosed '['"))?
-                .split('/');
-
-            let origin_id_hex = raw_origin.next().ok_or(DescriptorKeyParseError(
-                "No master fingerprint found after '['",
-            ))?;
+            let origin_end = s.find(']').ok_or(DescriptorKeyParseError::UnclosedOrigin)?;
+            let mut raw_origin = s[1..origin_end].split('/');
 
+            // Always present, possibly empty
+            let origin_id_hex = raw_origin.next().unwrap_or("");
             if origin_id_hex.len() != 8 {
-                return Err(DescriptorKeyParseError(
-                    "Master fingerprint should be 8 characters long",
-                ));
+                return Err(DescriptorKeyParseError::BadFingerprint { position: 1 });
             }
-            let parent_fingerprint = bip32::Fingerprint::from_hex(origin_id_hex).map_err(|_| {
-                DescriptorKeyParseError("Malformed master fingerprint, expected 8 hex chars")
-            })?;
-            let origin_path = raw_origin
-                .map(bip32::ChildNumber::from_str)
-                .collect::<Result<bip32::DerivationPath, bip32::Error>>()
-                .map_err(|_| {
-                    DescriptorKeyParseError("Error while parsing master derivation path")
-                })?;
-
-            let key = parts
-                .next()
-                .ok_or(DescriptorKeyParseError("No key after origin."))?;
-
-            if parts.next().is_some() {
-                Err(DescriptorKeyParseError(
-                    "Multiple ']' in Descriptor Public Key",
-                ))
-            } else {
-                Ok((key, Some((parent_fingerprint, origin_path))))
+            let parent_fingerprint = bip32::Fingerprint::from_hex(origin_id_hex)
+                .map_err(|_| DescriptorKeyParseError::BadFingerprint { position: 1 })?;
+            let origin_path = parse_derivation_path(raw_origin, 1 + origin_id_hex.len() + 1)?;
+
+            let key = &s[origin_end + 1..];
+            if let Some(second_end) = key.find(']') {
+                return Err(DescriptorKeyParseError::MultipleOriginClosures {
+                    position: origin_end + 1 + second_end,
+                });
             }
+            if key.is_empty() {
+                return Err(DescriptorKeyParseError::MissingKey);
+            }
+            Ok((key, Some((parent_fingerprint, origin_path))))
         } else {
             Ok((s, None))
         }
     }
 
-    /// Parse an extended key concatenated to a derivation path.
+    /// Parse an extended key concatenated to a derivation path, found at
+    /// `position` in the parsed key string.
     fn parse_xkey_deriv(
         key_deriv: &str,
+        position: usize,
     ) -> Result<(K, bip32::DerivationPath, Wildcard), DescriptorKeyParseError> {
         let mut key_deriv = key_deriv.split('/');
-        let xkey_str = key_deriv.next().ok_or(DescriptorKeyParseError(
-            "No key found after origin description",
-        ))?;
+        // Always present, possibly empty
+        let xkey_str = key_deriv.next().unwrap_or("");
         let xkey = K::from_str(xkey_str)
-            .map_err(|_| DescriptorKeyParseError("Error while parsing xkey."))?;
+            .map_err(|source| DescriptorKeyParseError::UnparsableXKey { position, source })?;
 
         let mut wildcard = Wildcard::None;
-        let derivation_path = key_deriv
-            .filter_map(|p| {
-                if wildcard == Wildcard::None && p == "*" {
-                    wildcard = Wildcard::Unhardened;
-                    None
-                } else if wildcard == Wildcard::None && (p == "*'" || p == "*h") {
-                    wildcard = Wildcard::Hardened;
-                    None
-                } else if wildcard != Wildcard::None {
-                    Some(Err(DescriptorKeyParseError(
-                        "'*' may only appear as last element in a derivation path.",
-                    )))
-                } else {
-                    Some(bip32::ChildNumber::from_str(p).map_err(|_| {
-                        DescriptorKeyParseError("Error while parsing key derivation path")
-                    }))
-                }
-            })
-            .collect::<Result<bip32::DerivationPath, _>>()?;
+        let mut wildcard_position = 0;
+        let mut steps = Vec::new();
+        let mut step_position = position + xkey_str.len() + 1;
+        for step in key_deriv {
+            if wildcard != Wildcard::None {
+                return Err(DescriptorKeyParseError::WildcardNotAtEnd {
+                    position: wildcard_position,
+                });
+            }
+            if step == "*" {
+                wildcard = Wildcard::Unhardened;
+                wildcard_position = step_position;
+            } else if step == "*'" || step == "*h" {
+                wildcard = Wildcard::Hardened;
+                wildcard_position = step_position;
+            } else {
+                steps.push(parse_child_number(step, step_position)?);
+            }
+            step_position += step.len() + 1;
+        }
 
-        Ok((xkey, derivation_path, wildcard))
+        Ok((xkey, steps.into(), wildcard))
     }
 
     /// Compares this key with a `keysource` and returns the matching derivation path, if any.
@@ -735,8 +1336,16 @@ impl<K: InnerXKey> DescriptorXKey<K> {
 }
 
 impl MiniscriptKey for DescriptorPublicKey {
-    // This allows us to be able to derive public keys even for PkH s
+    // This allows us to be able to derive public keys even for PkH s. The
+    // keys of `pkh()` fragments stay keys until derivation, so that key
+    // iteration and policy lifting see the same extended keys as the rest of
+    // the descriptor. A distinct hash type could not be derived, and would
+    // break the code of downstream crates relying on `Hash = Self`.
     type Hash = Self;
+    type Sha256 = sha256::Hash;
+    type Hash256 = hash256::Hash;
+    type Ripemd160 = ripemd160::Hash;
+    type Hash160 = hash160::Hash;
 
     fn is_uncompressed(&self) -> bool {
         match self {
@@ -763,6 +1372,27 @@ impl MiniscriptKey for DescriptorPublicKey {
     }
 }
 
+impl MiniscriptKey for DescriptorSecretKey {
+    // Same as for `DescriptorPublicKey`, key hashes can only be computed once
+    // the key is converted to a public key
+    type Hash = Self;
+    type Sha256 = sha256::Hash;
+    type Hash256 = hash256::Hash;
+    type Ripemd160 = ripemd160::Hash;
+    type Hash160 = hash160::Hash;
+
+    fn is_uncompressed(&self) -> bool {
+        match self {
+            DescriptorSecretKey::Single(SinglePriv { ref key, .. }) => !key.compressed,
+            _ => false,
+        }
+    }
+
+    fn to_pubkeyhash(&self) -> Self {
+        self.clone()
+    }
+}
+
 impl DerivedDescriptorKey {
     /// Computes the raw [`bitcoin::PublicKey`] for this descriptor key.
     ///
@@ -802,6 +1432,10 @@ impl fmt::Display for DerivedDescriptorKey {
 impl MiniscriptKey for DerivedDescriptorKey {
     // This allows us to be able to derive public keys even for PkH s
     type Hash = Self;
+    type Sha256 = sha256::Hash;
+    type Hash256 = hash256::Hash;
+    type Ripemd160 = ripemd160::Hash;
+    type Hash160 = hash160::Hash;
 
     fn is_uncompressed(&self) -> bool {
         self.key.is_uncompressed()
@@ -825,16 +1459,33 @@ impl ToPublicKey for DerivedDescriptorKey {
     fn hash_to_hash160(hash: &Self) -> hash160::Hash {
         hash.to_public_key().to_pubkeyhash()
     }
+
+    fn to_sha256(hash: &sha256::Hash) -> sha256::Hash {
+        *hash
+    }
+
+    fn to_hash256(hash: &hash256::Hash) -> hash256::Hash {
+        *hash
+    }
+
+    fn to_ripemd160(hash: &ripemd160::Hash) -> ripemd160::Hash {
+        *hash
+    }
+
+    fn to_hash160(hash: &hash160::Hash) -> hash160::Hash {
+        *hash
+    }
 }
 
 #[cfg(test)]
 mod test {
     use core::str::FromStr;
 
-    use bitcoin::secp256k1;
+    use bitcoin::{secp256k1, XOnlyPublicKey};
 
     use super::{DescriptorKeyParseError, DescriptorPublicKey, DescriptorSecretKey};
     use crate::prelude::*;
+    use crate::ToPublicKey;
 
     #[test]
     fn parse_descriptor_key_errors() {
@@ -842,61 +1493,64 @@ mod test {
         let desc = "[78412e3a/44'/0'/0']xpub6ERApfZwUNrhLCkDtcHTcxd75RbzS1ed54G1LkBUHQVHQKqhMkhgbmJbZRkrgZw4koxb5JaHWkY4ALHY2grBGRjaDMzQLcgJvLJuZZvRcEL/1/*/44";
         assert_eq!(
             DescriptorPublicKey::from_str(desc),
-            Err(DescriptorKeyParseError(
-                "\'*\' may only appear as last element in a derivation path."
-            ))
+            Err(DescriptorKeyParseError::WildcardNotAtEnd { position: 134 })
         );
 
         // And ones with invalid fingerprints
         let desc = "[NonHexor]xpub6ERApfZwUNrhLCkDtcHTcxd75RbzS1ed54G1LkBUHQVHQKqhMkhgbmJbZRkrgZw4koxb5JaHWkY4ALHY2grBGRjaDMzQLcgJvLJuZZvRcEL/1/*";
         assert_eq!(
             DescriptorPublicKey::from_str(desc),
-            Err(DescriptorKeyParseError(
-                "Malformed master fingerprint, expected 8 hex chars"
-            ))
+            Err(DescriptorKeyParseError::BadFingerprint { position: 1 })
         );
 
+        // ..or invalid derivation paths
+        let desc = "[78412e3a/44'/x/0']xpub6ERApfZwUNrhLCkDtcHTcxd75RbzS1ed54G1LkBUHQVHQKqhMkhgbmJbZRkrgZw4koxb5JaHWkY4ALHY2grBGRjaDMzQLcgJvLJuZZvRcEL/1/*";
+        match DescriptorPublicKey::from_str(desc) {
+            Err(DescriptorKeyParseError::BadDerivationPath { position: 14, .. }) => {}
+            res => panic!("unexpected {:?}", res),
+        }
+        let desc = "[78412e3a]xpub6ERApfZwUNrhLCkDtcHTcxd75RbzS1ed54G1LkBUHQVHQKqhMkhgbmJbZRkrgZw4koxb5JaHWkY4ALHY2grBGRjaDMzQLcgJvLJuZZvRcEL/1/-2";
+        match DescriptorPublicKey::from_str(desc) {
+            Err(DescriptorKeyParseError::BadDerivationPath { position: 124, .. }) => {}
+            res => panic!("unexpected {:?}", res),
+        }
+
         // And ones with invalid xpubs..
         let desc = "[78412e3a]xpub1ed54G1LkBUHQVHQKqhMkhgbmJbZRkrgZw4koxb5JaLcgJvLJuZZvRcEL/1/*";
-        assert_eq!(
-            DescriptorPublicKey::from_str(desc),
-            Err(DescriptorKeyParseError("Error while parsing xkey."))
-        );
+        match DescriptorPublicKey::from_str(desc) {
+            Err(DescriptorKeyParseError::UnparsableXKey { position: 10, .. }) => {}
+            res => panic!("unexpected {:?}", res),
+        }
 
         // ..or invalid raw keys
         let desc = "[78412e3a]0208a117f3897c3a13c9384b8695eed98dc31bc2500feb19a1af424cd47a5d83/1/*";
         assert_eq!(
             DescriptorPublicKey::from_str(desc),
-            Err(DescriptorKeyParseError(
-                "Public keys must be 64/66/130 characters in size"
-            ))
+            Err(DescriptorKeyParseError::BadPubKeyLength {
+                position: 10,
+                length: 68
+            })
         );
 
         // ..or invalid separators
         let desc = "[78412e3a]]03f28773c2d975288bc7d1d205c3748651b075fbc6610e58cddeeddf8f19405aa8";
         assert_eq!(
             DescriptorPublicKey::from_str(desc),
-            Err(DescriptorKeyParseError(
-                "Multiple \']\' in Descriptor Public Key"
-            ))
+            Err(DescriptorKeyParseError::MultipleOriginClosures { position: 10 })
         );
 
         // fuzzer errors
         let desc = "[11111f11]033333333333333333333333333333323333333333333333333333333433333333]]333]]3]]101333333333333433333]]]10]333333mmmm";
         assert_eq!(
             DescriptorPublicKey::from_str(desc),
-            Err(DescriptorKeyParseError(
-                "Multiple \']\' in Descriptor Public Key"
-            ))
+            Err(DescriptorKeyParseError::MultipleOriginClosures { position: 76 })
         );
 
         // fuzz failure, hybrid keys
         let desc = "0777777777777777777777777777777777777777777777777777777777777777777777777777777777777777777777777777777777777777777777777777777777";
         assert_eq!(
             DescriptorPublicKey::from_str(desc),
-            Err(DescriptorKeyParseError(
-                "Only publickeys with prefixes 02/03/04 are allowed"
-            ))
+            Err(DescriptorKeyParseError::BadPubKeyPrefix { position: 0 })
         );
     }
 
@@ -904,28 +1558,24 @@ mod test {
     fn parse_descriptor_secret_key_error() {
         // Xpubs are invalid
         let secret_key = "xpub6ERApfZwUNrhLCkDtcHTcxd75RbzS1ed54G1LkBUHQVHQKqhMkhgbmJbZRkrgZw4koxb5JaHWkY4ALHY2grBGRjaDMzQLcgJvLJuZZvRcEL";
-        assert_eq!(
-            DescriptorSecretKey::from_str(secret_key),
-            Err(DescriptorKeyParseError("Error while parsing xkey."))
-        );
+        match DescriptorSecretKey::from_str(secret_key) {
+            Err(DescriptorKeyParseError::UnparsableXKey { position: 0, .. }) => {}
+            res => panic!("unexpected {:?}", res),
+        }
 
         // And ones with invalid fingerprints
         let desc = "[NonHexor]tprv8ZgxMBicQKsPcwcD4gSnMti126ZiETsuX7qwrtMypr6FBwAP65puFn4v6c3jrN9VwtMRMph6nyT63NrfUL4C3nBzPcduzVSuHD7zbX2JKVc/1/*";
-        assert_eq!(
-            DescriptorSecretKey::from_str(desc),
-            Err(DescriptorKeyParseError(
-                "Malformed master fingerprint, expected 8 hex chars"
-            ))
-        );
+        match DescriptorSecretKey::from_str(desc) {
+            Err(DescriptorKeyParseError::BadFingerprint { position: 1 }) => {}
+            res => panic!("unexpected {:?}", res),
+        }
 
         // ..or invalid raw keys
         let desc = "[78412e3a]L32jTfVLei6BYTPUpwpJSkrHx8iL9GZzeErVS8y4Y/1/*";
-        assert_eq!(
-            DescriptorSecretKey::from_str(desc),
-            Err(DescriptorKeyParseError(
-                "Error while parsing a WIF private key"
-            ))
-        );
+        match DescriptorSecretKey::from_str(desc) {
+            Err(DescriptorKeyParseError::UnparsableWif { position: 10, .. }) => {}
+            res => panic!("unexpected {:?}", res),
+        }
     }
 
     #[test]
@@ -985,6 +1635,156 @@ mod test {
         );
     }
 
+    #[test]
+    #[cfg(feature = "zeroize")]
+    fn test_zeroize() {
+        use zeroize::Zeroize;
+
+        let secp = secp256k1::Secp256k1::signing_only();
+        let xprv = "[aabbccdd/90']tprv8ZgxMBicQKsPcwcD4gSnMti126ZiETsuX7qwrtMypr6FBwAP65puFn4v6c3jrN9VwtMRMph6nyT63NrfUL4C3nBzPcduzVSuHD7zbX2JKVc/0'/1'/*";
+        let mut secret_key = DescriptorSecretKey::from_str(xprv).unwrap();
+        let public_key = secret_key.to_public(&secp).unwrap();
+        // Deriving and dropping copies doesn't scrub the original key
+        drop(secret_key.clone().derive(3));
+        assert_eq!(secret_key.to_string(), xprv);
+
+        secret_key.zeroize();
+        match secret_key {
+            DescriptorSecretKey::XPrv(ref xkey) => {
+                assert_eq!(xkey.xkey.private_key, secp256k1::ONE_KEY);
+                assert_eq!(xkey.xkey.chain_code.as_bytes(), &[0; 32]);
+                assert_eq!(xkey.derivation_path.to_string(), "m/0'/1'");
+            }
+            _ => panic!("expected an extended key"),
+        }
+        assert_ne!(secret_key.to_public(&secp).unwrap(), public_key);
+
+        let mut secret_key = DescriptorSecretKey::from_str(
+            "[aabbccdd]L4rK1yDtCWekvXuE6oXD9jCYfFNV2cWRpVuPLBcCU2z8TrisoyY1",
+        )
+        .unwrap();
+        secret_key.zeroize();
+        match secret_key {
+            DescriptorSecretKey::Single(ref single) => {
+                assert_eq!(single.key.inner, secp256k1::ONE_KEY);
+                assert!(single.origin.is_some());
+            }
+            _ => panic!("expected a single key"),
+        }
+    }
+
+    #[test]
+    fn test_x_only_parity() {
+        use bitcoin::Script;
+
+        use crate::Descriptor;
+
+        let secp = secp256k1::Secp256k1::verification_only();
+        let xpub = DescriptorPublicKey::from_str("tpubDBrgjcxBxnXyL575sHdkpKohWu5qHKoQ7TJXKNrYznh5fVEGBv89hA8ENW7A8MFVpFUSvgLqc4Nj1WZcpePX6rrxviVtPowvMuGF5rdT2Vi/*").unwrap();
+        let desc = Descriptor::<DescriptorPublicKey>::from_str(&format!("tr({})", xpub)).unwrap();
+
+        // Derived keys keep their parity, whichever it is
+        let mut parities = BTreeSet::new();
+        for index in 0..8 {
+            let key = xpub.clone().derive(index);
+            let full = key.derive_public_key(&secp).unwrap();
+            let (xonly, parity) = key.key.to_x_only_with_parity(&secp).unwrap();
+            assert_eq!(xonly, XOnlyPublicKey::from(full.inner));
+            assert_eq!(parity as u8 + 2, full.inner.serialize()[0]);
+            assert_eq!(xonly, ToPublicKey::to_x_only_pubkey(&key));
+            parities.insert(parity as u8);
+
+            let (output_key, _) = key.key.to_tap_output_key(&secp, None).unwrap();
+            assert_eq!(
+                Script::new_v1_p2tr_tweaked(output_key),
+                desc.derived_descriptor(&secp, index)
+                    .unwrap()
+                    .script_pubkey()
+            );
+        }
+        assert_eq!(parities.len(), 2);
+
+        // Raw x-only keys are even
+        let xonly = DescriptorPublicKey::from_str(
+            "[aabbccdd]31c7d3fc85c148717848033ce276ae2b464a4e2c367ed33886cc428b8af48ff8",
+        )
+        .unwrap();
+        assert_eq!(
+            xonly.to_x_only_with_parity(&secp).unwrap().1,
+            secp256k1::Parity::Even
+        );
+        assert!(xpub.to_x_only_with_parity(&secp).is_err());
+    }
+
+    #[test]
+    fn test_secret_key_origin_and_derivation() {
+        let secp = secp256k1::Secp256k1::signing_only();
+
+        // Origins of WIF keys are kept
+        let secret_key = DescriptorSecretKey::from_str(
+            "[aabbccdd/84'/0'/0'/0/1]cVt4o7BGAig1UXywgGSmARhxMdzP5qvQsxKkSsc1XEkw3tDTQFpy",
+        )
+        .unwrap();
+        assert_eq!(
+            secret_key.to_string(),
+            "[aabbccdd/84'/0'/0'/0/1]cVt4o7BGAig1UXywgGSmARhxMdzP5qvQsxKkSsc1XEkw3tDTQFpy"
+        );
+        assert_eq!(secret_key.master_fingerprint(&secp).to_string(), "aabbccdd");
+        assert_eq!(
+            secret_key.full_derivation_path().to_string(),
+            "m/84'/0'/0'/0/1"
+        );
+        assert_eq!(
+            secret_key.to_public(&secp).unwrap().master_fingerprint(),
+            secret_key.master_fingerprint(&secp)
+        );
+
+        // Hardened wildcards are displayed and derived
+        let secret_key = DescriptorSecretKey::from_str("[aabbccdd/90']tprv8ZgxMBicQKsPcwcD4gSnMti126ZiETsuX7qwrtMypr6FBwAP65puFn4v6c3jrN9VwtMRMph6nyT63NrfUL4C3nBzPcduzVSuHD7zbX2JKVc/0'/*'").unwrap();
+        assert_eq!(secret_key.to_string(), "[aabbccdd/90']tprv8ZgxMBicQKsPcwcD4gSnMti126ZiETsuX7qwrtMypr6FBwAP65puFn4v6c3jrN9VwtMRMph6nyT63NrfUL4C3nBzPcduzVSuHD7zbX2JKVc/0'/*h");
+        assert!(secret_key.is_deriveable());
+        assert_eq!(secret_key.full_derivation_path().to_string(), "m/90'/0'");
+        let derived = secret_key.derive(3);
+        assert!(!derived.is_deriveable());
+        assert_eq!(derived.full_derivation_path().to_string(), "m/90'/0'/3'");
+        let expected = DescriptorSecretKey::from_str("[aabbccdd/90']tprv8ZgxMBicQKsPcwcD4gSnMti126ZiETsuX7qwrtMypr6FBwAP65puFn4v6c3jrN9VwtMRMph6nyT63NrfUL4C3nBzPcduzVSuHD7zbX2JKVc/0'/3'").unwrap();
+        assert_eq!(derived.to_string(), expected.to_string());
+        assert_eq!(
+            derived.to_public(&secp).unwrap(),
+            expected.to_public(&secp).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_secret_key_cmp() {
+        let xprv = "xprv9s21ZrQH143K3QTDL4LXw2F7HEK3wJUD2nW2nRk4stbPy6cq3jPPqjiChkVvvNKmPGJxWUtg6LnF5kejMRNNU3TGtRBeJgk33yuGBxrMPHi";
+        let xprv_apostrophe = DescriptorSecretKey::from_str(&format!("{}/0'/*", xprv)).unwrap();
+        let xprv_h = DescriptorSecretKey::from_str(&format!("{}/0h/*", xprv)).unwrap();
+        let xprv_other = DescriptorSecretKey::from_str(&format!("{}/1'/*", xprv)).unwrap();
+        let single =
+            DescriptorSecretKey::from_str("cVt4o7BGAig1UXywgGSmARhxMdzP5qvQsxKkSsc1XEkw3tDTQFpy")
+                .unwrap();
+        let single_origin = DescriptorSecretKey::from_str(
+            "[78412e3a/0']cVt4o7BGAig1UXywgGSmARhxMdzP5qvQsxKkSsc1XEkw3tDTQFpy",
+        )
+        .unwrap();
+
+        assert_eq!(xprv_apostrophe, xprv_h);
+        assert_ne!(xprv_apostrophe, xprv_other);
+        assert_ne!(single, single_origin);
+        assert!(xprv_apostrophe < xprv_other);
+        assert!(single < single_origin);
+        assert!(single_origin < xprv_apostrophe);
+
+        let set: BTreeSet<_> = vec![xprv_apostrophe.clone(), xprv_h, single.clone()]
+            .into_iter()
+            .collect();
+        assert_eq!(
+            set.into_iter().collect::<Vec<_>>(),
+            vec![single, xprv_apostrophe]
+        );
+    }
+
     #[test]
     fn test_master_fingerprint() {
         assert_eq!(
@@ -0,0 +1,342 @@
+// Miniscript
+// Written in 2022 by rust-miniscript developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Mnemonic Seeds
+//!
+//! Derivation of descriptor secret keys from BIP 39 mnemonic phrases.
+//!
+
+use core::fmt;
+#[cfg(feature = "std")]
+use std::error;
+
+use bitcoin::hashes::{hmac, sha256, sha512, Hash, HashEngine};
+use bitcoin::secp256k1::{Secp256k1, Signing};
+use bitcoin::util::bip32;
+use bitcoin::Network;
+use unicode_normalization::UnicodeNormalization;
+#[cfg(feature = "zeroize")]
+use zeroize::Zeroize;
+
+use super::{DescriptorSecretKey, DescriptorXKey, Wildcard};
+use crate::prelude::*;
+
+/// Number of PBKDF2 rounds stretching a mnemonic into a seed
+const PBKDF2_ROUNDS: usize = 2048;
+
+/// The English wordlist of BIP 39, sorted, one word per line
+const ENGLISH: &str = include_str!("bip39_english.txt");
+
+/// An error deriving a key from a mnemonic phrase
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MnemonicError {
+    /// The phrase does not have 12, 15, 18, 21 or 24 words
+    BadWordCount(usize),
+    /// The word at this position of the phrase is not in the English
+    /// wordlist
+    UnknownWord(usize),
+    /// The checksum of the phrase does not match its entropy
+    BadChecksum,
+    /// The key could not be derived from the seed
+    Bip32(bip32::Error),
+}
+
+impl fmt::Display for MnemonicError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MnemonicError::BadWordCount(n) => write!(
+                f,
+                "mnemonic has {} words instead of 12, 15, 18, 21 or 24",
+                n
+            ),
+            MnemonicError::UnknownWord(i) => {
+                write!(
+                    f,
+                    "mnemonic word {} is not in the BIP 39 English wordlist",
+                    i
+                )
+            }
+            MnemonicError::BadChecksum => f.write_str("invalid mnemonic checksum"),
+            MnemonicError::Bip32(ref e) => write!(f, "unable to derive the key: {}", e),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl error::Error for MnemonicError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            MnemonicError::BadWordCount(_)
+            | MnemonicError::UnknownWord(_)
+            | MnemonicError::BadChecksum => None,
+            MnemonicError::Bip32(ref e) => Some(e),
+        }
+    }
+}
+
+impl DescriptorSecretKey {
+    /// Derive the key at `derivation` from the seed of a BIP 39 mnemonic
+    /// `phrase` and `passphrase`, as `[fingerprint/derivation]xprv` where
+    /// `fingerprint` is the fingerprint of the master key of the seed.
+    ///
+    /// The phrase and passphrase are NFKD normalized and the words of the
+    /// phrase may be separated by any whitespace. The words must be in the
+    /// English wordlist and end with a valid checksum. The error does not
+    /// include the words, so it can be logged without leaking the phrase.
+    ///
+    /// With the `zeroize` feature, the copies of the phrase, the seed and the
+    /// master key made while deriving are overwritten, but not the strings
+    /// passed in.
+    pub fn from_mnemonic<C: Signing>(
+        secp: &Secp256k1<C>,
+        phrase: &str,
+        passphrase: &str,
+        network: Network,
+        derivation: bip32::DerivationPath,
+    ) -> Result<Self, MnemonicError> {
+        #[allow(unused_mut)]
+        let mut phrase = phrase.nfkd().collect::<String>();
+        let mut salt = String::from("mnemonic");
+        salt.extend(passphrase.nfkd());
+        let key = derive_key(secp, &phrase, &salt, network, derivation);
+        #[cfg(feature = "zeroize")]
+        {
+            phrase.zeroize();
+            salt.zeroize();
+        }
+        key
+    }
+}
+
+// Derive the key from the normalized phrase and salt, the body of
+// `DescriptorSecretKey::from_mnemonic`, which overwrites them on every return
+fn derive_key<C: Signing>(
+    secp: &Secp256k1<C>,
+    phrase: &str,
+    salt: &str,
+    network: Network,
+    derivation: bip32::DerivationPath,
+) -> Result<DescriptorSecretKey, MnemonicError> {
+    let words = phrase.split_whitespace().collect::<Vec<_>>();
+    if words.len() % 3 != 0 || words.len() < 12 || words.len() > 24 {
+        return Err(MnemonicError::BadWordCount(words.len()));
+    }
+    check_words(&words)?;
+    #[allow(unused_mut)]
+    let mut sentence = words.join(" ");
+    #[allow(unused_mut)]
+    let mut seed = pbkdf2_sha512(sentence.as_bytes(), salt.as_bytes());
+    #[cfg(feature = "zeroize")]
+    sentence.zeroize();
+
+    let master = bip32::ExtendedPrivKey::new_master(network, &seed);
+    #[cfg(feature = "zeroize")]
+    seed.zeroize();
+    #[allow(unused_mut)]
+    let mut master = master.map_err(MnemonicError::Bip32)?;
+    let xkey = master.derive_priv(secp, &derivation);
+    let fingerprint = master.fingerprint(secp);
+    #[cfg(feature = "zeroize")]
+    super::key::erase_xprv(&mut master);
+    Ok(DescriptorSecretKey::XPrv(DescriptorXKey {
+        origin: Some((fingerprint, derivation)),
+        xkey: xkey.map_err(MnemonicError::Bip32)?,
+        derivation_path: bip32::DerivationPath::master(),
+        wildcard: Wildcard::None,
+    }))
+}
+
+// Check that the words are in the English wordlist and that the checksum, the
+// last `words.len() / 3` bits of the phrase, is the start of the sha256 of the
+// entropy in the other bits. With the `zeroize` feature, the bits and the
+// entropy are overwritten
+fn check_words(words: &[&str]) -> Result<(), MnemonicError> {
+    let wordlist = ENGLISH.lines().collect::<Vec<_>>();
+    let mut bits = Vec::with_capacity(words.len() * 11);
+    for (i, word) in words.iter().enumerate() {
+        let index = match wordlist.binary_search(word) {
+            Ok(index) => index,
+            Err(_) => {
+                #[cfg(feature = "zeroize")]
+                bits.zeroize();
+                return Err(MnemonicError::UnknownWord(i));
+            }
+        };
+        bits.extend((0..11).rev().map(|bit| (index >> bit) & 1 == 1));
+    }
+    let to_byte = |bits: &[bool]| bits.iter().fold(0u8, |byte, &bit| byte << 1 | bit as u8);
+
+    let (entropy, checksum) = bits.split_at(bits.len() - words.len() / 3);
+    #[allow(unused_mut)]
+    let mut entropy = entropy.chunks(8).map(to_byte).collect::<Vec<_>>();
+    let valid = sha256::Hash::hash(&entropy)[0] >> (8 - checksum.len()) == to_byte(checksum);
+    #[cfg(feature = "zeroize")]
+    {
+        entropy.zeroize();
+        bits.zeroize();
+    }
+    if valid {
+        Ok(())
+    } else {
+        Err(MnemonicError::BadChecksum)
+    }
+}
+
+// PBKDF2 with HMAC-SHA512, yielding a single block of output. With the
+// `zeroize` feature the last block is overwritten, but not the states of the
+// HMAC engines, whose fields are private
+fn pbkdf2_sha512(password: &[u8], salt: &[u8]) -> [u8; 64] {
+    let keyed = hmac::HmacEngine::<sha512::Hash>::new(password);
+
+    let mut engine = keyed.clone();
+    engine.input(salt);
+    engine.input(&1u32.to_be_bytes());
+    let mut block = hmac::Hmac::<sha512::Hash>::from_engine(engine).into_inner();
+    let mut seed = block;
+    for _ in 1..PBKDF2_ROUNDS {
+        let mut engine = keyed.clone();
+        engine.input(&block);
+        block = hmac::Hmac::<sha512::Hash>::from_engine(engine).into_inner();
+        for (byte, other) in seed.iter_mut().zip(block.iter()) {
+            *byte ^= other;
+        }
+    }
+    #[cfg(feature = "zeroize")]
+    block.zeroize();
+    seed
+}
+
+#[cfg(test)]
+mod tests {
+    use core::str::FromStr;
+
+    use bitcoin::hashes::hex::ToHex;
+
+    use super::*;
+
+    #[test]
+    fn from_mnemonic() {
+        let phrase = "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about";
+        // BIP 39 test vector
+        assert_eq!(
+            pbkdf2_sha512(phrase.as_bytes(), b"mnemonicTREZOR").to_hex(),
+            "c55257c360c07c72029aebc1b53c05ed0362ada38ead3e3e9efa3708e53495531f09a6987599d18264c1e1c92f2cf141630c7a3c4ab7c81b2f001698e7463b04"
+        );
+
+        let secp = Secp256k1::signing_only();
+        let master = DescriptorSecretKey::from_mnemonic(
+            &secp,
+            phrase,
+            "TREZOR",
+            Network::Bitcoin,
+            bip32::DerivationPath::master(),
+        )
+        .unwrap();
+        let xprv = "xprv9s21ZrQH143K3h3fDYiay8mocZ3afhfULfb5GX8kCBdno77K4HiA15Tg23wpbeF1pLfs1c5SPmYHrEpTuuRhxMwvKDwqdKiGJS9XFKzUsAF";
+        match master {
+            DescriptorSecretKey::XPrv(ref xkey) => assert_eq!(xkey.xkey.to_string(), xprv),
+            _ => panic!("expected an extended key"),
+        }
+
+        // Extra whitespace does not change the seed
+        let key = DescriptorSecretKey::from_mnemonic(
+            &secp,
+            &phrase.replace(' ', " \n "),
+            "TREZOR",
+            Network::Bitcoin,
+            bip32::DerivationPath::from_str("m/84'/0'/0'").unwrap(),
+        )
+        .unwrap();
+        let xkey = match (&master, &key) {
+            (DescriptorSecretKey::XPrv(master), DescriptorSecretKey::XPrv(key)) => {
+                assert_eq!(
+                    key.origin,
+                    Some((
+                        master.xkey.fingerprint(&secp),
+                        bip32::DerivationPath::from_str("m/84'/0'/0'").unwrap()
+                    ))
+                );
+                key.xkey
+            }
+            _ => panic!("expected extended keys"),
+        };
+        let expected = bip32::ExtendedPrivKey::from_str(xprv)
+            .unwrap()
+            .derive_priv(
+                &secp,
+                &bip32::DerivationPath::from_str("m/84'/0'/0'").unwrap(),
+            )
+            .unwrap();
+        assert_eq!(xkey, expected);
+
+        assert_eq!(
+            DescriptorSecretKey::from_mnemonic(
+                &secp,
+                "abandon about",
+                "",
+                Network::Bitcoin,
+                bip32::DerivationPath::master(),
+            )
+            .unwrap_err(),
+            MnemonicError::BadWordCount(2)
+        );
+    }
+
+    #[test]
+    fn check_words() {
+        let check = |phrase: &str| super::check_words(&phrase.split(' ').collect::<Vec<_>>());
+        // BIP 39 test vectors
+        assert_eq!(
+            check("abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about"),
+            Ok(())
+        );
+        assert_eq!(
+            check("legal winner thank year wave sausage worth useful legal winner thank yellow"),
+            Ok(())
+        );
+        assert_eq!(
+            check("letter advice cage absurd amount doctor acoustic avoid letter advice cage absurd amount doctor acoustic avoid letter always"),
+            Ok(())
+        );
+        assert_eq!(
+            check("zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo vote"),
+            Ok(())
+        );
+
+        assert_eq!(
+            check("abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon"),
+            Err(MnemonicError::BadChecksum)
+        );
+        assert_eq!(
+            check("zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo"),
+            Err(MnemonicError::BadChecksum)
+        );
+        assert_eq!(
+            check("abandon abandon abandon abandon abandon abandonn abandon abandon abandon abandon abandon about"),
+            Err(MnemonicError::UnknownWord(5))
+        );
+
+        let secp = Secp256k1::signing_only();
+        assert_eq!(
+            DescriptorSecretKey::from_mnemonic(
+                &secp,
+                "Abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about",
+                "",
+                Network::Bitcoin,
+                bip32::DerivationPath::master(),
+            )
+            .unwrap_err(),
+            MnemonicError::UnknownWord(0)
+        );
+    }
+}
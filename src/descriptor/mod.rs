@@ -23,43 +23,66 @@
 //! these with BIP32 paths, pay-to-contract instructions, etc.
 //!
 
-use core::fmt;
 use core::ops::Range;
 use core::str::{self, FromStr};
+use core::{cmp, fmt};
+#[cfg(feature = "std")]
+use std::error;
 
 use bitcoin::blockdata::witness::Witness;
+use bitcoin::hashes::{hash160, Hash};
 use bitcoin::util::address::WitnessVersion;
-use bitcoin::{self, secp256k1, Address, Network, Script, TxIn};
+use bitcoin::util::bip32;
+use bitcoin::util::taproot::{ControlBlock, LeafVersion, TapBranchHash, TapLeafHash};
+use bitcoin::{self, secp256k1, Address, Amount, Network, Script, TxIn, TxOut};
 use sync::Arc;
 
 use self::checksum::verify_checksum;
-use crate::miniscript::{Legacy, Miniscript, Segwitv0};
+use crate::miniscript::limits::RuleSet;
+use crate::miniscript::{
+    context, ContextError, KeyRule, Legacy, Miniscript, ScriptContextError, Segwitv0, Tap,
+};
+use crate::policy::{semantic, Liftable};
 use crate::prelude::*;
+use crate::util::varint_len;
 use crate::{
-    expression, miniscript, BareCtx, Error, ForEach, ForEachKey, MiniscriptKey, Satisfier,
-    ToPublicKey, TranslatePk, TranslatePk2,
+    expression, interpreter, miniscript, BareCtx, Error, ForEach, ForEachKey, MiniscriptKey,
+    Satisfier, ToPublicKey, TranslatePk, Translator,
 };
 
 mod bare;
+mod builder;
 mod segwitv0;
 mod sh;
 mod sortedmulti;
+mod spend_paths;
+mod templates;
 mod tr;
 
 // Descriptor Exports
-pub use self::bare::{Bare, Pkh};
+pub use self::bare::{Addr, Bare, Pkh, Raw, Wv};
+pub use self::builder::DescriptorBuilder;
 pub use self::segwitv0::{Wpkh, Wsh, WshInner};
 pub use self::sh::{Sh, ShInner};
 pub use self::sortedmulti::SortedMultiVec;
-pub use self::tr::{TapTree, Tr};
+pub use self::spend_paths::{SpendPath, SpendPathScript, SpendPaths};
+pub use self::templates::Keychain;
+pub use self::tr::{RawTr, TapTree, Tr, UnspendableKey};
 
-mod checksum;
+pub mod checksum;
+#[cfg(feature = "serde")]
+pub mod core_import;
 mod key;
+#[cfg(feature = "bip39")]
+mod mnemonic;
+pub mod wallet_policy;
 
 pub use self::key::{
     ConversionError, DerivedDescriptorKey, DescriptorKeyParseError, DescriptorPublicKey,
     DescriptorSecretKey, DescriptorXKey, InnerXKey, SinglePriv, SinglePub, SinglePubKey, Wildcard,
 };
+#[cfg(feature = "bip39")]
+pub use self::mnemonic::MnemonicError;
 
 /// Alias type for a map of public key to secret key
 ///
@@ -84,6 +107,15 @@ pub enum Descriptor<Pk: MiniscriptKey> {
     Wsh(Wsh<Pk>),
     /// Pay-to-Taproot
     Tr(Tr<Pk>),
+    /// Pay-to-Taproot output key, with no known internal key or script tree
+    RawTr(RawTr<Pk>),
+    /// Raw script pubkey, which is not a miniscript and cannot be satisfied
+    Raw(Raw),
+    /// Address, whose script pubkey cannot be satisfied
+    Addr(Addr),
+    /// Witness program of a version without a descriptor, which cannot be
+    /// satisfied
+    Wv(Wv),
 }
 
 impl<Pk: MiniscriptKey> From<Bare<Pk>> for Descriptor<Pk> {
@@ -128,6 +160,34 @@ impl<Pk: MiniscriptKey> From<Tr<Pk>> for Descriptor<Pk> {
     }
 }
 
+impl<Pk: MiniscriptKey> From<RawTr<Pk>> for Descriptor<Pk> {
+    #[inline]
+    fn from(inner: RawTr<Pk>) -> Self {
+        Descriptor::RawTr(inner)
+    }
+}
+
+impl<Pk: MiniscriptKey> From<Raw> for Descriptor<Pk> {
+    #[inline]
+    fn from(inner: Raw) -> Self {
+        Descriptor::Raw(inner)
+    }
+}
+
+impl<Pk: MiniscriptKey> From<Addr> for Descriptor<Pk> {
+    #[inline]
+    fn from(inner: Addr) -> Self {
+        Descriptor::Addr(inner)
+    }
+}
+
+impl<Pk: MiniscriptKey> From<Wv> for Descriptor<Pk> {
+    #[inline]
+    fn from(inner: Wv) -> Self {
+        Descriptor::Wv(inner)
+    }
+}
+
 /// Descriptor Type of the descriptor
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
 pub enum DescriptorType {
@@ -153,6 +213,14 @@ pub enum DescriptorType {
     ShWshSortedMulti,
     /// Tr Descriptor
     Tr,
+    /// Raw Tr Descriptor (output key only)
+    RawTr,
+    /// Raw Descriptor (arbitrary script pubkey)
+    Raw,
+    /// Addr Descriptor (address only)
+    Addr,
+    /// Wv Descriptor (witness program of a version without a descriptor)
+    Wv(WitnessVersion),
 }
 
 impl DescriptorType {
@@ -162,11 +230,139 @@ impl DescriptorType {
     pub fn segwit_version(&self) -> Option<WitnessVersion> {
         use self::DescriptorType::*;
         match self {
-            Tr => Some(WitnessVersion::V1),
+            Tr | RawTr => Some(WitnessVersion::V1),
             Wpkh | ShWpkh | Wsh | ShWsh | ShWshSortedMulti | WshSortedMulti => {
                 Some(WitnessVersion::V0)
             }
-            Bare | Sh | Pkh | ShSortedMulti => None,
+            Wv(version) => Some(*version),
+            Bare | Sh | Pkh | ShSortedMulti | Raw | Addr => None,
+        }
+    }
+}
+
+/// How the outputs of a descriptor are spent, as returned by
+/// [`Descriptor::spend_model`]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct SpendModel {
+    /// The [`DescriptorType`] of the descriptor
+    pub desc_type: DescriptorType,
+    /// Witness version of the output, `None` for non-segwit outputs. This is
+    /// `Some(WitnessVersion::V0)` for p2sh wrapped segwit as well.
+    pub witness_version: Option<WitnessVersion>,
+    /// Whether the output can be spent with a taproot key spend
+    pub key_spend: bool,
+    /// Number of leaves of the taproot tree, including the leaves which are
+    /// not tapscript but not the hidden branches
+    pub tap_leaves: usize,
+    /// Whether spending requires a redeem script in the scriptSig
+    pub needs_redeem_script: bool,
+    /// Whether the output is segwit nested in p2sh
+    pub nested: bool,
+}
+
+impl SpendModel {
+    // Fill in what the script pubkey of a `raw` or `addr` descriptor tells
+    fn set_script_pubkey(&mut self, spk: &Script) {
+        self.witness_version = spk.witness_version();
+        self.key_spend = spk.is_v1_p2tr();
+        self.needs_redeem_script = spk.is_p2sh();
+    }
+}
+
+/// Upper bound on the size of a satisfaction of a descriptor, split into its
+/// scriptSig and witness parts.
+///
+/// Assumes all ec-signatures are 73 bytes and all schnorr signatures 66 bytes,
+/// including their length prefix and sighash suffix.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct SatisfactionWeight {
+    /// Size of the scriptSig, excluding its length prefix
+    pub script_sig_size: usize,
+    /// Number of witness elements, zero for non-segwit descriptors
+    pub witness_elements: usize,
+    /// Size of the witness elements, excluding their length prefixes
+    pub witness_size: usize,
+    /// Size of the compact-size length prefixes of the witness elements and of
+    /// the witness element count
+    pub witness_overhead: usize,
+}
+
+impl SatisfactionWeight {
+    /// Weight of the scriptSig, including its length prefix
+    pub fn script_sig_weight(&self) -> usize {
+        4 * (varint_len(self.script_sig_size) + self.script_sig_size)
+    }
+
+    /// Weight of the witness, including the element count and length prefixes
+    pub fn witness_weight(&self) -> usize {
+        self.witness_size + self.witness_overhead
+    }
+
+    /// Total weight of the satisfaction.
+    ///
+    /// For descriptors without a witness, this does not include the empty
+    /// witness (one weight unit) the input has in a transaction with segwit
+    /// inputs.
+    pub fn weight(&self) -> usize {
+        self.script_sig_weight() + self.witness_weight()
+    }
+
+    /// Weight the satisfaction adds to an unsigned input, i.e. to an input
+    /// with an empty scriptSig and, in a transaction with segwit inputs, an
+    /// empty witness.
+    ///
+    /// This is [`SatisfactionWeight::weight`] without the weight of the
+    /// one-byte scriptSig length and witness element count such an input
+    /// always has, and is the number to add to the weight of an unsigned
+    /// transaction when estimating its fee.
+    pub fn weight_to_satisfy(&self) -> usize {
+        let empty_witness = if self.witness_elements > 0 { 1 } else { 0 };
+        self.weight() - 4 - empty_witness
+    }
+
+    /// Total weight of a satisfied input in a transaction with segwit inputs,
+    /// including its outpoint, sequence, scriptSig and witness.
+    ///
+    /// Inputs without a witness still count the one weight unit of their empty
+    /// witness.
+    pub fn input_weight(&self) -> usize {
+        // outpoint (36 bytes), sequence (4 bytes) and the one-byte scriptSig
+        // length, plus the empty witness count
+        4 * (36 + 4 + 1) + 1 + self.weight_to_satisfy()
+    }
+
+    // The witness of a script satisfaction, with `elements` elements including
+    // the script but not the control block, where the satisfaction elements
+    // take `sat_size` bytes with their one-byte length prefixes
+    pub(super) fn from_witness_script(
+        elements: usize,
+        sat_size: usize,
+        script_size: usize,
+        control_block_size: Option<usize>,
+    ) -> Self {
+        let sat_prefixes = cmp::min(elements - 1, sat_size);
+        let mut weight = SatisfactionWeight {
+            script_sig_size: 0,
+            witness_elements: elements,
+            witness_size: sat_size - sat_prefixes + script_size,
+            witness_overhead: sat_prefixes + varint_len(script_size),
+        };
+        if let Some(size) = control_block_size {
+            weight.witness_elements += 1;
+            weight.witness_size += size;
+            weight.witness_overhead += varint_len(size);
+        }
+        weight.witness_overhead += varint_len(weight.witness_elements);
+        weight
+    }
+
+    // The witness of a taproot key spend
+    pub(super) fn tap_key_spend() -> Self {
+        SatisfactionWeight {
+            script_sig_size: 0,
+            witness_elements: 1,
+            witness_size: 65,
+            witness_overhead: 2,
         }
     }
 }
@@ -273,6 +469,51 @@ impl<Pk: MiniscriptKey> Descriptor<Pk> {
         Ok(Descriptor::Tr(Tr::new(key, script)?))
     }
 
+    /// Create new rawtr descriptor from an already tweaked taproot output key
+    pub fn new_rawtr(key: Pk) -> Self {
+        Descriptor::RawTr(RawTr::new(key))
+    }
+
+    /// Create new raw descriptor from an arbitrary script pubkey
+    pub fn new_raw(script: Script) -> Self {
+        Descriptor::Raw(Raw::new(script))
+    }
+
+    /// Create new addr descriptor from an address
+    pub fn new_addr(address: Address) -> Self {
+        Descriptor::Addr(Addr::new(address))
+    }
+
+    /// Create new wv descriptor from a witness version and program
+    ///
+    /// # Errors
+    /// See [`Wv::new`]
+    pub fn new_wv(version: WitnessVersion, program: Vec<u8>) -> Result<Self, Error> {
+        Ok(Descriptor::Wv(Wv::new(version, program)?))
+    }
+
+    /// Serializes the descriptor without the trailing `#checksum`
+    pub fn to_string_no_checksum(&self) -> String {
+        match *self {
+            Descriptor::Bare(ref bare) => bare.to_string_no_checksum(),
+            Descriptor::Pkh(ref pkh) => pkh.to_string_no_checksum(),
+            Descriptor::Wpkh(ref wpkh) => wpkh.to_string_no_checksum(),
+            Descriptor::Sh(ref sh) => sh.to_string_no_checksum(),
+            Descriptor::Wsh(ref wsh) => wsh.to_string_no_checksum(),
+            Descriptor::Tr(ref tr) => tr.to_string_no_checksum(),
+            Descriptor::RawTr(ref rawtr) => rawtr.to_string_no_checksum(),
+            Descriptor::Raw(ref raw) => raw.to_string_no_checksum(),
+            Descriptor::Addr(ref addr) => addr.to_string_no_checksum(),
+            Descriptor::Wv(ref wv) => wv.to_string_no_checksum(),
+        }
+    }
+
+    /// Serializes the descriptor followed by its `#checksum`, as expected by
+    /// Bitcoin Core. This is the same as the `Display` output.
+    pub fn to_string_with_checksum(&self) -> String {
+        self.to_string()
+    }
+
     /// Get the [DescriptorType] of [Descriptor]
     pub fn desc_type(&self) -> DescriptorType {
         match *self {
@@ -293,7 +534,47 @@ impl<Pk: MiniscriptKey> Descriptor<Pk> {
                 WshInner::Ms(ref _ms) => DescriptorType::Wsh,
             },
             Descriptor::Tr(ref _tr) => DescriptorType::Tr,
+            Descriptor::RawTr(ref _rawtr) => DescriptorType::RawTr,
+            Descriptor::Raw(ref _raw) => DescriptorType::Raw,
+            Descriptor::Addr(ref _addr) => DescriptorType::Addr,
+            Descriptor::Wv(ref wv) => DescriptorType::Wv(wv.version()),
+        }
+    }
+
+    /// Get the [`SpendModel`] of the descriptor.
+    ///
+    /// For `raw` and `addr` descriptors this is what the script pubkey tells:
+    /// its witness version, whether it is a p2sh output, and whether it is a
+    /// taproot output, whose key spend is always available by consensus.
+    pub fn spend_model(&self) -> SpendModel {
+        let desc_type = self.desc_type();
+        let mut model = SpendModel {
+            desc_type,
+            witness_version: desc_type.segwit_version(),
+            key_spend: false,
+            tap_leaves: 0,
+            needs_redeem_script: false,
+            nested: false,
+        };
+        match *self {
+            Descriptor::Bare(_) | Descriptor::Pkh(_) | Descriptor::Wpkh(_) => {}
+            Descriptor::Wsh(_) | Descriptor::Wv(_) => {}
+            Descriptor::Sh(ref sh) => {
+                model.needs_redeem_script = true;
+                model.nested = match sh.as_inner() {
+                    ShInner::Wsh(_) | ShInner::Wpkh(_) => true,
+                    ShInner::SortedMulti(_) | ShInner::Ms(_) => false,
+                };
+            }
+            Descriptor::Tr(ref tr) => {
+                model.key_spend = true;
+                model.tap_leaves = tr.iter_leaves().count();
+            }
+            Descriptor::RawTr(_) => model.key_spend = true,
+            Descriptor::Raw(ref raw) => model.set_script_pubkey(&raw.script_pubkey()),
+            Descriptor::Addr(ref addr) => model.set_script_pubkey(&addr.script_pubkey()),
         }
+        model
     }
 
     /// Checks whether the descriptor is safe.
@@ -306,15 +587,77 @@ impl<Pk: MiniscriptKey> Descriptor<Pk> {
     /// In general, all the guarantees of miniscript hold only for safe scripts.
     /// The signer may not be able to find satisfactions even if one exists.
     pub fn sanity_check(&self) -> Result<(), Error> {
+        self.sanity_check_with(&RuleSet::Standard)
+    }
+
+    /// Checks whether the descriptor is safe as [`Descriptor::sanity_check`]
+    /// does, checking whether the spend paths are possible under `rules`
+    /// rather than under the standardness and consensus rules.
+    pub fn sanity_check_with(&self, rules: &RuleSet) -> Result<(), Error> {
         match *self {
-            Descriptor::Bare(ref bare) => bare.sanity_check(),
+            Descriptor::Bare(ref bare) => bare.sanity_check_with(rules),
             Descriptor::Pkh(_) => Ok(()),
             Descriptor::Wpkh(ref wpkh) => wpkh.sanity_check(),
-            Descriptor::Wsh(ref wsh) => wsh.sanity_check(),
-            Descriptor::Sh(ref sh) => sh.sanity_check(),
-            Descriptor::Tr(ref tr) => tr.sanity_check(),
+            Descriptor::Wsh(ref wsh) => wsh.sanity_check_with(rules),
+            Descriptor::Sh(ref sh) => sh.sanity_check_with(rules),
+            Descriptor::Tr(ref tr) => tr.sanity_check_with(rules),
+            Descriptor::RawTr(ref rawtr) => rawtr.sanity_check(),
+            Descriptor::Raw(_) | Descriptor::Addr(_) | Descriptor::Wv(_) => Ok(()),
+        }
+    }
+
+    /// Returns the canonical form of the descriptor.
+    ///
+    /// Descriptors which only differ in the order of the keys of a
+    /// `sortedmulti` have the same canonical form, and so the same canonical
+    /// string. Keys are compared with the `Ord` implementation of `Pk`. The
+    /// `c:pk_k` and `c:pk_h` wrappers are always displayed as `pk` and `pkh`.
+    ///
+    /// Only rewrites which keep the script pubkey are made: fragments such as
+    /// `and_v(v:1,X)` are kept as they are, since dropping them would change
+    /// the script and the address of the descriptor.
+    pub fn canonicalize(&self) -> Result<Descriptor<Pk>, Error> {
+        fn sorted<Pk: MiniscriptKey>(pks: &[Pk]) -> Vec<Pk> {
+            let mut pks = pks.to_vec();
+            pks.sort();
+            pks
+        }
+
+        match *self {
+            Descriptor::Wsh(ref wsh) => match *wsh.as_inner() {
+                WshInner::SortedMulti(ref smv) => {
+                    Descriptor::new_wsh_sortedmulti(smv.k, sorted(&smv.pks))
+                }
+                WshInner::Ms(_) => Ok(self.clone()),
+            },
+            Descriptor::Sh(ref sh) => match *sh.as_inner() {
+                ShInner::Wsh(ref wsh) => match *wsh.as_inner() {
+                    WshInner::SortedMulti(ref smv) => {
+                        Descriptor::new_sh_wsh_sortedmulti(smv.k, sorted(&smv.pks))
+                    }
+                    WshInner::Ms(_) => Ok(self.clone()),
+                },
+                ShInner::SortedMulti(ref smv) => {
+                    Descriptor::new_sh_sortedmulti(smv.k, sorted(&smv.pks))
+                }
+                ShInner::Wpkh(_) | ShInner::Ms(_) => Ok(self.clone()),
+            },
+            Descriptor::Bare(_)
+            | Descriptor::Pkh(_)
+            | Descriptor::Wpkh(_)
+            | Descriptor::Tr(_)
+            | Descriptor::RawTr(_)
+            | Descriptor::Raw(_)
+            | Descriptor::Addr(_)
+            | Descriptor::Wv(_) => Ok(self.clone()),
         }
     }
+
+    /// Whether the two descriptors have the same [canonical
+    /// form](Descriptor::canonicalize).
+    pub fn canonical_eq(&self, other: &Descriptor<Pk>) -> Result<bool, Error> {
+        Ok(self.canonicalize()? == other.canonicalize()?)
+    }
 }
 
 impl<Pk: MiniscriptKey + ToPublicKey> Descriptor<Pk> {
@@ -332,6 +675,10 @@ impl<Pk: MiniscriptKey + ToPublicKey> Descriptor<Pk> {
             Descriptor::Wsh(ref wsh) => Ok(wsh.address(network)),
             Descriptor::Sh(ref sh) => Ok(sh.address(network)),
             Descriptor::Tr(ref tr) => Ok(tr.address(network)),
+            Descriptor::RawTr(ref rawtr) => Ok(rawtr.address(network)),
+            Descriptor::Raw(ref raw) => raw.address(network),
+            Descriptor::Addr(ref addr) => addr.address(network),
+            Descriptor::Wv(ref wv) => Ok(wv.address(network)),
         }
     }
 
@@ -344,9 +691,41 @@ impl<Pk: MiniscriptKey + ToPublicKey> Descriptor<Pk> {
             Descriptor::Wsh(ref wsh) => wsh.script_pubkey(),
             Descriptor::Sh(ref sh) => sh.script_pubkey(),
             Descriptor::Tr(ref tr) => tr.script_pubkey(),
+            Descriptor::RawTr(ref rawtr) => rawtr.script_pubkey(),
+            Descriptor::Raw(ref raw) => raw.script_pubkey(),
+            Descriptor::Addr(ref addr) => addr.script_pubkey(),
+            Descriptor::Wv(ref wv) => wv.script_pubkey(),
         }
     }
 
+    /// The minimum value of an output of the descriptor for it to be relayed
+    /// by Bitcoin Core, as computed by [`Script::dust_value`].
+    ///
+    /// This is a relay policy, based on a fixed estimate of the spend cost of
+    /// each kind of output. See [`Descriptor::economic_dust`] for the value
+    /// below which the output is not worth spending at a given feerate.
+    pub fn dust_value(&self) -> Amount {
+        self.script_pubkey().dust_value()
+    }
+
+    /// The fee of spending an output of the descriptor at `sat_per_kvb`
+    /// satoshis per 1000 virtual bytes, using the worst-case input weight of
+    /// [`SatisfactionWeight::input_weight`]. An output with a lower value
+    /// costs more to spend than it is worth.
+    ///
+    /// # Errors
+    /// When the descriptor is impossible to satisfy, as in
+    /// [`Descriptor::satisfaction_weight`], and [`Error::FeeOverflow`] when
+    /// the fee does not fit in a `u64`.
+    pub fn economic_dust(&self, sat_per_kvb: u64) -> Result<Amount, Error> {
+        let vbytes = (self.satisfaction_weight()?.input_weight() as u64 + 3) / 4;
+        let fee = vbytes
+            .checked_mul(sat_per_kvb)
+            .and_then(|fee| fee.checked_add(999))
+            .ok_or(Error::FeeOverflow)?;
+        Ok(Amount::from_sat(fee / 1000))
+    }
+
     /// Computes the scriptSig that will be in place for an unsigned input
     /// spending an output with this descriptor. For pre-segwit descriptors,
     /// which use the scriptSig for signatures, this returns the empty script.
@@ -362,6 +741,10 @@ impl<Pk: MiniscriptKey + ToPublicKey> Descriptor<Pk> {
             Descriptor::Wsh(_) => Script::new(),
             Descriptor::Sh(ref sh) => sh.unsigned_script_sig(),
             Descriptor::Tr(_) => Script::new(),
+            Descriptor::RawTr(_) => Script::new(),
+            Descriptor::Raw(_) => Script::new(),
+            Descriptor::Addr(_) => Script::new(),
+            Descriptor::Wv(_) => Script::new(),
         }
     }
 
@@ -379,6 +762,10 @@ impl<Pk: MiniscriptKey + ToPublicKey> Descriptor<Pk> {
             Descriptor::Wsh(ref wsh) => Ok(wsh.inner_script()),
             Descriptor::Sh(ref sh) => Ok(sh.inner_script()),
             Descriptor::Tr(_) => Err(Error::TrNoScriptCode),
+            Descriptor::RawTr(_) => Err(Error::TrNoScriptCode),
+            Descriptor::Raw(ref raw) => Ok(raw.script_pubkey()),
+            Descriptor::Addr(ref addr) => Ok(addr.script_pubkey()),
+            Descriptor::Wv(ref wv) => Ok(wv.script_pubkey()),
         }
     }
 
@@ -397,6 +784,10 @@ impl<Pk: MiniscriptKey + ToPublicKey> Descriptor<Pk> {
             Descriptor::Wsh(ref wsh) => Ok(wsh.ecdsa_sighash_script_code()),
             Descriptor::Sh(ref sh) => Ok(sh.ecdsa_sighash_script_code()),
             Descriptor::Tr(_) => Err(Error::TrNoScriptCode),
+            Descriptor::RawTr(_) => Err(Error::TrNoScriptCode),
+            Descriptor::Raw(ref raw) => Ok(raw.script_pubkey()),
+            Descriptor::Addr(ref addr) => Ok(addr.script_pubkey()),
+            Descriptor::Wv(ref wv) => Ok(wv.script_pubkey()),
         }
     }
 
@@ -414,6 +805,10 @@ impl<Pk: MiniscriptKey + ToPublicKey> Descriptor<Pk> {
             Descriptor::Wsh(ref wsh) => wsh.get_satisfaction(satisfier),
             Descriptor::Sh(ref sh) => sh.get_satisfaction(satisfier),
             Descriptor::Tr(ref tr) => tr.get_satisfaction(satisfier),
+            Descriptor::RawTr(ref rawtr) => rawtr.get_satisfaction(satisfier),
+            Descriptor::Raw(_) | Descriptor::Addr(_) | Descriptor::Wv(_) => {
+                Err(Error::ImpossibleSatisfaction)
+            }
         }
     }
 
@@ -431,6 +826,31 @@ impl<Pk: MiniscriptKey + ToPublicKey> Descriptor<Pk> {
             Descriptor::Wsh(ref wsh) => wsh.get_satisfaction_mall(satisfier),
             Descriptor::Sh(ref sh) => sh.get_satisfaction_mall(satisfier),
             Descriptor::Tr(ref tr) => tr.get_satisfaction_mall(satisfier),
+            Descriptor::RawTr(ref rawtr) => rawtr.get_satisfaction_mall(satisfier),
+            Descriptor::Raw(_) | Descriptor::Addr(_) | Descriptor::Wv(_) => {
+                Err(Error::ImpossibleSatisfaction)
+            }
+        }
+    }
+
+    // Helper function to get a satisfaction whose witness is checked against
+    // the limits of `rules`
+    pub(crate) fn satisfaction_with<S>(
+        &self,
+        satisfier: S,
+        allow_mall: bool,
+        rules: &RuleSet,
+    ) -> Result<(Vec<Vec<u8>>, Script), Error>
+    where
+        S: Satisfier<Pk>,
+    {
+        match *self {
+            Descriptor::Bare(ref bare) => bare.satisfaction_with(satisfier, allow_mall, rules),
+            Descriptor::Wsh(ref wsh) => wsh.satisfaction_with(satisfier, allow_mall, rules),
+            Descriptor::Sh(ref sh) => sh.satisfaction_with(satisfier, allow_mall, rules),
+            Descriptor::Tr(ref tr) => tr::best_tap_spend(tr, satisfier, allow_mall, rules),
+            _ if allow_mall => self.get_satisfaction_mall(satisfier),
+            _ => self.get_satisfaction(satisfier),
         }
     }
 
@@ -457,13 +877,46 @@ impl<Pk: MiniscriptKey + ToPublicKey> Descriptor<Pk> {
     /// # Errors
     /// When the descriptor is impossible to safisfy (ex: sh(OP_FALSE)).
     pub fn max_satisfaction_weight(&self) -> Result<usize, Error> {
+        Ok(self.satisfaction_weight()?.weight())
+    }
+
+    /// Computes an upper bound on the weight a satisfaction adds to an
+    /// unsigned input spending the descriptor.
+    ///
+    /// Unlike [`Descriptor::max_satisfaction_weight`], this excludes the
+    /// one-byte scriptSig length and witness element count every input has
+    /// before it is signed, so that it can be added directly to the weight
+    /// of an unsigned transaction. The weight of the witness alone and of the
+    /// whole input are given by [`SatisfactionWeight::witness_weight`] and
+    /// [`SatisfactionWeight::input_weight`] on the result of
+    /// [`Descriptor::satisfaction_weight`].
+    ///
+    /// # Errors
+    /// When the descriptor is impossible to safisfy (ex: sh(OP_FALSE)).
+    pub fn max_weight_to_satisfy(&self) -> Result<usize, Error> {
+        Ok(self.satisfaction_weight()?.weight_to_satisfy())
+    }
+
+    /// Computes an upper bound on the size of a satisfaction of the descriptor,
+    /// split into its scriptSig and witness parts.
+    ///
+    /// For taproot descriptors this is the most expensive spend path; the
+    /// weight of each path is given by [`Tr::leaf_satisfaction_weights`].
+    ///
+    /// # Errors
+    /// When the descriptor is impossible to safisfy (ex: sh(OP_FALSE)).
+    pub fn satisfaction_weight(&self) -> Result<SatisfactionWeight, Error> {
         let weight = match *self {
-            Descriptor::Bare(ref bare) => bare.max_satisfaction_weight()?,
-            Descriptor::Pkh(ref pkh) => pkh.max_satisfaction_weight(),
-            Descriptor::Wpkh(ref wpkh) => wpkh.max_satisfaction_weight(),
-            Descriptor::Wsh(ref wsh) => wsh.max_satisfaction_weight()?,
-            Descriptor::Sh(ref sh) => sh.max_satisfaction_weight()?,
-            Descriptor::Tr(ref tr) => tr.max_satisfaction_weight()?,
+            Descriptor::Bare(ref bare) => bare.satisfaction_weight()?,
+            Descriptor::Pkh(ref pkh) => pkh.satisfaction_weight(),
+            Descriptor::Wpkh(ref wpkh) => wpkh.satisfaction_weight(),
+            Descriptor::Wsh(ref wsh) => wsh.satisfaction_weight()?,
+            Descriptor::Sh(ref sh) => sh.satisfaction_weight()?,
+            Descriptor::Tr(ref tr) => tr.satisfaction_weight()?,
+            Descriptor::RawTr(ref rawtr) => rawtr.satisfaction_weight(),
+            Descriptor::Raw(_) | Descriptor::Addr(_) | Descriptor::Wv(_) => {
+                return Err(Error::ImpossibleSatisfaction)
+            }
         };
         Ok(weight)
     }
@@ -479,21 +932,23 @@ where
     ///
     /// # Panics
     ///
-    /// If `fpk` returns an uncompressed key when converting to a Segwit descriptor.
-    /// To prevent this panic, ensure `fpk` returns an error in this case instead.
-    fn translate_pk<Fpk, Fpkh, E>(&self, mut fpk: Fpk, mut fpkh: Fpkh) -> Result<Descriptor<Q>, E>
+    /// If the translator returns an uncompressed key when converting to a Segwit
+    /// descriptor. To prevent this panic, ensure it returns an error in this case instead.
+    fn translate_pk<T, E>(&self, t: &mut T) -> Result<Descriptor<Q>, E>
     where
-        Fpk: FnMut(&P) -> Result<Q, E>,
-        Fpkh: FnMut(&P::Hash) -> Result<Q::Hash, E>,
-        Q: MiniscriptKey,
+        T: Translator<P, Q, E> + ?Sized,
     {
         let desc = match *self {
-            Descriptor::Bare(ref bare) => Descriptor::Bare(bare.translate_pk(&mut fpk, &mut fpkh)?),
-            Descriptor::Pkh(ref pk) => Descriptor::Pkh(pk.translate_pk(&mut fpk, &mut fpkh)?),
-            Descriptor::Wpkh(ref pk) => Descriptor::Wpkh(pk.translate_pk(&mut fpk, &mut fpkh)?),
-            Descriptor::Sh(ref sh) => Descriptor::Sh(sh.translate_pk(&mut fpk, &mut fpkh)?),
-            Descriptor::Wsh(ref wsh) => Descriptor::Wsh(wsh.translate_pk(&mut fpk, &mut fpkh)?),
-            Descriptor::Tr(ref tr) => Descriptor::Tr(tr.translate_pk(&mut fpk, &mut fpkh)?),
+            Descriptor::Bare(ref bare) => Descriptor::Bare(bare.translate_pk(t)?),
+            Descriptor::Pkh(ref pk) => Descriptor::Pkh(pk.translate_pk(t)?),
+            Descriptor::Wpkh(ref pk) => Descriptor::Wpkh(pk.translate_pk(t)?),
+            Descriptor::Sh(ref sh) => Descriptor::Sh(sh.translate_pk(t)?),
+            Descriptor::Wsh(ref wsh) => Descriptor::Wsh(wsh.translate_pk(t)?),
+            Descriptor::Tr(ref tr) => Descriptor::Tr(tr.translate_pk(t)?),
+            Descriptor::RawTr(ref rawtr) => Descriptor::RawTr(rawtr.translate_pk(t)?),
+            Descriptor::Raw(ref raw) => Descriptor::Raw(raw.clone()),
+            Descriptor::Addr(ref addr) => Descriptor::Addr(addr.clone()),
+            Descriptor::Wv(ref wv) => Descriptor::Wv(wv.clone()),
         };
         Ok(desc)
     }
@@ -512,10 +967,124 @@ impl<Pk: MiniscriptKey> ForEachKey<Pk> for Descriptor<Pk> {
             Descriptor::Wsh(ref wsh) => wsh.for_each_key(pred),
             Descriptor::Sh(ref sh) => sh.for_each_key(pred),
             Descriptor::Tr(ref tr) => tr.for_each_key(pred),
+            Descriptor::RawTr(ref rawtr) => rawtr.for_each_key(pred),
+            Descriptor::Raw(_) | Descriptor::Addr(_) | Descriptor::Wv(_) => true,
+        }
+    }
+}
+
+/// Whether a set of signers can satisfy a descriptor, as reported by
+/// [`Descriptor::can_be_satisfied_by`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SatisfiabilityResult {
+    /// The signers can satisfy the descriptor on their own
+    Satisfiable,
+    /// The signers can satisfy the descriptor once the given policy, made of
+    /// `after` and `older` conditions only, is met
+    Timelocked(semantic::Policy<DescriptorPublicKey>),
+    /// The signers cannot satisfy the descriptor
+    Unsatisfiable,
+}
+
+/// An error replacing a key of a descriptor, as returned by
+/// [`Descriptor::replace_key`]
+#[derive(Debug, PartialEq)]
+pub enum KeyRotationError {
+    /// The key to replace does not appear in the descriptor
+    KeyNotFound,
+    /// The descriptor with the key replaced is invalid, e.g. because the new
+    /// key is not valid in the context it is used in
+    InvalidDescriptor(Error),
+}
+
+impl fmt::Display for KeyRotationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            KeyRotationError::KeyNotFound => f.write_str("key not found in the descriptor"),
+            KeyRotationError::InvalidDescriptor(ref e) => {
+                write!(f, "invalid descriptor after replacing the key: {}", e)
+            }
         }
     }
 }
 
+#[cfg(feature = "std")]
+impl error::Error for KeyRotationError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            KeyRotationError::KeyNotFound => None,
+            KeyRotationError::InvalidDescriptor(ref e) => Some(e),
+        }
+    }
+}
+
+// Replace the keys of `policy` by whether their master fingerprint is one of
+// `signers`, and the hash preimages, which no signer is assumed to know, by
+// unsatisfiable conditions
+fn signers_policy(
+    policy: semantic::Policy<DescriptorPublicKey>,
+    signers: &BTreeSet<bip32::Fingerprint>,
+) -> semantic::Policy<DescriptorPublicKey> {
+    match policy {
+        semantic::Policy::KeyHash(ref pk) if signers.contains(&pk.master_fingerprint()) => {
+            semantic::Policy::Trivial
+        }
+        semantic::Policy::KeyHash(..)
+        | semantic::Policy::Sha256(..)
+        | semantic::Policy::Hash256(..)
+        | semantic::Policy::Ripemd160(..)
+        | semantic::Policy::Hash160(..) => semantic::Policy::Unsatisfiable,
+        semantic::Policy::Threshold(k, subs) => semantic::Policy::Threshold(
+            k,
+            subs.into_iter()
+                .map(|sub| signers_policy(sub, signers))
+                .collect(),
+        ),
+        policy => policy,
+    }
+}
+
+// Translator deriving the wildcards of descriptor keys at an index
+struct Derivator(u32);
+
+impl Translator<DescriptorPublicKey, DerivedDescriptorKey, ()> for Derivator {
+    fn pk(&mut self, pk: &DescriptorPublicKey) -> Result<DerivedDescriptorKey, ()> {
+        Ok(pk.clone().derive(self.0))
+    }
+
+    fn pkh(&mut self, pkh: &DescriptorPublicKey) -> Result<DerivedDescriptorKey, ()> {
+        Ok(pkh.clone().derive(self.0))
+    }
+
+    translate_hash_clone!(DescriptorPublicKey, DerivedDescriptorKey, ());
+}
+
+// Translator computing the public keys of descriptor keys, deriving their
+// wildcards at `index` if given; keys with wildcards fail to translate
+// otherwise
+pub(crate) struct PublicKeyDerivator<'a, C: secp256k1::Verification> {
+    pub(crate) secp: &'a secp256k1::Secp256k1<C>,
+    pub(crate) index: Option<u32>,
+}
+
+impl<'a, C: secp256k1::Verification>
+    Translator<DescriptorPublicKey, bitcoin::PublicKey, ConversionError>
+    for PublicKeyDerivator<'a, C>
+{
+    fn pk(&mut self, pk: &DescriptorPublicKey) -> Result<bitcoin::PublicKey, ConversionError> {
+        match self.index {
+            Some(index) => pk.clone().derive(index).derive_public_key(self.secp),
+            None => pk.derive_public_key(self.secp),
+        }
+    }
+
+    fn pkh(&mut self, pkh: &DescriptorPublicKey) -> Result<hash160::Hash, ConversionError> {
+        self.pk(pkh).map(|pk| pk.to_pubkeyhash())
+    }
+
+    translate_hash_clone!(DescriptorPublicKey, bitcoin::PublicKey, ConversionError);
+}
+
 impl Descriptor<DescriptorPublicKey> {
     /// Whether or not the descriptor has any wildcards
     pub fn is_deriveable(&self) -> bool {
@@ -529,7 +1098,137 @@ impl Descriptor<DescriptorPublicKey> {
     /// In most cases, you would want to use [`Self::derived_descriptor`] directly to obtain
     /// a [`Descriptor<bitcoin::PublicKey>`]
     pub fn derive(&self, index: u32) -> Descriptor<DerivedDescriptorKey> {
-        self.translate_pk2_infallible(|pk| pk.clone().derive(index))
+        self.translate_pk(&mut Derivator(index))
+            .expect("Derivation at an index cannot fail")
+    }
+
+    /// Derives all wildcard keys in the descriptor at each index of `indices`
+    ///
+    /// Equivalent to calling [`Self::derive`] for each index, except that the
+    /// extended keys are derived up to the parent of the wildcard only once,
+    /// leaving a single derivation step per key for each index. As a result,
+    /// the keys of the returned descriptors are displayed with their path
+    /// moved to the origin, but produce the same scripts.
+    ///
+    /// Panics if given an index ≥ 2^31
+    pub fn derive_batch(&self, indices: Range<u32>) -> Vec<Descriptor<DerivedDescriptorKey>> {
+        struct PathDerivator(secp256k1::Secp256k1<secp256k1::VerifyOnly>);
+
+        impl Translator<DescriptorPublicKey, DescriptorPublicKey, ()> for PathDerivator {
+            fn pk(&mut self, pk: &DescriptorPublicKey) -> Result<DescriptorPublicKey, ()> {
+                Ok(pk.clone().derive_path(&self.0))
+            }
+
+            fn pkh(&mut self, pkh: &DescriptorPublicKey) -> Result<DescriptorPublicKey, ()> {
+                Ok(pkh.clone().derive_path(&self.0))
+            }
+
+            translate_hash_clone!(DescriptorPublicKey, DescriptorPublicKey, ());
+        }
+
+        let mut derivator = PathDerivator(secp256k1::Secp256k1::verification_only());
+        let parent = self
+            .translate_pk(&mut derivator)
+            .expect("Path derivation cannot fail");
+        indices.map(|index| parent.derive(index)).collect()
+    }
+
+    /// Whether the signers with the master fingerprints `keys` can satisfy
+    /// the descriptor, and which timelocks must be met for them to do so.
+    ///
+    /// Hash preimages are assumed to be unknown to the signers.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the descriptor cannot be lifted
+    /// to a semantic policy, for instance when it mixes timelocks.
+    pub fn can_be_satisfied_by(
+        &self,
+        keys: &BTreeSet<bip32::Fingerprint>,
+    ) -> Result<SatisfiabilityResult, Error> {
+        Ok(match signers_policy(self.lift()?, keys).normalized() {
+            semantic::Policy::Trivial => SatisfiabilityResult::Satisfiable,
+            semantic::Policy::Unsatisfiable => SatisfiabilityResult::Unsatisfiable,
+            timelocks => SatisfiabilityResult::Timelocked(timelocks),
+        })
+    }
+
+    /// Whether the two descriptors derive the same script pubkeys at every
+    /// index.
+    ///
+    /// Keys are compared by the public keys they derive, regardless of their
+    /// origin, of the metadata of xpubs, or of whether an xpub is given with a
+    /// derivation path or already derived along it. The descriptors are then
+    /// compared by their [canonical form](Descriptor::canonicalize), so
+    /// descriptors deriving the same scripts from differently written
+    /// miniscripts may still be reported as different.
+    pub fn derives_same_scripts(
+        &self,
+        other: &Descriptor<DescriptorPublicKey>,
+    ) -> Result<bool, Error> {
+        self.normalized(None).canonical_eq(&other.normalized(None))
+    }
+
+    /// Whether `derived`, a descriptor without wildcards, is the descriptor
+    /// of `self` at one of its indices, i.e. whether it derives the same
+    /// scripts as [`Self::derive`] at that index.
+    ///
+    /// The index is looked up from the last derivation step of the keys of
+    /// `derived`, as in `xpub/0/5` or `[d34db33f/84'/0'/0'/0/5]03ab..`. A
+    /// derived descriptor whose keys have no derivation information is only
+    /// contained in a descriptor without wildcards: its index can be found
+    /// from its script pubkey with [`Self::find_derivation_index_for_spk`]
+    /// instead.
+    pub fn contains(&self, derived: &Descriptor<DescriptorPublicKey>) -> Result<bool, Error> {
+        if derived.is_deriveable() {
+            return Ok(false);
+        }
+        if !self.is_deriveable() {
+            return self.derives_same_scripts(derived);
+        }
+
+        let mut indices = BTreeSet::new();
+        derived.for_each_key(|key| {
+            let path = key.as_key().full_derivation_path();
+            if let Some(&bip32::ChildNumber::Normal { index }) = path.as_ref().last() {
+                indices.insert(index);
+            }
+            true
+        });
+        let derived = derived.normalized(None);
+        for index in indices {
+            if self.normalized(Some(index)).canonical_eq(&derived)? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    // The descriptor with its keys normalized by `DescriptorPublicKey::normalized`
+    fn normalized(&self, index: Option<u32>) -> Descriptor<DescriptorPublicKey> {
+        struct KeyNormalizer {
+            secp: secp256k1::Secp256k1<secp256k1::VerifyOnly>,
+            index: Option<u32>,
+        }
+
+        impl Translator<DescriptorPublicKey, DescriptorPublicKey, ()> for KeyNormalizer {
+            fn pk(&mut self, pk: &DescriptorPublicKey) -> Result<DescriptorPublicKey, ()> {
+                Ok(pk.clone().normalized(&self.secp, self.index))
+            }
+
+            fn pkh(&mut self, pkh: &DescriptorPublicKey) -> Result<DescriptorPublicKey, ()> {
+                self.pk(pkh)
+            }
+
+            translate_hash_clone!(DescriptorPublicKey, DescriptorPublicKey, ());
+        }
+
+        let mut normalizer = KeyNormalizer {
+            secp: secp256k1::Secp256k1::verification_only(),
+            index,
+        };
+        self.translate_pk(&mut normalizer)
+            .expect("Normalizing keys cannot fail")
     }
 
     /// Derive a [`Descriptor`] with a concrete [`bitcoin::PublicKey`] at a given index
@@ -561,10 +1260,32 @@ impl Descriptor<DescriptorPublicKey> {
         secp: &secp256k1::Secp256k1<C>,
         index: u32,
     ) -> Result<Descriptor<bitcoin::PublicKey>, ConversionError> {
-        let derived = self
-            .derive(index)
-            .translate_pk2(|xpk| xpk.derive_public_key(secp))?;
-        Ok(derived)
+        self.translate_pk(&mut PublicKeyDerivator {
+            secp,
+            index: Some(index),
+        })
+    }
+
+    /// Derive only the script pubkey of the descriptor at a given index,
+    /// equivalent to calling [`Descriptor::script_pubkey`] on the result of
+    /// [`Self::derived_descriptor`].
+    ///
+    /// For [`Tr`] descriptors, the spend info is not computed, and the merkle
+    /// root of a tree without wildcards is cached, so that only the internal
+    /// key is derived and tweaked at each index.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if hardened derivation is attempted.
+    pub fn script_pubkey_at_index<C: secp256k1::Verification>(
+        &self,
+        secp: &secp256k1::Secp256k1<C>,
+        index: u32,
+    ) -> Result<Script, ConversionError> {
+        match *self {
+            Descriptor::Tr(ref tr) => tr.script_pubkey_at_index(secp, index),
+            _ => Ok(self.derived_descriptor(secp, index)?.script_pubkey()),
+        }
     }
 
     /// Parse a descriptor that may contain secret keys
@@ -575,88 +1296,566 @@ impl Descriptor<DescriptorPublicKey> {
         secp: &secp256k1::Secp256k1<C>,
         s: &str,
     ) -> Result<(Descriptor<DescriptorPublicKey>, KeyMap), Error> {
-        let parse_key = |s: &String,
-                         key_map: &mut KeyMap|
-         -> Result<DescriptorPublicKey, DescriptorKeyParseError> {
-            let (public_key, secret_key) = match DescriptorSecretKey::from_str(s) {
-                Ok(sk) => (sk.to_public(secp)?, Some(sk)),
-                Err(_) => (DescriptorPublicKey::from_str(s)?, None),
-            };
+        // A key of the descriptor, kept as a string until it is parsed by
+        // `KeyParser`
+        #[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+        struct KeyStr {
+            key: String,
+        }
 
-            if let Some(secret_key) = secret_key {
-                key_map.insert(public_key.clone(), secret_key);
+        impl fmt::Display for KeyStr {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str(&self.key)
             }
+        }
 
-            Ok(public_key)
-        };
-
-        let mut keymap_pk = KeyMap::new();
-        let mut keymap_pkh = KeyMap::new();
-
-        let descriptor = Descriptor::<String>::from_str(s)?;
-        let descriptor = descriptor
-            .translate_pk(
-                |pk| parse_key(pk, &mut keymap_pk),
-                |pkh| parse_key(pkh, &mut keymap_pkh),
-            )
-            .map_err(|e| Error::Unexpected(e.to_string()))?;
+        impl FromStr for KeyStr {
+            type Err = core::convert::Infallible;
 
-        keymap_pk.extend(keymap_pkh.into_iter());
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                Ok(KeyStr { key: s.to_owned() })
+            }
+        }
 
-        Ok((descriptor, keymap_pk))
-    }
+        impl MiniscriptKey for KeyStr {
+            type Hash = KeyStr;
+            type Sha256 = bitcoin::hashes::sha256::Hash;
+            type Hash256 = crate::hash256::Hash;
+            type Ripemd160 = bitcoin::hashes::ripemd160::Hash;
+            type Hash160 = hash160::Hash;
 
-    /// Serialize a descriptor to string with its secret keys
-    pub fn to_string_with_secret(&self, key_map: &KeyMap) -> String {
-        fn key_to_string(pk: &DescriptorPublicKey, key_map: &KeyMap) -> Result<String, ()> {
-            Ok(match key_map.get(pk) {
-                Some(secret) => secret.to_string(),
-                None => pk.to_string(),
-            })
+            fn to_pubkeyhash(&self) -> Self::Hash {
+                self.clone()
+            }
         }
 
-        let descriptor = self
-            .translate_pk::<_, _, ()>(
-                |pk| key_to_string(pk, key_map),
-                |pkh| key_to_string(pkh, key_map),
-            )
-            .expect("Translation to string cannot fail");
+        struct KeyParser<'a, C: secp256k1::Signing> {
+            secp: &'a secp256k1::Secp256k1<C>,
+            key_map: KeyMap,
+            // The position in `s` of the first occurrence of every terminal
+            positions: BTreeMap<&'a str, usize>,
+        }
 
-        descriptor.to_string()
-    }
+        impl<'a, C: secp256k1::Signing> KeyParser<'a, C> {
+            fn parse(&mut self, pk: &str) -> Result<DescriptorPublicKey, DescriptorKeyParseError> {
+                let (public_key, secret_key) = match DescriptorSecretKey::from_str(pk) {
+                    Ok(sk) => (sk.to_public(self.secp)?, Some(sk)),
+                    Err(_) => (DescriptorPublicKey::from_str(pk)?, None),
+                };
 
-    /// Utility method for deriving the descriptor at each index in a range to find one matching
-    /// `script_pubkey`.
-    ///
-    /// If it finds a match then it returns the index it was derived at and the concrete
-    /// descriptor at that index. If the descriptor is non-derivable then it will simply check the
-    /// script pubkey against the descriptor and return it if it matches (in this case the index
-    /// returned will be meaningless).
-    pub fn find_derivation_index_for_spk<C: secp256k1::Verification>(
-        &self,
-        secp: &secp256k1::Secp256k1<C>,
-        script_pubkey: &Script,
-        range: Range<u32>,
-    ) -> Result<Option<(u32, Descriptor<bitcoin::PublicKey>)>, ConversionError> {
-        let range = if self.is_deriveable() { range } else { 0..1 };
+                if let Some(secret_key) = secret_key {
+                    self.key_map.insert(public_key.clone(), secret_key);
+                }
 
-        for i in range {
-            let concrete = self.derived_descriptor(secp, i)?;
-            if &concrete.script_pubkey() == script_pubkey {
-                return Ok(Some((i, concrete)));
+                Ok(public_key)
             }
         }
 
-        Ok(None)
-    }
-}
+        impl<'a, C: secp256k1::Signing> Translator<KeyStr, DescriptorPublicKey, Error>
+            for KeyParser<'a, C>
+        {
+            fn pk(&mut self, pk: &KeyStr) -> Result<DescriptorPublicKey, Error> {
+                let position = self.positions.get(pk.key.as_str()).copied().unwrap_or(0);
+                self.parse(&pk.key)
+                    .map_err(|error| Error::BadDescriptorKey {
+                        position,
+                        error: error.offset_by(position),
+                    })
+            }
 
-impl<Pk> expression::FromTree for Descriptor<Pk>
-where
-    Pk: MiniscriptKey + str::FromStr,
+            fn pkh(&mut self, pkh: &KeyStr) -> Result<DescriptorPublicKey, Error> {
+                self.pk(pkh)
+            }
+
+            translate_hash_clone!(KeyStr, DescriptorPublicKey, Error);
+        }
+
+        // Record the position of the terminals of `tree`, which is at
+        // `offset` in `s`. The scripts of taproot leaves are terminals of the
+        // taproot tree, and are parsed for their own terminals.
+        fn add_positions<'a>(
+            tree: &expression::Tree<'a>,
+            offset: usize,
+            positions: &mut BTreeMap<&'a str, usize>,
+        ) {
+            let position = offset + tree.position;
+            if !tree.args.is_empty() {
+                for arg in &tree.args {
+                    add_positions(arg, offset, positions);
+                }
+            } else if tree.name.ends_with(')') {
+                let (start, end) = if tree.name.starts_with("leaf_ver(") {
+                    match tree.name.find(',') {
+                        Some(comma) => (comma + 1, tree.name.len() - 1),
+                        None => return,
+                    }
+                } else {
+                    (0, tree.name.len())
+                };
+                if let Ok(leaf) = expression::Tree::from_str(&tree.name[start..end]) {
+                    add_positions(&leaf, position + start, positions);
+                }
+            } else {
+                positions.entry(tree.name).or_insert(position);
+            }
+        }
+
+        let desc_str = verify_checksum(s)?;
+        let top = if desc_str.starts_with("tr(") {
+            tr::parse_tr_tree(desc_str)?
+        } else {
+            expression::Tree::from_str(desc_str)?
+        };
+        let descriptor: Descriptor<KeyStr> = expression::FromTree::from_tree(&top)?;
+
+        let mut parser = KeyParser {
+            secp,
+            key_map: KeyMap::new(),
+            positions: BTreeMap::new(),
+        };
+        add_positions(&top, 0, &mut parser.positions);
+        let descriptor = descriptor.translate_pk(&mut parser)?;
+
+        Ok((descriptor, parser.key_map))
+    }
+
+    /// Serialize a descriptor to string with its secret keys
+    pub fn to_string_with_secret(&self, key_map: &KeyMap) -> String {
+        struct KeyToString<'a>(&'a KeyMap);
+
+        impl<'a> Translator<DescriptorPublicKey, String, ()> for KeyToString<'a> {
+            fn pk(&mut self, pk: &DescriptorPublicKey) -> Result<String, ()> {
+                Ok(match self.0.get(pk) {
+                    Some(secret) => secret.to_string(),
+                    None => pk.to_string(),
+                })
+            }
+
+            fn pkh(&mut self, pkh: &DescriptorPublicKey) -> Result<String, ()> {
+                self.pk(pkh)
+            }
+
+            fn sha256(&mut self, sha256: &bitcoin::hashes::sha256::Hash) -> Result<String, ()> {
+                Ok(sha256.to_string())
+            }
+
+            fn hash256(&mut self, hash256: &crate::hash256::Hash) -> Result<String, ()> {
+                Ok(hash256.to_string())
+            }
+
+            fn ripemd160(
+                &mut self,
+                ripemd160: &bitcoin::hashes::ripemd160::Hash,
+            ) -> Result<String, ()> {
+                Ok(ripemd160.to_string())
+            }
+
+            fn hash160(&mut self, hash160: &hash160::Hash) -> Result<String, ()> {
+                Ok(hash160.to_string())
+            }
+        }
+
+        let descriptor = self
+            .translate_pk(&mut KeyToString(key_map))
+            .expect("Translation to string cannot fail");
+
+        descriptor.to_string()
+    }
+
+    /// Utility method for deriving the descriptor at each index in a range to find one matching
+    /// `script_pubkey`.
+    ///
+    /// If it finds a match then it returns the index it was derived at and the concrete
+    /// descriptor at that index. If the descriptor is non-derivable then it will simply check the
+    /// script pubkey against the descriptor and return it if it matches (in this case the index
+    /// returned will be meaningless).
+    pub fn find_derivation_index_for_spk<C: secp256k1::Verification>(
+        &self,
+        secp: &secp256k1::Secp256k1<C>,
+        script_pubkey: &Script,
+        range: Range<u32>,
+    ) -> Result<Option<(u32, Descriptor<bitcoin::PublicKey>)>, ConversionError> {
+        let range = if self.is_deriveable() { range } else { 0..1 };
+
+        for i in range {
+            if &self.script_pubkey_at_index(secp, i)? == script_pubkey {
+                return Ok(Some((i, self.derived_descriptor(secp, i)?)));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Infer a `rawtr` descriptor from a segwit v1 (taproot) `script_pubkey`.
+    ///
+    /// The output key is taken as-is, so the resulting descriptor can only be
+    /// used for key path spends.
+    ///
+    /// # Errors
+    /// When `script_pubkey` is not a v1 p2tr output or does not contain a
+    /// valid x-only public key.
+    pub fn rawtr_from_script_pubkey(script_pubkey: &Script) -> Result<Self, Error> {
+        if !script_pubkey.is_v1_p2tr() {
+            return Err(Error::BadDescriptor(
+                "script_pubkey is not a v1 p2tr output".to_string(),
+            ));
+        }
+        let output_key = secp256k1::XOnlyPublicKey::from_slice(&script_pubkey[2..])?;
+        Ok(Descriptor::new_rawtr(DescriptorPublicKey::Single(
+            SinglePub {
+                origin: None,
+                key: SinglePubKey::XOnly(output_key),
+            },
+        )))
+    }
+
+    /// Replace every occurrence of the key `old` in the descriptor by `new`,
+    /// e.g. to rotate the key of a compromised cosigner.
+    ///
+    /// # Errors
+    ///
+    /// When `old` does not appear in the descriptor, or when `new` is not
+    /// valid in a context `old` is used in (e.g. an uncompressed key inside
+    /// `wsh` or an x-only key outside of `tr`).
+    pub fn replace_key(
+        &self,
+        old: &DescriptorPublicKey,
+        new: DescriptorPublicKey,
+    ) -> Result<Descriptor<DescriptorPublicKey>, KeyRotationError> {
+        struct KeyReplacer<'a> {
+            old: &'a DescriptorPublicKey,
+            new: DescriptorPublicKey,
+            found: bool,
+        }
+
+        impl<'a> Translator<DescriptorPublicKey, DescriptorPublicKey, ()> for KeyReplacer<'a> {
+            fn pk(&mut self, pk: &DescriptorPublicKey) -> Result<DescriptorPublicKey, ()> {
+                if pk == self.old {
+                    self.found = true;
+                    Ok(self.new.clone())
+                } else {
+                    Ok(pk.clone())
+                }
+            }
+
+            fn pkh(&mut self, pkh: &DescriptorPublicKey) -> Result<DescriptorPublicKey, ()> {
+                self.pk(pkh)
+            }
+
+            translate_hash_clone!(DescriptorPublicKey, DescriptorPublicKey, ());
+        }
+
+        let mut replacer = KeyReplacer {
+            old,
+            new,
+            found: false,
+        };
+        let replaced = self
+            .translate_pk(&mut replacer)
+            .expect("Replacing keys cannot fail");
+        if !replacer.found {
+            return Err(KeyRotationError::KeyNotFound);
+        }
+        // Re-parsing the descriptor applies the context checks to the new key
+        Descriptor::from_str(&replaced.to_string()).map_err(KeyRotationError::InvalidDescriptor)
+    }
+}
+
+impl Descriptor<DerivedDescriptorKey> {
+    /// Convert all the keys of the descriptor, whose wildcards have been
+    /// derived, to [`bitcoin::PublicKey`]s.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if hardened derivation is attempted.
+    pub fn derived_descriptor<C: secp256k1::Verification>(
+        &self,
+        secp: &secp256k1::Secp256k1<C>,
+    ) -> Result<Descriptor<bitcoin::PublicKey>, ConversionError> {
+        struct PublicKeyTranslator<'a, C: secp256k1::Verification>(&'a secp256k1::Secp256k1<C>);
+
+        impl<'a, C: secp256k1::Verification>
+            Translator<DerivedDescriptorKey, bitcoin::PublicKey, ConversionError>
+            for PublicKeyTranslator<'a, C>
+        {
+            fn pk(
+                &mut self,
+                pk: &DerivedDescriptorKey,
+            ) -> Result<bitcoin::PublicKey, ConversionError> {
+                pk.derive_public_key(self.0)
+            }
+
+            fn pkh(
+                &mut self,
+                pkh: &DerivedDescriptorKey,
+            ) -> Result<hash160::Hash, ConversionError> {
+                self.pk(pkh).map(|pk| pk.to_pubkeyhash())
+            }
+
+            translate_hash_clone!(DerivedDescriptorKey, bitcoin::PublicKey, ConversionError);
+        }
+
+        self.translate_pk(&mut PublicKeyTranslator(secp))
+    }
+}
+
+/// The spend path taken by a transaction input, as inferred by
+/// [`Descriptor::from_txin`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum SpendBranch {
+    /// A spend of a non-taproot output, through its only script
+    Script,
+    /// A taproot key spend
+    TapKey,
+    /// A taproot script spend of the leaf with this hash, at this depth of
+    /// the tree
+    TapLeaf {
+        /// The hash of the spent leaf
+        leaf_hash: TapLeafHash,
+        /// The depth of the spent leaf, i.e. the length of the merkle branch
+        /// of its control block
+        depth: usize,
+    },
+}
+
+impl Descriptor<bitcoin::PublicKey> {
+    /// Infer the descriptor of the output `prevout` from its spend by `txin`,
+    /// along with the spend path taken.
+    ///
+    /// Non-taproot descriptors are inferred as in
+    /// [`Interpreter::inferred_descriptor`](crate::Interpreter::inferred_descriptor).
+    /// A taproot key spend is inferred as a `rawtr` of the output key. For a
+    /// taproot script spend, the internal key and the merkle branch are taken
+    /// from the control block, so that the descriptor is a `tr` with the spent
+    /// leaf and one `raw_node` per hidden branch of the path to it. Like in
+    /// [`Self::derived_descriptor`], x-only keys are converted to
+    /// [`bitcoin::PublicKey`]s with an even y-coordinate.
+    ///
+    /// Signatures are not verified.
+    pub fn from_txin(
+        txin: &TxIn,
+        prevout: &TxOut,
+    ) -> Result<(Self, SpendBranch), interpreter::Error> {
+        let spk = &prevout.script_pubkey;
+        let interpreter =
+            interpreter::Interpreter::from_txdata(spk, &txin.script_sig, &txin.witness, 0, 0)?;
+        if interpreter.is_taproot_v1_key_spend() {
+            let output_key = secp256k1::XOnlyPublicKey::from_slice(&spk[2..])
+                .map_err(|_| interpreter::Error::XOnlyPublicKeyParseError)?;
+            let desc = Descriptor::new_rawtr(output_key.to_public_key());
+            return Ok((desc, SpendBranch::TapKey));
+        }
+        if !interpreter.is_taproot_v1_script_spend() {
+            let desc = interpreter
+                .inferred_descriptor()
+                .map_err(interpreter::Error::Miniscript)?;
+            return Ok((desc, SpendBranch::Script));
+        }
+
+        // The interpreter checked that the witness ends with the leaf script
+        // and its control block, before the annex if any
+        let mut witness = txin.witness.to_vec();
+        if interpreter.annex().is_some() {
+            witness.pop();
+        }
+        let control_block = ControlBlock::from_slice(&witness[witness.len() - 1])
+            .map_err(interpreter::Error::ControlBlockParse)?;
+        let script = Script::from(witness[witness.len() - 2].clone());
+        let ms = Miniscript::<secp256k1::XOnlyPublicKey, Tap>::parse_insane(&script)
+            .map_err(interpreter::Error::Miniscript)?
+            .translate_pk(&mut XOnlyKeyTranslator)
+            .expect("Translating x-only keys cannot fail");
+        let branch = control_block.merkle_branch.as_inner();
+        let mut tree = TapTree::Leaf(Arc::new(ms));
+        for hash in branch {
+            tree = TapTree::Tree(
+                Arc::new(tree),
+                Arc::new(TapTree::Hidden(TapBranchHash::from_inner(
+                    hash.into_inner(),
+                ))),
+            );
+        }
+        let internal_key = control_block.internal_key.to_public_key();
+        let desc =
+            Descriptor::new_tr(internal_key, Some(tree)).map_err(interpreter::Error::Miniscript)?;
+        if desc.script_pubkey() != *spk {
+            return Err(interpreter::Error::ControlBlockVerificationError);
+        }
+        let branch = SpendBranch::TapLeaf {
+            leaf_hash: TapLeafHash::from_script(&script, LeafVersion::TapScript),
+            depth: branch.len(),
+        };
+        Ok((desc, branch))
+    }
+}
+
+// Translator converting x-only keys to keys with an even y-coordinate
+struct XOnlyKeyTranslator;
+
+impl Translator<secp256k1::XOnlyPublicKey, bitcoin::PublicKey, ()> for XOnlyKeyTranslator {
+    fn pk(&mut self, pk: &secp256k1::XOnlyPublicKey) -> Result<bitcoin::PublicKey, ()> {
+        Ok(pk.to_public_key())
+    }
+
+    fn pkh(&mut self, pkh: &hash160::Hash) -> Result<hash160::Hash, ()> {
+        Ok(*pkh)
+    }
+
+    translate_hash_clone!(secp256k1::XOnlyPublicKey, bitcoin::PublicKey, ());
+}
+
+impl Descriptor<DescriptorSecretKey> {
+    /// Convert every secret key of the descriptor to its public key.
+    ///
+    /// Parsing a `Descriptor<DescriptorSecretKey>` and calling this method is
+    /// equivalent to [`Descriptor::parse_descriptor`] without the key map; see
+    /// [`Descriptor::to_public_with_key_map`] to also get the secret keys.
+    pub fn to_public<C: secp256k1::Signing>(
+        &self,
+        secp: &secp256k1::Secp256k1<C>,
+    ) -> Result<Descriptor<DescriptorPublicKey>, DescriptorKeyParseError> {
+        self.to_public_with_key_map(secp)
+            .map(|(descriptor, _)| descriptor)
+    }
+
+    /// Convert every secret key of the descriptor to its public key, returning
+    /// a map to lookup the secret key given a public key.
+    ///
+    /// The returned map is the one accepted by
+    /// [`Descriptor::to_string_with_secret`], and can be used to find the
+    /// signing keys of the public descriptor.
+    pub fn to_public_with_key_map<C: secp256k1::Signing>(
+        &self,
+        secp: &secp256k1::Secp256k1<C>,
+    ) -> Result<(Descriptor<DescriptorPublicKey>, KeyMap), DescriptorKeyParseError> {
+        struct ToPublic<'a, C: secp256k1::Signing> {
+            secp: &'a secp256k1::Secp256k1<C>,
+            key_map: KeyMap,
+        }
+
+        impl<'a, C: secp256k1::Signing>
+            Translator<DescriptorSecretKey, DescriptorPublicKey, DescriptorKeyParseError>
+            for ToPublic<'a, C>
+        {
+            fn pk(
+                &mut self,
+                sk: &DescriptorSecretKey,
+            ) -> Result<DescriptorPublicKey, DescriptorKeyParseError> {
+                let pk = sk.to_public(self.secp)?;
+                self.key_map.insert(pk.clone(), sk.clone());
+                Ok(pk)
+            }
+
+            fn pkh(
+                &mut self,
+                sk: &DescriptorSecretKey,
+            ) -> Result<DescriptorPublicKey, DescriptorKeyParseError> {
+                self.pk(sk)
+            }
+
+            translate_hash_clone!(
+                DescriptorSecretKey,
+                DescriptorPublicKey,
+                DescriptorKeyParseError
+            );
+        }
+
+        let mut translator = ToPublic {
+            secp,
+            key_map: KeyMap::new(),
+        };
+        let descriptor = self.translate_pk(&mut translator)?;
+
+        Ok((descriptor, translator.key_map))
+    }
+}
+
+impl Descriptor<String> {
+    /// Bind the named placeholders of a descriptor template to concrete keys.
+    ///
+    /// A template is a descriptor parsed with `String` keys in which any key
+    /// starting with `@` is a placeholder, e.g. `wsh(multi(2,@alice,@bob))`.
+    /// Each placeholder is replaced by the key `keys` maps its name (without
+    /// the `@`) to; all other keys are parsed as [`DescriptorPublicKey`]s.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::collections::BTreeMap;
+    /// use std::str::FromStr;
+    /// use miniscript::descriptor::{Descriptor, DescriptorPublicKey};
+    ///
+    /// let template = Descriptor::<String>::from_str("wsh(multi(2,@alice,@bob))").unwrap();
+    ///
+    /// let mut keys = BTreeMap::new();
+    /// keys.insert(
+    ///     "alice".to_string(),
+    ///     DescriptorPublicKey::from_str("02d7924d4f7d43ea965a465ae3095ff41131e5946f3c85f79e44adbcf8e27e080e").unwrap(),
+    /// );
+    /// keys.insert(
+    ///     "bob".to_string(),
+    ///     DescriptorPublicKey::from_str("03b506a1dbe57b4bf48c95e0c7d417b87dd3b4349d290d2e7e9ba72c912652d80a").unwrap(),
+    /// );
+    /// let descriptor = template.bind(&keys).unwrap();
+    /// assert_eq!(
+    ///     descriptor.to_string(),
+    ///     "wsh(multi(2,02d7924d4f7d43ea965a465ae3095ff41131e5946f3c85f79e44adbcf8e27e080e,03b506a1dbe57b4bf48c95e0c7d417b87dd3b4349d290d2e7e9ba72c912652d80a))#n36c2swh"
+    /// );
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// When a placeholder has no key bound to it, or when the bound keys are
+    /// not valid in the context they are used in (e.g. an uncompressed key
+    /// inside `wsh` or an x-only key outside of `tr`).
+    pub fn bind(
+        &self,
+        keys: &BTreeMap<String, DescriptorPublicKey>,
+    ) -> Result<Descriptor<DescriptorPublicKey>, Error> {
+        struct Binder<'a>(&'a BTreeMap<String, DescriptorPublicKey>);
+
+        impl<'a> Translator<String, String, Error> for Binder<'a> {
+            fn pk(&mut self, pk: &String) -> Result<String, Error> {
+                if pk.starts_with('@') {
+                    self.0
+                        .get(&pk[1..])
+                        .map(|key| key.to_string())
+                        .ok_or_else(|| {
+                            Error::BadDescriptor(format!("no key bound to placeholder {}", pk))
+                        })
+                } else {
+                    Ok(pk.clone())
+                }
+            }
+
+            fn pkh(&mut self, pkh: &String) -> Result<String, Error> {
+                self.pk(pkh)
+            }
+
+            translate_hash_clone!(String, String, Error);
+        }
+
+        // Re-parsing the bound descriptor applies the same context checks as
+        // parsing it directly would.
+        let bound = self.translate_pk(&mut Binder(keys))?;
+        Descriptor::from_str(&bound.to_string())
+    }
+}
+
+impl<Pk> expression::FromTree for Descriptor<Pk>
+where
+    Pk: MiniscriptKey + str::FromStr,
     Pk::Hash: str::FromStr,
+    Pk::Sha256: str::FromStr,
+    Pk::Hash256: str::FromStr,
+    Pk::Ripemd160: str::FromStr,
+    Pk::Hash160: str::FromStr,
     <Pk as FromStr>::Err: ToString,
     <<Pk as MiniscriptKey>::Hash as FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Sha256 as FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Hash256 as FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Ripemd160 as FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Hash160 as FromStr>::Err: ToString,
 {
     /// Parse an expression tree into a descriptor
     fn from_tree(top: &expression::Tree) -> Result<Descriptor<Pk>, Error> {
@@ -666,6 +1865,10 @@ where
             ("sh", 1) => Descriptor::Sh(Sh::from_tree(top)?),
             ("wsh", 1) => Descriptor::Wsh(Wsh::from_tree(top)?),
             ("tr", _) => Descriptor::Tr(Tr::from_tree(top)?),
+            ("rawtr", 1) => Descriptor::RawTr(RawTr::from_tree(top)?),
+            ("raw", 1) => Descriptor::Raw(Raw::from_tree(top)?),
+            ("addr", 1) => Descriptor::Addr(Addr::from_tree(top)?),
+            ("wv", 2) => Descriptor::Wv(Wv::from_tree(top)?),
             _ => Descriptor::Bare(Bare::from_tree(top)?),
         })
     }
@@ -675,8 +1878,16 @@ impl<Pk> FromStr for Descriptor<Pk>
 where
     Pk: MiniscriptKey + str::FromStr,
     Pk::Hash: str::FromStr,
+    Pk::Sha256: str::FromStr,
+    Pk::Hash256: str::FromStr,
+    Pk::Ripemd160: str::FromStr,
+    Pk::Hash160: str::FromStr,
     <Pk as FromStr>::Err: ToString,
     <<Pk as MiniscriptKey>::Hash as FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Sha256 as FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Hash256 as FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Ripemd160 as FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Hash160 as FromStr>::Err: ToString,
 {
     type Err = Error;
 
@@ -694,6 +1905,113 @@ where
     }
 }
 
+impl<Pk> Descriptor<Pk>
+where
+    Pk: MiniscriptKey + str::FromStr,
+    Pk::Hash: str::FromStr,
+    Pk::Sha256: str::FromStr,
+    Pk::Hash256: str::FromStr,
+    Pk::Ripemd160: str::FromStr,
+    Pk::Hash160: str::FromStr,
+    <Pk as FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Hash as FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Sha256 as FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Hash256 as FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Ripemd160 as FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Hash160 as FromStr>::Err: ToString,
+{
+    /// Parse a descriptor which may contain whitespace and comments, such as
+    /// one written over several lines of a file maintained by hand.
+    ///
+    /// All whitespace is ignored, as is every `#` at the start of a line or
+    /// after whitespace together with the rest of its line. A `#` directly
+    /// following the descriptor still introduces its checksum, which is
+    /// computed over the descriptor without whitespace or comments.
+    pub fn from_str_lenient(s: &str) -> Result<Descriptor<Pk>, Error> {
+        Descriptor::from_str(&strip_whitespace_and_comments(s))
+    }
+
+    /// Parse a descriptor, expanding a `combo(KEY)` descriptor as found in
+    /// the wallet dumps of Bitcoin Core into the descriptors it stands for.
+    ///
+    /// These are `pk(KEY)` and `pkh(KEY)`, followed by `wpkh(KEY)` and
+    /// `sh(wpkh(KEY))` when `KEY` is compressed. Any other descriptor is
+    /// parsed as [`Descriptor::from_str`] into a single descriptor. There is
+    /// no `combo` variant of [`Descriptor`], so it cannot be satisfied or
+    /// displayed as a whole.
+    pub fn from_str_combo(s: &str) -> Result<Vec<Descriptor<Pk>>, Error> {
+        if !s.starts_with("combo(") {
+            return Ok(vec![Descriptor::from_str(s)?]);
+        }
+        let desc_str = verify_checksum(s)?;
+        let top = expression::Tree::from_str(desc_str)?;
+        if top.args.len() != 1 {
+            return Err(Error::Unexpected(format!(
+                "{}({} args) while parsing combo descriptor",
+                top.name,
+                top.args.len(),
+            )));
+        }
+        let pk = expression::terminal(&top.args[0], |pk| Pk::from_str(pk))?;
+        if pk.is_x_only_key() {
+            let e = ContextError::new::<Pk, BareCtx>(KeyRule::NoXOnly, &pk);
+            let e = Error::ContextError(ScriptContextError::Key(e));
+            return Err(context::mark_key::<Pk>(e, &top));
+        }
+
+        let mut descs = vec![
+            Descriptor::new_pk(pk.clone()),
+            Descriptor::new_pkh(pk.clone()),
+        ];
+        if !pk.is_uncompressed() {
+            descs.push(Descriptor::new_wpkh(pk.clone())?);
+            descs.push(Descriptor::new_sh_wpkh(pk)?);
+        }
+        Ok(descs)
+    }
+
+    /// Parse a descriptor as [`Descriptor::from_str`], failing with
+    /// [`Error::LimitExceeded`] when the string exceeds one of the limits of
+    /// `options`.
+    ///
+    /// The limits are checked before the keys and scripts of the descriptor
+    /// are parsed, the scripts of taproot leaves included, so this should be
+    /// preferred over [`Descriptor::from_str`] for untrusted input.
+    pub fn from_str_with_options(
+        s: &str,
+        options: &expression::ParseOptions,
+    ) -> Result<Descriptor<Pk>, Error> {
+        let desc_str = verify_checksum(s)?;
+        let top = if desc_str.starts_with("tr(") {
+            let top = tr::parse_tr_tree(desc_str)?;
+            tr::check_tr_tree_limits(&top, options)?;
+            top
+        } else {
+            options.parse_tree(desc_str)?
+        };
+        expression::FromTree::from_tree(&top)
+    }
+}
+
+// Remove the whitespace and comments accepted by `Descriptor::from_str_lenient`
+fn strip_whitespace_and_comments(s: &str) -> String {
+    let mut stripped = String::with_capacity(s.len());
+    for line in s.lines() {
+        let mut after_whitespace = true;
+        for ch in line.chars() {
+            if ch.is_whitespace() {
+                after_whitespace = true;
+            } else if ch == '#' && after_whitespace {
+                break;
+            } else {
+                stripped.push(ch);
+                after_whitespace = false;
+            }
+        }
+    }
+    stripped
+}
+
 impl<Pk: MiniscriptKey> fmt::Debug for Descriptor<Pk> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
@@ -703,6 +2021,10 @@ impl<Pk: MiniscriptKey> fmt::Debug for Descriptor<Pk> {
             Descriptor::Sh(ref sub) => write!(f, "{:?}", sub),
             Descriptor::Wsh(ref sub) => write!(f, "{:?}", sub),
             Descriptor::Tr(ref tr) => write!(f, "{:?}", tr),
+            Descriptor::RawTr(ref rawtr) => write!(f, "{:?}", rawtr),
+            Descriptor::Raw(ref raw) => write!(f, "{:?}", raw),
+            Descriptor::Addr(ref addr) => write!(f, "{:?}", addr),
+            Descriptor::Wv(ref wv) => write!(f, "{:?}", wv),
         }
     }
 }
@@ -716,6 +2038,10 @@ impl<Pk: MiniscriptKey> fmt::Display for Descriptor<Pk> {
             Descriptor::Sh(ref sub) => write!(f, "{}", sub),
             Descriptor::Wsh(ref sub) => write!(f, "{}", sub),
             Descriptor::Tr(ref tr) => write!(f, "{}", tr),
+            Descriptor::RawTr(ref rawtr) => write!(f, "{}", rawtr),
+            Descriptor::Raw(ref raw) => write!(f, "{}", raw),
+            Descriptor::Addr(ref addr) => write!(f, "{}", addr),
+            Descriptor::Wv(ref wv) => write!(f, "{}", wv),
         }
     }
 }
@@ -724,7 +2050,6 @@ serde_string_impl_pk!(Descriptor, "a script descriptor");
 
 #[cfg(test)]
 mod tests {
-    use core::cmp;
     use core::str::FromStr;
 
     use bitcoin::blockdata::opcodes::all::{OP_CLTV, OP_CSV};
@@ -740,31 +2065,15 @@ mod tests {
     use super::*;
     use crate::descriptor::key::Wildcard;
     use crate::descriptor::{DescriptorPublicKey, DescriptorSecretKey, DescriptorXKey, SinglePub};
+    use crate::miniscript::satisfy::KeyMapSatisfier;
     #[cfg(feature = "compiler")]
     use crate::policy;
-    use crate::{hex_script, Descriptor, DummyKey, Error, Miniscript, Satisfier, TranslatePk2};
+    use crate::{hex_script, Descriptor, DummyKey, Error, Miniscript, Satisfier};
 
     type StdDescriptor = Descriptor<PublicKey>;
     const TEST_PK: &'static str =
         "pk(020000000000000000000000000000000000000000000000000000000000000002)";
 
-    impl cmp::PartialEq for DescriptorSecretKey {
-        fn eq(&self, other: &Self) -> bool {
-            match (self, other) {
-                (&DescriptorSecretKey::Single(ref a), &DescriptorSecretKey::Single(ref b)) => {
-                    a.origin == b.origin && a.key == b.key
-                }
-                (&DescriptorSecretKey::XPrv(ref a), &DescriptorSecretKey::XPrv(ref b)) => {
-                    a.origin == b.origin
-                        && a.xkey == b.xkey
-                        && a.derivation_path == b.derivation_path
-                        && a.wildcard == b.wildcard
-                }
-                _ => false,
-            }
-        }
-    }
-
     fn roundtrip_descriptor(s: &str) {
         let desc = Descriptor::<DummyKey>::from_str(&s).unwrap();
         let output = desc.to_string();
@@ -1317,36 +2626,207 @@ mod tests {
     }
 
     #[test]
-    fn test_scriptcode() {
-        // P2WPKH (from bip143 test vectors)
-        let descriptor = Descriptor::<PublicKey>::from_str(
-            "wpkh(025476c2e83188368da1ff3e292e7acafcdb3566bb0ad253f62fc70f07aeee6357)",
+    fn satisfy_raw_pkh() {
+        let secp = secp256k1::Secp256k1::new();
+        let (_, key_map) = Descriptor::parse_descriptor(
+            &secp,
+            "wpkh(cVt4o7BGAig1UXywgGSmARhxMdzP5qvQsxKkSsc1XEkw3tDTQFpy)",
         )
         .unwrap();
-        assert_eq!(
-            *descriptor.script_code().unwrap().as_bytes(),
-            Vec::<u8>::from_hex("76a9141d0f172a0ecb48aee1be1f2687d2963ae33f71a188ac").unwrap()[..]
-        );
-
-        // P2SH-P2WPKH (from bip143 test vectors)
-        let descriptor = Descriptor::<PublicKey>::from_str(
-            "sh(wpkh(03ad1d8e89212f0b92c74d23bb710c00662ad1470198ac48c43f7d6f93a2a26873))",
+        let hashed_pk = key_map
+            .keys()
+            .next()
+            .unwrap()
+            .derive_public_key(&secp)
+            .unwrap();
+        let a = bitcoin::PublicKey::from_str(
+            "02937402303919b3a2ee5edd5009f4236f069bf75667b8e6ecf8e5464e20116a0e",
         )
         .unwrap();
-        assert_eq!(
-            *descriptor.script_code().unwrap().as_bytes(),
-            Vec::<u8>::from_hex("76a91479091972186c449eb1ded22b78e40d009bdf008988ac").unwrap()[..]
+        let sig_a = secp256k1::ecdsa::Signature::from_str("3045022100a7acc3719e9559a59d60d7b2837f9842df30e7edcd754e63227e6168cec72c5d022066c2feba4671c3d99ea75d9976b4da6c86968dbf3bab47b1061e7a1966b1778c").unwrap();
+
+        let descriptor = Descriptor::<bitcoin::PublicKey>::from_str(&format!(
+            "wsh(or_d(pkh({}),pk({})))",
+            hashed_pk.to_pubkeyhash(),
+            a
+        ))
+        .unwrap();
+        let mut sigs = HashMap::new();
+        sigs.insert(
+            a,
+            bitcoin::EcdsaSig {
+                sig: sig_a,
+                hash_ty: EcdsaSighashType::All,
+            },
         );
+        let mut key_index = HashMap::new();
+        key_index.insert(hashed_pk.to_pubkeyhash(), hashed_pk);
 
-        // P2WSH (from bitcoind's `createmultisig`)
-        let descriptor = Descriptor::<PublicKey>::from_str(
-            "wsh(multi(2,03789ed0bb717d88f7d321a368d905e7430207ebbd82bd342cf11ae157a7ace5fd,03dbc6764b8884a92e871274b87583e6d5c2a58819473e17e107ef3f6aa5a61626))",
+        let mut txin = bitcoin::TxIn {
+            previous_output: bitcoin::OutPoint::default(),
+            script_sig: bitcoin::Script::new(),
+            sequence: 0,
+            witness: Witness::default(),
+        };
+        // The hashed key is needed to dissatisfy the `pkh`
+        assert!(descriptor.satisfy(&mut txin, &sigs).is_err());
+
+        let key_map_sat = KeyMapSatisfier::new(&key_map, &secp);
+        descriptor
+            .satisfy(&mut txin, (&sigs, &key_map_sat))
+            .unwrap();
+        let wit = txin.witness.to_vec();
+        assert_eq!(wit.len(), 4);
+        assert_eq!(wit[1], Vec::<u8>::new());
+        assert_eq!(wit[2], hashed_pk.to_bytes());
+
+        let mut txin_index = txin.clone();
+        descriptor
+            .satisfy(&mut txin_index, (&sigs, &key_index))
+            .unwrap();
+        assert_eq!(txin_index, txin);
+
+        // Given the message, the satisfier signs for the hashed key
+        let msg = secp256k1::Message::from_slice(&[1; 32]).unwrap();
+        let key_map_sat = key_map_sat.with_sighash(msg, EcdsaSighashType::All);
+        descriptor.satisfy(&mut txin, &key_map_sat).unwrap();
+        let wit = txin.witness.to_vec();
+        assert_eq!(wit.len(), 3);
+        assert_eq!(wit[1], hashed_pk.to_bytes());
+        let sig = bitcoin::EcdsaSig::from_slice(&wit[0]).unwrap();
+        assert_eq!(sig.hash_ty, EcdsaSighashType::All);
+        secp.verify_ecdsa(&msg, &sig.sig, &hashed_pk.inner).unwrap();
+    }
+
+    #[test]
+    fn can_be_satisfied_by() {
+        let desc = Descriptor::<DescriptorPublicKey>::from_str(
+            "wsh(or_d(multi(2,\
+             [aaaaaaaa]0231c7d3fc85c148717848033ce276ae2b464a4e2c367ed33886cc428b8af48ff8,\
+             [bbbbbbbb]0250863ad64a87ae8a2fe83c1af1a8403cb53f53e486d8511dad8a04887e5b2352,\
+             [cccccccc]025476c2e83188368da1ff3e292e7acafcdb3566bb0ad253f62fc70f07aeee6357),\
+             and_v(v:pk([dddddddd]0283dfe85a3151d2517290da461fe2815591ef69f2b18a2ce63f01697a8b313145),older(1000))))",
         )
         .unwrap();
+        let signers = |fingerprints: &[&str]| -> BTreeSet<bip32::Fingerprint> {
+            fingerprints
+                .iter()
+                .map(|f| bip32::Fingerprint::from_str(f).unwrap())
+                .collect()
+        };
+
         assert_eq!(
-            *descriptor
-                .script_code().unwrap()
-                .as_bytes(),
+            desc.can_be_satisfied_by(&signers(&["aaaaaaaa", "cccccccc"])),
+            Ok(SatisfiabilityResult::Satisfiable)
+        );
+        assert_eq!(
+            desc.can_be_satisfied_by(&signers(&["aaaaaaaa", "dddddddd"])),
+            Ok(SatisfiabilityResult::Timelocked(semantic::Policy::Older(
+                1000
+            )))
+        );
+        assert_eq!(
+            desc.can_be_satisfied_by(&signers(&["aaaaaaaa", "eeeeeeee"])),
+            Ok(SatisfiabilityResult::Unsatisfiable)
+        );
+
+        // Hash preimages are not known to the signers
+        let desc = Descriptor::<DescriptorPublicKey>::from_str(
+            "wsh(or_d(pk([aaaaaaaa]0231c7d3fc85c148717848033ce276ae2b464a4e2c367ed33886cc428b8af48ff8),\
+             sha256(1111111111111111111111111111111111111111111111111111111111111111)))",
+        )
+        .unwrap();
+        assert_eq!(
+            desc.can_be_satisfied_by(&signers(&["aaaaaaaa"])),
+            Ok(SatisfiabilityResult::Satisfiable)
+        );
+        assert_eq!(
+            desc.can_be_satisfied_by(&signers(&[])),
+            Ok(SatisfiabilityResult::Unsatisfiable)
+        );
+    }
+
+    #[test]
+    fn derive_batch() {
+        let secp = secp256k1::Secp256k1::verification_only();
+        let descriptor = Descriptor::<DescriptorPublicKey>::from_str("wsh(multi(2,[d34db33f/44'/0'/0']xpub6ERApfZwUNrhLCkDtcHTcxd75RbzS1ed54G1LkBUHQVHQKqhMkhgbmJbZRkrgZw4koxb5JaHWkY4ALHY2grBGRjaDMzQLcgJvLJuZZvRcEL/1/*,xpub661MyMwAqRbcFW31YEwpkMuc5THy2PSt5bDMsktWQcFF8syAmRUapSCGu8ED9W6oDMSgv6Zz8idoc4a6mr8BDzTJY47LJhkJ8UB7WEGuduB/0/0/*,xpub69H7F5d8KSRgmmdJg2KhpAK8SR3DjMwAdkxj3ZuxV27CprR9LgpeyGmXUbC6wb7ERfvrnKZjXoUmmDznezpbZb7ap6r1D3tgFxHmwMkQTPH/2,03f28773c2d975288bc7d1d205c3748651b075fbc6610e58cddeeddf8f19405aa8))").unwrap();
+
+        let batch = descriptor.derive_batch(5..10);
+        assert_eq!(batch.len(), 5);
+        for (derived, index) in batch.iter().zip(5..10) {
+            let derived = derived.derived_descriptor(&secp).unwrap();
+            assert_eq!(
+                derived,
+                descriptor.derived_descriptor(&secp, index).unwrap()
+            );
+        }
+        // The derivation path of the keys is moved to their origin
+        assert!(batch[0].to_string().contains("[d34db33f/44'/0'/0'/1]xpub"));
+
+        // Keys with hardened steps cannot be derived in advance
+        let descriptor = Descriptor::<DescriptorPublicKey>::from_str("wpkh(xpub661MyMwAqRbcFW31YEwpkMuc5THy2PSt5bDMsktWQcFF8syAmRUapSCGu8ED9W6oDMSgv6Zz8idoc4a6mr8BDzTJY47LJhkJ8UB7WEGuduB/0'/1/*)").unwrap();
+        assert_eq!(descriptor.derive_batch(0..1), vec![descriptor.derive(0)]);
+    }
+
+    #[test]
+    fn script_pubkey_at_index() {
+        let secp = secp256k1::Secp256k1::verification_only();
+        let xpub = "xpub6BgBgsespWvERF3LHQu6CnqdvfEvtMcQjYrcRzx53QJjSxarj2afYWcLteoGVky7D3UKDP9QyrLprQ3VCECoY49yfdDEHGCtMMj92pReUsQ";
+        let pk = "03f28773c2d975288bc7d1d205c3748651b075fbc6610e58cddeeddf8f19405aa8";
+        for desc in &[
+            format!("wpkh({}/0/*)", xpub),
+            format!("tr({}/0/*)", xpub),
+            // The merkle root is cached for trees without wildcards
+            format!("tr({}/0/*,{{pk({}),pk({}/1)}})", xpub, pk, xpub),
+            format!("tr({}/0/*,{{pk({}),pk({}/1/*)}})", xpub, pk, xpub),
+        ] {
+            let descriptor = Descriptor::<DescriptorPublicKey>::from_str(desc).unwrap();
+            for index in (0..3).chain(0..3) {
+                assert_eq!(
+                    descriptor.script_pubkey_at_index(&secp, index).unwrap(),
+                    descriptor
+                        .derived_descriptor(&secp, index)
+                        .unwrap()
+                        .script_pubkey()
+                );
+            }
+        }
+        let descriptor =
+            Descriptor::<DescriptorPublicKey>::from_str(&format!("tr({}/0/*')", xpub)).unwrap();
+        assert!(descriptor.script_pubkey_at_index(&secp, 0).is_err());
+    }
+
+    #[test]
+    fn test_scriptcode() {
+        // P2WPKH (from bip143 test vectors)
+        let descriptor = Descriptor::<PublicKey>::from_str(
+            "wpkh(025476c2e83188368da1ff3e292e7acafcdb3566bb0ad253f62fc70f07aeee6357)",
+        )
+        .unwrap();
+        assert_eq!(
+            *descriptor.script_code().unwrap().as_bytes(),
+            Vec::<u8>::from_hex("76a9141d0f172a0ecb48aee1be1f2687d2963ae33f71a188ac").unwrap()[..]
+        );
+
+        // P2SH-P2WPKH (from bip143 test vectors)
+        let descriptor = Descriptor::<PublicKey>::from_str(
+            "sh(wpkh(03ad1d8e89212f0b92c74d23bb710c00662ad1470198ac48c43f7d6f93a2a26873))",
+        )
+        .unwrap();
+        assert_eq!(
+            *descriptor.script_code().unwrap().as_bytes(),
+            Vec::<u8>::from_hex("76a91479091972186c449eb1ded22b78e40d009bdf008988ac").unwrap()[..]
+        );
+
+        // P2WSH (from bitcoind's `createmultisig`)
+        let descriptor = Descriptor::<PublicKey>::from_str(
+            "wsh(multi(2,03789ed0bb717d88f7d321a368d905e7430207ebbd82bd342cf11ae157a7ace5fd,03dbc6764b8884a92e871274b87583e6d5c2a58819473e17e107ef3f6aa5a61626))",
+        )
+        .unwrap();
+        assert_eq!(
+            *descriptor
+                .script_code().unwrap()
+                .as_bytes(),
             Vec::<u8>::from_hex("522103789ed0bb717d88f7d321a368d905e7430207ebbd82bd342cf11ae157a7ace5fd2103dbc6764b8884a92e871274b87583e6d5c2a58819473e17e107ef3f6aa5a6162652ae").unwrap()[..]
         );
 
@@ -1486,12 +2966,12 @@ mod tests {
 
             // Same address
             let addr_one = desc_one
-                .translate_pk2(|xpk| xpk.derive_public_key(&secp_ctx))
+                .derived_descriptor(&secp_ctx)
                 .unwrap()
                 .address(bitcoin::Network::Bitcoin)
                 .unwrap();
             let addr_two = desc_two
-                .translate_pk2(|xpk| xpk.derive_public_key(&secp_ctx))
+                .derived_descriptor(&secp_ctx)
                 .unwrap()
                 .address(bitcoin::Network::Bitcoin)
                 .unwrap();
@@ -1555,10 +3035,64 @@ mod tests {
             "sh(multi(2,[00000000/111'/222]xpub6ERApfZwUNrhLCkDtcHTcxd75RbzS1ed54G1LkBUHQVHQKqhMkhgbmJbZRkrgZw4koxb5JaHWkY4ALHY2grBGRjaDMzQLcgJvLJuZZvRcEL,xpub68NZiKmJWnxxS6aaHmn81bvJeTESw724CRDs6HbuccFQN9Ku14VQrADWgqbhhTHBaohPX4CjNLf9fq9MYo6oDaPPLPxSb7gwQN3ih19Zm4Y/0))##tjq09x4t"
         );
 
+        // Key errors are located in the descriptor string
+        let pk = "03cc8a4bc64d897bddc5fbc2f670f7a8ba0b386779106cf1223c6fc5d7cd6fc115";
+        let bad = "[00000000/111'/2x2]xpub6ERApfZwUNrhLCkDtcHTcxd75RbzS1ed54G1LkBUHQVHQKqhMkhgbmJbZRkrgZw4koxb5JaHWkY4ALHY2grBGRjaDMzQLcgJvLJuZZvRcEL";
+        for desc in &[
+            format!("wsh(multi(1,{},{}))", pk, bad),
+            format!("tr({},and_v(v:pk({}),pk({})))", pk, pk, bad),
+            format!("tr({},{{pk({}),leaf_ver(c2,pk({}))}})", pk, pk, bad),
+            format!("tr({})", bad),
+        ] {
+            let position = desc.find(bad).unwrap();
+            let error = DescriptorPublicKey::from_str(bad).unwrap_err();
+            match error {
+                DescriptorKeyParseError::BadDerivationPath { position: 15, .. } => {}
+                ref e => panic!("unexpected error {:?}", e),
+            }
+            assert_eq!(
+                Descriptor::parse_descriptor(secp, desc).unwrap_err(),
+                Error::BadDescriptorKey {
+                    position,
+                    error: error.offset_by(position),
+                }
+            );
+        }
+
         Descriptor::parse_descriptor(&secp, "sh(multi(2,[00000000/111'/222]xprvA1RpRA33e1JQ7ifknakTFpgNXPmW2YvmhqLQYMmrj4xJXXWYpDPS3xz7iAxn8L39njGVyuoseXzU6rcxFLJ8HFsTjSyQbLYnMpCqE2VbFWc,xprv9uPDJpEQgRQfDcW7BkF7eTya6RPxXeJCqCJGHuCJ4GiRVLzkTXBAJMu2qaMWPrS7AANYqdq6vcBcBUdJCVVFceUvJFjaPdGZ2y9WACViL4L/0))#ggrsrxfy").expect("Valid descriptor with checksum");
         Descriptor::parse_descriptor(&secp, "sh(multi(2,[00000000/111'/222]xpub6ERApfZwUNrhLCkDtcHTcxd75RbzS1ed54G1LkBUHQVHQKqhMkhgbmJbZRkrgZw4koxb5JaHWkY4ALHY2grBGRjaDMzQLcgJvLJuZZvRcEL,xpub68NZiKmJWnxxS6aaHmn81bvJeTESw724CRDs6HbuccFQN9Ku14VQrADWgqbhhTHBaohPX4CjNLf9fq9MYo6oDaPPLPxSb7gwQN3ih19Zm4Y/0))#tjg09x5t").expect("Valid descriptor with checksum");
     }
 
+    #[test]
+    fn secret_key_descriptor() {
+        let secp = &secp256k1::Secp256k1::signing_only();
+        let desc_str = "wsh(multi(2,[00000000/111'/222]xprvA1RpRA33e1JQ7ifknakTFpgNXPmW2YvmhqLQYMmrj4xJXXWYpDPS3xz7iAxn8L39njGVyuoseXzU6rcxFLJ8HFsTjSyQbLYnMpCqE2VbFWc,cVt4o7BGAig1UXywgGSmARhxMdzP5qvQsxKkSsc1XEkw3tDTQFpy))";
+        let desc = Descriptor::<DescriptorSecretKey>::from_str(desc_str).unwrap();
+        let desc_with_checksum = desc.to_string();
+        assert!(desc_with_checksum.starts_with(desc_str));
+        assert_eq!(
+            Descriptor::<DescriptorSecretKey>::from_str(&desc_with_checksum).unwrap(),
+            desc
+        );
+
+        let (expected, expected_key_map) = Descriptor::parse_descriptor(secp, desc_str).unwrap();
+        assert_eq!(desc.to_public(secp).unwrap(), expected);
+        let (public, key_map) = desc.to_public_with_key_map(secp).unwrap();
+        assert_eq!(public, expected);
+        assert_eq!(key_map, expected_key_map);
+        assert_eq!(public.to_string_with_secret(&key_map), desc_with_checksum);
+
+        // Hardened steps need the private key to be converted
+        let desc = Descriptor::<DescriptorSecretKey>::from_str("wpkh(tprv8ZgxMBicQKsPcwcD4gSnMti126ZiETsuX7qwrtMypr6FBwAP65puFn4v6c3jrN9VwtMRMph6nyT63NrfUL4C3nBzPcduzVSuHD7zbX2JKVc/44'/0'/0'/0/*)").unwrap();
+        assert_eq!(desc.to_public(secp).unwrap().to_string(), "wpkh([2cbe2a6d/44'/0'/0']tpubDCvNhURocXGZsLNqWcqD3syHTqPXrMSTwi8feKVwAcpi29oYKsDD3Vex7x2TDneKMVN23RbLprfxB69v94iYqdaYHsVz3kPR37NQXeqouVz/0/*)#nhdxg96s");
+
+        // Uncompressed keys are rejected by segwit contexts
+        assert!(Descriptor::<DescriptorSecretKey>::from_str(
+            "wpkh(5KYZdUEo39z3FPrtuX2QbbwGnNP5zTd7yyr2SC1j299sBCnWjss)"
+        )
+        .is_err());
+    }
+
     #[test]
     #[cfg(feature = "compiler")]
     fn parse_and_derive() {
@@ -1607,6 +3141,328 @@ pk(03f28773c2d975288bc7d1d205c3748651b075fbc6610e58cddeeddf8f19405aa8))";
         assert_eq!(descriptor.to_string(), "sh(wsh(pk(xpub6ERApfZwUNrhLCkDtcHTcxd75RbzS1ed54G1LkBUHQVHQKqhMkhgbmJbZRkrgZw4koxb5JaHWkY4ALHY2grBGRjaDMzQLcgJvLJuZZvRcEL)))#6c6hwr22");
     }
 
+    #[test]
+    fn to_string_with_and_without_checksum() {
+        let descriptor_str = "sh(wpkh(xpub6ERApfZwUNrhLCkDtcHTcxd75RbzS1ed54G1LkBUHQVHQKqhMkhgbmJbZRkrgZw4koxb5JaHWkY4ALHY2grBGRjaDMzQLcgJvLJuZZvRcEL))";
+        let with_checksum = format!("{}#tjp2zm88", descriptor_str);
+
+        let descriptor = Descriptor::<DescriptorPublicKey>::from_str(&with_checksum).unwrap();
+        assert_eq!(descriptor.to_string_no_checksum(), descriptor_str);
+        assert_eq!(descriptor.to_string_with_checksum(), with_checksum);
+        assert_eq!(descriptor.to_string(), with_checksum);
+
+        let tr = Descriptor::<DescriptorPublicKey>::from_str(
+            "tr(03cc8a4bc64d897bddc5fbc2f670f7a8ba0b386779106cf1223c6fc5d7cd6fc115)#6qm9h8ym",
+        )
+        .unwrap();
+        assert_eq!(
+            tr.to_string_no_checksum(),
+            "tr(03cc8a4bc64d897bddc5fbc2f670f7a8ba0b386779106cf1223c6fc5d7cd6fc115)"
+        );
+        assert_eq!(tr.to_string_with_checksum(), tr.to_string());
+
+        Descriptor::<DescriptorPublicKey>::from_str(&format!("{}#tjp2zm89", descriptor_str))
+            .unwrap_err();
+    }
+
+    #[test]
+    fn rawtr_from_script_pubkey() {
+        let secp = secp256k1::Secp256k1::verification_only();
+        let key = "03cc8a4bc64d897bddc5fbc2f670f7a8ba0b386779106cf1223c6fc5d7cd6fc115";
+        let tr = Descriptor::<DescriptorPublicKey>::from_str(&format!("tr({})", key)).unwrap();
+        let spk = tr.derived_descriptor(&secp, 0).unwrap().script_pubkey();
+
+        let rawtr = Descriptor::rawtr_from_script_pubkey(&spk).unwrap();
+        assert_eq!(rawtr.desc_type(), DescriptorType::RawTr);
+        assert_eq!(
+            Descriptor::<DescriptorPublicKey>::from_str(&rawtr.to_string()).unwrap(),
+            rawtr
+        );
+        let rawtr = rawtr.derived_descriptor(&secp, 0).unwrap();
+        assert_eq!(rawtr.script_pubkey(), spk);
+        // The empty scriptSig, the witness element count, and the 65 byte
+        // signature with its length prefix
+        assert_eq!(rawtr.max_satisfaction_weight().unwrap(), 4 + 1 + 1 + 65);
+
+        // Only the key spend path is available
+        struct KeySpendSat(bitcoin::SchnorrSig);
+        impl Satisfier<bitcoin::PublicKey> for KeySpendSat {
+            fn lookup_tap_key_spend_sig(&self) -> Option<bitcoin::SchnorrSig> {
+                Some(self.0)
+            }
+        }
+        let sig = bitcoin::SchnorrSig::from_slice(&[1u8; 64]).unwrap();
+        let (witness, script_sig) = rawtr.get_satisfaction(KeySpendSat(sig)).unwrap();
+        assert_eq!(witness, vec![sig.to_vec()]);
+        assert!(script_sig.is_empty());
+
+        let wpkh = Descriptor::<DescriptorPublicKey>::from_str(&format!("wpkh({})", key)).unwrap();
+        let wpkh_spk = wpkh.derived_descriptor(&secp, 0).unwrap().script_pubkey();
+        Descriptor::rawtr_from_script_pubkey(&wpkh_spk).unwrap_err();
+    }
+
+    #[test]
+    fn raw_descriptor() {
+        // p2pkh script pubkey, which has an address
+        let hex = "76a91489abcdefabbaabbaabbaabbaabbaabbaabbaabba88ac";
+        let desc = Descriptor::<DescriptorPublicKey>::from_str(&format!("raw({})", hex)).unwrap();
+        assert_eq!(desc.desc_type(), DescriptorType::Raw);
+        assert_eq!(desc.to_string_no_checksum(), format!("raw({})", hex));
+        assert_eq!(
+            Descriptor::<DescriptorPublicKey>::from_str(&desc.to_string()).unwrap(),
+            desc
+        );
+        assert!(!desc.is_deriveable());
+
+        let secp = secp256k1::Secp256k1::verification_only();
+        let desc = desc.derived_descriptor(&secp, 0).unwrap();
+        let spk = Script::from_hex(hex).unwrap();
+        assert_eq!(desc.script_pubkey(), spk);
+        assert_eq!(desc.script_pubkey().dust_value(), spk.dust_value());
+        let addr = desc.address(Network::Bitcoin).unwrap();
+        assert_eq!(addr.address_type(), Some(bitcoin::AddressType::P2pkh));
+        assert_eq!(addr.script_pubkey(), spk);
+        assert_eq!(
+            desc.get_satisfaction(()),
+            Err(Error::ImpossibleSatisfaction)
+        );
+        assert_eq!(
+            desc.satisfaction_weight(),
+            Err(Error::ImpossibleSatisfaction)
+        );
+        assert_eq!(
+            desc.lift(),
+            Err(Error::LiftError(crate::policy::LiftError::UnknownScript))
+        );
+        assert_eq!(desc.iter_spend_paths().count(), 0);
+
+        // OP_RETURN has no address
+        let desc =
+            Descriptor::<bitcoin::PublicKey>::new_raw(Script::from_hex("6a0401020304").unwrap());
+        assert_eq!(
+            desc.to_string(),
+            Descriptor::<bitcoin::PublicKey>::from_str(&desc.to_string())
+                .unwrap()
+                .to_string()
+        );
+        assert_eq!(
+            desc.address(Network::Bitcoin),
+            Err(Error::BareDescriptorAddr)
+        );
+
+        Descriptor::<DescriptorPublicKey>::from_str("raw(zz)").unwrap_err();
+        Descriptor::<DescriptorPublicKey>::from_str("raw(00,00)").unwrap_err();
+    }
+
+    #[test]
+    fn addr_descriptor() {
+        let addr = "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4";
+        let desc = Descriptor::<DescriptorPublicKey>::from_str(&format!("addr({})", addr)).unwrap();
+        assert_eq!(desc.desc_type(), DescriptorType::Addr);
+        assert_eq!(desc.to_string_no_checksum(), format!("addr({})", addr));
+        assert_eq!(
+            Descriptor::<DescriptorPublicKey>::from_str(&desc.to_string()).unwrap(),
+            desc
+        );
+
+        let secp = secp256k1::Secp256k1::verification_only();
+        let desc = desc.derived_descriptor(&secp, 0).unwrap();
+        assert_eq!(
+            desc.script_pubkey(),
+            Address::from_str(addr).unwrap().script_pubkey()
+        );
+        assert_eq!(desc.address(Network::Bitcoin).unwrap().to_string(), addr);
+        assert_eq!(
+            desc.address(Network::Testnet),
+            Err(Error::AddrNetworkMismatch(
+                Network::Bitcoin,
+                Network::Testnet
+            ))
+        );
+        assert_eq!(
+            desc.get_satisfaction(()),
+            Err(Error::ImpossibleSatisfaction)
+        );
+        assert_eq!(
+            desc.lift(),
+            Err(Error::LiftError(crate::policy::LiftError::UnknownScript))
+        );
+
+        // Legacy testnet addresses are also valid on regtest
+        let desc =
+            Descriptor::<bitcoin::PublicKey>::from_str("addr(mipcBbFg9gMiCh81Kj8tqqdgoZub1ZJRfn)")
+                .unwrap();
+        assert!(desc.address(Network::Regtest).is_ok());
+        assert!(desc.address(Network::Bitcoin).is_err());
+
+        Descriptor::<DescriptorPublicKey>::from_str("addr(bc1qnotanaddress)").unwrap_err();
+    }
+
+    #[test]
+    fn from_str_with_options() {
+        let options = |max_nodes, max_depth, max_keys| expression::ParseOptions {
+            max_nodes,
+            max_depth,
+            max_keys,
+        };
+        let (a, b) = (
+            "02e96fe52ef0e22d2f131dd425ce1893073a3c6ad20e8cac36726393dfb4856a4c",
+            "02cc24adfed5a481b000192042b2399087437d8eb16095c3dda1d45a4fbf868017",
+        );
+
+        let wsh = format!("wsh(and_v(v:pk({}),pk({})))", a, b);
+        let desc = Descriptor::<bitcoin::PublicKey>::from_str_with_options(
+            &wsh,
+            &options(None, None, None),
+        )
+        .unwrap();
+        assert_eq!(desc, Descriptor::from_str(&wsh).unwrap());
+        // wsh, and_v, v:pk (two nodes), pk and two keys
+        Descriptor::<bitcoin::PublicKey>::from_str_with_options(
+            &wsh,
+            &options(Some(7), Some(4), Some(2)),
+        )
+        .unwrap();
+        assert_eq!(
+            Descriptor::<bitcoin::PublicKey>::from_str_with_options(
+                &wsh,
+                &options(Some(6), None, None)
+            )
+            .unwrap_err(),
+            Error::LimitExceeded {
+                kind: expression::LimitKind::Nodes,
+                limit: 6
+            }
+        );
+
+        // The scripts of taproot leaves are counted, at the depth of the leaf
+        let tr = format!("tr({},{{pk({}),pk({})}})", a, a, b);
+        let desc = Descriptor::<bitcoin::PublicKey>::from_str_with_options(
+            &tr,
+            &options(None, Some(4), Some(3)),
+        )
+        .unwrap();
+        assert_eq!(desc, Descriptor::from_str(&tr).unwrap());
+        assert_eq!(
+            Descriptor::<bitcoin::PublicKey>::from_str_with_options(
+                &tr,
+                &options(None, None, Some(2))
+            )
+            .unwrap_err(),
+            Error::LimitExceeded {
+                kind: expression::LimitKind::Keys,
+                limit: 2
+            }
+        );
+        assert_eq!(
+            Descriptor::<bitcoin::PublicKey>::from_str_with_options(
+                &tr,
+                &options(None, Some(3), None)
+            )
+            .unwrap_err(),
+            Error::LimitExceeded {
+                kind: expression::LimitKind::Depth,
+                limit: 3
+            }
+        );
+
+        assert_eq!(
+            Miniscript::<bitcoin::PublicKey, Segwitv0>::from_str_with_options(
+                &format!("and_v(v:pk({}),pk({}))", a, b),
+                &options(None, None, Some(1))
+            )
+            .unwrap_err(),
+            Error::LimitExceeded {
+                kind: expression::LimitKind::Keys,
+                limit: 1
+            }
+        );
+    }
+
+    #[test]
+    fn wv_descriptor() {
+        // Pay-to-anchor
+        let desc = Descriptor::<DescriptorPublicKey>::from_str("wv(1,4e73)").unwrap();
+        assert_eq!(desc.desc_type(), DescriptorType::Wv(WitnessVersion::V1));
+        assert_eq!(desc.desc_type().segwit_version(), Some(WitnessVersion::V1));
+        assert_eq!(
+            Descriptor::<DescriptorPublicKey>::from_str(&desc.to_string()).unwrap(),
+            desc
+        );
+        let secp = secp256k1::Secp256k1::verification_only();
+        let desc = desc.derived_descriptor(&secp, 0).unwrap();
+        assert_eq!(desc.script_pubkey().to_hex(), "51024e73");
+        assert_eq!(
+            desc.address(Network::Bitcoin).unwrap().to_string(),
+            "bc1pfeessrawgf"
+        );
+        assert_eq!(
+            desc.get_satisfaction(()),
+            Err(Error::ImpossibleSatisfaction)
+        );
+        assert_eq!(
+            Descriptor::Wv(Wv::from_script_pubkey(&desc.script_pubkey()).unwrap()),
+            desc
+        );
+
+        let program = "751e76e8199196d454941c45d1b3a323f1433bd6";
+        let desc =
+            Descriptor::<bitcoin::PublicKey>::from_str(&format!("wv(16,{})", program)).unwrap();
+        assert_eq!(desc.to_string_no_checksum(), format!("wv(16,{})", program));
+        assert_eq!(
+            desc.script_pubkey(),
+            Address::from_str(&desc.address(Network::Bitcoin).unwrap().to_string())
+                .unwrap()
+                .script_pubkey()
+        );
+
+        // Versions and programs which have descriptors of their own, or are
+        // not witness programs
+        let p2wpkh = format!("wv(0,{})", program);
+        Descriptor::<DescriptorPublicKey>::from_str(&p2wpkh).unwrap_err();
+        let p2tr = "wv(1,a60869f0dbcf1dc659c9cecbaf8050135ea9e8cdc487053f1dc6880949dc684c)";
+        Descriptor::<DescriptorPublicKey>::from_str(p2tr).unwrap_err();
+        Descriptor::<DescriptorPublicKey>::from_str("wv(17,4e73)").unwrap_err();
+        Descriptor::<DescriptorPublicKey>::from_str("wv(2,00)").unwrap_err();
+        Wv::from_script_pubkey(&Script::from_str("6a").unwrap()).unwrap_err();
+    }
+
+    #[test]
+    fn pkh_wildcard_xpub() {
+        // Keys hashed by `pkh()` fragments are their own hash, so that every
+        // analysis sees the extended key rather than an underivable hash
+        let xpub = "[78412e3a/44'/0'/0']xpub6ERApfZwUNrhLCkDtcHTcxd75RbzS1ed54G1LkBUHQVHQKqhMkhgbmJbZRkrgZw4koxb5JaHWkY4ALHY2grBGRjaDMzQLcgJvLJuZZvRcEL/1/*";
+        let pk = "03f28773c2d975288bc7d1d205c3748651b075fbc6610e58cddeeddf8f19405aa8";
+        let xpub_key = DescriptorPublicKey::from_str(xpub).unwrap();
+        let secp = secp256k1::Secp256k1::verification_only();
+        for desc in &[
+            format!("pkh({})", xpub),
+            format!("wsh(or_d(pkh({}),pk({})))", xpub, pk),
+            format!("tr({},pkh({}))", pk, xpub),
+        ] {
+            let desc = Descriptor::<DescriptorPublicKey>::from_str(desc).unwrap();
+            assert!(desc.is_deriveable());
+            assert!(desc.for_any_key(|key| *key.as_key() == xpub_key));
+            let policy = desc.lift().unwrap();
+            assert!(policy.for_any_key(|key| *key.as_key() == xpub_key));
+
+            let derived_key = xpub_key.clone().derive(5).derive_public_key(&secp).unwrap();
+            let derived = desc.derived_descriptor(&secp, 5).unwrap();
+            let derived_policy = derived.lift().unwrap();
+            assert!(derived_policy.for_any_key(|key| match key {
+                ForEach::Key(_) => false,
+                ForEach::Hash(hash) => *hash == derived_key.to_pubkeyhash(),
+            }));
+            assert_eq!(
+                derived.script_pubkey(),
+                desc.derive(5)
+                    .derived_descriptor(&secp)
+                    .unwrap()
+                    .script_pubkey()
+            );
+        }
+    }
+
     #[test]
     fn test_xonly_keys() {
         let comp_key = "0308c0fcf8895f4361b4fc77afe2ad53b0bd27dcebfd863421b2b246dc283d4103";
@@ -1622,6 +3478,134 @@ pk(03f28773c2d975288bc7d1d205c3748651b075fbc6610e58cddeeddf8f19405aa8))";
             .unwrap_err();
     }
 
+    #[test]
+    fn context_key_errors() {
+        use crate::miniscript::{KeyRule, ScriptContextError};
+        use crate::ScriptContext;
+
+        let comp_key = "0308c0fcf8895f4361b4fc77afe2ad53b0bd27dcebfd863421b2b246dc283d4103";
+        let x_only_key = "08c0fcf8895f4361b4fc77afe2ad53b0bd27dcebfd863421b2b246dc283d4103";
+        let uncomp_key = "0479be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798483ada7726a3c4655da4fbfc0e1108a8fd17b448a68554199c47d08ffb10d4b8";
+        let key_error = |err: &Error| match *err {
+            Error::ContextError(ScriptContextError::Key(ref e)) => {
+                (e.rule, e.key.clone(), e.context, e.position)
+            }
+            ref err => panic!("unexpected error {:?}", err),
+        };
+
+        let wsh = format!("wsh(multi(2,{},{},{}))", comp_key, comp_key, uncomp_key);
+        let err = Descriptor::<PublicKey>::from_str(&wsh).unwrap_err();
+        assert_eq!(
+            key_error(&err),
+            (
+                KeyRule::CompressedOnly,
+                uncomp_key.to_owned(),
+                "Segwitv0",
+                Some(wsh.find(uncomp_key).unwrap())
+            )
+        );
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "uncompressed key {} at position 146 not allowed in Segwitv0 (compressed-only)",
+                uncomp_key
+            )
+        );
+        // The checksum does not move the key
+        let with_checksum = format!("{}#{}", wsh, desc_checksum(&wsh).unwrap());
+        assert_eq!(
+            Descriptor::<PublicKey>::from_str(&with_checksum).unwrap_err(),
+            err
+        );
+        assert_eq!(
+            Descriptor::<PublicKey>::from_str_with_options(&wsh, &Default::default()).unwrap_err(),
+            err
+        );
+
+        let sh = format!("sh(and_v(v:pk({}),pk({})))", comp_key, x_only_key);
+        assert_eq!(
+            key_error(&Descriptor::<DescriptorPublicKey>::from_str(&sh).unwrap_err()),
+            (
+                KeyRule::NoXOnly,
+                x_only_key.to_owned(),
+                "Legacy/p2sh",
+                Some(sh.rfind(x_only_key).unwrap())
+            )
+        );
+
+        for desc in &[
+            format!("wpkh({})", uncomp_key),
+            format!("wsh(sortedmulti(1,{},{}))", comp_key, uncomp_key),
+        ] {
+            assert_eq!(
+                key_error(&Descriptor::<PublicKey>::from_str(desc).unwrap_err()),
+                (
+                    KeyRule::CompressedOnly,
+                    uncomp_key.to_owned(),
+                    "Segwitv0",
+                    Some(desc.find(uncomp_key).unwrap())
+                )
+            );
+        }
+
+        // The key is found in the fragment rejecting it, not in the hash
+        // written the same way before it
+        let sh = format!("sh(and_v(v:sha256({}),pk({})))", x_only_key, x_only_key);
+        assert_eq!(
+            key_error(&Descriptor::<DescriptorPublicKey>::from_str(&sh).unwrap_err()),
+            (
+                KeyRule::NoXOnly,
+                x_only_key.to_owned(),
+                "Legacy/p2sh",
+                Some(sh.rfind(x_only_key).unwrap())
+            )
+        );
+
+        for tr in &[
+            format!("tr({},pk({}))", comp_key, uncomp_key),
+            format!(
+                "tr({},{{pk({}),leaf_ver(c2,pk({}))}})",
+                comp_key, comp_key, uncomp_key
+            ),
+        ] {
+            assert_eq!(
+                key_error(&Descriptor::<PublicKey>::from_str(tr).unwrap_err()),
+                (
+                    KeyRule::NoUncompressed,
+                    uncomp_key.to_owned(),
+                    "TapscriptCtx",
+                    Some(tr.find(uncomp_key).unwrap())
+                )
+            );
+        }
+
+        let ms = format!("and_v(v:pk({}),pk({}))", comp_key, uncomp_key);
+        assert_eq!(
+            key_error(&Miniscript::<PublicKey, Segwitv0>::from_str_insane(&ms).unwrap_err()),
+            (
+                KeyRule::CompressedOnly,
+                uncomp_key.to_owned(),
+                "Segwitv0",
+                Some(ms.find(uncomp_key).unwrap())
+            )
+        );
+
+        // There is no position without a string
+        let pk = crate::Terminal::PkK(PublicKey::from_str(uncomp_key).unwrap());
+        let ms = Miniscript::<PublicKey, Segwitv0>::from_ast(pk).unwrap();
+        assert_eq!(
+            key_error(&Error::from(
+                Segwitv0::check_global_validity(&ms).unwrap_err()
+            )),
+            (
+                KeyRule::CompressedOnly,
+                uncomp_key.to_owned(),
+                "Segwitv0",
+                None
+            )
+        );
+    }
+
     #[test]
     fn test_find_derivation_index_for_spk() {
         let secp = secp256k1::Secp256k1::verification_only();
@@ -1648,4 +3632,508 @@ pk(03f28773c2d975288bc7d1d205c3748651b075fbc6610e58cddeeddf8f19405aa8))";
             Ok(Some((1, expected_concrete)))
         );
     }
+
+    #[test]
+    fn bind_template() {
+        let comp_key = "0308c0fcf8895f4361b4fc77afe2ad53b0bd27dcebfd863421b2b246dc283d4103";
+        let x_only_key = "08c0fcf8895f4361b4fc77afe2ad53b0bd27dcebfd863421b2b246dc283d4103";
+        let uncomp_key = "0414fc03b8df87cd7b872996810db8458d61da8448e531569c8517b469a119d267be5645686309c6e6736dbd93940707cc9143d3cf29f1b877ff340e2cb2d259cf";
+        let xpub = "xpub6ERApfZwUNrhLCkDtcHTcxd75RbzS1ed54G1LkBUHQVHQKqhMkhgbmJbZRkrgZw4koxb5JaHWkY4ALHY2grBGRjaDMzQLcgJvLJuZZvRcEL";
+        let mut keys = BTreeMap::new();
+        keys.insert(
+            "comp".to_string(),
+            DescriptorPublicKey::from_str(comp_key).unwrap(),
+        );
+        keys.insert(
+            "xonly".to_string(),
+            DescriptorPublicKey::from_str(x_only_key).unwrap(),
+        );
+        keys.insert(
+            "uncomp".to_string(),
+            DescriptorPublicKey::from_str(uncomp_key).unwrap(),
+        );
+        keys.insert(
+            "xpub".to_string(),
+            DescriptorPublicKey::from_str(&format!("{}/1/*", xpub)).unwrap(),
+        );
+
+        // Placeholders can be mixed with literal keys and bound as key hashes
+        let template = Descriptor::<String>::from_str(&format!(
+            "wsh(or_d(pk(@comp),and_v(v:pkh(@xpub),pk({}))))",
+            comp_key
+        ))
+        .unwrap();
+        let desc = template.bind(&keys).unwrap();
+        assert_eq!(
+            desc,
+            Descriptor::<DescriptorPublicKey>::from_str(&format!(
+                "wsh(or_d(pk({}),and_v(v:pkh({}/1/*),pk({}))))",
+                comp_key, xpub, comp_key
+            ))
+            .unwrap()
+        );
+
+        let template = Descriptor::<String>::from_str("tr(@xonly,pk(@comp))").unwrap();
+        assert_eq!(
+            template.bind(&keys).unwrap().to_string(),
+            Descriptor::<DescriptorPublicKey>::from_str(&format!(
+                "tr({},pk({}))",
+                x_only_key, comp_key
+            ))
+            .unwrap()
+            .to_string()
+        );
+
+        // Missing placeholder
+        let template = Descriptor::<String>::from_str("wpkh(@carol)").unwrap();
+        template.bind(&keys).unwrap_err();
+
+        // Keys must be valid in the context they are bound into
+        let template = Descriptor::<String>::from_str("wsh(pk(@uncomp))").unwrap();
+        template.bind(&keys).unwrap_err();
+        let template = Descriptor::<String>::from_str("wsh(pk(@xonly))").unwrap();
+        template.bind(&keys).unwrap_err();
+        let template = Descriptor::<String>::from_str("sh(pk(@uncomp))").unwrap();
+        template.bind(&keys).unwrap();
+    }
+
+    #[test]
+    fn canonicalize() {
+        let canonical = |s: &str| {
+            Descriptor::<String>::from_str(s)
+                .unwrap()
+                .canonicalize()
+                .unwrap()
+                .to_string()
+        };
+        assert!(canonical("wsh(sortedmulti(2,C,A,B))").starts_with("wsh(sortedmulti(2,A,B,C))#"));
+        // Redundant fragments are part of the script, and are kept
+        assert_ne!(
+            canonical("sh(wsh(or_d(pk(A),and_v(v:1,and_v(v:pk(B),older(10))))))"),
+            canonical("sh(wsh(or_d(pk(A),and_v(v:pk(B),older(10)))))")
+        );
+        assert_ne!(
+            canonical("tr(A,{pk(B),and_v(v:1,pk(C))})"),
+            canonical("tr(A,{pk(B),pk(C)})")
+        );
+
+        let a = Descriptor::<String>::from_str("sh(sortedmulti(1,B,A))").unwrap();
+        let b = Descriptor::<String>::from_str("sh(sortedmulti(1,A,B))").unwrap();
+        assert!(a != b);
+        assert!(a.canonical_eq(&b).unwrap());
+        let c = Descriptor::<String>::from_str("sh(sortedmulti(1,A,C))").unwrap();
+        assert!(!a.canonical_eq(&c).unwrap());
+
+        // The canonical form has the same script pubkey
+        let (a, b, c) = (
+            "0231c7d3fc85c148717848033ce276ae2b464a4e2c367ed33886cc428b8af48ff8",
+            "0250863ad64a87ae8a2fe83c1af1a8403cb53f53e486d8511dad8a04887e5b2352",
+            "025476c2e83188368da1ff3e292e7acafcdb3566bb0ad253f62fc70f07aeee6357",
+        );
+        for desc in &[
+            format!("wsh(sortedmulti(2,{},{},{}))", c, a, b),
+            format!("sh(wsh(sortedmulti(1,{},{})))", b, a),
+            format!("sh(sortedmulti(1,{},{}))", c, b),
+            format!(
+                "wsh(or_d(pk({}),and_v(v:1,and_v(v:pk({}),older(10)))))",
+                a, b
+            ),
+            format!("tr({},{{pk({}),and_v(v:1,pk({}))}})", a, b, c),
+            format!("pkh({})", a),
+        ] {
+            let desc = Descriptor::<bitcoin::PublicKey>::from_str(desc).unwrap();
+            let canonical = desc.canonicalize().unwrap();
+            assert_eq!(canonical.script_pubkey(), desc.script_pubkey());
+        }
+    }
+
+    #[test]
+    fn satisfaction_weight() {
+        let pks = [
+            "0231c7d3fc85c148717848033ce276ae2b464a4e2c367ed33886cc428b8af48ff8",
+            "0250863ad64a87ae8a2fe83c1af1a8403cb53f53e486d8511dad8a04887e5b2352",
+            "025476c2e83188368da1ff3e292e7acafcdb3566bb0ad253f62fc70f07aeee6357",
+        ];
+        // 2-of-3 multisig: a dummy element, two signatures and the 105 byte script
+        let wsh =
+            StdDescriptor::from_str(&format!("wsh(multi(2,{},{},{}))", pks[0], pks[1], pks[2]))
+                .unwrap();
+        let weight = wsh.satisfaction_weight().unwrap();
+        assert_eq!(
+            weight,
+            SatisfactionWeight {
+                script_sig_size: 0,
+                witness_elements: 4,
+                witness_size: 72 * 2 + 105,
+                witness_overhead: 1 + 4,
+            }
+        );
+        assert_eq!(weight.weight(), wsh.max_satisfaction_weight().unwrap());
+        assert_eq!(
+            wsh.max_weight_to_satisfy().unwrap(),
+            weight.weight() - 4 - 1
+        );
+        assert_eq!(weight.input_weight(), 4 * 41 + weight.witness_weight());
+
+        let sh_wsh = StdDescriptor::from_str(&format!(
+            "sh(wsh(multi(2,{},{},{})))",
+            pks[0], pks[1], pks[2]
+        ))
+        .unwrap();
+        assert_eq!(
+            sh_wsh.satisfaction_weight().unwrap(),
+            SatisfactionWeight {
+                script_sig_size: 35,
+                ..weight
+            }
+        );
+
+        let pkh = StdDescriptor::from_str(&format!("pkh({})", pks[0])).unwrap();
+        assert_eq!(pkh.satisfaction_weight().unwrap().witness_weight(), 0);
+        assert_eq!(pkh.max_satisfaction_weight().unwrap(), 4 * (1 + 73 + 34));
+        assert_eq!(pkh.max_weight_to_satisfy().unwrap(), 4 * (73 + 34));
+        assert_eq!(
+            pkh.satisfaction_weight().unwrap().input_weight(),
+            4 * (36 + 4 + 1 + 73 + 34) + 1
+        );
+
+        // Bitcoin Core's dust threshold of p2pkh, and the fee of its 149 vbytes
+        // input, rounded up
+        assert_eq!(pkh.dust_value(), Amount::from_sat(546));
+        assert_eq!(pkh.economic_dust(1000).unwrap(), Amount::from_sat(149));
+        assert_eq!(pkh.economic_dust(1500).unwrap(), Amount::from_sat(224));
+        assert_eq!(pkh.economic_dust(0).unwrap(), Amount::ZERO);
+        assert_eq!(pkh.economic_dust(u64::MAX), Err(Error::FeeOverflow));
+
+        // The key spend has a 65 byte signature; the leaf a 65 byte signature,
+        // the 34 byte script and the 65 byte control block
+        let tr =
+            StdDescriptor::from_str(&format!("tr({},{{pk({}),pk({})}})", pks[0], pks[1], pks[2]))
+                .unwrap();
+        let tr = match tr {
+            Descriptor::Tr(tr) => tr,
+            _ => unreachable!(),
+        };
+        assert_eq!(tr.key_spend_satisfaction_weight().weight(), 4 + 1 + 1 + 65);
+        let leaf = SatisfactionWeight {
+            script_sig_size: 0,
+            witness_elements: 3,
+            witness_size: 65 + 34 + 65,
+            witness_overhead: 1 + 3,
+        };
+        assert_eq!(tr.leaf_satisfaction_weights(), vec![Some(leaf), Some(leaf)]);
+        assert_eq!(tr.satisfaction_weight().unwrap(), leaf);
+        assert_eq!(tr.max_satisfaction_weight().unwrap(), 4 + 65 + 34 + 65 + 4);
+    }
+
+    #[test]
+    fn from_str_lenient() {
+        let desc =
+            Descriptor::<String>::from_str("wsh(or_d(pk(A),and_v(v:pk(B),older(144))))").unwrap();
+        let checksum = desc.to_string().split('#').nth(1).unwrap().to_owned();
+        let policy_file = format!(
+            "# Vault of A, recoverable by B after a day
+            wsh(
+                or_d(
+                    pk(A),  # hot key
+                    and_v(v:pk(B), older(144))
+                )
+            )#{}
+            ",
+            checksum
+        );
+        assert_eq!(Descriptor::from_str_lenient(&policy_file).unwrap(), desc);
+        // The checksum still has to match
+        let policy_file = policy_file.replace(&checksum, "qqqqqqqq");
+        assert!(Descriptor::<String>::from_str_lenient(&policy_file).is_err());
+        // The strict parser rejects whitespace
+        assert!(Descriptor::<String>::from_str("wsh(or_d(pk(A), pk(B)))").is_err());
+    }
+
+    #[test]
+    fn replace_key() {
+        let key = |s: &str| DescriptorPublicKey::from_str(s).unwrap();
+        let a = key("02d7924d4f7d43ea965a465ae3095ff41131e5946f3c85f79e44adbcf8e27e080e");
+        let b = key("03b506a1dbe57b4bf48c95e0c7d417b87dd3b4349d290d2e7e9ba72c912652d80a");
+        let c = key("0231c7d3fc85c148717848033ce276ae2b464a4e2c367ed33886cc428b8af48ff8");
+        let desc = Descriptor::<DescriptorPublicKey>::from_str(&format!(
+            "wsh(or_d(multi(2,{a},{b}),and_v(v:pkh({a}),older(144))))",
+            a = a,
+            b = b
+        ))
+        .unwrap();
+
+        let rotated = desc.replace_key(&a, c.clone()).unwrap();
+        assert_eq!(
+            rotated,
+            Descriptor::from_str(&format!(
+                "wsh(or_d(multi(2,{c},{b}),and_v(v:pkh({c}),older(144))))",
+                c = c,
+                b = b
+            ))
+            .unwrap()
+        );
+        assert_eq!(
+            rotated.replace_key(&a, b.clone()),
+            Err(KeyRotationError::KeyNotFound)
+        );
+        // x-only keys are only valid in tapscript
+        let x_only = key("d7924d4f7d43ea965a465ae3095ff41131e5946f3c85f79e44adbcf8e27e080e");
+        match desc.replace_key(&a, x_only) {
+            Err(KeyRotationError::InvalidDescriptor(_)) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+    }
+
+    #[test]
+    fn spend_model() {
+        let model = |s: &str| Descriptor::<String>::from_str(s).unwrap().spend_model();
+
+        let pkh = model("pkh(A)");
+        assert_eq!(pkh.desc_type, DescriptorType::Pkh);
+        assert_eq!(pkh.witness_version, None);
+        assert!(!pkh.key_spend && !pkh.needs_redeem_script && !pkh.nested);
+
+        let sh = model("sh(multi(1,A,B))");
+        assert_eq!(sh.witness_version, None);
+        assert!(sh.needs_redeem_script && !sh.nested);
+
+        for desc in &[
+            "sh(wpkh(A))",
+            "sh(wsh(pk(A)))",
+            "sh(wsh(sortedmulti(1,A,B)))",
+        ] {
+            let nested = model(desc);
+            assert_eq!(nested.witness_version, Some(WitnessVersion::V0));
+            assert!(nested.needs_redeem_script && nested.nested);
+        }
+
+        let wsh = model("wsh(pk(A))");
+        assert_eq!(wsh.witness_version, Some(WitnessVersion::V0));
+        assert!(!wsh.key_spend && !wsh.needs_redeem_script && !wsh.nested);
+
+        assert_eq!(
+            model("tr(A,{pk(B),{pk(C),pk(D)}})"),
+            SpendModel {
+                desc_type: DescriptorType::Tr,
+                witness_version: Some(WitnessVersion::V1),
+                key_spend: true,
+                tap_leaves: 3,
+                needs_redeem_script: false,
+                nested: false,
+            }
+        );
+        assert_eq!(model("tr(A)").tap_leaves, 0);
+
+        let raw =
+            model("raw(51200101010101010101010101010101010101010101010101010101010101010101)");
+        assert_eq!(raw.witness_version, Some(WitnessVersion::V1));
+        assert!(raw.key_spend && !raw.needs_redeem_script);
+
+        let addr = model("addr(3J98t1WpEZ73CNmQviecrnyiWrnqRhWNLy)");
+        assert_eq!(addr.witness_version, None);
+        assert!(!addr.key_spend && addr.needs_redeem_script && !addr.nested);
+    }
+
+    #[test]
+    fn derives_same_scripts_and_contains() {
+        let secp = secp256k1::Secp256k1::verification_only();
+        let desc = |s: &str| Descriptor::<DescriptorPublicKey>::from_str(s).unwrap();
+        let xpub_a = "xpub6ERApfZwUNrhLCkDtcHTcxd75RbzS1ed54G1LkBUHQVHQKqhMkhgbmJbZRkrgZw4koxb5JaHWkY4ALHY2grBGRjaDMzQLcgJvLJuZZvRcEL";
+        let xpub_b = "xpub68NZiKmJWnxxS6aaHmn81bvJeTESw724CRDs6HbuccFQN9Ku14VQrADWgqbhhTHBaohPX4CjNLf9fq9MYo6oDaPPLPxSb7gwQN3ih19Zm4Y";
+        let child = bip32::ExtendedPubKey::from_str(xpub_a)
+            .unwrap()
+            .ckd_pub(&secp, bip32::ChildNumber::from_normal_idx(0).unwrap())
+            .unwrap();
+
+        let parent = desc(&format!("wpkh([d34db33f/84'/0'/0']{}/0/*)", xpub_a));
+        // The origin and the place of the derivation path do not matter
+        let same = [
+            format!("wpkh({}/0/*)", xpub_a),
+            format!("wpkh({}/*)", child),
+        ];
+        for other in &same {
+            assert!(parent.derives_same_scripts(&desc(other)).unwrap());
+        }
+        for other in &[
+            format!("wpkh({}/1/*)", xpub_a),
+            format!("wpkh({}/0/*')", xpub_a),
+            format!("sh(wpkh({}/0/*))", xpub_a),
+        ] {
+            assert!(!parent.derives_same_scripts(&desc(other)).unwrap());
+        }
+
+        // Nor does the order of the keys of a sortedmulti
+        let multi = desc(&format!("wsh(sortedmulti(1,{}/*,{}/*))", xpub_a, xpub_b));
+        let swapped = desc(&format!("wsh(sortedmulti(1,{}/*,{}/*))", xpub_b, xpub_a));
+        assert!(multi.derives_same_scripts(&swapped).unwrap());
+
+        // Derived descriptors are contained at their index
+        let derived = desc(&parent.derive(5).to_string());
+        assert!(parent.contains(&derived).unwrap());
+        assert!(desc(&same[1]).contains(&derived).unwrap());
+        assert!(!parent
+            .contains(&desc(&multi.derive(5).to_string()))
+            .unwrap());
+        let pk = child
+            .ckd_pub(&secp, bip32::ChildNumber::from_normal_idx(5).unwrap())
+            .unwrap()
+            .public_key;
+        let single = desc(&format!("wpkh([d34db33f/84'/0'/0'/0/5]{})", pk));
+        assert!(parent.contains(&single).unwrap());
+        let wrong_index = desc(&format!("wpkh([d34db33f/84'/0'/0'/0/6]{})", pk));
+        assert!(!parent.contains(&wrong_index).unwrap());
+        assert!(!parent.contains(&parent).unwrap());
+        assert!(swapped
+            .contains(&desc(&multi.derive(7).to_string()))
+            .unwrap());
+    }
+
+    #[test]
+    fn from_txin() {
+        let secp = secp256k1::Secp256k1::signing_only();
+        let keys: Vec<_> = (1..5u8)
+            .map(|i| {
+                let sk = secp256k1::SecretKey::from_slice(&[i; 32]).unwrap();
+                PublicKey::new(secp256k1::PublicKey::from_secret_key(&secp, &sk))
+            })
+            .collect();
+        let sig = vec![0x30; 72];
+        let spend = |desc: &StdDescriptor, script_sig: Script, witness: Vec<Vec<u8>>| {
+            let txin = TxIn {
+                script_sig,
+                witness: Witness::from_vec(witness),
+                ..TxIn::default()
+            };
+            let prevout = TxOut {
+                value: 1000,
+                script_pubkey: desc.script_pubkey(),
+            };
+            Descriptor::from_txin(&txin, &prevout).unwrap()
+        };
+
+        let wpkh = StdDescriptor::from_str(&format!("wpkh({})", keys[0])).unwrap();
+        let witness = vec![sig.clone(), keys[0].to_bytes()];
+        assert_eq!(
+            spend(&wpkh, Script::new(), witness),
+            (wpkh, SpendBranch::Script)
+        );
+
+        let sh_wsh =
+            StdDescriptor::from_str(&format!("sh(wsh(multi(1,{},{})))", keys[0], keys[1])).unwrap();
+        let witness_script = sh_wsh.explicit_script().unwrap();
+        let script_sig = script::Builder::new()
+            .push_slice(&witness_script.to_v0_p2wsh()[..])
+            .into_script();
+        let witness = vec![vec![], sig.clone(), witness_script.to_bytes()];
+        assert_eq!(
+            spend(&sh_wsh, script_sig, witness),
+            (sh_wsh, SpendBranch::Script)
+        );
+
+        let tr = StdDescriptor::from_str(&format!(
+            "tr({},{{pk({}),{{pk({}),pk({})}}}})",
+            keys[0], keys[1], keys[2], keys[3]
+        ))
+        .unwrap();
+        let output_key = match tr {
+            Descriptor::Tr(ref tr) => tr.spend_info().output_key(),
+            _ => unreachable!(),
+        };
+        let (desc, branch) = spend(&tr, Script::new(), vec![vec![0x01; 64]]);
+        assert_eq!(
+            desc,
+            Descriptor::new_rawtr(output_key.to_inner().to_public_key())
+        );
+        assert_eq!(branch, SpendBranch::TapKey);
+
+        // The leaf of the third key, whose sibling and uncle are hidden
+        let leaf = Miniscript::<PublicKey, Tap>::from_str(&format!("pk({})", keys[2])).unwrap();
+        let leaf_script = leaf.encode();
+        let control_block = match tr {
+            Descriptor::Tr(ref tr) => tr
+                .spend_info()
+                .control_block(&(leaf_script.clone(), LeafVersion::TapScript))
+                .unwrap(),
+            _ => unreachable!(),
+        };
+        let witness = vec![
+            vec![0x01; 64],
+            leaf_script.to_bytes(),
+            control_block.serialize(),
+        ];
+        let (desc, branch) = spend(&tr, Script::new(), witness);
+        assert_eq!(desc.script_pubkey(), tr.script_pubkey());
+        match desc {
+            Descriptor::Tr(ref inferred) => {
+                // Keys only keep their x coordinate
+                let leaves: Vec<_> = inferred
+                    .iter_scripts()
+                    .map(|(depth, ms)| (depth, ms.encode()))
+                    .collect();
+                assert_eq!(leaves, vec![(2, leaf_script.clone())]);
+                assert_eq!(
+                    inferred.internal_key().to_x_only_pubkey(),
+                    keys[0].to_x_only_pubkey()
+                );
+            }
+            _ => panic!("expected a tr descriptor"),
+        }
+        assert_eq!(
+            branch,
+            SpendBranch::TapLeaf {
+                leaf_hash: TapLeafHash::from_script(&leaf_script, LeafVersion::TapScript),
+                depth: 2,
+            }
+        );
+    }
+
+    #[test]
+    fn from_str_combo() {
+        let comp_key = "0308c0fcf8895f4361b4fc77afe2ad53b0bd27dcebfd863421b2b246dc283d4103";
+        let uncomp_key = "0479be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798483ada7726a3c4655da4fbfc0e1108a8fd17b448a68554199c47d08ffb10d4b8";
+        let x_only_key = "08c0fcf8895f4361b4fc77afe2ad53b0bd27dcebfd863421b2b246dc283d4103";
+        let expand = |s: &str| {
+            Descriptor::<DescriptorPublicKey>::from_str_combo(s).map(|descs| {
+                descs
+                    .iter()
+                    .map(|desc| desc.to_string_no_checksum())
+                    .collect::<Vec<_>>()
+            })
+        };
+
+        let combo = format!("combo({})", comp_key);
+        let expected = vec![
+            format!("pk({})", comp_key),
+            format!("pkh({})", comp_key),
+            format!("wpkh({})", comp_key),
+            format!("sh(wpkh({}))", comp_key),
+        ];
+        assert_eq!(expand(&combo).unwrap(), expected);
+        let with_checksum = format!("{}#{}", combo, desc_checksum(&combo).unwrap());
+        assert_eq!(expand(&with_checksum).unwrap(), expected);
+        assert!(expand(&format!("{}#00000000", combo)).is_err());
+
+        // Uncompressed keys have no segwit descriptors
+        assert_eq!(
+            expand(&format!("combo({})", uncomp_key)).unwrap(),
+            vec![
+                format!("pk({})", uncomp_key),
+                format!("pkh({})", uncomp_key)
+            ]
+        );
+        match expand(&format!("combo({})", x_only_key)) {
+            Err(Error::ContextError(ScriptContextError::Key(e))) => {
+                assert_eq!(e.rule, KeyRule::NoXOnly)
+            }
+            res => panic!("unexpected result {:?}", res),
+        }
+        assert!(expand(&format!("combo({},{})", comp_key, comp_key)).is_err());
+
+        // Other descriptors are parsed as they are
+        assert_eq!(
+            expand(&format!("wpkh({})", comp_key)).unwrap(),
+            vec![format!("wpkh({})", comp_key)]
+        );
+    }
 }
@@ -22,15 +22,15 @@ use core::str::FromStr;
 use bitcoin::{self, Address, Network, Script};
 
 use super::checksum::{desc_checksum, verify_checksum};
-use super::SortedMultiVec;
+use super::{SatisfactionWeight, SortedMultiVec};
 use crate::expression::{self, FromTree};
-use crate::miniscript::context::{ScriptContext, ScriptContextError};
+use crate::miniscript::context::{self, ContextError, KeyRule, ScriptContext, ScriptContextError};
+use crate::miniscript::limits::RuleSet;
 use crate::policy::{semantic, Liftable};
 use crate::prelude::*;
-use crate::util::varint_len;
 use crate::{
     Error, ForEach, ForEachKey, Miniscript, MiniscriptKey, Satisfier, Segwitv0, ToPublicKey,
-    TranslatePk,
+    TranslatePk, Translator,
 };
 /// A Segwitv0 wsh descriptor
 #[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
@@ -78,9 +78,15 @@ impl<Pk: MiniscriptKey> Wsh<Pk> {
 
     /// Checks whether the descriptor is safe.
     pub fn sanity_check(&self) -> Result<(), Error> {
+        self.sanity_check_with(&RuleSet::Standard)
+    }
+
+    /// Checks whether the descriptor is safe as [`Self::sanity_check`] does,
+    /// checking its resource usage against `rules`
+    pub fn sanity_check_with(&self, rules: &RuleSet) -> Result<(), Error> {
         match self.inner {
-            WshInner::SortedMulti(ref smv) => smv.sanity_check()?,
-            WshInner::Ms(ref ms) => ms.sanity_check()?,
+            WshInner::SortedMulti(ref smv) => smv.sanity_check_with(rules)?,
+            WshInner::Ms(ref ms) => ms.sanity_check_with(rules)?,
         }
         Ok(())
     }
@@ -95,6 +101,12 @@ impl<Pk: MiniscriptKey> Wsh<Pk> {
     /// # Errors
     /// When the descriptor is impossible to safisfy (ex: sh(OP_FALSE)).
     pub fn max_satisfaction_weight(&self) -> Result<usize, Error> {
+        Ok(self.satisfaction_weight()?.weight())
+    }
+
+    /// Computes an upper bound on the size of a satisfaction, see
+    /// [`Descriptor::satisfaction_weight`](crate::Descriptor::satisfaction_weight).
+    pub fn satisfaction_weight(&self) -> Result<SatisfactionWeight, Error> {
         let (script_size, max_sat_elems, max_sat_size) = match self.inner {
             WshInner::SortedMulti(ref smv) => (
                 smv.script_size(),
@@ -107,11 +119,12 @@ impl<Pk: MiniscriptKey> Wsh<Pk> {
                 ms.max_satisfaction_size()?,
             ),
         };
-        Ok(4 +  // scriptSig length byte
-            varint_len(script_size) +
-            script_size +
-            varint_len(max_sat_elems) +
-            max_sat_size)
+        Ok(SatisfactionWeight::from_witness_script(
+            max_sat_elems,
+            max_sat_size,
+            script_size,
+            None,
+        ))
     }
 }
 
@@ -149,26 +162,36 @@ impl<Pk: MiniscriptKey + ToPublicKey> Wsh<Pk> {
     where
         S: Satisfier<Pk>,
     {
-        let mut witness = match self.inner {
-            WshInner::SortedMulti(ref smv) => smv.satisfy(satisfier)?,
-            WshInner::Ms(ref ms) => ms.satisfy(satisfier)?,
-        };
-        let witness_script = self.inner_script();
-        witness.push(witness_script.into_bytes());
-        let script_sig = Script::new();
-        Ok((witness, script_sig))
+        self.satisfaction_with(satisfier, false, &RuleSet::Standard)
     }
 
     /// Returns satisfying, possibly malleable, witness and scriptSig with
     /// minimum weight to spend an output controlled by the given descriptor if
     /// it is possible to construct one using the `satisfier`.
     pub fn get_satisfaction_mall<S>(&self, satisfier: S) -> Result<(Vec<Vec<u8>>, Script), Error>
+    where
+        S: Satisfier<Pk>,
+    {
+        self.satisfaction_with(satisfier, true, &RuleSet::Standard)
+    }
+
+    // Helper function to get a satisfaction whose witness is checked against
+    // the limits of `rules`
+    pub(crate) fn satisfaction_with<S>(
+        &self,
+        satisfier: S,
+        allow_mall: bool,
+        rules: &RuleSet,
+    ) -> Result<(Vec<Vec<u8>>, Script), Error>
     where
         S: Satisfier<Pk>,
     {
         let mut witness = match self.inner {
-            WshInner::SortedMulti(ref smv) => smv.satisfy(satisfier)?,
-            WshInner::Ms(ref ms) => ms.satisfy_malleable(satisfier)?,
+            WshInner::SortedMulti(ref smv) => smv.satisfy_with_rules(satisfier, rules)?,
+            WshInner::Ms(ref ms) if allow_mall => {
+                ms.satisfy_malleable_with_rules(satisfier, rules)?
+            }
+            WshInner::Ms(ref ms) => ms.satisfy_with_rules(satisfier, rules)?,
         };
         witness.push(self.inner_script().into_bytes());
         let script_sig = Script::new();
@@ -198,8 +221,16 @@ impl<Pk> FromTree for Wsh<Pk>
 where
     Pk: MiniscriptKey + FromStr,
     Pk::Hash: FromStr,
+    Pk::Sha256: FromStr,
+    Pk::Hash256: FromStr,
+    Pk::Ripemd160: FromStr,
+    Pk::Hash160: FromStr,
     <Pk as FromStr>::Err: ToString,
     <<Pk as MiniscriptKey>::Hash as FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Sha256 as FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Hash256 as FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Ripemd160 as FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Hash160 as FromStr>::Err: ToString,
 {
     fn from_tree(top: &expression::Tree) -> Result<Self, Error> {
         if top.name == "wsh" && top.args.len() == 1 {
@@ -244,8 +275,16 @@ impl<Pk> FromStr for Wsh<Pk>
 where
     Pk: MiniscriptKey + FromStr,
     Pk::Hash: FromStr,
+    Pk::Sha256: FromStr,
+    Pk::Hash256: FromStr,
+    Pk::Ripemd160: FromStr,
+    Pk::Hash160: FromStr,
     <Pk as FromStr>::Err: ToString,
     <<Pk as MiniscriptKey>::Hash as FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Sha256 as FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Hash256 as FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Ripemd160 as FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Hash160 as FromStr>::Err: ToString,
 {
     type Err = Error;
 
@@ -276,14 +315,13 @@ where
 {
     type Output = Wsh<Q>;
 
-    fn translate_pk<Fpk, Fpkh, E>(&self, mut fpk: Fpk, mut fpkh: Fpkh) -> Result<Self::Output, E>
+    fn translate_pk<T, E>(&self, t: &mut T) -> Result<Self::Output, E>
     where
-        Fpk: FnMut(&P) -> Result<Q, E>,
-        Fpkh: FnMut(&P::Hash) -> Result<Q::Hash, E>,
+        T: Translator<P, Q, E> + ?Sized,
     {
         let inner = match self.inner {
-            WshInner::SortedMulti(ref smv) => WshInner::SortedMulti(smv.translate_pk(&mut fpk)?),
-            WshInner::Ms(ref ms) => WshInner::Ms(ms.translate_pk(&mut fpk, &mut fpkh)?),
+            WshInner::SortedMulti(ref smv) => WshInner::SortedMulti(smv.translate_pk(t)?),
+            WshInner::Ms(ref ms) => WshInner::Ms(ms.translate_pk(t)?),
         };
         Ok(Wsh { inner })
     }
@@ -301,8 +339,8 @@ impl<Pk: MiniscriptKey> Wpkh<Pk> {
     pub fn new(pk: Pk) -> Result<Self, Error> {
         // do the top-level checks
         if pk.is_uncompressed() {
-            Err(Error::ContextError(ScriptContextError::CompressedOnly(
-                pk.to_string(),
+            Err(Error::ContextError(ScriptContextError::Key(
+                ContextError::new::<_, Segwitv0>(KeyRule::CompressedOnly, &pk),
             )))
         } else {
             Ok(Self { pk })
@@ -327,8 +365,8 @@ impl<Pk: MiniscriptKey> Wpkh<Pk> {
     /// Checks whether the descriptor is safe.
     pub fn sanity_check(&self) -> Result<(), Error> {
         if self.pk.is_uncompressed() {
-            Err(Error::ContextError(ScriptContextError::CompressedOnly(
-                self.pk.to_string(),
+            Err(Error::ContextError(ScriptContextError::Key(
+                ContextError::new::<_, Segwitv0>(KeyRule::CompressedOnly, &self.pk),
             )))
         } else {
             Ok(())
@@ -342,7 +380,18 @@ impl<Pk: MiniscriptKey> Wpkh<Pk> {
     /// sighash suffix. Includes the weight of the VarInts encoding the
     /// scriptSig and witness stack length.
     pub fn max_satisfaction_weight(&self) -> usize {
-        4 + 1 + 73 + Segwitv0::pk_len(&self.pk)
+        self.satisfaction_weight().weight()
+    }
+
+    /// Computes an upper bound on the size of a satisfaction, see
+    /// [`Descriptor::satisfaction_weight`](crate::Descriptor::satisfaction_weight).
+    pub fn satisfaction_weight(&self) -> SatisfactionWeight {
+        SatisfactionWeight {
+            script_sig_size: 0,
+            witness_elements: 2,
+            witness_size: 72 + Segwitv0::pk_len(&self.pk) - 1,
+            witness_overhead: 3,
+        }
     }
 }
 
@@ -427,14 +476,21 @@ impl<Pk> FromTree for Wpkh<Pk>
 where
     Pk: MiniscriptKey + FromStr,
     Pk::Hash: FromStr,
+    Pk::Sha256: FromStr,
+    Pk::Hash256: FromStr,
+    Pk::Ripemd160: FromStr,
+    Pk::Hash160: FromStr,
     <Pk as FromStr>::Err: ToString,
     <<Pk as MiniscriptKey>::Hash as FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Sha256 as FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Hash256 as FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Ripemd160 as FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Hash160 as FromStr>::Err: ToString,
 {
     fn from_tree(top: &expression::Tree) -> Result<Self, Error> {
         if top.name == "wpkh" && top.args.len() == 1 {
-            Ok(Wpkh::new(expression::terminal(&top.args[0], |pk| {
-                Pk::from_str(pk)
-            })?)?)
+            Wpkh::new(expression::terminal(&top.args[0], |pk| Pk::from_str(pk))?)
+                .map_err(|e| context::mark_key::<Pk>(e, top))
         } else {
             Err(Error::Unexpected(format!(
                 "{}({} args) while parsing wpkh descriptor",
@@ -449,8 +505,16 @@ impl<Pk> FromStr for Wpkh<Pk>
 where
     Pk: MiniscriptKey + FromStr,
     Pk::Hash: FromStr,
+    Pk::Sha256: FromStr,
+    Pk::Hash256: FromStr,
+    Pk::Ripemd160: FromStr,
+    Pk::Hash160: FromStr,
     <Pk as FromStr>::Err: ToString,
     <<Pk as MiniscriptKey>::Hash as FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Sha256 as FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Hash256 as FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Ripemd160 as FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Hash160 as FromStr>::Err: ToString,
 {
     type Err = Error;
 
@@ -478,11 +542,10 @@ where
 {
     type Output = Wpkh<Q>;
 
-    fn translate_pk<Fpk, Fpkh, E>(&self, mut fpk: Fpk, _fpkh: Fpkh) -> Result<Self::Output, E>
+    fn translate_pk<T, E>(&self, t: &mut T) -> Result<Self::Output, E>
     where
-        Fpk: FnMut(&P) -> Result<Q, E>,
-        Fpkh: FnMut(&P::Hash) -> Result<Q::Hash, E>,
+        T: Translator<P, Q, E> + ?Sized,
     {
-        Ok(Wpkh::new(fpk(&self.pk)?).expect("Uncompressed keys in Wpkh"))
+        Ok(Wpkh::new(t.pk(&self.pk)?).expect("Uncompressed keys in Wpkh"))
     }
 }
@@ -25,15 +25,16 @@ use bitcoin::blockdata::script;
 use bitcoin::{Address, Network, Script};
 
 use super::checksum::{desc_checksum, verify_checksum};
-use super::{SortedMultiVec, Wpkh, Wsh};
+use super::{SatisfactionWeight, SortedMultiVec, Wpkh, Wsh};
 use crate::expression::{self, FromTree};
 use crate::miniscript::context::ScriptContext;
+use crate::miniscript::limits::RuleSet;
 use crate::policy::{semantic, Liftable};
 use crate::prelude::*;
-use crate::util::{varint_len, witness_to_scriptsig};
+use crate::util::witness_to_scriptsig;
 use crate::{
     push_opcode_size, Error, ForEach, ForEachKey, Legacy, Miniscript, MiniscriptKey, Satisfier,
-    Segwitv0, ToPublicKey, TranslatePk,
+    Segwitv0, ToPublicKey, TranslatePk, Translator,
 };
 
 /// A Legacy p2sh Descriptor
@@ -80,12 +81,7 @@ impl<Pk: MiniscriptKey> fmt::Debug for Sh<Pk> {
 
 impl<Pk: MiniscriptKey> fmt::Display for Sh<Pk> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let desc = match self.inner {
-            ShInner::Wsh(ref wsh) => format!("sh({})", wsh.to_string_no_checksum()),
-            ShInner::Wpkh(ref pk) => format!("sh({})", pk.to_string_no_checksum()),
-            ShInner::SortedMulti(ref smv) => format!("sh({})", smv),
-            ShInner::Ms(ref ms) => format!("sh({})", ms),
-        };
+        let desc = self.to_string_no_checksum();
         let checksum = desc_checksum(&desc).map_err(|_| fmt::Error)?;
         write!(f, "{}#{}", &desc, &checksum)
     }
@@ -95,8 +91,16 @@ impl<Pk> FromTree for Sh<Pk>
 where
     Pk: MiniscriptKey + FromStr,
     Pk::Hash: FromStr,
+    Pk::Sha256: FromStr,
+    Pk::Hash256: FromStr,
+    Pk::Ripemd160: FromStr,
+    Pk::Hash160: FromStr,
     <Pk as FromStr>::Err: ToString,
     <<Pk as MiniscriptKey>::Hash as FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Sha256 as FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Hash256 as FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Ripemd160 as FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Hash160 as FromStr>::Err: ToString,
 {
     fn from_tree(top: &expression::Tree) -> Result<Self, Error> {
         if top.name == "sh" && top.args.len() == 1 {
@@ -126,8 +130,16 @@ impl<Pk> FromStr for Sh<Pk>
 where
     Pk: MiniscriptKey + FromStr,
     Pk::Hash: FromStr,
+    Pk::Sha256: FromStr,
+    Pk::Hash256: FromStr,
+    Pk::Ripemd160: FromStr,
+    Pk::Hash160: FromStr,
     <Pk as FromStr>::Err: ToString,
     <<Pk as MiniscriptKey>::Hash as FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Sha256 as FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Hash256 as FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Ripemd160 as FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Hash160 as FromStr>::Err: ToString,
 {
     type Err = Error;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
@@ -148,6 +160,16 @@ impl<Pk: MiniscriptKey> Sh<Pk> {
         &self.inner
     }
 
+    /// Get the descriptor without the checksum
+    pub fn to_string_no_checksum(&self) -> String {
+        match self.inner {
+            ShInner::Wsh(ref wsh) => format!("sh({})", wsh.to_string_no_checksum()),
+            ShInner::Wpkh(ref pk) => format!("sh({})", pk.to_string_no_checksum()),
+            ShInner::SortedMulti(ref smv) => format!("sh({})", smv),
+            ShInner::Ms(ref ms) => format!("sh({})", ms),
+        }
+    }
+
     /// Create a new p2sh descriptor with the raw miniscript
     pub fn new(ms: Miniscript<Pk, Legacy>) -> Result<Self, Error> {
         // do the top-level checks
@@ -183,11 +205,17 @@ impl<Pk: MiniscriptKey> Sh<Pk> {
 
     /// Checks whether the descriptor is safe.
     pub fn sanity_check(&self) -> Result<(), Error> {
+        self.sanity_check_with(&RuleSet::Standard)
+    }
+
+    /// Checks whether the descriptor is safe as [`Self::sanity_check`] does,
+    /// checking its resource usage against `rules`
+    pub fn sanity_check_with(&self, rules: &RuleSet) -> Result<(), Error> {
         match self.inner {
-            ShInner::Wsh(ref wsh) => wsh.sanity_check()?,
+            ShInner::Wsh(ref wsh) => wsh.sanity_check_with(rules)?,
             ShInner::Wpkh(ref wpkh) => wpkh.sanity_check()?,
-            ShInner::SortedMulti(ref smv) => smv.sanity_check()?,
-            ShInner::Ms(ref ms) => ms.sanity_check()?,
+            ShInner::SortedMulti(ref smv) => smv.sanity_check_with(rules)?,
+            ShInner::Ms(ref ms) => ms.sanity_check_with(rules)?,
         }
         Ok(())
     }
@@ -226,22 +254,38 @@ impl<Pk: MiniscriptKey> Sh<Pk> {
     /// # Errors
     /// When the descriptor is impossible to safisfy (ex: sh(OP_FALSE)).
     pub fn max_satisfaction_weight(&self) -> Result<usize, Error> {
+        Ok(self.satisfaction_weight()?.weight())
+    }
+
+    /// Computes an upper bound on the size of a satisfaction, see
+    /// [`Descriptor::satisfaction_weight`](crate::Descriptor::satisfaction_weight).
+    pub fn satisfaction_weight(&self) -> Result<SatisfactionWeight, Error> {
         Ok(match self.inner {
-            // add weighted script sig, len byte stays the same
-            ShInner::Wsh(ref wsh) => 4 * 35 + wsh.max_satisfaction_weight()?,
+            // the script sig pushes the 34 byte witness program
+            ShInner::Wsh(ref wsh) => SatisfactionWeight {
+                script_sig_size: 35,
+                ..wsh.satisfaction_weight()?
+            },
             ShInner::SortedMulti(ref smv) => {
                 let ss = smv.script_size();
                 let ps = push_opcode_size(ss);
-                let scriptsig_len = ps + ss + smv.max_satisfaction_size();
-                4 * (varint_len(scriptsig_len) + scriptsig_len)
+                SatisfactionWeight {
+                    script_sig_size: ps + ss + smv.max_satisfaction_size(),
+                    ..Default::default()
+                }
             }
-            // add weighted script sig, len byte stays the same
-            ShInner::Wpkh(ref wpkh) => 4 * 23 + wpkh.max_satisfaction_weight(),
+            // the script sig pushes the 22 byte witness program
+            ShInner::Wpkh(ref wpkh) => SatisfactionWeight {
+                script_sig_size: 23,
+                ..wpkh.satisfaction_weight()
+            },
             ShInner::Ms(ref ms) => {
                 let ss = ms.script_size();
                 let ps = push_opcode_size(ss);
-                let scriptsig_len = ps + ss + ms.max_satisfaction_size()?;
-                4 * (varint_len(scriptsig_len) + scriptsig_len)
+                SatisfactionWeight {
+                    script_sig_size: ps + ss + ms.max_satisfaction_size()?,
+                    ..Default::default()
+                }
             }
         })
     }
@@ -332,13 +376,37 @@ impl<Pk: MiniscriptKey + ToPublicKey> Sh<Pk> {
     /// weight to spend an output controlled by the given descriptor if it is
     /// possible to construct one using the `satisfier`.
     pub fn get_satisfaction<S>(&self, satisfier: S) -> Result<(Vec<Vec<u8>>, Script), Error>
+    where
+        S: Satisfier<Pk>,
+    {
+        self.satisfaction_with(satisfier, false, &RuleSet::Standard)
+    }
+
+    /// Returns satisfying, possibly malleable, witness and scriptSig with
+    /// minimum weight to spend an output controlled by the given descriptor if
+    /// it is possible to construct one using the `satisfier`.
+    pub fn get_satisfaction_mall<S>(&self, satisfier: S) -> Result<(Vec<Vec<u8>>, Script), Error>
+    where
+        S: Satisfier<Pk>,
+    {
+        self.satisfaction_with(satisfier, true, &RuleSet::Standard)
+    }
+
+    // Helper function to get a satisfaction whose witness is checked against
+    // the limits of `rules`
+    pub(crate) fn satisfaction_with<S>(
+        &self,
+        satisfier: S,
+        allow_mall: bool,
+        rules: &RuleSet,
+    ) -> Result<(Vec<Vec<u8>>, Script), Error>
     where
         S: Satisfier<Pk>,
     {
         let script_sig = self.unsigned_script_sig();
         match self.inner {
             ShInner::Wsh(ref wsh) => {
-                let (witness, _) = wsh.get_satisfaction(satisfier)?;
+                let (witness, _) = wsh.satisfaction_with(satisfier, allow_mall, rules)?;
                 Ok((witness, script_sig))
             }
             ShInner::Wpkh(ref wpkh) => {
@@ -346,43 +414,23 @@ impl<Pk: MiniscriptKey + ToPublicKey> Sh<Pk> {
                 Ok((witness, script_sig))
             }
             ShInner::SortedMulti(ref smv) => {
-                let mut script_witness = smv.satisfy(satisfier)?;
+                let mut script_witness = smv.satisfy_with_rules(satisfier, rules)?;
                 script_witness.push(smv.encode().into_bytes());
                 let script_sig = witness_to_scriptsig(&script_witness);
                 let witness = vec![];
                 Ok((witness, script_sig))
             }
             ShInner::Ms(ref ms) => {
-                let mut script_witness = ms.satisfy(satisfier)?;
-                script_witness.push(ms.encode().into_bytes());
-                let script_sig = witness_to_scriptsig(&script_witness);
-                let witness = vec![];
-                Ok((witness, script_sig))
-            }
-        }
-    }
-
-    /// Returns satisfying, possibly malleable, witness and scriptSig with
-    /// minimum weight to spend an output controlled by the given descriptor if
-    /// it is possible to construct one using the `satisfier`.
-    pub fn get_satisfaction_mall<S>(&self, satisfier: S) -> Result<(Vec<Vec<u8>>, Script), Error>
-    where
-        S: Satisfier<Pk>,
-    {
-        let script_sig = self.unsigned_script_sig();
-        match self.inner {
-            ShInner::Wsh(ref wsh) => {
-                let (witness, _) = wsh.get_satisfaction_mall(satisfier)?;
-                Ok((witness, script_sig))
-            }
-            ShInner::Ms(ref ms) => {
-                let mut script_witness = ms.satisfy_malleable(satisfier)?;
+                let mut script_witness = if allow_mall {
+                    ms.satisfy_malleable_with_rules(satisfier, rules)?
+                } else {
+                    ms.satisfy_with_rules(satisfier, rules)?
+                };
                 script_witness.push(ms.encode().into_bytes());
                 let script_sig = witness_to_scriptsig(&script_witness);
                 let witness = vec![];
                 Ok((witness, script_sig))
             }
-            _ => self.get_satisfaction(satisfier),
         }
     }
 }
@@ -409,16 +457,15 @@ where
 {
     type Output = Sh<Q>;
 
-    fn translate_pk<Fpk, Fpkh, E>(&self, mut fpk: Fpk, mut fpkh: Fpkh) -> Result<Self::Output, E>
+    fn translate_pk<T, E>(&self, t: &mut T) -> Result<Self::Output, E>
     where
-        Fpk: FnMut(&P) -> Result<Q, E>,
-        Fpkh: FnMut(&P::Hash) -> Result<Q::Hash, E>,
+        T: Translator<P, Q, E> + ?Sized,
     {
         let inner = match self.inner {
-            ShInner::Wsh(ref wsh) => ShInner::Wsh(wsh.translate_pk(&mut fpk, &mut fpkh)?),
-            ShInner::Wpkh(ref wpkh) => ShInner::Wpkh(wpkh.translate_pk(&mut fpk, &mut fpkh)?),
-            ShInner::SortedMulti(ref smv) => ShInner::SortedMulti(smv.translate_pk(&mut fpk)?),
-            ShInner::Ms(ref ms) => ShInner::Ms(ms.translate_pk(&mut fpk, &mut fpkh)?),
+            ShInner::Wsh(ref wsh) => ShInner::Wsh(wsh.translate_pk(t)?),
+            ShInner::Wpkh(ref wpkh) => ShInner::Wpkh(wpkh.translate_pk(t)?),
+            ShInner::SortedMulti(ref smv) => ShInner::SortedMulti(smv.translate_pk(t)?),
+            ShInner::Ms(ref ms) => ShInner::Ms(ms.translate_pk(t)?),
         };
         Ok(Sh { inner })
     }
@@ -22,13 +22,13 @@ use core::str::FromStr;
 
 use bitcoin::blockdata::script;
 
-use crate::miniscript::context::ScriptContext;
+use crate::miniscript::context::{self, ScriptContext};
 use crate::miniscript::decode::Terminal;
-use crate::miniscript::limits::MAX_PUBKEYS_PER_MULTISIG;
+use crate::miniscript::limits::{RuleSet, MAX_PUBKEYS_PER_MULTISIG};
 use crate::prelude::*;
 use crate::{
     errstr, expression, miniscript, policy, script_num_size, Error, ForEach, ForEachKey,
-    Miniscript, MiniscriptKey, Satisfier, ToPublicKey,
+    Miniscript, MiniscriptKey, Satisfier, ToPublicKey, Translator,
 };
 
 /// Contents of a "sortedmulti" descriptor
@@ -88,21 +88,21 @@ impl<Pk: MiniscriptKey, Ctx: ScriptContext> SortedMultiVec<Pk, Ctx> {
             .map(|sub| expression::terminal(sub, Pk::from_str))
             .collect();
 
-        pks.map(|pks| SortedMultiVec::new(k as usize, pks))?
+        SortedMultiVec::new(k as usize, pks?).map_err(|e| context::mark_key::<Pk>(e, tree))
     }
 
-    /// This will panic if fpk returns an uncompressed key when
+    /// This will panic if the translator returns an uncompressed key when
     /// converting to a Segwit descriptor. To prevent this panic, ensure
-    /// fpk returns an error in this case instead.
-    pub fn translate_pk<FPk, Q, FuncError>(
+    /// it returns an error in this case instead.
+    pub fn translate_pk<T, Q, FuncError>(
         &self,
-        fpk: &mut FPk,
+        t: &mut T,
     ) -> Result<SortedMultiVec<Q, Ctx>, FuncError>
     where
-        FPk: FnMut(&Pk) -> Result<Q, FuncError>,
+        T: Translator<Pk, Q, FuncError> + ?Sized,
         Q: MiniscriptKey,
     {
-        let pks: Result<Vec<Q>, _> = self.pks.iter().map(&mut *fpk).collect();
+        let pks: Result<Vec<Q>, _> = self.pks.iter().map(|pk| t.pk(pk)).collect();
         Ok(SortedMultiVec {
             k: self.k,
             pks: pks?,
@@ -124,11 +124,17 @@ impl<Pk: MiniscriptKey, Ctx: ScriptContext> ForEachKey<Pk> for SortedMultiVec<Pk
 impl<Pk: MiniscriptKey, Ctx: ScriptContext> SortedMultiVec<Pk, Ctx> {
     /// utility function to sanity a sorted multi vec
     pub fn sanity_check(&self) -> Result<(), Error> {
+        self.sanity_check_with(&RuleSet::Standard)
+    }
+
+    /// utility function to sanity a sorted multi vec, checking its resource
+    /// usage against `rules`
+    pub fn sanity_check_with(&self, rules: &RuleSet) -> Result<(), Error> {
         let ms: Miniscript<Pk, Ctx> =
             Miniscript::from_ast(Terminal::Multi(self.k, self.pks.clone()))
                 .expect("Must typecheck");
         // '?' for doing From conversion
-        ms.sanity_check()?;
+        ms.sanity_check_with(rules)?;
         Ok(())
     }
 }
@@ -164,12 +170,27 @@ impl<Pk: MiniscriptKey, Ctx: ScriptContext> SortedMultiVec<Pk, Ctx> {
     /// Attempt to produce a satisfying witness for the
     /// witness script represented by the parse tree
     pub fn satisfy<S>(&self, satisfier: S) -> Result<Vec<Vec<u8>>, Error>
+    where
+        Pk: ToPublicKey,
+        S: Satisfier<Pk>,
+    {
+        self.satisfy_with_rules(satisfier, &RuleSet::Standard)
+    }
+
+    /// Attempt to produce a satisfying witness for the witness script
+    /// represented by the parse tree, checking it against the limits of the
+    /// script context under `rules`
+    pub fn satisfy_with_rules<S>(
+        &self,
+        satisfier: S,
+        rules: &RuleSet,
+    ) -> Result<Vec<Vec<u8>>, Error>
     where
         Pk: ToPublicKey,
         S: Satisfier<Pk>,
     {
         let ms = Miniscript::from_ast(self.sorted_node()).expect("Multi node typecheck");
-        ms.satisfy(satisfier)
+        ms.satisfy_with_rules(satisfier, rules)
     }
 
     /// Size, in bytes of the script-pubkey. If this Miniscript is used outside
@@ -0,0 +1,223 @@
+// Miniscript
+// Written in 2022 by rust-miniscript developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Spend paths
+//!
+//! Uniform iteration over the scripts a descriptor can be spent with,
+//! whether it is a taproot descriptor or not
+//!
+
+use super::tr::TapTreeIter;
+use super::{Descriptor, ShInner, WshInner};
+use crate::miniscript::analyzable::PathTimelocks;
+use crate::miniscript::iter::PkPkh;
+use crate::prelude::*;
+use crate::{BareCtx, Legacy, Miniscript, MiniscriptKey, ScriptContext, Segwitv0, Tap};
+
+/// The script of a [`SpendPath`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SpendPathScript<'a, Pk: MiniscriptKey> {
+    /// A single key: the key of `pkh` and `wpkh`, or the key spend of `tr`
+    /// and `rawtr`
+    Key(&'a Pk),
+    /// A `sortedmulti` with its threshold and keys
+    SortedMulti(usize, &'a [Pk]),
+    /// The miniscript of a bare descriptor
+    Bare(&'a Miniscript<Pk, BareCtx>),
+    /// The miniscript of a `sh` descriptor
+    Legacy(&'a Miniscript<Pk, Legacy>),
+    /// The miniscript of a `wsh` or `sh(wsh)` descriptor
+    Segwitv0(&'a Miniscript<Pk, Segwitv0>),
+    /// The miniscript of a taproot leaf
+    Tap(&'a Miniscript<Pk, Tap>),
+}
+
+/// One of the scripts a descriptor can be spent with, as returned by
+/// [`Descriptor::iter_spend_paths`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpendPath<'a, Pk: MiniscriptKey> {
+    /// The position of the taproot leaf among the leaves yielded by
+    /// [`Tr::iter_scripts`](super::Tr::iter_scripts), or `None` when the
+    /// path is not a taproot script path
+    pub leaf_index: Option<usize>,
+    /// The script spent by the path
+    pub script: SpendPathScript<'a, Pk>,
+    /// The keys and key hashes appearing in the script
+    pub keys: Vec<PkPkh<Pk>>,
+    /// The timelocks required by each way of satisfying the script
+    pub timelocks: Vec<PathTimelocks>,
+}
+
+impl<'a, Pk: MiniscriptKey> SpendPath<'a, Pk> {
+    fn key(pk: &'a Pk) -> Self {
+        SpendPath {
+            leaf_index: None,
+            script: SpendPathScript::Key(pk),
+            keys: vec![PkPkh::PlainPubkey(pk.clone())],
+            timelocks: vec![PathTimelocks::default()],
+        }
+    }
+
+    fn sorted_multi(k: usize, pks: &'a [Pk]) -> Self {
+        SpendPath {
+            leaf_index: None,
+            script: SpendPathScript::SortedMulti(k, pks),
+            keys: pks.iter().cloned().map(PkPkh::PlainPubkey).collect(),
+            timelocks: vec![PathTimelocks::default()],
+        }
+    }
+
+    fn miniscript<Ctx: ScriptContext>(
+        leaf_index: Option<usize>,
+        script: SpendPathScript<'a, Pk>,
+        ms: &'a Miniscript<Pk, Ctx>,
+    ) -> Self {
+        SpendPath {
+            leaf_index,
+            script,
+            keys: ms.iter_pk_pkh().collect(),
+            timelocks: ms.timelock_info(),
+        }
+    }
+}
+
+/// Iterator over the spend paths of a descriptor, as returned by
+/// [`Descriptor::iter_spend_paths`]
+pub struct SpendPaths<'a, Pk: MiniscriptKey> {
+    /// The path which is not a taproot script path, if not yet yielded
+    first: Option<SpendPath<'a, Pk>>,
+    /// The leaves of a taproot tree
+    leaves: Option<TapTreeIter<'a, Pk>>,
+    next_leaf_index: usize,
+}
+
+impl<'a, Pk: MiniscriptKey> Iterator for SpendPaths<'a, Pk> {
+    type Item = SpendPath<'a, Pk>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(first) = self.first.take() {
+            return Some(first);
+        }
+        let (_depth, ms) = self.leaves.as_mut()?.next()?;
+        let leaf_index = self.next_leaf_index;
+        self.next_leaf_index += 1;
+        Some(SpendPath::miniscript(
+            Some(leaf_index),
+            SpendPathScript::Tap(ms),
+            ms,
+        ))
+    }
+}
+
+impl<Pk: MiniscriptKey> Descriptor<Pk> {
+    /// Iterates over every script the descriptor can be spent with.
+    ///
+    /// Raw and addr descriptors have no known spend path and other non-taproot
+    /// descriptors have a single one. Taproot descriptors yield their key
+    /// spend first, followed by one path per leaf of their tree in the order
+    /// of [`Tr::iter_scripts`](super::Tr::iter_scripts);
+    /// hidden branches are skipped. Keys are not derived, so the paths of a
+    /// descriptor with wildcards describe the script at every index.
+    pub fn iter_spend_paths(&self) -> SpendPaths<'_, Pk> {
+        let (first, leaves) = match *self {
+            Descriptor::Bare(ref bare) => {
+                let ms = bare.as_inner();
+                (
+                    SpendPath::miniscript(None, SpendPathScript::Bare(ms), ms),
+                    None,
+                )
+            }
+            Descriptor::Pkh(ref pkh) => (SpendPath::key(pkh.as_inner()), None),
+            Descriptor::Wpkh(ref wpkh) => (SpendPath::key(wpkh.as_inner()), None),
+            Descriptor::Sh(ref sh) => {
+                let path = match *sh.as_inner() {
+                    ShInner::Wsh(ref wsh) => wsh_spend_path(wsh.as_inner()),
+                    ShInner::Wpkh(ref wpkh) => SpendPath::key(wpkh.as_inner()),
+                    ShInner::SortedMulti(ref smv) => SpendPath::sorted_multi(smv.k, &smv.pks),
+                    ShInner::Ms(ref ms) => {
+                        SpendPath::miniscript(None, SpendPathScript::Legacy(ms), ms)
+                    }
+                };
+                (path, None)
+            }
+            Descriptor::Wsh(ref wsh) => (wsh_spend_path(wsh.as_inner()), None),
+            Descriptor::Tr(ref tr) => (SpendPath::key(tr.internal_key()), Some(tr.iter_scripts())),
+            Descriptor::RawTr(ref rawtr) => (SpendPath::key(rawtr.as_inner()), None),
+            Descriptor::Raw(_) | Descriptor::Addr(_) | Descriptor::Wv(_) => {
+                return SpendPaths {
+                    first: None,
+                    leaves: None,
+                    next_leaf_index: 0,
+                }
+            }
+        };
+        SpendPaths {
+            first: Some(first),
+            leaves,
+            next_leaf_index: 0,
+        }
+    }
+}
+
+fn wsh_spend_path<Pk: MiniscriptKey>(inner: &WshInner<Pk>) -> SpendPath<'_, Pk> {
+    match *inner {
+        WshInner::SortedMulti(ref smv) => SpendPath::sorted_multi(smv.k, &smv.pks),
+        WshInner::Ms(ref ms) => SpendPath::miniscript(None, SpendPathScript::Segwitv0(ms), ms),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::str::FromStr;
+
+    use super::*;
+
+    #[test]
+    fn iter_spend_paths() {
+        let desc =
+            Descriptor::<String>::from_str("wsh(or_d(pk(A),and_v(v:pk(B),older(144))))").unwrap();
+        let paths: Vec<_> = desc.iter_spend_paths().collect();
+        assert_eq!(paths.len(), 1);
+        assert_eq!(paths[0].leaf_index, None);
+        assert_eq!(
+            paths[0].keys,
+            vec![
+                PkPkh::PlainPubkey("A".to_owned()),
+                PkPkh::PlainPubkey("B".to_owned())
+            ]
+        );
+        assert_eq!(paths[0].timelocks.len(), 2);
+        assert_eq!(paths[0].timelocks[1].older_height, Some(144));
+
+        let desc = Descriptor::<String>::from_str("sh(wsh(sortedmulti(1,A,B)))").unwrap();
+        let paths: Vec<_> = desc.iter_spend_paths().collect();
+        assert_eq!(paths.len(), 1);
+        match paths[0].script {
+            SpendPathScript::SortedMulti(1, pks) => assert_eq!(pks, ["A", "B"]),
+            ref script => panic!("unexpected script {:?}", script),
+        }
+        assert_eq!(paths[0].timelocks, vec![PathTimelocks::default()]);
+
+        let desc =
+            Descriptor::<String>::from_str("tr(A,{pk(B),and_v(v:pk(C),after(500000))})").unwrap();
+        let paths: Vec<_> = desc.iter_spend_paths().collect();
+        assert_eq!(paths.len(), 3);
+        assert_eq!(paths[0].script, SpendPathScript::Key(&"A".to_owned()));
+        assert_eq!(paths[0].leaf_index, None);
+        assert_eq!(paths[1].leaf_index, Some(0));
+        assert_eq!(paths[1].keys, vec![PkPkh::PlainPubkey("B".to_owned())]);
+        assert_eq!(paths[2].leaf_index, Some(1));
+        assert_eq!(paths[2].keys, vec![PkPkh::PlainPubkey("C".to_owned())]);
+        assert_eq!(paths[2].timelocks[0].after_height, Some(500000));
+    }
+}
@@ -0,0 +1,190 @@
+// Miniscript
+// Written in 2022 by rust-miniscript developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Descriptor Templates
+//!
+//! Constructors for the standard single-signature descriptors of BIP 44,
+//! BIP 49, BIP 84 and BIP 86, from an account-level extended public key.
+//!
+
+use bitcoin::util::bip32;
+use bitcoin::Network;
+
+use super::{Descriptor, DescriptorPublicKey, DescriptorXKey, Wildcard};
+use crate::prelude::*;
+use crate::Error;
+
+/// The keychain of a standard descriptor, i.e. the `change` level of its
+/// derivation path
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Keychain {
+    /// Receiving addresses, derived at `/0/*`
+    External,
+    /// Change addresses, derived at `/1/*`
+    Internal,
+}
+
+impl Keychain {
+    fn child_number(self) -> bip32::ChildNumber {
+        let index = match self {
+            Keychain::External => 0,
+            Keychain::Internal => 1,
+        };
+        bip32::ChildNumber::from_normal_idx(index).expect("0 and 1 are normal indexes")
+    }
+}
+
+/// The key `[fingerprint/purpose'/coin_type'/account']xpub/keychain/*` of a
+/// standard descriptor, where `xpub` is the account-level extended key. The
+/// coin type is 0 for mainnet keys and 1 for any other network.
+fn standard_key(
+    purpose: u32,
+    xpub: bip32::ExtendedPubKey,
+    fingerprint: bip32::Fingerprint,
+    account: u32,
+    keychain: Keychain,
+) -> Result<DescriptorPublicKey, Error> {
+    let coin_type = match xpub.network {
+        Network::Bitcoin => 0,
+        _ => 1,
+    };
+    let origin_path = [purpose, coin_type, account]
+        .iter()
+        .map(|&index| bip32::ChildNumber::from_hardened_idx(index))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(DescriptorPublicKey::XPub(DescriptorXKey {
+        origin: Some((fingerprint, origin_path.into())),
+        xkey: xpub,
+        derivation_path: vec![keychain.child_number()].into(),
+        wildcard: Wildcard::Unhardened,
+    }))
+}
+
+impl Descriptor<DescriptorPublicKey> {
+    /// Create the BIP 44 descriptor `pkh(key)` of an account, where `key` is
+    /// `[fingerprint/44'/coin_type'/account']xpub/keychain/*`.
+    ///
+    /// `xpub` is the extended public key of the account and `fingerprint`
+    /// the fingerprint of the master key it was derived from. The coin type
+    /// is 0 when `xpub` is a mainnet key and 1 otherwise.
+    ///
+    /// Errors with [`Error::Bip32`] if given an account ≥ 2^31
+    pub fn new_bip44(
+        xpub: bip32::ExtendedPubKey,
+        fingerprint: bip32::Fingerprint,
+        account: u32,
+        keychain: Keychain,
+    ) -> Result<Self, Error> {
+        Ok(Descriptor::new_pkh(standard_key(
+            44,
+            xpub,
+            fingerprint,
+            account,
+            keychain,
+        )?))
+    }
+
+    /// Create the BIP 49 descriptor `sh(wpkh(key))` of an account, with `key`
+    /// built as in [`Descriptor::new_bip44`] below the purpose `49'`.
+    ///
+    /// Errors with [`Error::Bip32`] if given an account ≥ 2^31
+    pub fn new_bip49(
+        xpub: bip32::ExtendedPubKey,
+        fingerprint: bip32::Fingerprint,
+        account: u32,
+        keychain: Keychain,
+    ) -> Result<Self, Error> {
+        Descriptor::new_sh_wpkh(standard_key(49, xpub, fingerprint, account, keychain)?)
+    }
+
+    /// Create the BIP 84 descriptor `wpkh(key)` of an account, with `key`
+    /// built as in [`Descriptor::new_bip44`] below the purpose `84'`.
+    ///
+    /// Errors with [`Error::Bip32`] if given an account ≥ 2^31
+    pub fn new_bip84(
+        xpub: bip32::ExtendedPubKey,
+        fingerprint: bip32::Fingerprint,
+        account: u32,
+        keychain: Keychain,
+    ) -> Result<Self, Error> {
+        Descriptor::new_wpkh(standard_key(84, xpub, fingerprint, account, keychain)?)
+    }
+
+    /// Create the BIP 86 descriptor `tr(key)` of an account, with `key` built
+    /// as in [`Descriptor::new_bip44`] below the purpose `86'`.
+    ///
+    /// Errors with [`Error::Bip32`] if given an account ≥ 2^31
+    pub fn new_bip86(
+        xpub: bip32::ExtendedPubKey,
+        fingerprint: bip32::Fingerprint,
+        account: u32,
+        keychain: Keychain,
+    ) -> Result<Self, Error> {
+        Descriptor::new_tr(
+            standard_key(86, xpub, fingerprint, account, keychain)?,
+            None,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::str::FromStr;
+
+    use super::*;
+
+    #[test]
+    fn standard_descriptors() {
+        let xpub = bip32::ExtendedPubKey::from_str("xpub6ERApfZwUNrhLCkDtcHTcxd75RbzS1ed54G1LkBUHQVHQKqhMkhgbmJbZRkrgZw4koxb5JaHWkY4ALHY2grBGRjaDMzQLcgJvLJuZZvRcEL").unwrap();
+        let fingerprint = bip32::Fingerprint::from_str("78412e3a").unwrap();
+        let key = "[78412e3a/{}'/0'/0']xpub6ERApfZwUNrhLCkDtcHTcxd75RbzS1ed54G1LkBUHQVHQKqhMkhgbmJbZRkrgZw4koxb5JaHWkY4ALHY2grBGRjaDMzQLcgJvLJuZZvRcEL/{}/*";
+        let key = |purpose: &str, keychain: &str| {
+            key.replacen("{}", purpose, 1).replacen("{}", keychain, 1)
+        };
+
+        let desc = Descriptor::new_bip44(xpub, fingerprint, 0, Keychain::External).unwrap();
+        assert_eq!(
+            desc.to_string_no_checksum(),
+            format!("pkh({})", key("44", "0"))
+        );
+        let desc = Descriptor::new_bip49(xpub, fingerprint, 0, Keychain::Internal).unwrap();
+        assert_eq!(
+            desc.to_string_no_checksum(),
+            format!("sh(wpkh({}))", key("49", "1"))
+        );
+        let desc = Descriptor::new_bip84(xpub, fingerprint, 0, Keychain::External).unwrap();
+        assert_eq!(
+            desc.to_string_no_checksum(),
+            format!("wpkh({})", key("84", "0"))
+        );
+        let desc = Descriptor::new_bip86(xpub, fingerprint, 0, Keychain::Internal).unwrap();
+        assert_eq!(
+            desc.to_string_no_checksum(),
+            format!("tr({})", key("86", "1"))
+        );
+        assert_eq!(
+            desc,
+            Descriptor::<DescriptorPublicKey>::from_str(&format!("tr({})", key("86", "1")))
+                .unwrap()
+        );
+
+        let tpub = bip32::ExtendedPubKey::from_str("tpubD6NzVbkrYhZ4YqYr3amYH15zjxHvBkUUeadieW8AxTZC7aY2L8aPSk3tpW6yW1QnWzXAB7zoiaNMfwXPPz9S68ZCV4yWvkVXjdeksLskCed").unwrap();
+        let desc = Descriptor::new_bip84(tpub, fingerprint, 1, Keychain::External).unwrap();
+        assert_eq!(desc.to_string_no_checksum(), "wpkh([78412e3a/84'/1'/1']tpubD6NzVbkrYhZ4YqYr3amYH15zjxHvBkUUeadieW8AxTZC7aY2L8aPSk3tpW6yW1QnWzXAB7zoiaNMfwXPPz9S68ZCV4yWvkVXjdeksLskCed/0/*)");
+
+        match Descriptor::new_bip44(xpub, fingerprint, 1 << 31, Keychain::External) {
+            Err(Error::Bip32(bip32::Error::InvalidChildNumber(n))) => assert_eq!(n, 1 << 31),
+            res => panic!("unexpected result {:?}", res),
+        }
+    }
+}
@@ -1,39 +1,57 @@
 // Tapscript
-use core::cmp::{self, max};
+use core::cmp::{self, max, Reverse};
 use core::str::FromStr;
-use core::{fmt, hash};
+use core::{fmt, hash, iter};
 
 use bitcoin::blockdata::opcodes;
+use bitcoin::hashes::hex::FromHex;
+use bitcoin::hashes::{sha256, Hash, HashEngine};
+use bitcoin::util::psbt;
+use bitcoin::util::schnorr::TweakedPublicKey;
 use bitcoin::util::taproot::{
-    LeafVersion, TaprootBuilder, TaprootBuilderError, TaprootSpendInfo, TAPROOT_CONTROL_BASE_SIZE,
-    TAPROOT_CONTROL_MAX_NODE_COUNT, TAPROOT_CONTROL_NODE_SIZE,
+    LeafVersion, TapBranchHash, TapLeafHash, TaprootBuilder, TaprootBuilderError, TaprootSpendInfo,
+    TAPROOT_CONTROL_BASE_SIZE, TAPROOT_CONTROL_MAX_NODE_COUNT, TAPROOT_CONTROL_NODE_SIZE,
 };
-use bitcoin::{secp256k1, Address, Network, Script};
+use bitcoin::{secp256k1, Address, Network, Script, XOnlyPublicKey};
 use sync::Arc;
 
 use super::checksum::{desc_checksum, verify_checksum};
-use crate::expression::{self, FromTree};
-use crate::miniscript::Miniscript;
+use super::{
+    ConversionError, DescriptorPublicKey, PublicKeyDerivator, SatisfactionWeight, SinglePub,
+    SinglePubKey,
+};
+use crate::expression::{self, FromTree, LimitCounter, ParseOptions};
+use crate::miniscript::iter::PkPkh;
+use crate::miniscript::limits::RuleSet;
+use crate::miniscript::{context, Miniscript};
 use crate::policy::semantic::Policy;
-use crate::policy::Liftable;
+use crate::policy::{LiftError, Liftable};
 use crate::prelude::*;
 use crate::util::{varint_len, witness_size};
 use crate::{
     errstr, Error, ForEach, ForEachKey, MiniscriptKey, Satisfier, Tap, ToPublicKey, TranslatePk,
+    Translator,
 };
 
 /// A Taproot Tree representation.
-// Hidden leaves are not yet supported in descriptor spec. Conceptually, it should
-// be simple to integrate those here, but it is best to wait on core for the exact syntax.
 #[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
 pub enum TapTree<Pk: MiniscriptKey> {
     /// A taproot tree structure
     Tree(Arc<TapTree<Pk>>, Arc<TapTree<Pk>>),
     /// A taproot leaf denoting a spending condition
-    // A new leaf version would require a new Context, therefore there is no point
-    // in adding a LeafVersion with Leaf type here. All Miniscripts right now
-    // are of Leafversion::default
     Leaf(Arc<Miniscript<Pk, Tap>>),
+    /// A leaf with a version other than [`LeafVersion::TapScript`], written
+    /// as `leaf_ver(VERSION,MS)` with the version as two hex digits.
+    ///
+    /// The script of the leaf is the encoding of the Miniscript, but it is not
+    /// interpreted as tapscript by consensus, so these leaves are never
+    /// satisfied, lifted or yielded by [`TapTree::iter`].
+    VersionedLeaf(LeafVersion, Arc<Miniscript<Pk, Tap>>),
+    /// A branch known only by its node hash, written as `raw_node(HASH)`.
+    ///
+    /// The outputs of trees with hidden branches can only be spent using the
+    /// key spend path or the known leaves.
+    Hidden(TapBranchHash),
 }
 
 /// A taproot descriptor
@@ -50,6 +68,11 @@ pub struct Tr<Pk: MiniscriptKey> {
     // to the contents of the `Option` from inside a `MutexGuard`. There is no outer
     // `Arc` because when this structure is cloned, we create a whole new mutex.
     spend_info: Mutex<Option<Arc<TaprootSpendInfo>>>,
+    /// Merkle root of a tree without wildcard keys, cached when deriving
+    /// script pubkeys with [`Descriptor::script_pubkey_at_index`].
+    ///
+    /// [`Descriptor::script_pubkey_at_index`]: crate::Descriptor::script_pubkey_at_index
+    tree_hash: Mutex<Option<TapBranchHash>>,
 }
 
 impl<Pk: MiniscriptKey> Clone for Tr<Pk> {
@@ -67,6 +90,7 @@ impl<Pk: MiniscriptKey> Clone for Tr<Pk> {
                     .as_ref()
                     .map(Arc::clone),
             ),
+            tree_hash: Mutex::new(*self.tree_hash.lock().expect("Lock poisoned")),
         }
     }
 }
@@ -115,37 +139,340 @@ impl<Pk: MiniscriptKey> TapTree<Pk> {
             TapTree::Tree(ref left_tree, ref right_tree) => {
                 1 + max(left_tree.taptree_height(), right_tree.taptree_height())
             }
-            TapTree::Leaf(..) => 1,
+            TapTree::Leaf(..) | TapTree::VersionedLeaf(..) | TapTree::Hidden(..) => 1,
+        }
+    }
+
+    /// Create a tree with `left` and `right` as the children of its root.
+    ///
+    /// # Errors
+    /// When the resulting tree would be deeper than the 128 levels allowed
+    /// by consensus.
+    pub fn combine(left: TapTree<Pk>, right: TapTree<Pk>) -> Result<Self, Error> {
+        let tree = TapTree::Tree(Arc::new(left), Arc::new(right));
+        if tree.taptree_height() <= TAPROOT_CONTROL_MAX_NODE_COUNT {
+            Ok(tree)
+        } else {
+            Err(Error::MaxRecursiveDepthExceeded)
+        }
+    }
+
+    /// Create a tree from its leaves and their depths, given in depth-first
+    /// order, as yielded by [`TapTree::iter`].
+    ///
+    /// The root is at depth 0, so a tree with a single leaf is built from
+    /// `[(0, ms)]` and a tree with two leaves from `[(1, left), (1, right)]`.
+    ///
+    /// # Errors
+    /// When the depths are larger than allowed by consensus, or do not
+    /// describe a complete binary tree.
+    pub fn from_depths<I>(leaves: I) -> Result<Self, Error>
+    where
+        I: IntoIterator<Item = (usize, Miniscript<Pk, Tap>)>,
+    {
+        Self::from_depths_helper(
+            leaves
+                .into_iter()
+                .map(|(depth, ms)| (depth, TapTree::Leaf(Arc::new(ms)))),
+        )
+    }
+
+    // Create a tree from leaves of any kind and their depths
+    fn from_depths_helper<I>(leaves: I) -> Result<Self, Error>
+    where
+        I: IntoIterator<Item = (usize, TapTree<Pk>)>,
+    {
+        // Subtrees which are still missing their sibling, along with the
+        // depth of their root. Depths are strictly increasing in the stack.
+        let mut stack: Vec<(usize, TapTree<Pk>)> = vec![];
+        for (depth, leaf) in leaves {
+            if depth >= TAPROOT_CONTROL_MAX_NODE_COUNT {
+                return Err(Error::MaxRecursiveDepthExceeded);
+            }
+            // A subtree deeper than the leaf can no longer get its sibling,
+            // and a subtree at depth 0 is already the complete tree
+            if stack
+                .last()
+                .map(|&(d, _)| d > depth || d == 0)
+                .unwrap_or(false)
+            {
+                return Err(Error::BadDescriptor(format!(
+                    "taproot leaf at depth {} is not in depth-first order",
+                    depth
+                )));
+            }
+            let mut node = (depth, leaf);
+            while stack.last().map(|&(d, _)| d) == Some(node.0) {
+                let (depth, left) = stack.pop().expect("just checked it is not empty");
+                node = (depth - 1, TapTree::Tree(Arc::new(left), Arc::new(node.1)));
+            }
+            stack.push(node);
+        }
+        match stack.pop() {
+            Some((0, tree)) if stack.is_empty() => Ok(tree),
+            Some(_) => Err(Error::BadDescriptor(
+                "taproot leaf depths do not describe a complete tree".to_owned(),
+            )),
+            None => Err(errstr("empty taproot tree")),
         }
     }
 
-    /// Iterate over all miniscripts
+    /// Create a tree from its leaves and their weights using Huffman coding,
+    /// so that leaves with higher weights are closer to the root and are
+    /// cheaper to spend.
+    ///
+    /// # Errors
+    /// When `leaves` is empty, or when the weights are so unbalanced that the
+    /// tree would be deeper than allowed by consensus.
+    pub fn with_huffman<I>(leaves: I) -> Result<Self, Error>
+    where
+        I: IntoIterator<Item = (usize, Miniscript<Pk, Tap>)>,
+    {
+        let mut node_weights = BinaryHeap::<(Reverse<usize>, TapTree<Pk>)>::new();
+        for (weight, ms) in leaves {
+            node_weights.push((Reverse(weight), TapTree::Leaf(Arc::new(ms))));
+        }
+        if node_weights.is_empty() {
+            return Err(errstr("empty taproot tree"));
+        }
+        while node_weights.len() > 1 {
+            let (w1, t1) = node_weights.pop().expect("len must atleast be two");
+            let (w2, t2) = node_weights.pop().expect("len must atleast be two");
+            let weight = w1.0.saturating_add(w2.0);
+            node_weights.push((Reverse(weight), TapTree::combine(t1, t2)?));
+        }
+        Ok(node_weights.pop().expect("checked non empty").1)
+    }
+
+    /// Create a leaf with the given version, which is a [`TapTree::Leaf`] for
+    /// [`LeafVersion::TapScript`] and a [`TapTree::VersionedLeaf`] otherwise
+    pub fn leaf_with_version(ver: LeafVersion, ms: Arc<Miniscript<Pk, Tap>>) -> Self {
+        match ver {
+            LeafVersion::TapScript => TapTree::Leaf(ms),
+            LeafVersion::Future(..) => TapTree::VersionedLeaf(ver, ms),
+        }
+    }
+
+    /// Iterate over all tapscript miniscripts, skipping hidden branches and
+    /// leaves of other versions
     pub fn iter(&self) -> TapTreeIter<Pk> {
         TapTreeIter {
+            leaves: self.iter_leaves(),
+        }
+    }
+
+    /// Iterate over the miniscripts of all leaves with their version,
+    /// skipping hidden branches
+    pub fn iter_leaves(&self) -> TapLeafIter<'_, Pk> {
+        TapLeafIter {
             stack: vec![(0, self)],
         }
     }
 
     // Helper function to translate keys
-    fn translate_helper<FPk, FPkh, Q, Error>(
-        &self,
-        fpk: &mut FPk,
-        fpkh: &mut FPkh,
-    ) -> Result<TapTree<Q>, Error>
+    fn translate_helper<T, Q, Error>(&self, t: &mut T) -> Result<TapTree<Q>, Error>
     where
-        FPk: FnMut(&Pk) -> Result<Q, Error>,
-        FPkh: FnMut(&Pk::Hash) -> Result<Q::Hash, Error>,
+        T: Translator<Pk, Q, Error> + ?Sized,
         Q: MiniscriptKey,
     {
         let frag = match self {
             TapTree::Tree(l, r) => TapTree::Tree(
-                Arc::new(l.translate_helper(fpk, fpkh)?),
-                Arc::new(r.translate_helper(fpk, fpkh)?),
+                Arc::new(l.translate_helper(t)?),
+                Arc::new(r.translate_helper(t)?),
             ),
-            TapTree::Leaf(ms) => TapTree::Leaf(Arc::new(ms.translate_pk(fpk, fpkh)?)),
+            TapTree::Leaf(ms) => TapTree::Leaf(Arc::new(ms.translate_pk(t)?)),
+            TapTree::VersionedLeaf(ver, ms) => {
+                TapTree::VersionedLeaf(*ver, Arc::new(ms.translate_pk(t)?))
+            }
+            TapTree::Hidden(hash) => TapTree::Hidden(*hash),
         };
         Ok(frag)
     }
+
+    /// The tree in the format of the `tap_tree` field of psbt outputs, with
+    /// the depth and leaf version of every leaf script.
+    ///
+    /// # Errors
+    /// When the tree has hidden branches, which the psbt field cannot hold.
+    pub fn to_psbt_tap_tree(&self) -> Result<psbt::TapTree, Error>
+    where
+        Pk: ToPublicKey,
+    {
+        psbt::TapTree::from_builder(self.taproot_builder()).map_err(|e| match e {
+            psbt::IncompleteTapTree::HiddenParts(_) => {
+                errstr("taproot trees with hidden branches cannot be written to a psbt")
+            }
+            psbt::IncompleteTapTree::NotFinalized(_) => {
+                unreachable!("every leaf of a complete tree is added")
+            }
+        })
+    }
+
+    // Helper function to add the leaves of the tree to a builder, depth first
+    fn taproot_builder(&self) -> TaprootBuilder
+    where
+        Pk: ToPublicKey,
+    {
+        let mut builder = TaprootBuilder::new();
+        // Depth first walk over all leaves, including the hidden ones
+        let mut stack = vec![(0, self)];
+        while let Some((depth, node)) = stack.pop() {
+            builder = match *node {
+                TapTree::Tree(ref l, ref r) => {
+                    stack.push((depth + 1, r));
+                    stack.push((depth + 1, l));
+                    continue;
+                }
+                TapTree::Leaf(ref ms) => builder.add_leaf(depth, ms.encode()),
+                TapTree::VersionedLeaf(ver, ref ms) => {
+                    builder.add_leaf_with_ver(depth, ms.encode(), ver)
+                }
+                TapTree::Hidden(hash) => {
+                    builder.add_hidden_node(depth, sha256::Hash::from_inner(hash.into_inner()))
+                }
+            }
+            .expect("Computing spend data on a valid Tree should always succeed");
+        }
+        builder
+    }
+
+    /// The unspendable internal key of the given kind for this tree
+    pub fn unspendable_key<C: secp256k1::Verification>(
+        &self,
+        secp: &secp256k1::Secp256k1<C>,
+        kind: UnspendableKey,
+    ) -> XOnlyPublicKey
+    where
+        Pk: ToPublicKey,
+    {
+        kind.key(secp, self.node_hash())
+    }
+
+    // Helper function to compute the hash of the root node
+    fn node_hash(&self) -> TapBranchHash
+    where
+        Pk: ToPublicKey,
+    {
+        let leaf = |hash: TapLeafHash| TapBranchHash::from_inner(hash.into_inner());
+        match *self {
+            TapTree::Tree(ref l, ref r) => TapBranchHash::from_node_hashes(
+                sha256::Hash::from_inner(l.node_hash().into_inner()),
+                sha256::Hash::from_inner(r.node_hash().into_inner()),
+            ),
+            TapTree::Leaf(ref ms) => leaf(TapLeafHash::from_script(
+                &ms.encode(),
+                LeafVersion::TapScript,
+            )),
+            TapTree::VersionedLeaf(ver, ref ms) => {
+                leaf(TapLeafHash::from_script(&ms.encode(), ver))
+            }
+            TapTree::Hidden(hash) => hash,
+        }
+    }
+}
+
+impl TapTree<XOnlyPublicKey> {
+    /// Create a tree from the `tap_tree` field of a psbt output, such as one
+    /// written by [`TapTree::to_psbt_tap_tree`] or by another wallet.
+    ///
+    /// The leaves are in the order of [`psbt::TapTree::script_leaves`], in
+    /// which the field is serialized. Writers may give the children of a
+    /// branch in either order, so the tree may be a mirror image of the one
+    /// the field was written from, with the same merkle root.
+    ///
+    /// # Errors
+    /// When a leaf script is not a sane miniscript.
+    pub fn from_psbt_tap_tree(tree: &psbt::TapTree) -> Result<Self, Error> {
+        let leaves = tree
+            .script_leaves()
+            .map(|leaf| {
+                let ms = Miniscript::<XOnlyPublicKey, Tap>::parse(leaf.script())?;
+                let leaf_tree = TapTree::leaf_with_version(leaf.leaf_version(), Arc::new(ms));
+                Ok((usize::from(leaf.depth()), leaf_tree))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        Self::from_depths_helper(leaves)
+    }
+}
+
+impl TapTree<DescriptorPublicKey> {
+    /// A `tr` descriptor of this tree with an unspendable internal key of the
+    /// given kind, so that it can only be spent using the tree. The key can
+    /// be checked with [`Tr::internal_key_is_unspendable`] once derived.
+    ///
+    /// # Errors
+    /// With [`UnspendableKey::Derived`], [`Error::Conversion`] when the tree
+    /// has wildcards: its merkle root, and so the key, would be different at
+    /// every index.
+    pub fn with_unspendable_key<C: secp256k1::Verification>(
+        self,
+        secp: &secp256k1::Secp256k1<C>,
+        kind: UnspendableKey,
+    ) -> Result<Tr<DescriptorPublicKey>, Error> {
+        let key = match kind {
+            UnspendableKey::Nums => nums_point(),
+            UnspendableKey::Derived => self
+                .translate_helper(&mut PublicKeyDerivator { secp, index: None })
+                .map_err(Error::Conversion)?
+                .unspendable_key(secp, kind),
+        };
+        let internal_key = DescriptorPublicKey::Single(SinglePub {
+            origin: None,
+            key: SinglePubKey::XOnly(key),
+        });
+        Tr::new(internal_key, Some(self))
+    }
+}
+
+/// A provably unspendable internal key, for `tr` descriptors which can only be
+/// spent using their script tree
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Hash)]
+pub enum UnspendableKey {
+    /// The point `H` of BIP 341, with x coordinate
+    /// `50929b74c1a04954b78b4b6035e97a5e078a5a0f28ec96d547bfee9ace803ac0`.
+    /// It is the same for every tree, so spends of outputs using it reveal
+    /// that they have no key spend.
+    Nums,
+    /// `H + r·G`, with `r` the BIP 340 tagged hash
+    /// `miniscript/UnspendableKey` of the merkle root of the tree. Until the
+    /// output is spent, only those knowing the tree can check that the key is
+    /// unspendable.
+    ///
+    /// A script path spend reveals the internal key, and its control block
+    /// and leaf give the merkle root, so from then on anyone can recompute
+    /// `r` and see that the output has no key spend. Unlike the secret random
+    /// `r` suggested by BIP 341, this does not hide that the output could
+    /// only be spent using its tree.
+    Derived,
+}
+
+impl UnspendableKey {
+    /// The unspendable internal key of this kind for a tree with the given
+    /// merkle root
+    pub fn key<C: secp256k1::Verification>(
+        self,
+        secp: &secp256k1::Secp256k1<C>,
+        merkle_root: TapBranchHash,
+    ) -> XOnlyPublicKey {
+        let mut key = nums_point();
+        if self == UnspendableKey::Derived {
+            let tag = sha256::Hash::hash(b"miniscript/UnspendableKey");
+            let mut engine = sha256::Hash::engine();
+            engine.input(&tag[..]);
+            engine.input(&tag[..]);
+            engine.input(&merkle_root[..]);
+            let r = sha256::Hash::from_engine(engine);
+            key.tweak_add_assign(secp, &r[..])
+                .expect("hash is a valid tweak with overwhelming probability");
+        }
+        key
+    }
+}
+
+// The point `H` of BIP 341, whose discrete logarithm is unknown
+fn nums_point() -> XOnlyPublicKey {
+    const NUMS_X: &str = "50929b74c1a04954b78b4b6035e97a5e078a5a0f28ec96d547bfee9ace803ac0";
+    XOnlyPublicKey::from_str(NUMS_X).expect("valid x-only key")
 }
 
 impl<Pk: MiniscriptKey> fmt::Display for TapTree<Pk> {
@@ -153,6 +480,10 @@ impl<Pk: MiniscriptKey> fmt::Display for TapTree<Pk> {
         match self {
             TapTree::Tree(ref left, ref right) => write!(f, "{{{},{}}}", *left, *right),
             TapTree::Leaf(ref script) => write!(f, "{}", *script),
+            TapTree::VersionedLeaf(ver, ref script) => {
+                write!(f, "leaf_ver({:02x},{})", ver, *script)
+            }
+            TapTree::Hidden(ref hash) => write!(f, "raw_node({})", hash),
         }
     }
 }
@@ -162,6 +493,10 @@ impl<Pk: MiniscriptKey> fmt::Debug for TapTree<Pk> {
         match self {
             TapTree::Tree(ref left, ref right) => write!(f, "{{{:?},{:?}}}", *left, *right),
             TapTree::Leaf(ref script) => write!(f, "{:?}", *script),
+            TapTree::VersionedLeaf(ver, ref script) => {
+                write!(f, "leaf_ver({:02x},{:?})", ver, *script)
+            }
+            TapTree::Hidden(ref hash) => write!(f, "raw_node({})", hash),
         }
     }
 }
@@ -176,13 +511,15 @@ impl<Pk: MiniscriptKey> Tr<Pk> {
                 internal_key,
                 tree,
                 spend_info: Mutex::new(None),
+                tree_hash: Mutex::new(None),
             })
         } else {
             Err(Error::MaxRecursiveDepthExceeded)
         }
     }
 
-    fn to_string_no_checksum(&self) -> String {
+    /// Get the descriptor without the checksum
+    pub fn to_string_no_checksum(&self) -> String {
         let key = &self.internal_key;
         match self.tree {
             Some(ref s) => format!("tr({},{})", key, s),
@@ -195,6 +532,22 @@ impl<Pk: MiniscriptKey> Tr<Pk> {
         &self.internal_key
     }
 
+    /// Whether the internal key also appears in a leaf script, as a key or
+    /// as the hash of a key.
+    ///
+    /// Such a key can sign for both the key spend and the script path, which
+    /// is usually a mistake: the leaves using it are redundant with the key
+    /// spend, and signatures of its holder are linkable across both paths.
+    pub fn reuses_internal_key(&self) -> bool {
+        let internal_hash = self.internal_key.to_pubkeyhash();
+        self.iter_leaves().any(|(_, _, ms)| {
+            ms.iter_pk_pkh().any(|pk_pkh| match pk_pkh {
+                PkPkh::PlainPubkey(pk) => pk == self.internal_key,
+                PkPkh::HashedPubkey(hash) => hash == internal_hash,
+            })
+        })
+    }
+
     /// Obtain the [`TapTree`] of the [`Tr`] descriptor
     pub fn taptree(&self) -> &Option<TapTree<Pk>> {
         &self.tree
@@ -203,9 +556,18 @@ impl<Pk: MiniscriptKey> Tr<Pk> {
     /// Iterate over all scripts in merkle tree. If there is no script path, the iterator
     /// yields [`None`]
     pub fn iter_scripts(&self) -> TapTreeIter<Pk> {
+        TapTreeIter {
+            leaves: self.iter_leaves(),
+        }
+    }
+
+    /// Iterate over the scripts of all leaves with their version, including
+    /// the leaves which are not tapscript. If there is no script path, the
+    /// iterator yields [`None`]
+    pub fn iter_leaves(&self) -> TapLeafIter<'_, Pk> {
         match self.tree {
-            Some(ref t) => t.iter(),
-            None => TapTreeIter { stack: vec![] },
+            Some(ref t) => t.iter_leaves(),
+            None => TapLeafIter { stack: vec![] },
         }
     }
 
@@ -230,39 +592,36 @@ impl<Pk: MiniscriptKey> Tr<Pk> {
         // This would be cheap operation after static context support from upstream
         let secp = secp256k1::Secp256k1::verification_only();
         // Key spend path with no merkle root
-        let data = if self.tree.is_none() {
-            TaprootSpendInfo::new_key_spend(&secp, self.internal_key.to_x_only_pubkey(), None)
-        } else {
-            let mut builder = TaprootBuilder::new();
-            for (depth, ms) in self.iter_scripts() {
-                let script = ms.encode();
-                builder = builder
-                    .add_leaf(depth, script)
-                    .expect("Computing spend data on a valid Tree should always succeed");
+        let data = match self.tree {
+            None => {
+                TaprootSpendInfo::new_key_spend(&secp, self.internal_key.to_x_only_pubkey(), None)
             }
-            // Assert builder cannot error here because we have a well formed descriptor
-            match builder.finalize(&secp, self.internal_key.to_x_only_pubkey()) {
-                Ok(data) => data,
-                Err(e) => match e {
-                    TaprootBuilderError::InvalidMerkleTreeDepth(_) => {
-                        unreachable!("Depth checked in struct construction")
-                    }
-                    TaprootBuilderError::NodeNotInDfsOrder => {
-                        unreachable!("Insertion is called in DFS order")
-                    }
-                    TaprootBuilderError::OverCompleteTree => {
-                        unreachable!("Taptree is a well formed tree")
-                    }
-                    TaprootBuilderError::InvalidInternalKey(_) => {
-                        unreachable!("Internal key checked for validity")
-                    }
-                    TaprootBuilderError::IncompleteTree => {
-                        unreachable!("Taptree is a well formed tree")
-                    }
-                    TaprootBuilderError::EmptyTree => {
-                        unreachable!("Taptree is a well formed tree with atleast 1 element")
-                    }
-                },
+            Some(ref tree) => {
+                let builder = tree.taproot_builder();
+                // Assert builder cannot error here because we have a well formed descriptor
+                match builder.finalize(&secp, self.internal_key.to_x_only_pubkey()) {
+                    Ok(data) => data,
+                    Err(e) => match e {
+                        TaprootBuilderError::InvalidMerkleTreeDepth(_) => {
+                            unreachable!("Depth checked in struct construction")
+                        }
+                        TaprootBuilderError::NodeNotInDfsOrder => {
+                            unreachable!("Insertion is called in DFS order")
+                        }
+                        TaprootBuilderError::OverCompleteTree => {
+                            unreachable!("Taptree is a well formed tree")
+                        }
+                        TaprootBuilderError::InvalidInternalKey(_) => {
+                            unreachable!("Internal key checked for validity")
+                        }
+                        TaprootBuilderError::IncompleteTree => {
+                            unreachable!("Taptree is a well formed tree")
+                        }
+                        TaprootBuilderError::EmptyTree => {
+                            unreachable!("Taptree is a well formed tree with atleast 1 element")
+                        }
+                    },
+                }
             }
         };
         let spend_info = Arc::new(data);
@@ -272,8 +631,14 @@ impl<Pk: MiniscriptKey> Tr<Pk> {
 
     /// Checks whether the descriptor is safe.
     pub fn sanity_check(&self) -> Result<(), Error> {
+        self.sanity_check_with(&RuleSet::Standard)
+    }
+
+    /// Checks whether the descriptor is safe as [`Self::sanity_check`] does,
+    /// checking its resource usage against `rules`
+    pub fn sanity_check_with(&self, rules: &RuleSet) -> Result<(), Error> {
         for (_depth, ms) in self.iter_scripts() {
-            ms.sanity_check()?;
+            ms.sanity_check_with(rules)?;
         }
         Ok(())
     }
@@ -288,27 +653,40 @@ impl<Pk: MiniscriptKey> Tr<Pk> {
     /// # Errors
     /// When the descriptor is impossible to safisfy (ex: sh(OP_FALSE)).
     pub fn max_satisfaction_weight(&self) -> Result<usize, Error> {
-        let mut max_wieght = Some(65);
-        for (depth, ms) in self.iter_scripts() {
-            let script_size = ms.script_size();
-            let max_sat_elems = match ms.max_satisfaction_witness_elements() {
-                Ok(elem) => elem,
-                Err(..) => continue,
-            };
-            let max_sat_size = match ms.max_satisfaction_size() {
-                Ok(sz) => sz,
-                Err(..) => continue,
-            };
-            let control_block_sz = control_block_len(depth);
-            let wit_size = 4 + // scriptSig len byte
-            control_block_sz + // first element control block
-            varint_len(script_size) +
-            script_size + // second element script len with prefix
-            varint_len(max_sat_elems) +
-            max_sat_size; // witness
-            max_wieght = cmp::max(max_wieght, Some(wit_size));
-        }
-        max_wieght.ok_or(Error::ImpossibleSatisfaction)
+        Ok(self.satisfaction_weight()?.weight())
+    }
+
+    /// Computes an upper bound on the size of a satisfaction over all spend
+    /// paths, see [`Descriptor::satisfaction_weight`](crate::Descriptor::satisfaction_weight).
+    pub fn satisfaction_weight(&self) -> Result<SatisfactionWeight, Error> {
+        let leaves = self.leaf_satisfaction_weights().into_iter().flatten();
+        Ok(leaves
+            .chain(iter::once(SatisfactionWeight::tap_key_spend()))
+            .max_by_key(SatisfactionWeight::weight)
+            .expect("the key spend path is always present"))
+    }
+
+    /// Computes an upper bound on the size of a key spend satisfaction
+    pub fn key_spend_satisfaction_weight(&self) -> SatisfactionWeight {
+        SatisfactionWeight::tap_key_spend()
+    }
+
+    /// Computes an upper bound on the size of a satisfaction of each script
+    /// leaf, including the leaf script and control block, in the order of
+    /// [`Tr::iter_scripts`]. Leaves which are impossible to satisfy are `None`.
+    pub fn leaf_satisfaction_weights(&self) -> Vec<Option<SatisfactionWeight>> {
+        self.iter_scripts()
+            .map(|(depth, ms)| {
+                let max_sat_elems = ms.max_satisfaction_witness_elements().ok()?;
+                let max_sat_size = ms.max_satisfaction_size().ok()?;
+                Some(SatisfactionWeight::from_witness_script(
+                    max_sat_elems,
+                    max_sat_size,
+                    ms.script_size(),
+                    Some(control_block_len(depth)),
+                ))
+            })
+            .collect()
     }
 }
 
@@ -329,6 +707,22 @@ impl<Pk: MiniscriptKey + ToPublicKey> Tr<Pk> {
         Address::p2tr_tweaked(spend_info.output_key(), network)
     }
 
+    /// Whether the internal key is an [`UnspendableKey`] of the tree of the
+    /// descriptor, so that its outputs can only be spent using the tree.
+    ///
+    /// Other unspendable keys, such as ones with a random tweak, are not
+    /// recognized.
+    pub fn internal_key_is_unspendable<C: secp256k1::Verification>(
+        &self,
+        secp: &secp256k1::Secp256k1<C>,
+    ) -> bool {
+        let key = self.internal_key.to_x_only_pubkey();
+        key == nums_point()
+            || self.tree.as_ref().map_or(false, |tree| {
+                key == tree.unspendable_key(secp, UnspendableKey::Derived)
+            })
+    }
+
     /// Returns satisfying non-malleable witness and scriptSig with minimum
     /// weight to spend an output controlled by the given descriptor if it is
     /// possible to construct one using the `satisfier`.
@@ -336,7 +730,12 @@ impl<Pk: MiniscriptKey + ToPublicKey> Tr<Pk> {
     where
         S: Satisfier<Pk>,
     {
-        best_tap_spend(self, satisfier, false /* allow_mall */)
+        best_tap_spend(
+            self,
+            satisfier,
+            false, /* allow_mall */
+            &RuleSet::Standard,
+        )
     }
 
     /// Returns satisfying, possibly malleable, witness and scriptSig with
@@ -346,7 +745,50 @@ impl<Pk: MiniscriptKey + ToPublicKey> Tr<Pk> {
     where
         S: Satisfier<Pk>,
     {
-        best_tap_spend(self, satisfier, true /* allow_mall */)
+        best_tap_spend(
+            self,
+            satisfier,
+            true, /* allow_mall */
+            &RuleSet::Standard,
+        )
+    }
+}
+
+impl Tr<DescriptorPublicKey> {
+    // Helper function to derive the script pubkey at `index` without
+    // computing the spend info of the derived descriptor
+    pub(super) fn script_pubkey_at_index<C: secp256k1::Verification>(
+        &self,
+        secp: &secp256k1::Secp256k1<C>,
+        index: u32,
+    ) -> Result<Script, ConversionError> {
+        let mut derivator = PublicKeyDerivator {
+            secp,
+            index: Some(index),
+        };
+        let internal_key = derivator.pk(&self.internal_key)?.to_x_only_pubkey();
+        let merkle_root = match self.tree {
+            None => None,
+            Some(ref tree) => {
+                let cached = *self.tree_hash.lock().expect("Lock poisoned");
+                match cached {
+                    Some(hash) => Some(hash),
+                    None => {
+                        let derived = tree.translate_helper(&mut derivator)?;
+                        let hash = derived.node_hash();
+                        // Without wildcards, the tree is the same at every index
+                        if !tree
+                            .iter()
+                            .any(|(_, ms)| ms.for_any_key(|key| key.as_key().is_deriveable()))
+                        {
+                            *self.tree_hash.lock().expect("Lock poisoned") = Some(hash);
+                        }
+                        Some(hash)
+                    }
+                }
+            }
+        };
+        Ok(Script::new_v1_p2tr(secp, internal_key, merkle_root))
     }
 }
 
@@ -362,9 +804,10 @@ impl<Pk: MiniscriptKey + ToPublicKey> Tr<Pk> {
 ///                                           D    E
 /// would yield (2, A), (2, B), (2,C), (3, D), (3, E).
 ///
+/// Hidden branches and leaves which are not tapscript are skipped.
 #[derive(Debug, Clone)]
 pub struct TapTreeIter<'a, Pk: MiniscriptKey> {
-    stack: Vec<(u8, &'a TapTree<Pk>)>,
+    leaves: TapLeafIter<'a, Pk>,
 }
 
 impl<'a, Pk> Iterator for TapTreeIter<'a, Pk>
@@ -373,6 +816,30 @@ where
 {
     type Item = (u8, &'a Miniscript<Pk, Tap>);
 
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let (depth, LeafVersion::TapScript, ms) = self.leaves.next()? {
+                return Some((depth, ms));
+            }
+        }
+    }
+}
+
+/// Iterator over the leaves of Taproot structures, yielding triples of
+/// (depth, leaf version, miniscript) in the same order as [`TapTreeIter`].
+///
+/// Hidden branches are skipped.
+#[derive(Debug, Clone)]
+pub struct TapLeafIter<'a, Pk: MiniscriptKey> {
+    stack: Vec<(u8, &'a TapTree<Pk>)>,
+}
+
+impl<'a, Pk> Iterator for TapLeafIter<'a, Pk>
+where
+    Pk: MiniscriptKey + 'a,
+{
+    type Item = (u8, LeafVersion, &'a Miniscript<Pk, Tap>);
+
     fn next(&mut self) -> Option<Self::Item> {
         while !self.stack.is_empty() {
             let (depth, last) = self.stack.pop().expect("Size checked above");
@@ -381,7 +848,9 @@ where
                     self.stack.push((depth + 1, r));
                     self.stack.push((depth + 1, l));
                 }
-                TapTree::Leaf(ref ms) => return Some((depth, ms)),
+                TapTree::Leaf(ref ms) => return Some((depth, LeafVersion::TapScript, ms)),
+                TapTree::VersionedLeaf(ver, ref ms) => return Some((depth, *ver, ms)),
+                TapTree::Hidden(..) => {}
             }
         }
         None
@@ -392,8 +861,16 @@ impl<Pk: MiniscriptKey> FromTree for Tr<Pk>
 where
     Pk: MiniscriptKey + FromStr,
     Pk::Hash: FromStr,
+    Pk::Sha256: FromStr,
+    Pk::Hash256: FromStr,
+    Pk::Ripemd160: FromStr,
+    Pk::Hash160: FromStr,
     <Pk as FromStr>::Err: ToString,
     <<Pk as MiniscriptKey>::Hash as FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Sha256 as FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Hash256 as FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Ripemd160 as FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Hash160 as FromStr>::Err: ToString,
 {
     fn from_tree(top: &expression::Tree) -> Result<Self, Error> {
         // Helper function to parse taproot script path
@@ -403,15 +880,69 @@ where
         where
             Pk: MiniscriptKey + FromStr,
             Pk::Hash: FromStr,
+            Pk::Sha256: FromStr,
+            Pk::Hash256: FromStr,
+            Pk::Ripemd160: FromStr,
+            Pk::Hash160: FromStr,
             <Pk as FromStr>::Err: ToString,
             <<Pk as MiniscriptKey>::Hash as FromStr>::Err: ToString,
+            <<Pk as MiniscriptKey>::Sha256 as FromStr>::Err: ToString,
+            <<Pk as MiniscriptKey>::Hash256 as FromStr>::Err: ToString,
+            <<Pk as MiniscriptKey>::Ripemd160 as FromStr>::Err: ToString,
+            <<Pk as MiniscriptKey>::Hash160 as FromStr>::Err: ToString,
         {
             match tree {
-                expression::Tree { name, args } if !name.is_empty() && args.is_empty() => {
-                    let script = Miniscript::<Pk, Tap>::from_str(name)?;
+                expression::Tree { name, args, .. }
+                    if name.len() > 10
+                        && &name[..9] == "raw_node("
+                        && name.as_bytes()[name.len() - 1] == b')'
+                        && args.is_empty() =>
+                {
+                    let hash = TapBranchHash::from_hex(&name[9..name.len() - 1])
+                        .map_err(|e| Error::BadDescriptor(e.to_string()))?;
+                    Ok(TapTree::Hidden(hash))
+                }
+                expression::Tree {
+                    name,
+                    args,
+                    position,
+                } if name.len() > 10
+                    && &name[..9] == "leaf_ver("
+                    && name.as_bytes()[name.len() - 1] == b')'
+                    && args.is_empty() =>
+                {
+                    let (ver, script) = split_once(&name[9..name.len() - 1], ',')
+                        .filter(|&(ver, script)| ver.len() == 2 && !script.is_empty())
+                        .ok_or_else(|| {
+                            Error::BadDescriptor(format!("invalid versioned leaf {}", name))
+                        })?;
+                    let ver = u8::from_str_radix(ver, 16)
+                        .map_err(|e| Error::BadDescriptor(e.to_string()))?;
+                    let ver = match LeafVersion::from_consensus(ver) {
+                        Ok(LeafVersion::TapScript) => {
+                            return Err(Error::BadDescriptor(
+                                "tapscript leaves are written without leaf_ver".to_string(),
+                            ))
+                        }
+                        Ok(ver) => ver,
+                        Err(e) => return Err(Error::BadDescriptor(e.to_string())),
+                    };
+                    // The script ends before the closing bracket of the leaf
+                    let offset = position + name.len() - 1 - script.len();
+                    let script = Miniscript::<Pk, Tap>::from_str(script)
+                        .map_err(|e| context::offset_key(e, offset))?;
+                    Ok(TapTree::VersionedLeaf(ver, Arc::new(script)))
+                }
+                expression::Tree {
+                    name,
+                    args,
+                    position,
+                } if !name.is_empty() && args.is_empty() => {
+                    let script = Miniscript::<Pk, Tap>::from_str(name)
+                        .map_err(|e| context::offset_key(e, *position))?;
                     Ok(TapTree::Leaf(Arc::new(script)))
                 }
-                expression::Tree { name, args } if name.is_empty() && args.len() == 2 => {
+                expression::Tree { name, args, .. } if name.is_empty() && args.len() == 2 => {
                     let left = parse_tr_script_spend(&args[0])?;
                     let right = parse_tr_script_spend(&args[1])?;
                     Ok(TapTree::Tree(Arc::new(left), Arc::new(right)))
@@ -437,6 +968,7 @@ where
                         internal_key: expression::terminal(key, Pk::from_str)?,
                         tree: None,
                         spend_info: Mutex::new(None),
+                        tree_hash: Mutex::new(None),
                     })
                 }
                 2 => {
@@ -453,6 +985,7 @@ where
                         internal_key: expression::terminal(key, Pk::from_str)?,
                         tree: Some(ret),
                         spend_info: Mutex::new(None),
+                        tree_hash: Mutex::new(None),
                     })
                 }
                 _ => {
@@ -477,8 +1010,16 @@ impl<Pk: MiniscriptKey> FromStr for Tr<Pk>
 where
     Pk: MiniscriptKey + FromStr,
     Pk::Hash: FromStr,
+    Pk::Sha256: FromStr,
+    Pk::Hash256: FromStr,
+    Pk::Ripemd160: FromStr,
+    Pk::Hash160: FromStr,
     <Pk as FromStr>::Err: ToString,
     <<Pk as MiniscriptKey>::Hash as FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Sha256 as FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Hash256 as FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Ripemd160 as FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Hash160 as FromStr>::Err: ToString,
 {
     type Err = Error;
 
@@ -507,7 +1048,7 @@ impl<Pk: MiniscriptKey> fmt::Display for Tr<Pk> {
 }
 
 // Helper function to parse string into miniscript tree form
-fn parse_tr_tree(s: &str) -> Result<expression::Tree, Error> {
+pub(super) fn parse_tr_tree(s: &str) -> Result<expression::Tree, Error> {
     for ch in s.bytes() {
         if !ch.is_ascii() {
             return Err(Error::Unprintable(ch));
@@ -520,10 +1061,12 @@ fn parse_tr_tree(s: &str) -> Result<expression::Tree, Error> {
             let internal_key = expression::Tree {
                 name: rest,
                 args: vec![],
+                position: 3,
             };
             return Ok(expression::Tree {
                 name: "tr",
                 args: vec![internal_key],
+                position: 0,
             });
         }
         // use str::split_once() method to refactor this when compiler version bumps up
@@ -533,18 +1076,21 @@ fn parse_tr_tree(s: &str) -> Result<expression::Tree, Error> {
         let internal_key = expression::Tree {
             name: key,
             args: vec![],
+            position: 3,
         };
         if script.is_empty() {
             return Ok(expression::Tree {
                 name: "tr",
                 args: vec![internal_key],
+                position: 0,
             });
         }
-        let (tree, rest) = expression::Tree::from_slice_delim(script, 1, '{')?;
+        let (tree, rest) = expression::Tree::from_slice_delim(script, key.len() + 4, 1, '{')?;
         if rest.is_empty() {
             Ok(expression::Tree {
                 name: "tr",
                 args: vec![internal_key, tree],
+                position: 0,
             })
         } else {
             Err(errstr(rest))
@@ -554,6 +1100,45 @@ fn parse_tr_tree(s: &str) -> Result<expression::Tree, Error> {
     }
 }
 
+// Check the limits of `options` on a tree returned by `parse_tr_tree`, whose
+// leaves are parsed as miniscript trees
+pub(super) fn check_tr_tree_limits(
+    top: &expression::Tree,
+    options: &ParseOptions,
+) -> Result<(), Error> {
+    fn add_tap_tree(
+        counter: &mut LimitCounter,
+        tree: &expression::Tree,
+        depth: usize,
+    ) -> Result<(), Error> {
+        if tree.name.is_empty() {
+            counter.add_node(depth, 0, 0)?;
+            tree.args
+                .iter()
+                .try_for_each(|arg| add_tap_tree(counter, arg, depth + 1))
+        } else if tree.name.starts_with("raw_node(") {
+            counter.add_node(depth, 0, 0)
+        } else {
+            let mut script = tree.name;
+            if script.starts_with("leaf_ver(") && script.ends_with(')') {
+                if let Some((_, leaf_script)) = split_once(&script[9..script.len() - 1], ',') {
+                    script = leaf_script;
+                }
+            }
+            counter.add(&expression::Tree::from_str(script)?, depth)
+        }
+    }
+
+    let mut counter = LimitCounter::new(options);
+    // The tr node and its internal key
+    counter.add_node(1, 0, 1)?;
+    counter.add_node(2, 0, 0)?;
+    match top.args.get(1) {
+        Some(tree) => add_tap_tree(&mut counter, tree, 2),
+        None => Ok(()),
+    }
+}
+
 fn split_once(inp: &str, delim: char) -> Option<(&str, &str)> {
     if inp.is_empty() {
         None
@@ -582,6 +1167,8 @@ impl<Pk: MiniscriptKey> Liftable<Pk> for TapTree<Pk> {
                     Ok(Policy::Threshold(1, vec![lift_helper(l)?, lift_helper(r)?]))
                 }
                 TapTree::Leaf(ref leaf) => leaf.lift(),
+                TapTree::VersionedLeaf(..) => Err(LiftError::UnknownLeafVersion.into()),
+                TapTree::Hidden(..) => Err(LiftError::HiddenTapBranch.into()),
             }
         }
 
@@ -612,8 +1199,8 @@ impl<Pk: MiniscriptKey> ForEachKey<Pk> for Tr<Pk> {
         Pk::Hash: 'a,
     {
         let script_keys_res = self
-            .iter_scripts()
-            .all(|(_d, ms)| ms.for_each_key(&mut pred));
+            .iter_leaves()
+            .all(|(_d, _ver, ms)| ms.for_each_key(&mut pred));
         script_keys_res && pred(ForEach::Key(&self.internal_key))
     }
 }
@@ -625,23 +1212,211 @@ where
 {
     type Output = Tr<Q>;
 
-    fn translate_pk<Fpk, Fpkh, E>(&self, mut fpk: Fpk, mut fpkh: Fpkh) -> Result<Self::Output, E>
+    fn translate_pk<T, E>(&self, t: &mut T) -> Result<Self::Output, E>
     where
-        Fpk: FnMut(&P) -> Result<Q, E>,
-        Fpkh: FnMut(&P::Hash) -> Result<Q::Hash, E>,
+        T: Translator<P, Q, E> + ?Sized,
     {
         let translate_desc = Tr {
-            internal_key: fpk(&self.internal_key)?,
+            internal_key: t.pk(&self.internal_key)?,
             tree: match &self.tree {
-                Some(tree) => Some(tree.translate_helper(&mut fpk, &mut fpkh)?),
+                Some(tree) => Some(tree.translate_helper(t)?),
                 None => None,
             },
             spend_info: Mutex::new(None),
+            tree_hash: Mutex::new(None),
         };
         Ok(translate_desc)
     }
 }
 
+/// A taproot descriptor for an output whose tweaked output key is known, but
+/// whose internal key and script tree are not. Written as `rawtr(KEY)`.
+///
+/// Such outputs can only be spent using the key spend path.
+#[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub struct RawTr<Pk: MiniscriptKey> {
+    /// the (already tweaked) taproot output key
+    pk: Pk,
+}
+
+impl<Pk: MiniscriptKey> RawTr<Pk> {
+    /// Create a new [`RawTr`] descriptor from the taproot output key
+    pub fn new(pk: Pk) -> Self {
+        Self { pk }
+    }
+
+    /// Get a reference to the output key
+    pub fn as_inner(&self) -> &Pk {
+        &self.pk
+    }
+
+    /// Get the output key
+    pub fn into_inner(self) -> Pk {
+        self.pk
+    }
+
+    /// Get the descriptor without the checksum
+    pub fn to_string_no_checksum(&self) -> String {
+        format!("rawtr({})", self.pk)
+    }
+
+    /// Checks whether the descriptor is safe.
+    pub fn sanity_check(&self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Computes an upper bound on the weight of a satisfying witness to the
+    /// transaction.
+    ///
+    /// Only the key spend path is available, so this is the weight of a
+    /// single schnorr signature.
+    pub fn max_satisfaction_weight(&self) -> usize {
+        self.satisfaction_weight().weight()
+    }
+
+    /// Computes an upper bound on the size of a satisfaction, see
+    /// [`Descriptor::satisfaction_weight`](crate::Descriptor::satisfaction_weight).
+    pub fn satisfaction_weight(&self) -> SatisfactionWeight {
+        SatisfactionWeight::tap_key_spend()
+    }
+}
+
+impl<Pk: MiniscriptKey + ToPublicKey> RawTr<Pk> {
+    /// Obtains the corresponding script pubkey for this descriptor.
+    pub fn script_pubkey(&self) -> Script {
+        let output_key = TweakedPublicKey::dangerous_assume_tweaked(self.pk.to_x_only_pubkey());
+        Script::new_v1_p2tr_tweaked(output_key)
+    }
+
+    /// Obtains the corresponding address for this descriptor.
+    pub fn address(&self, network: Network) -> Address {
+        let output_key = TweakedPublicKey::dangerous_assume_tweaked(self.pk.to_x_only_pubkey());
+        Address::p2tr_tweaked(output_key, network)
+    }
+
+    /// Returns satisfying non-malleable witness and scriptSig with minimum
+    /// weight to spend an output controlled by the given descriptor if it is
+    /// possible to construct one using the `satisfier`.
+    pub fn get_satisfaction<S>(&self, satisfier: S) -> Result<(Vec<Vec<u8>>, Script), Error>
+    where
+        S: Satisfier<Pk>,
+    {
+        match satisfier.lookup_tap_key_spend_sig() {
+            Some(sig) => Ok((vec![sig.to_vec()], Script::new())),
+            None => Err(Error::MissingSig(self.pk.to_public_key())),
+        }
+    }
+
+    /// Returns satisfying, possibly malleable, witness and scriptSig with
+    /// minimum weight to spend an output controlled by the given descriptor if
+    /// it is possible to construct one using the `satisfier`.
+    pub fn get_satisfaction_mall<S>(&self, satisfier: S) -> Result<(Vec<Vec<u8>>, Script), Error>
+    where
+        S: Satisfier<Pk>,
+    {
+        self.get_satisfaction(satisfier)
+    }
+}
+
+impl<Pk: MiniscriptKey> fmt::Debug for RawTr<Pk> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "rawtr({:?})", self.pk)
+    }
+}
+
+impl<Pk: MiniscriptKey> fmt::Display for RawTr<Pk> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let desc = self.to_string_no_checksum();
+        let checksum = desc_checksum(&desc).map_err(|_| fmt::Error)?;
+        write!(f, "{}#{}", &desc, &checksum)
+    }
+}
+
+impl<Pk: MiniscriptKey> Liftable<Pk> for RawTr<Pk> {
+    fn lift(&self) -> Result<Policy<Pk>, Error> {
+        Ok(Policy::KeyHash(self.pk.to_pubkeyhash()))
+    }
+}
+
+impl<Pk> FromTree for RawTr<Pk>
+where
+    Pk: MiniscriptKey + FromStr,
+    Pk::Hash: FromStr,
+    Pk::Sha256: FromStr,
+    Pk::Hash256: FromStr,
+    Pk::Ripemd160: FromStr,
+    Pk::Hash160: FromStr,
+    <Pk as FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Hash as FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Sha256 as FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Hash256 as FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Ripemd160 as FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Hash160 as FromStr>::Err: ToString,
+{
+    fn from_tree(top: &expression::Tree) -> Result<Self, Error> {
+        if top.name == "rawtr" && top.args.len() == 1 {
+            Ok(RawTr::new(expression::terminal(&top.args[0], |pk| {
+                Pk::from_str(pk)
+            })?))
+        } else {
+            Err(Error::Unexpected(format!(
+                "{}({} args) while parsing rawtr descriptor",
+                top.name,
+                top.args.len(),
+            )))
+        }
+    }
+}
+
+impl<Pk> FromStr for RawTr<Pk>
+where
+    Pk: MiniscriptKey + FromStr,
+    Pk::Hash: FromStr,
+    Pk::Sha256: FromStr,
+    Pk::Hash256: FromStr,
+    Pk::Ripemd160: FromStr,
+    Pk::Hash160: FromStr,
+    <Pk as FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Hash as FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Sha256 as FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Hash256 as FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Ripemd160 as FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Hash160 as FromStr>::Err: ToString,
+{
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let desc_str = verify_checksum(s)?;
+        let top = expression::Tree::from_str(desc_str)?;
+        Self::from_tree(&top)
+    }
+}
+
+impl<Pk: MiniscriptKey> ForEachKey<Pk> for RawTr<Pk> {
+    fn for_each_key<'a, F: FnMut(ForEach<'a, Pk>) -> bool>(&'a self, mut pred: F) -> bool
+    where
+        Pk: 'a,
+        Pk::Hash: 'a,
+    {
+        pred(ForEach::Key(&self.pk))
+    }
+}
+
+impl<P, Q> TranslatePk<P, Q> for RawTr<P>
+where
+    P: MiniscriptKey,
+    Q: MiniscriptKey,
+{
+    type Output = RawTr<Q>;
+
+    fn translate_pk<T, E>(&self, t: &mut T) -> Result<Self::Output, E>
+    where
+        T: Translator<P, Q, E> + ?Sized,
+    {
+        Ok(RawTr::new(t.pk(&self.pk)?))
+    }
+}
+
 // Helper function to compute the len of control block at a given depth
 fn control_block_len(depth: u8) -> usize {
     TAPROOT_CONTROL_BASE_SIZE + (depth as usize) * TAPROOT_CONTROL_NODE_SIZE
@@ -649,10 +1424,11 @@ fn control_block_len(depth: u8) -> usize {
 
 // Helper function to get a script spend satisfaction
 // try script spend
-fn best_tap_spend<Pk, S>(
+pub(crate) fn best_tap_spend<Pk, S>(
     desc: &Tr<Pk>,
     satisfier: S,
     allow_mall: bool,
+    rules: &RuleSet,
 ) -> Result<(Vec<Vec<u8>>, Script), Error>
 where
     Pk: ToPublicKey,
@@ -668,12 +1444,12 @@ where
         let (mut min_wit, mut min_wit_len) = (None, None);
         for (depth, ms) in desc.iter_scripts() {
             let mut wit = if allow_mall {
-                match ms.satisfy_malleable(&satisfier) {
+                match ms.satisfy_malleable_with_rules(&satisfier, rules) {
                     Ok(wit) => wit,
                     Err(..) => continue, // No witness for this script in tr descriptor, look for next one
                 }
             } else {
-                match ms.satisfy(&satisfier) {
+                match ms.satisfy_with_rules(&satisfier, rules) {
                     Ok(wit) => wit,
                     Err(..) => continue, // No witness for this script in tr descriptor, look for next one
                 }
@@ -735,4 +1511,256 @@ mod tests {
             ForEach::Hash(_h) => unreachable!(),
         }));
     }
+
+    #[test]
+    fn unspendable_key() {
+        let secp = secp256k1::Secp256k1::verification_only();
+        let key = "02e6642fd69bd211f93f7f1f36ca51a26a5290eb2dd1b0d8279a87bb0d480c8443";
+        let tree = format!("{{pk({}),and_v(v:pk({}),older(144))}}", key, key);
+        let tr = Tr::<DescriptorPublicKey>::from_str(&format!("tr({},{})", key, tree)).unwrap();
+        let tree = tr.taptree().clone().unwrap();
+
+        let nums = tree
+            .clone()
+            .with_unspendable_key(&secp, UnspendableKey::Nums)
+            .unwrap();
+        assert!(nums
+            .to_string()
+            .starts_with("tr(50929b74c1a04954b78b4b6035e97a5e078a5a0f28ec96d547bfee9ace803ac0,"));
+        let derived = tree
+            .clone()
+            .with_unspendable_key(&secp, UnspendableKey::Derived)
+            .unwrap();
+        assert_ne!(derived.internal_key(), nums.internal_key());
+        assert_eq!(derived.taptree(), nums.taptree());
+
+        let mut derivator = PublicKeyDerivator {
+            secp: &secp,
+            index: None,
+        };
+        let nums = nums.translate_pk(&mut derivator).unwrap();
+        let derived = derived.translate_pk(&mut derivator).unwrap();
+        let tr = tr.translate_pk(&mut derivator).unwrap();
+        assert!(nums.internal_key_is_unspendable(&secp));
+        assert!(derived.internal_key_is_unspendable(&secp));
+        assert!(!tr.internal_key_is_unspendable(&secp));
+        let derived_key = derived.internal_key().to_x_only_pubkey();
+        assert_eq!(
+            derived
+                .taptree()
+                .as_ref()
+                .unwrap()
+                .unspendable_key(&secp, UnspendableKey::Derived),
+            derived_key
+        );
+
+        // The derived key is bound to its tree
+        let other =
+            Tr::<XOnlyPublicKey>::from_str(&format!("tr({},pk({}))", derived_key, &key[2..]))
+                .unwrap();
+        assert!(!other.internal_key_is_unspendable(&secp));
+
+        // The merkle root of a tree with wildcards is unknown
+        let xpub = "xpub6ERApfZwUNrhLCkDtcHTcxd75RbzS1ed54G1LkBUHQVHQKqhMkhgbmJbZRkrgZw4koxb5JaHWkY4ALHY2grBGRjaDMzQLcgJvLJuZZvRcEL";
+        let tr =
+            Tr::<DescriptorPublicKey>::from_str(&format!("tr({},pk({}/*))", key, xpub)).unwrap();
+        let tree = tr.taptree().clone().unwrap();
+        assert_eq!(
+            tree.clone()
+                .with_unspendable_key(&secp, UnspendableKey::Derived),
+            Err(Error::Conversion(ConversionError::Wildcard))
+        );
+        assert!(tree
+            .with_unspendable_key(&secp, UnspendableKey::Nums)
+            .is_ok());
+    }
+
+    #[test]
+    fn taptree_builders() {
+        let leaf = |s: &str| Miniscript::<String, Tap>::from_str(s).unwrap();
+
+        let tr = Tr::<String>::from_str("tr(a,{pk(b),{pk(c),pk(d)}})").unwrap();
+        let tree = tr.taptree().as_ref().unwrap();
+        let depths = tree.iter().map(|(d, ms)| (d as usize, ms.clone()));
+        assert_eq!(&TapTree::from_depths(depths).unwrap(), tree);
+        assert_eq!(
+            TapTree::from_depths(vec![(0, leaf("pk(a)"))])
+                .unwrap()
+                .to_string(),
+            "pk(a)"
+        );
+        for depths in &[vec![], vec![1], vec![1, 1, 1], vec![0, 0], vec![2, 1, 2]] {
+            let leaves = depths.iter().map(|&d| (d, leaf("pk(a)")));
+            assert!(TapTree::from_depths(leaves).is_err());
+        }
+
+        let tree = TapTree::with_huffman(vec![
+            (1, leaf("pk(b)")),
+            (10, leaf("pk(a)")),
+            (1, leaf("pk(c)")),
+        ])
+        .unwrap();
+        let depths: Vec<_> = tree.iter().map(|(d, ms)| (d, ms.to_string())).collect();
+        assert!(depths.contains(&(1, "pk(a)".to_owned())));
+        assert!(depths.contains(&(2, "pk(b)".to_owned())));
+        assert!(depths.contains(&(2, "pk(c)".to_owned())));
+        assert!(TapTree::<String>::with_huffman(vec![]).is_err());
+
+        // Trees can be at most 128 levels deep
+        let mut tree = TapTree::Leaf(Arc::new(leaf("pk(a)")));
+        for _ in 1..TAPROOT_CONTROL_MAX_NODE_COUNT {
+            tree = TapTree::combine(TapTree::Leaf(Arc::new(leaf("pk(b)"))), tree).unwrap();
+        }
+        assert!(Tr::new("a".to_owned(), Some(tree.clone())).is_ok());
+        assert!(TapTree::combine(TapTree::Leaf(Arc::new(leaf("pk(b)"))), tree).is_err());
+    }
+
+    #[test]
+    fn hidden_branches() {
+        use bitcoin::util::taproot::ControlBlock;
+
+        struct LeafSigs(bitcoin::PublicKey);
+        impl Satisfier<bitcoin::PublicKey> for LeafSigs {
+            fn lookup_tap_leaf_script_sig(
+                &self,
+                pk: &bitcoin::PublicKey,
+                _: &TapLeafHash,
+            ) -> Option<bitcoin::SchnorrSig> {
+                Some(bitcoin::SchnorrSig {
+                    sig: secp256k1::schnorr::Signature::from_slice(&[1; 64]).ok()?,
+                    hash_ty: bitcoin::SchnorrSighashType::Default,
+                })
+                .filter(|_| *pk == self.0)
+            }
+        }
+
+        let pks = crate::miniscript::iter::test::gen_bitcoin_pubkeys(4, true);
+        let full = Tr::<bitcoin::PublicKey>::from_str(&format!(
+            "tr({},{{pk({}),{{pk({}),pk({})}}}})",
+            pks[0], pks[1], pks[2], pks[3]
+        ))
+        .unwrap();
+        let hidden_ms =
+            Miniscript::<bitcoin::PublicKey, Tap>::from_str(&format!("pk({})", pks[3])).unwrap();
+        let leaf_hash = TapLeafHash::from_script(&hidden_ms.encode(), LeafVersion::TapScript);
+        let hidden_str = format!(
+            "tr({},{{pk({}),{{pk({}),raw_node({})}}}})",
+            pks[0],
+            pks[1],
+            pks[2],
+            TapBranchHash::from_inner(leaf_hash.into_inner())
+        );
+        let hidden = Tr::<bitcoin::PublicKey>::from_str(&hidden_str).unwrap();
+        assert_eq!(hidden.to_string_no_checksum(), hidden_str);
+        assert_eq!(Tr::from_str(&hidden.to_string()).unwrap(), hidden);
+        assert!(
+            Tr::<bitcoin::PublicKey>::from_str(&format!("tr({},raw_node(00))", pks[0])).is_err()
+        );
+
+        // Hiding a branch does not change the output
+        assert_eq!(hidden.script_pubkey(), full.script_pubkey());
+        assert_eq!(hidden.iter_scripts().count(), 2);
+        match hidden.lift() {
+            Err(Error::LiftError(LiftError::HiddenTapBranch)) => {}
+            res => panic!("unexpected lift {:?}", res),
+        }
+
+        // The known leaves can still be spent, with a valid control block
+        let (witness, _) = hidden.get_satisfaction(LeafSigs(pks[2])).unwrap();
+        assert_eq!(witness.len(), 3);
+        let script = Script::from(witness[1].clone());
+        let control_block = ControlBlock::from_slice(&witness[2]).unwrap();
+        let secp = secp256k1::Secp256k1::verification_only();
+        assert!(control_block.verify_taproot_commitment(
+            &secp,
+            hidden.spend_info().output_key().to_inner(),
+            &script
+        ));
+        assert!(hidden.get_satisfaction(LeafSigs(pks[3])).is_err());
+    }
+
+    #[test]
+    fn versioned_leaves() {
+        use bitcoin::util::taproot::ControlBlock;
+
+        let pks = crate::miniscript::iter::test::gen_bitcoin_pubkeys(3, true);
+        let desc_str = format!(
+            "tr({},{{pk({}),leaf_ver(c2,pk({}))}})",
+            pks[0], pks[1], pks[2]
+        );
+        let desc = Tr::<bitcoin::PublicKey>::from_str(&desc_str).unwrap();
+        assert_eq!(desc.to_string_no_checksum(), desc_str);
+        assert_eq!(Tr::from_str(&desc.to_string()).unwrap(), desc);
+
+        let ver = LeafVersion::from_consensus(0xc2).unwrap();
+        let versions: Vec<_> = desc.iter_leaves().map(|(_, ver, _)| ver).collect();
+        assert_eq!(versions, vec![LeafVersion::TapScript, ver]);
+        assert_eq!(desc.iter_scripts().count(), 1);
+        match desc.lift() {
+            Err(Error::LiftError(LiftError::UnknownLeafVersion)) => {}
+            res => panic!("unexpected lift {:?}", res),
+        }
+
+        // The version is committed to in the output
+        let tapscript_only = Tr::<bitcoin::PublicKey>::from_str(&format!(
+            "tr({},{{pk({}),pk({})}})",
+            pks[0], pks[1], pks[2]
+        ))
+        .unwrap();
+        assert_ne!(desc.script_pubkey(), tapscript_only.script_pubkey());
+        let ms =
+            Miniscript::<bitcoin::PublicKey, Tap>::from_str(&format!("pk({})", pks[2])).unwrap();
+        let control_block: ControlBlock = desc
+            .spend_info()
+            .control_block(&(ms.encode(), ver))
+            .unwrap();
+        assert_eq!(control_block.leaf_version, ver);
+        let secp = secp256k1::Secp256k1::verification_only();
+        assert!(control_block.verify_taproot_commitment(
+            &secp,
+            desc.spend_info().output_key().to_inner(),
+            &ms.encode()
+        ));
+
+        let ms = Arc::new(ms);
+        assert_eq!(
+            TapTree::leaf_with_version(LeafVersion::TapScript, Arc::clone(&ms)),
+            TapTree::Leaf(Arc::clone(&ms))
+        );
+        assert_eq!(
+            TapTree::leaf_with_version(ver, Arc::clone(&ms)),
+            TapTree::VersionedLeaf(ver, ms)
+        );
+
+        // Invalid, odd, annex and default versions are rejected
+        for ver in &["gg", "c3", "50", "c0", "c"] {
+            assert!(Tr::<bitcoin::PublicKey>::from_str(&format!(
+                "tr({},leaf_ver({},pk({})))",
+                pks[0], ver, pks[1]
+            ))
+            .is_err());
+        }
+        assert!(
+            Tr::<bitcoin::PublicKey>::from_str(&format!("tr({},leaf_ver(c2))", pks[0])).is_err()
+        );
+    }
+
+    #[test]
+    fn rawtr_script_pubkey() {
+        let output_key = "a60869f0dbcf1dc659c9cecbaf8050135ea9e8cdc487053f1dc6880949dc684c";
+        let rawtr = RawTr::<bitcoin::secp256k1::XOnlyPublicKey>::from_str(&format!(
+            "rawtr({})",
+            output_key
+        ))
+        .unwrap();
+        assert_eq!(
+            rawtr.script_pubkey(),
+            Script::from_str(&format!("5120{}", output_key)).unwrap()
+        );
+        assert_eq!(
+            rawtr.to_string_no_checksum(),
+            format!("rawtr({})", output_key)
+        );
+        assert_eq!(RawTr::from_str(&rawtr.to_string()).unwrap(), rawtr);
+    }
 }
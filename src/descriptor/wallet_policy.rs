@@ -0,0 +1,450 @@
+// Miniscript
+// Written in 2022 by rust-miniscript developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Wallet policies
+//!
+//! Conversion of descriptors to and from the BIP 388 wallet policies used by
+//! hardware signers to register multi-key wallets. A wallet policy is a
+//! descriptor template, in which every key is replaced by a placeholder such
+//! as `@0/**` or `@1/<2;3>/*`, together with the vector of keys the
+//! placeholders refer to.
+//!
+
+use core::fmt;
+use core::str::FromStr;
+
+use bitcoin::hashes::{hash160, ripemd160, sha256};
+use bitcoin::util::bip32;
+
+use super::{Descriptor, DescriptorPublicKey, DescriptorXKey, Wildcard};
+use crate::prelude::*;
+use crate::{hash256, Error, TranslatePk, Translator};
+
+/// A BIP 388 wallet policy
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WalletPolicy {
+    /// The descriptor template, with a key placeholder as every key
+    template: Descriptor<String>,
+    /// The keys referred to by the placeholders of the template
+    keys: Vec<DescriptorPublicKey>,
+}
+
+// A key placeholder `@index/<receive;change>/*` of a descriptor template
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Placeholder {
+    index: usize,
+    receive: u32,
+    change: u32,
+}
+
+impl Placeholder {
+    fn parse(s: &str) -> Result<Placeholder, Error> {
+        let err = || Error::BadDescriptor(format!("invalid key placeholder '{}'", s));
+        let (index, path) = match s.find('/') {
+            Some(slash) if s.starts_with('@') => (&s[1..slash], &s[slash + 1..]),
+            _ => return Err(err()),
+        };
+        if index.is_empty() || !index.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(err());
+        }
+        if index.len() > 1 && index.starts_with('0') {
+            return Err(err());
+        }
+        let index = usize::from_str(index).map_err(|_| err())?;
+        let (receive, change) = if path == "**" {
+            (0, 1)
+        } else {
+            if !path.starts_with('<') || !path.ends_with(">/*") || path.len() < 4 {
+                return Err(err());
+            }
+            let path = &path[1..path.len() - 3];
+            let semicolon = path.find(';').ok_or_else(err)?;
+            let parse_step = |step: &str| -> Result<u32, Error> {
+                if step.is_empty() || !step.bytes().all(|b| b.is_ascii_digit()) {
+                    return Err(err());
+                }
+                match u32::from_str(step) {
+                    Ok(step) if step < (1 << 31) => Ok(step),
+                    _ => Err(err()),
+                }
+            };
+            let receive = parse_step(&path[..semicolon])?;
+            let change = parse_step(&path[semicolon + 1..])?;
+            if receive == change {
+                return Err(err());
+            }
+            (receive, change)
+        };
+        Ok(Placeholder {
+            index,
+            receive,
+            change,
+        })
+    }
+}
+
+impl fmt::Display for Placeholder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.receive == 0 && self.change == 1 {
+            write!(f, "@{}/**", self.index)
+        } else {
+            write!(f, "@{}/<{};{}>/*", self.index, self.receive, self.change)
+        }
+    }
+}
+
+impl WalletPolicy {
+    /// Create a wallet policy from a descriptor template and the vector of
+    /// keys its placeholders refer to.
+    ///
+    /// Every key must be an xpub, optionally with origin information, but
+    /// without derivation steps or wildcard. The placeholders must refer to
+    /// every key, and first refer to `@0`, then to `@1`, and so on.
+    pub fn new(template: &str, keys: Vec<DescriptorPublicKey>) -> Result<Self, Error> {
+        if template.contains('#') {
+            return Err(Error::BadDescriptor(
+                "a descriptor template has no checksum".to_owned(),
+            ));
+        }
+        for key in &keys {
+            match *key {
+                DescriptorPublicKey::XPub(ref xpub)
+                    if xpub.derivation_path.as_ref().is_empty()
+                        && xpub.wildcard == Wildcard::None => {}
+                _ => {
+                    return Err(Error::BadDescriptor(format!(
+                        "wallet policy key '{}' is not a bare xpub",
+                        key
+                    )))
+                }
+            }
+        }
+        let template = Descriptor::<String>::from_str(template)?;
+
+        // The placeholders are visited in the order they appear in
+        struct PlaceholderChecker {
+            n_keys: usize,
+            next_index: usize,
+        }
+
+        impl Translator<String, String, Error> for PlaceholderChecker {
+            fn pk(&mut self, pk: &String) -> Result<String, Error> {
+                let placeholder = Placeholder::parse(pk)?;
+                if placeholder.index > self.next_index || placeholder.index >= self.n_keys {
+                    return Err(Error::BadDescriptor(format!(
+                        "unexpected key placeholder '{}'",
+                        pk
+                    )));
+                }
+                if placeholder.index == self.next_index {
+                    self.next_index += 1;
+                }
+                Ok(placeholder.to_string())
+            }
+
+            fn pkh(&mut self, pkh: &String) -> Result<String, Error> {
+                self.pk(pkh)
+            }
+
+            fn sha256(&mut self, sha256: &String) -> Result<String, Error> {
+                sha256::Hash::from_str(sha256).map_err(|e| Error::Unexpected(e.to_string()))?;
+                Ok(sha256.clone())
+            }
+
+            fn hash256(&mut self, hash256: &String) -> Result<String, Error> {
+                hash256::Hash::from_str(hash256).map_err(|e| Error::Unexpected(e.to_string()))?;
+                Ok(hash256.clone())
+            }
+
+            fn ripemd160(&mut self, ripemd160: &String) -> Result<String, Error> {
+                ripemd160::Hash::from_str(ripemd160)
+                    .map_err(|e| Error::Unexpected(e.to_string()))?;
+                Ok(ripemd160.clone())
+            }
+
+            fn hash160(&mut self, hash160: &String) -> Result<String, Error> {
+                hash160::Hash::from_str(hash160).map_err(|e| Error::Unexpected(e.to_string()))?;
+                Ok(hash160.clone())
+            }
+        }
+
+        let mut checker = PlaceholderChecker {
+            n_keys: keys.len(),
+            next_index: 0,
+        };
+        let template = template.translate_pk(&mut checker)?;
+        if checker.next_index != keys.len() {
+            return Err(Error::BadDescriptor(
+                "wallet policy key not referred to by the template".to_owned(),
+            ));
+        }
+
+        Ok(WalletPolicy { template, keys })
+    }
+
+    /// Create the wallet policy of a receive descriptor.
+    ///
+    /// Every key must be an xpub derived at an unhardened step followed by an
+    /// unhardened wildcard, as in `[d34db33f/48'/0'/0'/2']xpub.../0/*`; the
+    /// descriptor of the change addresses is assumed to use the next step.
+    pub fn from_descriptor(descriptor: &Descriptor<DescriptorPublicKey>) -> Result<Self, Error> {
+        struct PlaceholderMaker(Vec<DescriptorPublicKey>);
+
+        impl Translator<DescriptorPublicKey, String, Error> for PlaceholderMaker {
+            fn pk(&mut self, pk: &DescriptorPublicKey) -> Result<String, Error> {
+                let err = || {
+                    Error::BadDescriptor(format!(
+                        "key '{}' can not be expressed as a key placeholder",
+                        pk
+                    ))
+                };
+                let xpub = match *pk {
+                    DescriptorPublicKey::XPub(ref xpub)
+                        if xpub.wildcard == Wildcard::Unhardened =>
+                    {
+                        xpub
+                    }
+                    _ => return Err(err()),
+                };
+                let receive = match *xpub.derivation_path.as_ref() {
+                    [bip32::ChildNumber::Normal { index }] if index + 1 < (1 << 31) => index,
+                    _ => return Err(err()),
+                };
+                let key = DescriptorPublicKey::XPub(DescriptorXKey {
+                    origin: xpub.origin.clone(),
+                    xkey: xpub.xkey,
+                    derivation_path: bip32::DerivationPath::master(),
+                    wildcard: Wildcard::None,
+                });
+
+                let keys = &mut self.0;
+                let index = match keys.iter().position(|k| *k == key) {
+                    Some(index) => index,
+                    None => {
+                        keys.push(key);
+                        keys.len() - 1
+                    }
+                };
+                Ok(Placeholder {
+                    index,
+                    receive,
+                    change: receive + 1,
+                }
+                .to_string())
+            }
+
+            fn pkh(&mut self, pkh: &DescriptorPublicKey) -> Result<String, Error> {
+                self.pk(pkh)
+            }
+
+            fn sha256(&mut self, sha256: &sha256::Hash) -> Result<String, Error> {
+                Ok(sha256.to_string())
+            }
+
+            fn hash256(&mut self, hash256: &hash256::Hash) -> Result<String, Error> {
+                Ok(hash256.to_string())
+            }
+
+            fn ripemd160(&mut self, ripemd160: &ripemd160::Hash) -> Result<String, Error> {
+                Ok(ripemd160.to_string())
+            }
+
+            fn hash160(&mut self, hash160: &hash160::Hash) -> Result<String, Error> {
+                Ok(hash160.to_string())
+            }
+        }
+
+        let mut maker = PlaceholderMaker(vec![]);
+        let template = descriptor.translate_pk(&mut maker)?;
+
+        Ok(WalletPolicy {
+            template,
+            keys: maker.0,
+        })
+    }
+
+    /// The descriptor template, without checksum, with `/<0;1>/*` written as
+    /// `/**`
+    pub fn template(&self) -> String {
+        self.template.to_string_no_checksum()
+    }
+
+    /// The keys referred to by the placeholders of the template
+    pub fn keys(&self) -> &[DescriptorPublicKey] {
+        &self.keys
+    }
+
+    /// The descriptor of the receive addresses, or of the change addresses if
+    /// `change` is set
+    pub fn to_descriptor(&self, change: bool) -> Descriptor<DescriptorPublicKey> {
+        struct KeyExpander<'a> {
+            keys: &'a [DescriptorPublicKey],
+            change: bool,
+        }
+
+        impl<'a> Translator<String, DescriptorPublicKey, ()> for KeyExpander<'a> {
+            fn pk(&mut self, pk: &String) -> Result<DescriptorPublicKey, ()> {
+                let placeholder = Placeholder::parse(pk).expect("checked in constructor");
+                let step = if self.change {
+                    placeholder.change
+                } else {
+                    placeholder.receive
+                };
+                let mut key = self.keys[placeholder.index].clone();
+                if let DescriptorPublicKey::XPub(ref mut xpub) = key {
+                    xpub.derivation_path = vec![bip32::ChildNumber::Normal { index: step }].into();
+                    xpub.wildcard = Wildcard::Unhardened;
+                }
+                Ok(key)
+            }
+
+            fn pkh(&mut self, pkh: &String) -> Result<DescriptorPublicKey, ()> {
+                self.pk(pkh)
+            }
+
+            fn sha256(&mut self, sha256: &String) -> Result<sha256::Hash, ()> {
+                Ok(sha256::Hash::from_str(sha256).expect("checked in constructor"))
+            }
+
+            fn hash256(&mut self, hash256: &String) -> Result<hash256::Hash, ()> {
+                Ok(hash256::Hash::from_str(hash256).expect("checked in constructor"))
+            }
+
+            fn ripemd160(&mut self, ripemd160: &String) -> Result<ripemd160::Hash, ()> {
+                Ok(ripemd160::Hash::from_str(ripemd160).expect("checked in constructor"))
+            }
+
+            fn hash160(&mut self, hash160: &String) -> Result<hash160::Hash, ()> {
+                Ok(hash160::Hash::from_str(hash160).expect("checked in constructor"))
+            }
+        }
+
+        self.template
+            .translate_pk(&mut KeyExpander {
+                keys: &self.keys,
+                change,
+            })
+            .expect("infallible translation")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const XPUB_A: &str = "[d34db33f/48'/0'/0'/2']tpubDBrgjcxBxnXyL575sHdkpKohWu5qHKoQ7TJXKNrYznh5fVEGBv89hA8ENW7A8MFVpFUSvgLqc4Nj1WZcpePX6rrxviVtPowvMuGF5rdT2Vi";
+    const XPUB_B: &str = "[deadbeef/48'/0'/0'/2']tpubDCvNhURocXGZsLNqWcqD3syHTqPXrMSTwi8feKVwAcpi29oYKsDD3Vex7x2TDneKMVN23RbLprfxB69v94iYqdaYHsVz3kPR37NQXeqouVz";
+
+    #[test]
+    fn wallet_policy_roundtrip() {
+        let desc = Descriptor::<DescriptorPublicKey>::from_str(&format!(
+            "wsh(or_d(pk({a}/0/*),and_v(v:pk({b}/2/*),older(4032))))",
+            a = XPUB_A,
+            b = XPUB_B
+        ))
+        .unwrap();
+        let policy = WalletPolicy::from_descriptor(&desc).unwrap();
+        assert_eq!(
+            policy.template(),
+            "wsh(or_d(pk(@0/**),and_v(v:pk(@1/<2;3>/*),older(4032))))"
+        );
+        let keys: Vec<String> = policy.keys().iter().map(|k| k.to_string()).collect();
+        assert_eq!(keys, vec![XPUB_A, XPUB_B]);
+        assert_eq!(policy.to_descriptor(false), desc);
+        assert_eq!(
+            policy.to_descriptor(true).to_string_no_checksum(),
+            format!(
+                "wsh(or_d(pk({}/1/*),and_v(v:pk({}/3/*),older(4032))))",
+                XPUB_A, XPUB_B
+            )
+        );
+        assert_eq!(
+            WalletPolicy::new(&policy.template(), policy.keys().to_vec()).unwrap(),
+            policy
+        );
+
+        // The same key at two different steps shares its placeholder index
+        let desc = Descriptor::<DescriptorPublicKey>::from_str(&format!(
+            "wsh(multi(1,{a}/0/*,{a}/2/*))",
+            a = XPUB_A
+        ))
+        .unwrap();
+        let policy = WalletPolicy::from_descriptor(&desc).unwrap();
+        assert_eq!(policy.template(), "wsh(multi(1,@0/**,@0/<2;3>/*))");
+        assert_eq!(policy.keys().len(), 1);
+
+        let desc = Descriptor::<DescriptorPublicKey>::from_str(&format!(
+            "wsh(multi(1,{}/0/0,{}/0/*))",
+            XPUB_A, XPUB_B
+        ))
+        .unwrap();
+        assert!(WalletPolicy::from_descriptor(&desc).is_err());
+    }
+
+    #[test]
+    fn wallet_policy_new() {
+        let keys = |keys: &[&str]| -> Vec<DescriptorPublicKey> {
+            keys.iter()
+                .map(|k| DescriptorPublicKey::from_str(k).unwrap())
+                .collect()
+        };
+        let policy =
+            WalletPolicy::new("tr(@0/**,pk(@1/<0;1>/*))", keys(&[XPUB_A, XPUB_B])).unwrap();
+        assert_eq!(policy.template(), "tr(@0/**,pk(@1/**))");
+        assert_eq!(
+            policy.to_descriptor(true).to_string_no_checksum(),
+            format!("tr({}/1/*,pk({}/1/*))", XPUB_A, XPUB_B)
+        );
+
+        let hash = "926a54995ca48600920a19bf7bc502ca5f2f7d07e6f804c4f00ebf0325084dbc";
+        let template = format!("wsh(and_v(v:pk(@0/**),sha256({})))", hash);
+        let policy = WalletPolicy::new(&template, keys(&[XPUB_A])).unwrap();
+        assert_eq!(policy.template(), template);
+        assert_eq!(
+            policy.to_descriptor(false).to_string_no_checksum(),
+            format!("wsh(and_v(v:pk({}/0/*),sha256({})))", XPUB_A, hash)
+        );
+        assert_eq!(
+            WalletPolicy::from_descriptor(&policy.to_descriptor(false)).unwrap(),
+            policy
+        );
+
+        for template in &[
+            // placeholders out of order
+            "wsh(multi(1,@1/**,@0/**))",
+            // unused key
+            "wsh(pk(@0/**))",
+            // out of range key
+            "wsh(multi(1,@0/**,@2/**))",
+            // not a placeholder
+            "wsh(multi(1,@0/**,@1/0/*))",
+            "wsh(multi(1,@0/**,@01/**))",
+            "wsh(multi(1,@0/**,@1/<1;1>/*))",
+            "wsh(multi(1,@0/**,@1/<0;2147483648>/*))",
+            // not a hash
+            "wsh(and_v(v:multi(1,@0/**,@1/**),sha256(H)))",
+        ] {
+            assert!(
+                WalletPolicy::new(template, keys(&[XPUB_A, XPUB_B])).is_err(),
+                "{}",
+                template
+            );
+        }
+        // keys must not carry derivation steps
+        assert!(WalletPolicy::new(
+            "wsh(multi(1,@0/**,@1/**))",
+            keys(&[XPUB_A, &format!("{}/0", XPUB_B)])
+        )
+        .is_err());
+    }
+}
@@ -14,6 +14,7 @@
 
 //! # Function-like Expression Language
 //!
+use core::fmt;
 use core::str::FromStr;
 
 use crate::prelude::*;
@@ -26,6 +27,8 @@ pub struct Tree<'a> {
     pub name: &'a str,
     /// The comma-separated contents of the `(...)`, if any
     pub args: Vec<Tree<'a>>,
+    /// The byte offset of `name` in the parsed string
+    pub position: usize,
 }
 
 /// A trait for extracting a structure from a Tree representation in token form
@@ -107,64 +110,98 @@ impl<'a> Tree<'a> {
     /// Parse an expression with round brackets
     pub fn from_slice(sl: &'a str) -> Result<(Tree<'a>, &'a str), Error> {
         // Parsing TapTree or just miniscript
-        Self::from_slice_delim(sl, 0u32, '(')
+        Self::from_slice_delim(sl, 0, 0u32, '(')
     }
 
+    // Parses the expression at the start of `sl`, which starts at byte
+    // offset `start` of the parsed string
     pub(crate) fn from_slice_delim(
-        mut sl: &'a str,
+        sl: &'a str,
+        start: usize,
         depth: u32,
         delim: char,
     ) -> Result<(Tree<'a>, &'a str), Error> {
-        if depth >= MAX_RECURSION_DEPTH {
-            return Err(Error::MaxRecursiveDepthExceeded);
-        }
+        Self::from_slice_delim_max_depth(sl, start, depth, MAX_RECURSION_DEPTH, delim)
+    }
 
-        match next_expr(sl, delim) {
-            // String-ending terminal
-            Found::Nothing => Ok((
-                Tree {
-                    name: sl,
-                    args: vec![],
-                },
-                "",
-            )),
-            // Terminal
-            Found::Comma(n) | Found::RBracket(n) => Ok((
-                Tree {
-                    name: &sl[..n],
-                    args: vec![],
-                },
-                &sl[n..],
-            )),
-            // Function call
-            Found::LBracket(n) => {
-                let mut ret = Tree {
-                    name: &sl[..n],
-                    args: vec![],
-                };
+    // Parses the expression at the start of `sl`, which starts at byte offset
+    // `start` of the parsed string and whose top node is at `depth`,
+    // returning it and the rest of `sl`.
+    //
+    // The function calls being parsed are kept on an explicit stack rather
+    // than on the call stack, so that the nesting of the expression is only
+    // limited by `max_depth`.
+    fn from_slice_delim_max_depth(
+        mut sl: &'a str,
+        start: usize,
+        depth: u32,
+        max_depth: u32,
+        delim: char,
+    ) -> Result<(Tree<'a>, &'a str), Error> {
+        let closing = closing_delim(delim) as u8;
+        // The rest of the string is always a suffix of `sl`
+        let end = start + sl.len();
+        let mut stack: Vec<Tree<'a>> = vec![];
+        loop {
+            if depth + stack.len() as u32 >= max_depth {
+                return Err(Error::MaxRecursiveDepthExceeded);
+            }
 
-                sl = &sl[n + 1..];
-                loop {
-                    let (arg, new_sl) = Tree::from_slice_delim(sl, depth + 1, delim)?;
-                    ret.args.push(arg);
+            let position = end - sl.len();
+            let mut node = match next_expr(sl, delim) {
+                // String-ending terminal
+                Found::Nothing => {
+                    let node = Tree {
+                        name: sl,
+                        args: vec![],
+                        position,
+                    };
+                    sl = "";
+                    node
+                }
+                // Terminal
+                Found::Comma(n) | Found::RBracket(n) => {
+                    let node = Tree {
+                        name: &sl[..n],
+                        args: vec![],
+                        position,
+                    };
+                    sl = &sl[n..];
+                    node
+                }
+                // Function call, whose arguments are parsed next
+                Found::LBracket(n) => {
+                    stack.push(Tree {
+                        name: &sl[..n],
+                        args: vec![],
+                        position,
+                    });
+                    sl = &sl[n + 1..];
+                    continue;
+                }
+            };
 
-                    if new_sl.is_empty() {
-                        return Err(Error::ExpectedChar(closing_delim(delim)));
-                    }
+            // Add the parsed node to its parent, closing all the function
+            // calls it ends
+            loop {
+                let parent = match stack.last_mut() {
+                    Some(parent) => parent,
+                    None => return Ok((node, sl)),
+                };
+                parent.args.push(node);
 
-                    sl = &new_sl[1..];
-                    match new_sl.as_bytes()[0] {
-                        b',' => {}
-                        last_byte => {
-                            if last_byte == closing_delim(delim) as u8 {
-                                break;
-                            } else {
-                                return Err(Error::ExpectedChar(closing_delim(delim)));
-                            }
-                        }
-                    }
+                let next_byte = match sl.as_bytes().first() {
+                    Some(byte) => *byte,
+                    None => return Err(Error::ExpectedChar(closing as char)),
+                };
+                sl = &sl[1..];
+                if next_byte == b',' {
+                    break;
+                } else if next_byte == closing {
+                    node = stack.pop().expect("parent is on the stack");
+                } else {
+                    return Err(Error::ExpectedChar(closing as char));
                 }
-                Ok((ret, sl))
             }
         }
     }
@@ -172,6 +209,17 @@ impl<'a> Tree<'a> {
     /// Parses a tree from a string
     #[allow(clippy::should_implement_trait)] // Cannot use std::str::FromStr because of lifetimes.
     pub fn from_str(s: &'a str) -> Result<Tree<'a>, Error> {
+        Self::from_str_with_max_depth(s, MAX_RECURSION_DEPTH)
+    }
+
+    /// Parses a tree from a string, failing with
+    /// [`Error::MaxRecursiveDepthExceeded`] if the nesting of the expressions
+    /// reaches `max_depth`.
+    ///
+    /// [`Tree::from_str`] uses the limit of the Miniscript recursion depth;
+    /// a lower limit bounds the work done by the [`FromTree`] conversions of
+    /// untrusted input.
+    pub fn from_str_with_max_depth(s: &'a str, max_depth: u32) -> Result<Tree<'a>, Error> {
         // Filter out non-ASCII because we byte-index strings all over the
         // place and Rust gets very upset when you splinch a string.
         for ch in s.bytes() {
@@ -180,7 +228,7 @@ impl<'a> Tree<'a> {
             }
         }
 
-        let (top, rem) = Tree::from_slice(s)?;
+        let (top, rem) = Tree::from_slice_delim_max_depth(s, 0, 0, max_depth, '(')?;
         if rem.is_empty() {
             Ok(top)
         } else {
@@ -189,6 +237,129 @@ impl<'a> Tree<'a> {
     }
 }
 
+/// Limits on the expressions parsed by
+/// [`Miniscript::from_str_with_options`](crate::Miniscript::from_str_with_options)
+/// and [`Descriptor::from_str_with_options`](crate::Descriptor::from_str_with_options)
+///
+/// The limits are checked on the expression tree of the string, before any
+/// key is parsed or any miniscript fragment is type checked, so that parsing
+/// untrusted input fails early. The default options set no limit beyond the
+/// Miniscript recursion depth.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub struct ParseOptions {
+    /// Maximum number of nodes, counting each expression and each wrapper,
+    /// such as the `v` of `v:pk(A)`, as one node
+    pub max_nodes: Option<usize>,
+    /// Maximum nesting depth of the expressions, where a lone terminal has
+    /// depth 1 and `pk(A)` has depth 2. Taproot tree branches count as one
+    /// level each.
+    pub max_depth: Option<usize>,
+    /// Maximum number of keys, in key fragments such as `pk`, `multi` and
+    /// `sortedmulti` and as the key of `wpkh`, `tr` and `rawtr`
+    pub max_keys: Option<usize>,
+}
+
+/// A limit of [`ParseOptions`], as reported by [`Error::LimitExceeded`]
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub enum LimitKind {
+    /// [`ParseOptions::max_nodes`]
+    Nodes,
+    /// [`ParseOptions::max_depth`]
+    Depth,
+    /// [`ParseOptions::max_keys`]
+    Keys,
+}
+
+impl fmt::Display for LimitKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LimitKind::Nodes => f.write_str("nodes"),
+            LimitKind::Depth => f.write_str("nesting depth"),
+            LimitKind::Keys => f.write_str("keys"),
+        }
+    }
+}
+
+impl ParseOptions {
+    /// Parses a tree from a string as [`Tree::from_str`], checking its limits
+    pub(crate) fn parse_tree<'a>(&self, s: &'a str) -> Result<Tree<'a>, Error> {
+        // Stop parsing as soon as the depth limit is reached
+        let top = match self.max_depth {
+            Some(max) if max < MAX_RECURSION_DEPTH as usize => {
+                Tree::from_str_with_max_depth(s, max as u32).map_err(|e| match e {
+                    Error::MaxRecursiveDepthExceeded => Error::LimitExceeded {
+                        kind: LimitKind::Depth,
+                        limit: max,
+                    },
+                    e => e,
+                })?
+            }
+            _ => Tree::from_str(s)?,
+        };
+        let mut counter = LimitCounter::new(self);
+        counter.add(&top, 1)?;
+        Ok(top)
+    }
+}
+
+/// Counts the nodes and keys of the trees of an expression against the limits
+/// of a [`ParseOptions`]
+pub(crate) struct LimitCounter<'o> {
+    options: &'o ParseOptions,
+    nodes: usize,
+    keys: usize,
+}
+
+impl<'o> LimitCounter<'o> {
+    pub(crate) fn new(options: &'o ParseOptions) -> Self {
+        LimitCounter {
+            options,
+            nodes: 0,
+            keys: 0,
+        }
+    }
+
+    /// Counts a single node at `depth`, which has `wrappers` wrappers and
+    /// `keys` keys
+    pub(crate) fn add_node(
+        &mut self,
+        depth: usize,
+        wrappers: usize,
+        keys: usize,
+    ) -> Result<(), Error> {
+        self.nodes += 1 + wrappers;
+        self.keys += keys;
+        check_limit(LimitKind::Depth, depth, self.options.max_depth)?;
+        check_limit(LimitKind::Nodes, self.nodes, self.options.max_nodes)?;
+        check_limit(LimitKind::Keys, self.keys, self.options.max_keys)
+    }
+
+    /// Counts the nodes of `tree`, whose top node is at `depth`
+    pub(crate) fn add(&mut self, tree: &Tree, depth: usize) -> Result<(), Error> {
+        let (wrappers, name) = match tree.name.find(':') {
+            Some(idx) => (idx, &tree.name[idx + 1..]),
+            None => (0, tree.name),
+        };
+        let keys = match (name, tree.args.len()) {
+            ("pk", _) | ("pkh", _) | ("pk_k", _) | ("pk_h", _) => tree.args.len(),
+            ("wpkh", _) | ("tr", _) | ("rawtr", _) => 1,
+            ("multi", n) | ("multi_a", n) | ("sortedmulti", n) if n > 0 => n - 1,
+            _ => 0,
+        };
+        self.add_node(depth, wrappers, keys)?;
+        tree.args
+            .iter()
+            .try_for_each(|arg| self.add(arg, depth + 1))
+    }
+}
+
+fn check_limit(kind: LimitKind, value: usize, limit: Option<usize>) -> Result<(), Error> {
+    match limit {
+        Some(limit) if value > limit => Err(Error::LimitExceeded { kind, limit }),
+        _ => Ok(()),
+    }
+}
+
 /// Parse a string as a u32, for timelocks or thresholds
 pub fn parse_num(s: &str) -> Result<u32, Error> {
     if s.len() > 1 {
@@ -248,7 +419,7 @@ where
 #[cfg(test)]
 mod tests {
 
-    use super::parse_num;
+    use super::*;
 
     #[test]
     fn test_parse_num() {
@@ -260,6 +431,92 @@ mod tests {
         assert!(parse_num("-6").is_err());
     }
 
+    #[test]
+    fn test_parse_tree() {
+        let tree = Tree::from_str("or_b(pk(A),s:pk(B))").unwrap();
+        assert_eq!(tree.name, "or_b");
+        assert_eq!(tree.args.len(), 2);
+        assert_eq!(tree.args[0].name, "pk");
+        assert_eq!(tree.args[0].args[0].name, "A");
+        assert!(tree.args[0].args[0].args.is_empty());
+        assert_eq!(tree.args[1].name, "s:pk");
+        assert_eq!(tree.args[1].args[0].name, "B");
+        assert_eq!(tree.position, 0);
+        assert_eq!(tree.args[0].args[0].position, 8);
+        assert_eq!(tree.args[1].position, 11);
+        assert_eq!(tree.args[1].args[0].position, 16);
+
+        let (tree, rest) = Tree::from_slice_delim("{pk(A),{pk(B),pk(C)}}", 3, 1, '{').unwrap();
+        assert_eq!(rest, "");
+        assert_eq!(tree.args[0].name, "pk(A)");
+        assert_eq!(tree.args[1].args[1].name, "pk(C)");
+        assert_eq!(tree.args[1].args[1].position, 17);
+
+        assert!(Tree::from_str("pk(A").is_err());
+        assert!(Tree::from_str("pk(A))").is_err());
+        assert!(Tree::from_str("pk(A}").is_err());
+    }
+
+    #[test]
+    fn test_parse_tree_max_depth() {
+        assert!(Tree::from_str_with_max_depth("a(b(c))", 3).is_ok());
+        assert_eq!(
+            Tree::from_str_with_max_depth("a(b(c))", 2).unwrap_err(),
+            Error::MaxRecursiveDepthExceeded
+        );
+
+        // Deeply nested input fails without exhausting the stack
+        let deep = "a(".repeat(100_000);
+        assert_eq!(
+            Tree::from_str(&deep).unwrap_err(),
+            Error::MaxRecursiveDepthExceeded
+        );
+        let deep = format!("{}x{}", "a(".repeat(401), ")".repeat(401));
+        assert!(Tree::from_str(&deep).is_ok());
+    }
+
+    #[test]
+    fn test_parse_options() {
+        let s = "or_b(pk(A),s:pk(B))";
+        let limits = |max_nodes, max_depth, max_keys| ParseOptions {
+            max_nodes,
+            max_depth,
+            max_keys,
+        };
+        assert!(ParseOptions::default().parse_tree(s).is_ok());
+        assert!(limits(Some(6), Some(3), Some(2)).parse_tree(s).is_ok());
+        assert_eq!(
+            limits(Some(5), None, None).parse_tree(s).unwrap_err(),
+            Error::LimitExceeded {
+                kind: LimitKind::Nodes,
+                limit: 5
+            }
+        );
+        assert_eq!(
+            limits(None, Some(2), None).parse_tree(s).unwrap_err(),
+            Error::LimitExceeded {
+                kind: LimitKind::Depth,
+                limit: 2
+            }
+        );
+        assert_eq!(
+            limits(None, None, Some(1)).parse_tree(s).unwrap_err(),
+            Error::LimitExceeded {
+                kind: LimitKind::Keys,
+                limit: 1
+            }
+        );
+        assert_eq!(
+            limits(None, None, Some(2))
+                .parse_tree("multi(1,A,B,C)")
+                .unwrap_err(),
+            Error::LimitExceeded {
+                kind: LimitKind::Keys,
+                limit: 2
+            }
+        );
+    }
+
     // Add tests for tapscript parsing
     // tr(D,{or_i(pk(A),pk(B)),{after(9),pk(C)}})
 }
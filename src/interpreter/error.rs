@@ -77,6 +77,8 @@ pub enum Error {
     NonEmptyWitness,
     ///ScriptSig must be empty for pure segwit transactions
     NonEmptyScriptSig,
+    /// The script pubkey is not a taproot output
+    NonTaprootScriptPubkey,
     /// Script abortion because of incorrect dissatisfaction for Checksig.
     /// Any input witness apart from sat(sig) or nsat(0) leads to
     /// this error. This is network standardness assumption and miniscript only
@@ -103,7 +105,10 @@ pub enum Error {
     /// Errors in signature hash calculations
     SighashError(bitcoin::util::sighash::Error),
     /// Taproot Annex Unsupported
+    #[deprecated(since = "8.0.0", note = "The annex is supported by the interpreter")]
     TapAnnexUnsupported,
+    /// Taproot leaf version other than Tapscript
+    TapLeafVersionUnsupported(taproot::LeafVersion),
     /// An uncompressed public key was encountered in a context where it is
     /// disallowed (e.g. in a Segwit script or p2wpkh output)
     UncompressedPubkey,
@@ -122,6 +127,7 @@ pub enum Error {
 }
 
 impl fmt::Display for Error {
+    #[allow(deprecated)]
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             Error::AbsoluteLocktimeNotMet(n) => write!(
@@ -163,6 +169,7 @@ impl fmt::Display for Error {
             }
             Error::NonEmptyWitness => f.write_str("legacy spend had nonempty witness"),
             Error::NonEmptyScriptSig => f.write_str("segwit spend had nonempty scriptsig"),
+            Error::NonTaprootScriptPubkey => f.write_str("script pubkey is not a taproot output"),
             Error::Miniscript(ref e) => write!(f, "parse error: {}", e),
             Error::MissingExtraZeroMultiSig => f.write_str("CMS missing extra zero"),
             Error::MultiSigEvaluationError => {
@@ -180,6 +187,9 @@ impl fmt::Display for Error {
             Error::SchnorrSig(ref s) => write!(f, "Schnorr sig error: {}", s),
             Error::SighashError(ref e) => fmt::Display::fmt(e, f),
             Error::TapAnnexUnsupported => f.write_str("Encountered annex element"),
+            Error::TapLeafVersionUnsupported(ver) => {
+                write!(f, "Unsupported taproot leaf version {}", ver.to_consensus())
+            }
             Error::UncompressedPubkey => {
                 f.write_str("uncompressed pubkey in non-legacy descriptor")
             }
@@ -197,7 +207,8 @@ impl fmt::Display for Error {
 
 #[cfg(feature = "std")]
 impl error::Error for Error {
-    fn cause(&self) -> Option<&dyn error::Error> {
+    #[allow(deprecated)]
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
         use self::Error::*;
 
         match self {
@@ -220,6 +231,7 @@ impl error::Error for Error {
             | MultiSigEvaluationError
             | NonEmptyWitness
             | NonEmptyScriptSig
+            | NonTaprootScriptPubkey
             | PubkeyParseError
             | XOnlyPublicKeyParseError
             | PkEvaluationError(_)
@@ -227,6 +239,7 @@ impl error::Error for Error {
             | RelativeLocktimeNotMet(_)
             | ScriptSatisfactionError
             | TapAnnexUnsupported
+            | TapLeafVersionUnsupported(_)
             | UncompressedPubkey
             | UnexpectedStackBoolean
             | UnexpectedStackEnd
@@ -15,12 +15,12 @@
 use bitcoin;
 use bitcoin::blockdata::witness::Witness;
 use bitcoin::hashes::{hash160, sha256, Hash};
-use bitcoin::util::taproot::{ControlBlock, TAPROOT_ANNEX_PREFIX};
+use bitcoin::util::taproot::{ControlBlock, LeafVersion, TAPROOT_ANNEX_PREFIX};
 
 use super::{stack, BitcoinKey, Error, Stack, TypedHash160};
 use crate::miniscript::context::{NoChecks, ScriptContext};
 use crate::prelude::*;
-use crate::{BareCtx, Legacy, Miniscript, MiniscriptKey, Segwitv0, Tap};
+use crate::{BareCtx, Legacy, Miniscript, MiniscriptKey, Segwitv0, Tap, Translator};
 
 /// Attempts to parse a slice as a Bitcoin public key, checking compressedness
 /// if asked to, but otherwise dropping it
@@ -98,6 +98,68 @@ pub(super) enum Inner {
     Script(Miniscript<super::BitcoinKey, NoChecks>, ScriptType),
 }
 
+/// Parses an `Inner` for a taproot script spend of `tap_script` from the output
+/// `spk`, checking that `ctrl_blk` proves `tap_script` is committed to by `spk`.
+pub(super) fn from_tapleaf(
+    spk: &bitcoin::Script,
+    ctrl_blk: &ControlBlock,
+    tap_script: &bitcoin::Script,
+) -> Result<Inner, Error> {
+    if !spk.is_v1_p2tr() {
+        return Err(Error::NonTaprootScriptPubkey);
+    }
+    let output_key = bitcoin::XOnlyPublicKey::from_slice(&spk[2..])
+        .map_err(|_| Error::XOnlyPublicKeyParseError)?;
+    if ctrl_blk.leaf_version != LeafVersion::TapScript {
+        return Err(Error::TapLeafVersionUnsupported(ctrl_blk.leaf_version));
+    }
+    let ms = Miniscript::<bitcoin::XOnlyPublicKey, Tap>::parse_insane(tap_script)?;
+    // Creating new contexts is cheap
+    let secp = bitcoin::secp256k1::Secp256k1::verification_only();
+    if ctrl_blk.verify_taproot_commitment(&secp, output_key, tap_script) {
+        Ok(Inner::Script(ms.to_no_checks_ms(), ScriptType::Tr))
+    } else {
+        Err(Error::ControlBlockVerificationError)
+    }
+}
+
+/// The annex of a taproot spend of `spk` with `witness`, i.e. its last
+/// element when the witness has at least two elements and the last one
+/// starts with the annex prefix
+pub(super) fn taproot_annex<'txin>(
+    spk: &bitcoin::Script,
+    witness: &'txin Witness,
+) -> Option<&'txin [u8]> {
+    if !spk.is_v1_p2tr() || witness.len() < 2 {
+        return None;
+    }
+    witness
+        .last()
+        .filter(|last| last.first() == Some(&TAPROOT_ANNEX_PREFIX))
+}
+
+/// The leaf version of the control block of a taproot script spend of `spk`
+/// with `witness`, i.e. its last element besides the annex
+pub(super) fn taproot_leaf_version(
+    spk: &bitcoin::Script,
+    witness: &Witness,
+) -> Option<LeafVersion> {
+    if !spk.is_v1_p2tr() {
+        return None;
+    }
+    let n_elems = match taproot_annex(spk, witness) {
+        Some(_) => witness.len() - 1,
+        None => witness.len(),
+    };
+    if n_elems < 2 {
+        return None;
+    }
+    let ctrl_blk = witness.iter().nth(n_elems - 1)?;
+    ControlBlock::from_slice(ctrl_blk)
+        .ok()
+        .map(|ctrl_blk| ctrl_blk.leaf_version)
+}
+
 // The `Script` returned by this method is always generated/cloned ... when
 // rust-bitcoin is updated to use a copy-on-write internal representation we
 // should revisit this and return references to the actual txdata wherever
@@ -208,17 +270,10 @@ pub(super) fn from_txdata<'txin>(
         } else {
             let output_key = bitcoin::XOnlyPublicKey::from_slice(&spk[2..])
                 .map_err(|_| Error::XOnlyPublicKeyParseError)?;
-            let has_annex = wit_stack
-                .last()
-                .and_then(|x| x.as_push().ok())
-                .map(|x| !x.is_empty() && x[0] == TAPROOT_ANNEX_PREFIX)
-                .unwrap_or(false);
-            let has_annex = has_annex && (wit_stack.len() >= 2);
-            if has_annex {
-                // Annex is non-standard, bitcoin consensus rules ignore it.
-                // Our sighash structure and signature verification
-                // does not support annex, return error
-                return Err(Error::TapAnnexUnsupported);
+            if taproot_annex(spk, witness).is_some() {
+                // The annex is only committed to by signatures, which the
+                // interpreter verifies using `taproot_annex` itself
+                wit_stack.pop();
             }
             match wit_stack.len() {
                 0 => Err(Error::UnexpectedStackEnd),
@@ -373,25 +428,45 @@ pub(super) trait ToNoChecks {
     fn to_no_checks_ms(&self) -> Miniscript<BitcoinKey, NoChecks>;
 }
 
+struct TranslateFullPk;
+
+impl Translator<bitcoin::PublicKey, BitcoinKey, ()> for TranslateFullPk {
+    fn pk(&mut self, pk: &bitcoin::PublicKey) -> Result<BitcoinKey, ()> {
+        Ok(BitcoinKey::Fullkey(*pk))
+    }
+
+    fn pkh(&mut self, pkh: &hash160::Hash) -> Result<TypedHash160, ()> {
+        Ok(TypedHash160::FullKey(*pkh))
+    }
+
+    translate_hash_clone!(bitcoin::PublicKey, BitcoinKey, ());
+}
+
+struct TranslateXOnlyPk;
+
+impl Translator<bitcoin::XOnlyPublicKey, BitcoinKey, ()> for TranslateXOnlyPk {
+    fn pk(&mut self, pk: &bitcoin::XOnlyPublicKey) -> Result<BitcoinKey, ()> {
+        Ok(BitcoinKey::XOnlyPublicKey(*pk))
+    }
+
+    fn pkh(&mut self, pkh: &hash160::Hash) -> Result<TypedHash160, ()> {
+        Ok(TypedHash160::XonlyKey(*pkh))
+    }
+
+    translate_hash_clone!(bitcoin::XOnlyPublicKey, BitcoinKey, ());
+}
+
 impl<Ctx: ScriptContext> ToNoChecks for Miniscript<bitcoin::PublicKey, Ctx> {
     fn to_no_checks_ms(&self) -> Miniscript<BitcoinKey, NoChecks> {
-        // specify the () error type as this cannot error
-        self.real_translate_pk::<_, _, _, (), _>(
-            &mut |pk| Ok(BitcoinKey::Fullkey(*pk)),
-            &mut |pkh| Ok(TypedHash160::FullKey(*pkh)),
-        )
-        .expect("Translation should succeed")
+        self.real_translate_pk(&mut TranslateFullPk)
+            .expect("Translation should succeed")
     }
 }
 
 impl<Ctx: ScriptContext> ToNoChecks for Miniscript<bitcoin::XOnlyPublicKey, Ctx> {
     fn to_no_checks_ms(&self) -> Miniscript<BitcoinKey, NoChecks> {
-        // specify the () error type as this cannot error
-        self.real_translate_pk::<_, _, _, (), _>(
-            &mut |xpk| Ok(BitcoinKey::XOnlyPublicKey(*xpk)),
-            &mut |pkh| Ok(TypedHash160::XonlyKey(*pkh)),
-        )
-        .expect("Translation should succeed")
+        self.real_translate_pk(&mut TranslateXOnlyPk)
+            .expect("Translation should succeed")
     }
 }
 
@@ -24,13 +24,13 @@ use core::str::FromStr;
 
 use bitcoin::blockdata::witness::Witness;
 use bitcoin::hashes::{hash160, ripemd160, sha256, sha256d};
-use bitcoin::util::{sighash, taproot};
+use bitcoin::util::{bip32, psbt, sighash, taproot};
 use bitcoin::{self, secp256k1, TxOut};
 
 use crate::miniscript::context::NoChecks;
 use crate::miniscript::ScriptContext;
 use crate::prelude::*;
-use crate::{Descriptor, Miniscript, Terminal, ToPublicKey};
+use crate::{hash256, Descriptor, Miniscript, Terminal, ToPublicKey};
 
 mod error;
 mod inner;
@@ -48,6 +48,10 @@ pub struct Interpreter<'txin> {
     /// For non-Taproot spends, the scriptCode; for Taproot script-spends, this
     /// is the leaf script; for key-spends it is `None`.
     script_code: Option<bitcoin::Script>,
+    /// For Taproot spends, the annex of the witness if it has one
+    annex: Option<&'txin [u8]>,
+    /// For Taproot script-spends, the leaf version of the control block
+    leaf_version: Option<taproot::LeafVersion>,
     age: u32,
     height: u32,
 }
@@ -149,6 +153,10 @@ impl TypedHash160 {
 
 impl MiniscriptKey for BitcoinKey {
     type Hash = TypedHash160;
+    type Sha256 = sha256::Hash;
+    type Hash256 = hash256::Hash;
+    type Ripemd160 = ripemd160::Hash;
+    type Hash160 = hash160::Hash;
 
     fn to_pubkeyhash(&self) -> Self::Hash {
         match self {
@@ -177,11 +185,39 @@ impl<'txin> Interpreter<'txin> {
             inner,
             stack,
             script_code,
+            annex: inner::taproot_annex(spk, witness),
+            leaf_version: inner::taproot_leaf_version(spk, witness),
             age,
             height,
         })
     }
 
+    /// Constructs an interpreter for the taproot script spend of `leaf_script`
+    /// from the output `spk`, without any witness.
+    ///
+    /// This checks that `spk` is a taproot output, that `control_block` proves
+    /// `leaf_script` is committed to in it and that `leaf_script` is a Tapscript
+    /// Miniscript. Since there is no witness, iterating over the satisfied
+    /// constraints of the returned interpreter will fail; it is meant for
+    /// validating a leaf and control block pair in isolation, e.g. with
+    /// [`Interpreter::inferred_descriptor_string`].
+    pub fn from_tapleaf(
+        spk: &bitcoin::Script,
+        control_block: &taproot::ControlBlock,
+        leaf_script: &bitcoin::Script,
+    ) -> Result<Interpreter<'static>, Error> {
+        let inner = inner::from_tapleaf(spk, control_block, leaf_script)?;
+        Ok(Interpreter {
+            inner,
+            stack: Stack::from(vec![]),
+            script_code: Some(leaf_script.clone()),
+            annex: None,
+            leaf_version: Some(control_block.leaf_version),
+            age: 0,
+            height: 0,
+        })
+    }
+
     /// Same as [`Interpreter::iter`], but allows for a custom verification function.
     /// See [Self::iter_assume_sigs] for a simpler API without information about Prevouts
     /// but skips the signature verification
@@ -211,6 +247,9 @@ impl<'txin> Interpreter<'txin> {
             age: self.age,
             height: self.height,
             has_errored: false,
+            trace: None,
+            leaf_hash: self.leaf_hash(),
+            annex: self.annex,
         }
     }
 
@@ -270,21 +309,27 @@ impl<'txin> Interpreter<'txin> {
                 success.unwrap_or(false) // unwrap_or checks for errors, while success would have checksig results
             }
             KeySigPair::Schnorr(xpk, schnorr_sig) => {
+                let annex = self.annex.map(|annex| {
+                    sighash::Annex::new(annex).expect("Annex starts with the annex prefix")
+                });
                 let sighash_msg = if self.is_taproot_v1_key_spend() {
-                    cache.taproot_key_spend_signature_hash(input_idx, prevouts, schnorr_sig.hash_ty)
+                    cache.taproot_signature_hash(
+                        input_idx,
+                        prevouts,
+                        annex,
+                        None,
+                        schnorr_sig.hash_ty,
+                    )
                 } else if self.is_taproot_v1_script_spend() {
-                    let tap_script = self.script_code.as_ref().expect(
+                    let leaf_hash = self.leaf_hash().expect(
                         "Internal Hack: Saving leaf script instead\
                         of script code for script spend",
                     );
-                    let leaf_hash = taproot::TapLeafHash::from_script(
-                        tap_script,
-                        taproot::LeafVersion::TapScript,
-                    );
-                    cache.taproot_script_spend_signature_hash(
+                    cache.taproot_signature_hash(
                         input_idx,
                         prevouts,
-                        leaf_hash,
+                        annex,
+                        Some((leaf_hash, 0xFFFFFFFF)),
                         schnorr_sig.hash_ty,
                     )
                 } else {
@@ -420,6 +465,25 @@ impl<'txin> Interpreter<'txin> {
         }
     }
 
+    /// The annex of the witness, for taproot spends with one. Signatures of
+    /// the spend commit to it, but it is otherwise ignored.
+    pub fn annex(&self) -> Option<&'txin [u8]> {
+        self.annex
+    }
+
+    /// The hash of the executed leaf script, with the leaf version of the
+    /// control block, for taproot script spends
+    pub fn leaf_hash(&self) -> Option<taproot::TapLeafHash> {
+        if self.is_taproot_v1_script_spend() {
+            match (self.script_code.as_ref(), self.leaf_version) {
+                (Some(script), Some(ver)) => Some(taproot::TapLeafHash::from_script(script, ver)),
+                _ => None,
+            }
+        } else {
+            None
+        }
+    }
+
     /// Whether this is a taproot script spend
     pub fn is_taproot_v1_script_spend(&self) -> bool {
         match self.inner {
@@ -496,6 +560,38 @@ pub enum SatisfiedConstraint {
     },
 }
 
+/// A [`SatisfiedConstraint`] along with the spend path it was satisfied
+/// through, yielded by an [`AnnotatedIter`]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct AnnotatedConstraint {
+    /// The satisfied constraint
+    pub constraint: SatisfiedConstraint,
+    /// For taproot script spends, the hash of the executed leaf
+    pub leaf_hash: Option<taproot::TapLeafHash>,
+    /// For signatures, the origin of the signing key, if the psbt input given
+    /// to [`Iter::annotated`] records it
+    pub key_origin: Option<bip32::KeySource>,
+}
+
+/// A single step of the interpreter's evaluation, recorded by an [`Iter`]
+/// created with [`Iter::with_trace`].
+///
+/// The interpreter works on Miniscript fragments rather than individual
+/// opcodes, so each step corresponds to one visit of a fragment. Fragments
+/// with children are visited again after each child has been evaluated.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TraceStep {
+    /// The fragment being evaluated
+    pub fragment: String,
+    /// The number of children of `fragment` evaluated so far
+    pub n_evaluated: usize,
+    /// The stack before the step, with satisfied and dissatisfied results
+    /// represented as `vec![1]` and `vec![]`
+    pub stack_before: Vec<Vec<u8>>,
+    /// The stack after the step, in the same representation as `stack_before`
+    pub stack_after: Vec<Vec<u8>>,
+}
+
 ///This is used by the interpreter to know which evaluation state a AstemElem is.
 ///This is required because whenever a same node(for eg. OrB) appears on the stack, we don't
 ///know if the left child has been evaluated or not. And based on the result on
@@ -530,6 +626,55 @@ pub struct Iter<'intp, 'txin: 'intp> {
     age: u32,
     height: u32,
     has_errored: bool,
+    trace: Option<Vec<TraceStep>>,
+    leaf_hash: Option<taproot::TapLeafHash>,
+    annex: Option<&'txin [u8]>,
+}
+
+/// Iterator over the satisfied constraints of an [`Iter`], annotated with
+/// the spend path they were satisfied through. Created by [`Iter::annotated`].
+pub struct AnnotatedIter<'intp, 'txin: 'intp, 'psbt> {
+    iter: Iter<'intp, 'txin>,
+    psbt_input: Option<&'psbt psbt::Input>,
+}
+
+impl<'intp, 'txin: 'intp, 'psbt> AnnotatedIter<'intp, 'txin, 'psbt> {
+    fn key_origin(&self, key_sig: &KeySigPair) -> Option<bip32::KeySource> {
+        let input = self.psbt_input?;
+        match *key_sig {
+            KeySigPair::Ecdsa(pk, _) => input.bip32_derivation.get(&pk.inner).cloned(),
+            KeySigPair::Schnorr(xpk, _) => input
+                .tap_key_origins
+                .get(&xpk)
+                .map(|(_, origin)| origin.clone()),
+        }
+    }
+}
+
+impl<'intp, 'txin: 'intp, 'psbt> Iterator for AnnotatedIter<'intp, 'txin, 'psbt>
+where
+    NoChecks: ScriptContext,
+{
+    type Item = Result<AnnotatedConstraint, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let constraint = match self.iter.next()? {
+            Ok(constraint) => constraint,
+            Err(e) => return Some(Err(e)),
+        };
+        let key_origin = match constraint {
+            SatisfiedConstraint::PublicKey { ref key_sig }
+            | SatisfiedConstraint::PublicKeyHash { ref key_sig, .. } => self.key_origin(key_sig),
+            SatisfiedConstraint::HashLock { .. }
+            | SatisfiedConstraint::RelativeTimelock { .. }
+            | SatisfiedConstraint::AbsoluteTimelock { .. } => None,
+        };
+        Some(Ok(AnnotatedConstraint {
+            constraint,
+            leaf_hash: self.iter.leaf_hash,
+            key_origin,
+        }))
+    }
 }
 
 ///Iterator for Iter
@@ -557,6 +702,46 @@ impl<'intp, 'txin: 'intp> Iter<'intp, 'txin>
 where
     NoChecks: ScriptContext,
 {
+    /// Record a [`TraceStep`] for every fragment evaluated by this iterator.
+    ///
+    /// The trace can be inspected with [`Iter::trace`], usually after the
+    /// iterator has returned an error, to find which fragment failed.
+    pub fn with_trace(mut self) -> Self {
+        self.trace = Some(vec![]);
+        self
+    }
+
+    /// The steps evaluated so far. Empty unless the iterator was created
+    /// with [`Iter::with_trace`].
+    pub fn trace(&self) -> slice::Iter<'_, TraceStep> {
+        match self.trace {
+            Some(ref trace) => trace.iter(),
+            None => [].iter(),
+        }
+    }
+
+    /// The annex of the witness of the spend, for taproot spends with one
+    pub fn annex(&self) -> Option<&'txin [u8]> {
+        self.annex
+    }
+
+    /// Annotate every satisfied constraint with the leaf hash of a taproot
+    /// script spend and, given the psbt input of the spend, the origin of the
+    /// signing keys.
+    ///
+    /// Key origins are looked up in the `bip32_derivation` and
+    /// `tap_key_origins` fields of `psbt_input`, which finalization clears, so
+    /// it should be the input as it was before being finalized.
+    pub fn annotated<'psbt>(
+        self,
+        psbt_input: Option<&'psbt psbt::Input>,
+    ) -> AnnotatedIter<'intp, 'txin, 'psbt> {
+        AnnotatedIter {
+            iter: self,
+            psbt_input,
+        }
+    }
+
     /// Helper function to push a NodeEvaluationState on state stack
     fn push_evaluation_state(
         &mut self,
@@ -571,402 +756,416 @@ where
         })
     }
 
-    /// Helper function to step the iterator
-    fn iter_next(&mut self) -> Option<Result<SatisfiedConstraint, Error>> {
-        while let Some(node_state) = self.state.pop() {
-            //non-empty stack
-            match node_state.node.node {
-                Terminal::True => {
-                    debug_assert_eq!(node_state.n_evaluated, 0);
-                    debug_assert_eq!(node_state.n_satisfied, 0);
-                    self.stack.push(stack::Element::Satisfied);
+    /// Helper function to evaluate a single node state popped from the state
+    /// stack. Returns `None` if the iterator should continue with the next state.
+    fn step(
+        &mut self,
+        node_state: NodeEvaluationState<'intp>,
+    ) -> Option<Result<SatisfiedConstraint, Error>> {
+        match node_state.node.node {
+            Terminal::True => {
+                debug_assert_eq!(node_state.n_evaluated, 0);
+                debug_assert_eq!(node_state.n_satisfied, 0);
+                self.stack.push(stack::Element::Satisfied);
+            }
+            Terminal::False => {
+                debug_assert_eq!(node_state.n_evaluated, 0);
+                debug_assert_eq!(node_state.n_satisfied, 0);
+                self.stack.push(stack::Element::Dissatisfied);
+            }
+            Terminal::PkK(ref pk) => {
+                debug_assert_eq!(node_state.n_evaluated, 0);
+                debug_assert_eq!(node_state.n_satisfied, 0);
+                let res = self.stack.evaluate_pk(&mut self.verify_sig, pk);
+                if res.is_some() {
+                    return res;
                 }
-                Terminal::False => {
-                    debug_assert_eq!(node_state.n_evaluated, 0);
-                    debug_assert_eq!(node_state.n_satisfied, 0);
-                    self.stack.push(stack::Element::Dissatisfied);
+            }
+            Terminal::PkH(ref pkh) => {
+                debug_assert_eq!(node_state.n_evaluated, 0);
+                debug_assert_eq!(node_state.n_satisfied, 0);
+                let res = self.stack.evaluate_pkh(&mut self.verify_sig, pkh);
+                if res.is_some() {
+                    return res;
                 }
-                Terminal::PkK(ref pk) => {
-                    debug_assert_eq!(node_state.n_evaluated, 0);
-                    debug_assert_eq!(node_state.n_satisfied, 0);
-                    let res = self.stack.evaluate_pk(&mut self.verify_sig, pk);
-                    if res.is_some() {
-                        return res;
-                    }
+            }
+            Terminal::After(ref n) => {
+                debug_assert_eq!(node_state.n_evaluated, 0);
+                debug_assert_eq!(node_state.n_satisfied, 0);
+                let res = self.stack.evaluate_after(n, self.age);
+                if res.is_some() {
+                    return res;
                 }
-                Terminal::PkH(ref pkh) => {
-                    debug_assert_eq!(node_state.n_evaluated, 0);
-                    debug_assert_eq!(node_state.n_satisfied, 0);
-                    let res = self.stack.evaluate_pkh(&mut self.verify_sig, pkh);
-                    if res.is_some() {
-                        return res;
-                    }
+            }
+            Terminal::Older(ref n) => {
+                debug_assert_eq!(node_state.n_evaluated, 0);
+                debug_assert_eq!(node_state.n_satisfied, 0);
+                let res = self.stack.evaluate_older(n, self.height);
+                if res.is_some() {
+                    return res;
                 }
-                Terminal::After(ref n) => {
-                    debug_assert_eq!(node_state.n_evaluated, 0);
-                    debug_assert_eq!(node_state.n_satisfied, 0);
-                    let res = self.stack.evaluate_after(n, self.age);
-                    if res.is_some() {
-                        return res;
-                    }
+            }
+            Terminal::Sha256(ref hash) => {
+                debug_assert_eq!(node_state.n_evaluated, 0);
+                debug_assert_eq!(node_state.n_satisfied, 0);
+                let res = self.stack.evaluate_sha256(hash);
+                if res.is_some() {
+                    return res;
                 }
-                Terminal::Older(ref n) => {
-                    debug_assert_eq!(node_state.n_evaluated, 0);
-                    debug_assert_eq!(node_state.n_satisfied, 0);
-                    let res = self.stack.evaluate_older(n, self.height);
-                    if res.is_some() {
-                        return res;
-                    }
+            }
+            Terminal::Hash256(ref hash) => {
+                debug_assert_eq!(node_state.n_evaluated, 0);
+                debug_assert_eq!(node_state.n_satisfied, 0);
+                let res = self.stack.evaluate_hash256(hash);
+                if res.is_some() {
+                    return res;
                 }
-                Terminal::Sha256(ref hash) => {
-                    debug_assert_eq!(node_state.n_evaluated, 0);
-                    debug_assert_eq!(node_state.n_satisfied, 0);
-                    let res = self.stack.evaluate_sha256(hash);
-                    if res.is_some() {
-                        return res;
-                    }
+            }
+            Terminal::Hash160(ref hash) => {
+                debug_assert_eq!(node_state.n_evaluated, 0);
+                debug_assert_eq!(node_state.n_satisfied, 0);
+                let res = self.stack.evaluate_hash160(hash);
+                if res.is_some() {
+                    return res;
                 }
-                Terminal::Hash256(ref hash) => {
-                    debug_assert_eq!(node_state.n_evaluated, 0);
-                    debug_assert_eq!(node_state.n_satisfied, 0);
-                    let res = self.stack.evaluate_hash256(hash);
-                    if res.is_some() {
-                        return res;
-                    }
+            }
+            Terminal::Ripemd160(ref hash) => {
+                debug_assert_eq!(node_state.n_evaluated, 0);
+                debug_assert_eq!(node_state.n_satisfied, 0);
+                let res = self.stack.evaluate_ripemd160(hash);
+                if res.is_some() {
+                    return res;
                 }
-                Terminal::Hash160(ref hash) => {
-                    debug_assert_eq!(node_state.n_evaluated, 0);
-                    debug_assert_eq!(node_state.n_satisfied, 0);
-                    let res = self.stack.evaluate_hash160(hash);
-                    if res.is_some() {
-                        return res;
-                    }
+            }
+            Terminal::Alt(ref sub) | Terminal::Swap(ref sub) | Terminal::Check(ref sub) => {
+                debug_assert_eq!(node_state.n_evaluated, 0);
+                debug_assert_eq!(node_state.n_satisfied, 0);
+                self.push_evaluation_state(sub, 0, 0);
+            }
+            Terminal::DupIf(ref sub) if node_state.n_evaluated == 0 => match self.stack.pop() {
+                Some(stack::Element::Dissatisfied) => {
+                    self.stack.push(stack::Element::Dissatisfied);
+                }
+                Some(stack::Element::Satisfied) => {
+                    self.push_evaluation_state(node_state.node, 1, 1);
+                    self.push_evaluation_state(sub, 0, 0);
                 }
-                Terminal::Ripemd160(ref hash) => {
-                    debug_assert_eq!(node_state.n_evaluated, 0);
-                    debug_assert_eq!(node_state.n_satisfied, 0);
-                    let res = self.stack.evaluate_ripemd160(hash);
-                    if res.is_some() {
-                        return res;
+                Some(stack::Element::Push(_v)) => {
+                    return Some(Err(Error::UnexpectedStackElementPush))
+                }
+                None => return Some(Err(Error::UnexpectedStackEnd)),
+            },
+            Terminal::DupIf(ref _sub) if node_state.n_evaluated == 1 => {
+                self.stack.push(stack::Element::Satisfied);
+            }
+            Terminal::ZeroNotEqual(ref sub) | Terminal::Verify(ref sub)
+                if node_state.n_evaluated == 0 =>
+            {
+                self.push_evaluation_state(node_state.node, 1, 0);
+                self.push_evaluation_state(sub, 0, 0);
+            }
+            Terminal::Verify(ref _sub) if node_state.n_evaluated == 1 => match self.stack.pop() {
+                Some(stack::Element::Satisfied) => (),
+                Some(_) => return Some(Err(Error::VerifyFailed)),
+                None => return Some(Err(Error::UnexpectedStackEnd)),
+            },
+            Terminal::ZeroNotEqual(ref _sub) if node_state.n_evaluated == 1 => {
+                match self.stack.pop() {
+                    Some(stack::Element::Dissatisfied) => {
+                        self.stack.push(stack::Element::Dissatisfied)
                     }
+                    Some(_) => self.stack.push(stack::Element::Satisfied),
+                    None => return Some(Err(Error::UnexpectedStackEnd)),
                 }
-                Terminal::Alt(ref sub) | Terminal::Swap(ref sub) | Terminal::Check(ref sub) => {
-                    debug_assert_eq!(node_state.n_evaluated, 0);
-                    debug_assert_eq!(node_state.n_satisfied, 0);
-                    self.push_evaluation_state(sub, 0, 0);
+            }
+            Terminal::NonZero(ref sub) => {
+                debug_assert_eq!(node_state.n_evaluated, 0);
+                debug_assert_eq!(node_state.n_satisfied, 0);
+                match self.stack.last() {
+                    Some(&stack::Element::Dissatisfied) => (),
+                    Some(_) => self.push_evaluation_state(sub, 0, 0),
+                    None => return Some(Err(Error::UnexpectedStackEnd)),
                 }
-                Terminal::DupIf(ref sub) if node_state.n_evaluated == 0 => match self.stack.pop() {
+            }
+            Terminal::AndV(ref left, ref right) => {
+                debug_assert_eq!(node_state.n_evaluated, 0);
+                debug_assert_eq!(node_state.n_satisfied, 0);
+                self.push_evaluation_state(right, 0, 0);
+                self.push_evaluation_state(left, 0, 0);
+            }
+            Terminal::OrB(ref left, ref _right) | Terminal::AndB(ref left, ref _right)
+                if node_state.n_evaluated == 0 =>
+            {
+                self.push_evaluation_state(node_state.node, 1, 0);
+                self.push_evaluation_state(left, 0, 0);
+            }
+            Terminal::OrB(ref _left, ref right) | Terminal::AndB(ref _left, ref right)
+                if node_state.n_evaluated == 1 =>
+            {
+                match self.stack.pop() {
                     Some(stack::Element::Dissatisfied) => {
-                        self.stack.push(stack::Element::Dissatisfied);
+                        self.push_evaluation_state(node_state.node, 2, 0);
+                        self.push_evaluation_state(right, 0, 0);
                     }
                     Some(stack::Element::Satisfied) => {
-                        self.push_evaluation_state(node_state.node, 1, 1);
-                        self.push_evaluation_state(sub, 0, 0);
+                        self.push_evaluation_state(node_state.node, 2, 1);
+                        self.push_evaluation_state(right, 0, 0);
                     }
                     Some(stack::Element::Push(_v)) => {
                         return Some(Err(Error::UnexpectedStackElementPush))
                     }
                     None => return Some(Err(Error::UnexpectedStackEnd)),
-                },
-                Terminal::DupIf(ref _sub) if node_state.n_evaluated == 1 => {
-                    self.stack.push(stack::Element::Satisfied);
                 }
-                Terminal::ZeroNotEqual(ref sub) | Terminal::Verify(ref sub)
-                    if node_state.n_evaluated == 0 =>
-                {
-                    self.push_evaluation_state(node_state.node, 1, 0);
-                    self.push_evaluation_state(sub, 0, 0);
-                }
-                Terminal::Verify(ref _sub) if node_state.n_evaluated == 1 => {
-                    match self.stack.pop() {
-                        Some(stack::Element::Satisfied) => (),
-                        Some(_) => return Some(Err(Error::VerifyFailed)),
-                        None => return Some(Err(Error::UnexpectedStackEnd)),
+            }
+            Terminal::AndB(ref _left, ref _right) if node_state.n_evaluated == 2 => {
+                match self.stack.pop() {
+                    Some(stack::Element::Satisfied) if node_state.n_satisfied == 1 => {
+                        self.stack.push(stack::Element::Satisfied)
                     }
+                    Some(_) => self.stack.push(stack::Element::Dissatisfied),
+                    None => return Some(Err(Error::UnexpectedStackEnd)),
                 }
-                Terminal::ZeroNotEqual(ref _sub) if node_state.n_evaluated == 1 => {
-                    match self.stack.pop() {
-                        Some(stack::Element::Dissatisfied) => {
-                            self.stack.push(stack::Element::Dissatisfied)
-                        }
-                        Some(_) => self.stack.push(stack::Element::Satisfied),
-                        None => return Some(Err(Error::UnexpectedStackEnd)),
+            }
+            Terminal::AndOr(ref left, ref _right, _)
+            | Terminal::OrC(ref left, ref _right)
+            | Terminal::OrD(ref left, ref _right)
+                if node_state.n_evaluated == 0 =>
+            {
+                self.push_evaluation_state(node_state.node, 1, 0);
+                self.push_evaluation_state(left, 0, 0);
+            }
+            Terminal::OrB(ref _left, ref _right) if node_state.n_evaluated == 2 => {
+                match self.stack.pop() {
+                    Some(stack::Element::Dissatisfied) if node_state.n_satisfied == 0 => {
+                        self.stack.push(stack::Element::Dissatisfied)
                     }
-                }
-                Terminal::NonZero(ref sub) => {
-                    debug_assert_eq!(node_state.n_evaluated, 0);
-                    debug_assert_eq!(node_state.n_satisfied, 0);
-                    match self.stack.last() {
-                        Some(&stack::Element::Dissatisfied) => (),
-                        Some(_) => self.push_evaluation_state(sub, 0, 0),
-                        None => return Some(Err(Error::UnexpectedStackEnd)),
+                    Some(_) => {
+                        self.stack.push(stack::Element::Satisfied);
                     }
+                    None => return Some(Err(Error::UnexpectedStackEnd)),
                 }
-                Terminal::AndV(ref left, ref right) => {
-                    debug_assert_eq!(node_state.n_evaluated, 0);
-                    debug_assert_eq!(node_state.n_satisfied, 0);
-                    self.push_evaluation_state(right, 0, 0);
-                    self.push_evaluation_state(left, 0, 0);
-                }
-                Terminal::OrB(ref left, ref _right) | Terminal::AndB(ref left, ref _right)
-                    if node_state.n_evaluated == 0 =>
-                {
-                    self.push_evaluation_state(node_state.node, 1, 0);
-                    self.push_evaluation_state(left, 0, 0);
-                }
-                Terminal::OrB(ref _left, ref right) | Terminal::AndB(ref _left, ref right)
-                    if node_state.n_evaluated == 1 =>
-                {
-                    match self.stack.pop() {
-                        Some(stack::Element::Dissatisfied) => {
-                            self.push_evaluation_state(node_state.node, 2, 0);
-                            self.push_evaluation_state(right, 0, 0);
-                        }
-                        Some(stack::Element::Satisfied) => {
-                            self.push_evaluation_state(node_state.node, 2, 1);
-                            self.push_evaluation_state(right, 0, 0);
-                        }
-                        Some(stack::Element::Push(_v)) => {
-                            return Some(Err(Error::UnexpectedStackElementPush))
-                        }
-                        None => return Some(Err(Error::UnexpectedStackEnd)),
+            }
+            Terminal::OrC(ref _left, ref right) if node_state.n_evaluated == 1 => {
+                match self.stack.pop() {
+                    Some(stack::Element::Satisfied) => (),
+                    Some(stack::Element::Dissatisfied) => self.push_evaluation_state(right, 0, 0),
+                    Some(stack::Element::Push(_v)) => {
+                        return Some(Err(Error::UnexpectedStackElementPush))
                     }
+                    None => return Some(Err(Error::UnexpectedStackEnd)),
                 }
-                Terminal::AndB(ref _left, ref _right) if node_state.n_evaluated == 2 => {
-                    match self.stack.pop() {
-                        Some(stack::Element::Satisfied) if node_state.n_satisfied == 1 => {
-                            self.stack.push(stack::Element::Satisfied)
-                        }
-                        Some(_) => self.stack.push(stack::Element::Dissatisfied),
-                        None => return Some(Err(Error::UnexpectedStackEnd)),
+            }
+            Terminal::OrD(ref _left, ref right) if node_state.n_evaluated == 1 => {
+                match self.stack.pop() {
+                    Some(stack::Element::Satisfied) => self.stack.push(stack::Element::Satisfied),
+                    Some(stack::Element::Dissatisfied) => self.push_evaluation_state(right, 0, 0),
+                    Some(stack::Element::Push(_v)) => {
+                        return Some(Err(Error::UnexpectedStackElementPush))
                     }
+                    None => return Some(Err(Error::UnexpectedStackEnd)),
                 }
-                Terminal::AndOr(ref left, ref _right, _)
-                | Terminal::OrC(ref left, ref _right)
-                | Terminal::OrD(ref left, ref _right)
-                    if node_state.n_evaluated == 0 =>
-                {
-                    self.push_evaluation_state(node_state.node, 1, 0);
-                    self.push_evaluation_state(left, 0, 0);
-                }
-                Terminal::OrB(ref _left, ref _right) if node_state.n_evaluated == 2 => {
-                    match self.stack.pop() {
-                        Some(stack::Element::Dissatisfied) if node_state.n_satisfied == 0 => {
-                            self.stack.push(stack::Element::Dissatisfied)
-                        }
-                        Some(_) => {
-                            self.stack.push(stack::Element::Satisfied);
-                        }
-                        None => return Some(Err(Error::UnexpectedStackEnd)),
+            }
+            Terminal::AndOr(_, ref left, ref right) | Terminal::OrI(ref left, ref right) => {
+                match self.stack.pop() {
+                    Some(stack::Element::Satisfied) => self.push_evaluation_state(left, 0, 0),
+                    Some(stack::Element::Dissatisfied) => self.push_evaluation_state(right, 0, 0),
+                    Some(stack::Element::Push(_v)) => {
+                        return Some(Err(Error::UnexpectedStackElementPush))
                     }
+                    None => return Some(Err(Error::UnexpectedStackEnd)),
                 }
-                Terminal::OrC(ref _left, ref right) if node_state.n_evaluated == 1 => {
-                    match self.stack.pop() {
-                        Some(stack::Element::Satisfied) => (),
-                        Some(stack::Element::Dissatisfied) => {
-                            self.push_evaluation_state(right, 0, 0)
-                        }
-                        Some(stack::Element::Push(_v)) => {
-                            return Some(Err(Error::UnexpectedStackElementPush))
-                        }
-                        None => return Some(Err(Error::UnexpectedStackEnd)),
+            }
+            Terminal::Thresh(ref _k, ref subs) if node_state.n_evaluated == 0 => {
+                self.push_evaluation_state(node_state.node, 1, 0);
+                self.push_evaluation_state(&subs[0], 0, 0);
+            }
+            Terminal::Thresh(k, ref subs) if node_state.n_evaluated == subs.len() => {
+                match self.stack.pop() {
+                    Some(stack::Element::Dissatisfied) if node_state.n_satisfied == k => {
+                        self.stack.push(stack::Element::Satisfied)
                     }
-                }
-                Terminal::OrD(ref _left, ref right) if node_state.n_evaluated == 1 => {
-                    match self.stack.pop() {
-                        Some(stack::Element::Satisfied) => {
-                            self.stack.push(stack::Element::Satisfied)
-                        }
-                        Some(stack::Element::Dissatisfied) => {
-                            self.push_evaluation_state(right, 0, 0)
-                        }
-                        Some(stack::Element::Push(_v)) => {
-                            return Some(Err(Error::UnexpectedStackElementPush))
-                        }
-                        None => return Some(Err(Error::UnexpectedStackEnd)),
+                    Some(stack::Element::Satisfied) if node_state.n_satisfied == k - 1 => {
+                        self.stack.push(stack::Element::Satisfied)
                     }
-                }
-                Terminal::AndOr(_, ref left, ref right) | Terminal::OrI(ref left, ref right) => {
-                    match self.stack.pop() {
-                        Some(stack::Element::Satisfied) => self.push_evaluation_state(left, 0, 0),
-                        Some(stack::Element::Dissatisfied) => {
-                            self.push_evaluation_state(right, 0, 0)
-                        }
-                        Some(stack::Element::Push(_v)) => {
-                            return Some(Err(Error::UnexpectedStackElementPush))
-                        }
-                        None => return Some(Err(Error::UnexpectedStackEnd)),
+                    Some(stack::Element::Satisfied) | Some(stack::Element::Dissatisfied) => {
+                        self.stack.push(stack::Element::Dissatisfied)
                     }
+                    Some(stack::Element::Push(_v)) => {
+                        return Some(Err(Error::UnexpectedStackElementPush))
+                    }
+                    None => return Some(Err(Error::UnexpectedStackEnd)),
                 }
-                Terminal::Thresh(ref _k, ref subs) if node_state.n_evaluated == 0 => {
-                    self.push_evaluation_state(node_state.node, 1, 0);
-                    self.push_evaluation_state(&subs[0], 0, 0);
-                }
-                Terminal::Thresh(k, ref subs) if node_state.n_evaluated == subs.len() => {
-                    match self.stack.pop() {
-                        Some(stack::Element::Dissatisfied) if node_state.n_satisfied == k => {
-                            self.stack.push(stack::Element::Satisfied)
-                        }
-                        Some(stack::Element::Satisfied) if node_state.n_satisfied == k - 1 => {
-                            self.stack.push(stack::Element::Satisfied)
-                        }
-                        Some(stack::Element::Satisfied) | Some(stack::Element::Dissatisfied) => {
-                            self.stack.push(stack::Element::Dissatisfied)
-                        }
-                        Some(stack::Element::Push(_v)) => {
-                            return Some(Err(Error::UnexpectedStackElementPush))
-                        }
-                        None => return Some(Err(Error::UnexpectedStackEnd)),
+            }
+            Terminal::Thresh(ref _k, ref subs) if node_state.n_evaluated != 0 => {
+                match self.stack.pop() {
+                    Some(stack::Element::Dissatisfied) => {
+                        self.push_evaluation_state(
+                            node_state.node,
+                            node_state.n_evaluated + 1,
+                            node_state.n_satisfied,
+                        );
+                        self.push_evaluation_state(&subs[node_state.n_evaluated], 0, 0);
+                    }
+                    Some(stack::Element::Satisfied) => {
+                        self.push_evaluation_state(
+                            node_state.node,
+                            node_state.n_evaluated + 1,
+                            node_state.n_satisfied + 1,
+                        );
+                        self.push_evaluation_state(&subs[node_state.n_evaluated], 0, 0);
                     }
+                    Some(stack::Element::Push(_v)) => {
+                        return Some(Err(Error::UnexpectedStackElementPush))
+                    }
+                    None => return Some(Err(Error::UnexpectedStackEnd)),
                 }
-                Terminal::Thresh(ref _k, ref subs) if node_state.n_evaluated != 0 => {
-                    match self.stack.pop() {
-                        Some(stack::Element::Dissatisfied) => {
+            }
+            Terminal::MultiA(k, ref subs) => {
+                if node_state.n_evaluated == subs.len() {
+                    if node_state.n_satisfied == k {
+                        self.stack.push(stack::Element::Satisfied);
+                    } else {
+                        self.stack.push(stack::Element::Dissatisfied);
+                    }
+                } else {
+                    // evaluate each key with as a pk
+                    // note that evaluate_pk will error on non-empty incorrect sigs
+                    // push 1 on satisfied sigs and push 0 on empty sigs
+                    match self
+                        .stack
+                        .evaluate_pk(&mut self.verify_sig, &subs[node_state.n_evaluated])
+                    {
+                        Some(Ok(x)) => {
                             self.push_evaluation_state(
                                 node_state.node,
                                 node_state.n_evaluated + 1,
-                                node_state.n_satisfied,
+                                node_state.n_satisfied + 1,
                             );
-                            self.push_evaluation_state(&subs[node_state.n_evaluated], 0, 0);
+                            match self.stack.pop() {
+                                Some(..) => return Some(Ok(x)),
+                                None => return Some(Err(Error::UnexpectedStackEnd)),
+                            }
                         }
-                        Some(stack::Element::Satisfied) => {
+                        None => {
                             self.push_evaluation_state(
                                 node_state.node,
                                 node_state.n_evaluated + 1,
-                                node_state.n_satisfied + 1,
+                                node_state.n_satisfied,
                             );
-                            self.push_evaluation_state(&subs[node_state.n_evaluated], 0, 0);
-                        }
-                        Some(stack::Element::Push(_v)) => {
-                            return Some(Err(Error::UnexpectedStackElementPush))
+                            match self.stack.pop() {
+                                Some(..) => {} // not-satisfied, look for next key
+                                None => return Some(Err(Error::UnexpectedStackEnd)),
+                            }
                         }
-                        None => return Some(Err(Error::UnexpectedStackEnd)),
+                        x => return x, //forward errors as is
                     }
                 }
-                Terminal::MultiA(k, ref subs) => {
-                    if node_state.n_evaluated == subs.len() {
-                        if node_state.n_satisfied == k {
-                            self.stack.push(stack::Element::Satisfied);
-                        } else {
-                            self.stack.push(stack::Element::Dissatisfied);
+            }
+            Terminal::Multi(ref k, ref subs) if node_state.n_evaluated == 0 => {
+                let len = self.stack.len();
+                if len < k + 1 {
+                    return Some(Err(Error::InsufficientSignaturesMultiSig));
+                } else {
+                    //Non-sat case. If the first sig is empty, others k elements must
+                    //be empty.
+                    match self.stack.last() {
+                        Some(&stack::Element::Dissatisfied) => {
+                            //Remove the extra zero from multi-sig check
+                            let sigs = self.stack.split_off(len - (k + 1));
+                            let nonsat = sigs
+                                .iter()
+                                .map(|sig| *sig == stack::Element::Dissatisfied)
+                                .filter(|empty| *empty)
+                                .count();
+                            if nonsat == *k + 1 {
+                                self.stack.push(stack::Element::Dissatisfied);
+                            } else {
+                                return Some(Err(Error::MissingExtraZeroMultiSig));
+                            }
                         }
-                    } else {
-                        // evaluate each key with as a pk
-                        // note that evaluate_pk will error on non-empty incorrect sigs
-                        // push 1 on satisfied sigs and push 0 on empty sigs
-                        match self
-                            .stack
-                            .evaluate_pk(&mut self.verify_sig, &subs[node_state.n_evaluated])
-                        {
-                            Some(Ok(x)) => {
-                                self.push_evaluation_state(
-                                    node_state.node,
-                                    node_state.n_evaluated + 1,
-                                    node_state.n_satisfied + 1,
-                                );
-                                match self.stack.pop() {
-                                    Some(..) => return Some(Ok(x)),
-                                    None => return Some(Err(Error::UnexpectedStackEnd)),
+                        None => return Some(Err(Error::UnexpectedStackEnd)),
+                        _ => {
+                            match self
+                                .stack
+                                .evaluate_multi(&mut self.verify_sig, &subs[subs.len() - 1])
+                            {
+                                Some(Ok(x)) => {
+                                    self.push_evaluation_state(
+                                        node_state.node,
+                                        node_state.n_evaluated + 1,
+                                        node_state.n_satisfied + 1,
+                                    );
+                                    return Some(Ok(x));
                                 }
-                            }
-                            None => {
-                                self.push_evaluation_state(
+                                None => self.push_evaluation_state(
                                     node_state.node,
                                     node_state.n_evaluated + 1,
                                     node_state.n_satisfied,
-                                );
-                                match self.stack.pop() {
-                                    Some(..) => {} // not-satisfied, look for next key
-                                    None => return Some(Err(Error::UnexpectedStackEnd)),
-                                }
+                                ),
+                                x => return x, //forward errors as is
                             }
-                            x => return x, //forward errors as is
                         }
                     }
                 }
-                Terminal::Multi(ref k, ref subs) if node_state.n_evaluated == 0 => {
-                    let len = self.stack.len();
-                    if len < k + 1 {
-                        return Some(Err(Error::InsufficientSignaturesMultiSig));
+            }
+            Terminal::Multi(k, ref subs) => {
+                if node_state.n_satisfied == k {
+                    //multi-sig bug: Pop extra 0
+                    if let Some(stack::Element::Dissatisfied) = self.stack.pop() {
+                        self.stack.push(stack::Element::Satisfied);
                     } else {
-                        //Non-sat case. If the first sig is empty, others k elements must
-                        //be empty.
-                        match self.stack.last() {
-                            Some(&stack::Element::Dissatisfied) => {
-                                //Remove the extra zero from multi-sig check
-                                let sigs = self.stack.split_off(len - (k + 1));
-                                let nonsat = sigs
-                                    .iter()
-                                    .map(|sig| *sig == stack::Element::Dissatisfied)
-                                    .filter(|empty| *empty)
-                                    .count();
-                                if nonsat == *k + 1 {
-                                    self.stack.push(stack::Element::Dissatisfied);
-                                } else {
-                                    return Some(Err(Error::MissingExtraZeroMultiSig));
-                                }
-                            }
-                            None => return Some(Err(Error::UnexpectedStackEnd)),
-                            _ => {
-                                match self
-                                    .stack
-                                    .evaluate_multi(&mut self.verify_sig, &subs[subs.len() - 1])
-                                {
-                                    Some(Ok(x)) => {
-                                        self.push_evaluation_state(
-                                            node_state.node,
-                                            node_state.n_evaluated + 1,
-                                            node_state.n_satisfied + 1,
-                                        );
-                                        return Some(Ok(x));
-                                    }
-                                    None => self.push_evaluation_state(
-                                        node_state.node,
-                                        node_state.n_evaluated + 1,
-                                        node_state.n_satisfied,
-                                    ),
-                                    x => return x, //forward errors as is
-                                }
-                            }
-                        }
+                        return Some(Err(Error::MissingExtraZeroMultiSig));
                     }
-                }
-                Terminal::Multi(k, ref subs) => {
-                    if node_state.n_satisfied == k {
-                        //multi-sig bug: Pop extra 0
-                        if let Some(stack::Element::Dissatisfied) = self.stack.pop() {
-                            self.stack.push(stack::Element::Satisfied);
-                        } else {
-                            return Some(Err(Error::MissingExtraZeroMultiSig));
-                        }
-                    } else if node_state.n_evaluated == subs.len() {
-                        return Some(Err(Error::MultiSigEvaluationError));
-                    } else {
-                        match self.stack.evaluate_multi(
-                            &mut self.verify_sig,
-                            &subs[subs.len() - node_state.n_evaluated - 1],
-                        ) {
-                            Some(Ok(x)) => {
-                                self.push_evaluation_state(
-                                    node_state.node,
-                                    node_state.n_evaluated + 1,
-                                    node_state.n_satisfied + 1,
-                                );
-                                return Some(Ok(x));
-                            }
-                            None => self.push_evaluation_state(
+                } else if node_state.n_evaluated == subs.len() {
+                    return Some(Err(Error::MultiSigEvaluationError));
+                } else {
+                    match self.stack.evaluate_multi(
+                        &mut self.verify_sig,
+                        &subs[subs.len() - node_state.n_evaluated - 1],
+                    ) {
+                        Some(Ok(x)) => {
+                            self.push_evaluation_state(
                                 node_state.node,
                                 node_state.n_evaluated + 1,
-                                node_state.n_satisfied,
-                            ),
-                            x => return x, //forward errors as is
+                                node_state.n_satisfied + 1,
+                            );
+                            return Some(Ok(x));
                         }
+                        None => self.push_evaluation_state(
+                            node_state.node,
+                            node_state.n_evaluated + 1,
+                            node_state.n_satisfied,
+                        ),
+                        x => return x, //forward errors as is
                     }
                 }
-                //All other match patterns should not be reached in any valid
-                //type checked Miniscript
-                _ => return Some(Err(Error::CouldNotEvaluate)),
-            };
+            }
+            //All other match patterns should not be reached in any valid
+            //type checked Miniscript
+            _ => return Some(Err(Error::CouldNotEvaluate)),
+        };
+        None
+    }
+
+    /// Helper function to step the iterator
+    fn iter_next(&mut self) -> Option<Result<SatisfiedConstraint, Error>> {
+        while let Some(node_state) = self.state.pop() {
+            //non-empty stack
+            let stack_before = self.trace.as_ref().map(|_| self.stack.to_witness());
+            let fragment = node_state.node;
+            let n_evaluated = node_state.n_evaluated;
+            let res = self.step(node_state);
+            if let (Some(trace), Some(stack_before)) = (self.trace.as_mut(), stack_before) {
+                trace.push(TraceStep {
+                    fragment: fragment.to_string(),
+                    n_evaluated,
+                    stack_before,
+                    stack_after: self.stack.to_witness(),
+                });
+            }
+            if res.is_some() {
+                return res;
+            }
         }
 
         //state empty implies that either the execution has terminated or we have a
@@ -1035,7 +1234,7 @@ mod tests {
     use super::inner::ToNoChecks;
     use super::*;
     use crate::miniscript::context::NoChecks;
-    use crate::{Miniscript, MiniscriptKey, ToPublicKey};
+    use crate::{Miniscript, MiniscriptKey, Segwitv0, ToPublicKey};
 
     fn setup_keys_sigs(
         n: usize,
@@ -1133,6 +1332,9 @@ mod tests {
                 age: 1002,
                 height: 1002,
                 has_errored: false,
+                trace: None,
+                leaf_hash: None,
+                annex: None,
             }
         }
 
@@ -1200,6 +1402,20 @@ mod tests {
             vec![SatisfiedConstraint::AbsoluteTimelock { time: 1000 }]
         );
 
+        //Check After with a timestamp, which a block height never satisfies
+        let after_time = no_checks_ms("after(500000000)");
+        let stack = Stack::from(vec![]);
+        let vfyfn = vfyfn_.clone(); // sigh rust 1.29...
+        let constraints = from_stack(Box::new(vfyfn), stack, &after_time);
+        let after_err: Result<Vec<SatisfiedConstraint>, Error> = constraints.collect();
+        match after_err {
+            Err(Error::Miniscript(crate::Error::MixedLockTimeUnits(
+                crate::LockTime::Seconds(500000000),
+                crate::LockTime::Blocks(1002),
+            ))) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+
         //Check Older
         let stack = Stack::from(vec![]);
         let vfyfn = vfyfn_.clone(); // sigh rust 1.29...
@@ -1594,6 +1810,242 @@ mod tests {
         assert!(multi_a_error.is_err());
     }
 
+    #[test]
+    fn trace_failed_satisfaction() {
+        let preimage = [0xab; 32];
+        let hash = sha256::Hash::hash(&preimage);
+        let ms: Miniscript<bitcoin::PublicKey, Segwitv0> =
+            Miniscript::from_str_insane(&format!("and_v(v:sha256({}),older(10))", hash)).unwrap();
+        let script = ms.encode();
+        let spk = script.to_v0_p2wsh();
+        let script_sig = bitcoin::Script::new();
+
+        // Wrong preimage: the trace shows the sha256 fragment dissatisfied
+        // and the verify wrapper around it failing
+        let witness = Witness::from_vec(vec![vec![0xcd; 32], script.to_bytes()]);
+        let interpreter = Interpreter::from_txdata(&spk, &script_sig, &witness, 10, 10).unwrap();
+        let mut iter = interpreter.iter_assume_sigs().with_trace();
+        match iter.next() {
+            Some(Err(Error::VerifyFailed)) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+        let trace: Vec<_> = iter.trace().collect();
+        let sha_step = trace
+            .iter()
+            .find(|step| step.fragment == format!("sha256({})", hash))
+            .unwrap();
+        assert_eq!(sha_step.stack_before, vec![vec![0xcd; 32]]);
+        assert_eq!(sha_step.stack_after, vec![vec![]]);
+        let last = trace.last().unwrap();
+        assert_eq!(last.fragment, format!("v:sha256({})", hash));
+        assert_eq!(last.n_evaluated, 1);
+        assert_eq!(last.stack_before, vec![vec![]]);
+
+        // Without tracing no steps are recorded
+        let mut iter = interpreter.iter_assume_sigs();
+        match iter.next() {
+            Some(Err(Error::VerifyFailed)) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+        assert_eq!(iter.trace().count(), 0);
+
+        // Correct preimage
+        let witness = Witness::from_vec(vec![preimage.to_vec(), script.to_bytes()]);
+        let interpreter = Interpreter::from_txdata(&spk, &script_sig, &witness, 10, 10).unwrap();
+        let mut iter = interpreter.iter_assume_sigs().with_trace();
+        assert!(iter.by_ref().all(|res| res.is_ok()));
+        assert_eq!(iter.trace().last().unwrap().stack_after, vec![vec![1]]);
+    }
+
+    #[test]
+    fn taproot_annex() {
+        let secp = Secp256k1::new();
+        let keypair = secp256k1::KeyPair::from_seckey_slice(&secp, &[0x11; 32]).unwrap();
+        let xpk = bitcoin::XOnlyPublicKey::from_keypair(&keypair);
+        let spk = bitcoin::Script::new_v1_p2tr_tweaked(
+            bitcoin::util::schnorr::TweakedPublicKey::dangerous_assume_tweaked(xpk),
+        );
+        let prevouts = [TxOut {
+            value: 100_000,
+            script_pubkey: spk.clone(),
+        }];
+        let prevouts = sighash::Prevouts::All(&prevouts);
+        let tx = bitcoin::Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![bitcoin::TxIn::default()],
+            output: vec![TxOut {
+                value: 90_000,
+                script_pubkey: spk.clone(),
+            }],
+        };
+        let annex = vec![bitcoin::util::taproot::TAPROOT_ANNEX_PREFIX, 0xab, 0xcd];
+        let sign = |annex: Option<&[u8]>| {
+            let sighash = sighash::SighashCache::new(&tx)
+                .taproot_signature_hash(
+                    0,
+                    &prevouts,
+                    annex.map(|annex| sighash::Annex::new(annex).unwrap()),
+                    None,
+                    bitcoin::SchnorrSighashType::Default,
+                )
+                .unwrap();
+            let msg = secp256k1::Message::from_slice(&sighash[..]).unwrap();
+            secp.sign_schnorr_no_aux_rand(&msg, &keypair)
+                .as_ref()
+                .to_vec()
+        };
+        let script_sig = bitcoin::Script::new();
+
+        // The annex is skipped and committed to by the signature
+        let witness = Witness::from_vec(vec![sign(Some(&annex)), annex.clone()]);
+        let interpreter = Interpreter::from_txdata(&spk, &script_sig, &witness, 0, 0).unwrap();
+        assert!(interpreter.is_taproot_v1_key_spend());
+        assert_eq!(interpreter.annex(), Some(&annex[..]));
+        let iter = interpreter.iter(&secp, &tx, 0, &prevouts);
+        assert_eq!(iter.annex(), Some(&annex[..]));
+        let constraints: Result<Vec<_>, _> = iter.collect();
+        assert_eq!(constraints.unwrap().len(), 1);
+
+        // A signature not committing to the annex is invalid
+        let witness = Witness::from_vec(vec![sign(None), annex.clone()]);
+        let interpreter = Interpreter::from_txdata(&spk, &script_sig, &witness, 0, 0).unwrap();
+        assert!(interpreter
+            .iter(&secp, &tx, 0, &prevouts)
+            .any(|res| res.is_err()));
+
+        // Without an annex, nothing changes
+        let witness = Witness::from_vec(vec![sign(None)]);
+        let interpreter = Interpreter::from_txdata(&spk, &script_sig, &witness, 0, 0).unwrap();
+        assert_eq!(interpreter.annex(), None);
+        assert!(interpreter
+            .iter(&secp, &tx, 0, &prevouts)
+            .all(|res| res.is_ok()));
+    }
+
+    #[test]
+    fn tapleaf_without_witness() {
+        let desc = crate::Descriptor::<bitcoin::PublicKey>::from_str(
+            "tr(0231c7d3fc85c148717848033ce276ae2b464a4e2c367ed33886cc428b8af48ff8,\
+             {pk(0250863ad64a87ae8a2fe83c1af1a8403cb53f53e486d8511dad8a04887e5b2352),\
+             pk(025476c2e83188368da1ff3e292e7acafcdb3566bb0ad253f62fc70f07aeee6357)})",
+        )
+        .unwrap();
+        let tr = match desc {
+            crate::Descriptor::Tr(ref tr) => tr,
+            _ => unreachable!(),
+        };
+        let spk = desc.script_pubkey();
+        let spend_info = tr.spend_info();
+        let leaves: Vec<_> = tr.iter_scripts().map(|(_, ms)| ms.encode()).collect();
+        let ctrl_blk = spend_info
+            .control_block(&(leaves[0].clone(), taproot::LeafVersion::TapScript))
+            .unwrap();
+
+        let interpreter = Interpreter::from_tapleaf(&spk, &ctrl_blk, &leaves[0]).unwrap();
+        assert!(interpreter.is_taproot_v1_script_spend());
+        assert_eq!(
+            interpreter.inferred_descriptor_string(),
+            "tr(hidden_paths_not_yet_supported,pk(0250863ad64a87ae8a2fe83c1af1a8403cb53f53e486d8511dad8a04887e5b2352))"
+        );
+
+        // The control block does not commit to the other leaf
+        match Interpreter::from_tapleaf(&spk, &ctrl_blk, &leaves[1]) {
+            Err(Error::ControlBlockVerificationError) => {}
+            res => panic!("unexpected result {:?}", res.map(|_| ())),
+        }
+        match Interpreter::from_tapleaf(&leaves[0].to_v0_p2wsh(), &ctrl_blk, &leaves[0]) {
+            Err(Error::NonTaprootScriptPubkey) => {}
+            res => panic!("unexpected result {:?}", res.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn annotated_constraints() {
+        let (pks, der_sigs, _, _, _, xpks, _, ser_schnorr_sigs) = setup_keys_sigs(2);
+        let origin = |n: u32| {
+            (
+                bip32::Fingerprint::from(&[n as u8; 4][..]),
+                vec![bip32::ChildNumber::from_normal_idx(n).unwrap()].into(),
+            )
+        };
+        let mut input = psbt::Input::default();
+        input.bip32_derivation.insert(pks[0].inner, origin(0));
+        input.tap_key_origins.insert(xpks[0], (vec![], origin(1)));
+
+        // Segwit v0 spends have no leaf hash
+        let ms = Miniscript::<bitcoin::PublicKey, Segwitv0>::from_str(&format!("pk({})", pks[0]))
+            .unwrap();
+        let spk = ms.encode().to_v0_p2wsh();
+        let witness = Witness::from_vec(vec![der_sigs[0].clone(), ms.encode().into_bytes()]);
+        let script_sig = bitcoin::Script::new();
+        let interpreter = Interpreter::from_txdata(&spk, &script_sig, &witness, 0, 0).unwrap();
+        let annotated: Vec<_> = interpreter
+            .iter_assume_sigs()
+            .annotated(Some(&input))
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(annotated.len(), 1);
+        assert_eq!(annotated[0].leaf_hash, None);
+        assert_eq!(annotated[0].key_origin, Some(origin(0)));
+
+        // Taproot script spends record the executed leaf
+        let desc = Descriptor::<bitcoin::XOnlyPublicKey>::from_str(&format!(
+            "tr({},pk({}))",
+            xpks[1], xpks[0]
+        ))
+        .unwrap();
+        let tr = match desc {
+            Descriptor::Tr(ref tr) => tr,
+            _ => unreachable!(),
+        };
+        let leaf = tr.iter_scripts().next().unwrap().1.encode();
+        let leaf_hash = taproot::TapLeafHash::from_script(&leaf, taproot::LeafVersion::TapScript);
+        let ctrl_blk = tr
+            .spend_info()
+            .control_block(&(leaf.clone(), taproot::LeafVersion::TapScript))
+            .unwrap();
+        let witness = Witness::from_vec(vec![
+            ser_schnorr_sigs[0].clone(),
+            leaf.clone().into_bytes(),
+            ctrl_blk.serialize(),
+        ]);
+        let spk = desc.script_pubkey();
+        let interpreter = Interpreter::from_txdata(&spk, &script_sig, &witness, 0, 0).unwrap();
+        assert_eq!(interpreter.leaf_hash(), Some(leaf_hash));
+        let annotated: Vec<_> = interpreter
+            .iter_assume_sigs()
+            .annotated(Some(&input))
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(annotated[0].leaf_hash, Some(leaf_hash));
+        assert_eq!(annotated[0].key_origin, Some(origin(1)));
+
+        // The leaf hash commits to the leaf version of the control block
+        let ver = taproot::LeafVersion::from_consensus(0xc2).unwrap();
+        let spend_info = taproot::TaprootBuilder::new()
+            .add_leaf_with_ver(0, leaf.clone(), ver)
+            .unwrap()
+            .finalize(&secp256k1::Secp256k1::verification_only(), xpks[1])
+            .unwrap();
+        let ctrl_blk = spend_info.control_block(&(leaf.clone(), ver)).unwrap();
+        let witness = Witness::from_vec(vec![
+            ser_schnorr_sigs[0].clone(),
+            leaf.clone().into_bytes(),
+            ctrl_blk.serialize(),
+        ]);
+        let spk = bitcoin::Script::new_v1_p2tr_tweaked(spend_info.output_key());
+        let interpreter = Interpreter::from_txdata(&spk, &script_sig, &witness, 0, 0).unwrap();
+        assert_eq!(
+            interpreter.leaf_hash(),
+            Some(taproot::TapLeafHash::from_script(&leaf, ver))
+        );
+
+        // Without a psbt input there is no key origin
+        let annotated = interpreter.iter_assume_sigs().annotated(None).next();
+        assert_eq!(annotated.unwrap().unwrap().key_origin, None);
+    }
+
     // By design there is no support for parse a miniscript with BitcoinKey
     // because it does not implement FromStr
     fn no_checks_ms(ms: &str) -> Miniscript<BitcoinKey, NoChecks> {
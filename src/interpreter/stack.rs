@@ -23,6 +23,7 @@ use super::{
     verify_sersig, BitcoinKey, Error, HashLockType, KeySigPair, SatisfiedConstraint, TypedHash160,
 };
 use crate::prelude::*;
+use crate::{hash256, LockTime};
 
 /// Definition of Stack Element of the Stack used for interpretation of Miniscript.
 /// All stack elements with vec![] go to Dissatisfied and vec![1] are marked to Satisfied.
@@ -125,6 +126,19 @@ impl<'txin> Stack<'txin> {
         self.0.last()
     }
 
+    /// Returns the stack as witness elements, with satisfied and dissatisfied
+    /// results converted back to `vec![1]` and `vec![]`
+    pub(super) fn to_witness(&self) -> Vec<Vec<u8>> {
+        self.0
+            .iter()
+            .map(|elem| match *elem {
+                Element::Satisfied => vec![1],
+                Element::Dissatisfied => vec![],
+                Element::Push(sl) => sl.to_vec(),
+            })
+            .collect()
+    }
+
     /// Helper function to evaluate a Pk Node which takes the
     /// top of the stack as input signature and validates it.
     /// Sat: If the signature witness is correct, 1 is pushed
@@ -234,11 +248,13 @@ impl<'txin> Stack<'txin> {
         n: &u32,
         age: u32,
     ) -> Option<Result<SatisfiedConstraint, Error>> {
-        if age >= *n {
-            self.push(Element::Satisfied);
-            Some(Ok(SatisfiedConstraint::AbsoluteTimelock { time: *n }))
-        } else {
-            Some(Err(Error::AbsoluteLocktimeNotMet(*n)))
+        match LockTime::from_consensus(*n).is_implied_by(LockTime::from_consensus(age)) {
+            Ok(true) => {
+                self.push(Element::Satisfied);
+                Some(Ok(SatisfiedConstraint::AbsoluteTimelock { time: *n }))
+            }
+            Ok(false) => Some(Err(Error::AbsoluteLocktimeNotMet(*n))),
+            Err(e) => Some(Err(Error::Miniscript(e))),
         }
     }
 
@@ -290,16 +306,16 @@ impl<'txin> Stack<'txin> {
     /// `SIZE 32 EQUALVERIFY HASH256 h EQUAL`
     pub(super) fn evaluate_hash256(
         &mut self,
-        hash: &sha256d::Hash,
+        hash: &hash256::Hash,
     ) -> Option<Result<SatisfiedConstraint, Error>> {
         if let Some(Element::Push(preimage)) = self.pop() {
             if preimage.len() != 32 {
                 return Some(Err(Error::HashPreimageLengthMismatch));
             }
-            if sha256d::Hash::hash(preimage) == *hash {
+            if sha256d::Hash::hash(preimage) == hash.as_hash() {
                 self.push(Element::Satisfied);
                 Some(Ok(SatisfiedConstraint::HashLock {
-                    hash: HashLockType::Hash256(*hash),
+                    hash: HashLockType::Hash256(hash.as_hash()),
                     preimage: preimage_from_sl(preimage),
                 }))
             } else {
@@ -126,13 +126,13 @@ use core::{fmt, hash, str};
 use std::error;
 
 use bitcoin::blockdata::{opcodes, script};
-use bitcoin::hashes::{hash160, sha256, Hash};
+use bitcoin::hashes::{hash160, ripemd160, sha256, Hash};
 
 pub use crate::descriptor::{Descriptor, DescriptorPublicKey};
 pub use crate::interpreter::Interpreter;
 pub use crate::miniscript::context::{BareCtx, Legacy, ScriptContext, Segwitv0, Tap};
 pub use crate::miniscript::decode::Terminal;
-pub use crate::miniscript::satisfy::{Preimage32, Satisfier};
+pub use crate::miniscript::satisfy::{LockTime, Preimage32, Satisfier, SatisfierExt};
 pub use crate::miniscript::Miniscript;
 use crate::prelude::*;
 
@@ -153,12 +153,28 @@ pub trait MiniscriptKey: Clone + Eq + Ord + fmt::Debug + fmt::Display + hash::Ha
     /// The associated [`Hash`] type for this pubkey.
     type Hash: Clone + Eq + Ord + fmt::Display + fmt::Debug + hash::Hash;
 
+    /// The associated [`sha256::Hash`] type used in the `sha256` fragment.
+    type Sha256: Clone + Eq + Ord + fmt::Display + fmt::Debug + hash::Hash;
+
+    /// The associated [`hash256::Hash`] type used in the `hash256` fragment.
+    type Hash256: Clone + Eq + Ord + fmt::Display + fmt::Debug + hash::Hash;
+
+    /// The associated [`ripemd160::Hash`] type used in the `ripemd160` fragment.
+    type Ripemd160: Clone + Eq + Ord + fmt::Display + fmt::Debug + hash::Hash;
+
+    /// The associated [`hash160::Hash`] type used in the `hash160` fragment.
+    type Hash160: Clone + Eq + Ord + fmt::Display + fmt::Debug + hash::Hash;
+
     /// Converts this key to the associated pubkey hash.
     fn to_pubkeyhash(&self) -> Self::Hash;
 }
 
 impl MiniscriptKey for bitcoin::secp256k1::PublicKey {
     type Hash = hash160::Hash;
+    type Sha256 = sha256::Hash;
+    type Hash256 = hash256::Hash;
+    type Ripemd160 = ripemd160::Hash;
+    type Hash160 = hash160::Hash;
 
     fn to_pubkeyhash(&self) -> Self::Hash {
         hash160::Hash::hash(&self.serialize())
@@ -172,6 +188,10 @@ impl MiniscriptKey for bitcoin::PublicKey {
     }
 
     type Hash = hash160::Hash;
+    type Sha256 = sha256::Hash;
+    type Hash256 = hash256::Hash;
+    type Ripemd160 = ripemd160::Hash;
+    type Hash160 = hash160::Hash;
 
     fn to_pubkeyhash(&self) -> Self::Hash {
         hash160::Hash::hash(&self.to_bytes())
@@ -180,6 +200,10 @@ impl MiniscriptKey for bitcoin::PublicKey {
 
 impl MiniscriptKey for bitcoin::secp256k1::XOnlyPublicKey {
     type Hash = hash160::Hash;
+    type Sha256 = sha256::Hash;
+    type Hash256 = hash256::Hash;
+    type Ripemd160 = ripemd160::Hash;
+    type Hash160 = hash160::Hash;
 
     fn to_pubkeyhash(&self) -> Self::Hash {
         hash160::Hash::hash(&self.serialize())
@@ -192,6 +216,10 @@ impl MiniscriptKey for bitcoin::secp256k1::XOnlyPublicKey {
 
 impl MiniscriptKey for String {
     type Hash = String;
+    type Sha256 = String; // specify hashes as string
+    type Hash256 = String;
+    type Ripemd160 = String;
+    type Hash160 = String;
 
     fn to_pubkeyhash(&self) -> Self::Hash {
         (&self).to_string()
@@ -216,6 +244,18 @@ pub trait ToPublicKey: MiniscriptKey {
     /// should give the same result as calling `to_public_key` and hashing
     /// the result directly.
     fn hash_to_hash160(hash: &<Self as MiniscriptKey>::Hash) -> hash160::Hash;
+
+    /// Converts the generic associated [`MiniscriptKey::Sha256`] to [`sha256::Hash`]
+    fn to_sha256(hash: &<Self as MiniscriptKey>::Sha256) -> sha256::Hash;
+
+    /// Converts the generic associated [`MiniscriptKey::Hash256`] to [`hash256::Hash`]
+    fn to_hash256(hash: &<Self as MiniscriptKey>::Hash256) -> hash256::Hash;
+
+    /// Converts the generic associated [`MiniscriptKey::Ripemd160`] to [`ripemd160::Hash`]
+    fn to_ripemd160(hash: &<Self as MiniscriptKey>::Ripemd160) -> ripemd160::Hash;
+
+    /// Converts the generic associated [`MiniscriptKey::Hash160`] to [`hash160::Hash`]
+    fn to_hash160(hash: &<Self as MiniscriptKey>::Hash160) -> hash160::Hash;
 }
 
 impl ToPublicKey for bitcoin::PublicKey {
@@ -226,6 +266,22 @@ impl ToPublicKey for bitcoin::PublicKey {
     fn hash_to_hash160(hash: &hash160::Hash) -> hash160::Hash {
         *hash
     }
+
+    fn to_sha256(hash: &sha256::Hash) -> sha256::Hash {
+        *hash
+    }
+
+    fn to_hash256(hash: &hash256::Hash) -> hash256::Hash {
+        *hash
+    }
+
+    fn to_ripemd160(hash: &ripemd160::Hash) -> ripemd160::Hash {
+        *hash
+    }
+
+    fn to_hash160(hash: &hash160::Hash) -> hash160::Hash {
+        *hash
+    }
 }
 
 impl ToPublicKey for bitcoin::secp256k1::PublicKey {
@@ -236,6 +292,22 @@ impl ToPublicKey for bitcoin::secp256k1::PublicKey {
     fn hash_to_hash160(hash: &hash160::Hash) -> hash160::Hash {
         *hash
     }
+
+    fn to_sha256(hash: &sha256::Hash) -> sha256::Hash {
+        *hash
+    }
+
+    fn to_hash256(hash: &hash256::Hash) -> hash256::Hash {
+        *hash
+    }
+
+    fn to_ripemd160(hash: &ripemd160::Hash) -> ripemd160::Hash {
+        *hash
+    }
+
+    fn to_hash160(hash: &hash160::Hash) -> hash160::Hash {
+        *hash
+    }
 }
 
 impl ToPublicKey for bitcoin::secp256k1::XOnlyPublicKey {
@@ -255,6 +327,22 @@ impl ToPublicKey for bitcoin::secp256k1::XOnlyPublicKey {
     fn hash_to_hash160(hash: &hash160::Hash) -> hash160::Hash {
         *hash
     }
+
+    fn to_sha256(hash: &sha256::Hash) -> sha256::Hash {
+        *hash
+    }
+
+    fn to_hash256(hash: &hash256::Hash) -> hash256::Hash {
+        *hash
+    }
+
+    fn to_ripemd160(hash: &ripemd160::Hash) -> ripemd160::Hash {
+        *hash
+    }
+
+    fn to_hash160(hash: &hash160::Hash) -> hash160::Hash {
+        *hash
+    }
 }
 
 /// Dummy key which de/serializes to the empty string; useful sometimes for testing
@@ -274,6 +362,10 @@ impl str::FromStr for DummyKey {
 
 impl MiniscriptKey for DummyKey {
     type Hash = DummyKeyHash;
+    type Sha256 = sha256::Hash;
+    type Hash256 = hash256::Hash;
+    type Ripemd160 = ripemd160::Hash;
+    type Hash160 = hash160::Hash;
 
     fn to_pubkeyhash(&self) -> Self::Hash {
         DummyKeyHash
@@ -303,6 +395,22 @@ impl ToPublicKey for DummyKey {
     fn hash_to_hash160(_: &DummyKeyHash) -> hash160::Hash {
         hash160::Hash::from_str("f54a5851e9372b87810a8e60cdd2e7cfd80b6e31").unwrap()
     }
+
+    fn to_sha256(hash: &sha256::Hash) -> sha256::Hash {
+        *hash
+    }
+
+    fn to_hash256(hash: &hash256::Hash) -> hash256::Hash {
+        *hash
+    }
+
+    fn to_ripemd160(hash: &ripemd160::Hash) -> ripemd160::Hash {
+        *hash
+    }
+
+    fn to_hash160(hash: &hash160::Hash) -> hash160::Hash {
+        *hash
+    }
 }
 
 /// Dummy keyhash which de/serializes to the empty string; useful sometimes for testing
@@ -332,134 +440,93 @@ impl hash::Hash for DummyKeyHash {
     }
 }
 
-/// Converts a descriptor using abstract keys to one using specific keys.
+/// Translates the keys and hashes of a type `P` to those of a type `Q`, for
+/// use with [`TranslatePk`], which calls it for every key and hash of a
+/// structure in a single pass.
 ///
-/// # Panics
+/// When `P` and `Q` use the same hash types, the hash methods can be
+/// implemented with [`translate_hash_clone!`], and when the translated
+/// structures have no hashes, with [`translate_hash_fail!`].
 ///
-/// If `fpk` returns an uncompressed key when converting to a segwit descriptor.
-/// To prevent this panic, ensure `fpk` returns an error in this case instead.
-pub trait TranslatePk<P, Q>
+/// # Examples
+///
+/// ```
+/// use std::collections::BTreeMap;
+/// use std::str::FromStr;
+/// use miniscript::bitcoin::PublicKey;
+/// use miniscript::{translate_hash_fail, Descriptor, MiniscriptKey, TranslatePk, Translator};
+///
+/// struct Placeholders(BTreeMap<String, PublicKey>);
+///
+/// impl Translator<String, PublicKey, ()> for Placeholders {
+///     fn pk(&mut self, pk: &String) -> Result<PublicKey, ()> {
+///         self.0.get(pk).copied().ok_or(())
+///     }
+///
+///     fn pkh(&mut self, pkh: &String) -> Result<<PublicKey as MiniscriptKey>::Hash, ()> {
+///         self.pk(pkh).map(|pk| pk.to_pubkeyhash())
+///     }
+///
+///     // The template has no hashes
+///     translate_hash_fail!(String, PublicKey, ());
+/// }
+///
+/// let mut keys = BTreeMap::new();
+/// keys.insert(
+///     "alice".to_owned(),
+///     PublicKey::from_str("0270cf3c71f65a3d93d285d9149fddeeb638f87a2d4d8cf16c525f71c417439777").unwrap(),
+/// );
+/// let template = Descriptor::<String>::from_str("wpkh(alice)").unwrap();
+/// let descriptor = template.translate_pk(&mut Placeholders(keys)).unwrap();
+/// assert_eq!(
+///     descriptor.to_string(),
+///     "wpkh(0270cf3c71f65a3d93d285d9149fddeeb638f87a2d4d8cf16c525f71c417439777)#dn0w6tfc"
+/// );
+/// ```
+pub trait Translator<P, Q, E>
 where
     P: MiniscriptKey,
     Q: MiniscriptKey,
 {
-    /// The associated output type. This must be `Self<Q>`.
-    type Output;
+    /// Translates a public key
+    fn pk(&mut self, pk: &P) -> Result<Q, E>;
 
-    /// Translates a struct from one generic to another where the translation
-    /// for Pk is provided by function `fpk`, and translation for PkH is
-    /// provided by function `fpkh`.
-    fn translate_pk<Fpk, Fpkh, E>(&self, fpk: Fpk, fpkh: Fpkh) -> Result<Self::Output, E>
-    where
-        Fpk: FnMut(&P) -> Result<Q, E>,
-        Fpkh: FnMut(&P::Hash) -> Result<Q::Hash, E>;
+    /// Translates a public key hash, as used in the `pk_h` fragment
+    fn pkh(&mut self, pkh: &P::Hash) -> Result<Q::Hash, E>;
 
-    /// Calls `Self::translate_pk` with conversion functions that cannot fail.
-    fn translate_pk_infallible<Fpk, Fpkh>(&self, mut fpk: Fpk, mut fpkh: Fpkh) -> Self::Output
-    where
-        Fpk: FnMut(&P) -> Q,
-        Fpkh: FnMut(&P::Hash) -> Q::Hash,
-    {
-        self.translate_pk::<_, _, ()>(|pk| Ok(fpk(pk)), |pkh| Ok(fpkh(pkh)))
-            .expect("infallible translation function")
-    }
-}
-
-/// Variant of `TranslatePk` where P and Q both have the same hash
-/// type, and the hashes can be converted by just cloning them.
-pub trait TranslatePk1<P, Q>: TranslatePk<P, Q>
-where
-    P: MiniscriptKey,
-    Q: MiniscriptKey<Hash = P::Hash>,
-{
-    /// Translates a struct from one generic to another where the translation
-    /// for Pk is provided by function `fpk`.
-    fn translate_pk1<Fpk, E>(&self, fpk: Fpk) -> Result<<Self as TranslatePk<P, Q>>::Output, E>
-    where
-        Fpk: FnMut(&P) -> Result<Q, E>,
-    {
-        self.translate_pk(fpk, |h| Ok(h.clone()))
-    }
+    /// Translates a hash used in the `sha256` fragment
+    fn sha256(&mut self, sha256: &P::Sha256) -> Result<Q::Sha256, E>;
 
-    /// Translates a struct from one generic to another where the translation
-    /// for Pk is provided by function `fpk`.
-    fn translate_pk1_infallible<Fpk>(&self, fpk: Fpk) -> <Self as TranslatePk<P, Q>>::Output
-    where
-        Fpk: FnMut(&P) -> Q,
-    {
-        self.translate_pk_infallible(fpk, P::Hash::clone)
-    }
-}
-impl<P, Q, T> TranslatePk1<P, Q> for T
-where
-    P: MiniscriptKey,
-    Q: MiniscriptKey<Hash = P::Hash>,
-    T: TranslatePk<P, Q>,
-{
-}
+    /// Translates a hash used in the `hash256` fragment
+    fn hash256(&mut self, hash256: &P::Hash256) -> Result<Q::Hash256, E>;
 
-/// Variant of `TranslatePk` where P's hash is P, so the hashes
-/// can be converted by reusing the key-conversion function.
-pub trait TranslatePk2<P, Q>: TranslatePk<P, Q>
-where
-    P: MiniscriptKey<Hash = P>,
-    Q: MiniscriptKey,
-{
-    /// Translates a struct from one generic to another where the translation
-    /// for Pk is provided by function `fpk`.
-    fn translate_pk2<Fpk, E>(&self, fpk: Fpk) -> Result<<Self as TranslatePk<P, Q>>::Output, E>
-    where
-        Fpk: Fn(&P) -> Result<Q, E>,
-    {
-        self.translate_pk(&fpk, |h| fpk(h).map(|q| q.to_pubkeyhash()))
-    }
+    /// Translates a hash used in the `ripemd160` fragment
+    fn ripemd160(&mut self, ripemd160: &P::Ripemd160) -> Result<Q::Ripemd160, E>;
 
-    /// Translates a struct from one generic to another where the translation
-    /// for Pk is provided by function `fpk`.
-    fn translate_pk2_infallible<Fpk>(&self, fpk: Fpk) -> <Self as TranslatePk<P, Q>>::Output
-    where
-        Fpk: Fn(&P) -> Q,
-    {
-        self.translate_pk_infallible(&fpk, |h| fpk(h).to_pubkeyhash())
-    }
+    /// Translates a hash used in the `hash160` fragment
+    fn hash160(&mut self, hash160: &P::Hash160) -> Result<Q::Hash160, E>;
 }
-impl<P, Q, T> TranslatePk2<P, Q> for T
+
+/// Converts a descriptor using abstract keys to one using specific keys.
+///
+/// # Panics
+///
+/// If the translator returns an uncompressed key when converting to a segwit
+/// descriptor. To prevent this panic, ensure it returns an error in this case
+/// instead.
+pub trait TranslatePk<P, Q>
 where
-    P: MiniscriptKey<Hash = P>,
+    P: MiniscriptKey,
     Q: MiniscriptKey,
-    T: TranslatePk<P, Q>,
 {
-}
-
-/// Variant of `TranslatePk` where Q's hash is `hash160` so we can
-/// derive hashes by calling `hash_to_hash160`.
-pub trait TranslatePk3<P: MiniscriptKey + ToPublicKey, Q: MiniscriptKey<Hash = hash160::Hash>>:
-    TranslatePk<P, Q>
-{
-    /// Translates a struct from one generic to another where the translation
-    /// for Pk is provided by function `fpk`.
-    fn translate_pk3<Fpk, E>(&self, fpk: Fpk) -> Result<<Self as TranslatePk<P, Q>>::Output, E>
-    where
-        Fpk: FnMut(&P) -> Result<Q, E>,
-    {
-        self.translate_pk(fpk, |h| Ok(P::hash_to_hash160(h)))
-    }
+    /// The associated output type. This must be `Self<Q>`.
+    type Output;
 
-    /// Translates a struct from one generic to another where the translation
-    /// for Pk is provided by function `fpk`.
-    fn translate_pk3_infallible<Fpk>(&self, fpk: Fpk) -> <Self as TranslatePk<P, Q>>::Output
+    /// Translates a struct from one generic to another where the translations
+    /// of all keys and hashes are provided by `translator`.
+    fn translate_pk<T, E>(&self, translator: &mut T) -> Result<Self::Output, E>
     where
-        Fpk: FnMut(&P) -> Q,
-    {
-        self.translate_pk_infallible(fpk, P::hash_to_hash160)
-    }
-}
-impl<P, Q, T> TranslatePk3<P, Q> for T
-where
-    P: MiniscriptKey + ToPublicKey,
-    Q: MiniscriptKey<Hash = hash160::Hash>,
-    T: TranslatePk<P, Q>,
-{
+        T: Translator<P, Q, E> + ?Sized;
 }
 
 /// Either a key or a keyhash
@@ -551,8 +618,14 @@ pub enum Error {
     RelativeLocktimeNotMet(u32),
     /// Could not satisfy, absolute locktime not met
     AbsoluteLocktimeNotMet(u32),
+    /// An absolute timelock was compared with a locktime of the other unit
+    MixedLockTimeUnits(LockTime, LockTime),
     /// General failure to satisfy
     CouldNotSatisfy,
+    /// A satisfaction exceeds a resource limit of its script context
+    ResourceLimitExceeded(miniscript::limits::LimitExceeded),
+    /// A buffer given to an allocation-free satisfaction is too small
+    SatisfyBufferTooSmall,
     /// Typechecking failed
     TypeCheck(String),
     /// General error in creating descriptor
@@ -589,6 +662,31 @@ pub enum Error {
     TrNoScriptCode,
     /// No explicit script for Tr descriptors
     TrNoExplicitScript,
+    /// The address of an addr descriptor, for the first network, is not valid
+    /// for the second one
+    AddrNetworkMismatch(bitcoin::Network, bitcoin::Network),
+    /// A key of a descriptor could not be parsed by
+    /// [`Descriptor::parse_descriptor`]
+    BadDescriptorKey {
+        /// The byte offset of the key in the descriptor string
+        position: usize,
+        /// The key parsing error, with positions relative to the descriptor
+        /// string
+        error: descriptor::DescriptorKeyParseError,
+    },
+    /// BIP32 error, such as an index out of range when building a key
+    Bip32(bitcoin::util::bip32::Error),
+    /// A parsed expression exceeds a limit of its [`expression::ParseOptions`]
+    LimitExceeded {
+        /// The limit exceeded
+        kind: expression::LimitKind,
+        /// The value of the limit
+        limit: usize,
+    },
+    /// The fee of spending an output at the given feerate overflowed
+    FeeOverflow,
+    /// A descriptor key could not be converted to a bitcoin public key
+    Conversion(descriptor::ConversionError),
 }
 
 // https://github.com/sipa/miniscript/pull/5 for discussion on this number
@@ -596,6 +694,101 @@ const MAX_RECURSION_DEPTH: u32 = 402;
 // https://github.com/bitcoin/bips/blob/master/bip-0141.mediawiki
 const MAX_SCRIPT_SIZE: u32 = 10000;
 
+/// The area of the library an [`Error`] comes from, as returned by
+/// [`Error::kind`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ErrorKind {
+    /// Decoding a script into a miniscript, codes 1xx
+    ScriptDecode,
+    /// Parsing a miniscript or policy from a string, codes 2xx
+    Parse,
+    /// Type checking and script context rules, codes 3xx
+    TypeCheck,
+    /// Satisfying a miniscript, codes 4xx
+    Satisfaction,
+    /// Compiling and lifting policies, codes 5xx
+    Policy,
+    /// Descriptors, keys and addresses, codes 6xx
+    Descriptor,
+}
+
+impl Error {
+    /// A numeric code identifying the variant of the error.
+    ///
+    /// Codes are stable: a code is never reassigned to another variant, and
+    /// its hundreds digit gives the [`ErrorKind`] of the error. Programs which
+    /// need to distinguish errors across versions of the library should
+    /// match on codes rather than on display strings.
+    pub fn code(&self) -> u16 {
+        match *self {
+            Error::InvalidOpcode(_) => 100,
+            Error::NonMinimalVerify(_) => 101,
+            Error::InvalidPush(_) => 102,
+            Error::Script(_) => 103,
+            Error::CmsTooManyKeys(_) => 104,
+            Error::MultiATooManyKeys(_) => 105,
+            Error::UnexpectedStart => 106,
+            Error::Trailing(_) => 107,
+            Error::BadPubkey(_) => 108,
+            Error::PubKeyCtxError(..) => 109,
+            Error::Unprintable(_) => 200,
+            Error::ExpectedChar(_) => 201,
+            Error::Unexpected(_) => 202,
+            Error::MultiColon(_) => 203,
+            Error::MultiAt(_) => 204,
+            Error::AtOutsideOr(_) => 205,
+            Error::LikelyFalse => 206,
+            Error::UnknownWrapper(_) => 207,
+            Error::MaxRecursiveDepthExceeded => 208,
+            Error::LimitExceeded { .. } => 209,
+            Error::NonTopLevel(_) => 300,
+            Error::TypeCheck(_) => 301,
+            Error::ContextError(_) => 302,
+            Error::ScriptSizeTooLarge => 303,
+            Error::NonStandardBareScript => 304,
+            Error::AnalysisError(_) => 305,
+            Error::ImpossibleSatisfaction => 306,
+            Error::MissingHash(_) => 400,
+            Error::MissingSig(_) => 401,
+            Error::RelativeLocktimeNotMet(_) => 402,
+            Error::AbsoluteLocktimeNotMet(_) => 403,
+            Error::CouldNotSatisfy => 404,
+            Error::SatisfyBufferTooSmall => 405,
+            Error::ResourceLimitExceeded(_) => 406,
+            Error::MixedLockTimeUnits(..) => 407,
+            #[cfg(feature = "compiler")]
+            Error::CompilerError(_) => 500,
+            Error::PolicyError(_) => 501,
+            Error::LiftError(_) => 502,
+            Error::BadDescriptor(_) => 600,
+            Error::AddrError(_) => 601,
+            Error::Secp(_) => 602,
+            Error::BareDescriptorAddr => 603,
+            Error::TaprootSpendInfoUnavialable => 604,
+            Error::TrNoScriptCode => 605,
+            Error::TrNoExplicitScript => 606,
+            Error::AddrNetworkMismatch(..) => 607,
+            Error::BadDescriptorKey { .. } => 608,
+            Error::Bip32(_) => 609,
+            Error::FeeOverflow => 610,
+            Error::Conversion(_) => 611,
+        }
+    }
+
+    /// The area of the library the error comes from
+    pub fn kind(&self) -> ErrorKind {
+        match self.code() / 100 {
+            1 => ErrorKind::ScriptDecode,
+            2 => ErrorKind::Parse,
+            3 => ErrorKind::TypeCheck,
+            4 => ErrorKind::Satisfaction,
+            5 => ErrorKind::Policy,
+            6 => ErrorKind::Descriptor,
+            _ => unreachable!("every code has a kind"),
+        }
+    }
+}
+
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
@@ -626,7 +819,14 @@ impl fmt::Display for Error {
                 "required absolute locktime CLTV of {} blocks, not met",
                 n
             ),
+            Error::MixedLockTimeUnits(lock, lock_time) => write!(
+                f,
+                "absolute timelock at {} is incomparable with locktime {}",
+                lock, lock_time
+            ),
             Error::CouldNotSatisfy => f.write_str("could not satisfy"),
+            Error::ResourceLimitExceeded(ref e) => write!(f, "satisfaction {}", e),
+            Error::SatisfyBufferTooSmall => f.write_str("satisfaction buffer too small"),
             Error::BadPubkey(ref e) => fmt::Display::fmt(e, f),
             Error::TypeCheck(ref e) => write!(f, "typecheck: {}", e),
             Error::BadDescriptor(ref e) => write!(f, "Invalid descriptor: {}", e),
@@ -670,13 +870,28 @@ impl fmt::Display for Error {
             Error::TrNoExplicitScript => {
                 write!(f, "No script code for Tr descriptors")
             }
+            Error::LimitExceeded { kind, limit } => {
+                write!(f, "expression exceeds the limit of {} {}", limit, kind)
+            }
+            Error::AddrNetworkMismatch(addr_network, network) => write!(
+                f,
+                "Address for network {} is not valid for network {}",
+                addr_network, network
+            ),
+            Error::BadDescriptorKey {
+                position,
+                ref error,
+            } => write!(f, "invalid key at position {}: {}", position, error),
+            Error::Bip32(ref e) => fmt::Display::fmt(e, f),
+            Error::FeeOverflow => f.write_str("fee overflowed"),
+            Error::Conversion(ref e) => fmt::Display::fmt(e, f),
         }
     }
 }
 
 #[cfg(feature = "std")]
 impl error::Error for Error {
-    fn cause(&self) -> Option<&dyn error::Error> {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
         use self::Error::*;
 
         match self {
@@ -700,7 +915,9 @@ impl error::Error for Error {
             | MissingSig(_)
             | RelativeLocktimeNotMet(_)
             | AbsoluteLocktimeNotMet(_)
+            | MixedLockTimeUnits(..)
             | CouldNotSatisfy
+            | ResourceLimitExceeded(_)
             | TypeCheck(_)
             | BadDescriptor(_)
             | MaxRecursiveDepthExceeded
@@ -710,7 +927,11 @@ impl error::Error for Error {
             | BareDescriptorAddr
             | TaprootSpendInfoUnavialable
             | TrNoScriptCode
-            | TrNoExplicitScript => None,
+            | TrNoExplicitScript
+            | AddrNetworkMismatch(..)
+            | LimitExceeded { .. }
+            | FeeOverflow
+            | SatisfyBufferTooSmall => None,
             Script(e) => Some(e),
             AddrError(e) => Some(e),
             BadPubkey(e) => Some(e),
@@ -722,6 +943,9 @@ impl error::Error for Error {
             ContextError(e) => Some(e),
             AnalysisError(e) => Some(e),
             PubKeyCtxError(e, _) => Some(e),
+            BadDescriptorKey { error, .. } => Some(error),
+            Bip32(e) => Some(e),
+            Conversion(e) => Some(e),
         }
     }
 }
@@ -765,6 +989,13 @@ impl From<bitcoin::secp256k1::Error> for Error {
     }
 }
 
+#[doc(hidden)]
+impl From<bitcoin::util::bip32::Error> for Error {
+    fn from(e: bitcoin::util::bip32::Error) -> Error {
+        Error::Bip32(e)
+    }
+}
+
 #[doc(hidden)]
 impl From<bitcoin::util::address::Error> for Error {
     fn from(e: bitcoin::util::address::Error) -> Error {
@@ -820,6 +1051,24 @@ fn push_opcode_size(script_size: usize) -> usize {
     }
 }
 
+/// The `hash256` hash used by the miniscript `hash256` fragment.
+///
+/// This is a double sha256, but unlike [`bitcoin::hashes::sha256d::Hash`] it is
+/// displayed and parsed in the same byte order as it appears in the script.
+pub mod hash256 {
+    use bitcoin::hashes::{
+        borrow_slice_impl, hash_newtype, hex_fmt_impl, index_impl, serde_impl, sha256d, Hash as _,
+    };
+
+    hash_newtype!(
+        Hash,
+        sha256d::Hash,
+        32,
+        doc = "A hash256 hash, displayed in script byte order.",
+        false
+    );
+}
+
 /// Helper function used by tests
 #[cfg(test)]
 fn hex_script(s: &str) -> bitcoin::Script {
@@ -831,6 +1080,44 @@ fn hex_script(s: &str) -> bitcoin::Script {
 mod tests {
     use super::*;
 
+    #[test]
+    fn error_codes() {
+        let errors = [
+            Error::UnexpectedStart,
+            Error::LikelyFalse,
+            Error::ContextError(
+                miniscript::context::ScriptContextError::MaxWitnessItemssExceeded {
+                    actual: 101,
+                    limit: 100,
+                },
+            ),
+            Error::CouldNotSatisfy,
+            Error::LiftError(policy::LiftError::HeightTimelockCombination),
+            Error::BareDescriptorAddr,
+        ];
+        let kinds = errors.iter().map(Error::kind).collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            vec![
+                ErrorKind::ScriptDecode,
+                ErrorKind::Parse,
+                ErrorKind::TypeCheck,
+                ErrorKind::Satisfaction,
+                ErrorKind::Policy,
+                ErrorKind::Descriptor,
+            ]
+        );
+        assert_eq!(Error::CouldNotSatisfy.code(), 404);
+
+        #[cfg(feature = "std")]
+        {
+            use std::error::Error as _;
+            assert!(errors[0].source().is_none());
+            assert!(errors[2].source().is_some());
+            assert!(errors[4].source().is_some());
+        }
+    }
+
     #[test]
     fn regression_bitcoin_key_hash() {
         use bitcoin::PublicKey;
@@ -880,6 +1167,30 @@ mod tests {
         let hash = pk.to_pubkeyhash();
         assert_eq!(hash, pk)
     }
+
+    #[test]
+    fn hash256_byte_order() {
+        use bitcoin::hashes::sha256d;
+
+        // The double sha256 of the empty string, in the order of its bytes
+        let hex = "5df6e0e2761359d30a8275058e299fcc0381534545f55cf43e41983f5d4c9456";
+        let hash = hash256::Hash::from_inner(sha256d::Hash::hash(b"").into_inner());
+        assert_eq!(hash.to_string(), hex);
+        assert_eq!(hash256::Hash::from_str(hex).unwrap(), hash);
+        // `sha256d::Hash` is displayed backwards
+        assert_eq!(
+            sha256d::Hash::hash(b"").to_string(),
+            "56944c5d3f98413ef45cf54545538103cc9f298e0575820ad3591376e2e0f65d"
+        );
+
+        // The hash is pushed to the script in the same order
+        let ms = Miniscript::<bitcoin::PublicKey, Segwitv0>::from_str_insane(&format!(
+            "hash256({})",
+            hex
+        ))
+        .unwrap();
+        assert_eq!(ms.encode(), hex_script(&format!("82012088aa20{}87", hex)));
+    }
 }
 
 mod prelude {
@@ -937,7 +1248,7 @@ mod prelude {
     pub use alloc::{
         borrow::{Borrow, Cow, ToOwned},
         boxed::Box,
-        collections::{vec_deque::VecDeque, BTreeMap, BinaryHeap},
+        collections::{vec_deque::VecDeque, BTreeMap, BTreeSet, BinaryHeap},
         rc, slice,
         string::{String, ToString},
         sync,
@@ -947,7 +1258,7 @@ mod prelude {
     pub use std::{
         borrow::{Borrow, Cow, ToOwned},
         boxed::Box,
-        collections::{vec_deque::VecDeque, BTreeMap, BinaryHeap, HashMap, HashSet},
+        collections::{vec_deque::VecDeque, BTreeMap, BTreeSet, BinaryHeap, HashMap, HashSet},
         rc, slice,
         string::{String, ToString},
         sync,
@@ -1,6 +1,7 @@
 //! Macros
 //!
-//! Macros meant to be used inside the Rust Miniscript library
+//! Macros meant to be used inside the Rust Miniscript library, and the
+//! exported helpers for implementing its traits
 
 /// Allows tests to create a miniscript directly from string as
 /// `ms_str!("c:or_i(pk({}),pk({}))", pk1, pk2)`
@@ -18,16 +19,84 @@ macro_rules! policy_str {
 
 /// A macro that implements serde serialization and deserialization using the
 /// `fmt::Display` and `str::FromStr` traits.
+///
+/// The `nopk` form is for types which are not generic over the key.
 macro_rules! serde_string_impl_pk {
+    (nopk $name:ident, $expecting:expr) => {
+        #[cfg(feature = "serde")]
+        impl<'de> $crate::serde::Deserialize<'de> for $name {
+            fn deserialize<D>(deserializer: D) -> Result<$name, D::Error>
+            where
+                D: $crate::serde::de::Deserializer<'de>,
+            {
+                use core::fmt::{self, Formatter};
+                use core::str::FromStr;
+
+                struct Visitor;
+                impl<'de> $crate::serde::de::Visitor<'de> for Visitor {
+                    type Value = $name;
+
+                    fn expecting(&self, formatter: &mut Formatter) -> fmt::Result {
+                        formatter.write_str($expecting)
+                    }
+
+                    fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
+                    where
+                        E: $crate::serde::de::Error,
+                    {
+                        $name::from_str(v).map_err(E::custom)
+                    }
+
+                    fn visit_borrowed_str<E>(self, v: &'de str) -> Result<Self::Value, E>
+                    where
+                        E: $crate::serde::de::Error,
+                    {
+                        self.visit_str(v)
+                    }
+
+                    fn visit_string<E>(self, v: String) -> Result<Self::Value, E>
+                    where
+                        E: $crate::serde::de::Error,
+                    {
+                        self.visit_str(&v)
+                    }
+                }
+
+                deserializer.deserialize_str(Visitor)
+            }
+        }
+
+        #[cfg(feature = "serde")]
+        impl $crate::serde::Serialize for $name {
+            fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+            where
+                S: $crate::serde::Serializer,
+            {
+                serializer.collect_str(&self)
+            }
+        }
+    };
     ($name:ident, $expecting:expr $(, $gen:ident; $gen_con:ident)*) => {
         #[cfg(feature = "serde")]
         impl<'de, Pk $(, $gen)*> $crate::serde::Deserialize<'de> for $name<Pk $(, $gen)*>
         where
             Pk: $crate::MiniscriptKey + core::str::FromStr,
             Pk::Hash: core::str::FromStr,
+            Pk::Sha256: core::str::FromStr,
+            Pk::Hash256: core::str::FromStr,
+            Pk::Ripemd160: core::str::FromStr,
+            Pk::Hash160: core::str::FromStr,
             <Pk as core::str::FromStr>::Err: core::fmt::Display,
             <<Pk as $crate::MiniscriptKey>::Hash as core::str::FromStr>::Err:
                 core::fmt::Display,
+            <<Pk as $crate::MiniscriptKey>::Sha256 as core::str::FromStr>::Err:
+                core::fmt::Display,
+            <<Pk as $crate::MiniscriptKey>::Hash256 as core::str::FromStr>::Err:
+                core::fmt::Display,
+            <<Pk as $crate::MiniscriptKey>::Ripemd160 as core::str::FromStr>::Err:
+                core::fmt::Display,
+            <<Pk as $crate::MiniscriptKey>::Hash160 as core::str::FromStr>::Err:
+                core::fmt::Display,
             $($gen : $gen_con,)*
         {
             fn deserialize<D>(deserializer: D) -> Result<$name<Pk $(, $gen)*>, D::Error>
@@ -44,9 +113,21 @@ macro_rules! serde_string_impl_pk {
                 where
                     Pk: $crate::MiniscriptKey + core::str::FromStr,
                     Pk::Hash: core::str::FromStr,
+                    Pk::Sha256: core::str::FromStr,
+                    Pk::Hash256: core::str::FromStr,
+                    Pk::Ripemd160: core::str::FromStr,
+                    Pk::Hash160: core::str::FromStr,
                     <Pk as core::str::FromStr>::Err: core::fmt::Display,
                     <<Pk as $crate::MiniscriptKey>::Hash as core::str::FromStr>::Err:
                         core::fmt::Display,
+                    <<Pk as $crate::MiniscriptKey>::Sha256 as core::str::FromStr>::Err:
+                        core::fmt::Display,
+                    <<Pk as $crate::MiniscriptKey>::Hash256 as core::str::FromStr>::Err:
+                        core::fmt::Display,
+                    <<Pk as $crate::MiniscriptKey>::Ripemd160 as core::str::FromStr>::Err:
+                        core::fmt::Display,
+                    <<Pk as $crate::MiniscriptKey>::Hash160 as core::str::FromStr>::Err:
+                        core::fmt::Display,
                     $($gen: $gen_con,)*
                 {
                     type Value = $name<Pk $(, $gen)*>;
@@ -96,3 +177,81 @@ macro_rules! serde_string_impl_pk {
         }
     };
 }
+
+/// Implements the hash methods of a [`Translator`](crate::Translator) from
+/// `$source` to `$target` keys by cloning the hashes, for keys which use the
+/// same hash types, as all the key types of this library but `String` do.
+///
+/// Used inside an `impl Translator<$source, $target, $error_ty>` block, next
+/// to the `pk` and `pkh` methods.
+#[macro_export]
+macro_rules! translate_hash_clone {
+    ($source:ty, $target:ty, $error_ty:ty) => {
+        fn sha256(
+            &mut self,
+            sha256: &<$source as $crate::MiniscriptKey>::Sha256,
+        ) -> Result<<$target as $crate::MiniscriptKey>::Sha256, $error_ty> {
+            Ok(sha256.clone())
+        }
+
+        fn hash256(
+            &mut self,
+            hash256: &<$source as $crate::MiniscriptKey>::Hash256,
+        ) -> Result<<$target as $crate::MiniscriptKey>::Hash256, $error_ty> {
+            Ok(hash256.clone())
+        }
+
+        fn ripemd160(
+            &mut self,
+            ripemd160: &<$source as $crate::MiniscriptKey>::Ripemd160,
+        ) -> Result<<$target as $crate::MiniscriptKey>::Ripemd160, $error_ty> {
+            Ok(ripemd160.clone())
+        }
+
+        fn hash160(
+            &mut self,
+            hash160: &<$source as $crate::MiniscriptKey>::Hash160,
+        ) -> Result<<$target as $crate::MiniscriptKey>::Hash160, $error_ty> {
+            Ok(hash160.clone())
+        }
+    };
+}
+
+/// Implements the hash methods of a [`Translator`](crate::Translator) from
+/// `$source` to `$target` keys by panicking, for translating structures known
+/// to have no hashes.
+///
+/// Used inside an `impl Translator<$source, $target, $error_ty>` block, next
+/// to the `pk` and `pkh` methods.
+#[macro_export]
+macro_rules! translate_hash_fail {
+    ($source:ty, $target:ty, $error_ty:ty) => {
+        fn sha256(
+            &mut self,
+            _sha256: &<$source as $crate::MiniscriptKey>::Sha256,
+        ) -> Result<<$target as $crate::MiniscriptKey>::Sha256, $error_ty> {
+            panic!("Called sha256 on a translator of keys only")
+        }
+
+        fn hash256(
+            &mut self,
+            _hash256: &<$source as $crate::MiniscriptKey>::Hash256,
+        ) -> Result<<$target as $crate::MiniscriptKey>::Hash256, $error_ty> {
+            panic!("Called hash256 on a translator of keys only")
+        }
+
+        fn ripemd160(
+            &mut self,
+            _ripemd160: &<$source as $crate::MiniscriptKey>::Ripemd160,
+        ) -> Result<<$target as $crate::MiniscriptKey>::Ripemd160, $error_ty> {
+            panic!("Called ripemd160 on a translator of keys only")
+        }
+
+        fn hash160(
+            &mut self,
+            _hash160: &<$source as $crate::MiniscriptKey>::Hash160,
+        ) -> Result<<$target as $crate::MiniscriptKey>::Hash160, $error_ty> {
+            panic!("Called hash160 on a translator of keys only")
+        }
+    };
+}
@@ -17,11 +17,14 @@
 //! Tools for determining whether the guarantees offered by the library
 //! actually hold.
 
-use core::fmt;
+use core::{fmt, mem};
 #[cfg(feature = "std")]
 use std::error;
 
+use crate::miniscript::decode::Terminal;
 use crate::miniscript::iter::PkPkh;
+use crate::miniscript::limits::{RuleSet, LOCKTIME_THRESHOLD, SEQUENCE_LOCKTIME_TYPE_FLAG};
+use crate::miniscript::types::Dissat;
 use crate::prelude::*;
 use crate::{Miniscript, MiniscriptKey, ScriptContext};
 
@@ -70,7 +73,7 @@ impl fmt::Display for AnalysisError {
 
 #[cfg(feature = "std")]
 impl error::Error for AnalysisError {
-    fn cause(&self) -> Option<&dyn error::Error> {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
         use self::AnalysisError::*;
 
         match self {
@@ -83,7 +86,371 @@ impl error::Error for AnalysisError {
     }
 }
 
+/// The timelocks required by one way of satisfying a Miniscript, as
+/// returned by [`Miniscript::timelock_info`].
+///
+/// Each field holds the largest value of that kind required along the path,
+/// split by whether the value is interpreted as a block height or as a time.
+/// A path which has both a height and a time of the same kind can never be
+/// satisfied.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PathTimelocks {
+    /// Largest `after` value expressed as a block height
+    pub after_height: Option<u32>,
+    /// Largest `after` value expressed as a unix timestamp
+    pub after_time: Option<u32>,
+    /// Largest `older` value expressed as a number of blocks
+    pub older_height: Option<u32>,
+    /// Largest `older` value expressed in units of 512 seconds
+    /// (the raw sequence value, including the type flag)
+    pub older_time: Option<u32>,
+}
+
+impl PathTimelocks {
+    /// Whether the path requires any timelock at all
+    pub fn is_empty(&self) -> bool {
+        *self == PathTimelocks::default()
+    }
+
+    /// Whether the path mixes heights and times for the same kind of
+    /// timelock, and is thereby unspendable
+    pub fn has_mixed_timelocks(&self) -> bool {
+        (self.after_height.is_some() && self.after_time.is_some())
+            || (self.older_height.is_some() && self.older_time.is_some())
+    }
+
+    fn after(n: u32) -> Self {
+        let mut ret = PathTimelocks::default();
+        if n < LOCKTIME_THRESHOLD {
+            ret.after_height = Some(n);
+        } else {
+            ret.after_time = Some(n);
+        }
+        ret
+    }
+
+    fn older(n: u32) -> Self {
+        let mut ret = PathTimelocks::default();
+        if n & SEQUENCE_LOCKTIME_TYPE_FLAG == 0 {
+            ret.older_height = Some(n);
+        } else {
+            ret.older_time = Some(n);
+        }
+        ret
+    }
+
+    // The timelocks required when both paths must be taken
+    fn combine(self, other: Self) -> Self {
+        PathTimelocks {
+            after_height: self.after_height.max(other.after_height),
+            after_time: self.after_time.max(other.after_time),
+            older_height: self.older_height.max(other.older_height),
+            older_time: self.older_time.max(other.older_time),
+        }
+    }
+}
+
+// Paths for satisfying both `a` and `b`
+fn cross(a: &[PathTimelocks], b: &[PathTimelocks]) -> Vec<PathTimelocks> {
+    let mut ret: Vec<_> = a
+        .iter()
+        .flat_map(|x| b.iter().map(move |y| x.combine(*y)))
+        .collect();
+    ret.sort();
+    ret.dedup();
+    ret
+}
+
+// Paths for satisfying either `a` or `b`
+fn union<T: Ord>(mut a: Vec<T>, b: Vec<T>) -> Vec<T> {
+    a.extend(b);
+    a.sort();
+    a.dedup();
+    a
+}
+
+/// The witness stack used by a way of satisfying a Miniscript, as reported by
+/// [`Miniscript::max_stack_size_per_path`]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PathStackSize {
+    /// The timelocks required by the path
+    pub timelocks: PathTimelocks,
+    /// Maximum number of witness stack elements of the satisfaction, not
+    /// counting the script itself
+    pub stack_elements: usize,
+}
+
+// Paths for satisfying both `a` and `b`
+fn cross_stack(a: &[PathStackSize], b: &[PathStackSize]) -> Vec<PathStackSize> {
+    let mut ret: Vec<_> = a
+        .iter()
+        .flat_map(|x| {
+            b.iter().map(move |y| PathStackSize {
+                timelocks: x.timelocks.combine(y.timelocks),
+                stack_elements: x.stack_elements + y.stack_elements,
+            })
+        })
+        .collect();
+    ret.sort();
+    ret.dedup();
+    ret
+}
+
+// Paths for satisfying `a` along with `extra` elements, or no path at all when
+// `extra` is `None`, i.e. when the fragment pushing them cannot be dissatisfied
+fn add_stack(a: &[PathStackSize], extra: Option<usize>) -> Vec<PathStackSize> {
+    match extra {
+        Some(n) => a
+            .iter()
+            .map(|x| PathStackSize {
+                timelocks: x.timelocks,
+                stack_elements: x.stack_elements + n,
+            })
+            .collect(),
+        None => vec![],
+    }
+}
+
+/// Why a fragment of a Miniscript is malleable, as reported by
+/// [`Miniscript::malleability_report`]
+///
+/// Sub-fragments are identified by their position among the children of the
+/// fragment, e.g. `0` and `1` for the left and right branches of `or_i`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MalleabilityReason {
+    /// The listed sub-fragments can be satisfied without a signature, so a
+    /// third party can switch the satisfaction to one of them
+    SiglessBranches(Vec<usize>),
+    /// The sub-fragment does not have a unique dissatisfaction, so a third
+    /// party can replace its dissatisfaction by another one
+    NonUniqueDissatisfaction(usize),
+}
+
+impl fmt::Display for MalleabilityReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MalleabilityReason::SiglessBranches(ref subs) => {
+                f.write_str("branches")?;
+                for (i, sub) in subs.iter().enumerate() {
+                    write!(f, "{}{}", if i == 0 { " " } else { ", " }, sub)?;
+                }
+                f.write_str(" are satisfiable by a third party without signature")
+            }
+            MalleabilityReason::NonUniqueDissatisfaction(sub) => write!(
+                f,
+                "branch {} can be dissatisfied by a third party in more than one way",
+                sub
+            ),
+        }
+    }
+}
+
+/// A malleable fragment of a Miniscript, as reported by
+/// [`Miniscript::malleability_report`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MalleabilityIssue<'a, Pk: MiniscriptKey, Ctx: ScriptContext> {
+    /// The malleable fragment
+    pub fragment: &'a Miniscript<Pk, Ctx>,
+    /// Why the fragment is malleable
+    pub reason: MalleabilityReason,
+}
+
+impl<'a, Pk: MiniscriptKey, Ctx: ScriptContext> fmt::Display for MalleabilityIssue<'a, Pk, Ctx> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.fragment, self.reason)
+    }
+}
+
+// The reasons for which `ms` is malleable, even if all its children are not
+fn malleability_reasons<Pk: MiniscriptKey, Ctx: ScriptContext>(
+    ms: &Miniscript<Pk, Ctx>,
+) -> Vec<MalleabilityReason> {
+    let mut ret = vec![];
+    let mut check_dissat = |i: usize, sub: &Miniscript<Pk, Ctx>| {
+        if sub.ty.mall.dissat != Dissat::Unique {
+            ret.push(MalleabilityReason::NonUniqueDissatisfaction(i));
+        }
+    };
+    // The branches which must not all be sigless, and how many of them may be
+    let (branches, max_sigless): (Vec<&Miniscript<Pk, Ctx>>, usize) = match ms.node {
+        Terminal::OrB(ref l, ref r) => {
+            check_dissat(0, l);
+            check_dissat(1, r);
+            (vec![l, r], 1)
+        }
+        Terminal::OrD(ref l, ref r) | Terminal::OrC(ref l, ref r) => {
+            check_dissat(0, l);
+            (vec![l, r], 1)
+        }
+        Terminal::OrI(ref l, ref r) => (vec![l, r], 1),
+        Terminal::AndOr(ref a, ref b, ref c) => {
+            check_dissat(0, a);
+            (vec![a, b, c], 2)
+        }
+        Terminal::Thresh(k, ref subs) => {
+            for (i, sub) in subs.iter().enumerate() {
+                check_dissat(i, sub);
+            }
+            (subs.iter().map(|sub| &**sub).collect(), k)
+        }
+        _ => (vec![], 0),
+    };
+    let sigless: Vec<usize> = branches
+        .iter()
+        .enumerate()
+        .filter(|&(_, sub)| !sub.ty.mall.safe)
+        .map(|(i, _)| i)
+        .collect();
+    if sigless.len() > max_sigless {
+        ret.push(MalleabilityReason::SiglessBranches(sigless));
+    }
+    ret
+}
+
 impl<Pk: MiniscriptKey, Ctx: ScriptContext> Miniscript<Pk, Ctx> {
+    /// Returns the timelocks required by each way of satisfying the Miniscript.
+    ///
+    /// Spend paths which only differ in the keys or hash preimages they need
+    /// are reported once, so that every returned [`PathTimelocks`] is distinct.
+    /// A path requiring no timelock is reported as an empty [`PathTimelocks`].
+    /// This allows e.g. computing the earliest height at which a recovery
+    /// path becomes spendable.
+    pub fn timelock_info(&self) -> Vec<PathTimelocks> {
+        match self.node {
+            Terminal::False => vec![],
+            Terminal::After(n) => vec![PathTimelocks::after(n)],
+            Terminal::Older(n) => vec![PathTimelocks::older(n)],
+            Terminal::True
+            | Terminal::PkK(..)
+            | Terminal::PkH(..)
+            | Terminal::Sha256(..)
+            | Terminal::Hash256(..)
+            | Terminal::Ripemd160(..)
+            | Terminal::Hash160(..)
+            | Terminal::Multi(..)
+            | Terminal::MultiA(..)
+            | Terminal::SortedMultiA(..) => vec![PathTimelocks::default()],
+            Terminal::Alt(ref sub)
+            | Terminal::Swap(ref sub)
+            | Terminal::Check(ref sub)
+            | Terminal::DupIf(ref sub)
+            | Terminal::Verify(ref sub)
+            | Terminal::NonZero(ref sub)
+            | Terminal::ZeroNotEqual(ref sub) => sub.timelock_info(),
+            Terminal::AndV(ref a, ref b) | Terminal::AndB(ref a, ref b) => {
+                cross(&a.timelock_info(), &b.timelock_info())
+            }
+            Terminal::AndOr(ref a, ref b, ref c) => union(
+                cross(&a.timelock_info(), &b.timelock_info()),
+                c.timelock_info(),
+            ),
+            Terminal::OrB(ref a, ref b)
+            | Terminal::OrD(ref a, ref b)
+            | Terminal::OrC(ref a, ref b)
+            | Terminal::OrI(ref a, ref b) => union(a.timelock_info(), b.timelock_info()),
+            Terminal::Thresh(k, ref subs) => {
+                // by_count[j] holds the paths satisfying exactly j of the subs
+                // seen so far
+                let mut by_count = vec![vec![]; k + 1];
+                by_count[0].push(PathTimelocks::default());
+                for sub in subs {
+                    let sub_paths = sub.timelock_info();
+                    for j in (1..k + 1).rev() {
+                        let new_paths = cross(&by_count[j - 1], &sub_paths);
+                        let old_paths = mem::take(&mut by_count[j]);
+                        by_count[j] = union(old_paths, new_paths);
+                    }
+                }
+                by_count.pop().expect("k + 1 > 0")
+            }
+        }
+    }
+
+    /// Returns the timelocks and the maximum number of witness stack elements
+    /// of each way of satisfying the Miniscript.
+    ///
+    /// The paths are the ones of [`Miniscript::timelock_info`], reported once
+    /// for each distinct stack size. The branches which are not taken are
+    /// counted with their largest dissatisfaction. This allows finding the
+    /// paths of a `wsh` descriptor which exceed the standardness limit of
+    /// [`MAX_STANDARD_P2WSH_STACK_ITEMS`](crate::miniscript::limits::MAX_STANDARD_P2WSH_STACK_ITEMS)
+    /// elements, and can therefore not be relayed, before funds are sent to it.
+    pub fn max_stack_size_per_path(&self) -> Vec<PathStackSize> {
+        let leaf = |n: usize| {
+            vec![PathStackSize {
+                timelocks: PathTimelocks::default(),
+                stack_elements: n,
+            }]
+        };
+        let dissat = |ms: &Self| ms.ext.stack_elem_count_dissat;
+        match self.node {
+            Terminal::False => vec![],
+            Terminal::True => leaf(0),
+            Terminal::After(n) => vec![PathStackSize {
+                timelocks: PathTimelocks::after(n),
+                stack_elements: 0,
+            }],
+            Terminal::Older(n) => vec![PathStackSize {
+                timelocks: PathTimelocks::older(n),
+                stack_elements: 0,
+            }],
+            Terminal::PkK(..)
+            | Terminal::Sha256(..)
+            | Terminal::Hash256(..)
+            | Terminal::Ripemd160(..)
+            | Terminal::Hash160(..) => leaf(1),
+            Terminal::PkH(..) => leaf(2),
+            Terminal::Multi(k, _) => leaf(k + 1),
+            Terminal::MultiA(_, ref pks) | Terminal::SortedMultiA(_, ref pks) => leaf(pks.len()),
+            Terminal::Alt(ref sub)
+            | Terminal::Swap(ref sub)
+            | Terminal::Check(ref sub)
+            | Terminal::Verify(ref sub)
+            | Terminal::NonZero(ref sub)
+            | Terminal::ZeroNotEqual(ref sub) => sub.max_stack_size_per_path(),
+            Terminal::DupIf(ref sub) => add_stack(&sub.max_stack_size_per_path(), Some(1)),
+            Terminal::AndV(ref a, ref b) | Terminal::AndB(ref a, ref b) => {
+                cross_stack(&a.max_stack_size_per_path(), &b.max_stack_size_per_path())
+            }
+            Terminal::AndOr(ref a, ref b, ref c) => union(
+                cross_stack(&a.max_stack_size_per_path(), &b.max_stack_size_per_path()),
+                add_stack(&c.max_stack_size_per_path(), dissat(a)),
+            ),
+            Terminal::OrB(ref a, ref b) => union(
+                add_stack(&a.max_stack_size_per_path(), dissat(b)),
+                add_stack(&b.max_stack_size_per_path(), dissat(a)),
+            ),
+            Terminal::OrD(ref a, ref b) | Terminal::OrC(ref a, ref b) => union(
+                a.max_stack_size_per_path(),
+                add_stack(&b.max_stack_size_per_path(), dissat(a)),
+            ),
+            Terminal::OrI(ref a, ref b) => union(
+                add_stack(&a.max_stack_size_per_path(), Some(1)),
+                add_stack(&b.max_stack_size_per_path(), Some(1)),
+            ),
+            Terminal::Thresh(k, ref subs) => {
+                // by_count[j] holds the paths satisfying exactly j of the subs
+                // seen so far and dissatisfying the others
+                let mut by_count = vec![vec![]; k + 1];
+                by_count[0] = leaf(0);
+                for sub in subs {
+                    let sub_paths = sub.max_stack_size_per_path();
+                    for j in (0..k + 1).rev() {
+                        let old_paths = mem::take(&mut by_count[j]);
+                        let mut new_paths = add_stack(&old_paths, dissat(sub));
+                        if j > 0 {
+                            new_paths.extend(cross_stack(&by_count[j - 1], &sub_paths));
+                        }
+                        new_paths.sort();
+                        new_paths.dedup();
+                        by_count[j] = new_paths;
+                    }
+                }
+                by_count.pop().expect("k + 1 > 0")
+            }
+        }
+    }
+
     /// Whether all spend paths of miniscript require a signature
     pub fn requires_sig(&self) -> bool {
         self.ty.mall.safe
@@ -94,6 +461,22 @@ impl<Pk: MiniscriptKey, Ctx: ScriptContext> Miniscript<Pk, Ctx> {
         self.ty.mall.non_malleable
     }
 
+    /// Lists the fragments of the miniscript which make it malleable, and why.
+    ///
+    /// Each fragment is reported only for the malleability it introduces
+    /// itself: a fragment which is only malleable because one of its
+    /// sub-fragments is, is not reported. The report is empty if and only if
+    /// the miniscript is non-malleable.
+    pub fn malleability_report(&self) -> Vec<MalleabilityIssue<'_, Pk, Ctx>> {
+        self.iter()
+            .flat_map(|fragment| {
+                malleability_reasons(fragment)
+                    .into_iter()
+                    .map(move |reason| MalleabilityIssue { fragment, reason })
+            })
+            .collect()
+    }
+
     /// Whether the miniscript can exceed the resource limits(Opcodes, Stack limit etc)
     // It maybe possible to return a detail error type containing why the miniscript
     // failed. But doing so may require returning a collection of errors
@@ -101,6 +484,22 @@ impl<Pk: MiniscriptKey, Ctx: ScriptContext> Miniscript<Pk, Ctx> {
         Ctx::check_local_validity(self).is_ok()
     }
 
+    /// Whether the miniscript is within the resource limits of its context
+    /// under `rules`
+    pub fn within_resource_limits_with(&self, rules: &RuleSet) -> bool {
+        let policy = match *rules {
+            RuleSet::Standard => {
+                Ctx::check_global_policy_validity(self).is_ok()
+                    && Ctx::check_local_policy_validity(self).is_ok()
+            }
+            RuleSet::Consensus | RuleSet::Custom { .. } => true,
+        };
+        policy
+            && Ctx::check_global_consensus_validity(self).is_ok()
+            && Ctx::check_local_consensus_validity(self).is_ok()
+            && self.check_resource_limits_with(rules).is_ok()
+    }
+
     /// Whether the miniscript contains a combination of timelocks
     pub fn has_mixed_timelocks(&self) -> bool {
         self.ext.timelock_info.contains_unspendable_path()
@@ -148,4 +547,23 @@ impl<Pk: MiniscriptKey, Ctx: ScriptContext> Miniscript<Pk, Ctx> {
             Ok(())
         }
     }
+
+    /// Check whether the underlying Miniscript is safe as
+    /// [`Miniscript::sanity_check`] does, checking its resource usage against
+    /// `rules` rather than the standardness rules of its context
+    pub fn sanity_check_with(&self, rules: &RuleSet) -> Result<(), AnalysisError> {
+        if !self.requires_sig() {
+            Err(AnalysisError::SiglessBranch)
+        } else if !self.is_non_malleable() {
+            Err(AnalysisError::Malleable)
+        } else if !self.within_resource_limits_with(rules) {
+            Err(AnalysisError::BranchExceedResouceLimits)
+        } else if self.has_repeated_keys() {
+            Err(AnalysisError::RepeatedPubkeys)
+        } else if self.has_mixed_timelocks() {
+            Err(AnalysisError::HeightTimelockCombination)
+        } else {
+            Ok(())
+        }
+    }
 }
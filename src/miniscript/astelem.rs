@@ -23,18 +23,16 @@ use core::str::FromStr;
 use core::{fmt, str};
 
 use bitcoin::blockdata::{opcodes, script};
-use bitcoin::hashes::hex::FromHex;
-use bitcoin::hashes::{hash160, ripemd160, sha256, sha256d, Hash};
 use sync::Arc;
 
-use crate::miniscript::context::SigType;
+use crate::miniscript::context::{self, SigType};
 use crate::miniscript::types::{self, Property};
 use crate::miniscript::ScriptContext;
 use crate::prelude::*;
-use crate::util::MsKeyBuilder;
+use crate::util::{sort_xonly_keys, MsKeyBuilder};
 use crate::{
     errstr, expression, script_num_size, Error, ForEach, ForEachKey, Miniscript, MiniscriptKey,
-    Terminal, ToPublicKey, TranslatePk,
+    Terminal, ToPublicKey, TranslatePk, Translator,
 };
 
 impl<Pk: MiniscriptKey, Ctx: ScriptContext> Terminal<Pk, Ctx> {
@@ -71,12 +69,11 @@ where
     /// # Panics
     ///
     /// While converting to Segwit Miniscript using uncompressed public keys.
-    fn translate_pk<Fpk, Fpkh, E>(&self, mut fpk: Fpk, mut fpkh: Fpkh) -> Result<Self::Output, E>
+    fn translate_pk<T, E>(&self, translator: &mut T) -> Result<Self::Output, E>
     where
-        Fpk: FnMut(&Pk) -> Result<Q, E>,
-        Fpkh: FnMut(&Pk::Hash) -> Result<Q::Hash, E>,
+        T: Translator<Pk, Q, E> + ?Sized,
     {
-        self.real_translate_pk(&mut fpk, &mut fpkh)
+        self.real_translate_pk(translator)
     }
 }
 
@@ -121,95 +118,86 @@ impl<Pk: MiniscriptKey, Ctx: ScriptContext> Terminal<Pk, Ctx> {
                     && c.real_for_each_key(pred)
             }
             Terminal::Thresh(_, ref subs) => subs.iter().all(|sub| sub.real_for_each_key(pred)),
-            Terminal::Multi(_, ref keys) | Terminal::MultiA(_, ref keys) => {
-                keys.iter().all(|key| pred(ForEach::Key(key)))
-            }
+            Terminal::Multi(_, ref keys)
+            | Terminal::MultiA(_, ref keys)
+            | Terminal::SortedMultiA(_, ref keys) => keys.iter().all(|key| pred(ForEach::Key(key))),
         }
     }
 
-    pub(super) fn real_translate_pk<Fpk, Fpkh, Q, E, CtxQ>(
-        &self,
-        fpk: &mut Fpk,
-        fpkh: &mut Fpkh,
-    ) -> Result<Terminal<Q, CtxQ>, E>
+    pub(super) fn real_translate_pk<Q, E, CtxQ, T>(&self, t: &mut T) -> Result<Terminal<Q, CtxQ>, E>
     where
-        Fpk: FnMut(&Pk) -> Result<Q, E>,
-        Fpkh: FnMut(&Pk::Hash) -> Result<Q::Hash, E>,
         Q: MiniscriptKey,
         CtxQ: ScriptContext,
+        T: Translator<Pk, Q, E> + ?Sized,
     {
         let frag: Terminal<Q, CtxQ> = match *self {
-            Terminal::PkK(ref p) => Terminal::PkK(fpk(p)?),
-            Terminal::PkH(ref p) => Terminal::PkH(fpkh(p)?),
+            Terminal::PkK(ref p) => Terminal::PkK(t.pk(p)?),
+            Terminal::PkH(ref p) => Terminal::PkH(t.pkh(p)?),
             Terminal::After(n) => Terminal::After(n),
             Terminal::Older(n) => Terminal::Older(n),
-            Terminal::Sha256(x) => Terminal::Sha256(x),
-            Terminal::Hash256(x) => Terminal::Hash256(x),
-            Terminal::Ripemd160(x) => Terminal::Ripemd160(x),
-            Terminal::Hash160(x) => Terminal::Hash160(x),
+            Terminal::Sha256(ref x) => Terminal::Sha256(t.sha256(x)?),
+            Terminal::Hash256(ref x) => Terminal::Hash256(t.hash256(x)?),
+            Terminal::Ripemd160(ref x) => Terminal::Ripemd160(t.ripemd160(x)?),
+            Terminal::Hash160(ref x) => Terminal::Hash160(t.hash160(x)?),
             Terminal::True => Terminal::True,
             Terminal::False => Terminal::False,
-            Terminal::Alt(ref sub) => Terminal::Alt(Arc::new(sub.real_translate_pk(fpk, fpkh)?)),
-            Terminal::Swap(ref sub) => Terminal::Swap(Arc::new(sub.real_translate_pk(fpk, fpkh)?)),
-            Terminal::Check(ref sub) => {
-                Terminal::Check(Arc::new(sub.real_translate_pk(fpk, fpkh)?))
-            }
-            Terminal::DupIf(ref sub) => {
-                Terminal::DupIf(Arc::new(sub.real_translate_pk(fpk, fpkh)?))
-            }
-            Terminal::Verify(ref sub) => {
-                Terminal::Verify(Arc::new(sub.real_translate_pk(fpk, fpkh)?))
-            }
-            Terminal::NonZero(ref sub) => {
-                Terminal::NonZero(Arc::new(sub.real_translate_pk(fpk, fpkh)?))
-            }
+            Terminal::Alt(ref sub) => Terminal::Alt(Arc::new(sub.real_translate_pk(t)?)),
+            Terminal::Swap(ref sub) => Terminal::Swap(Arc::new(sub.real_translate_pk(t)?)),
+            Terminal::Check(ref sub) => Terminal::Check(Arc::new(sub.real_translate_pk(t)?)),
+            Terminal::DupIf(ref sub) => Terminal::DupIf(Arc::new(sub.real_translate_pk(t)?)),
+            Terminal::Verify(ref sub) => Terminal::Verify(Arc::new(sub.real_translate_pk(t)?)),
+            Terminal::NonZero(ref sub) => Terminal::NonZero(Arc::new(sub.real_translate_pk(t)?)),
             Terminal::ZeroNotEqual(ref sub) => {
-                Terminal::ZeroNotEqual(Arc::new(sub.real_translate_pk(fpk, fpkh)?))
+                Terminal::ZeroNotEqual(Arc::new(sub.real_translate_pk(t)?))
             }
             Terminal::AndV(ref left, ref right) => Terminal::AndV(
-                Arc::new(left.real_translate_pk(&mut *fpk, &mut *fpkh)?),
-                Arc::new(right.real_translate_pk(fpk, fpkh)?),
+                Arc::new(left.real_translate_pk(t)?),
+                Arc::new(right.real_translate_pk(t)?),
             ),
             Terminal::AndB(ref left, ref right) => Terminal::AndB(
-                Arc::new(left.real_translate_pk(&mut *fpk, &mut *fpkh)?),
-                Arc::new(right.real_translate_pk(fpk, fpkh)?),
+                Arc::new(left.real_translate_pk(t)?),
+                Arc::new(right.real_translate_pk(t)?),
             ),
             Terminal::AndOr(ref a, ref b, ref c) => Terminal::AndOr(
-                Arc::new(a.real_translate_pk(&mut *fpk, &mut *fpkh)?),
-                Arc::new(b.real_translate_pk(&mut *fpk, &mut *fpkh)?),
-                Arc::new(c.real_translate_pk(fpk, fpkh)?),
+                Arc::new(a.real_translate_pk(t)?),
+                Arc::new(b.real_translate_pk(t)?),
+                Arc::new(c.real_translate_pk(t)?),
             ),
             Terminal::OrB(ref left, ref right) => Terminal::OrB(
-                Arc::new(left.real_translate_pk(&mut *fpk, &mut *fpkh)?),
-                Arc::new(right.real_translate_pk(fpk, fpkh)?),
+                Arc::new(left.real_translate_pk(t)?),
+                Arc::new(right.real_translate_pk(t)?),
             ),
             Terminal::OrD(ref left, ref right) => Terminal::OrD(
-                Arc::new(left.real_translate_pk(&mut *fpk, &mut *fpkh)?),
-                Arc::new(right.real_translate_pk(fpk, fpkh)?),
+                Arc::new(left.real_translate_pk(t)?),
+                Arc::new(right.real_translate_pk(t)?),
             ),
             Terminal::OrC(ref left, ref right) => Terminal::OrC(
-                Arc::new(left.real_translate_pk(&mut *fpk, &mut *fpkh)?),
-                Arc::new(right.real_translate_pk(fpk, fpkh)?),
+                Arc::new(left.real_translate_pk(t)?),
+                Arc::new(right.real_translate_pk(t)?),
             ),
             Terminal::OrI(ref left, ref right) => Terminal::OrI(
-                Arc::new(left.real_translate_pk(&mut *fpk, &mut *fpkh)?),
-                Arc::new(right.real_translate_pk(fpk, fpkh)?),
+                Arc::new(left.real_translate_pk(t)?),
+                Arc::new(right.real_translate_pk(t)?),
             ),
             Terminal::Thresh(k, ref subs) => {
                 let subs: Result<Vec<Arc<Miniscript<Q, _>>>, _> = subs
                     .iter()
-                    .map(|s| s.real_translate_pk(&mut *fpk, &mut *fpkh).map(Arc::new))
+                    .map(|s| s.real_translate_pk(t).map(Arc::new))
                     .collect();
                 Terminal::Thresh(k, subs?)
             }
             Terminal::Multi(k, ref keys) => {
-                let keys: Result<Vec<Q>, _> = keys.iter().map(&mut *fpk).collect();
+                let keys: Result<Vec<Q>, _> = keys.iter().map(|k| t.pk(k)).collect();
                 Terminal::Multi(k, keys?)
             }
             Terminal::MultiA(k, ref keys) => {
-                let keys: Result<Vec<Q>, _> = keys.iter().map(&mut *fpk).collect();
+                let keys: Result<Vec<Q>, _> = keys.iter().map(|k| t.pk(k)).collect();
                 Terminal::MultiA(k, keys?)
             }
+            Terminal::SortedMultiA(k, ref keys) => {
+                let keys: Result<Vec<Q>, _> = keys.iter().map(|k| t.pk(k)).collect();
+                Terminal::SortedMultiA(k, keys?)
+            }
         };
         Ok(frag)
     }
@@ -276,14 +264,10 @@ impl<Pk: MiniscriptKey, Ctx: ScriptContext> fmt::Debug for Terminal<Pk, Ctx> {
                 Terminal::PkH(ref pkh) => write!(f, "pk_h({:?})", pkh),
                 Terminal::After(t) => write!(f, "after({})", t),
                 Terminal::Older(t) => write!(f, "older({})", t),
-                Terminal::Sha256(h) => write!(f, "sha256({})", h),
-                Terminal::Hash256(h) => {
-                    let mut x = h.into_inner();
-                    x.reverse();
-                    write!(f, "hash256({})", sha256d::Hash::from_inner(x))
-                }
-                Terminal::Ripemd160(h) => write!(f, "ripemd160({})", h),
-                Terminal::Hash160(h) => write!(f, "hash160({})", h),
+                Terminal::Sha256(ref h) => write!(f, "sha256({})", h),
+                Terminal::Hash256(ref h) => write!(f, "hash256({})", h),
+                Terminal::Ripemd160(ref h) => write!(f, "ripemd160({})", h),
+                Terminal::Hash160(ref h) => write!(f, "hash160({})", h),
                 Terminal::True => f.write_str("1"),
                 Terminal::False => f.write_str("0"),
                 Terminal::AndV(ref l, ref r) => write!(f, "and_v({:?},{:?})", l, r),
@@ -320,6 +304,13 @@ impl<Pk: MiniscriptKey, Ctx: ScriptContext> fmt::Debug for Terminal<Pk, Ctx> {
                     }
                     f.write_str(")")
                 }
+                Terminal::SortedMultiA(k, ref keys) => {
+                    write!(f, "sortedmulti_a({}", k)?;
+                    for k in keys {
+                        write!(f, ",{:?}", k)?;
+                    }
+                    f.write_str(")")
+                }
                 _ => unreachable!(),
             }
         }
@@ -333,14 +324,10 @@ impl<Pk: MiniscriptKey, Ctx: ScriptContext> fmt::Display for Terminal<Pk, Ctx> {
             Terminal::PkH(ref pkh) => write!(f, "pk_h({})", pkh),
             Terminal::After(t) => write!(f, "after({})", t),
             Terminal::Older(t) => write!(f, "older({})", t),
-            Terminal::Sha256(h) => write!(f, "sha256({})", h),
-            Terminal::Hash256(h) => {
-                let mut x = h.into_inner();
-                x.reverse();
-                write!(f, "hash256({})", sha256d::Hash::from_inner(x))
-            }
-            Terminal::Ripemd160(h) => write!(f, "ripemd160({})", h),
-            Terminal::Hash160(h) => write!(f, "hash160({})", h),
+            Terminal::Sha256(ref h) => write!(f, "sha256({})", h),
+            Terminal::Hash256(ref h) => write!(f, "hash256({})", h),
+            Terminal::Ripemd160(ref h) => write!(f, "ripemd160({})", h),
+            Terminal::Hash160(ref h) => write!(f, "hash160({})", h),
             Terminal::True => f.write_str("1"),
             Terminal::False => f.write_str("0"),
             Terminal::AndV(ref l, ref r) if r.node != Terminal::True => {
@@ -383,6 +370,13 @@ impl<Pk: MiniscriptKey, Ctx: ScriptContext> fmt::Display for Terminal<Pk, Ctx> {
                 }
                 f.write_str(")")
             }
+            Terminal::SortedMultiA(k, ref keys) => {
+                write!(f, "sortedmulti_a({}", k)?;
+                for k in keys {
+                    write!(f, ",{}", k)?;
+                }
+                f.write_str(")")
+            }
             // wrappers
             _ => {
                 if let Some((ch, sub)) = self.wrap_char() {
@@ -422,9 +416,17 @@ impl<Pk, Ctx> expression::FromTree for Arc<Terminal<Pk, Ctx>>
 where
     Pk: MiniscriptKey + str::FromStr,
     Pk::Hash: str::FromStr,
+    Pk::Sha256: str::FromStr,
+    Pk::Hash256: str::FromStr,
+    Pk::Ripemd160: str::FromStr,
+    Pk::Hash160: str::FromStr,
     Ctx: ScriptContext,
     <Pk as str::FromStr>::Err: ToString,
     <<Pk as MiniscriptKey>::Hash as str::FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Sha256 as str::FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Hash256 as str::FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Ripemd160 as str::FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Hash160 as str::FromStr>::Err: ToString,
 {
     fn from_tree(top: &expression::Tree) -> Result<Arc<Terminal<Pk, Ctx>>, Error> {
         Ok(Arc::new(expression::FromTree::from_tree(top)?))
@@ -435,9 +437,17 @@ impl<Pk, Ctx> expression::FromTree for Terminal<Pk, Ctx>
 where
     Pk: MiniscriptKey + str::FromStr,
     Pk::Hash: str::FromStr,
+    Pk::Sha256: str::FromStr,
+    Pk::Hash256: str::FromStr,
+    Pk::Ripemd160: str::FromStr,
+    Pk::Hash160: str::FromStr,
     Ctx: ScriptContext,
     <Pk as str::FromStr>::Err: ToString,
     <<Pk as MiniscriptKey>::Hash as str::FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Sha256 as str::FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Hash256 as str::FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Ripemd160 as str::FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Hash160 as str::FromStr>::Err: ToString,
 {
     fn from_tree(top: &expression::Tree) -> Result<Terminal<Pk, Ctx>, Error> {
         let mut aliased_wrap;
@@ -498,22 +508,16 @@ where
                 expression::parse_num(x).map(Terminal::Older)
             }),
             ("sha256", 1) => expression::terminal(&top.args[0], |x| {
-                sha256::Hash::from_hex(x).map(Terminal::Sha256)
+                Pk::Sha256::from_str(x).map(Terminal::Sha256)
             }),
             ("hash256", 1) => expression::terminal(&top.args[0], |x| {
-                sha256d::Hash::from_hex(x)
-                    .map(|x| x.into_inner())
-                    .map(|mut x| {
-                        x.reverse();
-                        x
-                    })
-                    .map(|x| Terminal::Hash256(sha256d::Hash::from_inner(x)))
+                Pk::Hash256::from_str(x).map(Terminal::Hash256)
             }),
             ("ripemd160", 1) => expression::terminal(&top.args[0], |x| {
-                ripemd160::Hash::from_hex(x).map(Terminal::Ripemd160)
+                Pk::Ripemd160::from_str(x).map(Terminal::Ripemd160)
             }),
             ("hash160", 1) => expression::terminal(&top.args[0], |x| {
-                hash160::Hash::from_hex(x).map(Terminal::Hash160)
+                Pk::Hash160::from_str(x).map(Terminal::Hash160)
             }),
             ("1", 0) => Ok(Terminal::True),
             ("0", 0) => Ok(Terminal::False),
@@ -552,7 +556,7 @@ where
 
                 Ok(Terminal::Thresh(k, subs?))
             }
-            ("multi", n) | ("multi_a", n) => {
+            ("multi", n) | ("multi_a", n) | ("sortedmulti_a", n) => {
                 if n == 0 {
                     return Err(errstr("no arguments given"));
                 }
@@ -566,11 +570,11 @@ where
                     .map(|sub| expression::terminal(sub, Pk::from_str))
                     .collect();
 
-                if frag_name == "multi" {
-                    pks.map(|pks| Terminal::Multi(k, pks))
-                } else {
-                    // must be multi_a
-                    pks.map(|pks| Terminal::MultiA(k, pks))
+                match frag_name {
+                    "multi" => pks.map(|pks| Terminal::Multi(k, pks)),
+                    "multi_a" => pks.map(|pks| Terminal::MultiA(k, pks)),
+                    // must be sortedmulti_a
+                    _ => pks.map(|pks| Terminal::SortedMultiA(k, pks)),
                 }
             }
             _ => Err(Error::Unexpected(format!(
@@ -582,7 +586,7 @@ where
         for ch in frag_wrap.chars().rev() {
             // Check whether the wrapper is valid under the current context
             let ms = Miniscript::from_ast(unwrapped)?;
-            Ctx::check_global_validity(&ms)?;
+            Ctx::check_global_validity(&ms).map_err(|e| context::mark_key::<Pk>(e.into(), top))?;
             match ch {
                 'a' => unwrapped = Terminal::Alt(Arc::new(ms)),
                 's' => unwrapped = Terminal::Swap(Arc::new(ms)),
@@ -617,7 +621,7 @@ where
         }
         // Check whether the unwrapped miniscript is valid under the current context
         let ms = Miniscript::from_ast(unwrapped)?;
-        Ctx::check_global_validity(&ms)?;
+        Ctx::check_global_validity(&ms).map_err(|e| context::mark_key::<Pk>(e.into(), top))?;
         Ok(ms.node)
     }
 }
@@ -657,33 +661,33 @@ impl<Pk: MiniscriptKey, Ctx: ScriptContext> Terminal<Pk, Ctx> {
                 .push_int(t as i64)
                 .push_opcode(opcodes::all::OP_CLTV),
             Terminal::Older(t) => builder.push_int(t as i64).push_opcode(opcodes::all::OP_CSV),
-            Terminal::Sha256(h) => builder
+            Terminal::Sha256(ref h) => builder
                 .push_opcode(opcodes::all::OP_SIZE)
                 .push_int(32)
                 .push_opcode(opcodes::all::OP_EQUALVERIFY)
                 .push_opcode(opcodes::all::OP_SHA256)
-                .push_slice(&h[..])
+                .push_slice(&Pk::to_sha256(h)[..])
                 .push_opcode(opcodes::all::OP_EQUAL),
-            Terminal::Hash256(h) => builder
+            Terminal::Hash256(ref h) => builder
                 .push_opcode(opcodes::all::OP_SIZE)
                 .push_int(32)
                 .push_opcode(opcodes::all::OP_EQUALVERIFY)
                 .push_opcode(opcodes::all::OP_HASH256)
-                .push_slice(&h[..])
+                .push_slice(&Pk::to_hash256(h)[..])
                 .push_opcode(opcodes::all::OP_EQUAL),
-            Terminal::Ripemd160(h) => builder
+            Terminal::Ripemd160(ref h) => builder
                 .push_opcode(opcodes::all::OP_SIZE)
                 .push_int(32)
                 .push_opcode(opcodes::all::OP_EQUALVERIFY)
                 .push_opcode(opcodes::all::OP_RIPEMD160)
-                .push_slice(&h[..])
+                .push_slice(&Pk::to_ripemd160(h)[..])
                 .push_opcode(opcodes::all::OP_EQUAL),
-            Terminal::Hash160(h) => builder
+            Terminal::Hash160(ref h) => builder
                 .push_opcode(opcodes::all::OP_SIZE)
                 .push_int(32)
                 .push_opcode(opcodes::all::OP_EQUALVERIFY)
                 .push_opcode(opcodes::all::OP_HASH160)
-                .push_slice(&h[..])
+                .push_slice(&Pk::to_hash160(h)[..])
                 .push_opcode(opcodes::all::OP_EQUAL),
             Terminal::True => builder.push_opcode(opcodes::OP_TRUE),
             Terminal::False => builder.push_opcode(opcodes::OP_FALSE),
@@ -775,6 +779,9 @@ impl<Pk: MiniscriptKey, Ctx: ScriptContext> Terminal<Pk, Ctx> {
                     .push_int(k as i64)
                     .push_opcode(opcodes::all::OP_NUMEQUAL)
             }
+            Terminal::SortedMultiA(k, ref keys) => {
+                Terminal::<Pk, Ctx>::MultiA(k, sort_xonly_keys(keys)).encode(builder)
+            }
         }
     }
 
@@ -829,7 +836,7 @@ impl<Pk: MiniscriptKey, Ctx: ScriptContext> Terminal<Pk, Ctx> {
                     + script_num_size(pks.len())
                     + pks.iter().map(|pk| Ctx::pk_len(pk)).sum::<usize>()
             }
-            Terminal::MultiA(k, ref pks) => {
+            Terminal::MultiA(k, ref pks) | Terminal::SortedMultiA(k, ref pks) => {
                 script_num_size(k)
                     + 1 // NUMEQUAL
                     + pks.iter().map(|pk| Ctx::pk_len(pk)).sum::<usize>() // n keys
@@ -12,6 +12,7 @@
 // If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
 //
 
+use core::str::FromStr;
 use core::{fmt, hash};
 #[cfg(feature = "std")]
 use std::error;
@@ -21,14 +22,14 @@ use bitcoin::blockdata::constants::MAX_BLOCK_WEIGHT;
 
 use super::decode::ParseableKey;
 use crate::miniscript::limits::{
-    MAX_OPS_PER_SCRIPT, MAX_PUBKEYS_PER_MULTISIG, MAX_SCRIPTSIG_SIZE, MAX_SCRIPT_ELEMENT_SIZE,
-    MAX_SCRIPT_SIZE, MAX_STACK_SIZE, MAX_STANDARD_P2WSH_SCRIPT_SIZE,
-    MAX_STANDARD_P2WSH_STACK_ITEMS,
+    LimitExceeded, ResourceLimit, ResourceUsage, MAX_OPS_PER_SCRIPT, MAX_PUBKEYS_PER_MULTISIG,
+    MAX_SCRIPTSIG_SIZE, MAX_SCRIPT_ELEMENT_SIZE, MAX_SCRIPT_SIZE, MAX_STACK_SIZE,
+    MAX_STANDARD_P2WSH_SCRIPT_SIZE, MAX_STANDARD_P2WSH_STACK_ITEMS,
 };
 use crate::miniscript::types;
 use crate::prelude::*;
-use crate::util::witness_to_scriptsig;
-use crate::{Error, Miniscript, MiniscriptKey, Terminal};
+use crate::util::{script_sig_push_len, witness_to_scriptsig};
+use crate::{expression, Error, Miniscript, MiniscriptKey, Terminal};
 
 /// Error for Script Context
 #[derive(Clone, PartialEq, Eq, Debug)]
@@ -45,16 +46,26 @@ pub enum ScriptContextError {
     MalleableDupIf,
     /// Only Compressed keys allowed under current descriptor
     /// Segwitv0 fragments do not allow uncompressed pubkeys
+    #[deprecated(since = "8.0.0", note = "Keys are reported as ScriptContextError::Key")]
     CompressedOnly(String),
     /// XOnly keys are only allowed in Tap context
     /// The first element is key, and second element is current script context
+    #[deprecated(since = "8.0.0", note = "Keys are reported as ScriptContextError::Key")]
     XOnlyKeysNotAllowed(String, &'static str),
     /// Tapscript descriptors cannot contain uncompressed keys
     /// Tap context can contain compressed or xonly
+    #[deprecated(since = "8.0.0", note = "Keys are reported as ScriptContextError::Key")]
     UncompressedKeysNotAllowed,
+    /// A key is not allowed by the rules of the script context
+    Key(ContextError),
     /// At least one satisfaction path in the Miniscript fragment has more than
     /// `MAX_STANDARD_P2WSH_STACK_ITEMS` (100) witness elements.
-    MaxWitnessItemssExceeded { actual: usize, limit: usize },
+    MaxWitnessItemssExceeded {
+        /// The number of witness elements
+        actual: usize,
+        /// The maximum number of witness elements
+        limit: usize,
+    },
     /// At least one satisfaction path in the Miniscript fragment contains more
     /// than `MAX_OPS_PER_SCRIPT`(201) opcodes.
     MaxOpCountExceeded,
@@ -71,16 +82,139 @@ pub enum ScriptContextError {
     /// No Multi Node in Taproot context
     TaprootMultiDisabled,
     /// Stack size exceeded in script execution
-    StackSizeLimitExceeded { actual: usize, limit: usize },
+    StackSizeLimitExceeded {
+        /// The stack size
+        actual: usize,
+        /// The maximum stack size
+        limit: usize,
+    },
     /// More than 20 keys in a Multi fragment
     CheckMultiSigLimitExceeded,
     /// MultiA is only allowed in post tapscript
     MultiANotAllowed,
 }
 
+/// A rule of a script context on the keys it may contain
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Hash)]
+pub enum KeyRule {
+    /// Segwitv0 fragments do not allow uncompressed pubkeys
+    CompressedOnly,
+    /// XOnly keys are only allowed in Tap context
+    NoXOnly,
+    /// Tap context can contain compressed or xonly keys, but not uncompressed
+    /// ones
+    NoUncompressed,
+}
+
+impl KeyRule {
+    /// The name of the rule
+    pub fn name(self) -> &'static str {
+        match self {
+            KeyRule::CompressedOnly => "compressed-only",
+            KeyRule::NoXOnly => "no-x-only",
+            KeyRule::NoUncompressed => "no-uncompressed",
+        }
+    }
+}
+
+impl fmt::Display for KeyRule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// A key breaking a [`KeyRule`] of its script context, as found when parsing
+/// a descriptor or miniscript, or when checking a miniscript built with
+/// [`Miniscript::from_ast`] against its context
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ContextError {
+    /// The rule broken by the key
+    pub rule: KeyRule,
+    /// The offending key
+    pub key: String,
+    /// The name of the script context
+    pub context: &'static str,
+    /// The byte offset of the key in the parsed string, if the error was
+    /// found while parsing a string
+    pub position: Option<usize>,
+}
+
+impl ContextError {
+    /// Create an error for `key` breaking `rule` in the context `Ctx`, at
+    /// an unknown position
+    pub fn new<Pk: MiniscriptKey, Ctx: ScriptContext>(rule: KeyRule, key: &Pk) -> Self {
+        ContextError {
+            rule,
+            key: key.to_string(),
+            context: Ctx::name_str(),
+            position: None,
+        }
+    }
+}
+
+impl fmt::Display for ContextError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.rule {
+            KeyRule::CompressedOnly | KeyRule::NoUncompressed => {
+                write!(f, "uncompressed key {}", self.key)?
+            }
+            KeyRule::NoXOnly => write!(f, "x-only key {}", self.key)?,
+        }
+        if let Some(position) = self.position {
+            write!(f, " at position {}", position)?;
+        }
+        write!(f, " not allowed in {} ({})", self.context, self.rule)
+    }
+}
+
+#[cfg(feature = "std")]
+impl error::Error for ContextError {}
+
+// Set the position of the offending key of a key error raised by the fragment
+// parsed from `top` to the position of that key among the arguments of `top`.
+// Errors raised by the fragments below `top` already carry their position and
+// are left unchanged.
+pub(crate) fn mark_key<Pk>(e: Error, top: &expression::Tree) -> Error
+where
+    Pk: MiniscriptKey + FromStr,
+{
+    match e {
+        Error::ContextError(ScriptContextError::Key(mut e)) => {
+            if e.position.is_none() {
+                e.position = top
+                    .args
+                    .iter()
+                    .find(|arg| {
+                        arg.args.is_empty()
+                            && (arg.name == e.key
+                                || Pk::from_str(arg.name)
+                                    .map(|pk| pk.to_string() == e.key)
+                                    .unwrap_or(false))
+                    })
+                    .map(|arg| arg.position);
+            }
+            Error::ContextError(ScriptContextError::Key(e))
+        }
+        e => e,
+    }
+}
+
+// Shift the position of the offending key of a key error found by the parser
+// of a part of a string, such as a taproot leaf, which starts at `offset`
+pub(crate) fn offset_key(e: Error, offset: usize) -> Error {
+    match e {
+        Error::ContextError(ScriptContextError::Key(mut e)) => {
+            e.position = e.position.map(|position| position + offset);
+            Error::ContextError(ScriptContextError::Key(e))
+        }
+        e => e,
+    }
+}
+
 #[cfg(feature = "std")]
 impl error::Error for ScriptContextError {
-    fn cause(&self) -> Option<&dyn error::Error> {
+    #[allow(deprecated)]
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
         use self::ScriptContextError::*;
 
         match self {
@@ -90,6 +224,7 @@ impl error::Error for ScriptContextError {
             | CompressedOnly(_)
             | XOnlyKeysNotAllowed(_, _)
             | UncompressedKeysNotAllowed
+            | Key(_)
             | MaxWitnessItemssExceeded { .. }
             | MaxOpCountExceeded
             | MaxWitnessScriptSizeExceeded
@@ -105,6 +240,7 @@ impl error::Error for ScriptContextError {
 }
 
 impl fmt::Display for ScriptContextError {
+    #[allow(deprecated)]
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             ScriptContextError::MalleablePkH => write!(f, "PkH is malleable under Legacy rules"),
@@ -128,6 +264,7 @@ impl fmt::Display for ScriptContextError {
                     "uncompressed keys cannot be used in Taproot descriptors."
                 )
             }
+            ScriptContextError::Key(ref e) => fmt::Display::fmt(e, f),
             ScriptContextError::MaxWitnessItemssExceeded { actual, limit } => write!(
                 f,
                 "At least one spending path in the Miniscript fragment has {} more \
@@ -187,10 +324,26 @@ impl fmt::Display for ScriptContextError {
 /// miniscript that is used for carrying out checks that dependent on the
 /// context under which the script is used.
 /// For example, disallowing uncompressed keys in Segwit context
+///
+/// Besides the contexts of this crate, downstream crates may implement this
+/// trait for contexts of their own, such as one for a future soft fork or for
+/// another chain. Parsing, type checking, the resource limits, satisfaction
+/// and the compiler only go through the methods of this trait, so a new
+/// context only needs to state its rules here.
+///
+/// As the trait is not sealed, adding a method without a default
+/// implementation breaks the contexts of downstream crates, and is only done
+/// in a major release.
 pub trait ScriptContext:
-    fmt::Debug + Clone + Ord + PartialOrd + Eq + PartialEq + hash::Hash + private::Sealed
+    fmt::Debug + Clone + Ord + PartialOrd + Eq + PartialEq + hash::Hash
 where
-    Self::Key: MiniscriptKey<Hash = bitcoin::hashes::hash160::Hash>,
+    Self::Key: MiniscriptKey<
+        Hash = bitcoin::hashes::hash160::Hash,
+        Sha256 = bitcoin::hashes::sha256::Hash,
+        Hash256 = crate::hash256::Hash,
+        Ripemd160 = bitcoin::hashes::ripemd160::Hash,
+        Hash160 = bitcoin::hashes::hash160::Hash,
+    >,
 {
     /// The consensus key associated with the type. Must be a parseable key
     type Key: ParseableKey;
@@ -209,7 +362,18 @@ where
     /// Check whether the given satisfaction is valid under the ScriptContext
     /// For example, segwit satisfactions may fail if the witness len is more
     /// 3600 or number of stack elements are more than 100.
-    fn check_witness<Pk: MiniscriptKey>(_witness: &[Vec<u8>]) -> Result<(), ScriptContextError> {
+    fn check_witness<Pk: MiniscriptKey>(witness: &[Vec<u8>]) -> Result<(), ScriptContextError> {
+        Self::check_witness_elements(witness.iter().map(Vec::as_slice))
+    }
+
+    /// Same as [`ScriptContext::check_witness`], for a satisfaction given as
+    /// an iterator over its elements, so that it can be checked without
+    /// collecting it. `check_witness` calls this by default, so contexts
+    /// should implement their limits here to apply them to both.
+    fn check_witness_elements<'w, I>(_elems: I) -> Result<(), ScriptContextError>
+    where
+        I: Iterator<Item = &'w [u8]>,
+    {
         // Only really need to do this for segwitv0 and legacy
         // Bare is already restrcited by standardness rules
         // and would reach these limits.
@@ -291,6 +455,20 @@ where
         Ok(())
     }
 
+    /// The consensus and standardness limits of this context which would be
+    /// exceeded by some spend path of a Miniscript with the given resource
+    /// usage
+    fn resource_limits_exceeded(_usage: &ResourceUsage) -> Vec<LimitExceeded> {
+        vec![]
+    }
+
+    /// The consensus and standardness limits of this context which are
+    /// exceeded by a satisfying witness. These are the limits checked by
+    /// [`ScriptContext::check_witness`].
+    fn witness_limits_exceeded(_witness: &[Vec<u8>]) -> Vec<LimitExceeded> {
+        vec![]
+    }
+
     /// Check whether the top-level is type B
     fn top_level_type_check<Pk: MiniscriptKey>(ms: &Miniscript<Pk, Self>) -> Result<(), Error> {
         if ms.ty.corr.base != types::Base::B {
@@ -333,6 +511,8 @@ where
     fn name_str() -> &'static str;
 }
 
+/// The type of signature checked by the `CHECKSIG` family of opcodes of a
+/// script context
 #[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Ord)]
 pub enum SigType {
     /// Ecdsa signature
@@ -361,10 +541,11 @@ impl ScriptContext for Legacy {
         }
     }
 
-    fn check_witness<Pk: MiniscriptKey>(witness: &[Vec<u8>]) -> Result<(), ScriptContextError> {
-        // In future, we could avoid by having a function to count only
-        // len of script instead of converting it.
-        if witness_to_scriptsig(witness).len() > MAX_SCRIPTSIG_SIZE {
+    fn check_witness_elements<'w, I>(elems: I) -> Result<(), ScriptContextError>
+    where
+        I: Iterator<Item = &'w [u8]>,
+    {
+        if elems.map(script_sig_push_len).sum::<usize>() > MAX_SCRIPTSIG_SIZE {
             return Err(ScriptContextError::MaxScriptSigSizeExceeded);
         }
         Ok(())
@@ -379,10 +560,10 @@ impl ScriptContext for Legacy {
 
         match ms.node {
             Terminal::PkK(ref key) if key.is_x_only_key() => {
-                return Err(ScriptContextError::XOnlyKeysNotAllowed(
-                    key.to_string(),
-                    Self::name_str(),
-                ))
+                return Err(ScriptContextError::Key(ContextError::new::<_, Self>(
+                    KeyRule::NoXOnly,
+                    key,
+                )))
             }
             Terminal::Multi(_k, ref pks) => {
                 if pks.len() > MAX_PUBKEYS_PER_MULTISIG {
@@ -390,14 +571,14 @@ impl ScriptContext for Legacy {
                 }
                 for pk in pks.iter() {
                     if pk.is_x_only_key() {
-                        return Err(ScriptContextError::XOnlyKeysNotAllowed(
-                            pk.to_string(),
-                            Self::name_str(),
-                        ));
+                        return Err(ScriptContextError::Key(ContextError::new::<_, Self>(
+                            KeyRule::NoXOnly,
+                            pk,
+                        )));
                     }
                 }
             }
-            Terminal::MultiA(..) => {
+            Terminal::MultiA(..) | Terminal::SortedMultiA(..) => {
                 return Err(ScriptContextError::MultiANotAllowed);
             }
             _ => {}
@@ -445,6 +626,49 @@ impl ScriptContext for Legacy {
         }
     }
 
+    fn resource_limits_exceeded(usage: &ResourceUsage) -> Vec<LimitExceeded> {
+        [
+            LimitExceeded::check(
+                ResourceLimit::OpsCount,
+                true,
+                usage.ops_count,
+                MAX_OPS_PER_SCRIPT,
+            ),
+            LimitExceeded::check(
+                ResourceLimit::StackSize,
+                true,
+                usage.stack_size,
+                MAX_STACK_SIZE,
+            ),
+            LimitExceeded::check(
+                ResourceLimit::ScriptSize,
+                true,
+                Some(usage.script_size),
+                MAX_SCRIPT_ELEMENT_SIZE,
+            ),
+            LimitExceeded::check(
+                ResourceLimit::ScriptSigSize,
+                false,
+                usage.max_satisfaction_size,
+                MAX_SCRIPTSIG_SIZE,
+            ),
+        ]
+        .iter()
+        .filter_map(|exceeded| *exceeded)
+        .collect()
+    }
+
+    fn witness_limits_exceeded(witness: &[Vec<u8>]) -> Vec<LimitExceeded> {
+        LimitExceeded::check(
+            ResourceLimit::ScriptSigSize,
+            false,
+            Some(witness_to_scriptsig(witness).len()),
+            MAX_SCRIPTSIG_SIZE,
+        )
+        .into_iter()
+        .collect()
+    }
+
     fn name_str() -> &'static str {
         "Legacy/p2sh"
     }
@@ -466,10 +690,14 @@ impl ScriptContext for Segwitv0 {
         Ok(())
     }
 
-    fn check_witness<Pk: MiniscriptKey>(witness: &[Vec<u8>]) -> Result<(), ScriptContextError> {
-        if witness.len() > MAX_STANDARD_P2WSH_STACK_ITEMS {
+    fn check_witness_elements<'w, I>(elems: I) -> Result<(), ScriptContextError>
+    where
+        I: Iterator<Item = &'w [u8]>,
+    {
+        let len = elems.count();
+        if len > MAX_STANDARD_P2WSH_STACK_ITEMS {
             return Err(ScriptContextError::MaxWitnessItemssExceeded {
-                actual: witness.len(),
+                actual: len,
                 limit: MAX_STANDARD_P2WSH_STACK_ITEMS,
             });
         }
@@ -486,12 +714,15 @@ impl ScriptContext for Segwitv0 {
         match ms.node {
             Terminal::PkK(ref pk) => {
                 if pk.is_uncompressed() {
-                    return Err(ScriptContextError::CompressedOnly(pk.to_string()));
+                    return Err(ScriptContextError::Key(ContextError::new::<_, Self>(
+                        KeyRule::CompressedOnly,
+                        pk,
+                    )));
                 } else if pk.is_x_only_key() {
-                    return Err(ScriptContextError::XOnlyKeysNotAllowed(
-                        pk.to_string(),
-                        Self::name_str(),
-                    ));
+                    return Err(ScriptContextError::Key(ContextError::new::<_, Self>(
+                        KeyRule::NoXOnly,
+                        pk,
+                    )));
                 }
                 Ok(())
             }
@@ -501,17 +732,22 @@ impl ScriptContext for Segwitv0 {
                 }
                 for pk in pks.iter() {
                     if pk.is_uncompressed() {
-                        return Err(ScriptContextError::CompressedOnly(pk.to_string()));
+                        return Err(ScriptContextError::Key(ContextError::new::<_, Self>(
+                            KeyRule::CompressedOnly,
+                            pk,
+                        )));
                     } else if pk.is_x_only_key() {
-                        return Err(ScriptContextError::XOnlyKeysNotAllowed(
-                            pk.to_string(),
-                            Self::name_str(),
-                        ));
+                        return Err(ScriptContextError::Key(ContextError::new::<_, Self>(
+                            KeyRule::NoXOnly,
+                            pk,
+                        )));
                     }
                 }
                 Ok(())
             }
-            Terminal::MultiA(..) => Err(ScriptContextError::MultiANotAllowed),
+            Terminal::MultiA(..) | Terminal::SortedMultiA(..) => {
+                Err(ScriptContextError::MultiANotAllowed)
+            }
             _ => Ok(()),
         }
     }
@@ -565,6 +801,55 @@ impl ScriptContext for Segwitv0 {
         34
     }
 
+    fn resource_limits_exceeded(usage: &ResourceUsage) -> Vec<LimitExceeded> {
+        [
+            LimitExceeded::check(
+                ResourceLimit::OpsCount,
+                true,
+                usage.ops_count,
+                MAX_OPS_PER_SCRIPT,
+            ),
+            LimitExceeded::check(
+                ResourceLimit::StackSize,
+                true,
+                usage.stack_size,
+                MAX_STACK_SIZE,
+            ),
+            LimitExceeded::check(
+                ResourceLimit::ScriptSize,
+                true,
+                Some(usage.script_size),
+                MAX_SCRIPT_SIZE,
+            ),
+            LimitExceeded::check(
+                ResourceLimit::ScriptSize,
+                false,
+                Some(usage.script_size),
+                MAX_STANDARD_P2WSH_SCRIPT_SIZE,
+            ),
+            LimitExceeded::check(
+                ResourceLimit::WitnessElements,
+                false,
+                usage.max_witness_elements,
+                MAX_STANDARD_P2WSH_STACK_ITEMS,
+            ),
+        ]
+        .iter()
+        .filter_map(|exceeded| *exceeded)
+        .collect()
+    }
+
+    fn witness_limits_exceeded(witness: &[Vec<u8>]) -> Vec<LimitExceeded> {
+        LimitExceeded::check(
+            ResourceLimit::WitnessElements,
+            false,
+            Some(witness.len()),
+            MAX_STANDARD_P2WSH_STACK_ITEMS,
+        )
+        .into_iter()
+        .collect()
+    }
+
     fn name_str() -> &'static str {
         "Segwitv0"
     }
@@ -588,11 +873,15 @@ impl ScriptContext for Tap {
         Ok(())
     }
 
-    fn check_witness<Pk: MiniscriptKey>(witness: &[Vec<u8>]) -> Result<(), ScriptContextError> {
+    fn check_witness_elements<'w, I>(elems: I) -> Result<(), ScriptContextError>
+    where
+        I: Iterator<Item = &'w [u8]>,
+    {
         // Note that tapscript has a 1000 limit compared to 100 of segwitv0
-        if witness.len() > MAX_STACK_SIZE {
+        let len = elems.count();
+        if len > MAX_STACK_SIZE {
             return Err(ScriptContextError::MaxWitnessItemssExceeded {
-                actual: witness.len(),
+                actual: len,
                 limit: MAX_STACK_SIZE,
             });
         }
@@ -614,7 +903,10 @@ impl ScriptContext for Tap {
         match ms.node {
             Terminal::PkK(ref pk) => {
                 if pk.is_uncompressed() {
-                    return Err(ScriptContextError::UncompressedKeysNotAllowed);
+                    return Err(ScriptContextError::Key(ContextError::new::<_, Self>(
+                        KeyRule::NoUncompressed,
+                        pk,
+                    )));
                 }
                 Ok(())
             }
@@ -675,6 +967,37 @@ impl ScriptContext for Tap {
         33
     }
 
+    fn resource_limits_exceeded(usage: &ResourceUsage) -> Vec<LimitExceeded> {
+        [
+            LimitExceeded::check(
+                ResourceLimit::StackSize,
+                true,
+                usage.stack_size,
+                MAX_STACK_SIZE,
+            ),
+            LimitExceeded::check(
+                ResourceLimit::ScriptSize,
+                true,
+                Some(usage.script_size),
+                MAX_BLOCK_WEIGHT as usize,
+            ),
+        ]
+        .iter()
+        .filter_map(|exceeded| *exceeded)
+        .collect()
+    }
+
+    fn witness_limits_exceeded(witness: &[Vec<u8>]) -> Vec<LimitExceeded> {
+        LimitExceeded::check(
+            ResourceLimit::WitnessElements,
+            true,
+            Some(witness.len()),
+            MAX_STACK_SIZE,
+        )
+        .into_iter()
+        .collect()
+    }
+
     fn name_str() -> &'static str {
         "TapscriptCtx"
     }
@@ -706,27 +1029,26 @@ impl ScriptContext for BareCtx {
             return Err(ScriptContextError::MaxWitnessScriptSizeExceeded);
         }
         match ms.node {
-            Terminal::PkK(ref key) if key.is_x_only_key() => {
-                return Err(ScriptContextError::XOnlyKeysNotAllowed(
-                    key.to_string(),
-                    Self::name_str(),
-                ))
-            }
+            Terminal::PkK(ref key) if key.is_x_only_key() => Err(ScriptContextError::Key(
+                ContextError::new::<_, Self>(KeyRule::NoXOnly, key),
+            )),
             Terminal::Multi(_k, ref pks) => {
                 if pks.len() > MAX_PUBKEYS_PER_MULTISIG {
                     return Err(ScriptContextError::CheckMultiSigLimitExceeded);
                 }
                 for pk in pks.iter() {
                     if pk.is_x_only_key() {
-                        return Err(ScriptContextError::XOnlyKeysNotAllowed(
-                            pk.to_string(),
-                            Self::name_str(),
-                        ));
+                        return Err(ScriptContextError::Key(ContextError::new::<_, Self>(
+                            KeyRule::NoXOnly,
+                            pk,
+                        )));
                     }
                 }
                 Ok(())
             }
-            Terminal::MultiA(..) => Err(ScriptContextError::MultiANotAllowed),
+            Terminal::MultiA(..) | Terminal::SortedMultiA(..) => {
+                Err(ScriptContextError::MultiANotAllowed)
+            }
             _ => Ok(()),
         }
     }
@@ -768,6 +1090,32 @@ impl ScriptContext for BareCtx {
         }
     }
 
+    fn resource_limits_exceeded(usage: &ResourceUsage) -> Vec<LimitExceeded> {
+        [
+            LimitExceeded::check(
+                ResourceLimit::OpsCount,
+                true,
+                usage.ops_count,
+                MAX_OPS_PER_SCRIPT,
+            ),
+            LimitExceeded::check(
+                ResourceLimit::StackSize,
+                true,
+                usage.stack_size,
+                MAX_STACK_SIZE,
+            ),
+            LimitExceeded::check(
+                ResourceLimit::ScriptSize,
+                true,
+                Some(usage.script_size),
+                MAX_SCRIPT_SIZE,
+            ),
+        ]
+        .iter()
+        .filter_map(|exceeded| *exceeded)
+        .collect()
+    }
+
     fn name_str() -> &'static str {
         "BareCtx"
     }
@@ -830,13 +1178,6 @@ impl ScriptContext for NoChecks {
         "NochecksEcdsa"
     }
 
-    fn check_witness<Pk: MiniscriptKey>(_witness: &[Vec<u8>]) -> Result<(), ScriptContextError> {
-        // Only really need to do this for segwitv0 and legacy
-        // Bare is already restrcited by standardness rules
-        // and would reach these limits.
-        Ok(())
-    }
-
     fn check_global_validity<Pk: MiniscriptKey>(
         ms: &Miniscript<Pk, Self>,
     ) -> Result<(), ScriptContextError> {
@@ -875,17 +1216,3 @@ impl ScriptContext for NoChecks {
         SigType::Ecdsa
     }
 }
-
-/// Private Mod to prevent downstream from implementing this public trait
-mod private {
-    use super::{BareCtx, Legacy, NoChecks, Segwitv0, Tap};
-
-    pub trait Sealed {}
-
-    // Implement for those same types, but no others.
-    impl Sealed for BareCtx {}
-    impl Sealed for Legacy {}
-    impl Sealed for Segwitv0 {}
-    impl Sealed for Tap {}
-    impl Sealed for NoChecks {}
-}
@@ -23,7 +23,7 @@ use core::marker::PhantomData;
 use std::error;
 
 use bitcoin::blockdata::constants::MAX_BLOCK_WEIGHT;
-use bitcoin::hashes::{hash160, ripemd160, sha256, sha256d, Hash};
+use bitcoin::hashes::{hash160, ripemd160, sha256, Hash};
 use sync::Arc;
 
 use crate::miniscript::lex::{Token as Tk, TokenIter};
@@ -32,7 +32,7 @@ use crate::miniscript::types::extra_props::ExtData;
 use crate::miniscript::types::{Property, Type};
 use crate::miniscript::ScriptContext;
 use crate::prelude::*;
-use crate::{bitcoin, Error, Miniscript, MiniscriptKey, ToPublicKey};
+use crate::{bitcoin, hash256, Error, Miniscript, MiniscriptKey, ToPublicKey};
 
 fn return_none<T>(_: usize) -> Option<T> {
     None
@@ -77,7 +77,7 @@ impl fmt::Display for KeyParseError {
 
 #[cfg(feature = "std")]
 impl error::Error for KeyParseError {
-    fn cause(&self) -> Option<&(dyn error::Error + 'static)> {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
         match self {
             KeyParseError::FullKeyParseError(e) => Some(e),
             KeyParseError::XonlyKeyParseError(e) => Some(e),
@@ -142,13 +142,13 @@ pub enum Terminal<Pk: MiniscriptKey, Ctx: ScriptContext> {
     Older(u32),
     // hashlocks
     /// `SIZE 32 EQUALVERIFY SHA256 <hash> EQUAL`
-    Sha256(sha256::Hash),
+    Sha256(Pk::Sha256),
     /// `SIZE 32 EQUALVERIFY HASH256 <hash> EQUAL`
-    Hash256(sha256d::Hash),
+    Hash256(Pk::Hash256),
     /// `SIZE 32 EQUALVERIFY RIPEMD160 <hash> EQUAL`
-    Ripemd160(ripemd160::Hash),
+    Ripemd160(Pk::Ripemd160),
     /// `SIZE 32 EQUALVERIFY HASH160 <hash> EQUAL`
-    Hash160(hash160::Hash),
+    Hash160(Pk::Hash160),
     // Wrappers
     /// `TOALTSTACK [E] FROMALTSTACK`
     Alt(Arc<Miniscript<Pk, Ctx>>),
@@ -191,6 +191,9 @@ pub enum Terminal<Pk: MiniscriptKey, Ctx: ScriptContext> {
     Multi(usize, Vec<Pk>),
     /// <key> CHECKSIG (<key> CHECKSIGADD)*(n-1) k NUMEQUAL
     MultiA(usize, Vec<Pk>),
+    /// Same as `MultiA`, with the keys sorted by their x-only serialization
+    /// when encoded. Decoding a script never produces this fragment.
+    SortedMultiA(usize, Vec<Pk>),
 }
 
 macro_rules! match_token {
@@ -366,7 +369,7 @@ pub fn parse<Ctx: ScriptContext>(
                                 Tk::Hash256, Tk::Verify, Tk::Equal, Tk::Num(32), Tk::Size => {
                                     non_term.push(NonTerm::Verify);
                                     term.reduce0(Terminal::Hash256(
-                                        sha256d::Hash::from_slice(hash).expect("valid size")
+                                        hash256::Hash::from_slice(hash).expect("valid size")
                                     ))?
                                 },
                             ),
@@ -410,7 +413,7 @@ pub fn parse<Ctx: ScriptContext>(
                             Tk::Equal,
                             Tk::Num(32),
                             Tk::Size => term.reduce0(Terminal::Hash256(
-                                sha256d::Hash::from_slice(hash).expect("valid size")
+                                hash256::Hash::from_slice(hash).expect("valid size")
                             ))?,
                         ),
                         Tk::Hash20(hash) => match_token!(
@@ -0,0 +1,359 @@
+// Miniscript
+// Written in 2022 by
+//     The rust-miniscript developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! Witness Interpretation
+//!
+//! Maps a final witness stack back onto the Miniscript it satisfies, to find
+//! which fragments were satisfied or dissatisfied and which branches of the
+//! disjunctions were executed.
+
+use bitcoin::hashes::{hash160, ripemd160, sha256, sha256d, Hash};
+
+use crate::interpreter::Error;
+use crate::miniscript::types::Base;
+use crate::prelude::*;
+use crate::{Miniscript, MiniscriptKey, ScriptContext, Terminal, ToPublicKey};
+
+/// The result of evaluating a fragment against a witness, as returned by
+/// [`Miniscript::interpret_witness`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WitnessInterpretation<'ms, Pk: MiniscriptKey, Ctx: ScriptContext> {
+    /// The evaluated fragment
+    pub node: &'ms Miniscript<Pk, Ctx>,
+    /// Whether the fragment was satisfied. Fragments of type `V` cannot be
+    /// dissatisfied and are always reported as satisfied.
+    pub satisfied: bool,
+    /// The interpretation of each child of `node`, in the order of
+    /// [`Miniscript::branches`], or `None` for children which were not
+    /// executed, e.g. the untaken branch of an `or_i`
+    pub children: Vec<Option<WitnessInterpretation<'ms, Pk, Ctx>>>,
+}
+
+/// Stack element, as in the interpreter
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum Element<'w> {
+    Satisfied,
+    Dissatisfied,
+    Push(&'w [u8]),
+}
+
+impl<'w> From<&'w [u8]> for Element<'w> {
+    fn from(v: &'w [u8]) -> Element<'w> {
+        if *v == [1] {
+            Element::Satisfied
+        } else if v.is_empty() {
+            Element::Dissatisfied
+        } else {
+            Element::Push(v)
+        }
+    }
+}
+
+/// Pops a boolean, as pushed by a fragment or used to select an `or_i` branch
+fn pop_bool(stack: &mut Vec<Element>) -> Result<bool, Error> {
+    match stack.pop() {
+        Some(Element::Satisfied) => Ok(true),
+        Some(Element::Dissatisfied) => Ok(false),
+        Some(Element::Push(_)) => Err(Error::UnexpectedStackElementPush),
+        None => Err(Error::UnexpectedStackEnd),
+    }
+}
+
+/// Pops a signature, returning whether it was non-empty
+fn pop_sig(stack: &mut Vec<Element>) -> Result<bool, Error> {
+    match stack.pop() {
+        Some(Element::Push(_)) => Ok(true),
+        Some(Element::Dissatisfied) => Ok(false),
+        Some(Element::Satisfied) => Err(Error::UnexpectedStackBoolean),
+        None => Err(Error::UnexpectedStackEnd),
+    }
+}
+
+/// Pops a hash preimage, returning whether it matches `hash`
+fn pop_preimage<H: Hash>(stack: &mut Vec<Element>, hash: H) -> Result<bool, Error> {
+    match stack.pop() {
+        Some(Element::Push(preimage)) => {
+            if preimage.len() != 32 {
+                Err(Error::HashPreimageLengthMismatch)
+            } else {
+                Ok(<H as Hash>::hash(preimage) == hash)
+            }
+        }
+        _ => Err(Error::UnexpectedStackEnd),
+    }
+}
+
+impl<Pk: MiniscriptKey + ToPublicKey, Ctx: ScriptContext> Miniscript<Pk, Ctx> {
+    /// Interprets a final witness stack for this Miniscript, recording which
+    /// fragments were satisfied or dissatisfied and which branches were taken.
+    ///
+    /// The witness is given in the order it appears in the transaction,
+    /// without the witness script (or the tapscript and control block). No
+    /// signatures are checked, as for [`crate::Interpreter::iter_assume_sigs`],
+    /// and neither are timelocks; hash preimages and public key hashes are.
+    pub fn interpret_witness<'ms>(
+        &'ms self,
+        witness: &[Vec<u8>],
+    ) -> Result<WitnessInterpretation<'ms, Pk, Ctx>, Error> {
+        let mut stack: Vec<Element> = witness
+            .iter()
+            .map(|elem| Element::from(&elem[..]))
+            .collect();
+        let res = interpret(self, &mut stack)?;
+        if stack.len() != 1 {
+            return Err(Error::ScriptSatisfactionError);
+        }
+        Ok(res)
+    }
+}
+
+fn interpret<'ms, 'w, Pk, Ctx>(
+    node: &'ms Miniscript<Pk, Ctx>,
+    stack: &mut Vec<Element<'w>>,
+) -> Result<WitnessInterpretation<'ms, Pk, Ctx>, Error>
+where
+    Pk: MiniscriptKey + ToPublicKey,
+    Ctx: ScriptContext,
+{
+    let branches = node.branches();
+    let mut children: Vec<_> = branches.iter().map(|_| None).collect();
+    // Evaluates child `i`, recording its interpretation
+    let mut eval = |i: usize, stack: &mut Vec<Element<'w>>| -> Result<(), Error> {
+        children[i] = Some(interpret(branches[i], stack)?);
+        Ok(())
+    };
+
+    let pushed = match node.node {
+        Terminal::True => true,
+        Terminal::False => false,
+        Terminal::PkK(_) => pop_sig(stack)?,
+        Terminal::PkH(ref hash) => {
+            match stack.pop() {
+                Some(Element::Push(key)) => {
+                    let hash = Pk::hash_to_hash160(hash);
+                    if hash160::Hash::hash(key) != hash {
+                        return Err(Error::PkHashVerifyFail(hash));
+                    }
+                }
+                _ => return Err(Error::UnexpectedStackEnd),
+            }
+            pop_sig(stack)?
+        }
+        Terminal::After(_) | Terminal::Older(_) => true,
+        Terminal::Sha256(ref hash) => pop_preimage::<sha256::Hash>(stack, Pk::to_sha256(hash))?,
+        Terminal::Hash256(ref hash) => {
+            pop_preimage::<sha256d::Hash>(stack, Pk::to_hash256(hash).as_hash())?
+        }
+        Terminal::Ripemd160(ref hash) => {
+            pop_preimage::<ripemd160::Hash>(stack, Pk::to_ripemd160(hash))?
+        }
+        Terminal::Hash160(ref hash) => pop_preimage::<hash160::Hash>(stack, Pk::to_hash160(hash))?,
+        Terminal::Alt(_) | Terminal::Swap(_) | Terminal::Check(_) => {
+            eval(0, stack)?;
+            pop_bool(stack)?
+        }
+        Terminal::DupIf(_) => {
+            if pop_bool(stack)? {
+                eval(0, stack)?;
+                true
+            } else {
+                false
+            }
+        }
+        Terminal::Verify(_) => {
+            eval(0, stack)?;
+            if !pop_bool(stack)? {
+                return Err(Error::VerifyFailed);
+            }
+            true
+        }
+        Terminal::NonZero(_) => match stack.last() {
+            Some(&Element::Dissatisfied) => pop_bool(stack)?,
+            Some(_) => {
+                eval(0, stack)?;
+                pop_bool(stack)?
+            }
+            None => return Err(Error::UnexpectedStackEnd),
+        },
+        Terminal::ZeroNotEqual(_) => {
+            eval(0, stack)?;
+            match stack.pop() {
+                Some(Element::Dissatisfied) => false,
+                Some(_) => true,
+                None => return Err(Error::UnexpectedStackEnd),
+            }
+        }
+        Terminal::AndV(..) => {
+            eval(0, stack)?;
+            eval(1, stack)?;
+            // A `V` right child leaves nothing on the stack
+            node.ty.corr.base == Base::V || pop_bool(stack)?
+        }
+        Terminal::AndB(..) => {
+            eval(0, stack)?;
+            let left = pop_bool(stack)?;
+            eval(1, stack)?;
+            pop_bool(stack)? && left
+        }
+        Terminal::OrB(..) => {
+            eval(0, stack)?;
+            let left = pop_bool(stack)?;
+            eval(1, stack)?;
+            pop_bool(stack)? || left
+        }
+        Terminal::OrC(..) => {
+            eval(0, stack)?;
+            if !pop_bool(stack)? {
+                eval(1, stack)?;
+            }
+            true
+        }
+        Terminal::OrD(..) => {
+            eval(0, stack)?;
+            pop_bool(stack)? || {
+                eval(1, stack)?;
+                pop_bool(stack)?
+            }
+        }
+        Terminal::OrI(..) => {
+            let i = if pop_bool(stack)? { 0 } else { 1 };
+            eval(i, stack)?;
+            node.ty.corr.base == Base::V || pop_bool(stack)?
+        }
+        Terminal::AndOr(..) => {
+            eval(0, stack)?;
+            let i = if pop_bool(stack)? { 1 } else { 2 };
+            eval(i, stack)?;
+            node.ty.corr.base == Base::V || pop_bool(stack)?
+        }
+        Terminal::Thresh(k, ref subs) => {
+            let mut n_satisfied = 0;
+            for i in 0..subs.len() {
+                eval(i, stack)?;
+                if pop_bool(stack)? {
+                    n_satisfied += 1;
+                }
+            }
+            n_satisfied == k
+        }
+        Terminal::Multi(k, _) => {
+            if stack.len() < k + 1 {
+                return Err(Error::InsufficientSignaturesMultiSig);
+            }
+            let sigs = stack.split_off(stack.len() - k);
+            // The extra element consumed by `OP_CHECKMULTISIG`
+            if stack.pop() != Some(Element::Dissatisfied) {
+                return Err(Error::MissingExtraZeroMultiSig);
+            }
+            let n_empty = sigs
+                .iter()
+                .filter(|sig| **sig == Element::Dissatisfied)
+                .count();
+            if n_empty == k {
+                false
+            } else if n_empty == 0 {
+                true
+            } else {
+                return Err(Error::MultiSigEvaluationError);
+            }
+        }
+        Terminal::MultiA(k, ref keys) | Terminal::SortedMultiA(k, ref keys) => {
+            let mut n_satisfied = 0;
+            for _ in keys {
+                if pop_sig(stack)? {
+                    n_satisfied += 1;
+                }
+            }
+            n_satisfied == k
+        }
+    };
+
+    // `V` fragments leave nothing on the stack and cannot be dissatisfied
+    let satisfied = if node.ty.corr.base == Base::V {
+        true
+    } else {
+        stack.push(if pushed {
+            Element::Satisfied
+        } else {
+            Element::Dissatisfied
+        });
+        pushed
+    };
+    Ok(WitnessInterpretation {
+        node,
+        satisfied,
+        children,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use core::str::FromStr;
+
+    use bitcoin::hashes::{sha256, Hash};
+
+    use super::*;
+    use crate::miniscript::iter::test::gen_bitcoin_pubkeys;
+    use crate::Segwitv0;
+
+    type Segwitv0Ms = Miniscript<bitcoin::PublicKey, Segwitv0>;
+
+    #[test]
+    fn interpret_witness() {
+        let keys = gen_bitcoin_pubkeys(2, true);
+        let sig = vec![0x30; 72];
+        let preimage = [0xab; 32];
+        let hash = sha256::Hash::hash(&preimage);
+
+        let ms = Segwitv0Ms::from_str(&format!(
+            "or_i(pk({}),and_v(v:pk({}),older(10)))",
+            keys[0], keys[1]
+        ))
+        .unwrap();
+        let res = ms.interpret_witness(&[sig.clone(), vec![]]).unwrap();
+        assert!(res.satisfied);
+        assert!(res.children[0].is_none());
+        let right = res.children[1].as_ref().unwrap();
+        assert_eq!(
+            right.node.to_string(),
+            format!("and_v(v:pk({}),older(10))", keys[1])
+        );
+        assert!(right.satisfied);
+        assert!(ms.interpret_witness(core::slice::from_ref(&sig)).is_err());
+
+        let ms = Segwitv0Ms::from_str(&format!(
+            "or_d(pk({}),and_v(v:sha256({}),pk({})))",
+            keys[0], hash, keys[1]
+        ))
+        .unwrap();
+        let res = ms
+            .interpret_witness(&[sig.clone(), preimage.to_vec(), vec![]])
+            .unwrap();
+        assert!(res.satisfied);
+        assert!(!res.children[0].as_ref().unwrap().satisfied);
+        assert!(res.children[1].as_ref().unwrap().satisfied);
+        // A preimage of the right length that does not match fails the `v:`
+        assert_eq!(
+            ms.interpret_witness(&[sig.clone(), vec![0; 32], vec![]])
+                .unwrap_err()
+                .to_string(),
+            Error::VerifyFailed.to_string()
+        );
+        assert!(ms.interpret_witness(&[vec![], sig.clone()]).is_err());
+        let res = ms.interpret_witness(&[sig]).unwrap();
+        assert!(res.satisfied);
+        assert!(res.children[0].as_ref().unwrap().satisfied);
+        assert!(res.children[1].is_none());
+    }
+}
@@ -54,6 +54,39 @@ impl<Pk: MiniscriptKey, Ctx: ScriptContext> Miniscript<Pk, Ctx> {
         PkPkhIter::new(self)
     }
 
+    /// Creates a new [DepthIter] iterator that will iterate over all [Miniscript] items within
+    /// AST in the same order as [Miniscript::iter], along with their depth: `0` for `self`, `1`
+    /// for its children and so on.
+    pub fn iter_with_depth(&self) -> DepthIter<'_, Pk, Ctx> {
+        DepthIter::new(self)
+    }
+
+    /// Creates a new [PostOrderIter] iterator that will iterate over all [Miniscript] items
+    /// within AST, returning every node after all of its children. For the specific algorithm
+    /// please see [PostOrderIter::next] function.
+    pub fn iter_postorder(&self) -> PostOrderIter<'_, Pk, Ctx> {
+        PostOrderIter::new(self)
+    }
+
+    /// Creates a new [TimelockIter] iterator that will iterate over all `after` and `older`
+    /// fragments present in [Miniscript] items within AST by traversing all its branches.
+    pub fn iter_timelocks(&self) -> TimelockIter<'_, Pk, Ctx> {
+        TimelockIter::new(self)
+    }
+
+    /// Creates a new [HashIter] iterator that will iterate over all hashlock fragments
+    /// present in [Miniscript] items within AST by traversing all its branches.
+    pub fn iter_hashes(&self) -> HashIter<'_, Pk, Ctx> {
+        HashIter::new(self)
+    }
+
+    /// Creates a new [ThreshIter] iterator that will iterate over all `thresh`, `multi`,
+    /// `multi_a` and `sortedmulti_a` fragments present in [Miniscript] items within AST by
+    /// traversing all its branches.
+    pub fn iter_thresholds(&self) -> ThreshIter<'_, Pk, Ctx> {
+        ThreshIter::new(self)
+    }
+
     /// Enumerates all child nodes of the current AST node (`self`) and returns a `Vec` referencing
     /// them.
     pub fn branches(&self) -> Vec<&Miniscript<Pk, Ctx>> {
@@ -124,7 +157,9 @@ impl<Pk: MiniscriptKey, Ctx: ScriptContext> Miniscript<Pk, Ctx> {
     pub fn get_leaf_pk(&self) -> Vec<Pk> {
         match self.node {
             Terminal::PkK(ref key) => vec![key.clone()],
-            Terminal::Multi(_, ref keys) | Terminal::MultiA(_, ref keys) => keys.clone(),
+            Terminal::Multi(_, ref keys)
+            | Terminal::MultiA(_, ref keys)
+            | Terminal::SortedMultiA(_, ref keys) => keys.clone(),
             _ => vec![],
         }
     }
@@ -142,9 +177,9 @@ impl<Pk: MiniscriptKey, Ctx: ScriptContext> Miniscript<Pk, Ctx> {
         match self.node {
             Terminal::PkH(ref hash) => vec![hash.clone()],
             Terminal::PkK(ref key) => vec![key.to_pubkeyhash()],
-            Terminal::Multi(_, ref keys) | Terminal::MultiA(_, ref keys) => {
-                keys.iter().map(Pk::to_pubkeyhash).collect()
-            }
+            Terminal::Multi(_, ref keys)
+            | Terminal::MultiA(_, ref keys)
+            | Terminal::SortedMultiA(_, ref keys) => keys.iter().map(Pk::to_pubkeyhash).collect(),
             _ => vec![],
         }
     }
@@ -160,7 +195,9 @@ impl<Pk: MiniscriptKey, Ctx: ScriptContext> Miniscript<Pk, Ctx> {
         match self.node {
             Terminal::PkH(ref hash) => vec![PkPkh::HashedPubkey(hash.clone())],
             Terminal::PkK(ref key) => vec![PkPkh::PlainPubkey(key.clone())],
-            Terminal::Multi(_, ref keys) | Terminal::MultiA(_, ref keys) => keys
+            Terminal::Multi(_, ref keys)
+            | Terminal::MultiA(_, ref keys)
+            | Terminal::SortedMultiA(_, ref keys) => keys
                 .iter()
                 .map(|key| PkPkh::PlainPubkey(key.clone()))
                 .collect(),
@@ -175,9 +212,9 @@ impl<Pk: MiniscriptKey, Ctx: ScriptContext> Miniscript<Pk, Ctx> {
     pub fn get_nth_pk(&self, n: usize) -> Option<Pk> {
         match (&self.node, n) {
             (&Terminal::PkK(ref key), 0) => Some(key.clone()),
-            (&Terminal::Multi(_, ref keys), _) | (&Terminal::MultiA(_, ref keys), _) => {
-                keys.get(n).cloned()
-            }
+            (&Terminal::Multi(_, ref keys), _)
+            | (&Terminal::MultiA(_, ref keys), _)
+            | (&Terminal::SortedMultiA(_, ref keys), _) => keys.get(n).cloned(),
             _ => None,
         }
     }
@@ -193,9 +230,9 @@ impl<Pk: MiniscriptKey, Ctx: ScriptContext> Miniscript<Pk, Ctx> {
         match (&self.node, n) {
             (&Terminal::PkH(ref hash), 0) => Some(hash.clone()),
             (&Terminal::PkK(ref key), 0) => Some(key.to_pubkeyhash()),
-            (&Terminal::Multi(_, ref keys), _) | (&Terminal::MultiA(_, ref keys), _) => {
-                keys.get(n).map(Pk::to_pubkeyhash)
-            }
+            (&Terminal::Multi(_, ref keys), _)
+            | (&Terminal::MultiA(_, ref keys), _)
+            | (&Terminal::SortedMultiA(_, ref keys), _) => keys.get(n).map(Pk::to_pubkeyhash),
             _ => None,
         }
     }
@@ -208,7 +245,9 @@ impl<Pk: MiniscriptKey, Ctx: ScriptContext> Miniscript<Pk, Ctx> {
         match (&self.node, n) {
             (&Terminal::PkH(ref hash), 0) => Some(PkPkh::HashedPubkey(hash.clone())),
             (&Terminal::PkK(ref key), 0) => Some(PkPkh::PlainPubkey(key.clone())),
-            (&Terminal::Multi(_, ref keys), _) | (&Terminal::MultiA(_, ref keys), _) => {
+            (&Terminal::Multi(_, ref keys), _)
+            | (&Terminal::MultiA(_, ref keys), _)
+            | (&Terminal::SortedMultiA(_, ref keys), _) => {
                 keys.get(n).map(|key| PkPkh::PlainPubkey(key.clone()))
             }
             _ => None,
@@ -448,6 +487,181 @@ impl<'a, Pk: MiniscriptKey, Ctx: ScriptContext> Iterator for PkPkhIter<'a, Pk, C
     }
 }
 
+/// Iterator for traversing all [Miniscript] miniscript AST references along with their depth
+/// below the node which constructs the iterator via [Miniscript::iter_with_depth] method.
+pub struct DepthIter<'a, Pk: MiniscriptKey, Ctx: ScriptContext> {
+    node_iter: Iter<'a, Pk, Ctx>,
+}
+
+impl<'a, Pk: MiniscriptKey, Ctx: ScriptContext> DepthIter<'a, Pk, Ctx> {
+    fn new(miniscript: &'a Miniscript<Pk, Ctx>) -> Self {
+        DepthIter {
+            node_iter: Iter::new(miniscript),
+        }
+    }
+}
+
+impl<'a, Pk: MiniscriptKey, Ctx: ScriptContext> Iterator for DepthIter<'a, Pk, Ctx> {
+    type Item = (usize, &'a Miniscript<Pk, Ctx>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = self.node_iter.next()?;
+        // `Iter::next` leaves the returned node on top of its path
+        Some((self.node_iter.path.len() - 1, node))
+    }
+}
+
+/// Iterator for traversing all [Miniscript] miniscript AST references in post-order starting
+/// from some specific node which constructs the iterator via [Miniscript::iter_postorder] method.
+pub struct PostOrderIter<'a, Pk: MiniscriptKey, Ctx: ScriptContext> {
+    // Same as for `Iter`: the nodes on the path along with the index of their next child
+    path: Vec<(&'a Miniscript<Pk, Ctx>, usize)>,
+}
+
+impl<'a, Pk: MiniscriptKey, Ctx: ScriptContext> PostOrderIter<'a, Pk, Ctx> {
+    fn new(miniscript: &'a Miniscript<Pk, Ctx>) -> Self {
+        PostOrderIter {
+            path: vec![(miniscript, 0)],
+        }
+    }
+}
+
+impl<'a, Pk: MiniscriptKey, Ctx: ScriptContext> Iterator for PostOrderIter<'a, Pk, Ctx> {
+    type Item = &'a Miniscript<Pk, Ctx>;
+
+    /// Descends to the leftmost leaf of the tree and returns it, then returns its siblings
+    /// (each after their own children) and, once all of them are done, their parent.
+    ///
+    /// For the AST given as an example for [Iter::next], `PostOrderIter::next()` will iterate
+    /// over the nodes in the following order:
+    /// `E > D > F > H > J > I > G > C > B > K > A`
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (node, child) = self.path.last_mut()?;
+            match node.get_nth_child(*child) {
+                Some(next) => {
+                    *child += 1;
+                    self.path.push((next, 0));
+                }
+                None => return self.path.pop().map(|(node, _)| node),
+            }
+        }
+    }
+}
+
+/// A timelock fragment of a miniscript AST
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Timelock {
+    /// Absolute timelock, `after(n)`
+    After(u32),
+    /// Relative timelock, `older(n)`
+    Older(u32),
+}
+
+/// Iterator for traversing all timelocks in AST starting from some specific node which
+/// constructs the iterator via [Miniscript::iter_timelocks] method.
+pub struct TimelockIter<'a, Pk: MiniscriptKey, Ctx: ScriptContext> {
+    node_iter: Iter<'a, Pk, Ctx>,
+}
+
+impl<'a, Pk: MiniscriptKey, Ctx: ScriptContext> TimelockIter<'a, Pk, Ctx> {
+    fn new(miniscript: &'a Miniscript<Pk, Ctx>) -> Self {
+        TimelockIter {
+            node_iter: Iter::new(miniscript),
+        }
+    }
+}
+
+impl<'a, Pk: MiniscriptKey, Ctx: ScriptContext> Iterator for TimelockIter<'a, Pk, Ctx> {
+    type Item = Timelock;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.node_iter.next()?.node {
+                Terminal::After(n) => break Some(Timelock::After(n)),
+                Terminal::Older(n) => break Some(Timelock::Older(n)),
+                _ => continue,
+            }
+        }
+    }
+}
+
+/// A reference to the hash of a hashlock fragment of a miniscript AST
+#[derive(Debug, PartialEq, Eq)]
+pub enum HashLock<'a, Pk: MiniscriptKey> {
+    /// `sha256(h)`
+    Sha256(&'a Pk::Sha256),
+    /// `hash256(h)`
+    Hash256(&'a Pk::Hash256),
+    /// `ripemd160(h)`
+    Ripemd160(&'a Pk::Ripemd160),
+    /// `hash160(h)`
+    Hash160(&'a Pk::Hash160),
+}
+
+/// Iterator for traversing all hashlocks in AST starting from some specific node which
+/// constructs the iterator via [Miniscript::iter_hashes] method.
+pub struct HashIter<'a, Pk: MiniscriptKey, Ctx: ScriptContext> {
+    node_iter: Iter<'a, Pk, Ctx>,
+}
+
+impl<'a, Pk: MiniscriptKey, Ctx: ScriptContext> HashIter<'a, Pk, Ctx> {
+    fn new(miniscript: &'a Miniscript<Pk, Ctx>) -> Self {
+        HashIter {
+            node_iter: Iter::new(miniscript),
+        }
+    }
+}
+
+impl<'a, Pk: MiniscriptKey, Ctx: ScriptContext> Iterator for HashIter<'a, Pk, Ctx> {
+    type Item = HashLock<'a, Pk>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.node_iter.next()?.node {
+                Terminal::Sha256(ref h) => break Some(HashLock::Sha256(h)),
+                Terminal::Hash256(ref h) => break Some(HashLock::Hash256(h)),
+                Terminal::Ripemd160(ref h) => break Some(HashLock::Ripemd160(h)),
+                Terminal::Hash160(ref h) => break Some(HashLock::Hash160(h)),
+                _ => continue,
+            }
+        }
+    }
+}
+
+/// Iterator for traversing all threshold fragments in AST starting from some specific node
+/// which constructs the iterator via [Miniscript::iter_thresholds] method. Each item is the
+/// threshold `k` along with the fragment node, whose `n` can be read off
+/// [Miniscript::branches] for `thresh` and [Miniscript::get_leaf_pk] for the multisigs.
+pub struct ThreshIter<'a, Pk: MiniscriptKey, Ctx: ScriptContext> {
+    node_iter: Iter<'a, Pk, Ctx>,
+}
+
+impl<'a, Pk: MiniscriptKey, Ctx: ScriptContext> ThreshIter<'a, Pk, Ctx> {
+    fn new(miniscript: &'a Miniscript<Pk, Ctx>) -> Self {
+        ThreshIter {
+            node_iter: Iter::new(miniscript),
+        }
+    }
+}
+
+impl<'a, Pk: MiniscriptKey, Ctx: ScriptContext> Iterator for ThreshIter<'a, Pk, Ctx> {
+    type Item = (usize, &'a Miniscript<Pk, Ctx>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let node = self.node_iter.next()?;
+            match node.node {
+                Terminal::Thresh(k, _)
+                | Terminal::Multi(k, _)
+                | Terminal::MultiA(k, _)
+                | Terminal::SortedMultiA(k, _) => break Some((k, node)),
+                _ => continue,
+            }
+        }
+    }
+}
+
 // Module is public since it export testcase generation which may be used in
 // dependent libraries for their own tasts based on Miniscript AST
 #[cfg(test)]
@@ -456,8 +670,9 @@ pub mod test {
     use bitcoin::hashes::{hash160, ripemd160, sha256, sha256d, Hash};
     use bitcoin::secp256k1;
 
-    use super::{Miniscript, PkPkh};
+    use super::{HashLock, Miniscript, PkPkh, Timelock};
     use crate::miniscript::context::Segwitv0;
+    use crate::Terminal;
 
     pub type TestData = (
         Miniscript<bitcoin::PublicKey, Segwitv0>,
@@ -666,6 +881,102 @@ pub mod test {
         })
     }
 
+    #[test]
+    fn traversal_orders() {
+        let (ms, _, _, _) = gen_testcases().pop().unwrap();
+        let names = |nodes: Vec<&Miniscript<bitcoin::PublicKey, Segwitv0>>| {
+            nodes
+                .into_iter()
+                .map(|node| match node.node {
+                    Terminal::OrD(..) => "or_d",
+                    Terminal::AndV(..) => "and_v",
+                    Terminal::Verify(..) => "v",
+                    Terminal::Thresh(..) => "thresh",
+                    Terminal::Check(..) => "c",
+                    Terminal::Alt(..) => "a",
+                    Terminal::PkH(..) => "pk_h",
+                    Terminal::Multi(..) => "multi",
+                    Terminal::Older(..) => "older",
+                    _ => unreachable!(),
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let with_depth = ms.iter_with_depth().collect::<Vec<_>>();
+        assert_eq!(
+            with_depth.iter().map(|&(_, node)| node).collect::<Vec<_>>(),
+            ms.iter().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            with_depth.iter().map(|&(d, _)| d).collect::<Vec<_>>(),
+            vec![0, 1, 1, 2, 3, 4, 5, 4, 5, 6, 4, 5, 6, 2]
+        );
+        assert_eq!(
+            names(ms.iter_postorder().collect()),
+            vec![
+                "multi", "pk_h", "c", "pk_h", "c", "a", "pk_h", "c", "a", "thresh", "v", "older",
+                "and_v", "or_d"
+            ]
+        );
+        let leaf: Miniscript<bitcoin::PublicKey, Segwitv0> = ms_str!("older({})", 1000);
+        assert_eq!(leaf.iter_postorder().collect::<Vec<_>>(), vec![&leaf]);
+        assert_eq!(leaf.iter_with_depth().collect::<Vec<_>>(), vec![(0, &leaf)]);
+    }
+
+    #[test]
+    fn find_fragments() {
+        let testcases = gen_testcases();
+        let timelocks = testcases
+            .iter()
+            .flat_map(|(ms, _, _, _)| ms.iter_timelocks())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            timelocks,
+            vec![
+                Timelock::After(1000),
+                Timelock::Older(1000),
+                Timelock::Older(10000),
+                Timelock::Older(10000)
+            ]
+        );
+
+        let hashes = testcases
+            .iter()
+            .flat_map(|(ms, _, _, _)| ms.iter_hashes())
+            .map(|hash| match hash {
+                HashLock::Sha256(_) => "sha256",
+                HashLock::Hash256(_) => "hash256",
+                HashLock::Ripemd160(_) => "ripemd160",
+                HashLock::Hash160(_) => "hash160",
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            hashes,
+            vec![
+                "sha256",
+                "hash256",
+                "hash160",
+                "ripemd160",
+                "sha256",
+                "sha256"
+            ]
+        );
+        let (ms, _, _, _) = &testcases[2];
+        match ms.iter_hashes().next() {
+            Some(HashLock::Sha256(h)) => {
+                assert_eq!(*h, sha256::Hash::hash(&[0xab; 32]))
+            }
+            _ => panic!("expected a sha256 hashlock"),
+        }
+
+        let (ms, _, _, _) = testcases.last().unwrap();
+        let thresholds = ms
+            .iter_thresholds()
+            .map(|(k, node)| (k, node.branches().len() + node.get_leaf_pk().len()))
+            .collect::<Vec<_>>();
+        assert_eq!(thresholds, vec![(3, 5), (2, 3)]);
+    }
+
     #[test]
     fn find_pubkeys_and_hashes() {
         gen_testcases().into_iter().for_each(|(ms, k, h, _)| {
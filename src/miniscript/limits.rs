@@ -2,6 +2,10 @@
 //! These constraints can be either Consensus or Policy (standardness) rules, for either Segwitv0
 //! or Legacy scripts.
 
+use core::fmt;
+
+use crate::prelude::*;
+
 /// Maximum operations per script
 // https://github.com/bitcoin/bitcoin/blob/875e1ccc9fe01e026e564dfd39a64d9a4b332a89/src/script/script.h#L26
 pub const MAX_OPS_PER_SCRIPT: usize = 201;
@@ -50,3 +54,176 @@ pub const MAX_BLOCK_WEIGHT: usize = 4000000;
 /// Maximum pubkeys as arguments to CHECKMULTISIG
 // https://github.com/bitcoin/bitcoin/blob/6acda4b00b3fc1bfac02f5de590e1a5386cbc779/src/script/script.h#L30
 pub const MAX_PUBKEYS_PER_MULTISIG: usize = 20;
+
+/// Worst-case resources used by the satisfactions of a Miniscript, as
+/// computed by [`Miniscript::resource_usage`](crate::Miniscript::resource_usage).
+///
+/// Every field is the maximum over all satisfactions, so that checking it
+/// against a limit tells whether some spend path of the Miniscript could
+/// violate that limit. Fields are `None` when the Miniscript cannot be
+/// satisfied.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Hash)]
+pub struct ResourceUsage {
+    /// Number of opcodes counted towards [`MAX_OPS_PER_SCRIPT`]
+    pub ops_count: Option<usize>,
+    /// Number of stack and altstack elements during execution, including the
+    /// initial witness elements
+    pub stack_size: Option<usize>,
+    /// Size of the script, in bytes
+    pub script_size: usize,
+    /// Number of witness elements, including the witness script itself
+    pub max_witness_elements: Option<usize>,
+    /// Size of the satisfaction, in bytes: the witness for segwit contexts and
+    /// the scriptSig otherwise
+    pub max_satisfaction_size: Option<usize>,
+}
+
+/// A resource limited by consensus or standardness rules
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Hash)]
+pub enum ResourceLimit {
+    /// Opcodes executed by a satisfaction
+    OpsCount,
+    /// Stack elements during execution
+    StackSize,
+    /// Size of the script
+    ScriptSize,
+    /// Witness elements of a satisfaction
+    WitnessElements,
+    /// Size of the scriptSig of a satisfaction
+    ScriptSigSize,
+}
+
+/// A consensus or standardness limit exceeded by some spend path of a Miniscript
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Hash)]
+pub struct LimitExceeded {
+    /// The exceeded limit
+    pub limit: ResourceLimit,
+    /// Whether the limit is a consensus rule, rather than a standardness rule
+    pub consensus: bool,
+    /// The worst-case usage of the resource
+    pub actual: usize,
+    /// The maximum allowed usage of the resource
+    pub max: usize,
+}
+
+impl LimitExceeded {
+    /// Create a [`LimitExceeded`] if `actual` is above `max`
+    pub fn check(
+        limit: ResourceLimit,
+        consensus: bool,
+        actual: Option<usize>,
+        max: usize,
+    ) -> Option<Self> {
+        match actual {
+            Some(actual) if actual > max => Some(LimitExceeded {
+                limit,
+                consensus,
+                actual,
+                max,
+            }),
+            _ => None,
+        }
+    }
+
+    /// By how much the limit is exceeded
+    pub fn excess(&self) -> usize {
+        self.actual - self.max
+    }
+}
+
+impl ResourceUsage {
+    /// The worst-case usage of the resource limited by `limit`
+    pub fn get(&self, limit: ResourceLimit) -> Option<usize> {
+        match limit {
+            ResourceLimit::OpsCount => self.ops_count,
+            ResourceLimit::StackSize => self.stack_size,
+            ResourceLimit::ScriptSize => Some(self.script_size),
+            ResourceLimit::WitnessElements => self.max_witness_elements,
+            ResourceLimit::ScriptSigSize => self.max_satisfaction_size,
+        }
+    }
+}
+
+/// The rules a Miniscript is checked against by
+/// [`Miniscript::check_resource_limits_with`](crate::Miniscript::check_resource_limits_with),
+/// [`Miniscript::sanity_check_with`](crate::Miniscript::sanity_check_with)
+/// and [`Miniscript::satisfy_with_rules`](crate::Miniscript::satisfy_with_rules),
+/// and by the descriptor sanity checks and psbt finalizer taking a rule set.
+///
+/// Consensus limits of the script context always apply: spends exceeding
+/// them can never be mined.
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Hash)]
+pub enum RuleSet {
+    /// Only the consensus limits, allowing spends which are valid but would
+    /// not be relayed by default nodes
+    Consensus,
+    /// The consensus and standardness limits
+    Standard,
+    /// The consensus limits, with a custom maximum replacing the standardness
+    /// limits of each listed resource. Resources which are not listed keep
+    /// their standardness limits.
+    Custom {
+        /// The maximum usage of each resource
+        limits: BTreeMap<ResourceLimit, usize>,
+    },
+}
+
+impl Default for RuleSet {
+    fn default() -> Self {
+        RuleSet::Standard
+    }
+}
+
+impl RuleSet {
+    /// The limits which are exceeded under this rule set, given the limits of
+    /// a script context which are `exceeded` and the `usage` of each resource
+    pub(crate) fn apply<F>(&self, exceeded: Vec<LimitExceeded>, usage: F) -> Vec<LimitExceeded>
+    where
+        F: Fn(ResourceLimit) -> Option<usize>,
+    {
+        match *self {
+            RuleSet::Consensus => exceeded.into_iter().filter(|e| e.consensus).collect(),
+            RuleSet::Standard => exceeded,
+            RuleSet::Custom { ref limits } => {
+                let mut exceeded: Vec<_> = exceeded
+                    .into_iter()
+                    .filter(|e| e.consensus || !limits.contains_key(&e.limit))
+                    .collect();
+                exceeded.extend(limits.iter().filter_map(|(&limit, &max)| {
+                    LimitExceeded::check(limit, false, usage(limit), max)
+                }));
+                exceeded
+            }
+        }
+    }
+}
+
+impl fmt::Display for ResourceLimit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            ResourceLimit::OpsCount => "opcode count",
+            ResourceLimit::StackSize => "stack size",
+            ResourceLimit::ScriptSize => "script size",
+            ResourceLimit::WitnessElements => "witness elements",
+            ResourceLimit::ScriptSigSize => "scriptSig size",
+        })
+    }
+}
+
+impl fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} of {} exceeds the {} limit of {} by {}",
+            self.limit,
+            self.actual,
+            if self.consensus {
+                "consensus"
+            } else {
+                "standardness"
+            },
+            self.max,
+            self.excess()
+        )
+    }
+}
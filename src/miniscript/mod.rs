@@ -30,13 +30,16 @@ use core::{fmt, hash, str};
 use bitcoin::blockdata::script;
 use bitcoin::util::taproot::{LeafVersion, TapLeafHash};
 
-pub use self::context::{BareCtx, Legacy, Segwitv0, Tap};
+pub use self::context::{
+    BareCtx, ContextError, KeyRule, Legacy, ScriptContextError, Segwitv0, SigType, Tap,
+};
 use crate::prelude::*;
 
 pub mod analyzable;
 pub mod astelem;
 pub(crate) mod context;
 pub mod decode;
+pub mod interpret;
 pub mod iter;
 pub mod lex;
 pub mod limits;
@@ -51,9 +54,14 @@ use self::lex::{lex, TokenIter};
 use self::types::Property;
 pub use crate::miniscript::context::ScriptContext;
 use crate::miniscript::decode::Terminal;
+use crate::miniscript::limits::{LimitExceeded, ResourceLimit, ResourceUsage, RuleSet};
 use crate::miniscript::types::extra_props::ExtData;
 use crate::miniscript::types::Type;
-use crate::{expression, Error, ForEach, ForEachKey, MiniscriptKey, ToPublicKey, TranslatePk};
+use crate::policy::{Liftable, Semantic};
+use crate::util::witness_to_scriptsig;
+use crate::{
+    expression, Error, ForEach, ForEachKey, MiniscriptKey, ToPublicKey, TranslatePk, Translator,
+};
 
 #[cfg(test)]
 mod ms_tests;
@@ -264,9 +272,60 @@ impl<Pk: MiniscriptKey, Ctx: ScriptContext> Miniscript<Pk, Ctx> {
     /// All signatures are assumed to be 73 bytes in size, including the
     /// length prefix (segwit) or push opcode (pre-segwit) and sighash
     /// postfix.
+    ///
+    /// A key repeated in several fragments of a branch is counted once per
+    /// fragment. Even when the fragments commit to the same sighash, so that
+    /// the same signature satisfies all of them, every `CHECKSIG` consumes its
+    /// own copy of the signature from the stack: reusing a signature saves
+    /// signing, not witness space.
     pub fn max_satisfaction_size(&self) -> Result<usize, Error> {
         Ctx::max_satisfaction_size(self).ok_or(Error::ImpossibleSatisfaction)
     }
+
+    /// Worst-case resources used by the satisfactions of this Miniscript,
+    /// which can be checked against the limits of the script context with
+    /// [`Miniscript::check_resource_limits`].
+    pub fn resource_usage(&self) -> ResourceUsage {
+        let stack_size = match (
+            self.ext.exec_stack_elem_count_sat,
+            self.ext.stack_elem_count_sat,
+        ) {
+            (Some(exec), Some(initial)) => Some(exec + initial),
+            _ => None,
+        };
+        ResourceUsage {
+            ops_count: self.ext.ops.op_count(),
+            stack_size,
+            script_size: self.script_size(),
+            max_witness_elements: self.max_satisfaction_witness_elements().ok(),
+            max_satisfaction_size: Ctx::max_satisfaction_size(self),
+        }
+    }
+
+    /// Check the resource usage of this Miniscript against the consensus and
+    /// standardness limits of its script context, returning every limit which
+    /// some spend path would exceed, and by how much.
+    pub fn check_resource_limits(&self) -> Result<ResourceUsage, Vec<LimitExceeded>> {
+        self.check_resource_limits_with(&RuleSet::Standard)
+    }
+
+    /// Check the resource usage of this Miniscript against the limits of its
+    /// script context under `rules`, returning every limit which some spend
+    /// path would exceed, and by how much.
+    pub fn check_resource_limits_with(
+        &self,
+        rules: &RuleSet,
+    ) -> Result<ResourceUsage, Vec<LimitExceeded>> {
+        let usage = self.resource_usage();
+        let exceeded = rules.apply(Ctx::resource_limits_exceeded(&usage), |limit| {
+            usage.get(limit)
+        });
+        if exceeded.is_empty() {
+            Ok(usage)
+        } else {
+            Err(exceeded)
+        }
+    }
 }
 
 impl<Pk: MiniscriptKey, Ctx: ScriptContext> ForEachKey<Pk> for Miniscript<Pk, Ctx> {
@@ -287,24 +346,18 @@ where
 {
     type Output = Miniscript<Q, Ctx>;
 
-    /// Translates a struct from one generic to another where the translation
-    /// for Pk is provided by function `fpk`, and translation for PkH is
-    /// provided by function `fpkh`.
+    /// Translates a struct from one generic to another where the translations
+    /// of all keys and hashes are provided by `translator`.
     ///
     /// # Panics
     ///
-    /// If `fpk` returns an uncompressed key when converting to a Segwit descriptor.
-    /// To prevent this panic, ensure `fpk` returns an error in this case instead.
-    fn translate_pk<Fpk, Fpkh, FuncError>(
-        &self,
-        mut fpk: Fpk,
-        mut fpkh: Fpkh,
-    ) -> Result<Self::Output, FuncError>
+    /// If the translator returns an uncompressed key when converting to a Segwit descriptor.
+    /// To prevent this panic, ensure it returns an error in this case instead.
+    fn translate_pk<T, FuncError>(&self, translator: &mut T) -> Result<Self::Output, FuncError>
     where
-        Fpk: FnMut(&Pk) -> Result<Q, FuncError>,
-        Fpkh: FnMut(&Pk::Hash) -> Result<Q::Hash, FuncError>,
+        T: Translator<Pk, Q, FuncError> + ?Sized,
     {
-        self.real_translate_pk(&mut fpk, &mut fpkh)
+        self.real_translate_pk(translator)
     }
 }
 
@@ -317,18 +370,16 @@ impl<Pk: MiniscriptKey, Ctx: ScriptContext> Miniscript<Pk, Ctx> {
         self.node.real_for_each_key(pred)
     }
 
-    pub(crate) fn real_translate_pk<Fpk, Fpkh, Q, FuncError, CtxQ>(
+    pub(crate) fn real_translate_pk<Q, FuncError, CtxQ, T>(
         &self,
-        fpk: &mut Fpk,
-        fpkh: &mut Fpkh,
+        t: &mut T,
     ) -> Result<Miniscript<Q, CtxQ>, FuncError>
     where
-        Fpk: FnMut(&Pk) -> Result<Q, FuncError>,
-        Fpkh: FnMut(&Pk::Hash) -> Result<Q::Hash, FuncError>,
         Q: MiniscriptKey,
         CtxQ: ScriptContext,
+        T: Translator<Pk, Q, FuncError> + ?Sized,
     {
-        let inner = self.node.real_translate_pk(fpk, fpkh)?;
+        let inner = self.node.real_translate_pk(t)?;
         let ms = Miniscript {
             //directly copying the type and ext is safe because translating public
             //key should not change any properties
@@ -351,12 +402,69 @@ impl<Pk: MiniscriptKey, Ctx: ScriptContext> Miniscript<Pk, Ctx> {
     where
         Pk: str::FromStr,
         Pk::Hash: str::FromStr,
+        Pk::Sha256: str::FromStr,
+        Pk::Hash256: str::FromStr,
+        Pk::Ripemd160: str::FromStr,
+        Pk::Hash160: str::FromStr,
         <Pk as str::FromStr>::Err: ToString,
         <<Pk as MiniscriptKey>::Hash as str::FromStr>::Err: ToString,
+        <<Pk as MiniscriptKey>::Sha256 as str::FromStr>::Err: ToString,
+        <<Pk as MiniscriptKey>::Hash256 as str::FromStr>::Err: ToString,
+        <<Pk as MiniscriptKey>::Ripemd160 as str::FromStr>::Err: ToString,
+        <<Pk as MiniscriptKey>::Hash160 as str::FromStr>::Err: ToString,
     {
         // This checks for invalid ASCII chars
         let top = expression::Tree::from_str(s)?;
-        let ms: Miniscript<Pk, Ctx> = expression::FromTree::from_tree(&top)?;
+        Self::from_tree_top_level(&top)
+    }
+
+    /// Parse a Miniscript from string as [`FromStr`](core::str::FromStr),
+    /// failing with [`Error::LimitExceeded`] when the string exceeds one of
+    /// the limits of `options`.
+    ///
+    /// The limits are checked before the keys and fragments are parsed, so
+    /// this should be preferred over `from_str` for untrusted input.
+    pub fn from_str_with_options(
+        s: &str,
+        options: &expression::ParseOptions,
+    ) -> Result<Miniscript<Pk, Ctx>, Error>
+    where
+        Pk: str::FromStr,
+        Pk::Hash: str::FromStr,
+        Pk::Sha256: str::FromStr,
+        Pk::Hash256: str::FromStr,
+        Pk::Ripemd160: str::FromStr,
+        Pk::Hash160: str::FromStr,
+        <Pk as str::FromStr>::Err: ToString,
+        <<Pk as MiniscriptKey>::Hash as str::FromStr>::Err: ToString,
+        <<Pk as MiniscriptKey>::Sha256 as str::FromStr>::Err: ToString,
+        <<Pk as MiniscriptKey>::Hash256 as str::FromStr>::Err: ToString,
+        <<Pk as MiniscriptKey>::Ripemd160 as str::FromStr>::Err: ToString,
+        <<Pk as MiniscriptKey>::Hash160 as str::FromStr>::Err: ToString,
+    {
+        let top = options.parse_tree(s)?;
+        let ms = Self::from_tree_top_level(&top)?;
+        ms.sanity_check()?;
+        Ok(ms)
+    }
+
+    // Parse a top level miniscript, which must be of type B
+    fn from_tree_top_level(top: &expression::Tree) -> Result<Miniscript<Pk, Ctx>, Error>
+    where
+        Pk: str::FromStr,
+        Pk::Hash: str::FromStr,
+        Pk::Sha256: str::FromStr,
+        Pk::Hash256: str::FromStr,
+        Pk::Ripemd160: str::FromStr,
+        Pk::Hash160: str::FromStr,
+        <Pk as str::FromStr>::Err: ToString,
+        <<Pk as MiniscriptKey>::Hash as str::FromStr>::Err: ToString,
+        <<Pk as MiniscriptKey>::Sha256 as str::FromStr>::Err: ToString,
+        <<Pk as MiniscriptKey>::Hash256 as str::FromStr>::Err: ToString,
+        <<Pk as MiniscriptKey>::Ripemd160 as str::FromStr>::Err: ToString,
+        <<Pk as MiniscriptKey>::Hash160 as str::FromStr>::Err: ToString,
+    {
+        let ms: Miniscript<Pk, Ctx> = expression::FromTree::from_tree(top)?;
 
         if ms.ty.corr.base != types::Base::B {
             Err(Error::NonTopLevel(format!("{:?}", ms)))
@@ -373,19 +481,9 @@ impl<Pk: MiniscriptKey, Ctx: ScriptContext> Miniscript<Pk, Ctx> {
     where
         Pk: ToPublicKey,
     {
-        // Only satisfactions for default versions (0xc0) are allowed.
-        let leaf_hash = TapLeafHash::from_script(&self.encode(), LeafVersion::TapScript);
-        match satisfy::Satisfaction::satisfy(&self.node, &satisfier, self.ty.mall.safe, &leaf_hash)
-            .stack
-        {
-            satisfy::Witness::Stack(stack) => {
-                Ctx::check_witness::<Pk>(&stack)?;
-                Ok(stack)
-            }
-            satisfy::Witness::Unavailable | satisfy::Witness::Impossible => {
-                Err(Error::CouldNotSatisfy)
-            }
-        }
+        let stack = self.satisfy_stack(satisfier, false)?;
+        Ctx::check_witness::<Pk>(&stack)?;
+        Ok(stack)
     }
 
     /// Attempt to produce a malleable satisfying witness for the
@@ -397,19 +495,113 @@ impl<Pk: MiniscriptKey, Ctx: ScriptContext> Miniscript<Pk, Ctx> {
     where
         Pk: ToPublicKey,
     {
+        let stack = self.satisfy_stack(satisfier, true)?;
+        Ctx::check_witness::<Pk>(&stack)?;
+        Ok(stack)
+    }
+
+    /// Attempt to produce a non-malleable satisfying witness, checking it
+    /// against the limits of the script context under `rules`. With
+    /// [`RuleSet::Consensus`] this produces witnesses which are valid but may
+    /// not be relayed by default nodes.
+    pub fn satisfy_with_rules<S: satisfy::Satisfier<Pk>>(
+        &self,
+        satisfier: S,
+        rules: &RuleSet,
+    ) -> Result<Vec<Vec<u8>>, Error>
+    where
+        Pk: ToPublicKey,
+    {
+        let stack = self.satisfy_stack(satisfier, false)?;
+        check_witness_with::<Ctx>(&stack, rules)?;
+        Ok(stack)
+    }
+
+    /// Attempt to produce a malleable satisfying witness, checking it against
+    /// the limits of the script context under `rules`
+    pub fn satisfy_malleable_with_rules<S: satisfy::Satisfier<Pk>>(
+        &self,
+        satisfier: S,
+        rules: &RuleSet,
+    ) -> Result<Vec<Vec<u8>>, Error>
+    where
+        Pk: ToPublicKey,
+    {
+        let stack = self.satisfy_stack(satisfier, true)?;
+        check_witness_with::<Ctx>(&stack, rules)?;
+        Ok(stack)
+    }
+
+    /// Attempt to produce the same non-malleable satisfying witness as
+    /// [`Miniscript::satisfy`], without allocating or recursing.
+    ///
+    /// `slots` needs one [`satisfy::Slot`] per fragment of the Miniscript,
+    /// and `witness` enough room for the whole witness. `leaf_hash` is only
+    /// used by Tapscript signatures; it is the hash of the leaf script of
+    /// this Miniscript, under the default leaf version. As with
+    /// [`Miniscript::satisfy`], the witness is checked against the
+    /// standardness limits of the script context; if it exceeds them, the
+    /// contents of `witness` are unspecified.
+    ///
+    /// This is not constant-time. Which fragments are satisfied, the
+    /// queries made to `satisfier` and the time they take all depend on the
+    /// signatures, preimages and timelocks that are available, and the
+    /// witness itself reveals the branches taken. A signer that must hide
+    /// which keys it holds from timing has to make its `satisfier` answer
+    /// every query in the same time, and should still expect the branch it
+    /// chose to be visible.
+    #[cfg(feature = "satisfy-no-alloc")]
+    pub fn satisfy_no_alloc<'a, S: satisfy::Satisfier<Pk>>(
+        &'a self,
+        satisfier: S,
+        leaf_hash: &TapLeafHash,
+        slots: &mut [satisfy::Slot<'a, Pk, Ctx>],
+        witness: &mut satisfy::WitnessBuffer,
+    ) -> Result<(), Error>
+    where
+        Pk: ToPublicKey,
+    {
+        satisfy::satisfy_into(self, &satisfier, leaf_hash, false, slots, witness)?;
+        Ctx::check_witness_elements(witness.iter())?;
+        Ok(())
+    }
+
+    /// Partially evaluate the Miniscript with the hash preimages provided by
+    /// `satisfier`, returning the semantic policy of what remains to be
+    /// satisfied once they are revealed.
+    ///
+    /// Hash fragments whose preimage is known become trivially satisfied and
+    /// the policy is normalized, so branches that only needed the preimages
+    /// are dropped. Signatures and timelocks provided by `satisfier` are
+    /// ignored.
+    pub fn substitute_hash_preimages<S: satisfy::Satisfier<Pk>>(
+        &self,
+        satisfier: &S,
+    ) -> Result<Semantic<Pk>, Error>
+    where
+        Pk: ToPublicKey,
+    {
+        Ok(self.lift()?.substitute_hash_preimages(satisfier))
+    }
+
+    // The satisfying witness, without checking it against any limit
+    fn satisfy_stack<S: satisfy::Satisfier<Pk>>(
+        &self,
+        satisfier: S,
+        allow_mall: bool,
+    ) -> Result<Vec<Vec<u8>>, Error>
+    where
+        Pk: ToPublicKey,
+    {
+        // Only satisfactions for default versions (0xc0) are allowed.
         let leaf_hash = TapLeafHash::from_script(&self.encode(), LeafVersion::TapScript);
-        match satisfy::Satisfaction::satisfy_mall(
-            &self.node,
-            &satisfier,
-            self.ty.mall.safe,
-            &leaf_hash,
-        )
-        .stack
-        {
-            satisfy::Witness::Stack(stack) => {
-                Ctx::check_witness::<Pk>(&stack)?;
-                Ok(stack)
-            }
+        let satisfaction = if allow_mall {
+            satisfy::Satisfaction::satisfy_mall(self, &satisfier, &leaf_hash)
+        } else {
+            satisfy::Satisfaction::satisfy(self, &satisfier, &leaf_hash)
+        };
+        match satisfaction.stack {
+            satisfy::Witness::Stack(stack) => Ok(stack),
             satisfy::Witness::Unavailable | satisfy::Witness::Impossible => {
                 Err(Error::CouldNotSatisfy)
             }
@@ -417,13 +609,88 @@ impl<Pk: MiniscriptKey, Ctx: ScriptContext> Miniscript<Pk, Ctx> {
     }
 }
 
+// Check a satisfying witness against the limits of `Ctx` under `rules`
+fn check_witness_with<Ctx: ScriptContext>(
+    witness: &[Vec<u8>],
+    rules: &RuleSet,
+) -> Result<(), Error> {
+    let exceeded = rules.apply(Ctx::witness_limits_exceeded(witness), |limit| match limit {
+        ResourceLimit::WitnessElements => Some(witness.len()),
+        ResourceLimit::ScriptSigSize => Some(witness_to_scriptsig(witness).len()),
+        _ => None,
+    });
+    match exceeded.first() {
+        Some(exceeded) => Err(Error::ResourceLimitExceeded(*exceeded)),
+        None => Ok(()),
+    }
+}
+
+impl<Pk: MiniscriptKey + ToPublicKey> Miniscript<Pk, Tap> {
+    /// Size, in bytes, of the satisfying witness produced when exactly the
+    /// keys in `signers` sign with the sighash type `hash_ty`.
+    ///
+    /// Unlike [`Miniscript::max_satisfaction_size`], which assumes the worst
+    /// case for every `multi_a`, this accounts for the signers that are left
+    /// out being satisfied by empty placeholders and for the branches that
+    /// the chosen signers make available. Any other data the satisfaction
+    /// needs, such as hash preimages or timelocks, is looked up in
+    /// `satisfier`; pass `()` if there is none. The size includes the length
+    /// prefix of every witness element but not the witness element count.
+    pub fn satisfaction_size_with_signers<S: satisfy::Satisfier<Pk>>(
+        &self,
+        signers: &[Pk],
+        hash_ty: bitcoin::SchnorrSighashType,
+        satisfier: S,
+    ) -> Result<usize, Error> {
+        // Provides a placeholder signature of the right size for each signer
+        struct Signers<'a, Pk> {
+            keys: &'a [Pk],
+            sig: bitcoin::SchnorrSig,
+        }
+
+        impl<'a, Pk: MiniscriptKey + ToPublicKey> satisfy::Satisfier<Pk> for Signers<'a, Pk> {
+            fn lookup_tap_leaf_script_sig(
+                &self,
+                pk: &Pk,
+                _: &TapLeafHash,
+            ) -> Option<bitcoin::SchnorrSig> {
+                if self.keys.contains(pk) {
+                    Some(self.sig)
+                } else {
+                    None
+                }
+            }
+        }
+
+        let sig = bitcoin::secp256k1::schnorr::Signature::from_slice(&[0; 64])
+            .expect("64 bytes is a valid signature length");
+        let signers = Signers {
+            keys: signers,
+            sig: bitcoin::SchnorrSig { sig, hash_ty },
+        };
+        let witness = self.satisfy((signers, satisfier))?;
+        Ok(witness
+            .iter()
+            .map(|elem| crate::util::varint_len(elem.len()) + elem.len())
+            .sum())
+    }
+}
+
 impl<Pk, Ctx> expression::FromTree for Arc<Miniscript<Pk, Ctx>>
 where
     Pk: MiniscriptKey + str::FromStr,
     Pk::Hash: str::FromStr,
+    Pk::Sha256: str::FromStr,
+    Pk::Hash256: str::FromStr,
+    Pk::Ripemd160: str::FromStr,
+    Pk::Hash160: str::FromStr,
     Ctx: ScriptContext,
     <Pk as str::FromStr>::Err: ToString,
     <<Pk as MiniscriptKey>::Hash as str::FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Sha256 as str::FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Hash256 as str::FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Ripemd160 as str::FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Hash160 as str::FromStr>::Err: ToString,
 {
     fn from_tree(top: &expression::Tree) -> Result<Arc<Miniscript<Pk, Ctx>>, Error> {
         Ok(Arc::new(expression::FromTree::from_tree(top)?))
@@ -434,9 +701,17 @@ impl<Pk, Ctx> expression::FromTree for Miniscript<Pk, Ctx>
 where
     Pk: MiniscriptKey + str::FromStr,
     Pk::Hash: str::FromStr,
+    Pk::Sha256: str::FromStr,
+    Pk::Hash256: str::FromStr,
+    Pk::Ripemd160: str::FromStr,
+    Pk::Hash160: str::FromStr,
     Ctx: ScriptContext,
     <Pk as str::FromStr>::Err: ToString,
     <<Pk as MiniscriptKey>::Hash as str::FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Sha256 as str::FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Hash256 as str::FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Ripemd160 as str::FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Hash160 as str::FromStr>::Err: ToString,
 {
     /// Parse an expression tree into a Miniscript. As a general rule, this
     /// should not be called directly; rather go through the descriptor API.
@@ -458,9 +733,17 @@ impl<Pk, Ctx> str::FromStr for Miniscript<Pk, Ctx>
 where
     Pk: MiniscriptKey + str::FromStr,
     Pk::Hash: str::FromStr,
+    Pk::Sha256: str::FromStr,
+    Pk::Hash256: str::FromStr,
+    Pk::Ripemd160: str::FromStr,
+    Pk::Hash160: str::FromStr,
     Ctx: ScriptContext,
     <Pk as str::FromStr>::Err: ToString,
     <<Pk as MiniscriptKey>::Hash as str::FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Sha256 as str::FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Hash256 as str::FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Ripemd160 as str::FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Hash160 as str::FromStr>::Err: ToString,
 {
     type Err = Error;
 
@@ -487,18 +770,75 @@ mod tests {
     use sync::Arc;
 
     use super::{Miniscript, ScriptContext, Segwitv0, Tap};
+    use crate::miniscript::analyzable::{PathStackSize, PathTimelocks};
+    use crate::miniscript::satisfy::{Older, PreimageFn, PreimageHash};
     use crate::miniscript::types::{self, ExtData, Property, Type};
     use crate::miniscript::Terminal;
-    use crate::policy::Liftable;
+    use crate::policy::{Liftable, Semantic};
     use crate::prelude::*;
     use crate::{
-        hex_script, DummyKey, DummyKeyHash, MiniscriptKey, Satisfier, ToPublicKey, TranslatePk,
-        TranslatePk1, TranslatePk2,
+        hex_script, DummyKey, DummyKeyHash, Error, MiniscriptKey, Satisfier, SatisfierExt,
+        ToPublicKey, TranslatePk, Translator,
     };
 
     type Segwitv0Script = Miniscript<bitcoin::PublicKey, Segwitv0>;
     type Tapscript = Miniscript<bitcoin::secp256k1::XOnlyPublicKey, Tap>;
 
+    // Translator mapping every key and key hash to itself
+    struct IdentityTranslator;
+
+    impl<Pk: MiniscriptKey> Translator<Pk, Pk, ()> for IdentityTranslator {
+        fn pk(&mut self, pk: &Pk) -> Result<Pk, ()> {
+            Ok(pk.clone())
+        }
+
+        fn pkh(&mut self, pkh: &Pk::Hash) -> Result<Pk::Hash, ()> {
+            Ok(pkh.clone())
+        }
+
+        translate_hash_clone!(Pk, Pk, ());
+    }
+
+    // Translator mapping every key to the same key, and failing on key hashes
+    struct ConstantTranslator<Pk>(Pk);
+
+    impl<Pk> Translator<String, Pk, ()> for ConstantTranslator<Pk>
+    where
+        Pk: MiniscriptKey<
+            Sha256 = sha256::Hash,
+            Hash256 = crate::hash256::Hash,
+            Ripemd160 = bitcoin::hashes::ripemd160::Hash,
+            Hash160 = hash160::Hash,
+        >,
+    {
+        fn pk(&mut self, _pk: &String) -> Result<Pk, ()> {
+            Ok(self.0.clone())
+        }
+
+        fn pkh(&mut self, _pkh: &String) -> Result<Pk::Hash, ()> {
+            Err(())
+        }
+
+        fn sha256(&mut self, sha256: &String) -> Result<sha256::Hash, ()> {
+            sha256::Hash::from_str(sha256).map_err(|_| ())
+        }
+
+        fn hash256(&mut self, hash256: &String) -> Result<crate::hash256::Hash, ()> {
+            crate::hash256::Hash::from_str(hash256).map_err(|_| ())
+        }
+
+        fn ripemd160(
+            &mut self,
+            ripemd160: &String,
+        ) -> Result<bitcoin::hashes::ripemd160::Hash, ()> {
+            bitcoin::hashes::ripemd160::Hash::from_str(ripemd160).map_err(|_| ())
+        }
+
+        fn hash160(&mut self, hash160: &String) -> Result<hash160::Hash, ()> {
+            hash160::Hash::from_str(hash160).map_err(|_| ())
+        }
+    }
+
     fn pubkeys(n: usize) -> Vec<bitcoin::PublicKey> {
         let mut ret = Vec::with_capacity(n);
         let secp = secp256k1::Secp256k1::new();
@@ -527,9 +867,17 @@ mod tests {
     ) where
         Pk: MiniscriptKey + str::FromStr,
         Pk::Hash: str::FromStr,
+        Pk::Sha256: str::FromStr,
+        Pk::Hash256: str::FromStr,
+        Pk::Ripemd160: str::FromStr,
+        Pk::Hash160: str::FromStr,
         Ctx: ScriptContext,
         <Pk as str::FromStr>::Err: ToString,
         <<Pk as MiniscriptKey>::Hash as str::FromStr>::Err: ToString,
+        <<Pk as MiniscriptKey>::Sha256 as str::FromStr>::Err: ToString,
+        <<Pk as MiniscriptKey>::Hash256 as str::FromStr>::Err: ToString,
+        <<Pk as MiniscriptKey>::Ripemd160 as str::FromStr>::Err: ToString,
+        <<Pk as MiniscriptKey>::Hash160 as str::FromStr>::Err: ToString,
         Str1: Into<Option<&'static str>>,
         Str2: Into<Option<&'static str>>,
     {
@@ -545,10 +893,7 @@ mod tests {
         let roundtrip = Miniscript::from_str(&display).expect("parse string serialization");
         assert_eq!(roundtrip, script);
 
-        let translated = script.translate_pk_infallible(Pk::clone, Pk::Hash::clone);
-        assert_eq!(translated, script);
-
-        let translated = script.translate_pk1_infallible(Pk::clone);
+        let translated = script.translate_pk(&mut IdentityTranslator).unwrap();
         assert_eq!(translated, script);
     }
 
@@ -1048,12 +1393,14 @@ mod tests {
         assert_eq!(tap_multi_a_ms.to_string(), "multi_a(1,A,B,C)");
 
         // Test encode/decode and translation tests
-        let tap_ms = tap_multi_a_ms.translate_pk2_infallible(|_| {
-            XOnlyPublicKey::from_str(
-                "e948a0bbf8b15ee47cf0851afbce8835b5f06d3003b8e7ed6104e82a1d41d6f8",
-            )
-            .unwrap()
-        });
+        let tap_ms = tap_multi_a_ms
+            .translate_pk(&mut ConstantTranslator(
+                XOnlyPublicKey::from_str(
+                    "e948a0bbf8b15ee47cf0851afbce8835b5f06d3003b8e7ed6104e82a1d41d6f8",
+                )
+                .unwrap(),
+            ))
+            .unwrap();
         // script rtt test
         assert_eq!(
             Miniscript::<XOnlyPublicKey, Tap>::parse_insane(&tap_ms.encode()).unwrap(),
@@ -1086,23 +1433,580 @@ mod tests {
         assert_eq!(wit, vec![schnorr_sig.as_ref().to_vec(), vec![], vec![]]);
     }
 
+    #[test]
+    fn satisfaction_size_with_signers() {
+        type TapMs = Miniscript<XOnlyPublicKey, Tap>;
+        let keys: Vec<XOnlyPublicKey> = [
+            "e948a0bbf8b15ee47cf0851afbce8835b5f06d3003b8e7ed6104e82a1d41d6f8",
+            "2788ee41e76f4f3af603da5bc8fa22997bc0344bb0f95666ba6aaff0242baa99",
+            "cc8a4bc64d897bddc5fbc2f670f7a8ba0b386779106cf1223c6fc5d7cd6fc115",
+            "79be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+        ]
+        .iter()
+        .map(|k| XOnlyPublicKey::from_str(k).unwrap())
+        .collect();
+        let (a, b, c, d) = (keys[0], keys[1], keys[2], keys[3]);
+        let all = bitcoin::SchnorrSighashType::All;
+        let default = bitcoin::SchnorrSighashType::Default;
+
+        let ms = TapMs::from_str(&format!("multi_a(2,{},{},{})", a, b, c)).unwrap();
+        assert_eq!(ms.max_satisfaction_size().unwrap(), 66 * 2 + 1);
+        assert_eq!(
+            ms.satisfaction_size_with_signers(&[a, b], all, ()).unwrap(),
+            66 * 2 + 1
+        );
+        assert_eq!(
+            ms.satisfaction_size_with_signers(&[b, c], default, ())
+                .unwrap(),
+            65 * 2 + 1
+        );
+        assert!(ms.satisfaction_size_with_signers(&[a], all, ()).is_err());
+
+        // The signers pick the branch, and so the dissatisfied placeholders
+        let ms = TapMs::from_str(&format!("or_d(pk({}),multi_a(2,{},{},{}))", d, a, b, c)).unwrap();
+        assert_eq!(
+            ms.satisfaction_size_with_signers(&[d], all, ()).unwrap(),
+            66
+        );
+        assert_eq!(
+            ms.satisfaction_size_with_signers(&[b, c], all, ()).unwrap(),
+            1 + 66 * 2 + 1
+        );
+    }
+
+    #[test]
+    fn sortedmulti_a_tests() {
+        type TapMs = Miniscript<XOnlyPublicKey, Tap>;
+        let a = "e948a0bbf8b15ee47cf0851afbce8835b5f06d3003b8e7ed6104e82a1d41d6f8";
+        let b = "2788ee41e76f4f3af603da5bc8fa22997bc0344bb0f95666ba6aaff0242baa99";
+
+        let segwit_ms = Miniscript::<String, Segwitv0>::from_str_insane("sortedmulti_a(1,A,B)");
+        assert_eq!(
+            segwit_ms.unwrap_err().to_string(),
+            "Multi a(CHECKSIGADD) only allowed post tapscript"
+        );
+        let sorted_ms = TapMs::from_str(&format!("sortedmulti_a(1,{},{})", a, b)).unwrap();
+        assert_eq!(
+            sorted_ms.to_string(),
+            format!("sortedmulti_a(1,{},{})", a, b)
+        );
+
+        // Encoded with the keys sorted, which decodes as a multi_a
+        let multi_ms = TapMs::from_str(&format!("multi_a(1,{},{})", b, a)).unwrap();
+        assert_eq!(sorted_ms.encode(), multi_ms.encode());
+        assert_eq!(TapMs::parse(&sorted_ms.encode()).unwrap(), multi_ms);
+        assert_eq!(sorted_ms.script_size(), multi_ms.script_size());
+        assert_eq!(sorted_ms.ty, multi_ms.ty);
+
+        // Satisfied in the order of the sorted keys
+        let a_key = XOnlyPublicKey::from_str(a).unwrap();
+        let schnorr_sig = secp256k1::schnorr::Signature::from_str("84526253c27c7aef56c7b71a5cd25bebb66dddda437826defc5b2568bde81f0784526253c27c7aef56c7b71a5cd25bebb66dddda437826defc5b2568bde81f07").unwrap();
+        struct KeySatisfier(XOnlyPublicKey, secp256k1::schnorr::Signature);
+
+        // a satisfier holding a signature for a single key
+        impl Satisfier<XOnlyPublicKey> for KeySatisfier {
+            fn lookup_tap_leaf_script_sig(
+                &self,
+                pk: &XOnlyPublicKey,
+                _h: &TapLeafHash,
+            ) -> Option<bitcoin::SchnorrSig> {
+                if *pk == self.0 {
+                    Some(bitcoin::SchnorrSig {
+                        sig: self.1,
+                        hash_ty: bitcoin::SchnorrSighashType::Default,
+                    })
+                } else {
+                    None
+                }
+            }
+        }
+        let s = KeySatisfier(a_key, schnorr_sig);
+        let wit = sorted_ms.satisfy(&s).unwrap();
+        assert_eq!(wit, vec![schnorr_sig.as_ref().to_vec(), vec![]]);
+        assert_eq!(wit, multi_ms.satisfy(&s).unwrap());
+
+        // The same output is produced regardless of the order of the keys
+        let internal = "cc8a4bc64d897bddc5fbc2f670f7a8ba0b386779106cf1223c6fc5d7cd6fc115";
+        let tr = |leaf: String| {
+            crate::Descriptor::<XOnlyPublicKey>::from_str(&format!("tr({},{})", internal, leaf))
+                .unwrap()
+                .script_pubkey()
+        };
+        assert_eq!(
+            tr(format!("sortedmulti_a(1,{},{})", a, b)),
+            tr(format!("sortedmulti_a(1,{},{})", b, a))
+        );
+        assert_eq!(
+            tr(format!("sortedmulti_a(1,{},{})", a, b)),
+            tr(format!("multi_a(1,{},{})", b, a))
+        );
+    }
+
+    #[test]
+    fn resource_usage() {
+        use crate::miniscript::limits::{LimitExceeded, ResourceLimit};
+
+        // 203 opcodes within a standard size script
+        let subs: Vec<_> = (1..68).map(|i| format!(",s:pk(K{})", i)).collect();
+        let ms_str = format!("thresh(1,pk(K0){})", subs.concat());
+        let segwit_ms = Miniscript::<String, Segwitv0>::from_str_insane(&ms_str).unwrap();
+        let usage = segwit_ms.resource_usage();
+        assert_eq!(usage.ops_count, Some(203));
+        assert_eq!(usage.script_size, 2516);
+        assert_eq!(usage.max_witness_elements, Some(69));
+        let exceeded = segwit_ms.check_resource_limits().unwrap_err();
+        assert_eq!(
+            exceeded,
+            vec![LimitExceeded {
+                limit: ResourceLimit::OpsCount,
+                consensus: true,
+                actual: 203,
+                max: 201,
+            }]
+        );
+        assert_eq!(exceeded[0].excess(), 2);
+        assert_eq!(
+            exceeded[0].to_string(),
+            "opcode count of 203 exceeds the consensus limit of 201 by 2"
+        );
+        assert!(!segwit_ms.within_resource_limits());
+
+        // Tapscript has no opcode limit
+        let tap_ms = Miniscript::<String, Tap>::from_str_insane(&ms_str).unwrap();
+        assert_eq!(tap_ms.check_resource_limits(), Ok(tap_ms.resource_usage()));
+    }
+
+    #[test]
+    fn rule_sets() {
+        use crate::miniscript::limits::{LimitExceeded, ResourceLimit, RuleSet};
+
+        // 101 signatures are within consensus limits but exceed the
+        // standardness limit of 100 witness elements
+        fn verify_all(keys: &[bitcoin::PublicKey]) -> String {
+            match keys.len() {
+                1 => format!("v:pk({})", keys[0]),
+                n => format!(
+                    "and_v({},{})",
+                    verify_all(&keys[..n / 2]),
+                    verify_all(&keys[n / 2..])
+                ),
+            }
+        }
+        let keys = pubkeys(101);
+        let ms_str = format!("and_v({},pk({}))", verify_all(&keys[..100]), keys[100]);
+        let ms = Miniscript::<bitcoin::PublicKey, Segwitv0>::from_str_insane(&ms_str).unwrap();
+        let witness_elements = LimitExceeded {
+            limit: ResourceLimit::WitnessElements,
+            consensus: false,
+            actual: 102,
+            max: 100,
+        };
+        assert_eq!(ms.check_resource_limits(), Err(vec![witness_elements]));
+        assert_eq!(
+            ms.check_resource_limits_with(&RuleSet::Standard),
+            ms.check_resource_limits()
+        );
+        assert_eq!(
+            ms.check_resource_limits_with(&RuleSet::Consensus),
+            Ok(ms.resource_usage())
+        );
+        assert_eq!(ms.sanity_check_with(&RuleSet::Consensus), Ok(()));
+
+        // Custom limits replace the standardness limit of their resource
+        let mut limits = BTreeMap::new();
+        limits.insert(ResourceLimit::WitnessElements, 200);
+        let relaxed = RuleSet::Custom { limits };
+        assert!(ms.check_resource_limits_with(&relaxed).is_ok());
+        let mut limits = BTreeMap::new();
+        limits.insert(ResourceLimit::ScriptSize, 1000);
+        let tightened = RuleSet::Custom { limits };
+        assert_eq!(
+            ms.check_resource_limits_with(&tightened),
+            Err(vec![
+                witness_elements,
+                LimitExceeded {
+                    limit: ResourceLimit::ScriptSize,
+                    consensus: false,
+                    actual: 3535,
+                    max: 1000,
+                }
+            ])
+        );
+        assert!(!ms.within_resource_limits_with(&tightened));
+
+        let sig = secp256k1::ecdsa::Signature::from_compact(&[1; 64]).unwrap();
+        let sigs: HashMap<_, _> = keys
+            .iter()
+            .map(|&pk| (pk, bitcoin::EcdsaSig::sighash_all(sig)))
+            .collect();
+        assert!(ms.satisfy(&sigs).is_err());
+        assert_eq!(
+            ms.satisfy_with_rules(&sigs, &RuleSet::Standard),
+            Err(Error::ResourceLimitExceeded(LimitExceeded {
+                actual: 101,
+                ..witness_elements
+            }))
+        );
+        let witness = ms.satisfy_with_rules(&sigs, &RuleSet::Consensus).unwrap();
+        assert_eq!(witness.len(), 101);
+        assert_eq!(
+            ms.satisfy_malleable_with_rules(&sigs, &relaxed),
+            Ok(witness)
+        );
+    }
+
     #[test]
     fn decode_bug_cpp_review() {
         let ms = Miniscript::<String, Segwitv0>::from_str_insane(
             "and_b(1,s:and_v(v:older(9),c:pk_k(A)))",
         )
         .unwrap();
-        let ms_trans = ms.translate_pk_infallible(
-            |_x| {
+        let ms_trans = ms
+            .translate_pk(&mut ConstantTranslator(
                 bitcoin::PublicKey::from_str(
                     "02fbcf092916824cc56c4591abeedd54586f5ffc73c6ba88118162e3500ad695ea",
                 )
-                .unwrap()
-            },
-            |_x| unreachable!(),
-        );
+                .unwrap(),
+            ))
+            .unwrap();
         let enc = ms_trans.encode();
         let ms = Miniscript::<bitcoin::PublicKey, Segwitv0>::parse_insane(&enc).unwrap();
         assert_eq!(ms_trans.encode(), ms.encode());
     }
+
+    #[test]
+    fn preimage_satisfiers() {
+        let sha_preimage = [0x01; 32];
+        let hash160_preimage = [0x02; 32];
+        let sha = sha256::Hash::hash(&sha_preimage);
+        let h160 = hash160::Hash::hash(&hash160_preimage);
+        let ms = Segwitv0Script::from_str_insane(&format!(
+            "and_v(v:sha256({}),and_v(v:hash160({}),older(10)))",
+            sha, h160
+        ))
+        .unwrap();
+
+        let mut sha_map = HashMap::new();
+        sha_map.insert(sha, sha_preimage);
+        let h160_fn = PreimageFn(|h: PreimageHash<bitcoin::PublicKey>| match h {
+            PreimageHash::Hash160(h) if *h == h160 => Some(hash160_preimage),
+            _ => None,
+        });
+
+        // Each satisfier alone is missing something
+        assert!(ms.satisfy(&sha_map).is_err());
+        assert!(ms.satisfy((&h160_fn, Older(10))).is_err());
+
+        let wit = ms.satisfy(sha_map.chain(h160_fn).chain(Older(10))).unwrap();
+        assert_eq!(wit, vec![hash160_preimage.to_vec(), sha_preimage.to_vec()]);
+    }
+
+    #[test]
+    fn substitute_hash_preimages() {
+        let keys = pubkeys(2);
+        let preimage = [0x01; 32];
+        let hash = sha256::Hash::hash(&preimage);
+        // A HTLC: the payee with the preimage, or the payer after a timeout
+        let htlc = Segwitv0Script::from_str(&format!(
+            "andor(pk({}),sha256({}),and_v(v:pk({}),older(144)))",
+            keys[0], hash, keys[1]
+        ))
+        .unwrap();
+
+        let mut preimages = HashMap::new();
+        preimages.insert(hash, [0x02; 32]);
+        // A wrong preimage leaves the policy unchanged
+        assert_eq!(
+            htlc.substitute_hash_preimages(&preimages).unwrap(),
+            htlc.lift().unwrap().normalized()
+        );
+
+        preimages.insert(hash, preimage);
+        let remaining = Segwitv0Script::from_str(&format!(
+            "or_d(pk({}),and_v(v:pk({}),older(144)))",
+            keys[0], keys[1]
+        ))
+        .unwrap();
+        assert_eq!(
+            htlc.substitute_hash_preimages(&preimages).unwrap(),
+            remaining.lift().unwrap().normalized()
+        );
+
+        let ms = Segwitv0Script::from_str_insane(&format!("sha256({})", hash)).unwrap();
+        assert_eq!(
+            ms.substitute_hash_preimages(&preimages).unwrap(),
+            Semantic::Trivial
+        );
+    }
+
+    #[test]
+    fn timelock_info() {
+        let ms = Miniscript::<String, Segwitv0>::from_str_insane(
+            "or_d(multi(2,A,B),or_i(and_v(v:pk(C),older(1000)),and_v(v:pk(D),and_v(v:older(4194400),after(700000)))))",
+        )
+        .unwrap();
+        assert_eq!(
+            ms.timelock_info(),
+            vec![
+                PathTimelocks::default(),
+                PathTimelocks {
+                    older_height: Some(1000),
+                    ..Default::default()
+                },
+                PathTimelocks {
+                    after_height: Some(700000),
+                    older_time: Some(4194400),
+                    ..Default::default()
+                },
+            ]
+        );
+
+        // Paths through a threshold combine the timelocks of the chosen subs
+        let ms = Miniscript::<String, Segwitv0>::from_str_insane(
+            "thresh(2,pk(A),sln:after(100),sln:after(500000001))",
+        )
+        .unwrap();
+        let info = ms.timelock_info();
+        assert_eq!(info.len(), 3);
+        assert!(info.iter().any(|path| path.has_mixed_timelocks()));
+        assert_eq!(info.iter().filter(|path| path.is_empty()).count(), 0);
+    }
+
+    #[test]
+    fn max_stack_size_per_path() {
+        let paths = |s: &str| {
+            Miniscript::<String, Segwitv0>::from_str_insane(s)
+                .unwrap()
+                .max_stack_size_per_path()
+        };
+        // The paths through the `or_i` also dissatisfy the `multi`
+        let older = PathTimelocks {
+            older_height: Some(1000),
+            ..Default::default()
+        };
+        assert_eq!(
+            paths("or_d(multi(2,A,B),or_i(and_v(v:pk(C),older(1000)),pk(D)))"),
+            vec![
+                PathStackSize {
+                    timelocks: PathTimelocks::default(),
+                    stack_elements: 3,
+                },
+                PathStackSize {
+                    timelocks: PathTimelocks::default(),
+                    stack_elements: 5,
+                },
+                PathStackSize {
+                    timelocks: older,
+                    stack_elements: 5,
+                },
+            ]
+        );
+
+        // Every path through a threshold satisfies or dissatisfies each sub
+        let after = PathTimelocks {
+            after_height: Some(100),
+            ..Default::default()
+        };
+        assert_eq!(
+            paths("thresh(2,pk(A),s:pk(B),sln:after(100))"),
+            vec![
+                PathStackSize {
+                    timelocks: PathTimelocks::default(),
+                    stack_elements: 3,
+                },
+                PathStackSize {
+                    timelocks: after,
+                    stack_elements: 3,
+                },
+            ]
+        );
+        assert_eq!(paths("and_v(v:pk(A),0)"), vec![]);
+    }
+
+    #[test]
+    fn max_satisfaction_size_repeated_keys() {
+        let size = |s: &str| {
+            Miniscript::<String, Segwitv0>::from_str_insane(s)
+                .unwrap()
+                .max_satisfaction_size()
+                .unwrap()
+        };
+        // Each occurrence of a key needs a signature in the witness
+        assert_eq!(size("and_v(v:pk(A),pk(A))"), 2 * 73);
+        assert_eq!(size("and_v(v:pk(A),pk(A))"), size("and_v(v:pk(A),pk(B))"));
+        // Keys repeated across branches are only counted in the satisfied one
+        assert_eq!(
+            size("or_i(and_v(v:pk(A),pk(B)),and_v(v:pk(A),pk(C)))"),
+            2 * 73 + 2
+        );
+    }
+
+    #[test]
+    fn malleability_report() {
+        let report = |s: &str| {
+            let ms = Miniscript::<String, Segwitv0>::from_str_insane(s).unwrap();
+            let report: Vec<_> = ms
+                .malleability_report()
+                .iter()
+                .map(|issue| issue.to_string())
+                .collect();
+            assert_eq!(report.is_empty(), ms.is_non_malleable());
+            report
+        };
+
+        assert!(report("or_d(multi(2,A,B),and_v(v:pk(C),older(1000)))").is_empty());
+        assert_eq!(
+            report("and_v(v:pk(A),or_i(older(1),after(2)))"),
+            vec!["or_i(older(1),after(2)): branches 0, 1 are satisfiable by a third party without signature"]
+        );
+        assert_eq!(
+            report("or_b(pk(A),a:or_i(pk(B),pk(C)))"),
+            vec!["or_b(pk(A),a:or_i(pk(B),pk(C))): branch 1 can be dissatisfied by a third party in more than one way"]
+        );
+        // Only the fragment introducing the malleability is reported
+        assert_eq!(
+            report("or_d(pk(A),and_v(v:pk(B),or_i(older(1),after(2))))").len(),
+            1
+        );
+        assert_eq!(
+            report("thresh(1,pk(A),sln:older(1),sln:after(2))"),
+            vec!["thresh(1,pk(A),sln:older(1),sln:after(2)): branches 1, 2 are satisfiable by a third party without signature"]
+        );
+    }
+
+    #[test]
+    fn absolute_timelock_units() {
+        use crate::miniscript::satisfy::After;
+        use crate::LockTime;
+
+        let height = LockTime::from_consensus(700_000);
+        let time = LockTime::from_consensus(1_600_000_000);
+        assert_eq!(height, LockTime::Blocks(700_000));
+        assert_eq!(time, LockTime::Seconds(1_600_000_000));
+        assert_eq!(height.partial_cmp(&time), None);
+        assert!(height < LockTime::Blocks(700_001));
+        assert_eq!(height.is_implied_by(LockTime::Blocks(700_001)), Ok(true));
+        assert_eq!(
+            height.is_implied_by(time),
+            Err(Error::MixedLockTimeUnits(height, time))
+        );
+        assert!(height.is_reached_at(700_000, 0));
+        assert!(!time.is_reached_at(800_000, 1_600_000_000));
+
+        // A time-based locktime never satisfies a height-based timelock
+        let after = |lock_time: u32, n: u32| {
+            Satisfier::<bitcoin::PublicKey>::check_after(&After(lock_time), n)
+        };
+        assert!(after(1_600_000_000, 1_500_000_000));
+        assert!(!after(1_600_000_000, 700_000));
+        assert!(!after(700_000, 1_500_000_000));
+
+        // The current height and median time past are provided separately
+        struct Tip(Option<u32>, Option<u32>);
+        impl Satisfier<bitcoin::PublicKey> for Tip {
+            fn lookup_current_height(&self) -> Option<u32> {
+                self.0
+            }
+
+            fn lookup_current_mtp(&self) -> Option<u32> {
+                self.1
+            }
+        }
+        let ms = Segwitv0Script::from_str_insane("after(700000)").unwrap();
+        assert!(ms.satisfy(Tip(Some(700_000), None)).is_ok());
+        assert!(ms.satisfy(Tip(Some(699_999), None)).is_err());
+        assert!(ms.satisfy(Tip(None, Some(1_600_000_000))).is_err());
+        let ms = Segwitv0Script::from_str_insane("after(1600000000)").unwrap();
+        assert!(ms.satisfy(Tip(Some(800_000), Some(1_600_000_001))).is_ok());
+        assert!(ms.satisfy(Tip(Some(800_000), Some(1_600_000_000))).is_err());
+    }
+
+    #[test]
+    fn downstream_script_context() {
+        use crate::miniscript::limits::{LimitExceeded, ResourceLimit, ResourceUsage};
+        use crate::miniscript::{ScriptContextError, SigType};
+
+        // A context as a downstream crate would define it: tapscript with
+        // scripts of at most 100 bytes
+        #[derive(Debug, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+        enum SmallTap {}
+
+        impl ScriptContext for SmallTap {
+            type Key = XOnlyPublicKey;
+            fn check_terminal_non_malleable<Pk: MiniscriptKey>(
+                _frag: &Terminal<Pk, Self>,
+            ) -> Result<(), ScriptContextError> {
+                Ok(())
+            }
+
+            fn check_global_consensus_validity<Pk: MiniscriptKey>(
+                ms: &Miniscript<Pk, Self>,
+            ) -> Result<(), ScriptContextError> {
+                if ms.ext.pk_cost > 100 {
+                    return Err(ScriptContextError::MaxWitnessScriptSizeExceeded);
+                }
+                match ms.node {
+                    Terminal::Multi(..) => Err(ScriptContextError::TaprootMultiDisabled),
+                    _ => Ok(()),
+                }
+            }
+
+            fn resource_limits_exceeded(usage: &ResourceUsage) -> Vec<LimitExceeded> {
+                LimitExceeded::check(
+                    ResourceLimit::ScriptSize,
+                    true,
+                    Some(usage.script_size),
+                    100,
+                )
+                .into_iter()
+                .collect()
+            }
+
+            fn max_satisfaction_size<Pk: MiniscriptKey>(
+                ms: &Miniscript<Pk, Self>,
+            ) -> Option<usize> {
+                ms.ext.max_sat_size.map(|x| x.0)
+            }
+
+            fn sig_type() -> SigType {
+                SigType::Schnorr
+            }
+
+            fn pk_len<Pk: MiniscriptKey>(_pk: &Pk) -> usize {
+                33
+            }
+
+            fn name_str() -> &'static str {
+                "SmallTap"
+            }
+        }
+
+        let keys: Vec<XOnlyPublicKey> = [
+            "e948a0bbf8b15ee47cf0851afbce8835b5f06d3003b8e7ed6104e82a1d41d6f8",
+            "2788ee41e76f4f3af603da5bc8fa22997bc0344bb0f95666ba6aaff0242baa99",
+            "cc8a4bc64d897bddc5fbc2f670f7a8ba0b386779106cf1223c6fc5d7cd6fc115",
+        ]
+        .iter()
+        .map(|k| XOnlyPublicKey::from_str(k).unwrap())
+        .collect();
+
+        // Parsing, encoding and decoding follow the rules of the new context
+        let ms_str = format!("and_v(v:pk({}),pk({}))", keys[0], keys[1]);
+        let ms = Miniscript::<XOnlyPublicKey, SmallTap>::from_str(&ms_str).unwrap();
+        let tap_ms = Tapscript::from_str(&ms_str).unwrap();
+        assert_eq!(ms.encode(), tap_ms.encode());
+        assert_eq!(Miniscript::parse(&ms.encode()).unwrap(), ms);
+        assert_eq!(ms.max_satisfaction_size().unwrap(), 132);
+        assert_eq!(ms.check_resource_limits().unwrap().script_size, 68);
+
+        // Which are stricter than the tapscript ones for larger scripts
+        let multi_a = format!("multi_a(1,{},{},{})", keys[0], keys[1], keys[2]);
+        assert!(Tapscript::from_str(&multi_a).is_ok());
+        assert!(Miniscript::<XOnlyPublicKey, SmallTap>::from_str(&multi_a).is_err());
+        let ms = Miniscript::<XOnlyPublicKey, SmallTap>::from_str_insane(&format!(
+            "and_v(v:{},pk({}))",
+            multi_a, keys[0]
+        ));
+        assert!(ms.is_err());
+    }
 }
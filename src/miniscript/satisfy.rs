@@ -17,28 +17,38 @@
 //! Traits and implementations to support producing witnesses for Miniscript
 //! scriptpubkeys.
 //!
+//! Satisfaction does not recurse: the fragments of a Miniscript are laid out
+//! in a slice of [`Slot`]s, which is walked once to choose the cheapest
+//! (dis)satisfaction of every fragment and once more to write the chosen
+//! witness into a [`WitnessBuffer`]. With the `satisfy-no-alloc` feature,
+//! `Miniscript::satisfy_no_alloc` takes both from the caller, so that it can
+//! run without allocating on signers with little memory.
+//!
 
-use core::{cmp, i64, mem};
+use core::cell::RefCell;
+use core::{cmp, fmt};
 
 use bitcoin;
-use bitcoin::hashes::{hash160, ripemd160, sha256, sha256d};
-use bitcoin::secp256k1::XOnlyPublicKey;
+use bitcoin::hashes::{hash160, ripemd160, sha256};
+use bitcoin::secp256k1::{self, XOnlyPublicKey};
 use bitcoin::util::taproot::{ControlBlock, LeafVersion, TapLeafHash};
-use sync::Arc;
 
+use super::context::SigType;
+use crate::descriptor::{DescriptorSecretKey, KeyMap, Wildcard};
 use crate::miniscript::limits::{
     LOCKTIME_THRESHOLD, SEQUENCE_LOCKTIME_DISABLE_FLAG, SEQUENCE_LOCKTIME_TYPE_FLAG,
 };
 use crate::prelude::*;
-use crate::util::witness_size;
-use crate::{Miniscript, MiniscriptKey, ScriptContext, Terminal, ToPublicKey};
+use crate::util::{varint_len, witness_size};
+use crate::{hash256, Error, Miniscript, MiniscriptKey, ScriptContext, Terminal, ToPublicKey};
 
 /// Type alias for 32 byte Preimage.
 pub type Preimage32 = [u8; 32];
 /// Trait describing a lookup table for signatures, hash preimages, etc.
 /// Every method has a default implementation that simply returns `None`
 /// on every query. Users are expected to override the methods that they
-/// have data for.
+/// have data for. The same item may be looked up more than once during a
+/// satisfaction, and must be given the same answer every time.
 pub trait Satisfier<Pk: MiniscriptKey + ToPublicKey> {
     /// Given a public key, look up an ECDSA signature with that key
     fn lookup_ecdsa_sig(&self, _: &Pk) -> Option<bitcoin::EcdsaSig> {
@@ -67,6 +77,28 @@ pub trait Satisfier<Pk: MiniscriptKey + ToPublicKey> {
         None
     }
 
+    /// Given a raw `hash160` of a public key, lookup the public key.
+    ///
+    /// This is only queried for `pk_h` fragments whose key neither
+    /// [`Satisfier::lookup_pkh_pk`] nor [`Satisfier::lookup_pkh_ecdsa_sig`]
+    /// know about, e.g. for keys only given by their hash in the descriptor,
+    /// and allows a wallet key index to provide them.
+    fn lookup_raw_pkh_pk(&self, _: &hash160::Hash) -> Option<bitcoin::PublicKey> {
+        None
+    }
+
+    /// Given a raw `hash160` of a public key, look up the EC signature and the
+    /// associated key.
+    ///
+    /// This is only queried for `pk_h` fragments when
+    /// [`Satisfier::lookup_pkh_ecdsa_sig`] does not know about the key.
+    fn lookup_raw_pkh_ecdsa_sig(
+        &self,
+        _: &hash160::Hash,
+    ) -> Option<(bitcoin::PublicKey, bitcoin::EcdsaSig)> {
+        None
+    }
+
     /// Given a keyhash, look up the EC signature and the associated key
     /// Even if signatures for public key Hashes are not available, the users
     /// can use this map to provide pkh -> pk mapping which can be useful
@@ -90,22 +122,22 @@ pub trait Satisfier<Pk: MiniscriptKey + ToPublicKey> {
     }
 
     /// Given a SHA256 hash, look up its preimage
-    fn lookup_sha256(&self, _: sha256::Hash) -> Option<Preimage32> {
+    fn lookup_sha256(&self, _: &Pk::Sha256) -> Option<Preimage32> {
         None
     }
 
     /// Given a HASH256 hash, look up its preimage
-    fn lookup_hash256(&self, _: sha256d::Hash) -> Option<Preimage32> {
+    fn lookup_hash256(&self, _: &Pk::Hash256) -> Option<Preimage32> {
         None
     }
 
     /// Given a RIPEMD160 hash, look up its preimage
-    fn lookup_ripemd160(&self, _: ripemd160::Hash) -> Option<Preimage32> {
+    fn lookup_ripemd160(&self, _: &Pk::Ripemd160) -> Option<Preimage32> {
         None
     }
 
     /// Given a HASH160 hash, look up its preimage
-    fn lookup_hash160(&self, _: hash160::Hash) -> Option<Preimage32> {
+    fn lookup_hash160(&self, _: &Pk::Hash160) -> Option<Preimage32> {
         None
     }
 
@@ -114,9 +146,38 @@ pub trait Satisfier<Pk: MiniscriptKey + ToPublicKey> {
         false
     }
 
-    /// Assert whether a absolute locktime is satisfied
-    fn check_after(&self, _: u32) -> bool {
-        false
+    /// The height of the chain tip the satisfaction is made at, against
+    /// which the default [`Satisfier::check_after`] checks height-based
+    /// absolute timelocks
+    fn lookup_current_height(&self) -> Option<u32> {
+        None
+    }
+
+    /// The median time past of the chain tip the satisfaction is made at,
+    /// against which the default [`Satisfier::check_after`] checks time-based
+    /// absolute timelocks
+    fn lookup_current_mtp(&self) -> Option<u32> {
+        None
+    }
+
+    /// Assert whether a absolute locktime is satisfied.
+    ///
+    /// By default, a height-based timelock is satisfied when it is reached by
+    /// a transaction in the block after the [current height], and a
+    /// time-based timelock when it is reached by a transaction in the block
+    /// after the [current median time past], as done by
+    /// [`LockTime::is_reached_at`]. A timelock is never satisfied by a value
+    /// of the other unit.
+    ///
+    /// [current height]: Satisfier::lookup_current_height
+    /// [current median time past]: Satisfier::lookup_current_mtp
+    fn check_after(&self, n: u32) -> bool {
+        match LockTime::from_consensus(n) {
+            LockTime::Blocks(height) => self
+                .lookup_current_height()
+                .map_or(false, |tip| height <= tip),
+            LockTime::Seconds(time) => self.lookup_current_mtp().map_or(false, |tip| time < tip),
+        }
     }
 }
 
@@ -154,11 +215,91 @@ pub struct After(pub u32);
 
 impl<Pk: MiniscriptKey + ToPublicKey> Satisfier<Pk> for After {
     fn check_after(&self, n: u32) -> bool {
-        // if n > self.0; we will be returning false anyways
-        if n < LOCKTIME_THRESHOLD && self.0 >= LOCKTIME_THRESHOLD {
-            false
+        LockTime::from_consensus(n)
+            .is_implied_by(LockTime::from_consensus(self.0))
+            .unwrap_or(false)
+    }
+}
+
+/// An absolute timelock, either the argument of an `after` fragment or the
+/// locktime of a transaction, in one of the two units defined by BIP 65.
+///
+/// Timelocks of different units are never comparable: a transaction locktime
+/// in one unit never satisfies an `after` in the other.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LockTime {
+    /// A block height, below [`LOCKTIME_THRESHOLD`]
+    Blocks(u32),
+    /// A unix timestamp, at or above [`LOCKTIME_THRESHOLD`], compared against
+    /// the median time past of blocks as defined in BIP 113
+    Seconds(u32),
+}
+
+impl LockTime {
+    /// Interpret a consensus-encoded absolute timelock
+    pub fn from_consensus(n: u32) -> Self {
+        if n < LOCKTIME_THRESHOLD {
+            LockTime::Blocks(n)
+        } else {
+            LockTime::Seconds(n)
+        }
+    }
+
+    /// The consensus encoding of the timelock
+    pub fn to_consensus_u32(self) -> u32 {
+        match self {
+            LockTime::Blocks(n) | LockTime::Seconds(n) => n,
+        }
+    }
+
+    /// Whether both timelocks are block heights, or both are timestamps
+    pub fn is_same_unit(self, other: LockTime) -> bool {
+        match (self, other) {
+            (LockTime::Blocks(_), LockTime::Blocks(_))
+            | (LockTime::Seconds(_), LockTime::Seconds(_)) => true,
+            _ => false,
+        }
+    }
+
+    /// Whether a transaction with locktime `lock_time` satisfies this timelock,
+    /// as checked by `OP_CHECKLOCKTIMEVERIFY`.
+    ///
+    /// # Errors
+    /// When the timelocks have different units, in which case the check
+    /// always fails.
+    pub fn is_implied_by(self, lock_time: LockTime) -> Result<bool, Error> {
+        if self.is_same_unit(lock_time) {
+            Ok(self.to_consensus_u32() <= lock_time.to_consensus_u32())
+        } else {
+            Err(Error::MixedLockTimeUnits(self, lock_time))
+        }
+    }
+
+    /// Whether a transaction with this locktime can be mined in the block after
+    /// a chain tip at `height` with median time past `mtp`
+    pub fn is_reached_at(self, height: u32, mtp: u32) -> bool {
+        match self {
+            LockTime::Blocks(n) => n <= height,
+            LockTime::Seconds(n) => n < mtp,
+        }
+    }
+}
+
+impl PartialOrd for LockTime {
+    fn partial_cmp(&self, other: &LockTime) -> Option<cmp::Ordering> {
+        if self.is_same_unit(*other) {
+            Some(self.to_consensus_u32().cmp(&other.to_consensus_u32()))
         } else {
-            n <= self.0
+            None
+        }
+    }
+}
+
+impl fmt::Display for LockTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LockTime::Blocks(n) => write!(f, "height {}", n),
+            LockTime::Seconds(n) => write!(f, "time {}", n),
         }
     }
 }
@@ -220,6 +361,154 @@ where
     }
 }
 
+impl<Pk: MiniscriptKey + ToPublicKey> Satisfier<Pk> for HashMap<hash160::Hash, bitcoin::PublicKey> {
+    fn lookup_raw_pkh_pk(&self, pk_hash: &hash160::Hash) -> Option<bitcoin::PublicKey> {
+        self.get(pk_hash).copied()
+    }
+}
+
+/// A [`Satisfier`] looking up the keys of `pk_h` fragments among the keys
+/// of a [`KeyMap`] which do not need any derivation, i.e. single keys and
+/// extended keys without wildcards.
+///
+/// The public keys are computed once, when creating the satisfier. Given the
+/// message to sign with [`KeyMapSatisfier::with_sighash`], the satisfier also
+/// signs for these fragments with the secret keys of the map.
+pub struct KeyMapSatisfier<'a, C: secp256k1::Signing + secp256k1::Verification> {
+    secp: &'a secp256k1::Secp256k1<C>,
+    // The public key and secret key of every key of the map, by hash
+    keys: BTreeMap<hash160::Hash, (bitcoin::PublicKey, &'a DescriptorSecretKey)>,
+    sighash: Option<(secp256k1::Message, bitcoin::EcdsaSighashType)>,
+}
+
+impl<'a, C: secp256k1::Signing + secp256k1::Verification> KeyMapSatisfier<'a, C> {
+    /// Create a satisfier for the keys of `key_map`, computing their public
+    /// keys with `secp`
+    pub fn new(key_map: &'a KeyMap, secp: &'a secp256k1::Secp256k1<C>) -> Self {
+        let keys = key_map
+            .iter()
+            .filter_map(|(pk, sk)| {
+                let pk = pk.derive_public_key(secp).ok()?;
+                Some((pk.to_pubkeyhash(), (pk, sk)))
+            })
+            .collect();
+        KeyMapSatisfier {
+            secp,
+            keys,
+            sighash: None,
+        }
+    }
+
+    /// Sign `msg` with sighash type `hash_ty` for the keys of `pk_h`
+    /// fragments
+    pub fn with_sighash(
+        mut self,
+        msg: secp256k1::Message,
+        hash_ty: bitcoin::EcdsaSighashType,
+    ) -> Self {
+        self.sighash = Some((msg, hash_ty));
+        self
+    }
+
+    // The secret key of a key of the map, derived along its derivation path
+    fn secret_key(&self, sk: &DescriptorSecretKey) -> Option<secp256k1::SecretKey> {
+        match *sk {
+            DescriptorSecretKey::Single(ref single) => Some(single.key.inner),
+            DescriptorSecretKey::XPrv(ref xprv) if xprv.wildcard == Wildcard::None => xprv
+                .xkey
+                .derive_priv(self.secp, &xprv.derivation_path)
+                .ok()
+                .map(|xkey| xkey.private_key),
+            DescriptorSecretKey::XPrv(..) => None,
+        }
+    }
+}
+
+impl<'a, C, Pk> Satisfier<Pk> for KeyMapSatisfier<'a, C>
+where
+    C: secp256k1::Signing + secp256k1::Verification,
+    Pk: MiniscriptKey + ToPublicKey,
+{
+    fn lookup_raw_pkh_pk(&self, pk_hash: &hash160::Hash) -> Option<bitcoin::PublicKey> {
+        self.keys.get(pk_hash).map(|&(pk, _)| pk)
+    }
+
+    fn lookup_raw_pkh_ecdsa_sig(
+        &self,
+        pk_hash: &hash160::Hash,
+    ) -> Option<(bitcoin::PublicKey, bitcoin::EcdsaSig)> {
+        let (msg, hash_ty) = self.sighash?;
+        let (pk, sk) = *self.keys.get(pk_hash)?;
+        let sk = self.secret_key(sk)?;
+        let sig = self.secp.sign_ecdsa_low_r(&msg, &sk);
+        Some((pk, bitcoin::EcdsaSig { sig, hash_ty }))
+    }
+}
+
+impl<Pk: MiniscriptKey + ToPublicKey> Satisfier<Pk> for HashMap<sha256::Hash, Preimage32> {
+    fn lookup_sha256(&self, h: &Pk::Sha256) -> Option<Preimage32> {
+        self.get(&Pk::to_sha256(h)).copied()
+    }
+}
+
+impl<Pk: MiniscriptKey + ToPublicKey> Satisfier<Pk> for HashMap<hash256::Hash, Preimage32> {
+    fn lookup_hash256(&self, h: &Pk::Hash256) -> Option<Preimage32> {
+        self.get(&Pk::to_hash256(h)).copied()
+    }
+}
+
+impl<Pk: MiniscriptKey + ToPublicKey> Satisfier<Pk> for HashMap<ripemd160::Hash, Preimage32> {
+    fn lookup_ripemd160(&self, h: &Pk::Ripemd160) -> Option<Preimage32> {
+        self.get(&Pk::to_ripemd160(h)).copied()
+    }
+}
+
+impl<Pk: MiniscriptKey + ToPublicKey> Satisfier<Pk> for HashMap<hash160::Hash, Preimage32> {
+    fn lookup_hash160(&self, h: &Pk::Hash160) -> Option<Preimage32> {
+        self.get(&Pk::to_hash160(h)).copied()
+    }
+}
+
+/// A hash whose preimage is being looked up, passed to the closure
+/// wrapped by [`PreimageFn`]
+#[derive(Debug)]
+pub enum PreimageHash<'a, Pk: MiniscriptKey> {
+    /// Hash from a `sha256` fragment
+    Sha256(&'a Pk::Sha256),
+    /// Hash from a `hash256` fragment
+    Hash256(&'a Pk::Hash256),
+    /// Hash from a `ripemd160` fragment
+    Ripemd160(&'a Pk::Ripemd160),
+    /// Hash from a `hash160` fragment
+    Hash160(&'a Pk::Hash160),
+}
+
+/// Newtype around a closure which implements `Satisfier` by answering
+/// every preimage lookup with the closure. All other lookups return `None`.
+pub struct PreimageFn<F>(pub F);
+
+impl<Pk, F> Satisfier<Pk> for PreimageFn<F>
+where
+    Pk: MiniscriptKey + ToPublicKey,
+    F: Fn(PreimageHash<Pk>) -> Option<Preimage32>,
+{
+    fn lookup_sha256(&self, h: &Pk::Sha256) -> Option<Preimage32> {
+        (self.0)(PreimageHash::Sha256(h))
+    }
+
+    fn lookup_hash256(&self, h: &Pk::Hash256) -> Option<Preimage32> {
+        (self.0)(PreimageHash::Hash256(h))
+    }
+
+    fn lookup_ripemd160(&self, h: &Pk::Ripemd160) -> Option<Preimage32> {
+        (self.0)(PreimageHash::Ripemd160(h))
+    }
+
+    fn lookup_hash160(&self, h: &Pk::Hash160) -> Option<Preimage32> {
+        (self.0)(PreimageHash::Hash160(h))
+    }
+}
+
 impl<'a, Pk: MiniscriptKey + ToPublicKey, S: Satisfier<Pk>> Satisfier<Pk> for &'a S {
     fn lookup_ecdsa_sig(&self, p: &Pk) -> Option<bitcoin::EcdsaSig> {
         (**self).lookup_ecdsa_sig(p)
@@ -233,6 +522,17 @@ impl<'a, Pk: MiniscriptKey + ToPublicKey, S: Satisfier<Pk>> Satisfier<Pk> for &'
         (**self).lookup_pkh_pk(pkh)
     }
 
+    fn lookup_raw_pkh_pk(&self, pkh: &hash160::Hash) -> Option<bitcoin::PublicKey> {
+        (**self).lookup_raw_pkh_pk(pkh)
+    }
+
+    fn lookup_raw_pkh_ecdsa_sig(
+        &self,
+        pkh: &hash160::Hash,
+    ) -> Option<(bitcoin::PublicKey, bitcoin::EcdsaSig)> {
+        (**self).lookup_raw_pkh_ecdsa_sig(pkh)
+    }
+
     fn lookup_pkh_ecdsa_sig(
         &self,
         pkh: &Pk::Hash,
@@ -257,19 +557,19 @@ impl<'a, Pk: MiniscriptKey + ToPublicKey, S: Satisfier<Pk>> Satisfier<Pk> for &'
         (**self).lookup_tap_control_block_map()
     }
 
-    fn lookup_sha256(&self, h: sha256::Hash) -> Option<Preimage32> {
+    fn lookup_sha256(&self, h: &Pk::Sha256) -> Option<Preimage32> {
         (**self).lookup_sha256(h)
     }
 
-    fn lookup_hash256(&self, h: sha256d::Hash) -> Option<Preimage32> {
+    fn lookup_hash256(&self, h: &Pk::Hash256) -> Option<Preimage32> {
         (**self).lookup_hash256(h)
     }
 
-    fn lookup_ripemd160(&self, h: ripemd160::Hash) -> Option<Preimage32> {
+    fn lookup_ripemd160(&self, h: &Pk::Ripemd160) -> Option<Preimage32> {
         (**self).lookup_ripemd160(h)
     }
 
-    fn lookup_hash160(&self, h: hash160::Hash) -> Option<Preimage32> {
+    fn lookup_hash160(&self, h: &Pk::Hash160) -> Option<Preimage32> {
         (**self).lookup_hash160(h)
     }
 
@@ -277,6 +577,14 @@ impl<'a, Pk: MiniscriptKey + ToPublicKey, S: Satisfier<Pk>> Satisfier<Pk> for &'
         (**self).check_older(t)
     }
 
+    fn lookup_current_height(&self) -> Option<u32> {
+        (**self).lookup_current_height()
+    }
+
+    fn lookup_current_mtp(&self) -> Option<u32> {
+        (**self).lookup_current_mtp()
+    }
+
     fn check_after(&self, t: u32) -> bool {
         (**self).check_after(t)
     }
@@ -299,6 +607,17 @@ impl<'a, Pk: MiniscriptKey + ToPublicKey, S: Satisfier<Pk>> Satisfier<Pk> for &'
         (**self).lookup_pkh_pk(pkh)
     }
 
+    fn lookup_raw_pkh_pk(&self, pkh: &hash160::Hash) -> Option<bitcoin::PublicKey> {
+        (**self).lookup_raw_pkh_pk(pkh)
+    }
+
+    fn lookup_raw_pkh_ecdsa_sig(
+        &self,
+        pkh: &hash160::Hash,
+    ) -> Option<(bitcoin::PublicKey, bitcoin::EcdsaSig)> {
+        (**self).lookup_raw_pkh_ecdsa_sig(pkh)
+    }
+
     fn lookup_pkh_ecdsa_sig(
         &self,
         pkh: &Pk::Hash,
@@ -319,19 +638,19 @@ impl<'a, Pk: MiniscriptKey + ToPublicKey, S: Satisfier<Pk>> Satisfier<Pk> for &'
         (**self).lookup_tap_control_block_map()
     }
 
-    fn lookup_sha256(&self, h: sha256::Hash) -> Option<Preimage32> {
+    fn lookup_sha256(&self, h: &Pk::Sha256) -> Option<Preimage32> {
         (**self).lookup_sha256(h)
     }
 
-    fn lookup_hash256(&self, h: sha256d::Hash) -> Option<Preimage32> {
+    fn lookup_hash256(&self, h: &Pk::Hash256) -> Option<Preimage32> {
         (**self).lookup_hash256(h)
     }
 
-    fn lookup_ripemd160(&self, h: ripemd160::Hash) -> Option<Preimage32> {
+    fn lookup_ripemd160(&self, h: &Pk::Ripemd160) -> Option<Preimage32> {
         (**self).lookup_ripemd160(h)
     }
 
-    fn lookup_hash160(&self, h: hash160::Hash) -> Option<Preimage32> {
+    fn lookup_hash160(&self, h: &Pk::Hash160) -> Option<Preimage32> {
         (**self).lookup_hash160(h)
     }
 
@@ -339,6 +658,14 @@ impl<'a, Pk: MiniscriptKey + ToPublicKey, S: Satisfier<Pk>> Satisfier<Pk> for &'
         (**self).check_older(t)
     }
 
+    fn lookup_current_height(&self) -> Option<u32> {
+        (**self).lookup_current_height()
+    }
+
+    fn lookup_current_mtp(&self) -> Option<u32> {
+        (**self).lookup_current_mtp()
+    }
+
     fn check_after(&self, t: u32) -> bool {
         (**self).check_after(t)
     }
@@ -421,6 +748,32 @@ macro_rules! impl_tuple_satisfier {
                 None
             }
 
+            fn lookup_raw_pkh_pk(
+                &self,
+                key_hash: &hash160::Hash,
+            ) -> Option<bitcoin::PublicKey> {
+                let &($(ref $ty,)*) = self;
+                $(
+                    if let Some(result) = $ty.lookup_raw_pkh_pk(key_hash) {
+                        return Some(result);
+                    }
+                )*
+                None
+            }
+
+            fn lookup_raw_pkh_ecdsa_sig(
+                &self,
+                key_hash: &hash160::Hash,
+            ) -> Option<(bitcoin::PublicKey, bitcoin::EcdsaSig)> {
+                let &($(ref $ty,)*) = self;
+                $(
+                    if let Some(result) = $ty.lookup_raw_pkh_ecdsa_sig(key_hash) {
+                        return Some(result);
+                    }
+                )*
+                None
+            }
+
             fn lookup_tap_control_block_map(
                 &self,
             ) -> Option<&BTreeMap<ControlBlock, (bitcoin::Script, LeafVersion)>> {
@@ -433,7 +786,7 @@ macro_rules! impl_tuple_satisfier {
                 None
             }
 
-            fn lookup_sha256(&self, h: sha256::Hash) -> Option<Preimage32> {
+            fn lookup_sha256(&self, h: &Pk::Sha256) -> Option<Preimage32> {
                 let &($(ref $ty,)*) = self;
                 $(
                     if let Some(result) = $ty.lookup_sha256(h) {
@@ -443,7 +796,7 @@ macro_rules! impl_tuple_satisfier {
                 None
             }
 
-            fn lookup_hash256(&self, h: sha256d::Hash) -> Option<Preimage32> {
+            fn lookup_hash256(&self, h: &Pk::Hash256) -> Option<Preimage32> {
                 let &($(ref $ty,)*) = self;
                 $(
                     if let Some(result) = $ty.lookup_hash256(h) {
@@ -453,7 +806,7 @@ macro_rules! impl_tuple_satisfier {
                 None
             }
 
-            fn lookup_ripemd160(&self, h: ripemd160::Hash) -> Option<Preimage32> {
+            fn lookup_ripemd160(&self, h: &Pk::Ripemd160) -> Option<Preimage32> {
                 let &($(ref $ty,)*) = self;
                 $(
                     if let Some(result) = $ty.lookup_ripemd160(h) {
@@ -463,7 +816,7 @@ macro_rules! impl_tuple_satisfier {
                 None
             }
 
-            fn lookup_hash160(&self, h: hash160::Hash) -> Option<Preimage32> {
+            fn lookup_hash160(&self, h: &Pk::Hash160) -> Option<Preimage32> {
                 let &($(ref $ty,)*) = self;
                 $(
                     if let Some(result) = $ty.lookup_hash160(h) {
@@ -483,6 +836,26 @@ macro_rules! impl_tuple_satisfier {
                 false
             }
 
+            fn lookup_current_height(&self) -> Option<u32> {
+                let &($(ref $ty,)*) = self;
+                $(
+                    if let Some(result) = $ty.lookup_current_height() {
+                        return Some(result);
+                    }
+                )*
+                None
+            }
+
+            fn lookup_current_mtp(&self) -> Option<u32> {
+                let &($(ref $ty,)*) = self;
+                $(
+                    if let Some(result) = $ty.lookup_current_mtp() {
+                        return Some(result);
+                    }
+                )*
+                None
+            }
+
             fn check_after(&self, n: u32) -> bool {
                 let &($(ref $ty,)*) = self;
                 $(
@@ -505,6 +878,187 @@ impl_tuple_satisfier!(A, B, C, D, E, F);
 impl_tuple_satisfier!(A, B, C, D, E, F, G);
 impl_tuple_satisfier!(A, B, C, D, E, F, G, H);
 
+/// Combinators for [`Satisfier`]s, implemented for every satisfier
+pub trait SatisfierExt<Pk: MiniscriptKey + ToPublicKey>: Satisfier<Pk> + Sized {
+    /// Combine this satisfier with `other`. Every lookup is tried on `self`
+    /// first and falls back to `other`; timelocks are satisfied if either
+    /// satisfier accepts them. Chains of any length can be built by calling
+    /// this repeatedly.
+    fn chain<S: Satisfier<Pk>>(self, other: S) -> (Self, S) {
+        (self, other)
+    }
+}
+
+impl<Pk: MiniscriptKey + ToPublicKey, S: Satisfier<Pk>> SatisfierExt<Pk> for S {}
+
+/// An item a [`Satisfier`] could not provide while satisfying a Miniscript,
+/// as recorded by a [`DiagnosticSatisfier`]
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum MissingItem<Pk: MiniscriptKey> {
+    /// Signature for the key
+    Signature(Pk),
+    /// Key and signature for the key hash
+    KeyHashSignature(Pk::Hash),
+    /// Preimage of the `sha256` hash
+    Sha256Preimage(Pk::Sha256),
+    /// Preimage of the `hash256` hash
+    Hash256Preimage(Pk::Hash256),
+    /// Preimage of the `ripemd160` hash
+    Ripemd160Preimage(Pk::Ripemd160),
+    /// Preimage of the `hash160` hash
+    Hash160Preimage(Pk::Hash160),
+    /// Absolute timelock which was not satisfied
+    After(u32),
+    /// Relative timelock which was not satisfied
+    Older(u32),
+}
+
+impl<Pk: MiniscriptKey> fmt::Display for MissingItem<Pk> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MissingItem::Signature(ref pk) => write!(f, "signature for key {}", pk),
+            MissingItem::KeyHashSignature(ref pkh) => {
+                write!(f, "key and signature for key hash {}", pkh)
+            }
+            MissingItem::Sha256Preimage(ref h) => write!(f, "preimage of sha256 {}", h),
+            MissingItem::Hash256Preimage(ref h) => write!(f, "preimage of hash256 {}", h),
+            MissingItem::Ripemd160Preimage(ref h) => write!(f, "preimage of ripemd160 {}", h),
+            MissingItem::Hash160Preimage(ref h) => write!(f, "preimage of hash160 {}", h),
+            MissingItem::After(n) => write!(f, "absolute timelock after({})", n),
+            MissingItem::Older(n) => write!(f, "relative timelock older({})", n),
+        }
+    }
+}
+
+/// Wrapper around a [`Satisfier`] which records every signature, preimage and
+/// timelock the inner satisfier could not provide.
+///
+/// Satisfaction explores every branch of a Miniscript, so after a failed
+/// satisfaction the recorded items explain why each branch failed. Items are
+/// also recorded for branches which were not needed by a successful
+/// satisfaction.
+pub struct DiagnosticSatisfier<Pk: MiniscriptKey, S> {
+    inner: S,
+    missing: RefCell<BTreeSet<MissingItem<Pk>>>,
+}
+
+impl<Pk: MiniscriptKey, S> DiagnosticSatisfier<Pk, S> {
+    /// Wrap `inner`, starting with no recorded items
+    pub fn new(inner: S) -> Self {
+        DiagnosticSatisfier {
+            inner,
+            missing: RefCell::new(BTreeSet::new()),
+        }
+    }
+
+    /// The items recorded so far, in sorted order
+    pub fn missing(&self) -> Vec<MissingItem<Pk>> {
+        self.missing.borrow().iter().cloned().collect()
+    }
+
+    /// Unwrap the inner satisfier
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+
+    fn record<T>(&self, res: Option<T>, item: impl FnOnce() -> MissingItem<Pk>) -> Option<T> {
+        if res.is_none() {
+            self.missing.borrow_mut().insert(item());
+        }
+        res
+    }
+}
+
+impl<Pk, S> Satisfier<Pk> for DiagnosticSatisfier<Pk, S>
+where
+    Pk: MiniscriptKey + ToPublicKey,
+    S: Satisfier<Pk>,
+{
+    fn lookup_ecdsa_sig(&self, pk: &Pk) -> Option<bitcoin::EcdsaSig> {
+        let res = self.inner.lookup_ecdsa_sig(pk);
+        self.record(res, || MissingItem::Signature(pk.clone()))
+    }
+
+    fn lookup_tap_key_spend_sig(&self) -> Option<bitcoin::SchnorrSig> {
+        self.inner.lookup_tap_key_spend_sig()
+    }
+
+    fn lookup_tap_leaf_script_sig(&self, pk: &Pk, h: &TapLeafHash) -> Option<bitcoin::SchnorrSig> {
+        let res = self.inner.lookup_tap_leaf_script_sig(pk, h);
+        self.record(res, || MissingItem::Signature(pk.clone()))
+    }
+
+    fn lookup_tap_control_block_map(
+        &self,
+    ) -> Option<&BTreeMap<ControlBlock, (bitcoin::Script, LeafVersion)>> {
+        self.inner.lookup_tap_control_block_map()
+    }
+
+    fn lookup_pkh_pk(&self, pkh: &Pk::Hash) -> Option<Pk> {
+        self.inner.lookup_pkh_pk(pkh)
+    }
+
+    fn lookup_pkh_ecdsa_sig(
+        &self,
+        pkh: &Pk::Hash,
+    ) -> Option<(bitcoin::PublicKey, bitcoin::EcdsaSig)> {
+        let res = self.inner.lookup_pkh_ecdsa_sig(pkh);
+        self.record(res, || MissingItem::<Pk>::KeyHashSignature(pkh.clone()))
+    }
+
+    fn lookup_pkh_tap_leaf_script_sig(
+        &self,
+        pkh: &(Pk::Hash, TapLeafHash),
+    ) -> Option<(XOnlyPublicKey, bitcoin::SchnorrSig)> {
+        let res = self.inner.lookup_pkh_tap_leaf_script_sig(pkh);
+        self.record(res, || MissingItem::<Pk>::KeyHashSignature(pkh.0.clone()))
+    }
+
+    fn lookup_sha256(&self, h: &Pk::Sha256) -> Option<Preimage32> {
+        let res = self.inner.lookup_sha256(h);
+        self.record(res, || MissingItem::<Pk>::Sha256Preimage(h.clone()))
+    }
+
+    fn lookup_hash256(&self, h: &Pk::Hash256) -> Option<Preimage32> {
+        let res = self.inner.lookup_hash256(h);
+        self.record(res, || MissingItem::<Pk>::Hash256Preimage(h.clone()))
+    }
+
+    fn lookup_ripemd160(&self, h: &Pk::Ripemd160) -> Option<Preimage32> {
+        let res = self.inner.lookup_ripemd160(h);
+        self.record(res, || MissingItem::<Pk>::Ripemd160Preimage(h.clone()))
+    }
+
+    fn lookup_hash160(&self, h: &Pk::Hash160) -> Option<Preimage32> {
+        let res = self.inner.lookup_hash160(h);
+        self.record(res, || MissingItem::<Pk>::Hash160Preimage(h.clone()))
+    }
+
+    fn check_older(&self, n: u32) -> bool {
+        let res = self.inner.check_older(n);
+        if !res {
+            self.missing.borrow_mut().insert(MissingItem::Older(n));
+        }
+        res
+    }
+
+    fn lookup_current_height(&self) -> Option<u32> {
+        self.inner.lookup_current_height()
+    }
+
+    fn lookup_current_mtp(&self) -> Option<u32> {
+        self.inner.lookup_current_mtp()
+    }
+
+    fn check_after(&self, n: u32) -> bool {
+        let res = self.inner.check_after(n);
+        if !res {
+            self.missing.borrow_mut().insert(MissingItem::After(n));
+        }
+        res
+    }
+}
+
 /// A witness, if available, for a Miniscript fragment
 #[derive(Clone, PartialEq, Eq, Debug)]
 pub enum Witness {
@@ -542,891 +1096,1468 @@ impl Ord for Witness {
     }
 }
 
-impl Witness {
-    /// Turn a signature into (part of) a satisfaction
-    fn signature<Pk: ToPublicKey, S: Satisfier<Pk>, Ctx: ScriptContext>(
-        sat: S,
-        pk: &Pk,
-        leaf_hash: &TapLeafHash,
-    ) -> Self {
-        match Ctx::sig_type() {
-            super::context::SigType::Ecdsa => match sat.lookup_ecdsa_sig(pk) {
-                Some(sig) => Witness::Stack(vec![sig.to_vec()]),
-                // Signatures cannot be forged
-                None => Witness::Impossible,
-            },
-            super::context::SigType::Schnorr => match sat.lookup_tap_leaf_script_sig(pk, leaf_hash)
-            {
-                Some(sig) => Witness::Stack(vec![sig.to_vec()]),
-                // Signatures cannot be forged
-                None => Witness::Impossible,
-            },
-        }
-    }
+/// A (dis)satisfaction of a Miniscript fragment
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Satisfaction {
+    /// The actual witness stack
+    pub stack: Witness,
+    /// Whether or not this (dis)satisfaction has a signature somewhere
+    /// in it
+    pub has_sig: bool,
+}
 
-    /// Turn a public key related to a pkh into (part of) a satisfaction
-    fn pkh_public_key<Pk: ToPublicKey, S: Satisfier<Pk>>(sat: S, pkh: &Pk::Hash) -> Self {
-        match sat.lookup_pkh_pk(pkh) {
-            Some(pk) => Witness::Stack(vec![pk.to_public_key().to_bytes()]),
-            // public key hashes are assumed to be unavailable
-            // instead of impossible since it is the same as pub-key hashes
-            None => Witness::Unavailable,
-        }
-    }
+/// The size of a (dis)satisfaction, or the reason there is none
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Stack {
+    /// Available, with the total length and number of its elements
+    Size { bytes: usize, elems: usize },
+    /// Third party can possibly satisfy the fragment but we cannot
+    Unavailable,
+    /// No third party can produce a satisfaction without private key
+    Impossible,
+}
+
+impl Stack {
+    /// Equivalent to an empty stack
+    const EMPTY: Stack = Stack::Size { bytes: 0, elems: 0 };
 
-    /// Turn a key/signature pair related to a pkh into (part of) a satisfaction
-    fn pkh_signature<Pk: ToPublicKey, S: Satisfier<Pk>>(sat: S, pkh: &Pk::Hash) -> Self {
-        match sat.lookup_pkh_ecdsa_sig(pkh) {
-            Some((pk, sig)) => Witness::Stack(vec![sig.to_vec(), pk.to_public_key().to_bytes()]),
-            None => Witness::Impossible,
+    /// A single element of `len` bytes
+    fn push(len: usize) -> Stack {
+        Stack::Size {
+            bytes: len,
+            elems: 1,
         }
     }
 
-    /// Turn a hash preimage into (part of) a satisfaction
-    fn ripemd160_preimage<Pk: ToPublicKey, S: Satisfier<Pk>>(sat: S, h: ripemd160::Hash) -> Self {
-        match sat.lookup_ripemd160(h) {
-            Some(pre) => Witness::Stack(vec![pre.to_vec()]),
-            // Note hash preimages are unavailable instead of impossible
-            None => Witness::Unavailable,
+    /// Concatenate, or otherwise combine, two (dis)satisfactions
+    fn combine(self, other: Stack) -> Stack {
+        match (self, other) {
+            (Stack::Impossible, _) | (_, Stack::Impossible) => Stack::Impossible,
+            (Stack::Unavailable, _) | (_, Stack::Unavailable) => Stack::Unavailable,
+            (
+                Stack::Size { bytes, elems },
+                Stack::Size {
+                    bytes: other_bytes,
+                    elems: other_elems,
+                },
+            ) => Stack::Size {
+                bytes: bytes + other_bytes,
+                elems: elems + other_elems,
+            },
         }
     }
 
-    /// Turn a hash preimage into (part of) a satisfaction
-    fn hash160_preimage<Pk: ToPublicKey, S: Satisfier<Pk>>(sat: S, h: hash160::Hash) -> Self {
-        match sat.lookup_hash160(h) {
-            Some(pre) => Witness::Stack(vec![pre.to_vec()]),
-            // Note hash preimages are unavailable instead of impossible
-            None => Witness::Unavailable,
+    /// The size used to compare witnesses, as in `util::witness_size`
+    fn weight(self) -> Option<usize> {
+        match self {
+            Stack::Size { bytes, elems } => Some(bytes + varint_len(elems)),
+            _ => None,
         }
     }
 
-    /// Turn a hash preimage into (part of) a satisfaction
-    fn sha256_preimage<Pk: ToPublicKey, S: Satisfier<Pk>>(sat: S, h: sha256::Hash) -> Self {
-        match sat.lookup_sha256(h) {
-            Some(pre) => Witness::Stack(vec![pre.to_vec()]),
-            // Note hash preimages are unavailable instead of impossible
-            None => Witness::Unavailable,
+    /// Order in which (dis)satisfactions are preferred, as for `Witness`
+    fn compare(self, other: Stack) -> cmp::Ordering {
+        match (self, other) {
+            (Stack::Size { .. }, Stack::Size { .. }) => self.weight().cmp(&other.weight()),
+            (Stack::Size { .. }, _) => cmp::Ordering::Less,
+            (_, Stack::Size { .. }) => cmp::Ordering::Greater,
+            (Stack::Impossible, Stack::Unavailable) => cmp::Ordering::Less,
+            (Stack::Unavailable, Stack::Impossible) => cmp::Ordering::Greater,
+            _ => cmp::Ordering::Equal,
+        }
+    }
+}
+
+/// A (dis)satisfaction of a fragment, without its witness
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+struct Cost {
+    stack: Stack,
+    has_sig: bool,
+}
+
+impl Cost {
+    fn new(stack: Stack, has_sig: bool) -> Cost {
+        Cost { stack, has_sig }
+    }
+
+    /// The non-malleable choice between two candidates, and whether it is the
+    /// second one
+    fn minimum(one: Cost, two: Cost) -> (Cost, bool) {
+        // If there is only one available satisfaction, we must choose that
+        // regardless of has_sig marker.
+        // This handles the case where both are impossible.
+        match (one.stack, two.stack) {
+            (Stack::Impossible, _) => return (two, true),
+            (_, Stack::Impossible) => return (one, false),
+            _ => {}
+        }
+        match (one.has_sig, two.has_sig) {
+            // If neither option has a signature, this is a malleability
+            // vector, so choose neither one.
+            (false, false) => (Cost::new(Stack::Unavailable, false), false),
+            // If only one has a signature, take the one that doesn't; a
+            // third party could malleate by removing the signature, but
+            // can't malleate if he'd have to add it
+            (false, true) => (Cost::new(one.stack, false), false),
+            (true, false) => (Cost::new(two.stack, false), true),
+            // If both have a signature associated with them, choose the
+            // cheaper one (where "cheaper" is defined such that available
+            // things are cheaper than unavailable ones)
+            (true, true) => {
+                let second = two.stack.compare(one.stack) == cmp::Ordering::Less;
+                let stack = if second { two.stack } else { one.stack };
+                (Cost::new(stack, true), second)
+            }
         }
     }
 
-    /// Turn a hash preimage into (part of) a satisfaction
-    fn hash256_preimage<Pk: ToPublicKey, S: Satisfier<Pk>>(sat: S, h: sha256d::Hash) -> Self {
-        match sat.lookup_hash256(h) {
-            Some(pre) => Witness::Stack(vec![pre.to_vec()]),
-            // Note hash preimages are unavailable instead of impossible
-            None => Witness::Unavailable,
+    /// The cheapest of two candidates, allowing witness malleability, and
+    /// whether it is the second one
+    fn minimum_mall(one: Cost, two: Cost) -> (Cost, bool) {
+        match (one.stack, two.stack) {
+            // If there is only one possible satisfaction, use it regardless
+            // of the other one
+            (Stack::Impossible, _) | (Stack::Unavailable, _) => return (two, true),
+            (_, Stack::Impossible) | (_, Stack::Unavailable) => return (one, false),
+            _ => {}
         }
+        let second = two.stack.compare(one.stack) == cmp::Ordering::Less;
+        let stack = if second { two.stack } else { one.stack };
+        // The fragment is has_sig only if both of the fragments are has_sig
+        (Cost::new(stack, one.has_sig && two.has_sig), second)
+    }
+}
+
+/// Where the (dis)satisfaction of a fragment goes in the witness
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+struct Layout {
+    sat: bool,
+    bytes: usize,
+    elems: usize,
+}
+
+/// Scratch space for one fragment of a Miniscript being satisfied
+///
+/// Satisfaction lays the fragments out in a slice of slots, which is walked
+/// once to choose the cheapest (dis)satisfaction of every fragment and once
+/// more to write the chosen witness. A Miniscript needs as many slots as it
+/// has fragments, that is as many as `ms.iter().count()`.
+pub struct Slot<'a, Pk: MiniscriptKey, Ctx: ScriptContext> {
+    node: Option<&'a Miniscript<Pk, Ctx>>,
+    /// Index of the slot of the first child; the others follow it
+    first_child: usize,
+    sat: Cost,
+    dissat: Cost,
+    /// Whether the second of two candidate (dis)satisfactions was chosen
+    sat_second: bool,
+    dissat_second: bool,
+    /// Whether the satisfaction of the parent `thresh` satisfies this fragment
+    thresh_sat: bool,
+    /// Set if the fragment is part of the witness
+    layout: Option<Layout>,
+}
+
+impl<'a, Pk: MiniscriptKey, Ctx: ScriptContext> Slot<'a, Pk, Ctx> {
+    /// An unused slot
+    pub const EMPTY: Self = Slot {
+        node: None,
+        first_child: 0,
+        sat: Cost {
+            stack: Stack::Impossible,
+            has_sig: false,
+        },
+        dissat: Cost {
+            stack: Stack::Impossible,
+            has_sig: false,
+        },
+        sat_second: false,
+        dissat_second: false,
+        thresh_sat: false,
+        layout: None,
+    };
+}
+
+impl<'a, Pk: MiniscriptKey, Ctx: ScriptContext> Default for Slot<'a, Pk, Ctx> {
+    fn default() -> Self {
+        Slot::EMPTY
     }
 }
 
-impl Witness {
-    /// Produce something like a 32-byte 0 push
-    fn hash_dissatisfaction() -> Self {
-        Witness::Stack(vec![vec![0; 32]])
+impl<'a, Pk: MiniscriptKey, Ctx: ScriptContext> Clone for Slot<'a, Pk, Ctx> {
+    fn clone(&self) -> Self {
+        *self
     }
+}
+
+impl<'a, Pk: MiniscriptKey, Ctx: ScriptContext> Copy for Slot<'a, Pk, Ctx> {}
 
-    /// Construct a satisfaction equivalent to an empty stack
-    fn empty() -> Self {
-        Witness::Stack(vec![])
+/// A satisfying witness written into caller-provided buffers
+///
+/// The elements are stored back to back in the data buffer, and their
+/// lengths in the length buffer.
+pub struct WitnessBuffer<'b> {
+    data: &'b mut [u8],
+    lens: &'b mut [usize],
+    elems: usize,
+}
+
+impl<'b> WitnessBuffer<'b> {
+    /// Create an empty witness, which can hold up to `lens.len()` elements
+    /// totalling up to `data.len()` bytes
+    pub fn new(data: &'b mut [u8], lens: &'b mut [usize]) -> Self {
+        WitnessBuffer {
+            data,
+            lens,
+            elems: 0,
+        }
     }
 
-    /// Construct a satisfaction equivalent to `OP_1`
-    fn push_1() -> Self {
-        Witness::Stack(vec![vec![1]])
+    /// The number of elements of the witness
+    pub fn len(&self) -> usize {
+        self.elems
     }
 
-    /// Construct a satisfaction equivalent to a single empty push
-    fn push_0() -> Self {
-        Witness::Stack(vec![vec![]])
+    /// Whether the witness has no elements
+    pub fn is_empty(&self) -> bool {
+        self.elems == 0
     }
 
-    /// Concatenate, or otherwise combine, two satisfactions
-    fn combine(one: Self, two: Self) -> Self {
-        match (one, two) {
-            (Witness::Impossible, _) | (_, Witness::Impossible) => Witness::Impossible,
-            (Witness::Unavailable, _) | (_, Witness::Unavailable) => Witness::Unavailable,
-            (Witness::Stack(mut a), Witness::Stack(b)) => {
-                a.extend(b);
-                Witness::Stack(a)
-            }
+    /// Iterate over the elements of the witness
+    pub fn iter(&self) -> impl Iterator<Item = &[u8]> + '_ {
+        let data = &*self.data;
+        self.lens[..self.elems].iter().scan(0, move |offset, &len| {
+            let elem = &data[*offset..*offset + len];
+            *offset += len;
+            Some(elem)
+        })
+    }
+
+    /// Write the element at the position of `cursor`, and move past it
+    fn put(&mut self, cursor: &mut (usize, usize), elem: &[u8]) -> Result<(), Error> {
+        let (bytes, elems) = *cursor;
+        if bytes + elem.len() > self.data.len() || elems >= self.lens.len() {
+            return Err(Error::SatisfyBufferTooSmall);
         }
+        self.data[bytes..bytes + elem.len()].copy_from_slice(elem);
+        self.lens[elems] = elem.len();
+        *cursor = (bytes + elem.len(), elems + 1);
+        Ok(())
     }
 }
 
-/// A (dis)satisfaction of a Miniscript fragment
-#[derive(Clone, PartialEq, Eq, Debug)]
-pub struct Satisfaction {
-    /// The actual witness stack
-    pub stack: Witness,
-    /// Whether or not this (dis)satisfaction has a signature somewhere
-    /// in it
-    pub has_sig: bool,
+/// A witness element serialized on the stack: a signature with its sighash
+/// byte, or a public key
+#[derive(Clone, Copy)]
+struct Push {
+    buf: [u8; 73],
+    len: usize,
 }
 
-impl Satisfaction {
-    // produce a non-malleable satisafaction for thesh frag
-    fn thresh<Pk, Ctx, Sat, F>(
-        k: usize,
-        subs: &[Arc<Miniscript<Pk, Ctx>>],
-        stfr: &Sat,
-        root_has_sig: bool,
-        leaf_hash: &TapLeafHash,
-        min_fn: &mut F,
-    ) -> Self
-    where
-        Pk: MiniscriptKey + ToPublicKey,
-        Ctx: ScriptContext,
-        Sat: Satisfier<Pk>,
-        F: FnMut(Satisfaction, Satisfaction) -> Satisfaction,
-    {
-        let mut sats = subs
-            .iter()
-            .map(|s| {
-                Self::satisfy_helper(
-                    &s.node,
-                    stfr,
-                    root_has_sig,
-                    leaf_hash,
-                    min_fn,
-                    &mut Self::thresh,
-                )
-            })
-            .collect::<Vec<_>>();
-        // Start with the to-return stack set to all dissatisfactions
-        let mut ret_stack = subs
-            .iter()
-            .map(|s| {
-                Self::dissatisfy_helper(
-                    &s.node,
-                    stfr,
-                    root_has_sig,
-                    leaf_hash,
-                    min_fn,
-                    &mut Self::thresh,
-                )
-            })
-            .collect::<Vec<_>>();
-
-        // Sort everything by (sat cost - dissat cost), except that
-        // satisfactions without signatures beat satisfactions with
-        // signatures
-        let mut sat_indices = (0..subs.len()).collect::<Vec<_>>();
-        sat_indices.sort_by_key(|&i| {
-            let stack_weight = match (&sats[i].stack, &ret_stack[i].stack) {
-                (&Witness::Unavailable, _) | (&Witness::Impossible, _) => i64::MAX,
-                // This can only be the case when we have PkH without the corresponding
-                // Pubkey.
-                (_, &Witness::Unavailable) | (_, &Witness::Impossible) => i64::MIN,
-                (&Witness::Stack(ref s), &Witness::Stack(ref d)) => {
-                    witness_size(s) as i64 - witness_size(d) as i64
-                }
-            };
-            let is_impossible = sats[i].stack == Witness::Impossible;
-            // First consider the candidates that are not impossible to satisfy
-            // by any party. Among those first consider the ones that have no sig
-            // because third party can malleate them if they are not chosen.
-            // Lastly, choose by weight.
-            (is_impossible, sats[i].has_sig, stack_weight)
-        });
-
-        for i in 0..k {
-            mem::swap(&mut ret_stack[sat_indices[i]], &mut sats[sat_indices[i]]);
+impl Push {
+    fn new(data: &[u8], sighash: Option<u8>) -> Push {
+        let mut buf = [0; 73];
+        buf[..data.len()].copy_from_slice(data);
+        let mut len = data.len();
+        if let Some(sighash) = sighash {
+            buf[len] = sighash;
+            len += 1;
         }
+        Push { buf, len }
+    }
 
-        // We preferably take satisfactions that are not impossible
-        // If we cannot find `k` satisfactions that are not impossible
-        // then the threshold branch is impossible to satisfy
-        // For example, the fragment thresh(2, hash, 0, 0, 0)
-        // is has an impossible witness
-        assert!(k > 0);
-        if sats[sat_indices[k - 1]].stack == Witness::Impossible {
-            Satisfaction {
-                stack: Witness::Impossible,
-                // If the witness is impossible, we don't care about the
-                // has_sig flag
-                has_sig: false,
-            }
+    fn public_key(pk: bitcoin::PublicKey) -> Push {
+        if pk.compressed {
+            Push::new(&pk.inner.serialize(), None)
+        } else {
+            Push::new(&pk.inner.serialize_uncompressed(), None)
         }
-        // We are now guaranteed that all elements in `k` satisfactions
-        // are not impossible(we sort by is_impossible bool).
-        // The above loop should have taken everything without a sig
-        // (since those were sorted higher than non-sigs). If there
-        // are remaining non-sig satisfactions this indicates a
-        // malleability vector
-        // For example, the fragment thresh(2, hash, hash, 0, 0)
-        // is uniquely satisfyiable because there is no satisfaction
-        // for the 0 fragment
-        else if k < sat_indices.len()
-            && !sats[sat_indices[k]].has_sig
-            && sats[sat_indices[k]].stack != Witness::Impossible
-        {
-            // All arguments should be `d`, so dissatisfactions have no
-            // signatures; and in this branch we assume too many weak
-            // arguments, so none of the satisfactions should have
-            // signatures either.
-            for sat in &ret_stack {
-                assert!(!sat.has_sig);
-            }
-            Satisfaction {
-                stack: Witness::Unavailable,
-                has_sig: false,
+    }
+
+    fn as_slice(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+}
+
+fn signature<Pk: ToPublicKey, Ctx: ScriptContext, S: Satisfier<Pk>>(
+    stfr: &S,
+    pk: &Pk,
+    leaf_hash: &TapLeafHash,
+) -> Option<Push> {
+    match Ctx::sig_type() {
+        SigType::Ecdsa => stfr
+            .lookup_ecdsa_sig(pk)
+            .map(|sig| Push::new(&sig.sig.serialize_der(), Some(sig.hash_ty as u8))),
+        SigType::Schnorr => stfr.lookup_tap_leaf_script_sig(pk, leaf_hash).map(|sig| {
+            let sighash = match sig.hash_ty {
+                bitcoin::SchnorrSighashType::Default => None,
+                hash_ty => Some(hash_ty as u8),
+            };
+            Push::new(sig.sig.as_ref(), sighash)
+        }),
+    }
+}
+
+fn pkh_signature<Pk: ToPublicKey, S: Satisfier<Pk>>(
+    stfr: &S,
+    pkh: &Pk::Hash,
+) -> Option<(Push, Push)> {
+    stfr.lookup_pkh_ecdsa_sig(pkh)
+        .or_else(|| stfr.lookup_raw_pkh_ecdsa_sig(&Pk::hash_to_hash160(pkh)))
+        .map(|(pk, sig)| {
+            (
+                Push::new(&sig.sig.serialize_der(), Some(sig.hash_ty as u8)),
+                Push::public_key(pk),
+            )
+        })
+}
+
+fn pkh_public_key<Pk: ToPublicKey, S: Satisfier<Pk>>(stfr: &S, pkh: &Pk::Hash) -> Option<Push> {
+    stfr.lookup_pkh_pk(pkh)
+        .map(|pk| pk.to_public_key())
+        .or_else(|| stfr.lookup_raw_pkh_pk(&Pk::hash_to_hash160(pkh)))
+        .map(Push::public_key)
+}
+
+fn preimage<Pk: ToPublicKey, Ctx: ScriptContext, S: Satisfier<Pk>>(
+    stfr: &S,
+    term: &Terminal<Pk, Ctx>,
+) -> Option<[u8; 32]> {
+    match *term {
+        Terminal::Sha256(ref h) => stfr.lookup_sha256(h),
+        Terminal::Hash256(ref h) => stfr.lookup_hash256(h),
+        Terminal::Ripemd160(ref h) => stfr.lookup_ripemd160(h),
+        Terminal::Hash160(ref h) => stfr.lookup_hash160(h),
+        _ => None,
+    }
+}
+
+// Visit the keys of a `multi_a` in the order their signatures are looked up,
+// that is in reverse, and for a `sortedmulti_a` in reverse of their x-only
+// serialization, until `f` returns false
+fn for_each_multi_a_key<Pk: ToPublicKey, F: FnMut(&Pk) -> bool>(
+    keys: &[Pk],
+    sorted: bool,
+    mut f: F,
+) {
+    if !sorted {
+        for pk in keys.iter().rev() {
+            if !f(pk) {
+                return;
             }
-        } else {
-            // Otherwise flatten everything out
-            Satisfaction {
-                has_sig: ret_stack.iter().any(|sat| sat.has_sig),
-                stack: ret_stack.into_iter().fold(Witness::empty(), |acc, next| {
-                    Witness::combine(next.stack, acc)
-                }),
+        }
+        return;
+    }
+    let mut prev: Option<([u8; 32], usize)> = None;
+    for _ in 0..keys.len() {
+        let mut next: Option<([u8; 32], usize)> = None;
+        for (i, pk) in keys.iter().enumerate() {
+            let key = (pk.to_x_only_pubkey().serialize(), i);
+            if prev.map_or(true, |prev| key < prev) && next.map_or(true, |next| key > next) {
+                next = Some(key);
             }
         }
+        let (_, i) = next.expect("one key left per iteration");
+        if !f(&keys[i]) {
+            return;
+        }
+        prev = next;
     }
+}
 
-    // produce a possily malleable satisafaction for thesh frag
-    fn thresh_mall<Pk, Ctx, Sat, F>(
-        k: usize,
-        subs: &[Arc<Miniscript<Pk, Ctx>>],
-        stfr: &Sat,
-        root_has_sig: bool,
-        leaf_hash: &TapLeafHash,
-        min_fn: &mut F,
-    ) -> Self
-    where
-        Pk: MiniscriptKey + ToPublicKey,
-        Ctx: ScriptContext,
-        Sat: Satisfier<Pk>,
-        F: FnMut(Satisfaction, Satisfaction) -> Satisfaction,
-    {
-        let mut sats = subs
-            .iter()
-            .map(|s| {
-                Self::satisfy_helper(
-                    &s.node,
-                    stfr,
-                    root_has_sig,
-                    leaf_hash,
-                    min_fn,
-                    &mut Self::thresh_mall,
-                )
-            })
-            .collect::<Vec<_>>();
-        // Start with the to-return stack set to all dissatisfactions
-        let mut ret_stack = subs
-            .iter()
-            .map(|s| {
-                Self::dissatisfy_helper(
-                    &s.node,
-                    stfr,
-                    root_has_sig,
-                    leaf_hash,
-                    min_fn,
-                    &mut Self::thresh_mall,
-                )
-            })
-            .collect::<Vec<_>>();
-
-        // Sort everything by (sat cost - dissat cost), except that
-        // satisfactions without signatures beat satisfactions with
-        // signatures
-        let mut sat_indices = (0..subs.len()).collect::<Vec<_>>();
-        sat_indices.sort_by_key(|&i| {
-            // For malleable satifactions, directly choose smallest weights
-            match (&sats[i].stack, &ret_stack[i].stack) {
-                (&Witness::Unavailable, _) | (&Witness::Impossible, _) => i64::MAX,
-                // This is only possible when one of the branches has PkH
-                (_, &Witness::Unavailable) | (_, &Witness::Impossible) => i64::MIN,
-                (&Witness::Stack(ref s), &Witness::Stack(ref d)) => {
-                    witness_size(s) as i64 - witness_size(d) as i64
-                }
+// Lay out the fragments in breadth-first order, so that the children of a
+// fragment come after it, next to each other
+fn layout_fragments<'a, Pk: MiniscriptKey, Ctx: ScriptContext>(
+    ms: &'a Miniscript<Pk, Ctx>,
+    slots: &mut [Slot<'a, Pk, Ctx>],
+) -> Result<usize, Error> {
+    if slots.is_empty() {
+        return Err(Error::SatisfyBufferTooSmall);
+    }
+    slots[0] = Slot {
+        node: Some(ms),
+        ..Slot::EMPTY
+    };
+    let mut len = 1;
+    let mut i = 0;
+    while i < len {
+        let node = slots[i].node.expect("filled slot");
+        slots[i].first_child = len;
+        let mut n = 0;
+        while let Some(child) = node.get_nth_child(n) {
+            if len == slots.len() {
+                return Err(Error::SatisfyBufferTooSmall);
             }
-        });
-
-        // swap the satisfactions
-        for i in 0..k {
-            mem::swap(&mut ret_stack[sat_indices[i]], &mut sats[sat_indices[i]]);
+            slots[len] = Slot {
+                node: Some(child),
+                ..Slot::EMPTY
+            };
+            len += 1;
+            n += 1;
         }
+        i += 1;
+    }
+    Ok(len)
+}
 
-        // combine the witness
-        // no non-malleability checks needed
-        Satisfaction {
-            has_sig: ret_stack.iter().any(|sat| sat.has_sig),
-            stack: ret_stack.into_iter().fold(Witness::empty(), |acc, next| {
-                Witness::combine(next.stack, acc)
+// The satisfaction of fragment `i`, whose children are already computed,
+// and whether it is the second candidate of its fragment
+fn sat_cost<Pk: ToPublicKey, Ctx: ScriptContext, S: Satisfier<Pk>>(
+    slots: &mut [Slot<Pk, Ctx>],
+    i: usize,
+    stfr: &S,
+    root_has_sig: bool,
+    leaf_hash: &TapLeafHash,
+    mall: bool,
+) -> (Cost, bool) {
+    let node = slots[i].node.expect("filled slot");
+    let first = slots[i].first_child;
+    let sat = |j: usize| slots[first + j].sat;
+    let dissat = |j: usize| slots[first + j].dissat;
+    let minimum = if mall {
+        Cost::minimum_mall
+    } else {
+        Cost::minimum
+    };
+    let timelock = |met: bool| {
+        if met {
+            Stack::EMPTY
+        } else if root_has_sig {
+            // If the root terminal has signature, the signature covers the
+            // nLockTime and nSequence values. The sender of the transaction
+            // should take care that it signs the value such that the
+            // timelock is not met
+            Stack::Impossible
+        } else {
+            Stack::Unavailable
+        }
+    };
+    let alone = |cost: Cost| (cost, false);
+    match node.node {
+        Terminal::PkK(ref pk) => alone(Cost::new(
+            signature::<_, Ctx, _>(stfr, pk, leaf_hash)
+                .map_or(Stack::Impossible, |sig| Stack::push(sig.len)),
+            true,
+        )),
+        Terminal::PkH(ref pkh) => alone(Cost::new(
+            pkh_signature(stfr, pkh).map_or(Stack::Impossible, |(sig, pk)| Stack::Size {
+                bytes: sig.len + pk.len,
+                elems: 2,
             }),
+            true,
+        )),
+        Terminal::After(t) => alone(Cost::new(timelock(stfr.check_after(t)), false)),
+        Terminal::Older(t) => alone(Cost::new(timelock(stfr.check_older(t)), false)),
+        Terminal::Sha256(..)
+        | Terminal::Hash256(..)
+        | Terminal::Ripemd160(..)
+        | Terminal::Hash160(..) => alone(Cost::new(
+            preimage(stfr, &node.node).map_or(Stack::Unavailable, |_| Stack::push(32)),
+            false,
+        )),
+        Terminal::True => alone(Cost::new(Stack::EMPTY, false)),
+        Terminal::False => alone(Cost::new(Stack::Impossible, false)),
+        Terminal::Alt(..)
+        | Terminal::Swap(..)
+        | Terminal::Check(..)
+        | Terminal::Verify(..)
+        | Terminal::NonZero(..)
+        | Terminal::ZeroNotEqual(..) => alone(sat(0)),
+        Terminal::DupIf(..) => alone(Cost::new(
+            sat(0).stack.combine(Stack::push(1)),
+            sat(0).has_sig,
+        )),
+        Terminal::AndV(..) | Terminal::AndB(..) => alone(Cost::new(
+            sat(1).stack.combine(sat(0).stack),
+            sat(0).has_sig || sat(1).has_sig,
+        )),
+        Terminal::AndOr(..) => minimum(
+            Cost::new(
+                sat(1).stack.combine(sat(0).stack),
+                sat(0).has_sig || sat(1).has_sig,
+            ),
+            Cost::new(
+                sat(2).stack.combine(dissat(0).stack),
+                dissat(0).has_sig || sat(2).has_sig,
+            ),
+        ),
+        Terminal::OrB(..) => minimum(
+            Cost::new(sat(1).stack.combine(dissat(0).stack), sat(1).has_sig),
+            Cost::new(dissat(1).stack.combine(sat(0).stack), sat(0).has_sig),
+        ),
+        Terminal::OrD(..) | Terminal::OrC(..) => minimum(
+            sat(0),
+            Cost::new(sat(1).stack.combine(dissat(0).stack), sat(1).has_sig),
+        ),
+        Terminal::OrI(..) => minimum(
+            Cost::new(sat(0).stack.combine(Stack::push(1)), sat(0).has_sig),
+            Cost::new(sat(1).stack.combine(Stack::push(0)), sat(1).has_sig),
+        ),
+        Terminal::Thresh(k, ref subs) => alone(thresh_sat_cost(slots, first, k, subs.len(), mall)),
+        Terminal::Multi(k, ref keys) => {
+            // The first `k` available signatures are used. This is a privacy
+            // issue, as it tells which keys were not available, but Bitcoin
+            // Core does the same for CHECKMULTISIG
+            let mut count = 0;
+            let mut bytes = 0;
+            for pk in keys {
+                if count == k {
+                    break;
+                }
+                if let Some(sig) = signature::<_, Ctx, _>(stfr, pk, leaf_hash) {
+                    bytes += sig.len;
+                    count += 1;
+                }
+            }
+            if count < k {
+                alone(Cost::new(Stack::Impossible, false))
+            } else {
+                alone(Cost::new(
+                    Stack::Size {
+                        bytes,
+                        elems: k + 1,
+                    },
+                    true,
+                ))
+            }
+        }
+        Terminal::MultiA(k, ref keys) | Terminal::SortedMultiA(k, ref keys) => {
+            let sorted = is_sorted(&node.node);
+            let mut count = 0;
+            let mut bytes = 0;
+            for_each_multi_a_key(keys, sorted, |pk| {
+                if let Some(sig) = signature::<_, Ctx, _>(stfr, pk, leaf_hash) {
+                    bytes += sig.len;
+                    count += 1;
+                }
+                count < k
+            });
+            if count < k {
+                alone(Cost::new(Stack::Impossible, false))
+            } else {
+                alone(Cost::new(
+                    Stack::Size {
+                        bytes,
+                        elems: keys.len(),
+                    },
+                    true,
+                ))
+            }
         }
     }
+}
 
-    fn minimum(sat1: Self, sat2: Self) -> Self {
-        // If there is only one available satisfaction, we must choose that
-        // regardless of has_sig marker.
-        // This handles the case where both are impossible.
-        match (&sat1.stack, &sat2.stack) {
-            (&Witness::Impossible, _) => return sat2,
-            (_, &Witness::Impossible) => return sat1,
-            _ => {}
-        }
-        match (sat1.has_sig, sat2.has_sig) {
-            // If neither option has a signature, this is a malleability
-            // vector, so choose neither one.
-            (false, false) => Satisfaction {
-                stack: Witness::Unavailable,
-                has_sig: false,
-            },
-            // If only one has a signature, take the one that doesn't; a
-            // third party could malleate by removing the signature, but
-            // can't malleate if he'd have to add it
-            (false, true) => Satisfaction {
-                stack: sat1.stack,
-                has_sig: false,
-            },
-            (true, false) => Satisfaction {
-                stack: sat2.stack,
-                has_sig: false,
-            },
-            // If both have a signature associated with them, choose the
-            // cheaper one (where "cheaper" is defined such that available
-            // things are cheaper than unavailable ones)
-            (true, true) => Satisfaction {
-                stack: cmp::min(sat1.stack, sat2.stack),
-                has_sig: true,
-            },
-        }
+fn is_sorted<Pk: MiniscriptKey, Ctx: ScriptContext>(term: &Terminal<Pk, Ctx>) -> bool {
+    match *term {
+        Terminal::SortedMultiA(..) => true,
+        _ => false,
     }
+}
 
-    // calculate the minimum witness allowing witness malleability
-    fn minimum_mall(sat1: Self, sat2: Self) -> Self {
-        match (&sat1.stack, &sat2.stack) {
-            // If there is only one possible satisfaction, use it regardless
-            // of the other one
-            (&Witness::Impossible, _) | (&Witness::Unavailable, _) => return sat2,
-            (_, &Witness::Impossible) | (_, &Witness::Unavailable) => return sat1,
-            _ => {}
+// The satisfaction of a `thresh`, marking the children it satisfies
+fn thresh_sat_cost<Pk: MiniscriptKey, Ctx: ScriptContext>(
+    slots: &mut [Slot<Pk, Ctx>],
+    first: usize,
+    k: usize,
+    n: usize,
+    mall: bool,
+) -> Cost {
+    // Without malleability, satisfactions that are not impossible come
+    // first, then those without signature, which a third party could
+    // malleate if they are not chosen. Then come the cheapest ones relative
+    // to their dissatisfaction.
+    let key = |slot: &Slot<Pk, Ctx>| {
+        let weight = match (slot.sat.stack.weight(), slot.dissat.stack.weight()) {
+            (None, _) => i64::max_value(),
+            // This can only be the case when we have PkH without the
+            // corresponding public key
+            (_, None) => i64::min_value(),
+            (Some(s), Some(d)) => s as i64 - d as i64,
+        };
+        if mall {
+            (false, false, weight)
+        } else {
+            (
+                slot.sat.stack == Stack::Impossible,
+                slot.sat.has_sig,
+                weight,
+            )
         }
-        Satisfaction {
-            stack: cmp::min(sat1.stack, sat2.stack),
-            // The fragment is has_sig only if both of the
-            // fragments are has_sig
-            has_sig: sat1.has_sig && sat2.has_sig,
+    };
+    // The cheapest unmarked child, the first one among equals
+    let cheapest = |slots: &[Slot<Pk, Ctx>]| {
+        let mut best: Option<usize> = None;
+        for j in first..first + n {
+            if !slots[j].thresh_sat && best.map_or(true, |b| key(&slots[j]) < key(&slots[b])) {
+                best = Some(j);
+            }
+        }
+        best
+    };
+
+    let mut last = first;
+    for _ in 0..k {
+        last = cheapest(slots).expect("k <= n");
+        slots[last].thresh_sat = true;
+    }
+    if !mall {
+        // If we cannot find `k` satisfactions that are not impossible then
+        // the threshold is impossible to satisfy, as for thresh(2,hash,0,0,0)
+        if slots[last].sat.stack == Stack::Impossible {
+            return Cost::new(Stack::Impossible, false);
         }
+        // A remaining satisfaction without signature is a malleability
+        // vector, as for thresh(2,hash,hash,0,0)
+        if let Some(next) = cheapest(slots) {
+            if !slots[next].sat.has_sig && slots[next].sat.stack != Stack::Impossible {
+                return Cost::new(Stack::Unavailable, false);
+            }
+        }
+    }
+    let mut cost = Cost::new(Stack::EMPTY, false);
+    for slot in &slots[first..first + n] {
+        let sub = if slot.thresh_sat {
+            slot.sat
+        } else {
+            slot.dissat
+        };
+        cost = Cost::new(sub.stack.combine(cost.stack), cost.has_sig || sub.has_sig);
     }
+    cost
+}
 
-    // produce a non-malleable satisfaction
-    fn satisfy_helper<Pk, Ctx, Sat, F, G>(
-        term: &Terminal<Pk, Ctx>,
-        stfr: &Sat,
-        root_has_sig: bool,
-        leaf_hash: &TapLeafHash,
-        min_fn: &mut F,
-        thresh_fn: &mut G,
-    ) -> Self
-    where
-        Pk: MiniscriptKey + ToPublicKey,
-        Ctx: ScriptContext,
-        Sat: Satisfier<Pk>,
-        F: FnMut(Satisfaction, Satisfaction) -> Satisfaction,
-        G: FnMut(
-            usize,
-            &[Arc<Miniscript<Pk, Ctx>>],
-            &Sat,
-            bool,
-            &TapLeafHash,
-            &mut F,
-        ) -> Satisfaction,
-    {
-        match *term {
-            Terminal::PkK(ref pk) => Satisfaction {
-                stack: Witness::signature::<_, _, Ctx>(stfr, pk, leaf_hash),
-                has_sig: true,
-            },
-            Terminal::PkH(ref pkh) => Satisfaction {
-                stack: Witness::pkh_signature(stfr, pkh),
-                has_sig: true,
+// The dissatisfaction of fragment `i`, whose children are already computed,
+// and whether it is the second candidate of its fragment
+fn dissat_cost<Pk: ToPublicKey, Ctx: ScriptContext, S: Satisfier<Pk>>(
+    slots: &[Slot<Pk, Ctx>],
+    i: usize,
+    stfr: &S,
+) -> (Cost, bool) {
+    let node = slots[i].node.expect("filled slot");
+    let first = slots[i].first_child;
+    let dissat = |j: usize| slots[first + j].dissat;
+    let sat = |j: usize| slots[first + j].sat;
+    let alone = |stack: Stack, has_sig: bool| (Cost::new(stack, has_sig), false);
+    match node.node {
+        Terminal::PkK(..) => alone(Stack::push(0), false),
+        Terminal::PkH(ref pkh) => alone(
+            Stack::push(0).combine(
+                pkh_public_key(stfr, pkh).map_or(Stack::Unavailable, |pk| Stack::push(pk.len)),
+            ),
+            false,
+        ),
+        Terminal::False => alone(Stack::EMPTY, false),
+        Terminal::True
+        | Terminal::Older(..)
+        | Terminal::After(..)
+        | Terminal::Verify(..)
+        | Terminal::OrC(..) => alone(Stack::Impossible, false),
+        Terminal::Sha256(..)
+        | Terminal::Hash256(..)
+        | Terminal::Ripemd160(..)
+        | Terminal::Hash160(..) => alone(Stack::push(32), false),
+        Terminal::Alt(..)
+        | Terminal::Swap(..)
+        | Terminal::Check(..)
+        | Terminal::ZeroNotEqual(..) => (dissat(0), false),
+        Terminal::DupIf(..) | Terminal::NonZero(..) => alone(Stack::push(0), false),
+        Terminal::AndV(..) => alone(
+            dissat(1).stack.combine(sat(0).stack),
+            sat(0).has_sig || dissat(1).has_sig,
+        ),
+        Terminal::AndB(..) | Terminal::OrB(..) | Terminal::OrD(..) => alone(
+            dissat(1).stack.combine(dissat(0).stack),
+            dissat(0).has_sig || dissat(1).has_sig,
+        ),
+        Terminal::AndOr(..) => alone(
+            dissat(2).stack.combine(dissat(0).stack),
+            dissat(0).has_sig || dissat(2).has_sig,
+        ),
+        // Dissatisfactions don't need to be non-malleable
+        Terminal::OrI(..) => Cost::minimum_mall(
+            Cost::new(dissat(0).stack.combine(Stack::push(1)), dissat(0).has_sig),
+            Cost::new(dissat(1).stack.combine(Stack::push(0)), dissat(1).has_sig),
+        ),
+        Terminal::Thresh(_, ref subs) => alone(
+            (0..subs.len()).fold(Stack::EMPTY, |acc, j| dissat(j).stack.combine(acc)),
+            false,
+        ),
+        Terminal::Multi(k, _) => alone(
+            Stack::Size {
+                bytes: 0,
+                elems: k + 1,
             },
-            Terminal::After(t) => Satisfaction {
-                stack: if stfr.check_after(t) {
-                    Witness::empty()
-                } else if root_has_sig {
-                    // If the root terminal has signature, the
-                    // signature covers the nLockTime and nSequence
-                    // values. The sender of the transaction should
-                    // take care that it signs the value such that the
-                    // timelock is not met
-                    Witness::Impossible
-                } else {
-                    Witness::Unavailable
-                },
-                has_sig: false,
+            false,
+        ),
+        Terminal::MultiA(_, ref keys) | Terminal::SortedMultiA(_, ref keys) => alone(
+            Stack::Size {
+                bytes: 0,
+                elems: keys.len(),
             },
-            Terminal::Older(t) => Satisfaction {
-                stack: if stfr.check_older(t) {
-                    Witness::empty()
-                } else if root_has_sig {
-                    // If the root terminal has signature, the
-                    // signature covers the nLockTime and nSequence
-                    // values. The sender of the transaction should
-                    // take care that it signs the value such that the
-                    // timelock is not met
-                    Witness::Impossible
-                } else {
-                    Witness::Unavailable
-                },
+            false,
+        ),
+    }
+}
 
-                has_sig: false,
-            },
-            Terminal::Ripemd160(h) => Satisfaction {
-                stack: Witness::ripemd160_preimage(stfr, h),
-                has_sig: false,
-            },
-            Terminal::Hash160(h) => Satisfaction {
-                stack: Witness::hash160_preimage(stfr, h),
-                has_sig: false,
-            },
-            Terminal::Sha256(h) => Satisfaction {
-                stack: Witness::sha256_preimage(stfr, h),
-                has_sig: false,
-            },
-            Terminal::Hash256(h) => Satisfaction {
-                stack: Witness::hash256_preimage(stfr, h),
-                has_sig: false,
-            },
-            Terminal::True => Satisfaction {
-                stack: Witness::empty(),
-                has_sig: false,
-            },
-            Terminal::False => Satisfaction {
-                stack: Witness::Impossible,
-                has_sig: false,
-            },
-            Terminal::Alt(ref sub)
-            | Terminal::Swap(ref sub)
-            | Terminal::Check(ref sub)
-            | Terminal::Verify(ref sub)
-            | Terminal::NonZero(ref sub)
-            | Terminal::ZeroNotEqual(ref sub) => {
-                Self::satisfy_helper(&sub.node, stfr, root_has_sig, leaf_hash, min_fn, thresh_fn)
+// Place the (dis)satisfaction of the fragment in slot `j` at `cursor`, and
+// move past it
+fn place<Pk: MiniscriptKey, Ctx: ScriptContext>(
+    slots: &mut [Slot<Pk, Ctx>],
+    j: usize,
+    sat: bool,
+    cursor: &mut (usize, usize),
+) -> Result<(), Error> {
+    let cost = if sat { slots[j].sat } else { slots[j].dissat };
+    match cost.stack {
+        Stack::Size { bytes, elems } => {
+            slots[j].layout = Some(Layout {
+                sat,
+                bytes: cursor.0,
+                elems: cursor.1,
+            });
+            *cursor = (cursor.0 + bytes, cursor.1 + elems);
+            Ok(())
+        }
+        _ => Err(Error::CouldNotSatisfy),
+    }
+}
+
+// Write the parts of the witness that fragment `i` pushes itself, and place
+// those of its children
+fn write_fragment<Pk: ToPublicKey, Ctx: ScriptContext, S: Satisfier<Pk>>(
+    slots: &mut [Slot<Pk, Ctx>],
+    i: usize,
+    stfr: &S,
+    leaf_hash: &TapLeafHash,
+    witness: &mut WitnessBuffer,
+) -> Result<(), Error> {
+    let layout = match slots[i].layout {
+        Some(layout) => layout,
+        None => return Ok(()),
+    };
+    let node = slots[i].node.expect("filled slot");
+    let first = slots[i].first_child;
+    let mut cursor = (layout.bytes, layout.elems);
+    let cursor = &mut cursor;
+
+    if layout.sat {
+        let second = slots[i].sat_second;
+        match node.node {
+            Terminal::PkK(ref pk) => {
+                let sig =
+                    signature::<_, Ctx, _>(stfr, pk, leaf_hash).ok_or(Error::CouldNotSatisfy)?;
+                witness.put(cursor, sig.as_slice())?;
             }
-            Terminal::DupIf(ref sub) => {
-                let sat = Self::satisfy_helper(
-                    &sub.node,
-                    stfr,
-                    root_has_sig,
-                    leaf_hash,
-                    min_fn,
-                    thresh_fn,
-                );
-                Satisfaction {
-                    stack: Witness::combine(sat.stack, Witness::push_1()),
-                    has_sig: sat.has_sig,
-                }
+            Terminal::PkH(ref pkh) => {
+                let (sig, pk) = pkh_signature(stfr, pkh).ok_or(Error::CouldNotSatisfy)?;
+                witness.put(cursor, sig.as_slice())?;
+                witness.put(cursor, pk.as_slice())?;
             }
-            Terminal::AndV(ref l, ref r) | Terminal::AndB(ref l, ref r) => {
-                let l_sat =
-                    Self::satisfy_helper(&l.node, stfr, root_has_sig, leaf_hash, min_fn, thresh_fn);
-                let r_sat =
-                    Self::satisfy_helper(&r.node, stfr, root_has_sig, leaf_hash, min_fn, thresh_fn);
-                Satisfaction {
-                    stack: Witness::combine(r_sat.stack, l_sat.stack),
-                    has_sig: l_sat.has_sig || r_sat.has_sig,
-                }
+            Terminal::After(..) | Terminal::Older(..) | Terminal::True => {}
+            Terminal::Sha256(..)
+            | Terminal::Hash256(..)
+            | Terminal::Ripemd160(..)
+            | Terminal::Hash160(..) => {
+                let preimage = preimage(stfr, &node.node).ok_or(Error::CouldNotSatisfy)?;
+                witness.put(cursor, &preimage)?;
             }
-            Terminal::AndOr(ref a, ref b, ref c) => {
-                let a_sat =
-                    Self::satisfy_helper(&a.node, stfr, root_has_sig, leaf_hash, min_fn, thresh_fn);
-                let a_nsat = Self::dissatisfy_helper(
-                    &a.node,
-                    stfr,
-                    root_has_sig,
-                    leaf_hash,
-                    min_fn,
-                    thresh_fn,
-                );
-                let b_sat =
-                    Self::satisfy_helper(&b.node, stfr, root_has_sig, leaf_hash, min_fn, thresh_fn);
-                let c_sat =
-                    Self::satisfy_helper(&c.node, stfr, root_has_sig, leaf_hash, min_fn, thresh_fn);
-
-                min_fn(
-                    Satisfaction {
-                        stack: Witness::combine(b_sat.stack, a_sat.stack),
-                        has_sig: a_sat.has_sig || b_sat.has_sig,
-                    },
-                    Satisfaction {
-                        stack: Witness::combine(c_sat.stack, a_nsat.stack),
-                        has_sig: a_nsat.has_sig || c_sat.has_sig,
-                    },
-                )
+            Terminal::False => return Err(Error::CouldNotSatisfy),
+            Terminal::Alt(..)
+            | Terminal::Swap(..)
+            | Terminal::Check(..)
+            | Terminal::Verify(..)
+            | Terminal::NonZero(..)
+            | Terminal::ZeroNotEqual(..) => place(slots, first, true, cursor)?,
+            Terminal::DupIf(..) => {
+                place(slots, first, true, cursor)?;
+                witness.put(cursor, &[1])?;
             }
-            Terminal::OrB(ref l, ref r) => {
-                let l_sat =
-                    Self::satisfy_helper(&l.node, stfr, root_has_sig, leaf_hash, min_fn, thresh_fn);
-                let r_sat =
-                    Self::satisfy_helper(&r.node, stfr, root_has_sig, leaf_hash, min_fn, thresh_fn);
-                let l_nsat = Self::dissatisfy_helper(
-                    &l.node,
-                    stfr,
-                    root_has_sig,
-                    leaf_hash,
-                    min_fn,
-                    thresh_fn,
-                );
-                let r_nsat = Self::dissatisfy_helper(
-                    &r.node,
-                    stfr,
-                    root_has_sig,
-                    leaf_hash,
-                    min_fn,
-                    thresh_fn,
-                );
-
-                assert!(!l_nsat.has_sig);
-                assert!(!r_nsat.has_sig);
-
-                min_fn(
-                    Satisfaction {
-                        stack: Witness::combine(r_sat.stack, l_nsat.stack),
-                        has_sig: r_sat.has_sig,
-                    },
-                    Satisfaction {
-                        stack: Witness::combine(r_nsat.stack, l_sat.stack),
-                        has_sig: l_sat.has_sig,
-                    },
-                )
+            Terminal::AndV(..) | Terminal::AndB(..) => {
+                place(slots, first + 1, true, cursor)?;
+                place(slots, first, true, cursor)?;
             }
-            Terminal::OrD(ref l, ref r) | Terminal::OrC(ref l, ref r) => {
-                let l_sat =
-                    Self::satisfy_helper(&l.node, stfr, root_has_sig, leaf_hash, min_fn, thresh_fn);
-                let r_sat =
-                    Self::satisfy_helper(&r.node, stfr, root_has_sig, leaf_hash, min_fn, thresh_fn);
-                let l_nsat = Self::dissatisfy_helper(
-                    &l.node,
-                    stfr,
-                    root_has_sig,
-                    leaf_hash,
-                    min_fn,
-                    thresh_fn,
-                );
-
-                assert!(!l_nsat.has_sig);
-
-                min_fn(
-                    l_sat,
-                    Satisfaction {
-                        stack: Witness::combine(r_sat.stack, l_nsat.stack),
-                        has_sig: r_sat.has_sig,
-                    },
-                )
+            Terminal::AndOr(..) if !second => {
+                place(slots, first + 1, true, cursor)?;
+                place(slots, first, true, cursor)?;
             }
-            Terminal::OrI(ref l, ref r) => {
-                let l_sat =
-                    Self::satisfy_helper(&l.node, stfr, root_has_sig, leaf_hash, min_fn, thresh_fn);
-                let r_sat =
-                    Self::satisfy_helper(&r.node, stfr, root_has_sig, leaf_hash, min_fn, thresh_fn);
-                min_fn(
-                    Satisfaction {
-                        stack: Witness::combine(l_sat.stack, Witness::push_1()),
-                        has_sig: l_sat.has_sig,
-                    },
-                    Satisfaction {
-                        stack: Witness::combine(r_sat.stack, Witness::push_0()),
-                        has_sig: r_sat.has_sig,
-                    },
-                )
+            Terminal::AndOr(..) => {
+                place(slots, first + 2, true, cursor)?;
+                place(slots, first, false, cursor)?;
+            }
+            Terminal::OrB(..) => {
+                place(slots, first + 1, !second, cursor)?;
+                place(slots, first, second, cursor)?;
+            }
+            Terminal::OrD(..) | Terminal::OrC(..) if !second => {
+                place(slots, first, true, cursor)?;
             }
-            Terminal::Thresh(k, ref subs) => {
-                thresh_fn(k, subs, stfr, root_has_sig, leaf_hash, min_fn)
+            Terminal::OrD(..) | Terminal::OrC(..) => {
+                place(slots, first + 1, true, cursor)?;
+                place(slots, first, false, cursor)?;
+            }
+            Terminal::OrI(..) if !second => {
+                place(slots, first, true, cursor)?;
+                witness.put(cursor, &[1])?;
+            }
+            Terminal::OrI(..) => {
+                place(slots, first + 1, true, cursor)?;
+                witness.put(cursor, &[])?;
+            }
+            Terminal::Thresh(_, ref subs) => {
+                for j in (first..first + subs.len()).rev() {
+                    let sat = slots[j].thresh_sat;
+                    place(slots, j, sat, cursor)?;
+                }
             }
             Terminal::Multi(k, ref keys) => {
-                // Collect all available signatures
-                let mut sig_count = 0;
-                let mut sigs = Vec::with_capacity(k);
+                witness.put(cursor, &[])?;
+                let mut count = 0;
                 for pk in keys {
-                    match Witness::signature::<_, _, Ctx>(stfr, pk, leaf_hash) {
-                        Witness::Stack(sig) => {
-                            sigs.push(sig);
-                            sig_count += 1;
-                        }
-                        Witness::Impossible => {}
-                        Witness::Unavailable => unreachable!(
-                            "Signature satisfaction without witness must be impossible"
-                        ),
+                    if count == k {
+                        break;
                     }
-                }
-
-                if sig_count < k {
-                    Satisfaction {
-                        stack: Witness::Impossible,
-                        has_sig: false,
-                    }
-                } else {
-                    // Throw away the most expensive ones
-                    for _ in 0..sig_count - k {
-                        let max_idx = sigs
-                            .iter()
-                            .enumerate()
-                            .max_by_key(|&(_, v)| v.len())
-                            .unwrap()
-                            .0;
-                        sigs[max_idx] = vec![];
-                    }
-
-                    Satisfaction {
-                        stack: sigs.into_iter().fold(Witness::push_0(), |acc, sig| {
-                            Witness::combine(acc, Witness::Stack(sig))
-                        }),
-                        has_sig: true,
+                    if let Some(sig) = signature::<_, Ctx, _>(stfr, pk, leaf_hash) {
+                        witness.put(cursor, sig.as_slice())?;
+                        count += 1;
                     }
                 }
             }
-            Terminal::MultiA(k, ref keys) => {
-                // Collect all available signatures
-                let mut sig_count = 0;
-                let mut sigs = vec![vec![vec![]]; keys.len()];
-                for (i, pk) in keys.iter().rev().enumerate() {
-                    match Witness::signature::<_, _, Ctx>(stfr, pk, leaf_hash) {
-                        Witness::Stack(sig) => {
-                            sigs[i] = sig;
-                            sig_count += 1;
-                            // This a privacy issue, we are only selecting the first available
-                            // sigs. Incase pk at pos 1 is not selected, we know we did not have access to it
-                            // bitcoin core also implements the same logic for MULTISIG, so I am not bothering
-                            // permuting the sigs for now
-                            if sig_count == k {
-                                break;
-                            }
+            Terminal::MultiA(k, ref keys) | Terminal::SortedMultiA(k, ref keys) => {
+                let mut count = 0;
+                let mut visited = 0;
+                let mut res = Ok(());
+                for_each_multi_a_key(keys, is_sorted(&node.node), |pk| {
+                    visited += 1;
+                    res = match signature::<_, Ctx, _>(stfr, pk, leaf_hash) {
+                        Some(sig) => {
+                            count += 1;
+                            witness.put(cursor, sig.as_slice())
                         }
-                        Witness::Impossible => {}
-                        Witness::Unavailable => unreachable!(
-                            "Signature satisfaction without witness must be impossible"
-                        ),
-                    }
+                        None => witness.put(cursor, &[]),
+                    };
+                    res.is_ok() && count < k
+                });
+                res?;
+                for _ in visited..keys.len() {
+                    witness.put(cursor, &[])?;
                 }
-
-                if sig_count < k {
-                    Satisfaction {
-                        stack: Witness::Impossible,
-                        has_sig: false,
-                    }
-                } else {
-                    Satisfaction {
-                        stack: sigs.into_iter().fold(Witness::empty(), |acc, sig| {
-                            Witness::combine(acc, Witness::Stack(sig))
-                        }),
-                        has_sig: true,
-                    }
+            }
+        }
+    } else {
+        let second = slots[i].dissat_second;
+        match node.node {
+            Terminal::PkK(..) | Terminal::DupIf(..) | Terminal::NonZero(..) => {
+                witness.put(cursor, &[])?;
+            }
+            Terminal::PkH(ref pkh) => {
+                let pk = pkh_public_key(stfr, pkh).ok_or(Error::CouldNotSatisfy)?;
+                witness.put(cursor, &[])?;
+                witness.put(cursor, pk.as_slice())?;
+            }
+            Terminal::False => {}
+            Terminal::True
+            | Terminal::Older(..)
+            | Terminal::After(..)
+            | Terminal::Verify(..)
+            | Terminal::OrC(..) => return Err(Error::CouldNotSatisfy),
+            Terminal::Sha256(..)
+            | Terminal::Hash256(..)
+            | Terminal::Ripemd160(..)
+            | Terminal::Hash160(..) => witness.put(cursor, &[0; 32])?,
+            Terminal::Alt(..)
+            | Terminal::Swap(..)
+            | Terminal::Check(..)
+            | Terminal::ZeroNotEqual(..) => place(slots, first, false, cursor)?,
+            Terminal::AndV(..) => {
+                place(slots, first + 1, false, cursor)?;
+                place(slots, first, true, cursor)?;
+            }
+            Terminal::AndB(..) | Terminal::OrB(..) | Terminal::OrD(..) => {
+                place(slots, first + 1, false, cursor)?;
+                place(slots, first, false, cursor)?;
+            }
+            Terminal::AndOr(..) => {
+                place(slots, first + 2, false, cursor)?;
+                place(slots, first, false, cursor)?;
+            }
+            Terminal::OrI(..) if !second => {
+                place(slots, first, false, cursor)?;
+                witness.put(cursor, &[1])?;
+            }
+            Terminal::OrI(..) => {
+                place(slots, first + 1, false, cursor)?;
+                witness.put(cursor, &[])?;
+            }
+            Terminal::Thresh(_, ref subs) => {
+                for j in (first..first + subs.len()).rev() {
+                    place(slots, j, false, cursor)?;
+                }
+            }
+            Terminal::Multi(k, _) => {
+                for _ in 0..k + 1 {
+                    witness.put(cursor, &[])?;
+                }
+            }
+            Terminal::MultiA(_, ref keys) | Terminal::SortedMultiA(_, ref keys) => {
+                for _ in 0..keys.len() {
+                    witness.put(cursor, &[])?;
                 }
             }
         }
     }
+    // The satisfier is queried again here, and if its answers changed since
+    // the costs were computed, e.g. a signature got shorter, the fragment no
+    // longer fits the place chosen for it
+    let cost = if layout.sat {
+        slots[i].sat
+    } else {
+        slots[i].dissat
+    };
+    match cost.stack {
+        Stack::Size { bytes, elems } if *cursor == (layout.bytes + bytes, layout.elems + elems) => {
+            Ok(())
+        }
+        _ => Err(Error::CouldNotSatisfy),
+    }
+}
+
+// Lay out `ms` in `slots` and choose the (dis)satisfaction of every fragment,
+// returning the number of slots used
+fn choose<'a, Pk: ToPublicKey, Ctx: ScriptContext, S: Satisfier<Pk>>(
+    ms: &'a Miniscript<Pk, Ctx>,
+    stfr: &S,
+    leaf_hash: &TapLeafHash,
+    mall: bool,
+    slots: &mut [Slot<'a, Pk, Ctx>],
+) -> Result<usize, Error> {
+    let len = layout_fragments(ms, slots)?;
+    let root_has_sig = ms.ty.mall.safe;
+    // Children come after their parents, so walking the slots backwards
+    // computes the children first
+    for i in (0..len).rev() {
+        let (sat, sat_second) = sat_cost(slots, i, stfr, root_has_sig, leaf_hash, mall);
+        let (dissat, dissat_second) = dissat_cost(slots, i, stfr);
+        slots[i].sat = sat;
+        slots[i].sat_second = sat_second;
+        slots[i].dissat = dissat;
+        slots[i].dissat_second = dissat_second;
+    }
+    Ok(len)
+}
+
+// Write the satisfaction chosen for the fragments laid out in `slots`
+fn write<Pk: ToPublicKey, Ctx: ScriptContext, S: Satisfier<Pk>>(
+    slots: &mut [Slot<Pk, Ctx>],
+    stfr: &S,
+    leaf_hash: &TapLeafHash,
+    witness: &mut WitnessBuffer,
+) -> Result<(), Error> {
+    let (bytes, elems) = match slots[0].sat.stack {
+        Stack::Size { bytes, elems } => (bytes, elems),
+        Stack::Unavailable | Stack::Impossible => return Err(Error::CouldNotSatisfy),
+    };
+    if bytes > witness.data.len() || elems > witness.lens.len() {
+        return Err(Error::SatisfyBufferTooSmall);
+    }
+    witness.elems = 0;
+    slots[0].layout = Some(Layout {
+        sat: true,
+        bytes: 0,
+        elems: 0,
+    });
+    // Parents come before their children, so walking the slots forwards
+    // places every fragment before writing it
+    for i in 0..slots.len() {
+        write_fragment(slots, i, stfr, leaf_hash, witness)?;
+    }
+    witness.elems = elems;
+    Ok(())
+}
+
+/// Satisfy `ms` into `witness`, using `slots` as scratch space
+#[cfg(feature = "satisfy-no-alloc")]
+pub(super) fn satisfy_into<'a, Pk, Ctx, S>(
+    ms: &'a Miniscript<Pk, Ctx>,
+    stfr: &S,
+    leaf_hash: &TapLeafHash,
+    mall: bool,
+    slots: &mut [Slot<'a, Pk, Ctx>],
+    witness: &mut WitnessBuffer,
+) -> Result<(), Error>
+where
+    Pk: MiniscriptKey + ToPublicKey,
+    Ctx: ScriptContext,
+    S: Satisfier<Pk>,
+{
+    let len = choose(ms, stfr, leaf_hash, mall, slots)?;
+    write(&mut slots[..len], stfr, leaf_hash, witness)
+}
 
-    // Helper function to produce a dissatisfaction
-    fn dissatisfy_helper<Pk, Ctx, Sat, F, G>(
-        term: &Terminal<Pk, Ctx>,
+impl Satisfaction {
+    // Satisfy `ms`, possibly malleably, into a newly allocated witness
+    fn satisfy_with<Pk, Ctx, Sat>(
+        ms: &Miniscript<Pk, Ctx>,
         stfr: &Sat,
-        root_has_sig: bool,
         leaf_hash: &TapLeafHash,
-        min_fn: &mut F,
-        thresh_fn: &mut G,
+        mall: bool,
     ) -> Self
     where
         Pk: MiniscriptKey + ToPublicKey,
         Ctx: ScriptContext,
         Sat: Satisfier<Pk>,
-        F: FnMut(Satisfaction, Satisfaction) -> Satisfaction,
-        G: FnMut(
-            usize,
-            &[Arc<Miniscript<Pk, Ctx>>],
-            &Sat,
-            bool,
-            &TapLeafHash,
-            &mut F,
-        ) -> Satisfaction,
     {
-        match *term {
-            Terminal::PkK(..) => Satisfaction {
-                stack: Witness::push_0(),
-                has_sig: false,
-            },
-            Terminal::PkH(ref pkh) => Satisfaction {
-                stack: Witness::combine(Witness::push_0(), Witness::pkh_public_key(stfr, pkh)),
-                has_sig: false,
-            },
-            Terminal::False => Satisfaction {
-                stack: Witness::empty(),
-                has_sig: false,
-            },
-            Terminal::True => Satisfaction {
-                stack: Witness::Impossible,
-                has_sig: false,
-            },
-            Terminal::Older(_) => Satisfaction {
-                stack: Witness::Impossible,
-                has_sig: false,
-            },
-            Terminal::After(_) => Satisfaction {
-                stack: Witness::Impossible,
-                has_sig: false,
-            },
-            Terminal::Sha256(_)
-            | Terminal::Hash256(_)
-            | Terminal::Ripemd160(_)
-            | Terminal::Hash160(_) => Satisfaction {
-                stack: Witness::hash_dissatisfaction(),
-                has_sig: false,
-            },
-            Terminal::Alt(ref sub)
-            | Terminal::Swap(ref sub)
-            | Terminal::Check(ref sub)
-            | Terminal::ZeroNotEqual(ref sub) => {
-                Self::dissatisfy_helper(&sub.node, stfr, root_has_sig, leaf_hash, min_fn, thresh_fn)
-            }
-            Terminal::DupIf(_) | Terminal::NonZero(_) => Satisfaction {
-                stack: Witness::push_0(),
-                has_sig: false,
-            },
-            Terminal::Verify(_) => Satisfaction {
-                stack: Witness::Impossible,
-                has_sig: false,
-            },
-            Terminal::AndV(ref v, ref other) => {
-                let vsat =
-                    Self::satisfy_helper(&v.node, stfr, root_has_sig, leaf_hash, min_fn, thresh_fn);
-                let odissat = Self::dissatisfy_helper(
-                    &other.node,
-                    stfr,
-                    root_has_sig,
-                    leaf_hash,
-                    min_fn,
-                    thresh_fn,
-                );
-                Satisfaction {
-                    stack: Witness::combine(odissat.stack, vsat.stack),
-                    has_sig: vsat.has_sig || odissat.has_sig,
-                }
-            }
-            Terminal::AndB(ref l, ref r)
-            | Terminal::OrB(ref l, ref r)
-            | Terminal::OrD(ref l, ref r)
-            | Terminal::AndOr(ref l, _, ref r) => {
-                let lnsat = Self::dissatisfy_helper(
-                    &l.node,
-                    stfr,
-                    root_has_sig,
-                    leaf_hash,
-                    min_fn,
-                    thresh_fn,
-                );
-                let rnsat = Self::dissatisfy_helper(
-                    &r.node,
-                    stfr,
-                    root_has_sig,
-                    leaf_hash,
-                    min_fn,
-                    thresh_fn,
-                );
-                Satisfaction {
-                    stack: Witness::combine(rnsat.stack, lnsat.stack),
-                    has_sig: rnsat.has_sig || lnsat.has_sig,
+        let mut slots = vec![Slot::EMPTY; ms.iter().count()];
+        let len = choose(ms, stfr, leaf_hash, mall, &mut slots).expect("one slot per fragment");
+        let root = slots[0].sat;
+        let stack = match root.stack {
+            Stack::Size { bytes, elems } => {
+                let (mut data, mut lens) = (vec![0; bytes], vec![0; elems]);
+                let mut witness = WitnessBuffer::new(&mut data, &mut lens);
+                match write(&mut slots[..len], stfr, leaf_hash, &mut witness) {
+                    Ok(()) => Witness::Stack(witness.iter().map(<[u8]>::to_vec).collect()),
+                    // The satisfier gave different answers to the two passes
+                    Err(_) => Witness::Unavailable,
                 }
             }
-            Terminal::OrC(..) => Satisfaction {
-                stack: Witness::Impossible,
-                has_sig: false,
-            },
-            Terminal::OrI(ref l, ref r) => {
-                let lnsat = Self::dissatisfy_helper(
-                    &l.node,
-                    stfr,
-                    root_has_sig,
-                    leaf_hash,
-                    min_fn,
-                    thresh_fn,
-                );
-                let dissat_1 = Satisfaction {
-                    stack: Witness::combine(lnsat.stack, Witness::push_1()),
-                    has_sig: lnsat.has_sig,
-                };
-
-                let rnsat = Self::dissatisfy_helper(
-                    &r.node,
-                    stfr,
-                    root_has_sig,
-                    leaf_hash,
-                    min_fn,
-                    thresh_fn,
-                );
-                let dissat_2 = Satisfaction {
-                    stack: Witness::combine(rnsat.stack, Witness::push_0()),
-                    has_sig: rnsat.has_sig,
-                };
-
-                // Dissatisfactions don't need to non-malleable. Use minimum_mall always
-                Satisfaction::minimum_mall(dissat_1, dissat_2)
-            }
-            Terminal::Thresh(_, ref subs) => Satisfaction {
-                stack: subs.iter().fold(Witness::empty(), |acc, sub| {
-                    let nsat = Self::dissatisfy_helper(
-                        &sub.node,
-                        stfr,
-                        root_has_sig,
-                        leaf_hash,
-                        min_fn,
-                        thresh_fn,
-                    );
-                    assert!(!nsat.has_sig);
-                    Witness::combine(nsat.stack, acc)
-                }),
-                has_sig: false,
-            },
-            Terminal::Multi(k, _) => Satisfaction {
-                stack: Witness::Stack(vec![vec![]; k + 1]),
-                has_sig: false,
-            },
-            Terminal::MultiA(_, ref pks) => Satisfaction {
-                stack: Witness::Stack(vec![vec![]; pks.len()]),
-                has_sig: false,
-            },
+            Stack::Unavailable => Witness::Unavailable,
+            Stack::Impossible => Witness::Impossible,
+        };
+        Satisfaction {
+            stack,
+            has_sig: root.has_sig,
         }
     }
 
-    /// Produce a satisfaction non-malleable satisfaction
-    pub(super) fn satisfy<
+    /// Produce a non-malleable satisfaction
+    pub(super) fn satisfy<Pk, Ctx, Sat>(
+        ms: &Miniscript<Pk, Ctx>,
+        stfr: &Sat,
+        leaf_hash: &TapLeafHash,
+    ) -> Self
+    where
         Pk: MiniscriptKey + ToPublicKey,
         Ctx: ScriptContext,
         Sat: Satisfier<Pk>,
-    >(
-        term: &Terminal<Pk, Ctx>,
-        stfr: &Sat,
-        root_has_sig: bool,
-        leaf_hash: &TapLeafHash,
-    ) -> Self {
-        Self::satisfy_helper(
-            term,
-            stfr,
-            root_has_sig,
-            leaf_hash,
-            &mut Satisfaction::minimum,
-            &mut Satisfaction::thresh,
-        )
+    {
+        Self::satisfy_with(ms, stfr, leaf_hash, false)
     }
 
     /// Produce a satisfaction(possibly malleable)
-    pub(super) fn satisfy_mall<
+    pub(super) fn satisfy_mall<Pk, Ctx, Sat>(
+        ms: &Miniscript<Pk, Ctx>,
+        stfr: &Sat,
+        leaf_hash: &TapLeafHash,
+    ) -> Self
+    where
         Pk: MiniscriptKey + ToPublicKey,
         Ctx: ScriptContext,
         Sat: Satisfier<Pk>,
-    >(
-        term: &Terminal<Pk, Ctx>,
-        stfr: &Sat,
-        root_has_sig: bool,
-        leaf_hash: &TapLeafHash,
-    ) -> Self {
-        Self::satisfy_helper(
-            term,
-            stfr,
-            root_has_sig,
-            leaf_hash,
-            &mut Satisfaction::minimum_mall,
-            &mut Satisfaction::thresh_mall,
+    {
+        Self::satisfy_with(ms, stfr, leaf_hash, true)
+    }
+}
+
+#[cfg(test)]
+mod reference;
+
+#[cfg(test)]
+mod tests {
+    use core::cell::Cell;
+    use core::str::FromStr;
+
+    use bitcoin::secp256k1::XOnlyPublicKey;
+
+    use super::*;
+    use crate::miniscript::iter::test::gen_bitcoin_pubkeys;
+    #[cfg(feature = "satisfy-no-alloc")]
+    use crate::miniscript::ScriptContextError;
+    use crate::util::{script_sig_push_len, witness_to_scriptsig};
+    use crate::{Legacy, Segwitv0, Tap};
+
+    // Signs for the keys at the given indices, with signatures of different
+    // sizes
+    struct Signers<Pk> {
+        keys: Vec<Pk>,
+        signers: Vec<usize>,
+        older: bool,
+    }
+
+    impl<Pk: ToPublicKey> Satisfier<Pk> for Signers<Pk> {
+        fn lookup_ecdsa_sig(&self, pk: &Pk) -> Option<bitcoin::EcdsaSig> {
+            let i = self.keys.iter().position(|k| k == pk)?;
+            if !self.signers.contains(&i) {
+                return None;
+            }
+            let sig = if i % 2 == 0 {
+                "3045022100a7acc3719e9559a59d60d7b2837f9842df30e7edcd754e63227e6168cec72c5d022066c2feba4671c3d99ea75d9976b4da6c86968dbf3bab47b1061e7a1966b1778c"
+            } else {
+                "3044022075b7b65a7e6cd386132c5883c9db15f9a849a0f32bc680e9986398879a57c276022056d94d12255a4424f51c700ac75122cb354895c9f2f88f0cbb47ba05c9c589ba"
+            };
+            Some(bitcoin::EcdsaSig::sighash_all(
+                bitcoin::secp256k1::ecdsa::Signature::from_str(sig).unwrap(),
+            ))
+        }
+
+        fn lookup_tap_leaf_script_sig(
+            &self,
+            pk: &Pk,
+            _: &TapLeafHash,
+        ) -> Option<bitcoin::SchnorrSig> {
+            let i = self.keys.iter().position(|k| k == pk)?;
+            if !self.signers.contains(&i) {
+                return None;
+            }
+            let hash_ty = if i % 2 == 0 {
+                bitcoin::SchnorrSighashType::Default
+            } else {
+                bitcoin::SchnorrSighashType::All
+            };
+            Some(bitcoin::SchnorrSig {
+                sig: bitcoin::secp256k1::schnorr::Signature::from_slice(&[i as u8 + 1; 64])
+                    .unwrap(),
+                hash_ty,
+            })
+        }
+
+        fn lookup_pkh_pk(&self, pkh: &Pk::Hash) -> Option<Pk> {
+            self.keys
+                .iter()
+                .find(|k| k.to_pubkeyhash() == *pkh)
+                .cloned()
+        }
+
+        fn lookup_pkh_ecdsa_sig(
+            &self,
+            pkh: &Pk::Hash,
+        ) -> Option<(bitcoin::PublicKey, bitcoin::EcdsaSig)> {
+            let pk = self.lookup_pkh_pk(pkh)?;
+            Some((pk.to_public_key(), self.lookup_ecdsa_sig(&pk)?))
+        }
+
+        fn check_older(&self, _: u32) -> bool {
+            self.older
+        }
+    }
+
+    // Whether there is a signature only matters for available witnesses
+    fn from_reference(sat: reference::Satisfaction) -> (Witness, bool) {
+        match sat.stack {
+            reference::Witness::Stack(stack) => (Witness::Stack(stack), sat.has_sig),
+            reference::Witness::Unavailable => (Witness::Unavailable, false),
+            reference::Witness::Impossible => (Witness::Impossible, false),
+        }
+    }
+
+    fn stack_and_sig(sat: Satisfaction) -> (Witness, bool) {
+        match sat.stack {
+            Witness::Stack(_) => (sat.stack, sat.has_sig),
+            _ => (sat.stack, false),
+        }
+    }
+
+    // Check that the (possibly malleable) satisfactions are the ones the
+    // recursive satisfier chose, and that satisfying into caller-provided
+    // buffers gives the same witness
+    fn check_same<Pk: ToPublicKey, Ctx: ScriptContext>(
+        ms: &Miniscript<Pk, Ctx>,
+        satisfier: &Signers<Pk>,
+    ) {
+        let leaf_hash = TapLeafHash::from_script(&ms.encode(), LeafVersion::TapScript);
+        let expected = from_reference(reference::Satisfaction::of(ms, satisfier, &leaf_hash));
+        assert_eq!(
+            stack_and_sig(Satisfaction::satisfy(ms, satisfier, &leaf_hash)),
+            expected,
+            "{} {:?} {}",
+            ms,
+            satisfier.signers,
+            satisfier.older
+        );
+        assert_eq!(
+            stack_and_sig(Satisfaction::satisfy_mall(ms, satisfier, &leaf_hash)),
+            from_reference(reference::Satisfaction::of_mall(ms, satisfier, &leaf_hash)),
+            "{} {:?} {}",
+            ms,
+            satisfier.signers,
+            satisfier.older
+        );
+
+        #[cfg(feature = "satisfy-no-alloc")]
+        {
+            let mut slots = [Slot::EMPTY; 32];
+            let mut data = [0; 1024];
+            let mut lens = [0; 32];
+            let mut witness = WitnessBuffer::new(&mut data, &mut lens);
+            let res = ms.satisfy_no_alloc(satisfier, &leaf_hash, &mut slots, &mut witness);
+            match expected.0 {
+                Witness::Stack(expected) => {
+                    assert_eq!(res, Ok(()), "{} {:?}", ms, satisfier.signers);
+                    let elems: Vec<Vec<u8>> = witness.iter().map(|e| e.to_vec()).collect();
+                    assert_eq!(elems, expected, "{} {:?}", ms, satisfier.signers);
+                }
+                _ => assert_eq!(
+                    res,
+                    Err(Error::CouldNotSatisfy),
+                    "{} {:?}",
+                    ms,
+                    satisfier.signers
+                ),
+            }
+        }
+    }
+
+    // Gives a shorter signature each time it is asked for one
+    struct Shrinking {
+        key: bitcoin::PublicKey,
+        calls: Cell<usize>,
+    }
+
+    impl Satisfier<bitcoin::PublicKey> for Shrinking {
+        fn lookup_ecdsa_sig(&self, pk: &bitcoin::PublicKey) -> Option<bitcoin::EcdsaSig> {
+            if *pk != self.key {
+                return None;
+            }
+            let sig = if self.calls.get() == 0 {
+                "3045022100a7acc3719e9559a59d60d7b2837f9842df30e7edcd754e63227e6168cec72c5d022066c2feba4671c3d99ea75d9976b4da6c86968dbf3bab47b1061e7a1966b1778c"
+            } else {
+                "3044022075b7b65a7e6cd386132c5883c9db15f9a849a0f32bc680e9986398879a57c276022056d94d12255a4424f51c700ac75122cb354895c9f2f88f0cbb47ba05c9c589ba"
+            };
+            self.calls.set(self.calls.get() + 1);
+            Some(bitcoin::EcdsaSig::sighash_all(
+                bitcoin::secp256k1::ecdsa::Signature::from_str(sig).unwrap(),
+            ))
+        }
+    }
+
+    #[test]
+    fn script_sig_size() {
+        let witness: Vec<Vec<u8>> = vec![
+            vec![],
+            vec![0],
+            vec![1],
+            vec![16],
+            vec![17],
+            vec![0x81],
+            vec![0x80],
+            vec![1, 0],
+            vec![0xff, 0x7f],
+            vec![0xff, 0xff, 0xff, 0x7f],
+            vec![0xff, 0xff, 0xff, 0xff],
+            vec![0xff; 5],
+            vec![3; 75],
+            vec![3; 76],
+            vec![3; 255],
+            vec![3; 256],
+        ];
+        for elem in &witness {
+            assert_eq!(
+                script_sig_push_len(elem),
+                witness_to_scriptsig(core::slice::from_ref(elem)).len(),
+                "{:?}",
+                elem
+            );
+        }
+        let size: usize = witness.iter().map(|e| script_sig_push_len(e)).sum();
+        assert_eq!(size, witness_to_scriptsig(&witness).len());
+
+        assert!(Legacy::check_witness::<bitcoin::PublicKey>(&[vec![3; 1000]]).is_ok());
+        assert!(Legacy::check_witness::<bitcoin::PublicKey>(&vec![vec![3; 1000]; 2]).is_err());
+    }
+
+    #[test]
+    fn satisfy_malleable() {
+        // Either timelock satisfies it, so a third party can choose
+        let ms = Miniscript::<bitcoin::PublicKey, Segwitv0>::from_str_insane(
+            "or_i(older(10),older(20))",
         )
+        .unwrap();
+        assert_eq!(ms.satisfy(Older(20)), Err(Error::CouldNotSatisfy));
+        assert_eq!(ms.satisfy_malleable(Older(20)), Ok(vec![vec![]]));
+        assert_eq!(ms.satisfy_malleable(Older(10)), Ok(vec![vec![1]]));
+    }
+
+    #[test]
+    fn satisfy_segwitv0() {
+        let keys = gen_bitcoin_pubkeys(5, true);
+        let subsets: &[&[usize]] = &[
+            &[],
+            &[0],
+            &[1],
+            &[2],
+            &[4],
+            &[0, 1],
+            &[1, 2],
+            &[0, 2],
+            &[0, 1, 2, 3],
+        ];
+        for policy in &[
+            "and_v(v:pk(K0),pk(K1))",
+            "or_d(pk(K0),and_v(v:pk(K1),older(10)))",
+            "or_b(pk(K0),s:pk(K1))",
+            "or_i(pk(K0),and_v(v:pk(K1),pk(K2)))",
+            "andor(pk(K0),pk(K1),or_i(pk(K2),pk(K3)))",
+            "thresh(2,pk(K0),s:pk(K1),s:pk(K2),sln:older(10))",
+            "multi(2,K0,K1,K2,K3)",
+            "or_d(multi(1,K0,K1),or_b(pk(K2),s:pk(K3)))",
+            "c:or_i(pk_k(K0),pk_h(H4))",
+            "t:or_c(pk(K0),v:pk(K1))",
+            "or_d(c:or_i(pk_k(K0),pk_h(H4)),pk(K2))",
+            "or_b(c:or_i(pk_k(K3),pk_k(K1)),ac:or_i(pk_k(K0),pk_k(K2)))",
+            "thresh(1,c:or_i(pk_k(K0),pk_k(K2)),ac:or_i(pk_k(K1),pk_k(K3)))",
+            "or_i(and_v(v:pk(K0),older(10)),or_i(pk(K1),multi(1,K1,K2)))",
+            // Both dissatisfactions of the `or_i` have the same size
+            "or_d(or_i(sha256(1111111111111111111111111111111111111111111111111111111111111111),c:pk_h(H4)),pk(K2))",
+        ] {
+            let mut s = policy.to_string();
+            for (i, key) in keys.iter().enumerate() {
+                s = s.replace(&format!("K{}", i), &key.to_string());
+                s = s.replace(&format!("H{}", i), &key.to_pubkeyhash().to_string());
+            }
+            let ms = Miniscript::<bitcoin::PublicKey, Segwitv0>::from_str_insane(&s).unwrap();
+            for signers in subsets {
+                for &older in &[false, true] {
+                    let satisfier = Signers {
+                        keys: keys.clone(),
+                        signers: signers.to_vec(),
+                        older,
+                    };
+                    check_same(&ms, &satisfier);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn satisfy_changing_satisfier() {
+        // The signature computed for the first pass no longer fits once it
+        // shrinks in the second
+        let keys = gen_bitcoin_pubkeys(2, true);
+        for policy in &[
+            format!("pk({})", keys[0]),
+            format!("multi(1,{},{})", keys[1], keys[0]),
+            format!("and_v(v:pk({}),pk({}))", keys[0], keys[0]),
+        ] {
+            let ms = Miniscript::<bitcoin::PublicKey, Segwitv0>::from_str_insane(policy).unwrap();
+            let satisfier = Shrinking {
+                key: keys[0],
+                calls: Cell::new(0),
+            };
+            assert_eq!(ms.satisfy(&satisfier), Err(Error::CouldNotSatisfy));
+            #[cfg(feature = "satisfy-no-alloc")]
+            {
+                satisfier.calls.set(0);
+                let leaf_hash = TapLeafHash::from_script(&ms.encode(), LeafVersion::TapScript);
+                let mut slots = [Slot::EMPTY; 8];
+                let (mut data, mut lens) = ([0; 1024], [0; 8]);
+                let mut witness = WitnessBuffer::new(&mut data, &mut lens);
+                assert_eq!(
+                    ms.satisfy_no_alloc(&satisfier, &leaf_hash, &mut slots, &mut witness),
+                    Err(Error::CouldNotSatisfy)
+                );
+            }
+        }
+    }
+
+    #[cfg(feature = "satisfy-no-alloc")]
+    #[test]
+    fn satisfy_no_alloc_buffers() {
+        // Buffers too small for the Miniscript or its witness
+        let keys = gen_bitcoin_pubkeys(2, true);
+        let ms = Miniscript::<bitcoin::PublicKey, Segwitv0>::from_str(&format!(
+            "and_v(v:pk({}),pk({}))",
+            keys[0], keys[1]
+        ))
+        .unwrap();
+        let satisfier = Signers {
+            keys: keys.clone(),
+            signers: vec![0, 1],
+            older: false,
+        };
+        let leaf_hash = TapLeafHash::from_script(&ms.encode(), LeafVersion::TapScript);
+        let (mut data, mut lens) = ([0; 1024], [0; 4]);
+        let mut witness = WitnessBuffer::new(&mut data, &mut lens);
+        assert_eq!(ms.iter().count(), 6);
+        let mut slots = [Slot::EMPTY; 5];
+        assert_eq!(
+            ms.satisfy_no_alloc(&satisfier, &leaf_hash, &mut slots, &mut witness),
+            Err(Error::SatisfyBufferTooSmall)
+        );
+        let mut slots = [Slot::EMPTY; 6];
+        assert_eq!(
+            ms.satisfy_no_alloc(&satisfier, &leaf_hash, &mut slots, &mut witness),
+            Ok(())
+        );
+        assert_eq!(witness.len(), 2);
+        let (mut data, mut lens) = ([0; 100], [0; 4]);
+        let mut witness = WitnessBuffer::new(&mut data, &mut lens);
+        assert_eq!(
+            ms.satisfy_no_alloc(&satisfier, &leaf_hash, &mut slots, &mut witness),
+            Err(Error::SatisfyBufferTooSmall)
+        );
+    }
+
+    #[cfg(feature = "satisfy-no-alloc")]
+    #[test]
+    fn satisfy_no_alloc_limits() {
+        // The dissatisfactions of the other `multi`s exceed the 100 elements
+        // of a standard P2WSH witness
+        let keys = gen_bitcoin_pubkeys(51, true);
+        let mut s = format!("thresh(1,multi(1,{})", keys[0]);
+        for key in &keys[1..] {
+            s.push_str(&format!(",a:multi(1,{})", key));
+        }
+        s.push(')');
+        let ms = Miniscript::<bitcoin::PublicKey, Segwitv0>::from_str_insane(&s).unwrap();
+        let satisfier = Signers {
+            keys: keys.clone(),
+            signers: vec![0],
+            older: false,
+        };
+        let expected = ms.satisfy(&satisfier);
+        assert_eq!(
+            expected,
+            Err(Error::ContextError(
+                ScriptContextError::MaxWitnessItemssExceeded {
+                    actual: 102,
+                    limit: 100,
+                }
+            ))
+        );
+
+        let leaf_hash = TapLeafHash::from_script(&ms.encode(), LeafVersion::TapScript);
+        let mut slots = vec![Slot::EMPTY; ms.iter().count()];
+        let (mut data, mut lens) = ([0; 1024], [0; 128]);
+        let mut witness = WitnessBuffer::new(&mut data, &mut lens);
+        assert_eq!(
+            ms.satisfy_no_alloc(&satisfier, &leaf_hash, &mut slots, &mut witness),
+            expected.map(|_| ())
+        );
+    }
+
+    #[test]
+    fn satisfy_tap() {
+        let keys: Vec<XOnlyPublicKey> = gen_bitcoin_pubkeys(4, true)
+            .into_iter()
+            .map(|pk| XOnlyPublicKey::from(pk.inner))
+            .collect();
+        let subsets: &[&[usize]] = &[&[0], &[1, 3], &[0, 2], &[0, 1, 2], &[0, 1, 2, 3]];
+        for policy in &[
+            "multi_a(2,K0,K1,K2,K3)",
+            "sortedmulti_a(2,K0,K1,K2,K3)",
+            "or_d(pk(K0),sortedmulti_a(2,K1,K2,K3))",
+            "and_v(v:pk(K0),multi_a(1,K1,K2,K3))",
+        ] {
+            let mut s = policy.to_string();
+            for (i, key) in keys.iter().enumerate() {
+                s = s.replace(&format!("K{}", i), &key.to_string());
+            }
+            let ms = Miniscript::<XOnlyPublicKey, Tap>::from_str(&s).unwrap();
+            for signers in subsets {
+                let satisfier = Signers {
+                    keys: keys.clone(),
+                    signers: signers.to_vec(),
+                    older: false,
+                };
+                check_same(&ms, &satisfier);
+            }
+        }
     }
 }
@@ -911,7 +911,9 @@ impl Property for ExtData {
             Terminal::False => Ok(Self::from_false()),
             Terminal::PkK(..) => Ok(Self::from_pk_k::<Ctx>()),
             Terminal::PkH(..) => Ok(Self::from_pk_h::<Ctx>()),
-            Terminal::Multi(k, ref pks) | Terminal::MultiA(k, ref pks) => {
+            Terminal::Multi(k, ref pks)
+            | Terminal::MultiA(k, ref pks)
+            | Terminal::SortedMultiA(k, ref pks) => {
                 if k == 0 {
                     return Err(Error {
                         fragment: fragment.clone(),
@@ -926,7 +928,9 @@ impl Property for ExtData {
                 }
                 match *fragment {
                     Terminal::Multi(..) => Ok(Self::from_multi(k, pks.len())),
-                    Terminal::MultiA(..) => Ok(Self::from_multi_a(k, pks.len())),
+                    Terminal::MultiA(..) | Terminal::SortedMultiA(..) => {
+                        Ok(Self::from_multi_a(k, pks.len()))
+                    }
                     _ => unreachable!(),
                 }
             }
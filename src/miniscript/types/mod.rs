@@ -217,7 +217,7 @@ impl<Pk: MiniscriptKey, Ctx: ScriptContext> fmt::Display for Error<Pk, Ctx> {
 
 #[cfg(feature = "std")]
 impl<Pk: MiniscriptKey, Ctx: ScriptContext> error::Error for Error<Pk, Ctx> {
-    fn cause(&self) -> Option<&dyn error::Error> {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
         None
     }
 }
@@ -414,7 +414,9 @@ pub trait Property: Sized {
             Terminal::False => Ok(Self::from_false()),
             Terminal::PkK(..) => Ok(Self::from_pk_k::<Ctx>()),
             Terminal::PkH(..) => Ok(Self::from_pk_h::<Ctx>()),
-            Terminal::Multi(k, ref pks) | Terminal::MultiA(k, ref pks) => {
+            Terminal::Multi(k, ref pks)
+            | Terminal::MultiA(k, ref pks)
+            | Terminal::SortedMultiA(k, ref pks) => {
                 if k == 0 {
                     return Err(Error {
                         fragment: fragment.clone(),
@@ -429,7 +431,9 @@ pub trait Property: Sized {
                 }
                 match *fragment {
                     Terminal::Multi(..) => Ok(Self::from_multi(k, pks.len())),
-                    Terminal::MultiA(..) => Ok(Self::from_multi_a(k, pks.len())),
+                    Terminal::MultiA(..) | Terminal::SortedMultiA(..) => {
+                        Ok(Self::from_multi_a(k, pks.len()))
+                    }
                     _ => unreachable!(),
                 }
             }
@@ -797,7 +801,9 @@ impl Property for Type {
             Terminal::False => Ok(Self::from_false()),
             Terminal::PkK(..) => Ok(Self::from_pk_k::<Ctx>()),
             Terminal::PkH(..) => Ok(Self::from_pk_h::<Ctx>()),
-            Terminal::Multi(k, ref pks) | Terminal::MultiA(k, ref pks) => {
+            Terminal::Multi(k, ref pks)
+            | Terminal::MultiA(k, ref pks)
+            | Terminal::SortedMultiA(k, ref pks) => {
                 if k == 0 {
                     return Err(Error {
                         fragment: fragment.clone(),
@@ -812,7 +818,9 @@ impl Property for Type {
                 }
                 match *fragment {
                     Terminal::Multi(..) => Ok(Self::from_multi(k, pks.len())),
-                    Terminal::MultiA(..) => Ok(Self::from_multi_a(k, pks.len())),
+                    Terminal::MultiA(..) | Terminal::SortedMultiA(..) => {
+                        Ok(Self::from_multi_a(k, pks.len()))
+                    }
                     _ => unreachable!(),
                 }
             }
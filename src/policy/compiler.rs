@@ -33,9 +33,141 @@ use crate::policy::Concrete;
 use crate::prelude::*;
 use crate::{policy, Miniscript, MiniscriptKey, Terminal};
 
-type PolicyCache<Pk, Ctx> =
+type Compilations<Pk, Ctx> =
     BTreeMap<(Concrete<Pk>, OrdF64, Option<OrdF64>), BTreeMap<CompilationKey, AstElemExt<Pk, Ctx>>>;
 
+/// Best compilations of every sub-policy compiled so far, for the options
+/// of the current compilation
+struct PolicyCache<Pk: MiniscriptKey, Ctx: ScriptContext> {
+    options: CompilerOptions,
+    compilations: Compilations<Pk, Ctx>,
+}
+
+impl<Pk: MiniscriptKey, Ctx: ScriptContext> PolicyCache<Pk, Ctx> {
+    fn new(options: CompilerOptions) -> Self {
+        PolicyCache {
+            options,
+            compilations: BTreeMap::new(),
+        }
+    }
+}
+
+/// The satisfaction cost minimized by the compiler
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum SatisfactionCost {
+    /// The expected satisfaction cost, weighting every branch by the
+    /// probabilities given in the policy
+    Average,
+    /// The satisfaction cost of the most expensive spend path
+    WorstCase,
+}
+
+impl Default for SatisfactionCost {
+    fn default() -> Self {
+        SatisfactionCost::Average
+    }
+}
+
+/// A frozen version of the compiler, for [`Concrete::compile_deterministic`]
+///
+/// A version fixes the candidate compilations considered for every fragment
+/// of the policy, the costs computed for them, and the order in which
+/// compilations are ranked: by cost, then by script size, then by the string
+/// serialization which sorts first. The output of a version for given
+/// options therefore never changes between releases of this library, so
+/// that the scripts of a wallet storing only its policy can be regenerated.
+/// Improvements to the compiler which change its output are only made
+/// available under a new version, and by compiling without a version.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Hash)]
+pub enum CompilerVersion {
+    /// The first frozen version, introduced with
+    /// [`Concrete::compile_deterministic`].
+    ///
+    /// It considers the same candidates, with the same costs, as
+    /// [`Concrete::compile`] in that release.
+    V1,
+    /// The compiler of [`CompilerVersion::V1`] which also compiles the
+    /// thresholds of at most four subs which are not all keys as trees of
+    /// `and`s and `or`s where these are cheaper, breaking ties the same way.
+    V2,
+}
+
+impl CompilerVersion {
+    // The version whose candidates and costs are used when compiling without
+    // a version. Changes to either must be made under a new version, enabled
+    // as for `expands_thresholds`, so that the output of older versions does
+    // not change.
+    const LATEST: CompilerVersion = CompilerVersion::V2;
+
+    // Whether small thresholds of mixed subs are also compiled as trees of
+    // `and`s and `or`s
+    fn expands_thresholds(self) -> bool {
+        self >= CompilerVersion::V2
+    }
+}
+
+/// Options for [`Concrete::compile_with_options`]
+///
+/// Compilations exceeding the size or opcode budget are discarded while
+/// compiling, along with all compilations built from them. The default options
+/// set no budget and minimize the average satisfaction cost, as done by
+/// [`Concrete::compile`].
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub struct CompilerOptions {
+    /// Maximum size of the script, in bytes
+    pub max_script_size: Option<usize>,
+    /// Maximum number of opcodes counted towards `MAX_OPS_PER_SCRIPT` executed
+    /// by any satisfaction
+    pub max_ops_count: Option<usize>,
+    /// The satisfaction cost to minimize
+    pub cost: SatisfactionCost,
+    /// The frozen compiler version to use. If `None`, the compiler may pick
+    /// different compilations of equal cost in future releases.
+    pub version: Option<CompilerVersion>,
+}
+
+impl CompilerOptions {
+    // Whether the compiled fragment fits the script size and opcode budget. Both
+    // only grow when the fragment is used in a larger compilation.
+    fn fits<Pk: MiniscriptKey, Ctx: ScriptContext>(&self, ms: &Miniscript<Pk, Ctx>) -> bool {
+        let size_fits = self
+            .max_script_size
+            .map_or(true, |max| ms.ext.pk_cost <= max);
+        let ops_fit = match (self.max_ops_count, ms.ext.ops.op_count()) {
+            (Some(max), Some(count)) => count <= max,
+            _ => true,
+        };
+        size_fits && ops_fit
+    }
+
+    // The version of the candidates and costs of the compilation
+    fn version(&self) -> CompilerVersion {
+        self.version.unwrap_or(CompilerVersion::LATEST)
+    }
+}
+
+/// Compare two compilations by the cost minimized under `options`, breaking
+/// ties as documented by the compiler version, if any. Returns `None` if
+/// either cost is NaN.
+fn cmp_compilations<Pk: MiniscriptKey, Ctx: ScriptContext>(
+    options: &CompilerOptions,
+    (a, a_cost): (&AstElemExt<Pk, Ctx>, f64),
+    (b, b_cost): (&AstElemExt<Pk, Ctx>, f64),
+) -> Option<cmp::Ordering> {
+    let ord = a_cost.partial_cmp(&b_cost)?;
+    Some(match options.version {
+        None => ord,
+        // Must keep ranking the compilations of older versions as when they
+        // were released
+        Some(CompilerVersion::V1) | Some(CompilerVersion::V2) => ord
+            .then(a.ms.ext.pk_cost.cmp(&b.ms.ext.pk_cost))
+            .then_with(|| a.ms.to_string().cmp(&b.ms.to_string())),
+    })
+}
+
+/// Largest threshold also compiled as the trees of `thresh_expansions`
+const MAX_THRESH_EXPANSION: usize = 4;
+
 ///Ordered f64 for comparison
 #[derive(Copy, Clone, PartialEq, PartialOrd, Debug)]
 pub(crate) struct OrdF64(pub f64);
@@ -60,6 +192,11 @@ pub enum CompilerError {
     /// There may exist other miniscripts which are under these limits but the
     /// compiler currently does not find them.
     LimitsExceeded,
+    /// No compilation of the policy fits within the script size and opcode
+    /// budget of the given [`CompilerOptions`].
+    /// There may exist other miniscripts which fit the budget but the
+    /// compiler currently does not find them.
+    NoCompilationFits(CompilerOptions),
     ///Policy related errors
     PolicyError(policy::concrete::PolicyError),
 }
@@ -76,6 +213,16 @@ impl fmt::Display for CompilerError {
             CompilerError::LimitsExceeded => f.write_str(
                 "At least one spending path has exceeded the standardness or consensus limits",
             ),
+            CompilerError::NoCompilationFits(ref options) => write!(
+                f,
+                "No compilation fits within {} bytes and {} opcodes",
+                options
+                    .max_script_size
+                    .map_or("unlimited".to_owned(), |s| s.to_string()),
+                options
+                    .max_ops_count
+                    .map_or("unlimited".to_owned(), |s| s.to_string()),
+            ),
             CompilerError::PolicyError(ref e) => fmt::Display::fmt(e, f),
         }
     }
@@ -83,11 +230,14 @@ impl fmt::Display for CompilerError {
 
 #[cfg(feature = "std")]
 impl error::Error for CompilerError {
-    fn cause(&self) -> Option<&dyn error::Error> {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
         use self::CompilerError::*;
 
         match self {
-            TopLevelNonSafe | ImpossibleNonMalleableCompilation | LimitsExceeded => None,
+            TopLevelNonSafe
+            | ImpossibleNonMalleableCompilation
+            | LimitsExceeded
+            | NoCompilationFits(_) => None,
             PolicyError(e) => Some(e),
         }
     }
@@ -481,6 +631,28 @@ impl<Pk: MiniscriptKey, Ctx: ScriptContext> AstElemExt<Pk, Ctx> {
                 (None, None) => 0.0,
             }
     }
+
+    /// Compute the cost minimized under the given `options`, given a
+    /// probability of satisfaction and a probability of dissatisfaction
+    fn cost(&self, options: &CompilerOptions, sat_prob: f64, dissat_prob: Option<f64>) -> f64 {
+        match options.cost {
+            SatisfactionCost::Average => self.cost_1d(sat_prob, dissat_prob),
+            SatisfactionCost::WorstCase => {
+                // Like the average cost, in segwit format
+                let sat_cost = self
+                    .ms
+                    .ext
+                    .max_sat_size
+                    .map_or(f64::INFINITY, |(wit, _)| wit as f64);
+                let dissat_cost = match (dissat_prob, self.ms.ext.max_dissat_size) {
+                    (Some(_), Some((wit, _))) => wit as f64,
+                    (Some(_), None) => f64::INFINITY,
+                    (None, _) => 0.0,
+                };
+                self.ms.ext.pk_cost as f64 + sat_cost.max(dissat_cost)
+            }
+        }
+    }
 }
 
 impl<Pk: MiniscriptKey, Ctx: ScriptContext> AstElemExt<Pk, Ctx> {
@@ -664,6 +836,7 @@ fn all_casts<Pk: MiniscriptKey, Ctx: ScriptContext>() -> [Cast<Pk, Ctx>; 10] {
 /// In general, we maintain the invariant that if anything is inserted into the
 /// map, it's cast closure must also be considered for best compilations.
 fn insert_elem<Pk: MiniscriptKey, Ctx: ScriptContext>(
+    options: &CompilerOptions,
     map: &mut BTreeMap<CompilationKey, AstElemExt<Pk, Ctx>>,
     elem: AstElemExt<Pk, Ctx>,
     sat_prob: f64,
@@ -675,11 +848,11 @@ fn insert_elem<Pk: MiniscriptKey, Ctx: ScriptContext>(
         return false;
     }
 
-    if Ctx::check_local_validity(&elem.ms).is_err() {
+    if Ctx::check_local_validity(&elem.ms).is_err() || !options.fits(&elem.ms) {
         return false;
     }
 
-    let elem_cost = elem.cost_1d(sat_prob, dissat_prob);
+    let elem_cost = elem.cost(options, sat_prob, dissat_prob);
 
     let elem_key = CompilationKey::from_type(elem.ms.ty, elem.ms.ext.has_free_verify, dissat_prob);
 
@@ -689,8 +862,14 @@ fn insert_elem<Pk: MiniscriptKey, Ctx: ScriptContext>(
     let is_worse = map
         .iter()
         .map(|(existing_key, existing_elem)| {
-            let existing_elem_cost = existing_elem.cost_1d(sat_prob, dissat_prob);
-            existing_key.is_subtype(elem_key) && existing_elem_cost <= elem_cost
+            let existing_elem_cost = existing_elem.cost(options, sat_prob, dissat_prob);
+            existing_key.is_subtype(elem_key)
+                && cmp_compilations(
+                    options,
+                    (existing_elem, existing_elem_cost),
+                    (&elem, elem_cost),
+                )
+                .map_or(false, |ord| ord != cmp::Ordering::Greater)
         })
         .any(|x| x);
     if !is_worse {
@@ -699,8 +878,14 @@ fn insert_elem<Pk: MiniscriptKey, Ctx: ScriptContext>(
         *map = mem::take(map)
             .into_iter()
             .filter(|&(ref existing_key, ref existing_elem)| {
-                let existing_elem_cost = existing_elem.cost_1d(sat_prob, dissat_prob);
-                !(elem_key.is_subtype(*existing_key) && existing_elem_cost >= elem_cost)
+                let existing_elem_cost = existing_elem.cost(options, sat_prob, dissat_prob);
+                !(elem_key.is_subtype(*existing_key)
+                    && cmp_compilations(
+                        options,
+                        (existing_elem, existing_elem_cost),
+                        (&elem, elem_cost),
+                    )
+                    .map_or(false, |ord| ord != cmp::Ordering::Less))
             })
             .collect();
         map.insert(elem_key, elem);
@@ -717,13 +902,14 @@ fn insert_elem<Pk: MiniscriptKey, Ctx: ScriptContext>(
 /// all map is smallest possible closure of all compilations of a policy with
 /// given sat and dissat probabilities.
 fn insert_elem_closure<Pk: MiniscriptKey, Ctx: ScriptContext>(
+    options: &CompilerOptions,
     map: &mut BTreeMap<CompilationKey, AstElemExt<Pk, Ctx>>,
     astelem_ext: AstElemExt<Pk, Ctx>,
     sat_prob: f64,
     dissat_prob: Option<f64>,
 ) {
     let mut cast_stack: VecDeque<AstElemExt<Pk, Ctx>> = VecDeque::new();
-    if insert_elem(map, astelem_ext.clone(), sat_prob, dissat_prob) {
+    if insert_elem(options, map, astelem_ext.clone(), sat_prob, dissat_prob) {
         cast_stack.push_back(astelem_ext);
     }
 
@@ -733,7 +919,7 @@ fn insert_elem_closure<Pk: MiniscriptKey, Ctx: ScriptContext>(
 
         for c in &casts {
             if let Ok(new_ext) = c.cast(&current) {
-                if insert_elem(map, new_ext.clone(), sat_prob, dissat_prob) {
+                if insert_elem(options, map, new_ext.clone(), sat_prob, dissat_prob) {
                     cast_stack.push_back(new_ext);
                 }
             }
@@ -758,7 +944,8 @@ fn insert_best_wrapped<Pk: MiniscriptKey, Ctx: ScriptContext>(
     sat_prob: f64,
     dissat_prob: Option<f64>,
 ) -> Result<(), CompilerError> {
-    insert_elem_closure(map, data, sat_prob, dissat_prob);
+    let options = policy_cache.options;
+    insert_elem_closure(&options, map, data, sat_prob, dissat_prob);
 
     if dissat_prob.is_some() {
         let casts: [Cast<Pk, Ctx>; 10] = all_casts::<Pk, Ctx>();
@@ -766,7 +953,7 @@ fn insert_best_wrapped<Pk: MiniscriptKey, Ctx: ScriptContext>(
         for c in &casts {
             for x in best_compilations(policy_cache, policy, sat_prob, None)?.values() {
                 if let Ok(new_ext) = c.cast(x) {
-                    insert_elem_closure(map, new_ext, sat_prob, dissat_prob);
+                    insert_elem_closure(&options, map, new_ext, sat_prob, dissat_prob);
                 }
             }
         }
@@ -789,7 +976,11 @@ where
     //Check the cache for hits
     let ord_sat_prob = OrdF64(sat_prob);
     let ord_dissat_prob = dissat_prob.map(OrdF64);
-    if let Some(ret) = policy_cache.get(&(policy.clone(), ord_sat_prob, ord_dissat_prob)) {
+    if let Some(ret) =
+        policy_cache
+            .compilations
+            .get(&(policy.clone(), ord_sat_prob, ord_dissat_prob))
+    {
         return Ok(ret.clone());
     }
 
@@ -845,10 +1036,18 @@ where
         }
         Concrete::After(n) => insert_wrap!(AstElemExt::terminal(Terminal::After(n))),
         Concrete::Older(n) => insert_wrap!(AstElemExt::terminal(Terminal::Older(n))),
-        Concrete::Sha256(hash) => insert_wrap!(AstElemExt::terminal(Terminal::Sha256(hash))),
-        Concrete::Hash256(hash) => insert_wrap!(AstElemExt::terminal(Terminal::Hash256(hash))),
-        Concrete::Ripemd160(hash) => insert_wrap!(AstElemExt::terminal(Terminal::Ripemd160(hash))),
-        Concrete::Hash160(hash) => insert_wrap!(AstElemExt::terminal(Terminal::Hash160(hash))),
+        Concrete::Sha256(ref hash) => {
+            insert_wrap!(AstElemExt::terminal(Terminal::Sha256(hash.clone())))
+        }
+        Concrete::Hash256(ref hash) => {
+            insert_wrap!(AstElemExt::terminal(Terminal::Hash256(hash.clone())))
+        }
+        Concrete::Ripemd160(ref hash) => {
+            insert_wrap!(AstElemExt::terminal(Terminal::Ripemd160(hash.clone())))
+        }
+        Concrete::Hash160(ref hash) => {
+            insert_wrap!(AstElemExt::terminal(Terminal::Hash160(hash.clone())))
+        }
         Concrete::And(ref subs) => {
             assert_eq!(subs.len(), 2, "and takes 2 args");
             let mut left = best_compilations(policy_cache, &subs[0], sat_prob, dissat_prob)?;
@@ -873,7 +1072,12 @@ where
             compile_tern!(&mut right, &mut q_zero_left, &mut zero_comp, [1.0, 0.0]);
         }
         Concrete::Or(ref subs) => {
-            let total = (subs[0].0 + subs[1].0) as f64;
+            let total = subs[0]
+                .0
+                .checked_add(subs[1].0)
+                .ok_or(CompilerError::PolicyError(
+                    policy::concrete::PolicyError::OddsOverflow,
+                ))? as f64;
             let lw = subs[0].0 as f64 / total;
             let rw = subs[1].0 as f64 / total;
 
@@ -979,7 +1183,8 @@ where
                 let be = best(types::Base::B, policy_cache, ast, sp, dp)?;
                 let bw = best(types::Base::W, policy_cache, ast, sp, dp)?;
 
-                let diff = be.cost_1d(sp, dp) - bw.cost_1d(sp, dp);
+                let options = &policy_cache.options;
+                let diff = be.cost(options, sp, dp) - bw.cost(options, sp, dp);
                 best_es.push((be.comp_ext_data, be));
                 best_ws.push((bw.comp_ext_data, bw));
 
@@ -1018,6 +1223,7 @@ where
                     }
                 })
                 .collect();
+            let all_keys = key_vec.len() == n;
 
             match Ctx::sig_type() {
                 SigType::Schnorr if key_vec.len() == subs.len() => {
@@ -1038,7 +1244,24 @@ where
                 _ => {}
             }
 
-            // FIXME: Should we also optimize thresh(1, subs) ?
+            // Small thresholds of mixed subs may be cheaper as trees of `and`s
+            // and `or`s
+            if policy_cache.options.version().expands_thresholds()
+                && !all_keys
+                && n <= MAX_THRESH_EXPANSION
+            {
+                for policy in thresh_expansions(k, subs) {
+                    match best_compilations(policy_cache, &policy, sat_prob, dissat_prob) {
+                        Ok(compilations) => {
+                            for (_, ast_ext) in compilations {
+                                insert_wrap!(ast_ext);
+                            }
+                        }
+                        Err(CompilerError::LimitsExceeded) => {}
+                        Err(e) => return Err(e),
+                    }
+                }
+            }
         }
     }
     for k in ret.keys() {
@@ -1053,11 +1276,54 @@ where
         // before calling this compile function
         Err(CompilerError::LimitsExceeded)
     } else {
-        policy_cache.insert((policy.clone(), ord_sat_prob, ord_dissat_prob), ret.clone());
+        policy_cache
+            .compilations
+            .insert((policy.clone(), ord_sat_prob, ord_dissat_prob), ret.clone());
         Ok(ret)
     }
 }
 
+/// Policies equivalent to `thresh(k, subs)` which may compile to smaller
+/// scripts than `thresh` or `multi`.
+///
+/// The threshold is expanded on each of its subs `s` as `or(and(s, thresh(k-1,
+/// rest)), thresh(k, rest))`, which is `and(s, thresh(k-1, rest))` for `k = n`
+/// and `or(s, thresh(1, rest))` for `k = 1`. Otherwise this repeats the other
+/// subs, so it is only done if they have no keys, as repeated keys would make
+/// the compilation insane.
+fn thresh_expansions<Pk: MiniscriptKey>(k: usize, subs: &[Concrete<Pk>]) -> Vec<Concrete<Pk>> {
+    let n = subs.len();
+    if n < 2 {
+        return vec![];
+    }
+    (0..n)
+        .filter_map(|i| {
+            let mut rest = subs.to_vec();
+            let sub = rest.remove(i);
+            if k == n {
+                return Some(Concrete::And(vec![sub, threshold(k - 1, rest)]));
+            }
+            if k == 1 {
+                return Some(Concrete::Or(vec![(1, sub), (n - 1, threshold(1, rest))]));
+            }
+            if rest.iter().any(|sub| !sub.keys().is_empty()) {
+                return None;
+            }
+            let and = Concrete::And(vec![sub, threshold(k - 1, rest.clone())]);
+            Some(Concrete::Or(vec![(k, and), (n - k, threshold(k, rest))]))
+        })
+        .collect()
+}
+
+// `thresh(k, subs)`, or its only sub
+fn threshold<Pk: MiniscriptKey>(k: usize, mut subs: Vec<Concrete<Pk>>) -> Concrete<Pk> {
+    if subs.len() == 1 {
+        subs.pop().expect("one sub")
+    } else {
+        Concrete::Threshold(k, subs)
+    }
+}
+
 /// Helper function to compile different types of binary fragments.
 /// `sat_prob` and `dissat_prob` represent the sat and dissat probabilities of
 /// root or. `weights` represent the odds for taking each sub branch
@@ -1129,8 +1395,37 @@ fn compile_tern<Pk: MiniscriptKey, Ctx: ScriptContext>(
 pub fn best_compilation<Pk: MiniscriptKey, Ctx: ScriptContext>(
     policy: &Concrete<Pk>,
 ) -> Result<Miniscript<Pk, Ctx>, CompilerError> {
-    let mut policy_cache = PolicyCache::<Pk, Ctx>::new();
-    let x = &*best_t(&mut policy_cache, policy, 1.0, None)?.ms;
+    best_compilation_with_options(policy, CompilerOptions::default())
+}
+
+/// Obtain the best compilation of for p=1.0 and q=0 under the given `options`
+pub fn best_compilation_with_options<Pk: MiniscriptKey, Ctx: ScriptContext>(
+    policy: &Concrete<Pk>,
+    options: CompilerOptions,
+) -> Result<Miniscript<Pk, Ctx>, CompilerError> {
+    let mut policy_cache = PolicyCache::<Pk, Ctx>::new(options);
+    let x = match best_t(&mut policy_cache, policy, 1.0, None) {
+        Err(CompilerError::LimitsExceeded)
+            if options.max_script_size.is_some() || options.max_ops_count.is_some() =>
+        {
+            // Only blame the options if the policy compiles without them, and
+            // not when it exceeds the limits of the context anyway
+            let unbounded = CompilerOptions {
+                max_script_size: None,
+                max_ops_count: None,
+                ..options
+            };
+            best_t(
+                &mut PolicyCache::<Pk, Ctx>::new(unbounded),
+                policy,
+                1.0,
+                None,
+            )?;
+            return Err(CompilerError::NoCompilationFits(options));
+        }
+        res => res?,
+    };
+    let x = &*x.ms;
     if !x.ty.mall.safe {
         Err(CompilerError::TopLevelNonSafe)
     } else if !x.ty.mall.non_malleable {
@@ -1156,8 +1451,13 @@ where
         .filter(|&(key, _)| {
             key.ty.corr.base == types::Base::B && key.dissat_prob == dissat_prob.map(OrdF64)
         })
-        .map(|(_, val)| val)
-        .min_by_key(|ext| OrdF64(ext.cost_1d(sat_prob, dissat_prob)))
+        .map(|(_, val)| {
+            let cost = val.cost(&policy_cache.options, sat_prob, dissat_prob);
+            (val, cost)
+        })
+        // will panic if given NaN
+        .min_by(|a, b| cmp_compilations(&policy_cache.options, (&a.0, a.1), (&b.0, b.1)).unwrap())
+        .map(|(val, _)| val)
         .ok_or(CompilerError::LimitsExceeded)
 }
 
@@ -1181,8 +1481,13 @@ where
                 && val.ms.ty.mall.dissat == types::Dissat::Unique
                 && key.dissat_prob == dissat_prob.map(OrdF64)
         })
-        .map(|(_, val)| val)
-        .min_by_key(|ext| OrdF64(ext.cost_1d(sat_prob, dissat_prob)))
+        .map(|(_, val)| {
+            let cost = val.cost(&policy_cache.options, sat_prob, dissat_prob);
+            (val, cost)
+        })
+        // will panic if given NaN
+        .min_by(|a, b| cmp_compilations(&policy_cache.options, (&a.0, a.1), (&b.0, b.1)).unwrap())
+        .map(|(val, _)| val)
         .ok_or(CompilerError::LimitsExceeded)
 }
 
@@ -1290,8 +1595,13 @@ mod tests {
     #[test]
     fn compile_q() {
         let policy = SPolicy::from_str("or(1@and(pk(A),pk(B)),127@pk(C))").expect("parsing");
-        let compilation: DummyTapAstElemExt =
-            best_t(&mut BTreeMap::new(), &policy, 1.0, None).unwrap();
+        let compilation: DummyTapAstElemExt = best_t(
+            &mut PolicyCache::new(CompilerOptions::default()),
+            &policy,
+            1.0,
+            None,
+        )
+        .unwrap();
 
         assert_eq!(compilation.cost_1d(1.0, None), 87.0 + 67.0390625);
         assert_eq!(
@@ -1303,8 +1613,13 @@ mod tests {
         let policy = SPolicy::from_str(
                 "and(and(and(or(127@thresh(2,pk(A),pk(B),thresh(2,or(127@pk(A),1@pk(B)),after(100),or(and(pk(C),after(200)),and(pk(D),sha256(66687aadf862bd776c8fc18b8e9f8e20089714856ee233b3902a591d0d5f2925))),pk(E))),1@pk(F)),sha256(66687aadf862bd776c8fc18b8e9f8e20089714856ee233b3902a591d0d5f2925)),or(127@pk(G),1@after(300))),or(127@after(400),pk(H)))"
             ).expect("parsing");
-        let compilation: DummyTapAstElemExt =
-            best_t(&mut BTreeMap::new(), &policy, 1.0, None).unwrap();
+        let compilation: DummyTapAstElemExt = best_t(
+            &mut PolicyCache::new(CompilerOptions::default()),
+            &policy,
+            1.0,
+            None,
+        )
+        .unwrap();
 
         assert_eq!(compilation.cost_1d(1.0, None), 433.0 + 275.7909749348958);
         assert_eq!(
@@ -1585,6 +1900,276 @@ mod tests {
         );
     }
 
+    #[test]
+    fn compile_with_options() {
+        // The worst case spends the unlikely branch, where `pk` is cheaper than `pkh`
+        let policy = SPolicy::from_str("or(99@pk(A),1@and(pk(B),older(144)))").unwrap();
+        let ms: Miniscript<String, Segwitv0> = policy.compile().unwrap();
+        assert_eq!(ms.to_string(), "or_d(pk(A),and_v(v:pkh(B),older(144)))");
+        let ms: Miniscript<String, Segwitv0> = policy
+            .compile_with_options(CompilerOptions {
+                cost: SatisfactionCost::WorstCase,
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(ms.to_string(), "or_d(pk(A),and_v(v:pk(B),older(144)))");
+
+        let policy = SPolicy::from_str("thresh(2,pk(A),pk(B),pk(C),pk(D))").unwrap();
+        let ms: Miniscript<String, Segwitv0> = policy.compile().unwrap();
+        let (size, ops) = (ms.script_size(), ms.ext.ops.op_count().unwrap());
+        let fits = CompilerOptions {
+            max_script_size: Some(size),
+            max_ops_count: Some(ops),
+            ..Default::default()
+        };
+        assert_eq!(policy.compile_with_options(fits), Ok(ms));
+
+        let too_small = CompilerOptions {
+            max_script_size: Some(size - 1),
+            ..Default::default()
+        };
+        assert_eq!(
+            policy.compile_with_options::<Segwitv0>(too_small),
+            Err(CompilerError::NoCompilationFits(too_small))
+        );
+        let too_few_ops = CompilerOptions {
+            max_ops_count: Some(ops - 1),
+            ..Default::default()
+        };
+        assert_eq!(
+            policy.compile_with_options::<Segwitv0>(too_few_ops),
+            Err(CompilerError::NoCompilationFits(too_few_ops))
+        );
+
+        // A policy exceeding the limits of the context is reported as such
+        let (keys, _) = pubkeys_and_a_sig(100);
+        let keys = keys.iter().map(|pubkey| Concrete::Key(*pubkey)).collect();
+        let policy = Concrete::Threshold(100, keys);
+        let big = CompilerOptions {
+            max_script_size: Some(100_000),
+            ..Default::default()
+        };
+        assert_eq!(
+            policy.compile_with_options::<Segwitv0>(big),
+            Err(CompilerError::LimitsExceeded)
+        );
+    }
+
+    #[test]
+    fn compile_deterministic() {
+        // These outputs are frozen: changing any of them changes the addresses
+        // of wallets relying on `CompilerVersion::V1`
+        let v1 = CompilerVersion::V1;
+        let hash = "1111111111111111111111111111111111111111111111111111111111111111";
+        let cases = [
+            ("pk(A)", "pk(A)", "pk(A)"),
+            (
+                "or(pk(A),pk(B))",
+                "or_b(pk(A),s:pk(B))",
+                "or_b(pk(A),s:pk(B))",
+            ),
+            (
+                "and(pk(A),or(pk(B),older(1000)))",
+                "and_v(or_c(pk(B),v:older(1000)),pk(A))",
+                "and_v(or_c(pk(B),v:older(1000)),pk(A))",
+            ),
+            (
+                "or(99@pk(A),1@and(pk(B),older(144)))",
+                "or_d(pk(A),and_v(v:pkh(B),older(144)))",
+                "or_d(pk(A),and_v(v:pkh(B),older(144)))",
+            ),
+            (
+                "thresh(2,pk(A),pk(B),pk(C))",
+                "multi(2,A,B,C)",
+                "multi_a(2,A,B,C)",
+            ),
+            (
+                "or(and(pk(A),pk(B)),and(pk(C),after(500000)))",
+                "andor(pk(A),pk(B),and_v(v:pk(C),after(500000)))",
+                "andor(pk(A),pk(B),and_v(v:pk(C),after(500000)))",
+            ),
+            // Both `or_i` orders cost the same, the tie is broken on the string
+            (
+                "or(3@and(pk(A),pk(B)),1@or(pk(C),and(pk(D),older(10))))",
+                "c:andor(pk(A),pk_k(B),or_i(and_v(v:older(10),pk_h(D)),pk_h(C)))",
+                "c:andor(pk(A),pk_k(B),or_i(and_v(v:older(10),pk_h(D)),pk_h(C)))",
+            ),
+            (
+                "and(pk(C),or(pk(A),after(100)))",
+                "and_v(or_c(pk(A),v:after(100)),pk(C))",
+                "and_v(or_c(pk(A),v:after(100)),pk(C))",
+            ),
+            (
+                "thresh(2,pk(A),pk(B),pk(C),pk(D),pk(E))",
+                "multi(2,A,B,C,D,E)",
+                "multi_a(2,A,B,C,D,E)",
+            ),
+            (
+                "or(9@and(pk(A),older(4032)),1@thresh(2,pk(B),pk(C),pk(D)))",
+                "andor(pk(A),older(4032),thresh(2,pkh(B),a:pkh(C),a:pkh(D)))",
+                "andor(pk(A),older(4032),thresh(2,pkh(B),a:pkh(C),a:pkh(D)))",
+            ),
+            (
+                "thresh(3,pk(A),older(144),after(500000),pk(B))",
+                "thresh(3,pk(A),sln:older(144),sln:after(500000),s:pk(B))",
+                "thresh(3,pk(A),sln:older(144),sln:after(500000),s:pk(B))",
+            ),
+            // Thresholds of mixed subs are not expanded into `and`s and `or`s
+            (
+                "thresh(1,pk(A),and(pk(B),older(144)))",
+                "thresh(1,pk(A),s:and_n(pk(B),n:older(144)))",
+                "thresh(1,pk(A),s:and_n(pk(B),n:older(144)))",
+            ),
+            (
+                "thresh(1,pk(A),pk(B),and(pk(C),older(144)))",
+                "thresh(1,pk(A),s:pk(B),s:and_n(pk(C),n:older(144)))",
+                "thresh(1,pk(A),s:pk(B),s:and_n(pk(C),n:older(144)))",
+            ),
+        ];
+        for &(policy, segwit, tap) in cases.iter() {
+            let policy = SPolicy::from_str(policy).unwrap();
+            let ms: Miniscript<String, Segwitv0> = policy.compile_deterministic(v1).unwrap();
+            assert_eq!(ms.to_string(), segwit);
+            let ms: Miniscript<String, Tap> = policy.compile_deterministic(v1).unwrap();
+            assert_eq!(ms.to_string(), tap);
+        }
+
+        let policy =
+            SPolicy::from_str(&format!("thresh(3,pk(A),pk(B),pk(C),sha256({}))", hash)).unwrap();
+        let ms: Miniscript<String, Segwitv0> = policy.compile_deterministic(v1).unwrap();
+        assert_eq!(
+            ms.to_string(),
+            format!("thresh(3,pk(A),s:pk(B),s:pk(C),sjtv:sha256({}))", hash)
+        );
+        let policy = SPolicy::from_str(&format!("or(pk(A),and(pk(B),sha256({})))", hash)).unwrap();
+        let expected = format!("c:or_i(and_v(v:sha256({}),pk_k(B)),pk_k(A))", hash);
+        let ms: Miniscript<String, Segwitv0> = policy.compile_deterministic(v1).unwrap();
+        assert_eq!(ms.to_string(), expected);
+        let ms: Miniscript<String, Tap> = policy.compile_deterministic(v1).unwrap();
+        assert_eq!(ms.to_string(), expected);
+    }
+
+    // The cost of the best compilation of `policy`, if any
+    fn best_cost<Ctx: ScriptContext>(
+        policy_cache: &mut PolicyCache<String, Ctx>,
+        policy: &SPolicy,
+    ) -> Option<f64> {
+        let options = policy_cache.options;
+        best_t(policy_cache, policy, 1.0, None)
+            .ok()
+            .map(|ast_ext| ast_ext.cost(&options, 1.0, None))
+    }
+
+    // Every policy equivalent to `thresh(k, subs)` made of `and`s, `or`s and
+    // smaller thresholds, which repeats no key
+    fn thresh_equivalents(k: usize, subs: &[SPolicy]) -> Vec<SPolicy> {
+        let n = subs.len();
+        if n == 1 {
+            return vec![subs[0].clone()];
+        }
+        let mut ret = vec![Concrete::Threshold(k, subs.to_vec())];
+        for i in 0..n {
+            let mut rest = subs.to_vec();
+            let sub = rest.remove(i);
+            if k == n {
+                for and in thresh_equivalents(k - 1, &rest) {
+                    ret.push(Concrete::And(vec![sub.clone(), and]));
+                }
+            } else if k == 1 {
+                for or in thresh_equivalents(1, &rest) {
+                    ret.push(Concrete::Or(vec![(1, sub.clone()), (n - 1, or)]));
+                }
+            } else if rest.iter().all(|sub| sub.keys().is_empty()) {
+                for and in thresh_equivalents(k - 1, &rest) {
+                    for or in thresh_equivalents(k, &rest) {
+                        let and = Concrete::And(vec![sub.clone(), and.clone()]);
+                        ret.push(Concrete::Or(vec![(k, and), (n - k, or)]));
+                    }
+                }
+            }
+        }
+        ret
+    }
+
+    #[test]
+    fn compile_thresh_expansions() {
+        let hash = "1111111111111111111111111111111111111111111111111111111111111111";
+        let subs = [
+            "pk(A)".to_owned(),
+            "pk(B)".to_owned(),
+            "older(144)".to_owned(),
+            "after(500000)".to_owned(),
+            format!("sha256({})", hash),
+        ];
+        let options = CompilerOptions {
+            cost: SatisfactionCost::WorstCase,
+            ..Default::default()
+        };
+        let mut segwit_cache = PolicyCache::<String, Segwitv0>::new(options);
+        let mut tap_cache = PolicyCache::<String, Tap>::new(options);
+        // Compare with every equivalent policy over up to 3 mixed subs
+        for a in 0..subs.len() {
+            for b in a + 1..subs.len() {
+                for c in b + 1..=subs.len() {
+                    let mut thresh_subs = vec![&subs[a], &subs[b]];
+                    thresh_subs.extend(subs.get(c));
+                    let thresh_subs: Vec<SPolicy> = thresh_subs
+                        .into_iter()
+                        .map(|s| SPolicy::from_str(s).unwrap())
+                        .collect();
+                    // Thresholds of keys only are compiled to `multi`
+                    if thresh_subs.iter().all(|sub| match *sub {
+                        Concrete::Key(_) => true,
+                        _ => false,
+                    }) {
+                        continue;
+                    }
+                    for k in 1..=thresh_subs.len() {
+                        let policy = Concrete::Threshold(k, thresh_subs.clone());
+                        let segwit = best_cost(&mut segwit_cache, &policy);
+                        let tap = best_cost(&mut tap_cache, &policy);
+                        for equivalent in thresh_equivalents(k, &thresh_subs) {
+                            let cost = best_cost(&mut segwit_cache, &equivalent);
+                            assert!(cost.map_or(true, |cost| segwit.map_or(false, |s| s <= cost)));
+                            let cost = best_cost(&mut tap_cache, &equivalent);
+                            assert!(cost.map_or(true, |cost| tap.map_or(false, |t| t <= cost)));
+                        }
+                    }
+                }
+            }
+        }
+
+        // `thresh` is no longer used where `and`s and `or`s are smaller, unless
+        // compiling with the first version
+        let cases = [
+            (
+                "thresh(1,pk(A),and(pk(B),older(144)))",
+                "thresh(1,pk(A),s:and_n(pk(B),n:older(144)))",
+                "andor(pk(B),older(144),pk(A))",
+            ),
+            // The keys are gathered in a `multi`
+            (
+                "thresh(1,pk(A),pk(B),and(pk(C),older(144)))",
+                "thresh(1,pk(A),s:pk(B),s:and_n(pk(C),n:older(144)))",
+                "andor(pk(C),older(144),multi(1,A,B))",
+            ),
+        ];
+        for &(policy, v1, v2) in cases.iter() {
+            let policy = SPolicy::from_str(policy).unwrap();
+            let ms_v1: Miniscript<String, Segwitv0> =
+                policy.compile_deterministic(CompilerVersion::V1).unwrap();
+            let ms_v2: Miniscript<String, Segwitv0> =
+                policy.compile_deterministic(CompilerVersion::V2).unwrap();
+            assert_eq!(ms_v1.to_string(), v1);
+            assert_eq!(ms_v2.to_string(), v2);
+            assert!(ms_v2.script_size() + 6 <= ms_v1.script_size());
+            assert_eq!(
+                policy.lift().unwrap().sorted(),
+                ms_v2.lift().unwrap().sorted()
+            );
+        }
+    }
+
     #[test]
     fn compile_tr_thresh() {
         for k in 1..4 {
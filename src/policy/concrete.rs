@@ -15,22 +15,25 @@
 //! Concrete Policies
 //!
 
+use core::str::FromStr;
 use core::{fmt, str};
 #[cfg(feature = "std")]
 use std::error;
 
-use bitcoin::hashes::hex::FromHex;
-use bitcoin::hashes::{hash160, ripemd160, sha256, sha256d};
 #[cfg(feature = "compiler")]
 use {
-    crate::descriptor::TapTree,
+    crate::descriptor::{TapTree, Tr},
     crate::miniscript::ScriptContext,
     crate::policy::compiler::CompilerError,
+    crate::policy::compiler::CompilerOptions,
+    crate::policy::compiler::CompilerVersion,
     crate::policy::compiler::OrdF64,
     crate::policy::{compiler, Concrete, Liftable, Semantic},
     crate::Descriptor,
     crate::Miniscript,
     crate::Tap,
+    crate::Terminal,
+    crate::{BareCtx, Legacy, Segwitv0},
     core::cmp::Reverse,
     sync::Arc,
 };
@@ -40,7 +43,7 @@ use crate::expression::{self, FromTree};
 use crate::miniscript::limits::{LOCKTIME_THRESHOLD, SEQUENCE_LOCKTIME_TYPE_FLAG};
 use crate::miniscript::types::extra_props::TimelockInfo;
 use crate::prelude::*;
-use crate::{errstr, Error, ForEach, ForEachKey, MiniscriptKey};
+use crate::{errstr, Error, ForEach, ForEachKey, MiniscriptKey, Translator};
 
 /// Concrete policy which corresponds directly to a Miniscript structure,
 /// and whose disjunctions are annotated with satisfaction probabilities
@@ -58,22 +61,40 @@ pub enum Policy<Pk: MiniscriptKey> {
     /// A relative locktime restriction
     Older(u32),
     /// A SHA256 whose preimage must be provided to satisfy the descriptor
-    Sha256(sha256::Hash),
+    Sha256(Pk::Sha256),
     /// A SHA256d whose preimage must be provided to satisfy the descriptor
-    Hash256(sha256d::Hash),
+    Hash256(Pk::Hash256),
     /// A RIPEMD160 whose preimage must be provided to satisfy the descriptor
-    Ripemd160(ripemd160::Hash),
+    Ripemd160(Pk::Ripemd160),
     /// A HASH160 whose preimage must be provided to satisfy the descriptor
-    Hash160(hash160::Hash),
+    Hash160(Pk::Hash160),
     /// A list of sub-policies, all of which must be satisfied
     And(Vec<Policy<Pk>>),
     /// A list of sub-policies, one of which must be satisfied, along with
-    /// relative probabilities for each one
+    /// relative probabilities for each one, written `or(n@A,m@B)` with odds
+    /// defaulting to 1 when omitted. Odds must be positive.
     Or(Vec<(usize, Policy<Pk>)>),
     /// A set of descriptors, satisfactions must be provided for `k` of them
     Threshold(usize, Vec<Policy<Pk>>),
 }
 
+/// The descriptor a policy is compiled into by [`Policy::compile_to_descriptor`]
+#[cfg(feature = "compiler")]
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub enum DescriptorCtx<Pk> {
+    /// A bare descriptor
+    Bare,
+    /// A `sh` descriptor
+    Sh,
+    /// A `wsh` descriptor
+    Wsh,
+    /// A `sh(wsh)` descriptor
+    ShWsh,
+    /// A `tr` descriptor, with the unspendable key used as internal key when
+    /// no key of the policy can be, as in [`Policy::compile_tr`]
+    Tr(Option<Pk>),
+}
+
 /// Detailed Error type for Policies
 #[derive(Copy, Clone, PartialEq, Eq, Debug)]
 pub enum PolicyError {
@@ -98,6 +119,12 @@ pub enum PolicyError {
     HeightTimelockCombination,
     /// Duplicate Public Keys
     DuplicatePubKeys,
+    /// More minimal satisfying sets than the given maximum
+    MaxSatisfyingSets(usize),
+    /// `Or` fragments can only have odds `n > 0`
+    ZeroOdds,
+    /// The odds of an `Or` fragment add up to more than `usize::MAX`
+    OddsOverflow,
 }
 
 impl fmt::Display for PolicyError {
@@ -129,13 +156,20 @@ impl fmt::Display for PolicyError {
                 f.write_str("Cannot lift policies that have a heightlock and timelock combination")
             }
             PolicyError::DuplicatePubKeys => f.write_str("Policy contains duplicate keys"),
+            PolicyError::MaxSatisfyingSets(max) => write!(
+                f,
+                "Policy has more than {} minimal satisfying sets of keys",
+                max
+            ),
+            PolicyError::ZeroOdds => f.write_str("Odds must be greater than 0; n > 0"),
+            PolicyError::OddsOverflow => f.write_str("Sum of the odds of an or overflowed"),
         }
     }
 }
 
 #[cfg(feature = "std")]
 impl error::Error for PolicyError {
-    fn cause(&self) -> Option<&dyn error::Error> {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
         use self::PolicyError::*;
 
         match self {
@@ -148,7 +182,10 @@ impl error::Error for PolicyError {
             | InsufficientArgsforOr
             | EntailmentMaxTerminals
             | HeightTimelockCombination
-            | DuplicatePubKeys => None,
+            | DuplicatePubKeys
+            | MaxSatisfyingSets(_)
+            | ZeroOdds
+            | OddsOverflow => None,
         }
     }
 }
@@ -194,17 +231,35 @@ impl<Pk: MiniscriptKey> Policy<Pk> {
         }
     }
 
-    /// Compile [`Policy::Or`] and [`Policy::Threshold`] according to odds
+    /// Compile [`Policy::Or`] and [`Policy::Threshold`] according to odds,
+    /// scaled by the weight of each leaf in `weights`. Unsatisfiable leaves,
+    /// such as those left by extracting an internal key which is reused, are
+    /// dropped; returns `None` if no leaf is left.
     #[cfg(feature = "compiler")]
-    fn compile_tr_policy(&self) -> Result<TapTree<Pk>, Error> {
+    fn compile_tr_policy(
+        &self,
+        weights: &BTreeMap<Policy<Pk>, usize>,
+    ) -> Result<Option<TapTree<Pk>>, Error> {
         let leaf_compilations: Vec<_> = self
             .to_tapleaf_prob_vec(1.0)
             .into_iter()
-            .filter(|x| x.1 != Policy::Unsatisfiable)
-            .map(|(prob, ref policy)| (OrdF64(prob), compiler::best_compilation(policy).unwrap()))
+            .filter(|x| match x.1.lift() {
+                Ok(semantic) => semantic.normalized() != Semantic::Unsatisfiable,
+                Err(_) => true,
+            })
+            .map(|(prob, ref policy)| {
+                let weight = weights.get(policy).map_or(1, |w| *w);
+                (
+                    OrdF64(prob * weight as f64),
+                    compiler::best_compilation(policy).unwrap(),
+                )
+            })
             .collect();
+        if leaf_compilations.is_empty() {
+            return Ok(None);
+        }
         let taptree = with_huffman_tree::<Pk>(leaf_compilations).unwrap();
-        Ok(taptree)
+        Ok(Some(taptree))
     }
 
     /// Extract the internal_key from policy tree.
@@ -262,9 +317,66 @@ impl<Pk: MiniscriptKey> Policy<Pk> {
     /// the respective miniscripts. A Huffman Tree is created from this vector which optimizes over
     /// the probabilitity of satisfaction for the respective branch in the TapTree.
     // TODO: We might require other compile errors for Taproot.
+    ///
+    /// Keys may not be reused anywhere in the policy, and the unspendable key
+    /// may not appear in the policy; see [`Policy::compile_tr_with_key_reuse`]
+    /// to allow key reuse across leaves.
     #[cfg(feature = "compiler")]
     pub fn compile_tr(&self, unspendable_key: Option<Pk>) -> Result<Descriptor<Pk>, Error> {
-        self.is_valid()?; // Check for validity
+        self.compile_tr_with_weights(unspendable_key, &BTreeMap::new())
+    }
+
+    /// Compile the [`Policy`] into a [`Tr`][`Descriptor::Tr`] Descriptor, like
+    /// [`Policy::compile_tr`], but allowing the same key to be used by several
+    /// leaves of the TapTree.
+    ///
+    /// Keys may be reused across the branches of the root-level disjunctions
+    /// which are compiled into separate leaves, and the internal key may also
+    /// appear in leaves, as reported by [`Tr::reuses_internal_key`]. Keys may
+    /// still not be reused within a single leaf.
+    ///
+    /// [`Tr::reuses_internal_key`]: crate::descriptor::Tr::reuses_internal_key
+    #[cfg(feature = "compiler")]
+    pub fn compile_tr_with_key_reuse(
+        &self,
+        unspendable_key: Option<Pk>,
+    ) -> Result<Descriptor<Pk>, Error> {
+        self.compile_tr_helper(unspendable_key, &BTreeMap::new(), true)
+    }
+
+    /// Compile the [`Policy`] into a [`Tr`][`Descriptor::Tr`] Descriptor, like
+    /// [`Policy::compile_tr`], but with additional per-branch weights.
+    ///
+    /// The probability of every leaf of the flattened policy vector, as derived
+    /// from the odds in the policy, is multiplied by the weight given for that
+    /// leaf's policy in `weights`; leaves that do not appear in `weights` have
+    /// weight 1. Heavier branches end up closer to the root of the TapTree. This
+    /// allows influencing branches which carry no odds in the policy, such as
+    /// those of a [`Thresh`][`Policy::Threshold`](1, ..).
+    #[cfg(feature = "compiler")]
+    pub fn compile_tr_with_weights(
+        &self,
+        unspendable_key: Option<Pk>,
+        weights: &BTreeMap<Policy<Pk>, usize>,
+    ) -> Result<Descriptor<Pk>, Error> {
+        self.compile_tr_helper(unspendable_key, weights, false)
+    }
+
+    #[cfg(feature = "compiler")]
+    fn compile_tr_helper(
+        &self,
+        unspendable_key: Option<Pk>,
+        weights: &BTreeMap<Policy<Pk>, usize>,
+        allow_key_reuse: bool,
+    ) -> Result<Descriptor<Pk>, Error> {
+        // Check for validity
+        if allow_key_reuse {
+            self.check_timelocks()?;
+            self.check_duplicate_keys_per_branch()?;
+            self.check_structure()?;
+        } else {
+            self.is_valid()?;
+        }
         match self.is_safe_nonmalleable() {
             (false, _) => Err(Error::from(CompilerError::TopLevelNonSafe)),
             (_, false) => Err(Error::from(
@@ -272,14 +384,17 @@ impl<Pk: MiniscriptKey> Policy<Pk> {
             )),
             _ => {
                 let (internal_key, policy) = self.clone().extract_key(unspendable_key)?;
-                let tree = Descriptor::new_tr(
+                let tree = Tr::new(
                     internal_key,
                     match policy {
                         Policy::Trivial => None,
-                        policy => Some(policy.compile_tr_policy()?),
+                        policy => policy.compile_tr_policy(weights)?,
                     },
                 )?;
-                Ok(tree)
+                if !allow_key_reuse && tree.reuses_internal_key() {
+                    return Err(Error::from(PolicyError::DuplicatePubKeys));
+                }
+                Ok(Descriptor::Tr(tree))
             }
         }
     }
@@ -287,17 +402,165 @@ impl<Pk: MiniscriptKey> Policy<Pk> {
     /// Compile the descriptor into an optimized `Miniscript` representation
     #[cfg(feature = "compiler")]
     pub fn compile<Ctx: ScriptContext>(&self) -> Result<Miniscript<Pk, Ctx>, CompilerError> {
+        self.compile_with_options(CompilerOptions::default())
+    }
+
+    /// Compile the descriptor into the best `Miniscript` representation which
+    /// fits the script size and opcode budget of `options`, minimizing the
+    /// satisfaction cost chosen in `options`.
+    ///
+    /// Returns [`CompilerError::NoCompilationFits`] if the compiler finds no
+    /// compilation within the budget.
+    #[cfg(feature = "compiler")]
+    pub fn compile_with_options<Ctx: ScriptContext>(
+        &self,
+        options: CompilerOptions,
+    ) -> Result<Miniscript<Pk, Ctx>, CompilerError> {
         self.is_valid()?;
         match self.is_safe_nonmalleable() {
             (false, _) => Err(CompilerError::TopLevelNonSafe),
             (_, false) => Err(CompilerError::ImpossibleNonMalleableCompilation),
-            _ => compiler::best_compilation(self),
+            _ => compiler::best_compilation_with_options(self, options),
+        }
+    }
+
+    /// Compile the policy into the same `Miniscript` representation as
+    /// every other release of this library using compiler `version`.
+    ///
+    /// Wallets which only store their policy should compile it this way, as
+    /// [`Policy::compile`] may pick a different compilation of equal cost
+    /// after an upgrade, changing the wallet addresses.
+    #[cfg(feature = "compiler")]
+    pub fn compile_deterministic<Ctx: ScriptContext>(
+        &self,
+        version: CompilerVersion,
+    ) -> Result<Miniscript<Pk, Ctx>, CompilerError> {
+        self.compile_with_options(CompilerOptions {
+            version: Some(version),
+            ..Default::default()
+        })
+    }
+
+    /// Compile the policy into a descriptor of the kind given by `desc_ctx`.
+    ///
+    /// A policy which is a threshold of plain keys, such as
+    /// `thresh(2,pk(A),pk(B),pk(C))`, is compiled into a `sortedmulti`
+    /// descriptor, or a tr descriptor with a single `sortedmulti_a` leaf,
+    /// whenever its script is no larger than that of the best Miniscript
+    /// compilation. Any other policy is compiled with [`Policy::compile`], or
+    /// [`Policy::compile_tr`] for [`DescriptorCtx::Tr`].
+    #[cfg(feature = "compiler")]
+    pub fn compile_to_descriptor(
+        &self,
+        desc_ctx: DescriptorCtx<Pk>,
+    ) -> Result<Descriptor<Pk>, Error> {
+        match desc_ctx {
+            DescriptorCtx::Bare => Descriptor::new_bare(self.compile::<BareCtx>()?),
+            DescriptorCtx::Sh => {
+                let ms = self.compile::<Legacy>()?;
+                match self.sortedmulti_keys(&ms) {
+                    Some((k, pks)) => Descriptor::new_sh_sortedmulti(k, pks),
+                    None => Descriptor::new_sh(ms),
+                }
+            }
+            DescriptorCtx::Wsh => {
+                let ms = self.compile::<Segwitv0>()?;
+                match self.sortedmulti_keys(&ms) {
+                    Some((k, pks)) => Descriptor::new_wsh_sortedmulti(k, pks),
+                    None => Descriptor::new_wsh(ms),
+                }
+            }
+            DescriptorCtx::ShWsh => {
+                let ms = self.compile::<Segwitv0>()?;
+                match self.sortedmulti_keys(&ms) {
+                    Some((k, pks)) => Descriptor::new_sh_wsh_sortedmulti(k, pks),
+                    None => Descriptor::new_sh_wsh(ms),
+                }
+            }
+            DescriptorCtx::Tr(unspendable_key) => {
+                let desc = self.compile_tr(unspendable_key)?;
+                let tr = match desc {
+                    Descriptor::Tr(ref tr) => tr,
+                    _ => unreachable!("compile_tr returns tr descriptors"),
+                };
+                // A threshold of at least two keys has no internal key in
+                // the policy, so the whole of it is compiled into one leaf
+                let sorted = match (self.key_threshold(), tr.taptree()) {
+                    (Some((k, pks)), &Some(TapTree::Leaf(ref leaf))) if k > 1 => {
+                        let ms = Miniscript::<Pk, Tap>::from_ast(Terminal::SortedMultiA(k, pks))?;
+                        if ms.script_size() <= leaf.script_size() {
+                            Some(ms)
+                        } else {
+                            None
+                        }
+                    }
+                    _ => None,
+                };
+                match sorted {
+                    Some(ms) => Ok(Descriptor::Tr(Tr::new(
+                        tr.internal_key().clone(),
+                        Some(TapTree::Leaf(Arc::new(ms))),
+                    )?)),
+                    None => Ok(desc),
+                }
+            }
+        }
+    }
+
+    /// The threshold and keys of `multi` if the policy is a threshold of
+    /// plain keys, and `multi` is no larger than the compilation `ms`
+    #[cfg(feature = "compiler")]
+    fn sortedmulti_keys<Ctx: ScriptContext>(
+        &self,
+        ms: &Miniscript<Pk, Ctx>,
+    ) -> Option<(usize, Vec<Pk>)> {
+        let (k, pks) = self.key_threshold()?;
+        let multi = Miniscript::<Pk, Ctx>::from_ast(Terminal::Multi(k, pks.clone())).ok()?;
+        if multi.script_size() <= ms.script_size() {
+            Some((k, pks))
+        } else {
+            None
+        }
+    }
+
+    /// The threshold and keys of the policy, if it is a threshold, a
+    /// conjunction or a disjunction of plain keys only
+    #[cfg(feature = "compiler")]
+    fn key_threshold(&self) -> Option<(usize, Vec<Pk>)> {
+        let key = |policy: &Policy<Pk>| match *policy {
+            Policy::Key(ref pk) => Some(pk.clone()),
+            _ => None,
+        };
+        match *self {
+            Policy::Threshold(k, ref subs) => {
+                Some((k, subs.iter().map(key).collect::<Option<_>>()?))
+            }
+            Policy::And(ref subs) => {
+                Some((subs.len(), subs.iter().map(key).collect::<Option<_>>()?))
+            }
+            Policy::Or(ref subs) => Some((
+                1,
+                subs.iter()
+                    .map(|(_, sub)| key(sub))
+                    .collect::<Option<_>>()?,
+            )),
+            _ => None,
         }
     }
 }
 
 impl<Pk: MiniscriptKey> ForEachKey<Pk> for Policy<Pk> {
     fn for_each_key<'a, F: FnMut(ForEach<'a, Pk>) -> bool>(&'a self, mut pred: F) -> bool
+    where
+        Pk: 'a,
+        Pk::Hash: 'a,
+    {
+        self.real_for_each_key(&mut pred)
+    }
+}
+
+impl<Pk: MiniscriptKey> Policy<Pk> {
+    fn real_for_each_key<'a, F: FnMut(ForEach<'a, Pk>) -> bool>(&'a self, pred: &mut F) -> bool
     where
         Pk: 'a,
         Pk::Hash: 'a,
@@ -312,9 +575,9 @@ impl<Pk: MiniscriptKey> ForEachKey<Pk> for Policy<Pk> {
             | Policy::After(..)
             | Policy::Older(..) => true,
             Policy::Threshold(_, ref subs) | Policy::And(ref subs) => {
-                subs.iter().all(|sub| sub.for_each_key(&mut pred))
+                subs.iter().all(|sub| sub.real_for_each_key(pred))
             }
-            Policy::Or(ref subs) => subs.iter().all(|(_, sub)| sub.for_each_key(&mut pred)),
+            Policy::Or(ref subs) => subs.iter().all(|(_, sub)| sub.real_for_each_key(pred)),
         }
     }
 }
@@ -326,57 +589,75 @@ impl<Pk: MiniscriptKey> Policy<Pk> {
     /// # Example
     ///
     /// ```
-    /// use miniscript::{bitcoin::PublicKey, policy::concrete::Policy};
+    /// use miniscript::bitcoin::{hashes::hash160, PublicKey};
+    /// use miniscript::{policy::concrete::Policy, translate_hash_fail, Translator};
+    /// use std::collections::BTreeMap;
     /// use std::str::FromStr;
     /// let alice_key = "0270cf3c71f65a3d93d285d9149fddeeb638f87a2d4d8cf16c525f71c417439777";
     /// let bob_key = "02f43b15c50a436f5335dbea8a64dd3b4e63e34c3b50c42598acb5f4f336b5d2fb";
     /// let placeholder_policy = Policy::<String>::from_str("and(pk(alice_key),pk(bob_key))").unwrap();
     ///
-    /// let real_policy = placeholder_policy.translate_pk(|placeholder: &String| match placeholder.as_str() {
-    ///     "alice_key" => PublicKey::from_str(alice_key),
-    ///     "bob_key"   => PublicKey::from_str(bob_key),
-    ///     _ => panic!("unknown key!")
-    /// }).unwrap();
+    /// // Information to translate abstract string type keys to concrete bitcoin::PublicKey s
+    /// let mut pk_map = BTreeMap::new();
+    /// pk_map.insert("alice_key".to_string(), PublicKey::from_str(alice_key).unwrap());
+    /// pk_map.insert("bob_key".to_string(), PublicKey::from_str(bob_key).unwrap());
+    ///
+    /// struct StrPkTranslator(BTreeMap<String, PublicKey>);
+    ///
+    /// impl Translator<String, PublicKey, ()> for StrPkTranslator {
+    ///     fn pk(&mut self, pk: &String) -> Result<PublicKey, ()> {
+    ///         self.0.get(pk).copied().ok_or(())
+    ///     }
+    ///
+    ///     fn pkh(&mut self, _pkh: &String) -> Result<hash160::Hash, ()> {
+    ///         unreachable!("Policy does not contain any pkh fragment");
+    ///     }
+    ///
+    ///     // The policy has no hashes
+    ///     translate_hash_fail!(String, PublicKey, ());
+    /// }
+    ///
+    /// let real_policy = placeholder_policy.translate_pk(&mut StrPkTranslator(pk_map)).unwrap();
     ///
     /// let expected_policy = Policy::from_str(&format!("and(pk({}),pk({}))", alice_key, bob_key)).unwrap();
     /// assert_eq!(real_policy, expected_policy);
     /// ```
-    pub fn translate_pk<Fpk, Q, E>(&self, mut fpk: Fpk) -> Result<Policy<Q>, E>
+    pub fn translate_pk<Q, E, T>(&self, t: &mut T) -> Result<Policy<Q>, E>
     where
-        Fpk: FnMut(&Pk) -> Result<Q, E>,
+        T: Translator<Pk, Q, E>,
         Q: MiniscriptKey,
     {
-        self._translate_pk(&mut fpk)
+        self._translate_pk(t)
     }
 
-    fn _translate_pk<Fpk, Q, E>(&self, fpk: &mut Fpk) -> Result<Policy<Q>, E>
+    fn _translate_pk<Q, E, T>(&self, t: &mut T) -> Result<Policy<Q>, E>
     where
-        Fpk: FnMut(&Pk) -> Result<Q, E>,
+        T: Translator<Pk, Q, E>,
         Q: MiniscriptKey,
     {
         match *self {
             Policy::Unsatisfiable => Ok(Policy::Unsatisfiable),
             Policy::Trivial => Ok(Policy::Trivial),
-            Policy::Key(ref pk) => fpk(pk).map(Policy::Key),
-            Policy::Sha256(ref h) => Ok(Policy::Sha256(*h)),
-            Policy::Hash256(ref h) => Ok(Policy::Hash256(*h)),
-            Policy::Ripemd160(ref h) => Ok(Policy::Ripemd160(*h)),
-            Policy::Hash160(ref h) => Ok(Policy::Hash160(*h)),
+            Policy::Key(ref pk) => t.pk(pk).map(Policy::Key),
+            Policy::Sha256(ref h) => t.sha256(h).map(Policy::Sha256),
+            Policy::Hash256(ref h) => t.hash256(h).map(Policy::Hash256),
+            Policy::Ripemd160(ref h) => t.ripemd160(h).map(Policy::Ripemd160),
+            Policy::Hash160(ref h) => t.hash160(h).map(Policy::Hash160),
             Policy::After(n) => Ok(Policy::After(n)),
             Policy::Older(n) => Ok(Policy::Older(n)),
             Policy::Threshold(k, ref subs) => {
                 let new_subs: Result<Vec<Policy<Q>>, _> =
-                    subs.iter().map(|sub| sub._translate_pk(fpk)).collect();
+                    subs.iter().map(|sub| sub._translate_pk(t)).collect();
                 new_subs.map(|ok| Policy::Threshold(k, ok))
             }
             Policy::And(ref subs) => Ok(Policy::And(
                 subs.iter()
-                    .map(|sub| sub._translate_pk(fpk))
+                    .map(|sub| sub._translate_pk(t))
                     .collect::<Result<Vec<Policy<Q>>, E>>()?,
             )),
             Policy::Or(ref subs) => Ok(Policy::Or(
                 subs.iter()
-                    .map(|&(ref prob, ref sub)| Ok((*prob, sub._translate_pk(fpk)?)))
+                    .map(|&(ref prob, ref sub)| Ok((*prob, sub._translate_pk(t)?)))
                     .collect::<Result<Vec<(usize, Policy<Q>)>, E>>()?,
             )),
         }
@@ -436,6 +717,24 @@ impl<Pk: MiniscriptKey> Policy<Pk> {
         }
     }
 
+    /// Check whether the policy reuses a public key within one of the branches
+    /// of its root-level disjunctions, i.e. of its [`Policy::Or`]s and
+    /// [`Policy::Threshold`]s with `k = 1`, recursively. Unlike
+    /// [`Policy::check_duplicate_keys`], a key may appear in several branches:
+    /// such branches are compiled into separate leaves by
+    /// [`Policy::compile_tr_with_key_reuse`].
+    pub fn check_duplicate_keys_per_branch(&self) -> Result<(), PolicyError> {
+        match *self {
+            Policy::Or(ref subs) => subs
+                .iter()
+                .try_for_each(|(_, sub)| sub.check_duplicate_keys_per_branch()),
+            Policy::Threshold(1, ref subs) => subs
+                .iter()
+                .try_for_each(|sub| sub.check_duplicate_keys_per_branch()),
+            _ => self.check_duplicate_keys(),
+        }
+    }
+
     /// Checks whether the given concrete policy contains a combination of
     /// timelocks and heightlocks.
     /// Returns an error if there is at least one satisfaction that contains
@@ -499,13 +798,19 @@ impl<Pk: MiniscriptKey> Policy<Pk> {
     pub fn is_valid(&self) -> Result<(), PolicyError> {
         self.check_timelocks()?;
         self.check_duplicate_keys()?;
+        self.check_structure()
+    }
+
+    // Checks the arity of the `and`, `or` and `thresh` fragments and the
+    // values of the timelocks, recursively
+    fn check_structure(&self) -> Result<(), PolicyError> {
         match *self {
             Policy::And(ref subs) => {
                 if subs.len() != 2 {
                     Err(PolicyError::NonBinaryArgAnd)
                 } else {
                     subs.iter()
-                        .map(|sub| sub.is_valid())
+                        .map(|sub| sub.check_structure())
                         .collect::<Result<Vec<()>, PolicyError>>()?;
                     Ok(())
                 }
@@ -513,9 +818,13 @@ impl<Pk: MiniscriptKey> Policy<Pk> {
             Policy::Or(ref subs) => {
                 if subs.len() != 2 {
                     Err(PolicyError::NonBinaryArgOr)
+                } else if subs.iter().any(|&(prob, _)| prob == 0) {
+                    Err(PolicyError::ZeroOdds)
+                } else if subs[0].0.checked_add(subs[1].0).is_none() {
+                    Err(PolicyError::OddsOverflow)
                 } else {
                     subs.iter()
-                        .map(|&(ref _prob, ref sub)| sub.is_valid())
+                        .map(|&(ref _prob, ref sub)| sub.check_structure())
                         .collect::<Result<Vec<()>, PolicyError>>()?;
                     Ok(())
                 }
@@ -525,7 +834,7 @@ impl<Pk: MiniscriptKey> Policy<Pk> {
                     Err(PolicyError::IncorrectThresh)
                 } else {
                     subs.iter()
-                        .map(|sub| sub.is_valid())
+                        .map(|sub| sub.check_structure())
                         .collect::<Result<Vec<()>, PolicyError>>()?;
                     Ok(())
                 }
@@ -601,10 +910,10 @@ impl<Pk: MiniscriptKey> fmt::Debug for Policy<Pk> {
             Policy::Key(ref pk) => write!(f, "pk({:?})", pk),
             Policy::After(n) => write!(f, "after({})", n),
             Policy::Older(n) => write!(f, "older({})", n),
-            Policy::Sha256(h) => write!(f, "sha256({})", h),
-            Policy::Hash256(h) => write!(f, "hash256({})", h),
-            Policy::Ripemd160(h) => write!(f, "ripemd160({})", h),
-            Policy::Hash160(h) => write!(f, "hash160({})", h),
+            Policy::Sha256(ref h) => write!(f, "sha256({})", h),
+            Policy::Hash256(ref h) => write!(f, "hash256({})", h),
+            Policy::Ripemd160(ref h) => write!(f, "ripemd160({})", h),
+            Policy::Hash160(ref h) => write!(f, "hash160({})", h),
             Policy::And(ref subs) => {
                 f.write_str("and(")?;
                 if !subs.is_empty() {
@@ -644,10 +953,10 @@ impl<Pk: MiniscriptKey> fmt::Display for Policy<Pk> {
             Policy::Key(ref pk) => write!(f, "pk({})", pk),
             Policy::After(n) => write!(f, "after({})", n),
             Policy::Older(n) => write!(f, "older({})", n),
-            Policy::Sha256(h) => write!(f, "sha256({})", h),
-            Policy::Hash256(h) => write!(f, "hash256({})", h),
-            Policy::Ripemd160(h) => write!(f, "ripemd160({})", h),
-            Policy::Hash160(h) => write!(f, "hash160({})", h),
+            Policy::Sha256(ref h) => write!(f, "sha256({})", h),
+            Policy::Hash256(ref h) => write!(f, "hash256({})", h),
+            Policy::Ripemd160(ref h) => write!(f, "ripemd160({})", h),
+            Policy::Hash160(ref h) => write!(f, "hash160({})", h),
             Policy::And(ref subs) => {
                 f.write_str("and(")?;
                 if !subs.is_empty() {
@@ -683,8 +992,16 @@ impl<Pk> str::FromStr for Policy<Pk>
 where
     Pk: MiniscriptKey + str::FromStr,
     Pk::Hash: str::FromStr,
+    Pk::Sha256: str::FromStr,
+    Pk::Hash256: str::FromStr,
+    Pk::Ripemd160: str::FromStr,
+    Pk::Hash160: str::FromStr,
     <Pk as str::FromStr>::Err: ToString,
     <<Pk as MiniscriptKey>::Hash as str::FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Sha256 as str::FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Hash256 as str::FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Ripemd160 as str::FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Hash160 as str::FromStr>::Err: ToString,
 {
     type Err = Error;
 
@@ -708,7 +1025,15 @@ impl<Pk> Policy<Pk>
 where
     Pk: MiniscriptKey + str::FromStr,
     Pk::Hash: str::FromStr,
+    Pk::Sha256: str::FromStr,
+    Pk::Hash256: str::FromStr,
+    Pk::Ripemd160: str::FromStr,
+    Pk::Hash160: str::FromStr,
     <Pk as str::FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Sha256 as str::FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Hash256 as str::FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Ripemd160 as str::FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Hash160 as str::FromStr>::Err: ToString,
 {
     /// Helper function for `from_tree` to parse subexpressions with
     /// names of the form x@y
@@ -732,7 +1057,7 @@ where
                 if !allow_prob {
                     return Err(Error::AtOutsideOr(top.name.to_owned()));
                 }
-                frag_prob = expression::parse_num(prob)? as usize;
+                frag_prob = parse_odds(prob)?;
                 frag_name = name;
             }
             (Some(_), Some(_), Some(_)) => {
@@ -762,16 +1087,16 @@ where
                 Ok(Policy::Older(num))
             }
             ("sha256", 1) => expression::terminal(&top.args[0], |x| {
-                sha256::Hash::from_hex(x).map(Policy::Sha256)
+                Pk::Sha256::from_str(x).map(Policy::Sha256)
             }),
             ("hash256", 1) => expression::terminal(&top.args[0], |x| {
-                sha256d::Hash::from_hex(x).map(Policy::Hash256)
+                Pk::Hash256::from_str(x).map(Policy::Hash256)
             }),
             ("ripemd160", 1) => expression::terminal(&top.args[0], |x| {
-                ripemd160::Hash::from_hex(x).map(Policy::Ripemd160)
+                Pk::Ripemd160::from_str(x).map(Policy::Ripemd160)
             }),
             ("hash160", 1) => expression::terminal(&top.args[0], |x| {
-                hash160::Hash::from_hex(x).map(Policy::Hash160)
+                Pk::Hash160::from_str(x).map(Policy::Hash160)
             }),
             ("and", _) => {
                 if top.args.len() != 2 {
@@ -819,13 +1144,37 @@ impl<Pk> FromTree for Policy<Pk>
 where
     Pk: MiniscriptKey + str::FromStr,
     Pk::Hash: str::FromStr,
+    Pk::Sha256: str::FromStr,
+    Pk::Hash256: str::FromStr,
+    Pk::Ripemd160: str::FromStr,
+    Pk::Hash160: str::FromStr,
     <Pk as str::FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Sha256 as str::FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Hash256 as str::FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Ripemd160 as str::FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Hash160 as str::FromStr>::Err: ToString,
 {
     fn from_tree(top: &expression::Tree) -> Result<Policy<Pk>, Error> {
         Policy::from_tree_prob(top, false).map(|(_, result)| result)
     }
 }
 
+/// Parse the odds `n` of an `n@` annotation, which may be as large as the
+/// odds of a [`Policy::Or`] so that every policy can be parsed back from its
+/// string form
+fn parse_odds(s: &str) -> Result<usize, Error> {
+    if s.len() > 1 && !s.starts_with(|ch| ('1'..='9').contains(&ch)) {
+        return Err(Error::Unexpected(
+            "Number must start with a digit 1-9".to_string(),
+        ));
+    }
+    match usize::from_str(s) {
+        Ok(0) => Err(Error::PolicyError(PolicyError::ZeroOdds)),
+        Ok(odds) => Ok(odds),
+        Err(_) => Err(errstr(s)),
+    }
+}
+
 /// Create a Huffman Tree from compiled [Miniscript] nodes
 #[cfg(feature = "compiler")]
 fn with_huffman_tree<Pk: MiniscriptKey>(
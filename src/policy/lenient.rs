@@ -0,0 +1,616 @@
+// Miniscript
+// Written in 2022 by rust-miniscript developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Lenient Script Lifting
+//!
+//! Lifting of scripts which are not Miniscript, such as hand-rolled
+//! timelocked escrows or Lightning HTLCs, to semantic policies. The script
+//! is executed symbolically along each of its `IF`/`ELSE` branches, collecting
+//! the keys, hash preimages and timelocks each branch requires from its
+//! witness.
+//!
+
+use bitcoin::blockdata::opcodes::{self, all};
+use bitcoin::blockdata::script::{self, Instruction};
+use bitcoin::hashes::{hash160, ripemd160, sha256, sha256d, Hash};
+
+use super::{Liftable, Semantic};
+use crate::miniscript::{Legacy, Segwitv0};
+use crate::prelude::*;
+use crate::{hash256, Error, Miniscript, MiniscriptKey};
+
+/// Maximum number of execution paths explored when lifting a script
+const MAX_PATHS: usize = 1024;
+
+/// Lift a script to a semantic policy, even if it is not a Miniscript.
+///
+/// Scripts which parse as Miniscript are lifted as such. Other scripts are
+/// lifted by symbolically executing every branch of their `IF`/`NOTIF`
+/// conditionals, which recognizes common templates such as bare and P2SH
+/// multisig, `CHECKLOCKTIMEVERIFY` escrows and Lightning HTLCs. Keys are
+/// lifted to `KeyHash` policies, including keys only committed to by their
+/// hash like in P2PKH.
+///
+/// Scripts using opcodes outside of the usual signature, hash, timelock and
+/// stack manipulation ones, or whose keys cannot be determined, are rejected.
+pub fn lift_script_lenient(script: &script::Script) -> Result<Semantic<bitcoin::PublicKey>, Error> {
+    if let Ok(ms) = Miniscript::<_, Segwitv0>::parse_insane(script) {
+        if let Ok(policy) = ms.lift() {
+            return Ok(policy);
+        }
+    }
+    if let Ok(ms) = Miniscript::<_, Legacy>::parse_insane(script) {
+        if let Ok(policy) = ms.lift() {
+            return Ok(policy);
+        }
+    }
+
+    let mut paths = vec![Path::default()];
+    for ins in script.instructions() {
+        let ins = ins.map_err(Error::Script)?;
+        let mut next = Vec::with_capacity(paths.len());
+        for path in paths {
+            next.extend(path.step(&ins)?);
+        }
+        if next.len() > MAX_PATHS {
+            return Err(unrecognized("too many execution paths"));
+        }
+        paths = next;
+    }
+
+    let mut policies = Vec::with_capacity(paths.len());
+    for mut path in paths {
+        if !path.exec.is_empty() {
+            return Err(unrecognized("unbalanced conditional"));
+        }
+        let top = path.pop();
+        if path.assume(top, true)? {
+            policies.push(path.into_policy()?);
+        }
+    }
+    Ok(Semantic::Threshold(1, policies).normalized())
+}
+
+fn unrecognized(reason: &str) -> Error {
+    Error::Unexpected(format!("cannot lift script: {}", reason))
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum HashType {
+    Sha256,
+    Hash256,
+    Ripemd160,
+    Hash160,
+}
+
+impl HashType {
+    fn hash(self, data: &[u8]) -> Vec<u8> {
+        match self {
+            HashType::Sha256 => sha256::Hash::hash(data).to_vec(),
+            HashType::Hash256 => sha256d::Hash::hash(data).to_vec(),
+            HashType::Ripemd160 => ripemd160::Hash::hash(data).to_vec(),
+            HashType::Hash160 => hash160::Hash::hash(data).to_vec(),
+        }
+    }
+}
+
+/// A symbolic stack element
+#[derive(Clone, PartialEq, Eq, Debug)]
+enum Value {
+    /// The `n`th element of the witness, from the top of the initial stack
+    Witness(usize),
+    /// A constant pushed by the script
+    Push(Vec<u8>),
+    /// The hash of a witness element
+    Hash(HashType, usize),
+    /// The size of a value
+    Size(Box<Value>),
+    /// The result of comparing two values
+    Equal(Box<Value>, Box<Value>),
+    /// The result of checking a signature for the given key
+    CheckSig(Box<Value>),
+    /// The result of checking `k` signatures for the given keys
+    CheckMultiSig(usize, Vec<Value>),
+}
+
+/// What the witness must provide for a path to be taken
+#[derive(Clone, PartialEq, Eq, Debug)]
+enum Condition {
+    Sig(Value),
+    MultiSig(usize, Vec<Value>),
+    Preimage(HashType, usize, Vec<u8>),
+    After(u32),
+    Older(u32),
+}
+
+/// The state of the symbolic execution of one path through the script
+#[derive(Clone, Default, Debug)]
+struct Path {
+    stack: Vec<Value>,
+    altstack: Vec<Value>,
+    // Number of witness elements popped off the initial stack
+    n_witness: usize,
+    exec: Vec<bool>,
+    conditions: Vec<Condition>,
+}
+
+impl Path {
+    fn pop(&mut self) -> Value {
+        self.stack.pop().unwrap_or_else(|| {
+            self.n_witness += 1;
+            Value::Witness(self.n_witness - 1)
+        })
+    }
+
+    fn pop_num(&mut self) -> Result<i64, Error> {
+        match self.pop() {
+            Value::Push(ref data) => script::read_scriptint(data).map_err(Error::Script),
+            _ => Err(unrecognized("non-constant number")),
+        }
+    }
+
+    fn peek_locktime(&mut self) -> Result<u32, Error> {
+        let top = self.pop();
+        let n = match top {
+            Value::Push(ref data) if data.len() <= 5 => {
+                script::read_scriptint(data).map_err(Error::Script)?
+            }
+            _ => return Err(unrecognized("non-constant timelock")),
+        };
+        self.stack.push(top);
+        if !(0..=u32::max_value() as i64).contains(&n) {
+            return Err(unrecognized("invalid timelock"));
+        }
+        Ok(n as u32)
+    }
+
+    // Record what the witness must provide for `value` to be `truthy`. Returns
+    // whether this is possible at all.
+    fn assume(&mut self, value: Value, truthy: bool) -> Result<bool, Error> {
+        match value {
+            Value::Witness(_) | Value::Size(_) => Ok(true),
+            Value::Push(ref data) => Ok(script::read_scriptbool(data) == truthy),
+            Value::Hash(..) => Ok(truthy),
+            // Any of these checks may be made to fail by the witness
+            _ if !truthy => Ok(true),
+            Value::CheckSig(key) => {
+                self.conditions.push(Condition::Sig(*key));
+                Ok(true)
+            }
+            Value::CheckMultiSig(k, keys) => {
+                self.conditions.push(Condition::MultiSig(k, keys));
+                Ok(true)
+            }
+            Value::Equal(left, right) => match (*left, *right) {
+                (Value::Hash(ty, n), Value::Push(hash))
+                | (Value::Push(hash), Value::Hash(ty, n)) => {
+                    self.conditions.push(Condition::Preimage(ty, n, hash));
+                    Ok(true)
+                }
+                (Value::Witness(_), _)
+                | (_, Value::Witness(_))
+                | (Value::Size(_), Value::Push(_))
+                | (Value::Push(_), Value::Size(_)) => Ok(true),
+                _ => Err(unrecognized("unsupported comparison")),
+            },
+        }
+    }
+
+    // Execute one instruction, returning the resulting paths
+    fn step(mut self, ins: &Instruction) -> Result<Vec<Path>, Error> {
+        let executing = self.exec.iter().all(|&b| b);
+        let op = match *ins {
+            Instruction::PushBytes(data) => {
+                if executing {
+                    self.stack.push(Value::Push(data.to_vec()));
+                }
+                return Ok(vec![self]);
+            }
+            Instruction::Op(op) => op,
+        };
+
+        match op {
+            all::OP_IF | all::OP_NOTIF => {
+                if !executing {
+                    self.exec.push(false);
+                    return Ok(vec![self]);
+                }
+                let cond = self.pop();
+                let mut paths = Vec::with_capacity(2);
+                for &truthy in &[true, false] {
+                    let mut path = self.clone();
+                    if path.assume(cond.clone(), truthy)? {
+                        path.exec.push(truthy == (op == all::OP_IF));
+                        paths.push(path);
+                    }
+                }
+                return Ok(paths);
+            }
+            all::OP_ELSE => match self.exec.last_mut() {
+                Some(exec) => *exec = !*exec,
+                None => return Err(unrecognized("unbalanced conditional")),
+            },
+            all::OP_ENDIF => {
+                if self.exec.pop().is_none() {
+                    return Err(unrecognized("unbalanced conditional"));
+                }
+            }
+            _ if !executing => {}
+            all::OP_RETURN => return Ok(vec![]),
+            all::OP_DUP => {
+                let top = self.pop();
+                self.stack.push(top.clone());
+                self.stack.push(top);
+            }
+            all::OP_DROP => {
+                self.pop();
+            }
+            all::OP_2DROP => {
+                self.pop();
+                self.pop();
+            }
+            all::OP_NIP => {
+                let top = self.pop();
+                self.pop();
+                self.stack.push(top);
+            }
+            all::OP_SWAP => {
+                let top = self.pop();
+                let second = self.pop();
+                self.stack.push(top);
+                self.stack.push(second);
+            }
+            all::OP_OVER => {
+                let top = self.pop();
+                let second = self.pop();
+                self.stack.push(second.clone());
+                self.stack.push(top);
+                self.stack.push(second);
+            }
+            all::OP_TOALTSTACK => {
+                let top = self.pop();
+                self.altstack.push(top);
+            }
+            all::OP_FROMALTSTACK => match self.altstack.pop() {
+                Some(top) => self.stack.push(top),
+                None => return Err(unrecognized("empty altstack")),
+            },
+            all::OP_SIZE => {
+                let top = self.pop();
+                self.stack.push(top.clone());
+                self.stack.push(match top {
+                    Value::Push(ref data) => Value::Push(scriptint(data.len() as i64)),
+                    top => Value::Size(Box::new(top)),
+                });
+            }
+            all::OP_SHA256 | all::OP_HASH256 | all::OP_RIPEMD160 | all::OP_HASH160 => {
+                let ty = match op {
+                    all::OP_SHA256 => HashType::Sha256,
+                    all::OP_HASH256 => HashType::Hash256,
+                    all::OP_RIPEMD160 => HashType::Ripemd160,
+                    _ => HashType::Hash160,
+                };
+                let hash = match self.pop() {
+                    Value::Witness(n) => Value::Hash(ty, n),
+                    Value::Push(ref data) => Value::Push(ty.hash(data)),
+                    _ => return Err(unrecognized("unsupported hash")),
+                };
+                self.stack.push(hash);
+            }
+            all::OP_EQUAL | all::OP_EQUALVERIFY => {
+                let right = self.pop();
+                let left = self.pop();
+                let equal = match (left, right) {
+                    (Value::Push(left), Value::Push(right)) => {
+                        Value::Push(scriptint((left == right) as i64))
+                    }
+                    (left, right) => Value::Equal(Box::new(left), Box::new(right)),
+                };
+                if op == all::OP_EQUAL {
+                    self.stack.push(equal);
+                } else if !self.assume(equal, true)? {
+                    return Ok(vec![]);
+                }
+            }
+            all::OP_CHECKSIG | all::OP_CHECKSIGVERIFY => {
+                let key = self.pop();
+                self.pop();
+                let check = Value::CheckSig(Box::new(key));
+                if op == all::OP_CHECKSIG {
+                    self.stack.push(check);
+                } else {
+                    self.assume(check, true)?;
+                }
+            }
+            all::OP_CHECKMULTISIG | all::OP_CHECKMULTISIGVERIFY => {
+                let n = self.pop_num()?;
+                if !(0..=20).contains(&n) {
+                    return Err(unrecognized("invalid multisig"));
+                }
+                let mut keys: Vec<_> = (0..n).map(|_| self.pop()).collect();
+                keys.reverse();
+                let k = self.pop_num()?;
+                if !(0..=n).contains(&k) {
+                    return Err(unrecognized("invalid multisig"));
+                }
+                // The signatures and the dummy element
+                for _ in 0..=k {
+                    self.pop();
+                }
+                let check = Value::CheckMultiSig(k as usize, keys);
+                if op == all::OP_CHECKMULTISIG {
+                    self.stack.push(check);
+                } else {
+                    self.assume(check, true)?;
+                }
+            }
+            all::OP_CLTV => {
+                let n = self.peek_locktime()?;
+                self.conditions.push(Condition::After(n));
+            }
+            all::OP_CSV => {
+                let n = self.peek_locktime()?;
+                self.conditions.push(Condition::Older(n));
+            }
+            all::OP_VERIFY => {
+                let top = self.pop();
+                if !self.assume(top, true)? {
+                    return Ok(vec![]);
+                }
+            }
+            op => match op.classify(opcodes::ClassifyContext::Legacy) {
+                opcodes::Class::PushNum(n) => self.stack.push(Value::Push(scriptint(n as i64))),
+                _ => return Err(Error::InvalidOpcode(op)),
+            },
+        }
+        Ok(vec![self])
+    }
+
+    fn key_hash(&self, key: &Value) -> Result<hash160::Hash, Error> {
+        match *key {
+            Value::Push(ref data) => {
+                let pk = bitcoin::PublicKey::from_slice(data).map_err(Error::BadPubkey)?;
+                Ok(pk.to_pubkeyhash())
+            }
+            // A key committed to by its hash, as in P2PKH
+            Value::Witness(n) => self
+                .conditions
+                .iter()
+                .filter_map(|cond| match *cond {
+                    Condition::Preimage(HashType::Hash160, m, ref hash) if m == n => {
+                        hash160::Hash::from_slice(hash).ok()
+                    }
+                    _ => None,
+                })
+                .next()
+                .ok_or_else(|| unrecognized("signature for an unknown key")),
+            _ => Err(unrecognized("signature for an unknown key")),
+        }
+    }
+
+    fn into_policy(self) -> Result<Semantic<bitcoin::PublicKey>, Error> {
+        let mut keys = BTreeSet::new();
+        let mut subs = Vec::with_capacity(self.conditions.len());
+        for cond in &self.conditions {
+            match *cond {
+                Condition::Sig(ref key) => {
+                    if let Value::Witness(n) = *key {
+                        keys.insert(n);
+                    }
+                    subs.push(Semantic::KeyHash(self.key_hash(key)?));
+                }
+                Condition::MultiSig(k, ref multi_keys) => {
+                    let mut key_hashes = Vec::with_capacity(multi_keys.len());
+                    for key in multi_keys {
+                        if let Value::Witness(n) = *key {
+                            keys.insert(n);
+                        }
+                        key_hashes.push(Semantic::KeyHash(self.key_hash(key)?));
+                    }
+                    subs.push(Semantic::Threshold(k, key_hashes));
+                }
+                Condition::After(n) => subs.push(Semantic::After(n)),
+                Condition::Older(n) => subs.push(Semantic::Older(n)),
+                Condition::Preimage(..) => {}
+            }
+        }
+        for cond in &self.conditions {
+            if let Condition::Preimage(ty, n, ref hash) = *cond {
+                // The hash of a key is already accounted for by its signature
+                if ty == HashType::Hash160 && keys.contains(&n) {
+                    continue;
+                }
+                // A hash of the wrong length can never be matched
+                let sub = match ty {
+                    HashType::Sha256 => sha256::Hash::from_slice(hash).map(Semantic::Sha256),
+                    HashType::Hash256 => hash256::Hash::from_slice(hash).map(Semantic::Hash256),
+                    HashType::Ripemd160 => {
+                        ripemd160::Hash::from_slice(hash).map(Semantic::Ripemd160)
+                    }
+                    HashType::Hash160 => hash160::Hash::from_slice(hash).map(Semantic::Hash160),
+                };
+                subs.push(sub.unwrap_or(Semantic::Unsatisfiable));
+            }
+        }
+        Ok(Semantic::Threshold(subs.len(), subs))
+    }
+}
+
+// Minimal encoding of a script number
+fn scriptint(n: i64) -> Vec<u8> {
+    let mut ret = Vec::new();
+    let neg = n < 0;
+    let mut abs = n.abs();
+    while abs > 0 {
+        ret.push((abs & 0xff) as u8);
+        abs >>= 8;
+    }
+    if let Some(last) = ret.last_mut() {
+        if *last & 0x80 != 0 {
+            ret.push(if neg { 0x80 } else { 0 });
+        } else if neg {
+            *last |= 0x80;
+        }
+    }
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use core::str::FromStr;
+
+    use bitcoin::blockdata::script::Builder;
+    use bitcoin::secp256k1::{PublicKey, Secp256k1, SecretKey};
+
+    use super::*;
+
+    fn key(n: u8) -> bitcoin::PublicKey {
+        let secp = Secp256k1::signing_only();
+        let sk = SecretKey::from_slice(&[n; 32]).unwrap();
+        bitcoin::PublicKey::new(PublicKey::from_secret_key(&secp, &sk))
+    }
+
+    fn pkh(n: u8) -> Semantic<bitcoin::PublicKey> {
+        Semantic::KeyHash(key(n).to_pubkeyhash())
+    }
+
+    fn assert_lifts(script: script::Script, expected: Semantic<bitcoin::PublicKey>) {
+        assert_eq!(
+            lift_script_lenient(&script).unwrap().sorted(),
+            expected.normalized().sorted()
+        );
+    }
+
+    #[test]
+    fn lift_templates() {
+        // Raw CLTV escrow, with the timelock before the miniscript `after`
+        let script = Builder::new()
+            .push_int(500_000)
+            .push_opcode(all::OP_CLTV)
+            .push_opcode(all::OP_DROP)
+            .push_key(&key(1))
+            .push_opcode(all::OP_CHECKSIG)
+            .into_script();
+        assert_lifts(
+            script,
+            Semantic::Threshold(2, vec![Semantic::After(500_000), pkh(1)]),
+        );
+
+        // P2SH 2-of-3 with uncompressed keys
+        let uncompressed = |n| {
+            let mut pk = key(n);
+            pk.compressed = false;
+            pk
+        };
+        let script = Builder::new()
+            .push_int(2)
+            .push_key(&uncompressed(1))
+            .push_key(&uncompressed(2))
+            .push_key(&uncompressed(3))
+            .push_int(3)
+            .push_opcode(all::OP_CHECKMULTISIG)
+            .into_script();
+        let expected = Semantic::Threshold(
+            2,
+            (1..4)
+                .map(|n| Semantic::KeyHash(uncompressed(n).to_pubkeyhash()))
+                .collect(),
+        );
+        assert_lifts(script, expected);
+
+        // HTLC sharing its CHECKSIG between branches
+        let hash = sha256::Hash::hash(&[7; 32]);
+        let script = Builder::new()
+            .push_opcode(all::OP_IF)
+            .push_opcode(all::OP_SHA256)
+            .push_slice(&hash[..])
+            .push_opcode(all::OP_EQUALVERIFY)
+            .push_key(&key(1))
+            .push_opcode(all::OP_ELSE)
+            .push_int(144)
+            .push_opcode(all::OP_CSV)
+            .push_opcode(all::OP_DROP)
+            .push_key(&key(2))
+            .push_opcode(all::OP_ENDIF)
+            .push_opcode(all::OP_CHECKSIG)
+            .into_script();
+        assert!(Miniscript::<_, Segwitv0>::parse_insane(&script).is_err());
+        let expected = Semantic::Threshold(
+            1,
+            vec![
+                Semantic::Threshold(2, vec![Semantic::Sha256(hash), pkh(1)]),
+                Semantic::Threshold(2, vec![Semantic::Older(144), pkh(2)]),
+            ],
+        );
+        assert_lifts(script, expected);
+
+        // Offered HTLC output of BOLT 3
+        let revocation = key(1);
+        let payment_hash = ripemd160::Hash::hash(&[9; 32]);
+        let script = Builder::new()
+            .push_opcode(all::OP_DUP)
+            .push_opcode(all::OP_HASH160)
+            .push_slice(&revocation.pubkey_hash()[..])
+            .push_opcode(all::OP_EQUAL)
+            .push_opcode(all::OP_IF)
+            .push_opcode(all::OP_CHECKSIG)
+            .push_opcode(all::OP_ELSE)
+            .push_key(&key(2))
+            .push_opcode(all::OP_SWAP)
+            .push_opcode(all::OP_SIZE)
+            .push_int(32)
+            .push_opcode(all::OP_EQUAL)
+            .push_opcode(all::OP_NOTIF)
+            .push_opcode(all::OP_DROP)
+            .push_int(2)
+            .push_opcode(all::OP_SWAP)
+            .push_key(&key(3))
+            .push_int(2)
+            .push_opcode(all::OP_CHECKMULTISIG)
+            .push_opcode(all::OP_ELSE)
+            .push_opcode(all::OP_HASH160)
+            .push_slice(&payment_hash[..])
+            .push_opcode(all::OP_EQUALVERIFY)
+            .push_opcode(all::OP_CHECKSIG)
+            .push_opcode(all::OP_ENDIF)
+            .push_opcode(all::OP_ENDIF)
+            .into_script();
+        let expected = Semantic::Threshold(
+            1,
+            vec![
+                pkh(1),
+                Semantic::Threshold(2, vec![pkh(2), pkh(3)]),
+                Semantic::Threshold(
+                    2,
+                    vec![
+                        Semantic::Hash160(hash160::Hash::from_inner(payment_hash.into_inner())),
+                        pkh(2),
+                    ],
+                ),
+            ],
+        );
+        assert_lifts(script, expected);
+
+        // Miniscripts are lifted as usual
+        let ms = Miniscript::<bitcoin::PublicKey, Segwitv0>::from_str(&format!(
+            "and_v(v:pk({}),older(10))",
+            key(1)
+        ))
+        .unwrap();
+        assert_lifts(ms.encode(), ms.lift().unwrap());
+
+        // Signatures for keys the script does not commit to
+        let script = Builder::new().push_opcode(all::OP_CHECKSIG).into_script();
+        assert!(lift_script_lenient(&script).is_err());
+    }
+}
@@ -28,9 +28,11 @@ use std::error;
 #[cfg(feature = "compiler")]
 pub mod compiler;
 pub mod concrete;
+mod lenient;
 pub mod semantic;
 
 pub use self::concrete::Policy as Concrete;
+pub use self::lenient::lift_script_lenient;
 /// Semantic policies are "abstract" policies elsewhere; but we
 /// avoid this word because it is a reserved keyword in Rust
 pub use self::semantic::Policy as Semantic;
@@ -66,6 +68,15 @@ pub enum LiftError {
     HeightTimelockCombination,
     /// Duplicate Public Keys
     BranchExceedResourceLimits,
+    /// Cannot lift taproot trees with hidden branches,
+    /// whose spending conditions are unknown.
+    HiddenTapBranch,
+    /// Cannot lift taproot trees with leaves of a version other than
+    /// tapscript, whose scripts have no known semantics.
+    UnknownLeafVersion,
+    /// Cannot lift descriptors of scripts that are not miniscript, whose
+    /// spending conditions are unknown.
+    UnknownScript,
 }
 
 impl fmt::Display for LiftError {
@@ -77,17 +88,28 @@ impl fmt::Display for LiftError {
             LiftError::BranchExceedResourceLimits => f.write_str(
                 "Cannot lift policies containing one branch that exceeds resource limits",
             ),
+            LiftError::HiddenTapBranch => {
+                f.write_str("Cannot lift taproot trees containing hidden branches")
+            }
+            LiftError::UnknownLeafVersion => {
+                f.write_str("Cannot lift taproot trees containing leaves of unknown versions")
+            }
+            LiftError::UnknownScript => f.write_str("Cannot lift scripts that are not miniscript"),
         }
     }
 }
 
 #[cfg(feature = "std")]
 impl error::Error for LiftError {
-    fn cause(&self) -> Option<&dyn error::Error> {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
         use self::LiftError::*;
 
         match self {
-            HeightTimelockCombination | BranchExceedResourceLimits => None,
+            HeightTimelockCombination
+            | BranchExceedResourceLimits
+            | HiddenTapBranch
+            | UnknownLeafVersion
+            | UnknownScript => None,
         }
     }
 }
@@ -128,10 +150,10 @@ impl<Pk: MiniscriptKey, Ctx: ScriptContext> Liftable<Pk> for Terminal<Pk, Ctx> {
             Terminal::PkH(ref pkh) => Semantic::KeyHash(pkh.clone()),
             Terminal::After(t) => Semantic::After(t),
             Terminal::Older(t) => Semantic::Older(t),
-            Terminal::Sha256(h) => Semantic::Sha256(h),
-            Terminal::Hash256(h) => Semantic::Hash256(h),
-            Terminal::Ripemd160(h) => Semantic::Ripemd160(h),
-            Terminal::Hash160(h) => Semantic::Hash160(h),
+            Terminal::Sha256(ref h) => Semantic::Sha256(h.clone()),
+            Terminal::Hash256(ref h) => Semantic::Hash256(h.clone()),
+            Terminal::Ripemd160(ref h) => Semantic::Ripemd160(h.clone()),
+            Terminal::Hash160(ref h) => Semantic::Hash160(h.clone()),
             Terminal::True => Semantic::Trivial,
             Terminal::False => Semantic::Unsatisfiable,
             Terminal::Alt(ref sub)
@@ -161,7 +183,9 @@ impl<Pk: MiniscriptKey, Ctx: ScriptContext> Liftable<Pk> for Terminal<Pk, Ctx> {
                 let semantic_subs: Result<_, Error> = subs.iter().map(|s| s.node.lift()).collect();
                 Semantic::Threshold(k, semantic_subs?)
             }
-            Terminal::Multi(k, ref keys) | Terminal::MultiA(k, ref keys) => Semantic::Threshold(
+            Terminal::Multi(k, ref keys)
+            | Terminal::MultiA(k, ref keys)
+            | Terminal::SortedMultiA(k, ref keys) => Semantic::Threshold(
                 k,
                 keys.iter()
                     .map(|k| Semantic::KeyHash(k.to_pubkeyhash()))
@@ -182,6 +206,10 @@ impl<Pk: MiniscriptKey> Liftable<Pk> for Descriptor<Pk> {
             Descriptor::Wsh(ref wsh) => wsh.lift(),
             Descriptor::Sh(ref sh) => sh.lift(),
             Descriptor::Tr(ref tr) => tr.lift(),
+            Descriptor::RawTr(ref rawtr) => rawtr.lift(),
+            Descriptor::Raw(ref raw) => raw.lift(),
+            Descriptor::Addr(ref addr) => addr.lift(),
+            Descriptor::Wv(ref wv) => wv.lift(),
         }
     }
 }
@@ -203,10 +231,10 @@ impl<Pk: MiniscriptKey> Liftable<Pk> for Concrete<Pk> {
             Concrete::Key(ref pk) => Semantic::KeyHash(pk.to_pubkeyhash()),
             Concrete::After(t) => Semantic::After(t),
             Concrete::Older(t) => Semantic::Older(t),
-            Concrete::Sha256(h) => Semantic::Sha256(h),
-            Concrete::Hash256(h) => Semantic::Hash256(h),
-            Concrete::Ripemd160(h) => Semantic::Ripemd160(h),
-            Concrete::Hash160(h) => Semantic::Hash160(h),
+            Concrete::Sha256(ref h) => Semantic::Sha256(h.clone()),
+            Concrete::Hash256(ref h) => Semantic::Hash256(h.clone()),
+            Concrete::Ripemd160(ref h) => Semantic::Ripemd160(h.clone()),
+            Concrete::Hash160(ref h) => Semantic::Hash160(h.clone()),
             Concrete::And(ref subs) => {
                 let semantic_subs: Result<_, Error> = subs.iter().map(Liftable::lift).collect();
                 Semantic::Threshold(2, semantic_subs?)
@@ -228,6 +256,7 @@ impl<Pk: MiniscriptKey> Liftable<Pk> for Concrete<Pk> {
 
 #[cfg(test)]
 mod tests {
+    use core::fmt;
     use core::str::FromStr;
 
     use bitcoin;
@@ -238,9 +267,9 @@ mod tests {
     use super::super::miniscript::Miniscript;
     use super::{Concrete, Liftable, Semantic};
     use crate::prelude::*;
-    use crate::DummyKey;
     #[cfg(feature = "compiler")]
     use crate::{descriptor::TapTree, Descriptor, Tap};
+    use crate::{DummyKey, MiniscriptKey};
 
     type ConcretePol = Concrete<DummyKey>;
     type SemanticPol = Semantic<DummyKey>;
@@ -290,6 +319,45 @@ mod tests {
         concrete_policy_rtt("ripemd160(aaaaaaaaaaaaaaaaaaaaaa0Daaaaaaaaaabaaaaa)");
     }
 
+    #[test]
+    fn policy_odds() {
+        // Omitted odds are 1
+        let policy = ConcretePol::from_str("or(pk(),and(pk(),or(95@pk(),after(1))))").unwrap();
+        assert_eq!(
+            policy.to_string(),
+            "or(1@pk(),1@and(pk(),or(95@pk(),1@after(1))))"
+        );
+        // Any odds of an `Or` can be written and parsed back
+        let policy = Concrete::Or(vec![
+            (core::usize::MAX, Concrete::Trivial),
+            (1, Concrete::Trivial),
+        ]);
+        let policy_str = format!("or({}@TRIVIAL,1@TRIVIAL)", core::usize::MAX);
+        assert_eq!(policy.to_string(), policy_str);
+        assert_eq!(ConcretePol::from_str(&policy_str).unwrap(), policy);
+
+        assert_eq!(
+            ConcretePol::from_str("or(0@pk(),1@pk())").unwrap_err(),
+            crate::Error::PolicyError(super::concrete::PolicyError::ZeroOdds)
+        );
+        assert!(ConcretePol::from_str("or(01@pk(),1@pk())").is_err());
+        assert!(ConcretePol::from_str("95@pk()").is_err());
+        assert!(ConcretePol::from_str("thresh(1,95@pk(),pk())").is_err());
+        let policy = Concrete::Or(vec![(0, Concrete::Key(DummyKey)), (1, Concrete::Trivial)]);
+        assert_eq!(
+            policy.is_valid(),
+            Err(super::concrete::PolicyError::ZeroOdds)
+        );
+        // Odds adding up to more than `usize::MAX` can be parsed, but not
+        // compiled
+        let policy_str = format!("or({}@pk(A),1@pk(B))", core::usize::MAX);
+        let policy = Concrete::<String>::from_str(&policy_str).unwrap();
+        assert_eq!(
+            policy.is_valid(),
+            Err(super::concrete::PolicyError::OddsOverflow)
+        );
+    }
+
     #[test]
     fn compile_invalid() {
         // Since the root Error does not support Eq type, we have to
@@ -371,6 +439,172 @@ mod tests {
         );
     }
 
+    /// A key type whose hash fragments hold named placeholders
+    #[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    struct PlaceholderKey(String);
+
+    impl fmt::Display for PlaceholderKey {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str(&self.0)
+        }
+    }
+
+    impl FromStr for PlaceholderKey {
+        type Err = core::convert::Infallible;
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            Ok(PlaceholderKey(s.to_owned()))
+        }
+    }
+
+    impl MiniscriptKey for PlaceholderKey {
+        type Hash = String;
+        type Sha256 = String;
+        type Hash256 = String;
+        type Ripemd160 = String;
+        type Hash160 = String;
+
+        fn to_pubkeyhash(&self) -> String {
+            self.0.clone()
+        }
+    }
+
+    #[test]
+    fn placeholder_hashes() {
+        let policy_str = "or(1@and(pk(A),sha256(H)),1@and(pk(B),hash256(H2)))";
+        let policy = Concrete::<PlaceholderKey>::from_str(policy_str).unwrap();
+        assert_eq!(policy.to_string(), policy_str);
+        assert_eq!(
+            policy.lift().unwrap(),
+            Semantic::Threshold(
+                1,
+                vec![
+                    Semantic::Threshold(
+                        2,
+                        vec![
+                            Semantic::KeyHash("A".to_owned()),
+                            Semantic::Sha256("H".to_owned())
+                        ]
+                    ),
+                    Semantic::Threshold(
+                        2,
+                        vec![
+                            Semantic::KeyHash("B".to_owned()),
+                            Semantic::Hash256("H2".to_owned())
+                        ]
+                    ),
+                ]
+            )
+        );
+
+        let ms_str = "andor(pk(A),ripemd160(H),and_v(v:pk(B),hash160(H2)))";
+        let ms = Miniscript::<PlaceholderKey, Segwitv0>::from_str(ms_str).unwrap();
+        assert_eq!(ms.to_string(), ms_str);
+    }
+
+    #[test]
+    #[cfg(feature = "compiler")]
+    fn compile_to_descriptor() {
+        use super::concrete::DescriptorCtx;
+
+        let policy: Concrete<String> = policy_str!("thresh(2,pk(A),pk(B),pk(C))");
+        let compile = |policy: &Concrete<String>, desc_ctx| {
+            policy
+                .compile_to_descriptor(desc_ctx)
+                .unwrap()
+                .to_string_no_checksum()
+        };
+        assert_eq!(
+            compile(&policy, DescriptorCtx::Wsh),
+            "wsh(sortedmulti(2,A,B,C))"
+        );
+        assert_eq!(
+            compile(&policy, DescriptorCtx::Sh),
+            "sh(sortedmulti(2,A,B,C))"
+        );
+        assert_eq!(
+            compile(&policy, DescriptorCtx::ShWsh),
+            "sh(wsh(sortedmulti(2,A,B,C)))"
+        );
+        assert_eq!(compile(&policy, DescriptorCtx::Bare), "multi(2,A,B,C)");
+        assert_eq!(
+            compile(&policy, DescriptorCtx::Tr(Some("UNSPENDABLE".to_string()))),
+            "tr(UNSPENDABLE,sortedmulti_a(2,A,B,C))"
+        );
+
+        // A 2-of-2 is smaller as a conjunction of checksigs
+        let policy: Concrete<String> = policy_str!("and(pk(A),pk(B))");
+        assert_eq!(
+            compile(&policy, DescriptorCtx::Tr(Some("UNSPENDABLE".to_string()))),
+            "tr(UNSPENDABLE,and_v(v:pk(A),pk(B)))"
+        );
+        let wsh = policy.compile_to_descriptor(DescriptorCtx::Wsh).unwrap();
+        assert_eq!(
+            wsh,
+            Descriptor::new_wsh(policy.compile::<Segwitv0>().unwrap()).unwrap()
+        );
+        let policy: Concrete<String> = policy_str!("thresh(3,pk(A),pk(B),pk(C))");
+        assert_eq!(
+            compile(&policy, DescriptorCtx::Tr(Some("UNSPENDABLE".to_string()))),
+            "tr(UNSPENDABLE,sortedmulti_a(3,A,B,C))"
+        );
+
+        // A single key threshold keeps its key spend in taproot
+        let policy: Concrete<String> = policy_str!("or(pk(A),pk(B))");
+        assert_eq!(
+            policy
+                .compile_to_descriptor(DescriptorCtx::Tr(None))
+                .unwrap(),
+            policy.compile_tr(None).unwrap()
+        );
+
+        // Other policies are compiled as usual
+        let policy: Concrete<String> = policy_str!("thresh(2,pk(A),pk(B),older(144))");
+        assert_eq!(
+            policy.compile_to_descriptor(DescriptorCtx::Wsh).unwrap(),
+            Descriptor::new_wsh(policy.compile::<Segwitv0>().unwrap()).unwrap()
+        );
+        assert!(policy
+            .compile_to_descriptor(DescriptorCtx::Tr(None))
+            .is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "compiler")]
+    fn taproot_compile_odds() {
+        // The odds of nested disjunctions multiply: C and D are the likeliest
+        // leaf with 3/4 * 9/10, and A and B are only more likely than E and F
+        let policy: Concrete<String> =
+            policy_str!("or(1@and(pk(A),pk(B)),3@or(9@and(pk(C),pk(D)),1@and(pk(E),pk(F))))");
+        let descriptor = policy.compile_tr(Some("UNSPENDABLE".to_string())).unwrap();
+        let tr = match descriptor {
+            Descriptor::Tr(ref tr) => tr,
+            _ => unreachable!(),
+        };
+        let leaves = tr
+            .iter_scripts()
+            .map(|(depth, ms)| (depth, ms.to_string()))
+            .collect::<Vec<_>>();
+        assert!(leaves.contains(&(1, "and_v(v:pk(C),pk(D))".to_string())));
+        assert!(leaves.contains(&(2, "and_v(v:pk(A),pk(B))".to_string())));
+        assert!(leaves.contains(&(2, "and_v(v:pk(E),pk(F))".to_string())));
+
+        // Odds adding up to more than `usize::MAX` are an error, not a panic
+        let policy: Concrete<String> = policy_str!("or({}@pk(A),1@pk(B))", core::usize::MAX);
+        let overflow = super::concrete::PolicyError::OddsOverflow;
+        assert_eq!(
+            policy.compile::<Segwitv0>(),
+            Err(super::compiler::CompilerError::PolicyError(overflow))
+        );
+        assert_eq!(
+            super::compiler::best_compilation::<_, Segwitv0>(&policy),
+            Err(super::compiler::CompilerError::PolicyError(overflow))
+        );
+        assert_eq!(
+            policy.compile_tr(Some("UNSPENDABLE".to_string())),
+            Err(crate::Error::PolicyError(overflow))
+        );
+    }
+
     #[test]
     #[cfg(feature = "compiler")]
     fn taproot_compile() {
@@ -487,4 +721,88 @@ mod tests {
             assert_eq!(descriptor, expected_descriptor);
         }
     }
+
+    #[test]
+    #[cfg(feature = "compiler")]
+    fn taproot_compile_with_key_reuse() {
+        let unspendable_key: String = "UNSPENDABLE".to_string();
+        let leaves = |descriptor: &Descriptor<String>| match descriptor {
+            Descriptor::Tr(tr) => tr
+                .iter_scripts()
+                .map(|(_, ms)| ms.to_string())
+                .collect::<Vec<_>>(),
+            _ => unreachable!(),
+        };
+
+        let policy: Concrete<String> = policy_str!("or(and(pk(A),pk(B)),and(pk(A),pk(D)))");
+        assert!(policy.compile_tr(Some(unspendable_key.clone())).is_err());
+        let descriptor = policy
+            .compile_tr_with_key_reuse(Some(unspendable_key.clone()))
+            .unwrap();
+        assert_eq!(
+            leaves(&descriptor),
+            vec!["and_v(v:pk(A),pk(D))", "and_v(v:pk(A),pk(B))"]
+        );
+
+        // The leaves using the extracted internal key are dropped
+        let policy: Concrete<String> = policy_str!("or(pk(A),and(pk(A),pk(B)))");
+        let descriptor = policy.compile_tr_with_key_reuse(None).unwrap();
+        assert_eq!(descriptor.to_string_no_checksum(), "tr(A)");
+
+        // Keys may not be reused within a leaf
+        let policy: Concrete<String> = policy_str!("or(pk(B),and(pk(A),or(pk(A),pk(C))))");
+        assert_eq!(
+            policy
+                .compile_tr_with_key_reuse(Some(unspendable_key.clone()))
+                .unwrap_err()
+                .to_string(),
+            "Policy contains duplicate keys"
+        );
+
+        // The unspendable key is used by a leaf
+        let policy: Concrete<String> = policy_str!("or(and(pk(A),pk(B)),and(pk(C),pk(D)))");
+        assert_eq!(
+            policy
+                .compile_tr(Some("A".to_string()))
+                .unwrap_err()
+                .to_string(),
+            "Policy contains duplicate keys"
+        );
+        match policy.compile_tr_with_key_reuse(Some("A".to_string())) {
+            Ok(Descriptor::Tr(tr)) => assert!(tr.reuses_internal_key()),
+            _ => panic!("expected a tr descriptor"),
+        }
+        match policy.compile_tr(Some(unspendable_key.clone())) {
+            Ok(Descriptor::Tr(tr)) => assert!(!tr.reuses_internal_key()),
+            _ => panic!("expected a tr descriptor"),
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "compiler")]
+    fn taproot_compile_with_weights() {
+        let unspendable_key: String = "UNSPENDABLE".to_string();
+        let policy: Concrete<String> =
+            policy_str!("thresh(1,and(pk(A),pk(B)),and(pk(C),pk(D)),and(pk(E),pk(F)))");
+        let depth_of = |descriptor: &Descriptor<String>, ms: &str| match descriptor {
+            Descriptor::Tr(tr) => tr
+                .iter_scripts()
+                .find(|(_, script)| script.to_string() == ms)
+                .map(|(depth, _)| depth)
+                .unwrap(),
+            _ => unreachable!(),
+        };
+
+        let descriptor = policy.compile_tr(Some(unspendable_key.clone())).unwrap();
+        assert_eq!(depth_of(&descriptor, "and_v(v:pk(E),pk(F))"), 2);
+
+        let mut weights = BTreeMap::new();
+        weights.insert(policy_str!("and(pk(E),pk(F))"), 10);
+        let descriptor = policy
+            .compile_tr_with_weights(Some(unspendable_key.clone()), &weights)
+            .unwrap();
+        assert_eq!(depth_of(&descriptor, "and_v(v:pk(E),pk(F))"), 1);
+        assert_eq!(depth_of(&descriptor, "and_v(v:pk(A),pk(B))"), 2);
+        assert_eq!(depth_of(&descriptor, "and_v(v:pk(C),pk(D))"), 2);
+    }
 }
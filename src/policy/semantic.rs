@@ -15,15 +15,17 @@
 //! Abstract Policies
 
 use core::str::FromStr;
-use core::{fmt, str};
+use core::{fmt, mem, str};
 
-use bitcoin::hashes::hex::FromHex;
-use bitcoin::hashes::{hash160, ripemd160, sha256, sha256d};
+use bitcoin::hashes::Hash;
 
 use super::concrete::PolicyError;
 use super::ENTAILMENT_MAX_TERMINALS;
 use crate::prelude::*;
-use crate::{errstr, expression, timelock, Error, ForEach, ForEachKey, MiniscriptKey};
+use crate::{
+    errstr, expression, timelock, Error, ForEach, ForEachKey, MiniscriptKey, Preimage32, Satisfier,
+    ToPublicKey, Translator,
+};
 
 /// Abstract policy which corresponds to the semantics of a Miniscript
 /// and which allows complex forms of analysis, e.g. filtering and
@@ -44,19 +46,29 @@ pub enum Policy<Pk: MiniscriptKey> {
     /// A relative locktime restriction
     Older(u32),
     /// A SHA256 whose preimage must be provided to satisfy the descriptor
-    Sha256(sha256::Hash),
+    Sha256(Pk::Sha256),
     /// A SHA256d whose preimage must be provided to satisfy the descriptor
-    Hash256(sha256d::Hash),
+    Hash256(Pk::Hash256),
     /// A RIPEMD160 whose preimage must be provided to satisfy the descriptor
-    Ripemd160(ripemd160::Hash),
+    Ripemd160(Pk::Ripemd160),
     /// A HASH160 whose preimage must be provided to satisfy the descriptor
-    Hash160(hash160::Hash),
+    Hash160(Pk::Hash160),
     /// A set of descriptors, satisfactions must be provided for `k` of them
     Threshold(usize, Vec<Policy<Pk>>),
 }
 
 impl<Pk: MiniscriptKey> ForEachKey<Pk> for Policy<Pk> {
     fn for_each_key<'a, F: FnMut(ForEach<'a, Pk>) -> bool>(&'a self, mut pred: F) -> bool
+    where
+        Pk: 'a,
+        Pk::Hash: 'a,
+    {
+        self.real_for_each_key(&mut pred)
+    }
+}
+
+impl<Pk: MiniscriptKey> Policy<Pk> {
+    fn real_for_each_key<'a, F: FnMut(ForEach<'a, Pk>) -> bool>(&'a self, pred: &mut F) -> bool
     where
         Pk: 'a,
         Pk::Hash: 'a,
@@ -70,7 +82,7 @@ impl<Pk: MiniscriptKey> ForEachKey<Pk> for Policy<Pk> {
             | Policy::Hash160(..)
             | Policy::After(..)
             | Policy::Older(..) => true,
-            Policy::Threshold(_, ref subs) => subs.iter().all(|sub| sub.for_each_key(&mut pred)),
+            Policy::Threshold(_, ref subs) => subs.iter().all(|sub| sub.real_for_each_key(pred)),
         }
     }
 }
@@ -82,47 +94,67 @@ impl<Pk: MiniscriptKey> Policy<Pk> {
     /// # Example
     ///
     /// ```
-    /// use miniscript::{bitcoin::{hashes::hash160, PublicKey}, policy::semantic::Policy};
+    /// use miniscript::bitcoin::{hashes::{hash160, hex}, PublicKey};
+    /// use miniscript::{policy::semantic::Policy, translate_hash_fail, Translator};
     /// use std::str::FromStr;
     /// let alice_pkh = "236ada020df3208d2517f4b0db03e16f92cd8cf1";
     /// let bob_pkh = "3e89b972416ae33870b4634d03b8cdc773200cac";
     /// let placeholder_policy = Policy::<String>::from_str("and(pkh(alice_pkh),pkh(bob_pkh))").unwrap();
     ///
-    /// let real_policy = placeholder_policy.translate_pkh(|placeholder| match placeholder.as_str() {
-    ///     "alice_pkh" => hash160::Hash::from_str(alice_pkh),
-    ///     "bob_pkh"   => hash160::Hash::from_str(bob_pkh),
-    ///     _ => panic!("unknown key hash!")
-    /// }).unwrap();
+    /// struct StrPkhTranslator {
+    ///     alice_pkh: &'static str,
+    ///     bob_pkh: &'static str,
+    /// }
+    ///
+    /// impl Translator<String, PublicKey, hex::Error> for StrPkhTranslator {
+    ///     fn pk(&mut self, _pk: &String) -> Result<PublicKey, hex::Error> {
+    ///         unreachable!("Policy does not contain any pk fragment");
+    ///     }
+    ///
+    ///     fn pkh(&mut self, pkh: &String) -> Result<hash160::Hash, hex::Error> {
+    ///         match pkh.as_str() {
+    ///             "alice_pkh" => hash160::Hash::from_str(self.alice_pkh),
+    ///             "bob_pkh"   => hash160::Hash::from_str(self.bob_pkh),
+    ///             _ => panic!("unknown key hash!")
+    ///         }
+    ///     }
+    ///
+    ///     // The policy has no hashes
+    ///     translate_hash_fail!(String, PublicKey, hex::Error);
+    /// }
+    ///
+    /// let mut translator = StrPkhTranslator { alice_pkh, bob_pkh };
+    /// let real_policy = placeholder_policy.translate_pkh(&mut translator).unwrap();
     ///
     /// let expected_policy = Policy::<PublicKey>::from_str(&format!("and(pkh({}),pkh({}))", alice_pkh, bob_pkh)).unwrap();
     /// assert_eq!(real_policy, expected_policy);
     /// ```
-    pub fn translate_pkh<Fpkh, Q, E>(&self, mut fpkh: Fpkh) -> Result<Policy<Q>, E>
+    pub fn translate_pkh<Q, E, T>(&self, t: &mut T) -> Result<Policy<Q>, E>
     where
-        Fpkh: FnMut(&Pk::Hash) -> Result<Q::Hash, E>,
+        T: Translator<Pk, Q, E>,
         Q: MiniscriptKey,
     {
-        self._translate_pkh(&mut fpkh)
+        self._translate_pkh(t)
     }
 
-    fn _translate_pkh<Fpkh, Q, E>(&self, fpkh: &mut Fpkh) -> Result<Policy<Q>, E>
+    fn _translate_pkh<Q, E, T>(&self, t: &mut T) -> Result<Policy<Q>, E>
     where
-        Fpkh: FnMut(&Pk::Hash) -> Result<Q::Hash, E>,
+        T: Translator<Pk, Q, E>,
         Q: MiniscriptKey,
     {
         match *self {
             Policy::Unsatisfiable => Ok(Policy::Unsatisfiable),
             Policy::Trivial => Ok(Policy::Trivial),
-            Policy::KeyHash(ref pkh) => fpkh(pkh).map(Policy::KeyHash),
-            Policy::Sha256(ref h) => Ok(Policy::Sha256(*h)),
-            Policy::Hash256(ref h) => Ok(Policy::Hash256(*h)),
-            Policy::Ripemd160(ref h) => Ok(Policy::Ripemd160(*h)),
-            Policy::Hash160(ref h) => Ok(Policy::Hash160(*h)),
+            Policy::KeyHash(ref pkh) => t.pkh(pkh).map(Policy::KeyHash),
+            Policy::Sha256(ref h) => t.sha256(h).map(Policy::Sha256),
+            Policy::Hash256(ref h) => t.hash256(h).map(Policy::Hash256),
+            Policy::Ripemd160(ref h) => t.ripemd160(h).map(Policy::Ripemd160),
+            Policy::Hash160(ref h) => t.hash160(h).map(Policy::Hash160),
             Policy::After(n) => Ok(Policy::After(n)),
             Policy::Older(n) => Ok(Policy::Older(n)),
             Policy::Threshold(k, ref subs) => {
                 let new_subs: Result<Vec<Policy<Q>>, _> =
-                    subs.iter().map(|sub| sub._translate_pkh(fpkh)).collect();
+                    subs.iter().map(|sub| sub._translate_pkh(t)).collect();
                 new_subs.map(|ok| Policy::Threshold(k, ok))
             }
         }
@@ -160,6 +192,24 @@ impl<Pk: MiniscriptKey> Policy<Pk> {
         }
     }
 
+    /// This function computes whether the current policy is logically
+    /// equivalent to the second one, that is whether both policies entail each
+    /// other. Timelocks and hashes are compared by value, so `after(100)` and
+    /// `after(200)` are treated as unrelated conditions.
+    ///
+    /// Both policies are first brought into a canonical form (nested `and`s and
+    /// `or`s flattened, branches sorted and deduplicated) so that policies that
+    /// differ only in the way they are written are recognized quickly; only
+    /// when that is not enough does this fall back to
+    /// [`Policy::entails`], which has the same limit on the number of terminals.
+    pub fn is_equivalent(&self, other: &Policy<Pk>) -> Result<bool, PolicyError> {
+        let (a, b) = (self.clone().canonicalized(), other.clone().canonicalized());
+        if a == b {
+            return Ok(true);
+        }
+        Ok(a.clone().entails(b.clone())? && b.entails(a)?)
+    }
+
     // Helper function to compute the number of constraints in policy.
     fn n_terminals(&self) -> usize {
         match self {
@@ -221,14 +271,15 @@ impl<Pk: MiniscriptKey> fmt::Debug for Policy<Pk> {
             Policy::KeyHash(ref pkh) => write!(f, "pkh({:?})", pkh),
             Policy::After(n) => write!(f, "after({})", n),
             Policy::Older(n) => write!(f, "older({})", n),
-            Policy::Sha256(h) => write!(f, "sha256({})", h),
-            Policy::Hash256(h) => write!(f, "hash256({})", h),
-            Policy::Ripemd160(h) => write!(f, "ripemd160({})", h),
-            Policy::Hash160(h) => write!(f, "hash160({})", h),
+            Policy::Sha256(ref h) => write!(f, "sha256({})", h),
+            Policy::Hash256(ref h) => write!(f, "hash256({})", h),
+            Policy::Ripemd160(ref h) => write!(f, "ripemd160({})", h),
+            Policy::Hash160(ref h) => write!(f, "hash160({})", h),
             Policy::Threshold(k, ref subs) => {
-                if k == subs.len() {
+                // `and` and `or` need at least two arguments
+                if k == subs.len() && k > 1 {
                     write!(f, "and(")?;
-                } else if k == 1 {
+                } else if k == 1 && subs.len() > 1 {
                     write!(f, "or(")?;
                 } else {
                     write!(f, "thresh({},", k)?;
@@ -254,14 +305,15 @@ impl<Pk: MiniscriptKey> fmt::Display for Policy<Pk> {
             Policy::KeyHash(ref pkh) => write!(f, "pkh({})", pkh),
             Policy::After(n) => write!(f, "after({})", n),
             Policy::Older(n) => write!(f, "older({})", n),
-            Policy::Sha256(h) => write!(f, "sha256({})", h),
-            Policy::Hash256(h) => write!(f, "hash256({})", h),
-            Policy::Ripemd160(h) => write!(f, "ripemd160({})", h),
-            Policy::Hash160(h) => write!(f, "hash160({})", h),
+            Policy::Sha256(ref h) => write!(f, "sha256({})", h),
+            Policy::Hash256(ref h) => write!(f, "hash256({})", h),
+            Policy::Ripemd160(ref h) => write!(f, "ripemd160({})", h),
+            Policy::Hash160(ref h) => write!(f, "hash160({})", h),
             Policy::Threshold(k, ref subs) => {
-                if k == subs.len() {
+                // `and` and `or` need at least two arguments
+                if k == subs.len() && k > 1 {
                     write!(f, "and(")?;
-                } else if k == 1 {
+                } else if k == 1 && subs.len() > 1 {
                     write!(f, "or(")?;
                 } else {
                     write!(f, "thresh({},", k)?;
@@ -283,8 +335,16 @@ impl<Pk> str::FromStr for Policy<Pk>
 where
     Pk: MiniscriptKey + str::FromStr,
     Pk::Hash: str::FromStr,
+    Pk::Sha256: str::FromStr,
+    Pk::Hash256: str::FromStr,
+    Pk::Ripemd160: str::FromStr,
+    Pk::Hash160: str::FromStr,
     <Pk as str::FromStr>::Err: ToString,
     <<Pk as MiniscriptKey>::Hash as str::FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Sha256 as str::FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Hash256 as str::FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Ripemd160 as str::FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Hash160 as str::FromStr>::Err: ToString,
 {
     type Err = Error;
 
@@ -306,8 +366,16 @@ impl<Pk> expression::FromTree for Policy<Pk>
 where
     Pk: MiniscriptKey + str::FromStr,
     Pk::Hash: str::FromStr,
+    Pk::Sha256: str::FromStr,
+    Pk::Hash256: str::FromStr,
+    Pk::Ripemd160: str::FromStr,
+    Pk::Hash160: str::FromStr,
     <Pk as str::FromStr>::Err: ToString,
     <<Pk as MiniscriptKey>::Hash as str::FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Sha256 as str::FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Hash256 as str::FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Ripemd160 as str::FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Hash160 as str::FromStr>::Err: ToString,
 {
     fn from_tree(top: &expression::Tree) -> Result<Policy<Pk>, Error> {
         match (top.name, top.args.len()) {
@@ -323,16 +391,16 @@ where
                 expression::parse_num(x).map(Policy::Older)
             }),
             ("sha256", 1) => expression::terminal(&top.args[0], |x| {
-                sha256::Hash::from_hex(x).map(Policy::Sha256)
+                Pk::Sha256::from_str(x).map(Policy::Sha256)
             }),
             ("hash256", 1) => expression::terminal(&top.args[0], |x| {
-                sha256d::Hash::from_hex(x).map(Policy::Hash256)
+                Pk::Hash256::from_str(x).map(Policy::Hash256)
             }),
             ("ripemd160", 1) => expression::terminal(&top.args[0], |x| {
-                ripemd160::Hash::from_hex(x).map(Policy::Ripemd160)
+                Pk::Ripemd160::from_str(x).map(Policy::Ripemd160)
             }),
             ("hash160", 1) => expression::terminal(&top.args[0], |x| {
-                hash160::Hash::from_hex(x).map(Policy::Hash160)
+                Pk::Hash160::from_str(x).map(Policy::Hash160)
             }),
             ("and", nsubs) => {
                 if nsubs < 2 {
@@ -365,12 +433,9 @@ where
 
                 let thresh = expression::parse_num(top.args[0].name)?;
 
-                // thresh(1) and thresh(n) are disallowed in semantic policies
-                if thresh <= 1 || thresh >= (nsubs as u32 - 1) {
-                    return Err(errstr(
-                        "Semantic Policy thresh cannot have k = 1 or k =n, use `and`/`or` instead",
-                    ));
-                }
+                // Unlike in concrete policies, `k` may be equal to 1 or to
+                // the number of subpolicies, which is how lifted thresholds
+                // over a single subpolicy are displayed
                 if thresh >= (nsubs as u32) {
                     return Err(errstr(top.args[0].name));
                 }
@@ -560,6 +625,39 @@ impl<Pk: MiniscriptKey> Policy<Pk> {
         self.normalized()
     }
 
+    /// Filter a policy by replacing the hash constraints whose preimage is
+    /// provided by `satisfier` with trivially satisfied ones, leaving the
+    /// constraints which remain to be satisfied.
+    ///
+    /// Preimages which do not match their hash are ignored.
+    pub fn substitute_hash_preimages<S: Satisfier<Pk>>(mut self, satisfier: &S) -> Policy<Pk>
+    where
+        Pk: ToPublicKey,
+    {
+        fn known<H: Hash>(hash: H, preimage: Option<Preimage32>) -> bool {
+            preimage.map_or(false, |preimage| <H as Hash>::hash(&preimage) == hash)
+        }
+
+        let revealed = match self {
+            Policy::Sha256(ref h) => known(Pk::to_sha256(h), satisfier.lookup_sha256(h)),
+            Policy::Hash256(ref h) => known(Pk::to_hash256(h), satisfier.lookup_hash256(h)),
+            Policy::Ripemd160(ref h) => known(Pk::to_ripemd160(h), satisfier.lookup_ripemd160(h)),
+            Policy::Hash160(ref h) => known(Pk::to_hash160(h), satisfier.lookup_hash160(h)),
+            _ => false,
+        };
+        self = match self {
+            _ if revealed => Policy::Trivial,
+            Policy::Threshold(k, subs) => Policy::Threshold(
+                k,
+                subs.into_iter()
+                    .map(|sub| sub.substitute_hash_preimages(satisfier))
+                    .collect(),
+            ),
+            x => x,
+        };
+        self.normalized()
+    }
+
     /// Count the number of public keys and keyhashes referenced in a policy.
     /// Duplicate keys will be double-counted.
     pub fn n_keys(&self) -> usize {
@@ -605,6 +703,83 @@ impl<Pk: MiniscriptKey> Policy<Pk> {
     }
 }
 
+// Remove the sets which are duplicates or strict supersets of another set
+fn minimize<T: Ord + Clone>(mut sets: Vec<BTreeSet<T>>) -> Vec<BTreeSet<T>> {
+    sets.sort_by_key(|set| set.len());
+    sets.dedup();
+    let mut ret: Vec<BTreeSet<T>> = Vec::with_capacity(sets.len());
+    for set in sets {
+        if !ret.iter().any(|smaller| smaller.is_subset(&set)) {
+            ret.push(set);
+        }
+    }
+    ret
+}
+
+impl<Pk: MiniscriptKey> Policy<Pk> {
+    /// Enumerate the minimal sets of keys whose signatures satisfy the
+    /// policy, assuming that all timelocks are met and all hash preimages are
+    /// known.
+    ///
+    /// No returned set is a superset of another. A policy which requires no
+    /// signature returns a single empty set, and an unsatisfiable policy
+    /// returns no set at all.
+    ///
+    /// # Errors
+    ///
+    /// As the number of sets grows exponentially with the size of the
+    /// thresholds, this returns [`PolicyError::MaxSatisfyingSets`] as soon as
+    /// more than `max_sets` sets are found for the policy or any of its
+    /// sub-policies.
+    pub fn minimal_satisfying_sets(
+        &self,
+        max_sets: usize,
+    ) -> Result<Vec<BTreeSet<Pk::Hash>>, PolicyError> {
+        let sets = match *self {
+            Policy::Unsatisfiable => vec![],
+            Policy::KeyHash(ref pkh) => {
+                let mut set = BTreeSet::new();
+                set.insert(pkh.clone());
+                vec![set]
+            }
+            Policy::Trivial
+            | Policy::After(..)
+            | Policy::Older(..)
+            | Policy::Sha256(..)
+            | Policy::Hash256(..)
+            | Policy::Ripemd160(..)
+            | Policy::Hash160(..) => vec![BTreeSet::new()],
+            Policy::Threshold(k, ref subs) => {
+                // by_count[j] holds the sets satisfying j of the subs seen
+                // so far
+                let mut by_count = vec![vec![]; k + 1];
+                by_count[0].push(BTreeSet::new());
+                for sub in subs {
+                    let sub_sets = sub.minimal_satisfying_sets(max_sets)?;
+                    for j in (1..k + 1).rev() {
+                        let mut sets = mem::take(&mut by_count[j]);
+                        for set in &by_count[j - 1] {
+                            for sub_set in &sub_sets {
+                                sets.push(set.union(sub_set).cloned().collect());
+                            }
+                        }
+                        let sets = minimize(sets);
+                        if sets.len() > max_sets {
+                            return Err(PolicyError::MaxSatisfyingSets(max_sets));
+                        }
+                        by_count[j] = sets;
+                    }
+                }
+                by_count.pop().expect("k + 1 > 0")
+            }
+        };
+        if sets.len() > max_sets {
+            return Err(PolicyError::MaxSatisfyingSets(max_sets));
+        }
+        Ok(sets)
+    }
+}
+
 impl<Pk: MiniscriptKey> Policy<Pk> {
     /// "Sort" a policy to bring it into a canonical form to allow comparisons.
     /// Does **not** allow policies to be compared for functional equivalence;
@@ -620,6 +795,40 @@ impl<Pk: MiniscriptKey> Policy<Pk> {
             x => x,
         }
     }
+
+    // Helper function for `is_equivalent`: normalize, sort and remove
+    // duplicate branches of `and`s and `or`s until a fixpoint is reached.
+    fn canonicalized(self) -> Policy<Pk> {
+        let mut pol = self.normalized().sorted();
+        loop {
+            let next = pol.clone().dedup_branches().normalized().sorted();
+            if next == pol {
+                return pol;
+            }
+            pol = next;
+        }
+    }
+
+    // Remove duplicate branches of `and`s and `or`s, which does not change
+    // the semantics of the policy. Assumes the branches are sorted.
+    fn dedup_branches(self) -> Policy<Pk> {
+        match self {
+            Policy::Threshold(k, subs) => {
+                let n = subs.len();
+                let mut subs: Vec<_> = subs.into_iter().map(Policy::dedup_branches).collect();
+                if k == 1 {
+                    subs.dedup();
+                    Policy::Threshold(1, subs)
+                } else if k == n {
+                    subs.dedup();
+                    Policy::Threshold(subs.len(), subs)
+                } else {
+                    Policy::Threshold(k, subs)
+                }
+            }
+            x => x,
+        }
+    }
 }
 
 #[cfg(test)]
@@ -650,6 +859,30 @@ mod tests {
         .is_ok());
     }
 
+    #[test]
+    fn lifted_policy_roundtrip() {
+        use crate::policy::Liftable;
+        use crate::{Miniscript, Segwitv0};
+
+        for ms in &[
+            "multi(1,A)",
+            "and_v(v:pk(A),multi(2,B,C))",
+            "or_d(multi(1,A),and_v(v:thresh(2,pkh(B),a:pkh(C),a:pkh(D)),older(144)))",
+            "thresh(3,pk(A),s:pk(B),s:pk(C),sln:after(100))",
+            "andor(pk(A),sha256(1111111111111111111111111111111111111111111111111111111111111111),or_i(pk(B),and_v(v:pk(C),after(10))))",
+        ] {
+            let ms = Miniscript::<String, Segwitv0>::from_str_insane(ms).unwrap();
+            let policy = ms.lift().unwrap();
+            assert_eq!(StringPolicy::from_str(&policy.to_string()).unwrap(), policy);
+        }
+
+        let policy = StringPolicy::from_str("thresh(1,pkh(A))").unwrap();
+        assert_eq!(policy.to_string(), "thresh(1,pkh(A))");
+        let policy = StringPolicy::from_str("thresh(2,pkh(A),thresh(1,pkh(B),pkh(C)))").unwrap();
+        assert_eq!(policy.to_string(), "and(pkh(A),or(pkh(B),pkh(C)))");
+        assert!(StringPolicy::from_str("thresh(3,pkh(A),pkh(B))").is_err());
+    }
+
     #[test]
     fn semantic_analysis() {
         let policy = StringPolicy::from_str("pkh()").unwrap();
@@ -827,6 +1060,36 @@ mod tests {
             .unwrap());
     }
 
+    #[test]
+    fn equivalence() {
+        let policy = |s: &str| StringPolicy::from_str(s).unwrap();
+
+        // Same policy written differently
+        let a = policy("and(pkh(A),or(pkh(B),and(pkh(C),pkh(C))))");
+        let b = policy("and(or(pkh(C),pkh(B)),pkh(A))");
+        assert!(a.is_equivalent(&b).unwrap());
+        assert!(b.is_equivalent(&a).unwrap());
+
+        // Equivalence that needs more than rewriting: distributivity
+        let a = policy("and(pkh(A),or(pkh(B),pkh(C)))");
+        let b = policy("or(and(pkh(A),pkh(B)),and(pkh(C),pkh(A)))");
+        assert!(a.is_equivalent(&b).unwrap());
+
+        // thresh(2,A,B,C) is or(and(A,B),and(A,C),and(B,C))
+        let a = policy("thresh(2,pkh(A),pkh(B),pkh(C))");
+        let b = policy("or(and(pkh(A),pkh(B)),or(and(pkh(C),pkh(A)),and(pkh(B),pkh(C))))");
+        assert!(a.is_equivalent(&b).unwrap());
+
+        // One way entailment only
+        let a = policy("and(pkh(A),pkh(B))");
+        let b = policy("or(pkh(A),pkh(B))");
+        assert!(!a.is_equivalent(&b).unwrap());
+        assert!(!b.is_equivalent(&a).unwrap());
+        assert!(!policy("after(100)")
+            .is_equivalent(&policy("after(200)"))
+            .unwrap());
+    }
+
     #[test]
     fn entailment_escrow() {
         // Escrow contract
@@ -875,4 +1138,42 @@ mod tests {
         assert!(auth_alice.entails(htlc_pol.clone()).unwrap());
         assert!(htlc_pol.entails(control_alice).unwrap());
     }
+
+    #[test]
+    fn minimal_satisfying_sets() {
+        let policy = |s: &str| StringPolicy::from_str(s).unwrap();
+        let sets = |keys: &[&[&str]]| -> Vec<BTreeSet<String>> {
+            keys.iter()
+                .map(|set| set.iter().map(|k| k.to_string()).collect())
+                .collect()
+        };
+
+        assert_eq!(
+            policy("thresh(2,pkh(A),pkh(B),pkh(C))").minimal_satisfying_sets(10),
+            Ok(sets(&[&["A", "B"], &["A", "C"], &["B", "C"]]))
+        );
+        // Supersets of other sets are left out
+        assert_eq!(
+            policy("or(pkh(A),and(pkh(A),pkh(B)))").minimal_satisfying_sets(10),
+            Ok(sets(&[&["A"]]))
+        );
+        // Timelocks and hashes are assumed to be met
+        assert_eq!(
+            policy("or(and(pkh(A),older(144)),and(pkh(B),pkh(C)))").minimal_satisfying_sets(10),
+            Ok(sets(&[&["A"], &["B", "C"]]))
+        );
+        assert_eq!(
+            policy("after(100)").minimal_satisfying_sets(10),
+            Ok(sets(&[&[]]))
+        );
+        assert_eq!(
+            policy("UNSATISFIABLE").minimal_satisfying_sets(10),
+            Ok(vec![])
+        );
+
+        assert_eq!(
+            policy("thresh(2,pkh(A),pkh(B),pkh(C))").minimal_satisfying_sets(2),
+            Err(PolicyError::MaxSatisfyingSets(2))
+        );
+    }
 }
@@ -19,17 +19,171 @@
 //! `https://github.com/bitcoin/bips/blob/master/bip-0174.mediawiki`
 //!
 
+use bitcoin::blockdata::opcodes;
+use bitcoin::blockdata::script::Instruction;
 use bitcoin::blockdata::witness::Witness;
+use bitcoin::hashes::{hash160, ripemd160, sha256, sha256d, Hash};
 use bitcoin::secp256k1::{self, Secp256k1};
+use bitcoin::util::bip32::{DerivationPath, Fingerprint};
 use bitcoin::util::key::XOnlyPublicKey;
 use bitcoin::util::sighash::Prevouts;
-use bitcoin::util::taproot::LeafVersion;
-use bitcoin::{self, PublicKey, Script, TxOut};
+use bitcoin::util::taproot::{LeafVersion, TapLeafHash};
+use bitcoin::{
+    self, EcdsaSig, EcdsaSighashType, PublicKey, SchnorrSig, SchnorrSighashType, Script, TxOut,
+};
 
-use super::{sanity_check, Error, InputError, Psbt, PsbtInputSatisfier};
+use super::{
+    sanity_check_with, BlockTime, Error, HashDigest, InputError, Psbt, PsbtInputSatisfier,
+    TapLeafFailure, TapLeafFailureReason,
+};
+use crate::descriptor::SinglePubKey;
+use crate::miniscript::limits::RuleSet;
+use crate::miniscript::satisfy::{DiagnosticSatisfier, MissingItem};
 use crate::prelude::*;
 use crate::util::witness_size;
-use crate::{interpreter, BareCtx, Descriptor, Legacy, Miniscript, Satisfier, Segwitv0, Tap};
+use crate::{
+    interpreter, BareCtx, Descriptor, Legacy, Miniscript, MiniscriptKey, Satisfier, Segwitv0, Tap,
+};
+
+// The taproot spend paths the finalizer may choose from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum TapSpendPath {
+    // The key spend if signed, otherwise the smallest satisfiable leaf
+    Any,
+    // Only the key spend
+    Key,
+    // Only the script path of the given leaf
+    Leaf(TapLeafHash),
+}
+
+/// Data computed while finalizing psbt inputs which later finalization
+/// attempts can reuse, e.g. when trying to finalize an input again each time
+/// one of its signatures arrives. See
+/// [`PsbtExt::finalize_inp_mut_with_context`](super::PsbtExt::finalize_inp_mut_with_context).
+///
+/// The descriptors inferred for non-taproot inputs and the hashes and
+/// miniscripts of taproot leaves are cached, keyed by the scripts they were
+/// computed from. A context therefore stays valid as the psbt is updated, and
+/// can be shared across inputs and psbts.
+#[derive(Debug, Clone, Default)]
+pub struct FinalizationContext {
+    // Descriptors inferred from a script pubkey, redeem script and
+    // witness script
+    descriptors: BTreeMap<DescriptorScripts, Descriptor<PublicKey>>,
+    // Leaf hashes and, for tapscript leaves which parse, miniscripts
+    tap_leaves: BTreeMap<(Script, LeafVersion), TapLeaf>,
+    // How to choose between the taproot leaves which can be satisfied, or
+    // `None` to always pick the one with the smallest witness
+    leaf_selection: Option<LeafSelection>,
+    // The rules the satisfactions must follow
+    rules: RuleSet,
+}
+
+// Random choice of a taproot leaf among the ones whose witness is at most
+// `tolerance` weight units heavier than the lightest one
+#[derive(Debug, Clone)]
+struct LeafSelection {
+    tolerance: usize,
+    // State of a splitmix64 generator
+    state: u64,
+}
+
+impl LeafSelection {
+    // A random index below `n`
+    fn choose(&mut self, n: usize) -> usize {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^= z >> 31;
+        (z % n as u64) as usize
+    }
+}
+
+// The script pubkey, redeem script and witness script of an input
+type DescriptorScripts = (Script, Option<Script>, Option<Script>);
+type TapLeaf = (TapLeafHash, Option<Miniscript<XOnlyPublicKey, Tap>>);
+
+impl FinalizationContext {
+    /// Create an empty context
+    pub fn new() -> Self {
+        FinalizationContext::default()
+    }
+
+    /// Create an empty context which, when several taproot leaves of an input
+    /// can be satisfied, picks one at random among those whose witness is at
+    /// most `tolerance` weight units heavier than the lightest one.
+    ///
+    /// Always spending the cheapest leaf lets observers tell wallets apart by
+    /// the paths they use. The choice is made by a generator seeded with
+    /// `seed`, so the same seed reproduces the same choices. Key spends are
+    /// still preferred whenever a key signature is available.
+    ///
+    /// Only the choice between the leaves of taproot inputs is randomized. The
+    /// satisfaction of the chosen leaf, and those of inputs which are not
+    /// taproot, are still picked deterministically among satisfactions of
+    /// equal weight.
+    pub fn with_random_tap_leaf_selection(tolerance: usize, seed: u64) -> Self {
+        FinalizationContext {
+            leaf_selection: Some(LeafSelection {
+                tolerance,
+                state: seed,
+            }),
+            ..Default::default()
+        }
+    }
+
+    /// Create an empty context choosing taproot leaves at random as
+    /// [`FinalizationContext::with_random_tap_leaf_selection`] does, with a
+    /// seed drawn from the thread random number generator
+    #[cfg(feature = "rand")]
+    pub fn with_random_tap_leaf_selection_from_entropy(tolerance: usize) -> Self {
+        Self::with_random_tap_leaf_selection(tolerance, secp256k1::rand::random())
+    }
+
+    // An empty context whose satisfactions must follow `rules`
+    fn with_rules(rules: &RuleSet) -> Self {
+        FinalizationContext {
+            rules: rules.clone(),
+            ..Default::default()
+        }
+    }
+
+    // The descriptor of the psbt input, inferred by `get_descriptor` unless it
+    // was already inferred from the same scripts. Failures are not cached.
+    fn descriptor(
+        &mut self,
+        psbt: &Psbt,
+        index: usize,
+    ) -> Result<&Descriptor<PublicKey>, InputError> {
+        let inp = &psbt.inputs[index];
+        let scripts = (
+            get_scriptpubkey(psbt, index)?.clone(),
+            inp.redeem_script.clone(),
+            inp.witness_script.clone(),
+        );
+        if !self.descriptors.contains_key(&scripts) {
+            let desc = get_descriptor(psbt, index)?;
+            self.descriptors.insert(scripts.clone(), desc);
+        }
+        Ok(&self.descriptors[&scripts])
+    }
+
+    // The hash of a taproot leaf, and its miniscript if it is a tapscript
+    // leaf which parses
+    fn tap_leaf(&mut self, script: &Script, ver: LeafVersion) -> &TapLeaf {
+        self.tap_leaves
+            .entry((script.clone(), ver))
+            .or_insert_with(|| {
+                let ms = if ver == LeafVersion::TapScript {
+                    Miniscript::<XOnlyPublicKey, Tap>::parse_insane(script).ok()
+                } else {
+                    None
+                };
+                (TapLeafHash::from_script(script, ver), ms)
+            })
+    }
+}
 
 // Satisfy the taproot descriptor. It is not possible to infer the complete
 // descriptor from psbt because the information about all the scripts might not
@@ -39,56 +193,105 @@ fn construct_tap_witness(
     spk: &Script,
     sat: &PsbtInputSatisfier,
     allow_mall: bool,
+    path: TapSpendPath,
+    ctx: &mut FinalizationContext,
 ) -> Result<Vec<Vec<u8>>, InputError> {
     assert!(spk.is_v1_p2tr());
 
     // try the key spend path first
-    if let Some(sig) =
-        <PsbtInputSatisfier as Satisfier<XOnlyPublicKey>>::lookup_tap_key_spend_sig(sat)
-    {
-        return Ok(vec![sig.to_vec()]);
+    if let TapSpendPath::Any | TapSpendPath::Key = path {
+        if let Some(sig) =
+            <PsbtInputSatisfier as Satisfier<XOnlyPublicKey>>::lookup_tap_key_spend_sig(sat)
+        {
+            return Ok(vec![sig.to_vec()]);
+        }
+    }
+    if path == TapSpendPath::Key {
+        return Err(InputError::MissingTapKeySig);
     }
     // Next script spends
-    let (mut min_wit, mut min_wit_len) = (None, None);
+    let rules = ctx.rules.clone();
+    let mut witnesses = vec![];
+    let mut failures = vec![];
     if let Some(block_map) =
         <PsbtInputSatisfier as Satisfier<XOnlyPublicKey>>::lookup_tap_control_block_map(sat)
     {
         for (control_block, (script, ver)) in block_map {
+            let (leaf_hash, ref ms) = *ctx.tap_leaf(script, *ver);
+            if let TapSpendPath::Leaf(wanted) = path {
+                if leaf_hash != wanted {
+                    continue;
+                }
+            }
+            let mut fail = |reason| {
+                failures.push(TapLeafFailure { leaf_hash, reason });
+            };
             if *ver != LeafVersion::TapScript {
                 // We don't know how to satisfy non default version scripts yet
+                fail(TapLeafFailureReason::UnsupportedLeafVersion(*ver));
                 continue;
             }
-            let ms = match Miniscript::<XOnlyPublicKey, Tap>::parse_insane(script) {
-                Ok(ms) => ms,
-                Err(..) => continue, // try another script
-            };
-            let mut wit = if allow_mall {
-                match ms.satisfy_malleable(sat) {
-                    Ok(ms) => ms,
-                    Err(..) => continue,
+            let ms = match *ms {
+                Some(ref ms) => ms,
+                None => {
+                    // Parse again for the error, and try another script
+                    let e = Miniscript::<XOnlyPublicKey, Tap>::parse_insane(script)
+                        .expect_err("Miniscripts are only missing from the cache on errors");
+                    fail(TapLeafFailureReason::MiniscriptError(e));
+                    continue;
                 }
+            };
+            let diag_sat = DiagnosticSatisfier::new(sat);
+            let res = if allow_mall {
+                ms.satisfy_malleable_with_rules(&diag_sat, &rules)
             } else {
-                match ms.satisfy(sat) {
-                    Ok(ms) => ms,
-                    Err(..) => continue,
+                ms.satisfy_with_rules(&diag_sat, &rules)
+            };
+            let mut wit = match res {
+                Ok(wit) => wit,
+                Err(crate::Error::CouldNotSatisfy) => {
+                    fail(TapLeafFailureReason::Unsatisfied(diag_sat.missing()));
+                    continue;
+                }
+                Err(e) => {
+                    fail(TapLeafFailureReason::MiniscriptError(e));
+                    continue;
                 }
             };
             wit.push(ms.encode().into_bytes());
             wit.push(control_block.serialize());
-            let wit_len = Some(witness_size(&wit));
-            if min_wit_len.is_some() && wit_len > min_wit_len {
-                continue;
-            } else {
-                // store the minimum
-                min_wit = Some(wit);
-                min_wit_len = wit_len;
-            }
+            let wit_len = witness_size(&wit);
+            witnesses.push((wit, wit_len));
         }
-        min_wit.ok_or(InputError::CouldNotSatisfyTr)
-    } else {
-        // No control blocks found
-        Err(InputError::CouldNotSatisfyTr)
     }
+    if let TapSpendPath::Leaf(leaf_hash) = path {
+        if failures.is_empty() && witnesses.is_empty() {
+            return Err(InputError::MissingTapLeaf(leaf_hash));
+        }
+    }
+    let min_wit_len = match witnesses.iter().map(|&(_, len)| len).min() {
+        Some(len) => len,
+        // No control blocks found, or none of them could be satisfied
+        None => return Err(InputError::CouldNotSatisfyTr(failures)),
+    };
+    let wit = match ctx.leaf_selection {
+        Some(ref mut selection) => {
+            let max_len = min_wit_len.saturating_add(selection.tolerance);
+            witnesses.retain(|&(_, len)| len <= max_len);
+            let index = selection.choose(witnesses.len());
+            witnesses.swap_remove(index).0
+        }
+        // The last of the smallest witnesses
+        None => {
+            witnesses
+                .into_iter()
+                .rev()
+                .find(|&(_, len)| len == min_wit_len)
+                .expect("The minimum is one of the witnesses")
+                .0
+        }
+    };
+    Ok(wit)
 }
 
 // Get the scriptpubkey for the psbt input
@@ -251,6 +454,276 @@ fn get_descriptor(psbt: &Psbt, index: usize) -> Result<Descriptor<PublicKey>, In
     }
 }
 
+// The keys with known origins which could still sign this input to complete one
+// of its satisfactions. Empty if the input can already be satisfied.
+pub(super) fn missing_signers(
+    psbt: &Psbt,
+    index: usize,
+) -> Result<BTreeSet<(Fingerprint, DerivationPath, SinglePubKey)>, InputError> {
+    let inp = &psbt.inputs[index];
+    let spk = get_scriptpubkey(psbt, index)?;
+    let sat = PsbtInputSatisfier::new(psbt, index);
+    let mut signers = BTreeSet::new();
+
+    if spk.is_v1_p2tr() {
+        let mut ctx = FinalizationContext::new();
+        let failures = match construct_tap_witness(spk, &sat, false, TapSpendPath::Any, &mut ctx) {
+            Ok(_) => return Ok(signers),
+            Err(InputError::CouldNotSatisfyTr(failures)) => failures,
+            Err(e) => return Err(e),
+        };
+        // The internal key can always complete the key spend
+        let mut keys: BTreeSet<_> = inp.tap_internal_key.into_iter().collect();
+        for failure in failures {
+            if let TapLeafFailureReason::Unsatisfied(missing) = failure.reason {
+                for item in missing {
+                    if let MissingItem::Signature(pk) = item {
+                        keys.insert(pk);
+                    }
+                }
+            }
+        }
+        for pk in keys {
+            if let Some(&(_, (fingerprint, ref path))) = inp.tap_key_origins.get(&pk) {
+                signers.insert((fingerprint, path.clone(), SinglePubKey::XOnly(pk)));
+            }
+        }
+        return Ok(signers);
+    }
+
+    let mut insert_signer = |pk: &bitcoin::secp256k1::PublicKey| {
+        if let Some(&(fingerprint, ref path)) = inp.bip32_derivation.get(pk) {
+            let pk = PublicKey::new(*pk);
+            signers.insert((fingerprint, path.clone(), SinglePubKey::FullKey(pk)));
+        }
+    };
+    let desc = match get_descriptor(psbt, index) {
+        Ok(desc) => desc,
+        // Without a signature the key of a single key descriptor is unknown,
+        // look for it in the key origins instead
+        Err(InputError::MissingPubkey) => {
+            for pk in inp.bip32_derivation.keys() {
+                let key = PublicKey::new(*pk);
+                let matches = |desc: Result<Descriptor<PublicKey>, _>| {
+                    desc.map(|desc| desc.script_pubkey() == *spk)
+                        .unwrap_or(false)
+                };
+                if matches(Ok(Descriptor::new_pkh(key)))
+                    || matches(Descriptor::new_wpkh(key))
+                    || matches(Descriptor::new_sh_wpkh(key))
+                {
+                    insert_signer(pk);
+                }
+            }
+            return Ok(signers);
+        }
+        Err(e) => return Err(e),
+    };
+    let diag_sat = DiagnosticSatisfier::new(sat);
+    if desc.get_satisfaction(&diag_sat).is_ok() {
+        return Ok(signers);
+    }
+    for item in diag_sat.missing() {
+        match item {
+            MissingItem::Signature(pk) => insert_signer(&pk.inner),
+            MissingItem::KeyHashSignature(hash) => {
+                for pk in inp.bip32_derivation.keys() {
+                    if PublicKey::new(*pk).to_pubkeyhash() == hash {
+                        insert_signer(pk);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(signers)
+}
+
+// The signatures and hash preimages missing from the cheapest satisfaction of
+// the input at `index`, assuming that every key with an origin in the
+// `bip32_derivation` or `tap_key_origins` of the input can sign and that every
+// preimage can be provided. Nothing is missing if the input can already be
+// satisfied.
+//
+// Unique placeholders of the size of the real items are added to a copy of the
+// input for everything missing, and the items returned are those whose
+// placeholder ends up in the satisfaction chosen by the finalizer.
+pub(super) fn cheapest_path_missing_items(
+    mut psbt: Psbt,
+    index: usize,
+) -> Result<(BTreeSet<SinglePubKey>, BTreeSet<HashDigest>), InputError> {
+    enum Placeholder {
+        Signature(SinglePubKey),
+        Preimage(HashDigest),
+    }
+    let mut placeholders = BTreeMap::new();
+    let mut next_placeholder = {
+        let mut count = 0u32;
+        move |tag: u8| {
+            count += 1;
+            let mut bytes = [tag; 64];
+            bytes[1..5].copy_from_slice(&count.to_be_bytes());
+            bytes
+        }
+    };
+
+    if satisfy_input(&psbt, index).is_ok() {
+        return Ok((BTreeSet::new(), BTreeSet::new()));
+    }
+
+    let mut scripts = vec![get_scriptpubkey(&psbt, index)?.clone()];
+    let inp = &mut psbt.inputs[index];
+    scripts.extend(inp.redeem_script.iter().cloned());
+    scripts.extend(inp.witness_script.iter().cloned());
+    scripts.extend(inp.tap_scripts.values().map(|(script, _)| script.clone()));
+
+    let ecdsa_hash_ty = inp
+        .sighash_type
+        .and_then(|ty| ty.ecdsa_hash_ty().ok())
+        .unwrap_or(EcdsaSighashType::All);
+    for pk in inp.bip32_derivation.keys() {
+        let pk = PublicKey::new(*pk);
+        inp.partial_sigs.entry(pk).or_insert_with(|| {
+            let sig = secp256k1::ecdsa::Signature::from_compact(&next_placeholder(0x01)[..])
+                .expect("Placeholder scalars are below the curve order");
+            let sig = EcdsaSig {
+                sig,
+                hash_ty: ecdsa_hash_ty,
+            };
+            placeholders.insert(
+                sig.to_vec(),
+                Placeholder::Signature(SinglePubKey::FullKey(pk)),
+            );
+            sig
+        });
+    }
+
+    let schnorr_hash_ty = inp
+        .sighash_type
+        .and_then(|ty| ty.schnorr_hash_ty().ok())
+        .unwrap_or(SchnorrSighashType::Default);
+    let mut schnorr_placeholder = || {
+        let sig = secp256k1::schnorr::Signature::from_slice(&next_placeholder(0x02))
+            .expect("Any 64 bytes are a schnorr signature");
+        SchnorrSig {
+            sig,
+            hash_ty: schnorr_hash_ty,
+        }
+    };
+    if let Some(internal_key) = inp.tap_internal_key {
+        if inp.tap_key_sig.is_none() && inp.tap_key_origins.contains_key(&internal_key) {
+            let sig = schnorr_placeholder();
+            inp.tap_key_sig = Some(sig);
+            let key = SinglePubKey::XOnly(internal_key);
+            placeholders.insert(sig.to_vec(), Placeholder::Signature(key));
+        }
+    }
+    for (pk, (leaf_hashes, _)) in &inp.tap_key_origins {
+        for leaf_hash in leaf_hashes {
+            inp.tap_script_sigs
+                .entry((*pk, *leaf_hash))
+                .or_insert_with(|| {
+                    let sig = schnorr_placeholder();
+                    let key = SinglePubKey::XOnly(*pk);
+                    placeholders.insert(sig.to_vec(), Placeholder::Signature(key));
+                    sig
+                });
+        }
+    }
+
+    // Any hash following a hashing opcode may be the digest of a hash fragment
+    let mut hashes = vec![];
+    for script in &scripts {
+        let mut prev_opcode = None;
+        for ins in script.instructions() {
+            let ins = match ins {
+                Ok(ins) => ins,
+                Err(_) => break,
+            };
+            match (prev_opcode, &ins) {
+                (Some(opcodes::all::OP_SHA256), Instruction::PushBytes(h)) => {
+                    hashes.extend(sha256::Hash::from_slice(h).ok().map(HashDigest::Sha256))
+                }
+                (Some(opcodes::all::OP_HASH256), Instruction::PushBytes(h)) => {
+                    hashes.extend(sha256d::Hash::from_slice(h).ok().map(HashDigest::Hash256))
+                }
+                (Some(opcodes::all::OP_RIPEMD160), Instruction::PushBytes(h)) => hashes.extend(
+                    ripemd160::Hash::from_slice(h)
+                        .ok()
+                        .map(HashDigest::Ripemd160),
+                ),
+                (Some(opcodes::all::OP_HASH160), Instruction::PushBytes(h)) => {
+                    hashes.extend(hash160::Hash::from_slice(h).ok().map(HashDigest::Hash160))
+                }
+                _ => {}
+            }
+            prev_opcode = match ins {
+                Instruction::Op(op) => Some(op),
+                Instruction::PushBytes(_) => None,
+            };
+        }
+    }
+    for hash in hashes {
+        let preimage = next_placeholder(0x03)[..32].to_vec();
+        let missing = match hash {
+            HashDigest::Sha256(h) => insert_missing(&mut inp.sha256_preimages, h, &preimage),
+            HashDigest::Hash256(h) => insert_missing(&mut inp.hash256_preimages, h, &preimage),
+            HashDigest::Ripemd160(h) => insert_missing(&mut inp.ripemd160_preimages, h, &preimage),
+            HashDigest::Hash160(h) => insert_missing(&mut inp.hash160_preimages, h, &preimage),
+        };
+        if missing {
+            placeholders.insert(preimage, Placeholder::Preimage(hash));
+        }
+    }
+
+    let (witness, script_sig) = satisfy_input(&psbt, index)?;
+    let pushes = script_sig.instructions().filter_map(|ins| match ins {
+        Ok(Instruction::PushBytes(bytes)) => Some(bytes.to_vec()),
+        _ => None,
+    });
+    let mut signatures = BTreeSet::new();
+    let mut preimages = BTreeSet::new();
+    for elem in witness.into_iter().chain(pushes) {
+        match placeholders.get(&elem) {
+            Some(Placeholder::Signature(pk)) => {
+                signatures.insert(pk.clone());
+            }
+            Some(Placeholder::Preimage(hash)) => {
+                preimages.insert(*hash);
+            }
+            None => {}
+        }
+    }
+    Ok((signatures, preimages))
+}
+
+// The non-malleable satisfaction of the input chosen by the finalizer, with the
+// timelocks of the unsigned transaction
+fn satisfy_input(psbt: &Psbt, index: usize) -> Result<(Vec<Vec<u8>>, Script), InputError> {
+    let spk = get_scriptpubkey(psbt, index)?;
+    let sat = PsbtInputSatisfier::new(psbt, index);
+    if spk.is_v1_p2tr() {
+        let mut ctx = FinalizationContext::new();
+        let witness = construct_tap_witness(spk, &sat, false, TapSpendPath::Any, &mut ctx)?;
+        Ok((witness, Script::new()))
+    } else {
+        get_descriptor(psbt, index)?
+            .get_satisfaction(sat)
+            .map_err(InputError::MiniscriptError)
+    }
+}
+
+// Insert `preimage` for `hash` if there is none, returning whether it was
+// inserted
+fn insert_missing<H: Ord>(map: &mut BTreeMap<H, Vec<u8>>, hash: H, preimage: &[u8]) -> bool {
+    let mut inserted = false;
+    map.entry(hash).or_insert_with(|| {
+        inserted = true;
+        preimage.to_vec()
+    });
+    inserted
+}
+
 /// Interprets all psbt inputs and checks whether the
 /// script is correctly interpreted according to the context
 /// The psbt must have included final script sig and final witness.
@@ -278,7 +751,7 @@ pub fn interpreter_check<C: secp256k1::Verification>(
 }
 
 // Run the miniscript interpreter on a single psbt input
-fn interpreter_inp_check<C: secp256k1::Verification, T: Borrow<TxOut>>(
+pub(super) fn interpreter_inp_check<C: secp256k1::Verification, T: Borrow<TxOut>>(
     psbt: &Psbt,
     secp: &Secp256k1<C>,
     index: usize,
@@ -319,7 +792,7 @@ pub fn finalize<C: secp256k1::Verification>(
     psbt: &mut Psbt,
     secp: &Secp256k1<C>,
 ) -> Result<(), super::Error> {
-    finalize_helper(psbt, secp, false)
+    finalize_helper(psbt, secp, false, &RuleSet::Standard)
 }
 
 /// Same as [finalize], but allows for malleable satisfactions
@@ -327,19 +800,21 @@ pub fn finalize_mall<C: secp256k1::Verification>(
     psbt: &mut Psbt,
     secp: &Secp256k1<C>,
 ) -> Result<(), super::Error> {
-    finalize_helper(psbt, secp, true)
+    finalize_helper(psbt, secp, true, &RuleSet::Standard)
 }
 
 pub fn finalize_helper<C: secp256k1::Verification>(
     psbt: &mut Psbt,
     secp: &Secp256k1<C>,
     allow_mall: bool,
+    rules: &RuleSet,
 ) -> Result<(), super::Error> {
-    sanity_check(psbt)?;
+    sanity_check_with(psbt, rules)?;
 
     // Actually construct the witnesses
+    let mut ctx = FinalizationContext::with_rules(rules);
     for index in 0..psbt.inputs.len() {
-        finalize_input(psbt, index, secp, allow_mall)?;
+        finalize_input(psbt, index, secp, allow_mall, &mut ctx)?;
     }
     // Interpreter is already run inside finalize_input for each input
     Ok(())
@@ -347,33 +822,45 @@ pub fn finalize_helper<C: secp256k1::Verification>(
 
 // Helper function to obtain psbt final_witness/final_script_sig.
 // Does not add fields to the psbt, only returns the values.
-fn finalize_input_helper<C: secp256k1::Verification>(
+// If `timelocks` is set, only the timelocks reached after the given chain tip
+// are satisfied, given the block the spent output was confirmed in. Taproot
+// inputs are only satisfied through the given spend `path`, which must be
+// `TapSpendPath::Any` for other inputs.
+pub(super) fn finalize_input_helper<C: secp256k1::Verification>(
     psbt: &Psbt,
     index: usize,
     secp: &Secp256k1<C>,
     allow_mall: bool,
+    timelocks: Option<(BlockTime, Option<BlockTime>)>,
+    path: TapSpendPath,
+    ctx: &mut FinalizationContext,
 ) -> Result<(Witness, Script), super::Error> {
     let (witness, script_sig) = {
         let spk = get_scriptpubkey(psbt, index).map_err(|e| Error::InputError(e, index))?;
-        let sat = PsbtInputSatisfier::new(psbt, index);
+        let sat = match timelocks {
+            Some((tip, confirmed_in)) => {
+                PsbtInputSatisfier::new(psbt, index).with_chain_tip(tip, confirmed_in)
+            }
+            None => PsbtInputSatisfier::new(psbt, index),
+        };
 
         if spk.is_v1_p2tr() {
             // Deal with tr case separately, unfortunately we cannot infer the full descriptor for Tr
-            let wit = construct_tap_witness(spk, &sat, allow_mall)
+            let wit = construct_tap_witness(spk, &sat, allow_mall, path, ctx)
                 .map_err(|e| Error::InputError(e, index))?;
             (wit, Script::new())
+        } else if path != TapSpendPath::Any {
+            return Err(Error::InputError(InputError::NonTaprootInput, index));
         } else {
             // Get a descriptor for this input.
-            let desc = get_descriptor(psbt, index).map_err(|e| Error::InputError(e, index))?;
+            let rules = ctx.rules.clone();
+            let desc = ctx
+                .descriptor(psbt, index)
+                .map_err(|e| Error::InputError(e, index))?;
 
             //generate the satisfaction witness and scriptsig
-            let sat = PsbtInputSatisfier::new(psbt, index);
-            if !allow_mall {
-                desc.get_satisfaction(sat)
-            } else {
-                desc.get_satisfaction_mall(sat)
-            }
-            .map_err(|e| Error::InputError(InputError::MiniscriptError(e), index))?
+            desc.satisfaction_with(sat, allow_mall, &rules)
+                .map_err(|e| Error::InputError(InputError::MiniscriptError(e), index))?
         }
     };
 
@@ -390,13 +877,35 @@ pub(super) fn finalize_input<C: secp256k1::Verification>(
     index: usize,
     secp: &Secp256k1<C>,
     allow_mall: bool,
+    ctx: &mut FinalizationContext,
 ) -> Result<(), super::Error> {
-    let (witness, script_sig) = finalize_input_helper(psbt, index, secp, allow_mall)?;
+    finalize_input_at(psbt, index, secp, allow_mall, None, TapSpendPath::Any, ctx)
+}
 
-    // Now mutate the psbt input. Note that we cannot error after this point.
-    // If the input is mutated, it means that the finalization succeeded.
+pub(super) fn finalize_input_at<C: secp256k1::Verification>(
+    psbt: &mut Psbt,
+    index: usize,
+    secp: &Secp256k1<C>,
+    allow_mall: bool,
+    timelocks: Option<(BlockTime, Option<BlockTime>)>,
+    path: TapSpendPath,
+    ctx: &mut FinalizationContext,
+) -> Result<(), super::Error> {
+    let (witness, script_sig) =
+        finalize_input_helper(psbt, index, secp, allow_mall, timelocks, path, ctx)?;
+    set_final_fields(&mut psbt.inputs[index], witness, script_sig);
+    Ok(())
+}
+
+// Mutate the psbt input to hold the given satisfaction. Note that we cannot
+// error at this point. If the input is mutated, it means that the
+// finalization succeeded.
+pub(super) fn set_final_fields(
+    input: &mut bitcoin::util::psbt::Input,
+    witness: Witness,
+    script_sig: Script,
+) {
     {
-        let input = &mut psbt.inputs[index];
         //Fill in the satisfactions
         input.final_script_sig = if script_sig.is_empty() {
             None
@@ -428,10 +937,125 @@ pub(super) fn finalize_input<C: secp256k1::Verification>(
         input.tap_internal_key = None; // x017
         input.tap_merkle_root = None; // 0x018
     }
+}
 
-    Ok(())
+/// Finalize all the inputs of the psbt, returning the errors of those which
+/// could not be finalized.
+///
+/// If `timelocks` is set, only the timelocks reached after the given chain tip
+/// are satisfied, given the block each spent output was confirmed in. The
+/// satisfactions must follow `rules`.
+#[cfg(not(feature = "parallel"))]
+pub(super) fn finalize_inputs<C: secp256k1::Verification>(
+    psbt: &mut Psbt,
+    secp: &Secp256k1<C>,
+    allow_mall: bool,
+    timelocks: Option<(BlockTime, &[Option<BlockTime>])>,
+    rules: &RuleSet,
+) -> Vec<Error> {
+    let mut errors = vec![];
+    let mut ctx = FinalizationContext::with_rules(rules);
+    for index in 0..psbt.inputs.len() {
+        let timelocks =
+            timelocks.map(|(tip, confirmed_in)| (tip, confirmed_in.get(index).copied().flatten()));
+        if let Err(e) = finalize_input_at(
+            psbt,
+            index,
+            secp,
+            allow_mall,
+            timelocks,
+            TapSpendPath::Any,
+            &mut ctx,
+        ) {
+            errors.push(e);
+        }
+    }
+    errors
 }
 
+/// Finalize all the inputs of the psbt, returning the errors of those which
+/// could not be finalized.
+///
+/// If `timelocks` is set, only the timelocks reached after the given chain tip
+/// are satisfied, given the block each spent output was confirmed in. The
+/// satisfactions must follow `rules`.
+///
+/// The satisfactions of the inputs are searched for and checked with `secp` on
+/// up to [`FINALIZER_THREADS`] threads, which share the psbt.
+#[cfg(feature = "parallel")]
+#[allow(clippy::incompatible_msrv)] // The `parallel` feature requires Rust 1.63
+pub(super) fn finalize_inputs<C: secp256k1::Verification>(
+    psbt: &mut Psbt,
+    secp: &Secp256k1<C>,
+    allow_mall: bool,
+    timelocks: Option<(BlockTime, &[Option<BlockTime>])>,
+    rules: &RuleSet,
+) -> Vec<Error> {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::{panic, thread};
+
+    let n_inputs = psbt.inputs.len();
+    let next_index = AtomicUsize::new(0);
+
+    // Workers take the next input to finalize until all are done, as the
+    // satisfaction search of some inputs may take much longer than others
+    let shared: &Psbt = psbt;
+    let mut results = thread::scope(|scope| {
+        let workers: Vec<_> = (0..core::cmp::min(n_inputs, FINALIZER_THREADS))
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut ctx = FinalizationContext::with_rules(rules);
+                    let mut results = vec![];
+                    loop {
+                        let index = next_index.fetch_add(1, Ordering::Relaxed);
+                        if index >= n_inputs {
+                            return results;
+                        }
+                        let timelocks = timelocks.map(|(tip, confirmed_in)| {
+                            (tip, confirmed_in.get(index).copied().flatten())
+                        });
+                        let res = finalize_input_helper(
+                            shared,
+                            index,
+                            secp,
+                            allow_mall,
+                            timelocks,
+                            TapSpendPath::Any,
+                            &mut ctx,
+                        );
+                        results.push((index, res));
+                    }
+                })
+            })
+            .collect();
+
+        let mut results = Vec::with_capacity(n_inputs);
+        for worker in workers {
+            match worker.join() {
+                Ok(res) => results.extend(res),
+                Err(e) => panic::resume_unwind(e),
+            }
+        }
+        results
+    });
+    results.sort_by_key(|&(index, _)| index);
+
+    let mut errors = vec![];
+    for (index, res) in results {
+        match res {
+            Ok((witness, script_sig)) => {
+                set_final_fields(&mut psbt.inputs[index], witness, script_sig)
+            }
+            Err(e) => errors.push(e),
+        }
+    }
+    errors
+}
+
+/// Maximum number of threads finalizing the inputs of a psbt
+#[cfg(feature = "parallel")]
+pub const FINALIZER_THREADS: usize = 8;
+
 #[cfg(test)]
 mod tests {
     use bitcoin::consensus::encode::deserialize;
@@ -450,4 +1074,30 @@ mod tests {
         let expected: bitcoin::util::psbt::PartiallySignedTransaction = deserialize(&Vec::<u8>::from_hex("70736274ff01009a020000000258e87a21b56daf0c23be8e7070456c336f7cbaa5c8757924f545887bb2abdd750000000000ffffffff838d0427d0ec650a68aa46bb0b098aea4422c071b2ca78352a077959d07cea1d0100000000ffffffff0270aaf00800000000160014d85c2b71d0060b09c9886aeb815e50991dda124d00e1f5050000000016001400aea9a2e5f0f876a588df5546e8742d1d87008f00000000000100bb0200000001aad73931018bd25f84ae400b68848be09db706eac2ac18298babee71ab656f8b0000000048473044022058f6fc7c6a33e1b31548d481c826c015bd30135aad42cd67790dab66d2ad243b02204a1ced2604c6735b6393e5b41691dd78b00f0c5942fb9f751856faa938157dba01feffffff0280f0fa020000000017a9140fb9463421696b82c833af241c78c17ddbde493487d0f20a270100000017a91429ca74f8a08f81999428185c97b5d852e4063f6187650000000107da00473044022074018ad4180097b873323c0015720b3684cc8123891048e7dbcd9b55ad679c99022073d369b740e3eb53dcefa33823c8070514ca55a7dd9544f157c167913261118c01483045022100f61038b308dc1da865a34852746f015772934208c6d24454393cd99bdf2217770220056e675a675a6d0a02b85b14e5e29074d8a25a9b5760bea2816f661910a006ea01475221029583bf39ae0a609747ad199addd634fa6108559d6c5cd39b4c2183f1ab96e07f2102dab61ff49a14db6a7d02b0cd1fbb78fc4b18312b5b4e54dae4dba2fbfef536d752ae0001012000c2eb0b0000000017a914b7f5faf40e3d40a5a459b1db3535f2b72fa921e8870107232200208c2353173743b595dfb4a07b72ba8e42e3797da74e87fe7d9d7497e3b20289030108da0400473044022062eb7a556107a7c73f45ac4ab5a1dddf6f7075fb1275969a7f383efff784bcb202200c05dbb7470dbf2f08557dd356c7325c1ed30913e996cd3840945db12228da5f01473044022065f45ba5998b59a27ffe1a7bed016af1f1f90d54b3aa8f7450aa5f56a25103bd02207f724703ad1edb96680b284b56d4ffcb88f7fb759eabbe08aa30f29b851383d20147522103089dc10c7ac6db54f91329af617333db388cead0c231f723379d1b99030b02dc21023add904f3d6dcf59ddb906b0dee23529b7ffb9ed50e5e86151926860221f0e7352ae00220203a9a4c37f5996d3aa25dbac6b570af0650394492942460b354753ed9eeca5877110d90c6a4f000000800000008004000080002202027f6399757d2eff55a136ad02c684b1838b6556e5f1b6b34282a94b6b5005109610d90c6a4f00000080000000800500008000").unwrap()).unwrap();
         assert_eq!(psbt, expected);
     }
+
+    #[test]
+    fn finalize_with_context() {
+        let psbt: Psbt = deserialize(&Vec::<u8>::from_hex("70736274ff01009a020000000258e87a21b56daf0c23be8e7070456c336f7cbaa5c8757924f545887bb2abdd750000000000ffffffff838d0427d0ec650a68aa46bb0b098aea4422c071b2ca78352a077959d07cea1d0100000000ffffffff0270aaf00800000000160014d85c2b71d0060b09c9886aeb815e50991dda124d00e1f5050000000016001400aea9a2e5f0f876a588df5546e8742d1d87008f00000000000100bb0200000001aad73931018bd25f84ae400b68848be09db706eac2ac18298babee71ab656f8b0000000048473044022058f6fc7c6a33e1b31548d481c826c015bd30135aad42cd67790dab66d2ad243b02204a1ced2604c6735b6393e5b41691dd78b00f0c5942fb9f751856faa938157dba01feffffff0280f0fa020000000017a9140fb9463421696b82c833af241c78c17ddbde493487d0f20a270100000017a91429ca74f8a08f81999428185c97b5d852e4063f6187650000002202029583bf39ae0a609747ad199addd634fa6108559d6c5cd39b4c2183f1ab96e07f473044022074018ad4180097b873323c0015720b3684cc8123891048e7dbcd9b55ad679c99022073d369b740e3eb53dcefa33823c8070514ca55a7dd9544f157c167913261118c01220202dab61ff49a14db6a7d02b0cd1fbb78fc4b18312b5b4e54dae4dba2fbfef536d7483045022100f61038b308dc1da865a34852746f015772934208c6d24454393cd99bdf2217770220056e675a675a6d0a02b85b14e5e29074d8a25a9b5760bea2816f661910a006ea01010304010000000104475221029583bf39ae0a609747ad199addd634fa6108559d6c5cd39b4c2183f1ab96e07f2102dab61ff49a14db6a7d02b0cd1fbb78fc4b18312b5b4e54dae4dba2fbfef536d752ae2206029583bf39ae0a609747ad199addd634fa6108559d6c5cd39b4c2183f1ab96e07f10d90c6a4f000000800000008000000080220602dab61ff49a14db6a7d02b0cd1fbb78fc4b18312b5b4e54dae4dba2fbfef536d710d90c6a4f0000008000000080010000800001012000c2eb0b0000000017a914b7f5faf40e3d40a5a459b1db3535f2b72fa921e887220203089dc10c7ac6db54f91329af617333db388cead0c231f723379d1b99030b02dc473044022062eb7a556107a7c73f45ac4ab5a1dddf6f7075fb1275969a7f383efff784bcb202200c05dbb7470dbf2f08557dd356c7325c1ed30913e996cd3840945db12228da5f012202023add904f3d6dcf59ddb906b0dee23529b7ffb9ed50e5e86151926860221f0e73473044022065f45ba5998b59a27ffe1a7bed016af1f1f90d54b3aa8f7450aa5f56a25103bd02207f724703ad1edb96680b284b56d4ffcb88f7fb759eabbe08aa30f29b851383d2010103040100000001042200208c2353173743b595dfb4a07b72ba8e42e3797da74e87fe7d9d7497e3b2028903010547522103089dc10c7ac6db54f91329af617333db388cead0c231f723379d1b99030b02dc21023add904f3d6dcf59ddb906b0dee23529b7ffb9ed50e5e86151926860221f0e7352ae2206023add904f3d6dcf59ddb906b0dee23529b7ffb9ed50e5e86151926860221f0e7310d90c6a4f000000800000008003000080220603089dc10c7ac6db54f91329af617333db388cead0c231f723379d1b99030b02dc10d90c6a4f00000080000000800200008000220203a9a4c37f5996d3aa25dbac6b570af0650394492942460b354753ed9eeca5877110d90c6a4f000000800000008004000080002202027f6399757d2eff55a136ad02c684b1838b6556e5f1b6b34282a94b6b5005109610d90c6a4f00000080000000800500008000").unwrap()).unwrap();
+        let expected: Psbt = deserialize(&Vec::<u8>::from_hex("70736274ff01009a020000000258e87a21b56daf0c23be8e7070456c336f7cbaa5c8757924f545887bb2abdd750000000000ffffffff838d0427d0ec650a68aa46bb0b098aea4422c071b2ca78352a077959d07cea1d0100000000ffffffff0270aaf00800000000160014d85c2b71d0060b09c9886aeb815e50991dda124d00e1f5050000000016001400aea9a2e5f0f876a588df5546e8742d1d87008f00000000000100bb0200000001aad73931018bd25f84ae400b68848be09db706eac2ac18298babee71ab656f8b0000000048473044022058f6fc7c6a33e1b31548d481c826c015bd30135aad42cd67790dab66d2ad243b02204a1ced2604c6735b6393e5b41691dd78b00f0c5942fb9f751856faa938157dba01feffffff0280f0fa020000000017a9140fb9463421696b82c833af241c78c17ddbde493487d0f20a270100000017a91429ca74f8a08f81999428185c97b5d852e4063f6187650000000107da00473044022074018ad4180097b873323c0015720b3684cc8123891048e7dbcd9b55ad679c99022073d369b740e3eb53dcefa33823c8070514ca55a7dd9544f157c167913261118c01483045022100f61038b308dc1da865a34852746f015772934208c6d24454393cd99bdf2217770220056e675a675a6d0a02b85b14e5e29074d8a25a9b5760bea2816f661910a006ea01475221029583bf39ae0a609747ad199addd634fa6108559d6c5cd39b4c2183f1ab96e07f2102dab61ff49a14db6a7d02b0cd1fbb78fc4b18312b5b4e54dae4dba2fbfef536d752ae0001012000c2eb0b0000000017a914b7f5faf40e3d40a5a459b1db3535f2b72fa921e8870107232200208c2353173743b595dfb4a07b72ba8e42e3797da74e87fe7d9d7497e3b20289030108da0400473044022062eb7a556107a7c73f45ac4ab5a1dddf6f7075fb1275969a7f383efff784bcb202200c05dbb7470dbf2f08557dd356c7325c1ed30913e996cd3840945db12228da5f01473044022065f45ba5998b59a27ffe1a7bed016af1f1f90d54b3aa8f7450aa5f56a25103bd02207f724703ad1edb96680b284b56d4ffcb88f7fb759eabbe08aa30f29b851383d20147522103089dc10c7ac6db54f91329af617333db388cead0c231f723379d1b99030b02dc21023add904f3d6dcf59ddb906b0dee23529b7ffb9ed50e5e86151926860221f0e7352ae00220203a9a4c37f5996d3aa25dbac6b570af0650394492942460b354753ed9eeca5877110d90c6a4f000000800000008004000080002202027f6399757d2eff55a136ad02c684b1838b6556e5f1b6b34282a94b6b5005109610d90c6a4f00000080000000800500008000").unwrap()).unwrap();
+
+        let secp = Secp256k1::verification_only();
+        let mut ctx = FinalizationContext::new();
+        // Without one of the signatures of the 2-of-2, the descriptor is
+        // inferred and cached but the input cannot be finalized
+        let mut unsigned = psbt.clone();
+        let pk = *unsigned.inputs[1].partial_sigs.keys().next().unwrap();
+        unsigned.inputs[1].partial_sigs.remove(&pk);
+        assert!(unsigned
+            .finalize_inp_mut_with_context(&secp, 1, &mut ctx)
+            .is_err());
+        assert_eq!(ctx.descriptors.len(), 1);
+
+        let mut psbt = psbt;
+        for index in 0..psbt.inputs.len() {
+            psbt.finalize_inp_mut_with_context(&secp, index, &mut ctx)
+                .unwrap();
+        }
+        assert_eq!(ctx.descriptors.len(), 2);
+        assert_eq!(psbt, expected);
+    }
 }
@@ -14,8 +14,8 @@
 
 //! # Partially-Signed Bitcoin Transactions
 //!
-//! This module implements the Finalizer and Extractor roles defined in
-//! BIP 174, PSBT, described at
+//! This module implements the Combiner, Finalizer and Extractor roles
+//! defined in BIP 174, PSBT, described at
 //! `https://github.com/bitcoin/bips/blob/master/bip-0174.mediawiki`
 //!
 
@@ -24,26 +24,50 @@ use core::ops::Deref;
 #[cfg(feature = "std")]
 use std::error;
 
+use bitcoin::hashes::hex::ToHex;
 use bitcoin::hashes::{hash160, ripemd160, sha256, sha256d};
 use bitcoin::secp256k1::{self, Secp256k1};
+use bitcoin::util::bip32;
 use bitcoin::util::psbt::{self, PartiallySignedTransaction as Psbt};
 use bitcoin::util::sighash::SighashCache;
-use bitcoin::util::taproot::{self, ControlBlock, LeafVersion, TapLeafHash};
+use bitcoin::util::taproot::{self, ControlBlock, LeafVersion, TapBranchHash, TapLeafHash};
 use bitcoin::{self, EcdsaSighashType, SchnorrSighashType, Script};
 
+use crate::miniscript::analyzable::PathTimelocks;
+use crate::miniscript::context::{ScriptContext, ScriptContextError};
+use crate::miniscript::decode::Terminal;
 use crate::miniscript::iter::PkPkh;
-use crate::miniscript::limits::SEQUENCE_LOCKTIME_DISABLE_FLAG;
-use crate::miniscript::satisfy::{After, Older};
+use crate::miniscript::limits::{
+    LimitExceeded, ResourceLimit, RuleSet, MAX_STANDARD_P2WSH_STACK_ITEMS,
+    SEQUENCE_LOCKTIME_DISABLE_FLAG, SEQUENCE_LOCKTIME_TYPE_FLAG,
+};
+use crate::miniscript::satisfy::{After, MissingItem, Older};
 use crate::prelude::*;
 use crate::{
-    descriptor, interpreter, Descriptor, DescriptorPublicKey, MiniscriptKey, Preimage32, Satisfier,
-    ToPublicKey, TranslatePk, TranslatePk2,
+    descriptor, hash256, interpreter, Descriptor, DescriptorPublicKey, Legacy, LockTime,
+    Miniscript, MiniscriptKey, Preimage32, Satisfier, Segwitv0, Tap, ToPublicKey, TranslatePk,
+    Translator,
 };
 
 mod finalizer;
+mod reserves;
+mod roles;
+mod signer;
+mod witness_builder;
+mod xpubs;
 
+#[cfg(feature = "parallel")]
+pub use self::finalizer::FINALIZER_THREADS;
 #[allow(deprecated)]
-pub use self::finalizer::{finalize, finalize_mall, interpreter_check};
+pub use self::finalizer::{finalize, finalize_mall, interpreter_check, FinalizationContext};
+pub use self::reserves::{
+    finalize_reserve_proof, reserve_proof_challenge, reserve_proof_psbt, verify_reserve_proof,
+    ReserveProofError,
+};
+pub use self::roles::{Role, RoleError};
+pub use self::signer::{Signature, SignerSatisfier, SigningOptions, TapSigner, TapSignerSatisfier};
+pub use self::witness_builder::WitnessBuilder;
+pub use self::xpubs::GlobalXpubError;
 
 /// Error type for entire Psbt
 #[derive(Debug)]
@@ -86,7 +110,7 @@ impl fmt::Display for Error {
 
 #[cfg(feature = "std")]
 impl error::Error for Error {
-    fn cause(&self) -> Option<&dyn error::Error> {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
         use self::Error::*;
 
         match self {
@@ -105,9 +129,9 @@ pub enum InputError {
     KeyErr(bitcoin::util::key::Error),
     /// Could not satisfy taproot descriptor
     /// This error is returned when both script path and key paths could not be
-    /// satisfied. We cannot return a detailed error because we try all miniscripts
-    /// in script spend path, we cannot know which miniscript failed.
-    CouldNotSatisfyTr,
+    /// satisfied. It lists why each script path in the psbt input could not be
+    /// satisfied.
+    CouldNotSatisfyTr(Vec<TapLeafFailure>),
     /// Error doing an interpreter-check on a finalized psbt
     Interpreter(interpreter::Error),
     /// Redeem script does not match the p2sh hash
@@ -141,6 +165,12 @@ pub enum InputError {
     MissingPubkey,
     /// Missing witness script for segwit descriptors
     MissingWitnessScript,
+    /// Missing the key spend signature of a taproot input
+    MissingTapKeySig,
+    /// Missing the control block of the requested taproot leaf
+    MissingTapLeaf(TapLeafHash),
+    /// A taproot spend path was requested for a non-taproot input
+    NonTaprootInput,
     ///Missing both the witness and non-witness utxo
     MissingUtxo,
     /// Non empty Witness script for p2sh
@@ -158,15 +188,48 @@ pub enum InputError {
         /// the corresponding publickey
         pubkey: bitcoin::PublicKey,
     },
+    /// A preimage of the psbt input does not hash to its claimed digest, or
+    /// its digest is pushed by a script of the input and it is not 32 bytes
+    /// long as required by Miniscript hash fragments
+    InvalidPreimage {
+        /// The claimed digest
+        hash: PreimageDigest,
+        /// The preimage
+        preimage: Vec<u8>,
+    },
+}
+
+/// The digest of a preimage in one of the preimage maps of a psbt input
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum PreimageDigest {
+    /// Digest of the `sha256_preimages` map
+    Sha256(sha256::Hash),
+    /// Digest of the `hash256_preimages` map
+    Hash256(sha256d::Hash),
+    /// Digest of the `ripemd160_preimages` map
+    Ripemd160(ripemd160::Hash),
+    /// Digest of the `hash160_preimages` map
+    Hash160(hash160::Hash),
+}
+
+impl fmt::Display for PreimageDigest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PreimageDigest::Sha256(ref h) => write!(f, "sha256({})", h),
+            PreimageDigest::Hash256(ref h) => write!(f, "hash256({})", h),
+            PreimageDigest::Ripemd160(ref h) => write!(f, "ripemd160({})", h),
+            PreimageDigest::Hash160(ref h) => write!(f, "hash160({})", h),
+        }
+    }
 }
 
 #[cfg(feature = "std")]
 impl error::Error for InputError {
-    fn cause(&self) -> Option<&dyn error::Error> {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
         use self::InputError::*;
 
         match self {
-            CouldNotSatisfyTr
+            CouldNotSatisfyTr(_)
             | InvalidRedeemScript { .. }
             | InvalidWitnessScript { .. }
             | InvalidSignature { .. }
@@ -174,11 +237,15 @@ impl error::Error for InputError {
             | MissingWitness
             | MissingPubkey
             | MissingWitnessScript
+            | MissingTapKeySig
+            | MissingTapLeaf(_)
+            | NonTaprootInput
             | MissingUtxo
             | NonEmptyWitnessScript
             | NonEmptyRedeemScript
             | NonStandardSighashType(_)
-            | WrongSighashFlag { .. } => None,
+            | WrongSighashFlag { .. }
+            | InvalidPreimage { .. } => None,
             SecpErr(e) => Some(e),
             KeyErr(e) => Some(e),
             Interpreter(e) => Some(e),
@@ -221,6 +288,17 @@ impl fmt::Display for InputError {
             }
             InputError::MissingWitnessScript => write!(f, "PSBT is missing witness script"),
             InputError::MissingPubkey => write!(f, "Missing pubkey for a pkh/wpkh"),
+            InputError::MissingTapKeySig => {
+                write!(f, "PSBT is missing the taproot key spend signature")
+            }
+            InputError::MissingTapLeaf(ref leaf_hash) => {
+                write!(
+                    f,
+                    "PSBT is missing the control block for leaf {}",
+                    leaf_hash
+                )
+            }
+            InputError::NonTaprootInput => write!(f, "PSBT input is not a taproot spend"),
             InputError::NonEmptyRedeemScript => write!(
                 f,
                 "PSBT has non-empty redeem script at for legacy transactions"
@@ -238,10 +316,67 @@ impl fmt::Display for InputError {
                  sighashflag {:?} rather than required {:?}",
                 pubkey, got, required
             ),
-            InputError::CouldNotSatisfyTr => {
-                write!(f, "Could not satisfy Tr descriptor")
+            InputError::CouldNotSatisfyTr(ref leaves) => {
+                write!(f, "Could not satisfy Tr descriptor")?;
+                for leaf in leaves {
+                    write!(f, "; {}", leaf)?;
+                }
+                Ok(())
             }
             InputError::NonStandardSighashType(e) => write!(f, "Non-standard sighash type {}", e),
+            InputError::InvalidPreimage {
+                ref hash,
+                ref preimage,
+            } => write!(
+                f,
+                "PSBT: {} is not a preimage of {} usable by the input scripts",
+                preimage.to_hex(),
+                hash
+            ),
+        }
+    }
+}
+
+/// A taproot script path which the finalizer could not satisfy
+#[derive(Debug)]
+pub struct TapLeafFailure {
+    /// Leaf hash of the script
+    pub leaf_hash: TapLeafHash,
+    /// Why the script could not be satisfied
+    pub reason: TapLeafFailureReason,
+}
+
+/// Reason a taproot script path could not be satisfied
+#[derive(Debug)]
+pub enum TapLeafFailureReason {
+    /// The leaf version is not [`LeafVersion::TapScript`]
+    UnsupportedLeafVersion(LeafVersion),
+    /// The script is not a miniscript, or its satisfaction is invalid
+    MiniscriptError(super::Error),
+    /// No satisfaction could be produced with the signatures, preimages and
+    /// timelocks available. Lists every item the psbt input was missing
+    /// while trying the branches of the script.
+    Unsatisfied(Vec<MissingItem<bitcoin::XOnlyPublicKey>>),
+}
+
+impl fmt::Display for TapLeafFailure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "leaf {}: ", self.leaf_hash)?;
+        match self.reason {
+            TapLeafFailureReason::UnsupportedLeafVersion(ver) => {
+                write!(f, "unsupported leaf version {}", ver.to_consensus())
+            }
+            TapLeafFailureReason::MiniscriptError(ref e) => write!(f, "{}", e),
+            TapLeafFailureReason::Unsatisfied(ref missing) => {
+                f.write_str("missing ")?;
+                for (i, item) in missing.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(", ")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                Ok(())
+            }
         }
     }
 }
@@ -277,26 +412,157 @@ pub struct PsbtInputSatisfier<'psbt> {
     pub psbt: &'psbt Psbt,
     /// input index
     pub index: usize,
+    // If set, timelocks are only satisfied if the locktime and sequence of
+    // the transaction allow it to be mined in the block after this chain tip
+    chain_tip: Option<BlockTime>,
+    // The block the spent output was confirmed in, used for relative
+    // timelocks when `chain_tip` is set. `None` if the output is unconfirmed.
+    confirmed_in: Option<BlockTime>,
+    // Signatures of the input which are never used
+    skipped_sigs: BTreeMap<SignatureKey, SignatureError>,
 }
 
 impl<'psbt> PsbtInputSatisfier<'psbt> {
     /// create a new PsbtInputsatisfier from
     /// psbt and index
     pub fn new(psbt: &'psbt Psbt, index: usize) -> Self {
-        Self { psbt, index }
+        Self {
+            psbt,
+            index,
+            chain_tip: None,
+            confirmed_in: None,
+            skipped_sigs: BTreeMap::new(),
+        }
+    }
+
+    /// Only satisfy the timelocks which are reached in the block after
+    /// `chain_tip`, given the block the spent output was `confirmed_in`
+    pub fn with_chain_tip(mut self, chain_tip: BlockTime, confirmed_in: Option<BlockTime>) -> Self {
+        self.chain_tip = Some(chain_tip);
+        self.confirmed_in = confirmed_in;
+        self
+    }
+
+    /// The signatures of the input which are never used, with the reason they
+    /// failed the verification of [`PsbtInputSatisfier::verify_signatures`]
+    pub fn skipped_sigs(&self) -> &BTreeMap<SignatureKey, SignatureError> {
+        &self.skipped_sigs
+    }
+
+    /// Verify every signature of the input against its sighash, as done by
+    /// [`PsbtExt::verify_signatures`], and skip those which fail in
+    /// satisfactions, recording them in `skipped_sigs`.
+    ///
+    /// Without this, signatures are used as they are, so a stale signature
+    /// left after the transaction was edited makes a witness which fails
+    /// [`interpreter_check`] rather than another spend path.
+    pub fn verify_signatures<C: secp256k1::Verification>(mut self, secp: &Secp256k1<C>) -> Self {
+        let mut cache = PsbtSighashCache::new(&self.psbt.unsigned_tx);
+        let results = verify_input_signatures(self.psbt, self.index, secp, &mut cache);
+        self.skipped_sigs = results
+            .into_iter()
+            .filter_map(|(key, res)| res.err().map(|e| (key, e)))
+            .collect();
+        self
+    }
+
+    // Whether the signature for `key` may be used
+    fn usable(&self, key: &SignatureKey) -> bool {
+        !self.skipped_sigs.contains_key(key)
+    }
+
+    // Whether the locktime of the transaction allows it in the next block
+    fn locktime_reached(&self) -> bool {
+        let locktime = self.psbt.unsigned_tx.lock_time;
+        match self.chain_tip {
+            None => true,
+            Some(tip) => LockTime::from_consensus(locktime).is_reached_at(tip.height, tip.mtp),
+        }
+    }
+
+    // Whether the relative locktime of the input sequence allows it in the
+    // next block, as defined in BIP 68
+    fn sequence_reached(&self) -> bool {
+        let seq = self.psbt.unsigned_tx.input[self.index].sequence;
+        let tip = match self.chain_tip {
+            Some(tip) => tip,
+            None => return true,
+        };
+        let value = u64::from(seq & SEQUENCE_LOCKTIME_MASK);
+        if value == 0 {
+            return true;
+        }
+        match self.confirmed_in {
+            None => false,
+            Some(conf) if seq & SEQUENCE_LOCKTIME_TYPE_FLAG != 0 => {
+                u64::from(tip.mtp) >= u64::from(conf.mtp) + (value << 9)
+            }
+            Some(conf) => u64::from(tip.height) + 1 >= u64::from(conf.height) + value,
+        }
+    }
+}
+
+// Mask of the relative locktime value in a sequence, as defined in BIP 68
+const SEQUENCE_LOCKTIME_MASK: u32 = 0x0000_ffff;
+
+// Sequences below this signal replaceability
+// https://github.com/bitcoin/bips/blob/master/bip-0125.mediawiki
+const RBF_SEQUENCE_THRESHOLD: u32 = 0xffff_fffe;
+
+// Whether a spend path with the given timelocks can be taken by an input with
+// `sequence`, in a transaction with the given version
+fn sequence_allows(timelocks: &PathTimelocks, sequence: u32, version: i32) -> bool {
+    let older_allowed = |older: Option<u32>| match older {
+        None => true,
+        Some(n) => {
+            version >= 2
+                && sequence & SEQUENCE_LOCKTIME_DISABLE_FLAG == 0
+                && <dyn Satisfier<bitcoin::PublicKey>>::check_older(&Older(sequence), n)
+        }
+    };
+    // A final sequence disables the transaction locktime
+    let after_allowed = (timelocks.after_height.is_none() && timelocks.after_time.is_none())
+        || sequence != 0xffffffff;
+    older_allowed(timelocks.older_height) && older_allowed(timelocks.older_time) && after_allowed
+}
+
+/// The height and median time past of a block
+///
+/// For the block a spent output was confirmed in, `mtp` is the median time
+/// past of its parent, from which relative timelocks are measured.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct BlockTime {
+    /// Height of the block
+    pub height: u32,
+    /// Median time past of the block
+    pub mtp: u32,
+}
+
+/// Satisfies the absolute timelocks reached in the block after this chain tip
+impl<Pk: MiniscriptKey + ToPublicKey> Satisfier<Pk> for BlockTime {
+    fn lookup_current_height(&self) -> Option<u32> {
+        Some(self.height)
+    }
+
+    fn lookup_current_mtp(&self) -> Option<u32> {
+        Some(self.mtp)
     }
 }
 
 impl<'psbt, Pk: MiniscriptKey + ToPublicKey> Satisfier<Pk> for PsbtInputSatisfier<'psbt> {
     fn lookup_tap_key_spend_sig(&self) -> Option<bitcoin::SchnorrSig> {
-        self.psbt.inputs[self.index].tap_key_sig
+        self.psbt.inputs[self.index]
+            .tap_key_sig
+            .filter(|_| self.usable(&SignatureKey::TapKey))
     }
 
     fn lookup_tap_leaf_script_sig(&self, pk: &Pk, lh: &TapLeafHash) -> Option<bitcoin::SchnorrSig> {
+        let key = (pk.to_x_only_pubkey(), *lh);
         self.psbt.inputs[self.index]
             .tap_script_sigs
-            .get(&(pk.to_x_only_pubkey(), *lh))
+            .get(&key)
             .copied()
+            .filter(|_| self.usable(&SignatureKey::TapScript(key.0, key.1)))
     }
 
     fn lookup_tap_control_block_map(
@@ -313,16 +579,20 @@ impl<'psbt, Pk: MiniscriptKey + ToPublicKey> Satisfier<Pk> for PsbtInputSatisfie
             .tap_script_sigs
             .iter()
             .find(|&((pubkey, lh), _sig)| {
-                pubkey.to_pubkeyhash() == Pk::hash_to_hash160(&pkh.0) && *lh == pkh.1
+                pubkey.to_pubkeyhash() == Pk::hash_to_hash160(&pkh.0)
+                    && *lh == pkh.1
+                    && self.usable(&SignatureKey::TapScript(*pubkey, *lh))
             })
             .map(|((x_only_pk, _leaf_hash), sig)| (*x_only_pk, *sig))
     }
 
     fn lookup_ecdsa_sig(&self, pk: &Pk) -> Option<bitcoin::EcdsaSig> {
+        let pk = pk.to_public_key();
         self.psbt.inputs[self.index]
             .partial_sigs
-            .get(&pk.to_public_key())
+            .get(&pk)
             .copied()
+            .filter(|_| self.usable(&SignatureKey::Ecdsa(pk)))
     }
 
     fn lookup_pkh_ecdsa_sig(
@@ -332,10 +602,36 @@ impl<'psbt, Pk: MiniscriptKey + ToPublicKey> Satisfier<Pk> for PsbtInputSatisfie
         self.psbt.inputs[self.index]
             .partial_sigs
             .iter()
-            .find(|&(pubkey, _sig)| pubkey.to_pubkeyhash() == Pk::hash_to_hash160(pkh))
+            .find(|&(pubkey, _sig)| {
+                pubkey.to_pubkeyhash() == Pk::hash_to_hash160(pkh)
+                    && self.usable(&SignatureKey::Ecdsa(*pubkey))
+            })
             .map(|(pk, sig)| (*pk, *sig))
     }
 
+    fn lookup_raw_pkh_pk(&self, pkh: &hash160::Hash) -> Option<bitcoin::PublicKey> {
+        let input = &self.psbt.inputs[self.index];
+        input
+            .partial_sigs
+            .keys()
+            .copied()
+            .chain(
+                input
+                    .bip32_derivation
+                    .keys()
+                    .map(|pk| bitcoin::PublicKey::new(*pk)),
+            )
+            .find(|pubkey| pubkey.to_pubkeyhash() == *pkh)
+    }
+
+    fn lookup_current_height(&self) -> Option<u32> {
+        self.chain_tip.map(|tip| tip.height)
+    }
+
+    fn lookup_current_mtp(&self) -> Option<u32> {
+        self.chain_tip.map(|tip| tip.mtp)
+    }
+
     fn check_after(&self, n: u32) -> bool {
         let locktime = self.psbt.unsigned_tx.lock_time;
         let seq = self.psbt.unsigned_tx.input[self.index].sequence;
@@ -345,7 +641,7 @@ impl<'psbt, Pk: MiniscriptKey + ToPublicKey> Satisfier<Pk> for PsbtInputSatisfie
         if seq == 0xffffffff {
             false
         } else {
-            <dyn Satisfier<Pk>>::check_after(&After(locktime), n)
+            <dyn Satisfier<Pk>>::check_after(&After(locktime), n) && self.locktime_reached()
         }
     }
 
@@ -359,35 +655,35 @@ impl<'psbt, Pk: MiniscriptKey + ToPublicKey> Satisfier<Pk> for PsbtInputSatisfie
             // transaction version and sequence check
             false
         } else {
-            <dyn Satisfier<Pk>>::check_older(&Older(seq), n)
+            <dyn Satisfier<Pk>>::check_older(&Older(seq), n) && self.sequence_reached()
         }
     }
 
-    fn lookup_hash160(&self, h: hash160::Hash) -> Option<Preimage32> {
+    fn lookup_hash160(&self, h: &Pk::Hash160) -> Option<Preimage32> {
         self.psbt.inputs[self.index]
             .hash160_preimages
-            .get(&h)
+            .get(&Pk::to_hash160(h))
             .and_then(try_vec_as_preimage32)
     }
 
-    fn lookup_sha256(&self, h: sha256::Hash) -> Option<Preimage32> {
+    fn lookup_sha256(&self, h: &Pk::Sha256) -> Option<Preimage32> {
         self.psbt.inputs[self.index]
             .sha256_preimages
-            .get(&h)
+            .get(&Pk::to_sha256(h))
             .and_then(try_vec_as_preimage32)
     }
 
-    fn lookup_hash256(&self, h: sha256d::Hash) -> Option<Preimage32> {
+    fn lookup_hash256(&self, h: &Pk::Hash256) -> Option<Preimage32> {
         self.psbt.inputs[self.index]
             .hash256_preimages
-            .get(&h)
+            .get(&Pk::to_hash256(h).as_hash())
             .and_then(try_vec_as_preimage32)
     }
 
-    fn lookup_ripemd160(&self, h: ripemd160::Hash) -> Option<Preimage32> {
+    fn lookup_ripemd160(&self, h: &Pk::Ripemd160) -> Option<Preimage32> {
         self.psbt.inputs[self.index]
             .ripemd160_preimages
-            .get(&h)
+            .get(&Pk::to_ripemd160(h))
             .and_then(try_vec_as_preimage32)
     }
 }
@@ -406,6 +702,12 @@ fn try_vec_as_preimage32(vec: &Vec<u8>) -> Option<Preimage32> {
 // rust-bitcoin TODO: (Long term)
 // Brainstorm about how we can enforce these in type system while having a nice API
 fn sanity_check(psbt: &Psbt) -> Result<(), Error> {
+    sanity_check_with(psbt, &RuleSet::Standard)
+}
+
+// Basic sanity checks on psbts, checking the spend paths of the inputs
+// against the limits of `rules`
+fn sanity_check_with(psbt: &Psbt, rules: &RuleSet) -> Result<(), Error> {
     if psbt.unsigned_tx.input.len() != psbt.inputs.len() {
         return Err(Error::WrongInputCount {
             in_tx: psbt.unsigned_tx.input.len(),
@@ -445,11 +747,113 @@ fn sanity_check(psbt: &Psbt) -> Result<(), Error> {
             }
             // Signatures are well-formed in psbt partial sigs
         }
+        check_preimages(psbt, index).map_err(|e| Error::InputError(e, index))?;
+        check_stack_size(input, rules).map_err(|e| Error::InputError(e, index))?;
     }
 
     Ok(())
 }
 
+// Check that the witness script of the input, if it is a miniscript, has a
+// spend path within the number of witness stack elements allowed by `rules`.
+// A script without one can never be finalized into a transaction following
+// those rules, whatever signatures are added to the input.
+fn check_stack_size(input: &psbt::Input, rules: &RuleSet) -> Result<(), InputError> {
+    let ms = match input.witness_script {
+        Some(ref script) => {
+            match Miniscript::<bitcoin::PublicKey, Segwitv0>::parse_insane(script) {
+                Ok(ms) => ms,
+                Err(_) => return Ok(()),
+            }
+        }
+        None => return Ok(()),
+    };
+    let smallest = ms
+        .max_stack_size_per_path()
+        .iter()
+        .map(|path| path.stack_elements)
+        .min();
+    let standard = LimitExceeded::check(
+        ResourceLimit::WitnessElements,
+        false,
+        smallest,
+        MAX_STANDARD_P2WSH_STACK_ITEMS,
+    );
+    let exceeded = rules.apply(standard.into_iter().collect(), |limit| match limit {
+        ResourceLimit::WitnessElements => smallest,
+        _ => None,
+    });
+    match exceeded.first() {
+        Some(exceeded) => Err(InputError::MiniscriptError(crate::Error::ContextError(
+            ScriptContextError::MaxWitnessItemssExceeded {
+                actual: exceeded.actual,
+                limit: exceeded.max,
+            },
+        ))),
+        None => Ok(()),
+    }
+}
+
+// Check that every preimage of the input hashes to its claimed digest, and
+// that the preimages of the digests pushed by the scripts of the input are 32
+// bytes long, as miniscript hash fragments require. Preimages of other digests
+// may be meant for other protocols and can have any length.
+fn check_preimages(psbt: &Psbt, index: usize) -> Result<(), InputError> {
+    let input = &psbt.inputs[index];
+    let mut scripts = vec![];
+    scripts.extend(input.witness_script.as_ref());
+    scripts.extend(input.redeem_script.as_ref());
+    scripts.extend(input.tap_scripts.values().map(|(script, _)| script));
+    scripts.extend(input.witness_utxo.as_ref().map(|utxo| &utxo.script_pubkey));
+    if let Some(ref tx) = input.non_witness_utxo {
+        let vout = psbt.unsigned_tx.input[index].previous_output.vout;
+        scripts.extend(tx.output.get(vout as usize).map(|utxo| &utxo.script_pubkey));
+    }
+    let referenced = |digest: &[u8]| {
+        scripts.iter().any(|script| {
+            script.instructions().any(|ins| match ins {
+                Ok(bitcoin::blockdata::script::Instruction::PushBytes(bytes)) => bytes == digest,
+                _ => false,
+            })
+        })
+    };
+
+    fn check_map<H: bitcoin::hashes::Hash, F: Fn(&[u8]) -> bool>(
+        map: &BTreeMap<H, Vec<u8>>,
+        digest: fn(H) -> PreimageDigest,
+        referenced: F,
+    ) -> Result<(), InputError> {
+        for (hash, preimage) in map {
+            if <H as bitcoin::hashes::Hash>::hash(preimage) != *hash
+                || (preimage.len() != 32 && referenced(&hash[..]))
+            {
+                return Err(InputError::InvalidPreimage {
+                    hash: digest(*hash),
+                    preimage: preimage.clone(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    check_map(&input.sha256_preimages, PreimageDigest::Sha256, referenced)?;
+    check_map(
+        &input.hash256_preimages,
+        PreimageDigest::Hash256,
+        referenced,
+    )?;
+    check_map(
+        &input.ripemd160_preimages,
+        PreimageDigest::Ripemd160,
+        referenced,
+    )?;
+    check_map(
+        &input.hash160_preimages,
+        PreimageDigest::Hash160,
+        referenced,
+    )
+}
+
 /// Additional operations for miniscript descriptors for various psbt roles.
 /// Note that these APIs would generally error when used on scripts that are not
 /// miniscripts.
@@ -467,6 +871,9 @@ pub trait PsbtExt {
     ///
     /// For finalizing individual inputs, see also [`PsbtExt::finalize_inp`]
     ///
+    /// With the `parallel` feature, the inputs are finalized on multiple
+    /// threads sharing `secp`.
+    ///
     /// # Errors:
     ///
     /// - A vector of errors, one of each of failed finalized input
@@ -475,6 +882,24 @@ pub trait PsbtExt {
         secp: &secp256k1::Secp256k1<C>,
     ) -> Result<(), Vec<Error>>;
 
+    /// Same as [`PsbtExt::finalize_mut`], but only satisfies the timelocks which
+    /// allow the transaction to be mined in the block after `chain_tip`, so that
+    /// a spend path which is not yet valid is never picked over one that is.
+    ///
+    /// `confirmed_in` gives, for each input, the block the spent output was
+    /// confirmed in, or `None` if it is unconfirmed; missing entries are
+    /// treated as unconfirmed.
+    ///
+    /// # Errors:
+    ///
+    /// - A vector of errors, one of each of failed finalized input
+    fn finalize_mut_at<C: secp256k1::Verification>(
+        &mut self,
+        secp: &secp256k1::Secp256k1<C>,
+        chain_tip: BlockTime,
+        confirmed_in: &[Option<BlockTime>],
+    ) -> Result<(), Vec<Error>>;
+
     /// Same as [`PsbtExt::finalize_mut`], but does not mutate the input psbt and
     /// returns a new psbt
     ///
@@ -487,12 +912,34 @@ pub trait PsbtExt {
         secp: &secp256k1::Secp256k1<C>,
     ) -> Result<Psbt, (Psbt, Vec<Error>)>;
 
+    /// Same as [`PsbtExt::finalize_mut`], but checks the satisfactions
+    /// against the limits of `rules` rather than the standardness rules. With
+    /// [`RuleSet::Consensus`] this finalizes inputs whose satisfactions are
+    /// valid but would not be relayed by default nodes.
+    ///
+    /// # Errors:
+    ///
+    /// - A vector of errors, one of each of failed finalized input
+    fn finalize_mut_with<C: secp256k1::Verification>(
+        &mut self,
+        secp: &secp256k1::Secp256k1<C>,
+        rules: &RuleSet,
+    ) -> Result<(), Vec<Error>>;
+
     /// Same as [PsbtExt::finalize_mut], but allows for malleable satisfactions
     fn finalize_mall_mut<C: secp256k1::Verification>(
         &mut self,
         secp: &Secp256k1<C>,
     ) -> Result<(), Vec<Error>>;
 
+    /// Same as [`PsbtExt::finalize_mut_with`], but allows for malleable
+    /// satisfactions
+    fn finalize_mall_mut_with<C: secp256k1::Verification>(
+        &mut self,
+        secp: &Secp256k1<C>,
+        rules: &RuleSet,
+    ) -> Result<(), Vec<Error>>;
+
     /// Same as [PsbtExt::finalize], but allows for malleable satisfactions
     fn finalize_mall<C: secp256k1::Verification>(
         self,
@@ -512,6 +959,35 @@ pub trait PsbtExt {
         index: usize,
     ) -> Result<(), Error>;
 
+    /// Same as [`PsbtExt::finalize_inp_mut`], but reuses the descriptors and
+    /// taproot leaves cached in `ctx` by earlier calls, and caches those it
+    /// computes. Use this when finalizing the same input repeatedly, e.g. each
+    /// time one of its signatures arrives.
+    ///
+    /// # Errors:
+    ///
+    /// - Input error detailing why the finalization failed. The psbt is not mutated when the finalization fails
+    fn finalize_inp_mut_with_context<C: secp256k1::Verification>(
+        &mut self,
+        secp: &secp256k1::Secp256k1<C>,
+        index: usize,
+        ctx: &mut FinalizationContext,
+    ) -> Result<(), Error>;
+
+    /// Same as [`PsbtExt::finalize_inp_mut`], but finalizes a taproot input through
+    /// the script path of the given `leaf`, or through the key spend if `leaf` is
+    /// `None`, instead of picking the smallest satisfiable spend path
+    ///
+    /// # Errors:
+    ///
+    /// - Input error detailing why the finalization failed. The psbt is not mutated when the finalization fails
+    fn finalize_inp_with_leaf<C: secp256k1::Verification>(
+        &mut self,
+        secp: &secp256k1::Secp256k1<C>,
+        index: usize,
+        leaf: Option<TapLeafHash>,
+    ) -> Result<(), Error>;
+
     /// Same as [`PsbtExt::finalize_inp_mut`], but does not mutate the psbt and returns a new one
     ///
     /// # Errors:
@@ -573,6 +1049,17 @@ pub trait PsbtExt {
         descriptor: &Descriptor<DescriptorPublicKey>,
     ) -> Result<(), UtxoUpdateError>;
 
+    /// Update the PSBT output at `output_index` with the descriptor of the
+    /// transaction output, as [`PsbtOutputExt::update_with_descriptor_unchecked`],
+    /// checking that the descriptor has the script pubkey of the output.
+    ///
+    /// The `descriptor` **must not have any wildcards** in it.
+    fn update_output_with_descriptor(
+        &mut self,
+        output_index: usize,
+        descriptor: &Descriptor<DescriptorPublicKey>,
+    ) -> Result<(), OutputUpdateError>;
+
     /// Get the sighash message(data to sign) at input index `idx` based on the sighash
     /// flag specified in the [`Psbt`] sighash field. If the input sighash flag psbt field is `None`
     /// the [`SchnorrSighashType::Default`](bitcoin::util::sighash::SchnorrSighashType::Default) is chosen
@@ -597,23 +1084,232 @@ pub trait PsbtExt {
         cache: &mut SighashCache<T>,
         tapleaf_hash: Option<TapLeafHash>,
     ) -> Result<PsbtSighashMsg, SighashError>;
+
+    /// Get the sighash message at input index `idx`, as [`PsbtExt::sighash_msg`],
+    /// using a [`PsbtSighashCache`].
+    ///
+    /// The spent outputs are collected once for all the calls sharing `cache`,
+    /// and the message of each input, leaf and sighash type is only computed
+    /// once, so signing with several keys of the same input or leaf does not
+    /// hash the transaction again.
+    fn sighash_msg_cached<T: Deref<Target = bitcoin::Transaction>>(
+        &self,
+        idx: usize,
+        cache: &mut PsbtSighashCache<T>,
+        tapleaf_hash: Option<TapLeafHash>,
+    ) -> Result<PsbtSighashMsg, SighashError>;
+
+    /// Compute the absolute fee paid by the psbt transaction, that is the sum of
+    /// the spent utxo values minus the sum of the output values.
+    ///
+    /// Every input must have either a `witness_utxo` or a `non_witness_utxo`.
+    fn fee(&self) -> Result<u64, FeeError>;
+
+    /// Estimate the weight of the final transaction, assuming that every input
+    /// is finalized with a satisfaction of the corresponding descriptor's
+    /// [`Descriptor::max_satisfaction_weight`].
+    ///
+    /// `descriptors` must contain exactly one descriptor per input, in input order.
+    /// As with [`PsbtExt::update_input_with_descriptor`], the descriptors
+    /// **must not have any wildcards** in them.
+    fn estimated_weight(
+        &self,
+        descriptors: &[Descriptor<DescriptorPublicKey>],
+    ) -> Result<usize, FeeError>;
+
+    /// Combine `other` into this psbt, implementing the Combiner role of BIP 174.
+    ///
+    /// Unlike [`Psbt::combine`], which silently keeps one side of a conflict,
+    /// this refuses to merge psbts that disagree on the script being spent: a
+    /// different `redeem_script`, `witness_script`, taproot internal key, merkle
+    /// root or leaf script for the same input (or output) is reported as an
+    /// error. Key origins for the same key must agree; the leaf hashes listed
+    /// for a taproot key are merged. Signatures already present in this psbt are
+    /// kept in favour of those from `other`.
+    ///
+    /// On error, this psbt is left unchanged.
+    fn combine_with(&mut self, other: Psbt) -> Result<(), CombineError>;
+
+    /// Verify every signature in the psbt inputs against the sighash of the
+    /// corresponding input, reporting the result for each key of each input.
+    ///
+    /// This checks the `partial_sigs`, the `tap_key_sig` and the `tap_script_sigs`
+    /// of every input, with the sighash type of each signature. Sighash
+    /// computations are shared between all signatures. No check is made that the
+    /// signed keys appear in the scripts being spent.
+    ///
+    /// The secp256k1 bindings used do not yet expose batch verification, so
+    /// schnorr signatures are currently checked one at a time.
+    fn verify_signatures<C: secp256k1::Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+    ) -> Vec<BTreeMap<SignatureKey, Result<(), SignatureError>>>;
+
+    /// The keys which could still sign the input at `index` to complete one of
+    /// its satisfactions, identified by the origins recorded in the
+    /// `bip32_derivation` and `tap_key_origins` fields of the input.
+    ///
+    /// The spent script is inferred from the input, as done by the finalizer,
+    /// and its satisfaction is attempted with the signatures, preimages and
+    /// timelocks already available. The set is empty if the input can already
+    /// be finalized. Otherwise it contains every key whose signature is missing
+    /// from some spend path, including the internal key of a taproot output;
+    /// keys without a recorded origin are left out.
+    fn signers_for_input(
+        &self,
+        index: usize,
+    ) -> Result<
+        BTreeSet<(
+            bip32::Fingerprint,
+            bip32::DerivationPath,
+            descriptor::SinglePubKey,
+        )>,
+        Error,
+    >;
+
+    /// Summarize what the signers of each input will be asked to approve: the
+    /// keys recorded in the `bip32_derivation` and `tap_key_origins` fields of
+    /// the input, and the sighash type of the requested signatures.
+    ///
+    /// The sighash type is the `sighash_type` of the input or, if unset,
+    /// `SIGHASH_ALL` for pre-taproot inputs and `SIGHASH_DEFAULT` for taproot
+    /// inputs, as used by [`PsbtExt::sighash_msg`]. The [`Display`] of each
+    /// summary is a compact text format suited to the screens of airgapped
+    /// signing devices.
+    ///
+    /// [`Display`]: core::fmt::Display
+    fn derivation_summary(&self) -> Vec<Result<InputDerivations, SighashError>>;
+
+    /// The keys whose signatures are still missing from the cheapest way of
+    /// satisfying the input at `index`.
+    ///
+    /// The spent script is inferred from the input, as done by the finalizer.
+    /// Signatures of the input which do not verify are considered missing.
+    /// The cheapest satisfaction is the one the finalizer would choose if
+    /// every key with an origin in the `bip32_derivation` or `tap_key_origins`
+    /// fields of the input signed and every hash preimage was provided, with
+    /// the timelocks of the unsigned transaction. Keys without a recorded
+    /// origin are assumed unable to sign. The set is empty if the input can
+    /// already be finalized.
+    ///
+    /// # Errors
+    /// When the input cannot be satisfied even so.
+    fn required_signatures<C: secp256k1::Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+        index: usize,
+    ) -> Result<BTreeSet<descriptor::SinglePubKey>, Error>;
+
+    /// The digests whose preimages are still missing from the cheapest way of
+    /// satisfying the input at `index`, chosen as for
+    /// [`PsbtExt::required_signatures`].
+    fn required_hash_preimages<C: secp256k1::Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+        index: usize,
+    ) -> Result<BTreeSet<HashDigest>, Error>;
+
+    /// Set the sequence of the input at `index`, e.g. to enable a relative
+    /// timelock, checking that the spend paths of `descriptor` which the
+    /// current sequence allows are still allowed by the new one.
+    ///
+    /// A spend path is allowed when the sequence and transaction version meet
+    /// all of its `older` requirements, and the sequence is not final if it
+    /// has `after` requirements. The psbt is left unchanged on error.
+    fn set_relative_timelock<Pk: MiniscriptKey>(
+        &mut self,
+        index: usize,
+        sequence: u32,
+        descriptor: &Descriptor<Pk>,
+    ) -> Result<(), SequenceError>;
+
+    /// Signal replaceability (BIP 125) on the input at `index`.
+    ///
+    /// An input whose sequence already signals replaceability, as any
+    /// sequence enabling a relative timelock does, is left unchanged.
+    /// Otherwise its sequence is set to `0xFFFFFFFD`, with the same checks
+    /// as [`PsbtExt::set_relative_timelock`].
+    fn enable_rbf<Pk: MiniscriptKey>(
+        &mut self,
+        index: usize,
+        descriptor: &Descriptor<Pk>,
+    ) -> Result<(), SequenceError>;
+
+    /// Check that the psbt has the fields expected before it is handed to the
+    /// given BIP 174 `role`, so that malformed psbts can be rejected early.
+    ///
+    /// No input may be finalized. Before the [`Role::Updater`], no input may be
+    /// signed. Before the [`Role::Signer`] and [`Role::Finalizer`], every input
+    /// must have the utxo required by its descriptor, as checked by
+    /// [`PsbtExt::update_input_with_descriptor`], and the `redeem_script`,
+    /// `witness_script` and taproot fields the updater sets for it.
+    ///
+    /// `descriptors` must contain exactly one descriptor per input, in input order.
+    /// As with [`PsbtExt::update_input_with_descriptor`], the descriptors
+    /// **must not have any wildcards** in them.
+    fn verify_role(
+        &self,
+        role: Role,
+        descriptors: &[Descriptor<DescriptorPublicKey>],
+    ) -> Result<(), RoleError>;
+
+    /// Add the xpubs of `descriptor` to the global `xpub` map of the psbt,
+    /// with their origins, as many hardware signers require for multisig.
+    ///
+    /// An xpub without origin is added as its own master key. The psbt is
+    /// left unchanged if it already has one of the xpubs with another origin.
+    fn update_global_xpubs(
+        &mut self,
+        descriptor: &Descriptor<DescriptorPublicKey>,
+    ) -> Result<(), GlobalXpubError>;
+
+    /// Check that the `bip32_derivation` and `tap_key_origins` entries of
+    /// every input are consistent with the global xpubs: a key whose origin is
+    /// below a global xpub must be derived from it.
+    ///
+    /// Keys whose origin is not below any global xpub, e.g. the keys of
+    /// another wallet, are not checked.
+    fn verify_global_xpubs<C: secp256k1::Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+    ) -> Result<(), GlobalXpubError>;
 }
 
 impl PsbtExt for Psbt {
     fn finalize_mut<C: secp256k1::Verification>(
         &mut self,
         secp: &secp256k1::Secp256k1<C>,
+    ) -> Result<(), Vec<Error>> {
+        self.finalize_mut_with(secp, &RuleSet::Standard)
+    }
+
+    fn finalize_mut_with<C: secp256k1::Verification>(
+        &mut self,
+        secp: &secp256k1::Secp256k1<C>,
+        rules: &RuleSet,
     ) -> Result<(), Vec<Error>> {
         // Actually construct the witnesses
-        let mut errors = vec![];
-        for index in 0..self.inputs.len() {
-            match finalizer::finalize_input(self, index, secp, /*allow_mall*/ false) {
-                Ok(..) => {}
-                Err(e) => {
-                    errors.push(e);
-                }
-            }
+        let errors = finalizer::finalize_inputs(self, secp, /*allow_mall*/ false, None, rules);
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
         }
+    }
+
+    fn finalize_mut_at<C: secp256k1::Verification>(
+        &mut self,
+        secp: &secp256k1::Secp256k1<C>,
+        chain_tip: BlockTime,
+        confirmed_in: &[Option<BlockTime>],
+    ) -> Result<(), Vec<Error>> {
+        let errors = finalizer::finalize_inputs(
+            self,
+            secp,
+            /*allow_mall*/ false,
+            Some((chain_tip, confirmed_in)),
+            &RuleSet::Standard,
+        );
         if errors.is_empty() {
             Ok(())
         } else {
@@ -635,15 +1331,15 @@ impl PsbtExt for Psbt {
         &mut self,
         secp: &secp256k1::Secp256k1<C>,
     ) -> Result<(), Vec<Error>> {
-        let mut errors = vec![];
-        for index in 0..self.inputs.len() {
-            match finalizer::finalize_input(self, index, secp, /*allow_mall*/ true) {
-                Ok(..) => {}
-                Err(e) => {
-                    errors.push(e);
-                }
-            }
-        }
+        self.finalize_mall_mut_with(secp, &RuleSet::Standard)
+    }
+
+    fn finalize_mall_mut_with<C: secp256k1::Verification>(
+        &mut self,
+        secp: &secp256k1::Secp256k1<C>,
+        rules: &RuleSet,
+    ) -> Result<(), Vec<Error>> {
+        let errors = finalizer::finalize_inputs(self, secp, /*allow_mall*/ true, None, rules);
         if errors.is_empty() {
             Ok(())
         } else {
@@ -665,6 +1361,30 @@ impl PsbtExt for Psbt {
         &mut self,
         secp: &secp256k1::Secp256k1<C>,
         index: usize,
+    ) -> Result<(), Error> {
+        self.finalize_inp_mut_with_context(secp, index, &mut FinalizationContext::new())
+    }
+
+    fn finalize_inp_mut_with_context<C: secp256k1::Verification>(
+        &mut self,
+        secp: &secp256k1::Secp256k1<C>,
+        index: usize,
+        ctx: &mut FinalizationContext,
+    ) -> Result<(), Error> {
+        if index >= self.inputs.len() {
+            return Err(Error::InputIdxOutofBounds {
+                psbt_inp: self.inputs.len(),
+                index,
+            });
+        }
+        finalizer::finalize_input(self, index, secp, /*allow_mall*/ false, ctx)
+    }
+
+    fn finalize_inp_with_leaf<C: secp256k1::Verification>(
+        &mut self,
+        secp: &secp256k1::Secp256k1<C>,
+        index: usize,
+        leaf: Option<TapLeafHash>,
     ) -> Result<(), Error> {
         if index >= self.inputs.len() {
             return Err(Error::InputIdxOutofBounds {
@@ -672,7 +1392,19 @@ impl PsbtExt for Psbt {
                 index,
             });
         }
-        finalizer::finalize_input(self, index, secp, /*allow_mall*/ false)
+        let path = match leaf {
+            Some(leaf_hash) => finalizer::TapSpendPath::Leaf(leaf_hash),
+            None => finalizer::TapSpendPath::Key,
+        };
+        finalizer::finalize_input_at(
+            self,
+            index,
+            secp,
+            /*allow_mall*/ false,
+            None,
+            path,
+            &mut FinalizationContext::new(),
+        )
     }
 
     fn finalize_inp<C: secp256k1::Verification>(
@@ -697,7 +1429,13 @@ impl PsbtExt for Psbt {
                 index,
             });
         }
-        finalizer::finalize_input(self, index, secp, /*allow_mall*/ false)
+        finalizer::finalize_input(
+            self,
+            index,
+            secp,
+            /*allow_mall*/ false,
+            &mut FinalizationContext::new(),
+        )
     }
 
     fn finalize_inp_mall<C: secp256k1::Verification>(
@@ -750,57 +1488,41 @@ impl PsbtExt for Psbt {
             .get(input_index)
             .ok_or(UtxoUpdateError::MissingInputUtxo)?;
 
-        let desc_type = desc.desc_type();
+        let expected_spk = utxo_script_pubkey(input, txin, desc.desc_type())?;
 
-        if let Some(non_witness_utxo) = &input.non_witness_utxo {
-            if txin.previous_output.txid != non_witness_utxo.txid() {
-                return Err(UtxoUpdateError::UtxoCheck);
-            }
+        let (_, spk_check_passed) =
+            update_item_with_descriptor_helper(input, desc, Some(expected_spk))
+                .map_err(UtxoUpdateError::DerivationError)?;
+
+        if !spk_check_passed {
+            return Err(UtxoUpdateError::MismatchedScriptPubkey);
         }
 
-        let expected_spk = {
-            match (&input.witness_utxo, &input.non_witness_utxo) {
-                (Some(witness_utxo), None) => {
-                    if desc_type.segwit_version().is_some() {
-                        witness_utxo.script_pubkey.clone()
-                    } else {
-                        return Err(UtxoUpdateError::UtxoCheck);
-                    }
-                }
-                (None, Some(non_witness_utxo)) => {
-                    if desc_type.segwit_version().is_some() {
-                        return Err(UtxoUpdateError::UtxoCheck);
-                    }
-
-                    non_witness_utxo
-                        .output
-                        .get(txin.previous_output.vout as usize)
-                        .ok_or(UtxoUpdateError::UtxoCheck)?
-                        .script_pubkey
-                        .clone()
-                }
-                (Some(witness_utxo), Some(non_witness_utxo)) => {
-                    if witness_utxo
-                        != non_witness_utxo
-                            .output
-                            .get(txin.previous_output.vout as usize)
-                            .ok_or(UtxoUpdateError::UtxoCheck)?
-                    {
-                        return Err(UtxoUpdateError::UtxoCheck);
-                    }
+        Ok(())
+    }
 
-                    witness_utxo.script_pubkey.clone()
-                }
-                (None, None) => return Err(UtxoUpdateError::UtxoCheck),
-            }
-        };
+    fn update_output_with_descriptor(
+        &mut self,
+        output_index: usize,
+        desc: &Descriptor<DescriptorPublicKey>,
+    ) -> Result<(), OutputUpdateError> {
+        let n_outputs = self.outputs.len();
+        let output = self
+            .outputs
+            .get_mut(output_index)
+            .ok_or(OutputUpdateError::IndexOutOfBounds(output_index, n_outputs))?;
+        let txout = self
+            .unsigned_tx
+            .output
+            .get(output_index)
+            .ok_or(OutputUpdateError::MissingTxOut)?;
 
         let (_, spk_check_passed) =
-            update_input_with_descriptor_helper(input, desc, Some(expected_spk))
-                .map_err(UtxoUpdateError::DerivationError)?;
+            update_item_with_descriptor_helper(output, desc, Some(txout.script_pubkey.clone()))
+                .map_err(OutputUpdateError::DerivationError)?;
 
         if !spk_check_passed {
-            return Err(UtxoUpdateError::MismatchedScriptPubkey);
+            return Err(OutputUpdateError::MismatchedScriptPubkey);
         }
 
         Ok(())
@@ -812,90 +1534,524 @@ impl PsbtExt for Psbt {
         cache: &mut SighashCache<T>,
         tapleaf_hash: Option<TapLeafHash>,
     ) -> Result<PsbtSighashMsg, SighashError> {
-        // Infer a descriptor at idx
         if idx >= self.inputs.len() {
             return Err(SighashError::IndexOutOfBounds(idx, self.inputs.len()));
         }
-        let inp = &self.inputs[idx];
         let prevouts = finalizer::prevouts(self).map_err(|_e| SighashError::MissingSpendUtxos)?;
         // Note that as per Psbt spec we should have access to spent_utxos for the transaction
         // Even if the transaction does not require SighashAll, we create `Prevouts::All` for code simplicity
         let prevouts = bitcoin::util::sighash::Prevouts::All(&prevouts);
-        let inp_spk =
-            finalizer::get_scriptpubkey(self, idx).map_err(|_e| SighashError::MissingInputUtxo)?;
-        if inp_spk.is_v1_p2tr() {
-            let hash_ty = inp
-                .sighash_type
-                .map(|sighash_type| sighash_type.schnorr_hash_ty())
-                .unwrap_or(Ok(SchnorrSighashType::Default))
-                .map_err(|_e| SighashError::InvalidSighashType)?;
-            match tapleaf_hash {
-                Some(leaf_hash) => {
-                    let tap_sighash_msg = cache
-                        .taproot_script_spend_signature_hash(idx, &prevouts, leaf_hash, hash_ty)?;
-                    Ok(PsbtSighashMsg::TapSighash(tap_sighash_msg))
+        sighash_msg_helper(
+            self,
+            idx,
+            cache,
+            &prevouts,
+            tapleaf_hash,
+            self.inputs[idx].sighash_type,
+        )
+    }
+
+    fn sighash_msg_cached<T: Deref<Target = bitcoin::Transaction>>(
+        &self,
+        idx: usize,
+        cache: &mut PsbtSighashCache<T>,
+        tapleaf_hash: Option<TapLeafHash>,
+    ) -> Result<PsbtSighashMsg, SighashError> {
+        if idx >= self.inputs.len() {
+            return Err(SighashError::IndexOutOfBounds(idx, self.inputs.len()));
+        }
+        cache.sighash_msg(self, idx, tapleaf_hash, self.inputs[idx].sighash_type)
+    }
+
+    fn fee(&self) -> Result<u64, FeeError> {
+        let mut input_value: u64 = 0;
+        for index in 0..self.inputs.len() {
+            let utxo =
+                finalizer::get_utxo(self, index).map_err(|_e| FeeError::MissingInputUtxo(index))?;
+            input_value = input_value
+                .checked_add(utxo.value)
+                .ok_or(FeeError::ValueOverflow)?;
+        }
+        let mut output_value: u64 = 0;
+        for output in &self.unsigned_tx.output {
+            output_value = output_value
+                .checked_add(output.value)
+                .ok_or(FeeError::ValueOverflow)?;
+        }
+        input_value
+            .checked_sub(output_value)
+            .ok_or(FeeError::NegativeFee)
+    }
+
+    fn estimated_weight(
+        &self,
+        descriptors: &[Descriptor<DescriptorPublicKey>],
+    ) -> Result<usize, FeeError> {
+        let n_inputs = self.unsigned_tx.input.len();
+        if descriptors.len() != n_inputs {
+            return Err(FeeError::DescriptorCountMismatch(
+                descriptors.len(),
+                n_inputs,
+            ));
+        }
+        let secp = secp256k1::Secp256k1::verification_only();
+
+        // Start with the weight of the transaction without any witness data, and
+        // replace every scriptSig by the satisfaction weight of its descriptor,
+        // which already accounts for the scriptSig and witness length prefixes.
+        let mut weight = self.unsigned_tx.weight();
+        let mut has_witness = false;
+        let mut n_legacy_inputs = 0;
+        for (index, (txin, desc)) in self.unsigned_tx.input.iter().zip(descriptors).enumerate() {
+            let script_sig_len = txin.script_sig.len();
+            weight -= 4 * (bitcoin::VarInt(script_sig_len as u64).len() + script_sig_len);
+
+            let derived = desc
+                .translate_pk(&mut descriptor::PublicKeyDerivator {
+                    secp: &secp,
+                    index: None,
+                })
+                .map_err(FeeError::DerivationError)?;
+            weight += derived
+                .max_satisfaction_weight()
+                .map_err(|_e| FeeError::Unsatisfiable(index))?;
+            if derived.desc_type().segwit_version().is_some() {
+                has_witness = true;
+            } else {
+                n_legacy_inputs += 1;
+            }
+        }
+        if has_witness {
+            // segwit marker and flag, plus an empty witness for every legacy input
+            weight += 2 + n_legacy_inputs;
+        }
+        Ok(weight)
+    }
+
+    fn combine_with(&mut self, mut other: Psbt) -> Result<(), CombineError> {
+        if self.unsigned_tx != other.unsigned_tx
+            || self.inputs.len() != other.inputs.len()
+            || self.outputs.len() != other.outputs.len()
+        {
+            return Err(CombineError::UnequalTransactions);
+        }
+
+        // Check everything before touching either psbt, and prepare `other` so
+        // that rust-bitcoin's combiner, which lets `other` win on map entries,
+        // produces the merged result.
+        for (index, (input, other_input)) in
+            self.inputs.iter().zip(other.inputs.iter_mut()).enumerate()
+        {
+            if conflicts(&input.redeem_script, &other_input.redeem_script) {
+                return Err(CombineError::ConflictingRedeemScript(index));
+            }
+            if conflicts(&input.witness_script, &other_input.witness_script) {
+                return Err(CombineError::ConflictingWitnessScript(index));
+            }
+            if conflicts(&input.tap_internal_key, &other_input.tap_internal_key)
+                || conflicts(&input.tap_merkle_root, &other_input.tap_merkle_root)
+            {
+                return Err(CombineError::ConflictingTapTree(index));
+            }
+            for (control_block, script) in &other_input.tap_scripts {
+                match input.tap_scripts.get(control_block) {
+                    Some(ours) if ours != script => {
+                        return Err(CombineError::ConflictingTapScript(index))
+                    }
+                    _ => {}
                 }
-                None => {
-                    let tap_sighash_msg =
-                        cache.taproot_key_spend_signature_hash(idx, &prevouts, hash_ty)?;
-                    Ok(PsbtSighashMsg::TapSighash(tap_sighash_msg))
+            }
+            for (pk, source) in &other_input.bip32_derivation {
+                match input.bip32_derivation.get(pk) {
+                    Some(ours) if ours != source => {
+                        return Err(CombineError::ConflictingKeyOrigin(index))
+                    }
+                    _ => {}
                 }
             }
-        } else {
-            let hash_ty = inp
-                .sighash_type
-                .map(|sighash_type| sighash_type.ecdsa_hash_ty())
-                .unwrap_or(Ok(EcdsaSighashType::All))
-                .map_err(|_e| SighashError::InvalidSighashType)?;
-            let amt = finalizer::get_utxo(self, idx)
-                .map_err(|_e| SighashError::MissingInputUtxo)?
-                .value;
-            let is_nested_wpkh = inp_spk.is_p2sh()
-                && inp
-                    .redeem_script
-                    .as_ref()
-                    .map(|x| x.is_v0_p2wpkh())
-                    .unwrap_or(false);
-            let is_nested_wsh = inp_spk.is_p2sh()
-                && inp
-                    .redeem_script
-                    .as_ref()
-                    .map(|x| x.is_v0_p2wsh())
-                    .unwrap_or(false);
-            if inp_spk.is_v0_p2wpkh() || inp_spk.is_v0_p2wsh() || is_nested_wpkh || is_nested_wsh {
-                let msg = if inp_spk.is_v0_p2wpkh() {
-                    let script_code = script_code_wpkh(inp_spk);
-                    cache.segwit_signature_hash(idx, &script_code, amt, hash_ty)?
-                } else if is_nested_wpkh {
-                    let script_code = script_code_wpkh(
-                        inp.redeem_script
-                            .as_ref()
-                            .expect("Redeem script non-empty checked earlier"),
-                    );
-                    cache.segwit_signature_hash(idx, &script_code, amt, hash_ty)?
+            merge_tap_key_origins(&input.tap_key_origins, &mut other_input.tap_key_origins)
+                .map_err(|_| CombineError::ConflictingKeyOrigin(index))?;
+
+            for pk in input.partial_sigs.keys() {
+                other_input.partial_sigs.remove(pk);
+            }
+            for key in input.tap_script_sigs.keys() {
+                other_input.tap_script_sigs.remove(key);
+            }
+        }
+        for (index, (output, other_output)) in self
+            .outputs
+            .iter()
+            .zip(other.outputs.iter_mut())
+            .enumerate()
+        {
+            if conflicts(&output.redeem_script, &other_output.redeem_script)
+                || conflicts(&output.witness_script, &other_output.witness_script)
+                || conflicts(&output.tap_internal_key, &other_output.tap_internal_key)
+            {
+                return Err(CombineError::ConflictingOutput(index));
+            }
+            for (pk, source) in &other_output.bip32_derivation {
+                match output.bip32_derivation.get(pk) {
+                    Some(ours) if ours != source => {
+                        return Err(CombineError::ConflictingOutput(index))
+                    }
+                    _ => {}
+                }
+            }
+            merge_tap_key_origins(&output.tap_key_origins, &mut other_output.tap_key_origins)
+                .map_err(|_| CombineError::ConflictingOutput(index))?;
+        }
+
+        // Global fields (xpubs) may still conflict, so combine into a copy.
+        let mut combined = self.clone();
+        combined.combine(other).map_err(CombineError::Psbt)?;
+        *self = combined;
+        Ok(())
+    }
+
+    fn signers_for_input(
+        &self,
+        index: usize,
+    ) -> Result<
+        BTreeSet<(
+            bip32::Fingerprint,
+            bip32::DerivationPath,
+            descriptor::SinglePubKey,
+        )>,
+        Error,
+    > {
+        if index >= self.inputs.len() {
+            return Err(Error::InputIdxOutofBounds {
+                psbt_inp: self.inputs.len(),
+                index,
+            });
+        }
+        finalizer::missing_signers(self, index).map_err(|e| Error::InputError(e, index))
+    }
+
+    fn required_signatures<C: secp256k1::Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+        index: usize,
+    ) -> Result<BTreeSet<descriptor::SinglePubKey>, Error> {
+        required_items(self, secp, index).map(|(signatures, _)| signatures)
+    }
+
+    fn required_hash_preimages<C: secp256k1::Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+        index: usize,
+    ) -> Result<BTreeSet<HashDigest>, Error> {
+        required_items(self, secp, index).map(|(_, preimages)| preimages)
+    }
+
+    fn derivation_summary(&self) -> Vec<Result<InputDerivations, SighashError>> {
+        (0..self.inputs.len())
+            .map(|idx| {
+                let inp = &self.inputs[idx];
+                let inp_spk = finalizer::get_scriptpubkey(self, idx)
+                    .map_err(|_e| SighashError::MissingInputUtxo)?;
+                let sighash_type = if inp_spk.is_v1_p2tr() {
+                    let hash_ty = inp
+                        .sighash_type
+                        .map(|sighash_type| sighash_type.schnorr_hash_ty())
+                        .unwrap_or(Ok(SchnorrSighashType::Default))
+                        .map_err(|_e| SighashError::InvalidSighashType)?;
+                    InputSighashType::Schnorr(hash_ty)
                 } else {
-                    // wsh and nested wsh, script code is witness script
-                    let script_code = inp
-                        .witness_script
-                        .as_ref()
-                        .ok_or(SighashError::MissingWitnessScript)?;
-                    cache.segwit_signature_hash(idx, script_code, amt, hash_ty)?
+                    let hash_ty = inp
+                        .sighash_type
+                        .map(|sighash_type| sighash_type.ecdsa_hash_ty())
+                        .unwrap_or(Ok(EcdsaSighashType::All))
+                        .map_err(|_e| SighashError::InvalidSighashType)?;
+                    InputSighashType::Ecdsa(hash_ty)
                 };
-                Ok(PsbtSighashMsg::EcdsaSighash(msg))
-            } else {
-                // legacy sighash case
-                let script_code = if inp_spk.is_p2sh() {
+
+                let full_keys = inp.bip32_derivation.iter().map(|(pk, (fp, path))| {
+                    let pk = bitcoin::PublicKey::new(*pk);
+                    (*fp, path.clone(), descriptor::SinglePubKey::FullKey(pk))
+                });
+                let xonly_keys = inp.tap_key_origins.iter().map(|(pk, (_, (fp, path)))| {
+                    (*fp, path.clone(), descriptor::SinglePubKey::XOnly(*pk))
+                });
+                Ok(InputDerivations {
+                    keys: full_keys.chain(xonly_keys).collect(),
+                    sighash_type,
+                })
+            })
+            .collect()
+    }
+
+    fn set_relative_timelock<Pk: MiniscriptKey>(
+        &mut self,
+        index: usize,
+        sequence: u32,
+        descriptor: &Descriptor<Pk>,
+    ) -> Result<(), SequenceError> {
+        let n_inputs = self.unsigned_tx.input.len();
+        let version = self.unsigned_tx.version;
+        let txin = self
+            .unsigned_tx
+            .input
+            .get_mut(index)
+            .ok_or(SequenceError::IndexOutOfBounds(index, n_inputs))?;
+        for path in descriptor.iter_spend_paths() {
+            for timelocks in path.timelocks {
+                if sequence_allows(&timelocks, txin.sequence, version)
+                    && !sequence_allows(&timelocks, sequence, version)
+                {
+                    return Err(SequenceError::DisallowedPath(timelocks));
+                }
+            }
+        }
+        txin.sequence = sequence;
+        Ok(())
+    }
+
+    fn enable_rbf<Pk: MiniscriptKey>(
+        &mut self,
+        index: usize,
+        descriptor: &Descriptor<Pk>,
+    ) -> Result<(), SequenceError> {
+        let n_inputs = self.unsigned_tx.input.len();
+        let txin = self
+            .unsigned_tx
+            .input
+            .get(index)
+            .ok_or(SequenceError::IndexOutOfBounds(index, n_inputs))?;
+        if txin.sequence < RBF_SEQUENCE_THRESHOLD {
+            return Ok(());
+        }
+        self.set_relative_timelock(index, RBF_SEQUENCE_THRESHOLD - 1, descriptor)
+    }
+
+    fn verify_role(
+        &self,
+        role: Role,
+        descriptors: &[Descriptor<DescriptorPublicKey>],
+    ) -> Result<(), RoleError> {
+        roles::verify_role(self, role, descriptors)
+    }
+
+    fn update_global_xpubs(
+        &mut self,
+        descriptor: &Descriptor<DescriptorPublicKey>,
+    ) -> Result<(), GlobalXpubError> {
+        xpubs::update_global_xpubs(self, descriptor)
+    }
+
+    fn verify_global_xpubs<C: secp256k1::Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+    ) -> Result<(), GlobalXpubError> {
+        xpubs::verify_global_xpubs(self, secp)
+    }
+
+    fn verify_signatures<C: secp256k1::Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+    ) -> Vec<BTreeMap<SignatureKey, Result<(), SignatureError>>> {
+        let mut cache = PsbtSighashCache::new(&self.unsigned_tx);
+        (0..self.inputs.len())
+            .map(|idx| verify_input_signatures(self, idx, secp, &mut cache))
+            .collect()
+    }
+}
+
+// Verify every signature of the input at `idx`, as done by
+// `PsbtExt::verify_signatures`
+fn verify_input_signatures<C: secp256k1::Verification, T: Deref<Target = bitcoin::Transaction>>(
+    psbt: &Psbt,
+    idx: usize,
+    secp: &Secp256k1<C>,
+    cache: &mut PsbtSighashCache<T>,
+) -> BTreeMap<SignatureKey, Result<(), SignatureError>> {
+    let inp = &psbt.inputs[idx];
+    let mut sighash = |tapleaf_hash, sighash_type: psbt::PsbtSighashType| {
+        cache
+            .sighash_msg(psbt, idx, tapleaf_hash, Some(sighash_type))
+            .map(|msg| msg.to_secp_msg())
+            .map_err(SignatureError::Sighash)
+    };
+
+    let mut results = BTreeMap::new();
+    for (pk, sig) in &inp.partial_sigs {
+        let res = sighash(None, sig.hash_ty.into()).and_then(|msg| {
+            secp.verify_ecdsa(&msg, &sig.sig, &pk.inner)
+                .map_err(|_e| SignatureError::InvalidSignature)
+        });
+        results.insert(SignatureKey::Ecdsa(*pk), res);
+    }
+    if let Some(sig) = inp.tap_key_sig {
+        let res = finalizer::get_scriptpubkey(psbt, idx)
+            .ok()
+            .filter(|spk| spk.is_v1_p2tr())
+            .and_then(|spk| bitcoin::XOnlyPublicKey::from_slice(&spk[2..]).ok())
+            .ok_or(SignatureError::InvalidOutputKey)
+            .and_then(|output_key| {
+                let msg = sighash(None, sig.hash_ty.into())?;
+                secp.verify_schnorr(&sig.sig, &msg, &output_key)
+                    .map_err(|_e| SignatureError::InvalidSignature)
+            });
+        results.insert(SignatureKey::TapKey, res);
+    }
+    for (&(pk, leaf_hash), sig) in &inp.tap_script_sigs {
+        let res = sighash(Some(leaf_hash), sig.hash_ty.into()).and_then(|msg| {
+            secp.verify_schnorr(&sig.sig, &msg, &pk)
+                .map_err(|_e| SignatureError::InvalidSignature)
+        });
+        results.insert(SignatureKey::TapScript(pk, leaf_hash), res);
+    }
+    results
+}
+
+// The signatures and preimages missing from the cheapest satisfaction of the
+// input at `index`, once its invalid signatures are removed
+fn required_items<C: secp256k1::Verification>(
+    psbt: &Psbt,
+    secp: &Secp256k1<C>,
+    index: usize,
+) -> Result<(BTreeSet<descriptor::SinglePubKey>, BTreeSet<HashDigest>), Error> {
+    if index >= psbt.inputs.len() {
+        return Err(Error::InputIdxOutofBounds {
+            psbt_inp: psbt.inputs.len(),
+            index,
+        });
+    }
+    let results = psbt.verify_signatures(secp).swap_remove(index);
+    let mut psbt = psbt.clone();
+    let inp = &mut psbt.inputs[index];
+    for (key, res) in results {
+        if res.is_err() {
+            match key {
+                SignatureKey::Ecdsa(pk) => {
+                    inp.partial_sigs.remove(&pk);
+                }
+                SignatureKey::TapKey => inp.tap_key_sig = None,
+                SignatureKey::TapScript(pk, leaf_hash) => {
+                    inp.tap_script_sigs.remove(&(pk, leaf_hash));
+                }
+            }
+        }
+    }
+    finalizer::cheapest_path_missing_items(psbt, index).map_err(|e| Error::InputError(e, index))
+}
+
+// Computes the sighash message of input `idx` for the given sighash type,
+// defaulting as per BIP 174 when `sighash_type` is `None`
+fn sighash_msg_helper<T: Deref<Target = bitcoin::Transaction>, P: Borrow<bitcoin::TxOut>>(
+    psbt: &Psbt,
+    idx: usize,
+    cache: &mut SighashCache<T>,
+    prevouts: &bitcoin::util::sighash::Prevouts<P>,
+    tapleaf_hash: Option<TapLeafHash>,
+    sighash_type: Option<psbt::PsbtSighashType>,
+) -> Result<PsbtSighashMsg, SighashError> {
+    let inp = &psbt.inputs[idx];
+    let inp_spk =
+        finalizer::get_scriptpubkey(psbt, idx).map_err(|_e| SighashError::MissingInputUtxo)?;
+    if inp_spk.is_v1_p2tr() {
+        let hash_ty = sighash_type
+            .map(|sighash_type| sighash_type.schnorr_hash_ty())
+            .unwrap_or(Ok(SchnorrSighashType::Default))
+            .map_err(|_e| SighashError::InvalidSighashType)?;
+        match tapleaf_hash {
+            Some(leaf_hash) => {
+                let tap_sighash_msg =
+                    cache.taproot_script_spend_signature_hash(idx, prevouts, leaf_hash, hash_ty)?;
+                Ok(PsbtSighashMsg::TapSighash(tap_sighash_msg))
+            }
+            None => {
+                let tap_sighash_msg =
+                    cache.taproot_key_spend_signature_hash(idx, prevouts, hash_ty)?;
+                Ok(PsbtSighashMsg::TapSighash(tap_sighash_msg))
+            }
+        }
+    } else {
+        let hash_ty = sighash_type
+            .map(|sighash_type| sighash_type.ecdsa_hash_ty())
+            .unwrap_or(Ok(EcdsaSighashType::All))
+            .map_err(|_e| SighashError::InvalidSighashType)?;
+        let amt = finalizer::get_utxo(psbt, idx)
+            .map_err(|_e| SighashError::MissingInputUtxo)?
+            .value;
+        let is_nested_wpkh = inp_spk.is_p2sh()
+            && inp
+                .redeem_script
+                .as_ref()
+                .map(|x| x.is_v0_p2wpkh())
+                .unwrap_or(false);
+        let is_nested_wsh = inp_spk.is_p2sh()
+            && inp
+                .redeem_script
+                .as_ref()
+                .map(|x| x.is_v0_p2wsh())
+                .unwrap_or(false);
+        if inp_spk.is_v0_p2wpkh() || inp_spk.is_v0_p2wsh() || is_nested_wpkh || is_nested_wsh {
+            let msg = if inp_spk.is_v0_p2wpkh() {
+                let script_code = script_code_wpkh(inp_spk);
+                cache.segwit_signature_hash(idx, &script_code, amt, hash_ty)?
+            } else if is_nested_wpkh {
+                let script_code = script_code_wpkh(
                     inp.redeem_script
                         .as_ref()
-                        .ok_or(SighashError::MissingRedeemScript)?
-                } else {
-                    inp_spk
-                };
-                let msg = cache.legacy_signature_hash(idx, script_code, hash_ty.to_u32())?;
-                Ok(PsbtSighashMsg::EcdsaSighash(msg))
+                        .expect("Redeem script non-empty checked earlier"),
+                );
+                cache.segwit_signature_hash(idx, &script_code, amt, hash_ty)?
+            } else {
+                // wsh and nested wsh, script code is witness script
+                let script_code = inp
+                    .witness_script
+                    .as_ref()
+                    .ok_or(SighashError::MissingWitnessScript)?;
+                cache.segwit_signature_hash(idx, script_code, amt, hash_ty)?
+            };
+            Ok(PsbtSighashMsg::EcdsaSighash(msg))
+        } else {
+            // legacy sighash case
+            let script_code = if inp_spk.is_p2sh() {
+                inp.redeem_script
+                    .as_ref()
+                    .ok_or(SighashError::MissingRedeemScript)?
+            } else {
+                inp_spk
+            };
+            let msg = cache.legacy_signature_hash(idx, script_code, hash_ty.to_u32())?;
+            Ok(PsbtSighashMsg::EcdsaSighash(msg))
+        }
+    }
+}
+
+// Whether two optional psbt fields are both set to different values
+fn conflicts<T: PartialEq>(ours: &Option<T>, theirs: &Option<T>) -> bool {
+    match (ours, theirs) {
+        (Some(ours), Some(theirs)) => ours != theirs,
+        _ => false,
+    }
+}
+
+// Replace every entry of `theirs` that is also in `ours` by the union of
+// their leaf hashes. Errors if the same key has different origins.
+fn merge_tap_key_origins(
+    ours: &BTreeMap<bitcoin::XOnlyPublicKey, (Vec<TapLeafHash>, bip32::KeySource)>,
+    theirs: &mut BTreeMap<bitcoin::XOnlyPublicKey, (Vec<TapLeafHash>, bip32::KeySource)>,
+) -> Result<(), ()> {
+    for (key, (their_leaves, their_source)) in theirs.iter_mut() {
+        if let Some((our_leaves, our_source)) = ours.get(key) {
+            if our_source != their_source {
+                return Err(());
+            }
+            let mut leaves = our_leaves.clone();
+            for leaf in their_leaves.iter() {
+                if !leaves.contains(leaf) {
+                    leaves.push(*leaf);
+                }
             }
+            *their_leaves = leaves;
         }
     }
+    Ok(())
 }
 
 /// Extension trait for PSBT inputs
@@ -920,6 +2076,20 @@ pub trait PsbtInputExt {
         &mut self,
         descriptor: &Descriptor<DescriptorPublicKey>,
     ) -> Result<Descriptor<bitcoin::PublicKey>, descriptor::ConversionError>;
+
+    /// Populate the PSBT input's `sha256_preimages`, `hash256_preimages`,
+    /// `ripemd160_preimages` and `hash160_preimages` with the preimages that
+    /// `store` knows for the hash fragments of the input's scripts.
+    ///
+    /// The scripts are taken from the input's `witness_script`, `redeem_script`
+    /// (unless it is a witness program) and `tap_scripts`, so this should be
+    /// called after these fields are populated, e.g. by
+    /// [`update_with_descriptor_unchecked`]. Preimages already present in the
+    /// input are kept. Returns an error if one of the scripts is not a Miniscript.
+    ///
+    /// [`update_with_descriptor_unchecked`]: PsbtInputExt::update_with_descriptor_unchecked
+    fn update_with_preimages<S: PreimageProvider>(&mut self, store: &S)
+        -> Result<(), super::Error>;
 }
 
 impl PsbtInputExt for psbt::Input {
@@ -927,110 +2097,414 @@ impl PsbtInputExt for psbt::Input {
         &mut self,
         descriptor: &Descriptor<DescriptorPublicKey>,
     ) -> Result<Descriptor<bitcoin::PublicKey>, descriptor::ConversionError> {
-        let (derived, _) = update_input_with_descriptor_helper(self, descriptor, None)?;
+        let (derived, _) = update_item_with_descriptor_helper(self, descriptor, None)?;
         Ok(derived)
     }
+
+    fn update_with_preimages<S: PreimageProvider>(
+        &mut self,
+        store: &S,
+    ) -> Result<(), super::Error> {
+        if let Some(ref witness_script) = self.witness_script {
+            let ms = Miniscript::<_, Segwitv0>::parse_insane(witness_script)?;
+            add_preimages(self, &ms, store);
+        } else if let Some(ref redeem_script) = self.redeem_script {
+            if !redeem_script.is_witness_program() {
+                let ms = Miniscript::<_, Legacy>::parse_insane(redeem_script)?;
+                add_preimages(self, &ms, store);
+            }
+        }
+        let tap_scripts: Vec<_> = self
+            .tap_scripts
+            .values()
+            .filter(|&&(_, ver)| ver == LeafVersion::TapScript)
+            .map(|(script, _)| script.clone())
+            .collect();
+        for script in tap_scripts {
+            let ms = Miniscript::<_, Tap>::parse_insane(&script)?;
+            add_preimages(self, &ms, store);
+        }
+        Ok(())
+    }
 }
 
-fn update_input_with_descriptor_helper(
-    input: &mut psbt::Input,
-    descriptor: &Descriptor<DescriptorPublicKey>,
-    check_script: Option<Script>,
-    // the return value is a tuple here since the two internal calls to it require different info.
-    // One needs the derived descriptor and the other needs to know whether the script_pubkey check
-    // failed.
-) -> Result<(Descriptor<bitcoin::PublicKey>, bool), descriptor::ConversionError> {
-    use core::cell::RefCell;
-    let secp = secp256k1::Secp256k1::verification_only();
+/// A store of hash preimages, used by [`PsbtInputExt::update_with_preimages`].
+///
+/// This is implemented for every [`Satisfier`] of `bitcoin::PublicKey`s, so the
+/// preimage maps and [`PreimageFn`](crate::miniscript::satisfy::PreimageFn)
+/// satisfiers can be used as stores, and combined with
+/// [`SatisfierExt::chain`](crate::SatisfierExt::chain).
+pub trait PreimageProvider {
+    /// Given a SHA256 hash, look up its preimage
+    fn sha256_preimage(&self, h: &sha256::Hash) -> Option<Preimage32>;
 
-    let derived = if let Descriptor::Tr(_) = &descriptor {
-        let mut hash_lookup = BTreeMap::new();
-        let derived = descriptor.translate_pk(
-            |xpk| xpk.derive_public_key(&secp),
-            |xpk| {
-                let xonly = xpk.derive_public_key(&secp)?.to_x_only_pubkey();
-                let hash = xonly.to_pubkeyhash();
-                hash_lookup.insert(hash, xonly);
-                Ok(hash)
-            },
-        )?;
+    /// Given a HASH256 hash, look up its preimage
+    fn hash256_preimage(&self, h: &hash256::Hash) -> Option<Preimage32>;
 
-        if let Some(check_script) = check_script {
-            if check_script != derived.script_pubkey() {
-                return Ok((derived, false));
-            }
-        }
+    /// Given a RIPEMD160 hash, look up its preimage
+    fn ripemd160_preimage(&self, h: &ripemd160::Hash) -> Option<Preimage32>;
 
-        // NOTE: they will both always be Tr
-        if let (Descriptor::Tr(tr_derived), Descriptor::Tr(tr_xpk)) = (&derived, descriptor) {
-            let spend_info = tr_derived.spend_info();
-            let ik_derived = spend_info.internal_key();
-            let ik_xpk = tr_xpk.internal_key();
-            input.tap_internal_key = Some(ik_derived);
-            input.tap_merkle_root = spend_info.merkle_root();
-            input.tap_key_origins.insert(
-                ik_derived,
-                (
-                    vec![],
-                    (ik_xpk.master_fingerprint(), ik_xpk.full_derivation_path()),
-                ),
-            );
+    /// Given a HASH160 hash, look up its preimage
+    fn hash160_preimage(&self, h: &hash160::Hash) -> Option<Preimage32>;
+}
 
-            for ((_depth_der, ms_derived), (_depth, ms)) in
-                tr_derived.iter_scripts().zip(tr_xpk.iter_scripts())
-            {
-                debug_assert_eq!(_depth_der, _depth);
-                let leaf_script = (ms_derived.encode(), LeafVersion::TapScript);
-                let tapleaf_hash = TapLeafHash::from_script(&leaf_script.0, leaf_script.1);
-                let control_block = spend_info
-                    .control_block(&leaf_script)
-                    .expect("Control block must exist in script map for every known leaf");
-                input.tap_scripts.insert(control_block, leaf_script);
+impl<S: Satisfier<bitcoin::PublicKey>> PreimageProvider for S {
+    fn sha256_preimage(&self, h: &sha256::Hash) -> Option<Preimage32> {
+        self.lookup_sha256(h)
+    }
 
-                for (pk_pkh_derived, pk_pkh_xpk) in ms_derived.iter_pk_pkh().zip(ms.iter_pk_pkh()) {
-                    let (xonly, xpk) = match (pk_pkh_derived, pk_pkh_xpk) {
-                        (PkPkh::PlainPubkey(pk), PkPkh::PlainPubkey(xpk)) => {
-                            (pk.to_x_only_pubkey(), xpk)
-                        }
-                        (PkPkh::HashedPubkey(hash), PkPkh::HashedPubkey(xpk)) => (
-                            *hash_lookup
-                                .get(&hash)
-                                .expect("translate_pk inserted an entry for every hash"),
-                            xpk,
-                        ),
-                        _ => unreachable!("the iterators work in the same order"),
-                    };
+    fn hash256_preimage(&self, h: &hash256::Hash) -> Option<Preimage32> {
+        self.lookup_hash256(h)
+    }
+
+    fn ripemd160_preimage(&self, h: &ripemd160::Hash) -> Option<Preimage32> {
+        self.lookup_ripemd160(h)
+    }
 
+    fn hash160_preimage(&self, h: &hash160::Hash) -> Option<Preimage32> {
+        self.lookup_hash160(h)
+    }
+}
+
+// Add the preimages of all hash fragments of `ms` known to `store` to the input
+fn add_preimages<Pk, Ctx, S>(input: &mut psbt::Input, ms: &Miniscript<Pk, Ctx>, store: &S)
+where
+    Pk: MiniscriptKey<
+        Sha256 = sha256::Hash,
+        Hash256 = hash256::Hash,
+        Ripemd160 = ripemd160::Hash,
+        Hash160 = hash160::Hash,
+    >,
+    Ctx: ScriptContext,
+    S: PreimageProvider,
+{
+    for node in ms.iter() {
+        match node.node {
+            Terminal::Sha256(ref h) => {
+                if let Some(preimage) = store.sha256_preimage(h) {
                     input
-                        .tap_key_origins
-                        .entry(xonly)
-                        .and_modify(|(tapleaf_hashes, _)| {
-                            if tapleaf_hashes.last() != Some(&tapleaf_hash) {
-                                tapleaf_hashes.push(tapleaf_hash);
-                            }
-                        })
-                        .or_insert_with(|| {
-                            (
-                                vec![tapleaf_hash],
-                                (xpk.master_fingerprint(), xpk.full_derivation_path()),
-                            )
-                        });
+                        .sha256_preimages
+                        .entry(*h)
+                        .or_insert_with(|| preimage.to_vec());
+                }
+            }
+            Terminal::Hash256(ref h) => {
+                if let Some(preimage) = store.hash256_preimage(h) {
+                    input
+                        .hash256_preimages
+                        .entry(h.as_hash())
+                        .or_insert_with(|| preimage.to_vec());
+                }
+            }
+            Terminal::Ripemd160(ref h) => {
+                if let Some(preimage) = store.ripemd160_preimage(h) {
+                    input
+                        .ripemd160_preimages
+                        .entry(*h)
+                        .or_insert_with(|| preimage.to_vec());
+                }
+            }
+            Terminal::Hash160(ref h) => {
+                if let Some(preimage) = store.hash160_preimage(h) {
+                    input
+                        .hash160_preimages
+                        .entry(*h)
+                        .or_insert_with(|| preimage.to_vec());
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+// Translator deriving the public keys of a taproot descriptor, recording the
+// x-only key of every derived key hash
+struct XOnlyHashLookUp(
+    BTreeMap<hash160::Hash, bitcoin::XOnlyPublicKey>,
+    secp256k1::Secp256k1<secp256k1::VerifyOnly>,
+);
+
+impl Translator<DescriptorPublicKey, bitcoin::PublicKey, descriptor::ConversionError>
+    for XOnlyHashLookUp
+{
+    fn pk(
+        &mut self,
+        xpk: &DescriptorPublicKey,
+    ) -> Result<bitcoin::PublicKey, descriptor::ConversionError> {
+        xpk.derive_public_key(&self.1)
+    }
+
+    fn pkh(
+        &mut self,
+        xpk: &DescriptorPublicKey,
+    ) -> Result<hash160::Hash, descriptor::ConversionError> {
+        let xonly = xpk.derive_public_key(&self.1)?.to_x_only_pubkey();
+        let hash = xonly.to_pubkeyhash();
+        self.0.insert(hash, xonly);
+        Ok(hash)
+    }
+
+    translate_hash_clone!(
+        DescriptorPublicKey,
+        bitcoin::PublicKey,
+        descriptor::ConversionError
+    );
+}
+
+// Translator deriving the public keys of a descriptor, recording the origin
+// of every derived key
+struct KeySourceLookUp(
+    BTreeMap<secp256k1::PublicKey, bip32::KeySource>,
+    secp256k1::Secp256k1<secp256k1::VerifyOnly>,
+);
+
+impl Translator<DescriptorPublicKey, bitcoin::PublicKey, descriptor::ConversionError>
+    for KeySourceLookUp
+{
+    fn pk(
+        &mut self,
+        xpk: &DescriptorPublicKey,
+    ) -> Result<bitcoin::PublicKey, descriptor::ConversionError> {
+        let derived = xpk.derive_public_key(&self.1)?;
+        self.0.insert(
+            derived.to_public_key().inner,
+            (xpk.master_fingerprint(), xpk.full_derivation_path()),
+        );
+        Ok(derived)
+    }
+
+    fn pkh(
+        &mut self,
+        xpk: &DescriptorPublicKey,
+    ) -> Result<hash160::Hash, descriptor::ConversionError> {
+        Ok(self.pk(xpk)?.to_pubkeyhash())
+    }
+
+    translate_hash_clone!(
+        DescriptorPublicKey,
+        bitcoin::PublicKey,
+        descriptor::ConversionError
+    );
+}
+
+/// Extension trait for PSBT outputs
+pub trait PsbtOutputExt {
+    /// Given the descriptor of a transaction output, populate the PSBT
+    /// output's fields so that signers of the transaction can recognize it,
+    /// e.g. as change.
+    ///
+    /// The `bip32_derivation`, `redeem_script` and `witness_script` are
+    /// populated as for inputs, while taproot outputs get their
+    /// `tap_internal_key`, `tap_key_origins` and `tap_tree`. The `tap_tree`
+    /// is left empty for trees with hidden branches, which it cannot hold.
+    ///
+    /// If the descriptor contains wildcards or otherwise cannot be transformed
+    /// into a concrete descriptor an error will be returned. This method
+    /// doesn't check that the output of the transaction has the script pubkey
+    /// of the descriptor. To do that see [`update_output_with_descriptor`].
+    ///
+    /// ## Return value
+    ///
+    /// For convenience, this returns the concrete descriptor that is computed
+    /// internally to fill out the PSBT output fields.
+    ///
+    /// [`update_output_with_descriptor`]: PsbtExt::update_output_with_descriptor
+    fn update_with_descriptor_unchecked(
+        &mut self,
+        descriptor: &Descriptor<DescriptorPublicKey>,
+    ) -> Result<Descriptor<bitcoin::PublicKey>, descriptor::ConversionError>;
+}
+
+impl PsbtOutputExt for psbt::Output {
+    fn update_with_descriptor_unchecked(
+        &mut self,
+        descriptor: &Descriptor<DescriptorPublicKey>,
+    ) -> Result<Descriptor<bitcoin::PublicKey>, descriptor::ConversionError> {
+        let (derived, _) = update_item_with_descriptor_helper(self, descriptor, None)?;
+        Ok(derived)
+    }
+}
+
+// The fields of psbt inputs and outputs populated from descriptors. The fields
+// which only inputs or only outputs have are `None` for the other.
+trait PsbtFields {
+    fn redeem_script(&mut self) -> &mut Option<Script>;
+    fn witness_script(&mut self) -> &mut Option<Script>;
+    fn bip32_derivation(&mut self) -> &mut BTreeMap<secp256k1::PublicKey, bip32::KeySource>;
+    fn tap_internal_key(&mut self) -> &mut Option<bitcoin::XOnlyPublicKey>;
+    fn tap_key_origins(
+        &mut self,
+    ) -> &mut BTreeMap<bitcoin::XOnlyPublicKey, (Vec<TapLeafHash>, bip32::KeySource)>;
+
+    // Only in inputs
+    fn tap_merkle_root(&mut self) -> Option<&mut Option<TapBranchHash>> {
+        None
+    }
+    fn tap_scripts(&mut self) -> Option<&mut BTreeMap<ControlBlock, (Script, LeafVersion)>> {
+        None
+    }
+
+    // Only in outputs
+    fn tap_tree(&mut self) -> Option<&mut Option<psbt::TapTree>> {
+        None
+    }
+}
+
+impl PsbtFields for psbt::Input {
+    fn redeem_script(&mut self) -> &mut Option<Script> {
+        &mut self.redeem_script
+    }
+    fn witness_script(&mut self) -> &mut Option<Script> {
+        &mut self.witness_script
+    }
+    fn bip32_derivation(&mut self) -> &mut BTreeMap<secp256k1::PublicKey, bip32::KeySource> {
+        &mut self.bip32_derivation
+    }
+    fn tap_internal_key(&mut self) -> &mut Option<bitcoin::XOnlyPublicKey> {
+        &mut self.tap_internal_key
+    }
+    fn tap_key_origins(
+        &mut self,
+    ) -> &mut BTreeMap<bitcoin::XOnlyPublicKey, (Vec<TapLeafHash>, bip32::KeySource)> {
+        &mut self.tap_key_origins
+    }
+    fn tap_merkle_root(&mut self) -> Option<&mut Option<TapBranchHash>> {
+        Some(&mut self.tap_merkle_root)
+    }
+    fn tap_scripts(&mut self) -> Option<&mut BTreeMap<ControlBlock, (Script, LeafVersion)>> {
+        Some(&mut self.tap_scripts)
+    }
+}
+
+impl PsbtFields for psbt::Output {
+    fn redeem_script(&mut self) -> &mut Option<Script> {
+        &mut self.redeem_script
+    }
+    fn witness_script(&mut self) -> &mut Option<Script> {
+        &mut self.witness_script
+    }
+    fn bip32_derivation(&mut self) -> &mut BTreeMap<secp256k1::PublicKey, bip32::KeySource> {
+        &mut self.bip32_derivation
+    }
+    fn tap_internal_key(&mut self) -> &mut Option<bitcoin::XOnlyPublicKey> {
+        &mut self.tap_internal_key
+    }
+    fn tap_key_origins(
+        &mut self,
+    ) -> &mut BTreeMap<bitcoin::XOnlyPublicKey, (Vec<TapLeafHash>, bip32::KeySource)> {
+        &mut self.tap_key_origins
+    }
+    fn tap_tree(&mut self) -> Option<&mut Option<psbt::TapTree>> {
+        Some(&mut self.tap_tree)
+    }
+}
+
+fn update_item_with_descriptor_helper<F: PsbtFields>(
+    item: &mut F,
+    descriptor: &Descriptor<DescriptorPublicKey>,
+    check_script: Option<Script>,
+    // the return value is a tuple here since the two internal calls to it require different info.
+    // One needs the derived descriptor and the other needs to know whether the script_pubkey check
+    // failed.
+) -> Result<(Descriptor<bitcoin::PublicKey>, bool), descriptor::ConversionError> {
+    let secp = secp256k1::Secp256k1::verification_only();
+
+    let derived = if let Descriptor::Tr(_) | Descriptor::RawTr(_) = &descriptor {
+        let mut hash_lookup = XOnlyHashLookUp(BTreeMap::new(), secp);
+        let derived = descriptor.translate_pk(&mut hash_lookup)?;
+        let hash_lookup = hash_lookup.0;
+
+        if let Some(check_script) = check_script {
+            if check_script != derived.script_pubkey() {
+                return Ok((derived, false));
+            }
+        }
+
+        // NOTE: they will both always be the same variant
+        if let (Descriptor::RawTr(rawtr_derived), Descriptor::RawTr(rawtr_xpk)) =
+            (&derived, descriptor)
+        {
+            // There is no internal key or merkle root to record, only the output key itself
+            let output_key = rawtr_derived.as_inner().to_x_only_pubkey();
+            let pk_xpk = rawtr_xpk.as_inner();
+            item.tap_key_origins().insert(
+                output_key,
+                (
+                    vec![],
+                    (pk_xpk.master_fingerprint(), pk_xpk.full_derivation_path()),
+                ),
+            );
+        }
+        if let (Descriptor::Tr(tr_derived), Descriptor::Tr(tr_xpk)) = (&derived, descriptor) {
+            let spend_info = tr_derived.spend_info();
+            let ik_derived = spend_info.internal_key();
+            let ik_xpk = tr_xpk.internal_key();
+            *item.tap_internal_key() = Some(ik_derived);
+            if let Some(tap_merkle_root) = item.tap_merkle_root() {
+                *tap_merkle_root = spend_info.merkle_root();
+            }
+            if let Some(tap_tree) = item.tap_tree() {
+                *tap_tree = tr_derived
+                    .taptree()
+                    .as_ref()
+                    .and_then(|tree| tree.to_psbt_tap_tree().ok());
+            }
+            item.tap_key_origins().insert(
+                ik_derived,
+                (
+                    vec![],
+                    (ik_xpk.master_fingerprint(), ik_xpk.full_derivation_path()),
+                ),
+            );
+
+            for ((_depth_der, ver, ms_derived), (_depth, _, ms)) in
+                tr_derived.iter_leaves().zip(tr_xpk.iter_leaves())
+            {
+                debug_assert_eq!(_depth_der, _depth);
+                let leaf_script = (ms_derived.encode(), ver);
+                let tapleaf_hash = TapLeafHash::from_script(&leaf_script.0, leaf_script.1);
+                let control_block = spend_info
+                    .control_block(&leaf_script)
+                    .expect("Control block must exist in script map for every known leaf");
+                if let Some(tap_scripts) = item.tap_scripts() {
+                    tap_scripts.insert(control_block, leaf_script);
+                }
+
+                for (pk_pkh_derived, pk_pkh_xpk) in ms_derived.iter_pk_pkh().zip(ms.iter_pk_pkh()) {
+                    let (xonly, xpk) = match (pk_pkh_derived, pk_pkh_xpk) {
+                        (PkPkh::PlainPubkey(pk), PkPkh::PlainPubkey(xpk)) => {
+                            (pk.to_x_only_pubkey(), xpk)
+                        }
+                        (PkPkh::HashedPubkey(hash), PkPkh::HashedPubkey(xpk)) => (
+                            *hash_lookup
+                                .get(&hash)
+                                .expect("translate_pk inserted an entry for every hash"),
+                            xpk,
+                        ),
+                        _ => unreachable!("the iterators work in the same order"),
+                    };
+
+                    item.tap_key_origins()
+                        .entry(xonly)
+                        .and_modify(|(tapleaf_hashes, _)| {
+                            if tapleaf_hashes.last() != Some(&tapleaf_hash) {
+                                tapleaf_hashes.push(tapleaf_hash);
+                            }
+                        })
+                        .or_insert_with(|| {
+                            (
+                                vec![tapleaf_hash],
+                                (xpk.master_fingerprint(), xpk.full_derivation_path()),
+                            )
+                        });
                 }
             }
         }
 
         derived
     } else {
-        // have to use a RefCell because we can't pass FnMut to translate_pk2
-        let bip32_derivation = RefCell::new(BTreeMap::new());
-        let derived = descriptor.translate_pk2(|xpk| {
-            let derived = xpk.derive_public_key(&secp)?;
-            bip32_derivation.borrow_mut().insert(
-                derived.to_public_key().inner,
-                (xpk.master_fingerprint(), xpk.full_derivation_path()),
-            );
-            Ok(derived)
-        })?;
+        let mut bip32_derivation = KeySourceLookUp(BTreeMap::new(), secp);
+        let derived = descriptor.translate_pk(&mut bip32_derivation)?;
 
         if let Some(check_script) = check_script {
             if check_script != derived.script_pubkey() {
@@ -1038,22 +2512,29 @@ fn update_input_with_descriptor_helper(
             }
         }
 
-        input.bip32_derivation = bip32_derivation.into_inner();
+        *item.bip32_derivation() = bip32_derivation.0;
 
         match &derived {
-            Descriptor::Bare(_) | Descriptor::Pkh(_) | Descriptor::Wpkh(_) => {}
+            Descriptor::Bare(_)
+            | Descriptor::Pkh(_)
+            | Descriptor::Wpkh(_)
+            | Descriptor::Raw(_)
+            | Descriptor::Addr(_)
+            | Descriptor::Wv(_) => {}
             Descriptor::Sh(sh) => match sh.as_inner() {
                 descriptor::ShInner::Wsh(wsh) => {
-                    input.witness_script = Some(wsh.inner_script());
-                    input.redeem_script = Some(wsh.inner_script().to_v0_p2wsh());
+                    *item.witness_script() = Some(wsh.inner_script());
+                    *item.redeem_script() = Some(wsh.inner_script().to_v0_p2wsh());
                 }
-                descriptor::ShInner::Wpkh(..) => input.redeem_script = Some(sh.inner_script()),
+                descriptor::ShInner::Wpkh(..) => *item.redeem_script() = Some(sh.inner_script()),
                 descriptor::ShInner::SortedMulti(_) | descriptor::ShInner::Ms(_) => {
-                    input.redeem_script = Some(sh.inner_script())
+                    *item.redeem_script() = Some(sh.inner_script())
                 }
             },
-            Descriptor::Wsh(wsh) => input.witness_script = Some(wsh.inner_script()),
-            Descriptor::Tr(_) => unreachable!("Tr is dealt with separately"),
+            Descriptor::Wsh(wsh) => *item.witness_script() = Some(wsh.inner_script()),
+            Descriptor::Tr(_) | Descriptor::RawTr(_) => {
+                unreachable!("Tr is dealt with separately")
+            }
         }
 
         derived
@@ -1062,6 +2543,56 @@ fn update_input_with_descriptor_helper(
     Ok((derived, true))
 }
 
+// The script pubkey of the utxo spent by `input`, checking that the utxo
+// fields are consistent with `txin` and with the kind of descriptor spending it
+fn utxo_script_pubkey(
+    input: &psbt::Input,
+    txin: &bitcoin::TxIn,
+    desc_type: descriptor::DescriptorType,
+) -> Result<Script, UtxoUpdateError> {
+    if let Some(non_witness_utxo) = &input.non_witness_utxo {
+        if txin.previous_output.txid != non_witness_utxo.txid() {
+            return Err(UtxoUpdateError::UtxoCheck);
+        }
+    }
+
+    let spk = match (&input.witness_utxo, &input.non_witness_utxo) {
+        (Some(witness_utxo), None) => {
+            if desc_type.segwit_version().is_some() {
+                witness_utxo.script_pubkey.clone()
+            } else {
+                return Err(UtxoUpdateError::UtxoCheck);
+            }
+        }
+        (None, Some(non_witness_utxo)) => {
+            if desc_type.segwit_version().is_some() {
+                return Err(UtxoUpdateError::UtxoCheck);
+            }
+
+            non_witness_utxo
+                .output
+                .get(txin.previous_output.vout as usize)
+                .ok_or(UtxoUpdateError::UtxoCheck)?
+                .script_pubkey
+                .clone()
+        }
+        (Some(witness_utxo), Some(non_witness_utxo)) => {
+            if witness_utxo
+                != non_witness_utxo
+                    .output
+                    .get(txin.previous_output.vout as usize)
+                    .ok_or(UtxoUpdateError::UtxoCheck)?
+            {
+                return Err(UtxoUpdateError::UtxoCheck);
+            }
+
+            witness_utxo.script_pubkey.clone()
+        }
+        (None, None) => return Err(UtxoUpdateError::UtxoCheck),
+    };
+    Ok(spk)
+}
+
 // Get a script from witness script pubkey hash
 fn script_code_wpkh(script: &Script) -> Script {
     assert!(script.is_v0_p2wpkh());
@@ -1110,7 +2641,7 @@ impl fmt::Display for UtxoUpdateError {
 
 #[cfg(feature = "std")]
 impl error::Error for UtxoUpdateError {
-    fn cause(&self) -> Option<&dyn error::Error> {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
         use self::UtxoUpdateError::*;
 
         match self {
@@ -1120,102 +2651,501 @@ impl error::Error for UtxoUpdateError {
     }
 }
 
-/// Return error type for [`PsbtExt::sighash_msg`]
+/// Return error type for [`PsbtExt::update_output_with_descriptor`]
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
-pub enum SighashError {
+pub enum OutputUpdateError {
     /// Index out of bounds
     IndexOutOfBounds(usize, usize),
-    /// Missing input utxo
-    MissingInputUtxo,
-    /// Missing Prevouts
-    MissingSpendUtxos,
-    /// Invalid Sighash type
-    InvalidSighashType,
-    /// Sighash computation error
-    /// Only happens when single does not have corresponding output as psbts
-    /// already have information to compute the sighash
-    SighashComputationError(bitcoin::util::sighash::Error),
-    /// Missing Witness script
-    MissingWitnessScript,
-    /// Missing Redeem script,
-    MissingRedeemScript,
+    /// The PSBT transaction didn't have an output at that index
+    MissingTxOut,
+    /// Derivation error
+    DerivationError(descriptor::ConversionError),
+    /// The output of the PSBT transaction had a script pubkey that did not
+    /// match the descriptor
+    MismatchedScriptPubkey,
 }
 
-impl fmt::Display for SighashError {
+impl fmt::Display for OutputUpdateError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            SighashError::IndexOutOfBounds(ind, len) => {
-                write!(f, "index {}, psbt input len: {}", ind, len)
+            OutputUpdateError::IndexOutOfBounds(ind, len) => {
+                write!(f, "index {}, psbt output len: {}", ind, len)
             }
-            SighashError::MissingInputUtxo => write!(f, "Missing input utxo in pbst"),
-            SighashError::MissingSpendUtxos => write!(f, "Missing Psbt spend utxos"),
-            SighashError::InvalidSighashType => write!(f, "Invalid Sighash type"),
-            SighashError::SighashComputationError(e) => {
-                write!(f, "Sighash computation error : {}", e)
+            OutputUpdateError::MissingTxOut => write!(f, "Missing transaction output in psbt"),
+            OutputUpdateError::DerivationError(e) => write!(f, "Key derivation error {}", e),
+            OutputUpdateError::MismatchedScriptPubkey => {
+                write!(f, "The output's script pubkey didn't match the descriptor")
             }
-            SighashError::MissingWitnessScript => write!(f, "Missing Witness Script"),
-            SighashError::MissingRedeemScript => write!(f, "Missing Redeem Script"),
         }
     }
 }
 
 #[cfg(feature = "std")]
-impl error::Error for SighashError {
-    fn cause(&self) -> Option<&dyn error::Error> {
-        use self::SighashError::*;
+impl error::Error for OutputUpdateError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        use self::OutputUpdateError::*;
 
         match self {
-            IndexOutOfBounds(_, _)
-            | MissingInputUtxo
-            | MissingSpendUtxos
-            | InvalidSighashType
-            | MissingWitnessScript
-            | MissingRedeemScript => None,
-            SighashComputationError(e) => Some(e),
+            IndexOutOfBounds(_, _) | MissingTxOut | MismatchedScriptPubkey => None,
+            DerivationError(e) => Some(e),
         }
     }
 }
 
-impl From<bitcoin::util::sighash::Error> for SighashError {
-    fn from(e: bitcoin::util::sighash::Error) -> Self {
-        SighashError::SighashComputationError(e)
+/// Return error type for [`PsbtExt::fee`] and [`PsbtExt::estimated_weight`]
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+pub enum FeeError {
+    /// The psbt input at this index has neither a `witness_utxo` nor a `non_witness_utxo`
+    MissingInputUtxo(usize),
+    /// The sum of the input or output values overflowed
+    ValueOverflow,
+    /// The outputs spend more than the inputs provide
+    NegativeFee,
+    /// The number of descriptors does not match the number of inputs
+    DescriptorCountMismatch(usize, usize),
+    /// Derivation error
+    DerivationError(descriptor::ConversionError),
+    /// The descriptor for the input at this index can not be satisfied
+    Unsatisfiable(usize),
+}
+
+impl fmt::Display for FeeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FeeError::MissingInputUtxo(ind) => write!(f, "Missing utxo for psbt input {}", ind),
+            FeeError::ValueOverflow => write!(f, "Sum of psbt amounts overflowed"),
+            FeeError::NegativeFee => write!(f, "Psbt outputs exceed the spent utxo values"),
+            FeeError::DescriptorCountMismatch(n_desc, n_inputs) => write!(
+                f,
+                "{} descriptors given for a psbt with {} inputs",
+                n_desc, n_inputs
+            ),
+            FeeError::DerivationError(e) => write!(f, "Key derivation error {}", e),
+            FeeError::Unsatisfiable(ind) => {
+                write!(f, "Descriptor for psbt input {} is unsatisfiable", ind)
+            }
+        }
     }
 }
 
-/// Sighash message(signing data) for a given psbt transaction input.
+#[cfg(feature = "std")]
+impl error::Error for FeeError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        use self::FeeError::*;
+
+        match self {
+            MissingInputUtxo(_)
+            | ValueOverflow
+            | NegativeFee
+            | DescriptorCountMismatch(_, _)
+            | Unsatisfiable(_) => None,
+            DerivationError(e) => Some(e),
+        }
+    }
+}
+
+/// Return error type for [`PsbtExt::set_relative_timelock`] and
+/// [`PsbtExt::enable_rbf`]
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
-pub enum PsbtSighashMsg {
-    /// Taproot Signature hash
-    TapSighash(taproot::TapSighashHash),
-    /// Ecdsa Sighash message (includes sighash for legacy/p2sh/segwitv0 outputs)
-    EcdsaSighash(bitcoin::Sighash),
+pub enum SequenceError {
+    /// Index out of bounds
+    IndexOutOfBounds(usize, usize),
+    /// The new sequence does not allow the spend path with these timelocks,
+    /// which the current sequence allows
+    DisallowedPath(PathTimelocks),
 }
 
-impl PsbtSighashMsg {
-    /// Convert the message to a [`secp256k1::Message`].
-    pub fn to_secp_msg(&self) -> secp256k1::Message {
-        match *self {
-            PsbtSighashMsg::TapSighash(msg) => {
-                secp256k1::Message::from_slice(msg.as_ref()).expect("Sighashes are 32 bytes")
-            }
-            PsbtSighashMsg::EcdsaSighash(msg) => {
-                secp256k1::Message::from_slice(msg.as_ref()).expect("Sighashes are 32 bytes")
+impl fmt::Display for SequenceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SequenceError::IndexOutOfBounds(ind, len) => {
+                write!(f, "index {}, psbt input len: {}", ind, len)
             }
+            SequenceError::DisallowedPath(timelocks) => write!(
+                f,
+                "The new sequence makes the spend path with timelocks {:?} unsatisfiable",
+                timelocks
+            ),
         }
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use std::str::FromStr;
+#[cfg(feature = "std")]
+impl error::Error for SequenceError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        use self::SequenceError::*;
 
-    use bitcoin::consensus::encode::deserialize;
-    use bitcoin::hashes::hex::FromHex;
-    use bitcoin::secp256k1::PublicKey;
+        match self {
+            IndexOutOfBounds(_, _) | DisallowedPath(_) => None,
+        }
+    }
+}
+
+/// Return error type for [`PsbtExt::combine_with`]
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
+pub enum CombineError {
+    /// The psbts are not for the same unsigned transaction
+    UnequalTransactions,
+    /// The psbt inputs at this index have different redeem scripts
+    ConflictingRedeemScript(usize),
+    /// The psbt inputs at this index have different witness scripts
+    ConflictingWitnessScript(usize),
+    /// The psbt inputs at this index have different taproot internal keys or
+    /// merkle roots
+    ConflictingTapTree(usize),
+    /// The psbt inputs at this index have different leaf scripts for the same
+    /// control block
+    ConflictingTapScript(usize),
+    /// The psbt inputs at this index have different origins for the same key
+    ConflictingKeyOrigin(usize),
+    /// The psbt outputs at this index describe different scripts or key origins
+    ConflictingOutput(usize),
+    /// Error while combining the global fields
+    Psbt(psbt::Error),
+}
+
+impl fmt::Display for CombineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CombineError::UnequalTransactions => {
+                f.write_str("The psbts do not have the same unsigned transaction")
+            }
+            CombineError::ConflictingRedeemScript(ind) => {
+                write!(f, "Conflicting redeem scripts for input {}", ind)
+            }
+            CombineError::ConflictingWitnessScript(ind) => {
+                write!(f, "Conflicting witness scripts for input {}", ind)
+            }
+            CombineError::ConflictingTapTree(ind) => {
+                write!(
+                    f,
+                    "Conflicting taproot internal key or merkle root for input {}",
+                    ind
+                )
+            }
+            CombineError::ConflictingTapScript(ind) => {
+                write!(f, "Conflicting taproot leaf scripts for input {}", ind)
+            }
+            CombineError::ConflictingKeyOrigin(ind) => {
+                write!(f, "Conflicting key origins for input {}", ind)
+            }
+            CombineError::ConflictingOutput(ind) => {
+                write!(f, "Conflicting scripts or key origins for output {}", ind)
+            }
+            CombineError::Psbt(e) => write!(f, "Psbt combine error: {}", e),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl error::Error for CombineError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        use self::CombineError::*;
+
+        match self {
+            UnequalTransactions
+            | ConflictingRedeemScript(_)
+            | ConflictingWitnessScript(_)
+            | ConflictingTapTree(_)
+            | ConflictingTapScript(_)
+            | ConflictingKeyOrigin(_)
+            | ConflictingOutput(_) => None,
+            Psbt(e) => Some(e),
+        }
+    }
+}
+
+/// Return error type for [`PsbtExt::sighash_msg`]
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+pub enum SighashError {
+    /// Index out of bounds
+    IndexOutOfBounds(usize, usize),
+    /// Missing input utxo
+    MissingInputUtxo,
+    /// Missing Prevouts
+    MissingSpendUtxos,
+    /// Invalid Sighash type
+    InvalidSighashType,
+    /// Sighash computation error
+    /// Only happens when single does not have corresponding output as psbts
+    /// already have information to compute the sighash
+    SighashComputationError(bitcoin::util::sighash::Error),
+    /// Missing Witness script
+    MissingWitnessScript,
+    /// Missing Redeem script,
+    MissingRedeemScript,
+}
+
+impl fmt::Display for SighashError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SighashError::IndexOutOfBounds(ind, len) => {
+                write!(f, "index {}, psbt input len: {}", ind, len)
+            }
+            SighashError::MissingInputUtxo => write!(f, "Missing input utxo in pbst"),
+            SighashError::MissingSpendUtxos => write!(f, "Missing Psbt spend utxos"),
+            SighashError::InvalidSighashType => write!(f, "Invalid Sighash type"),
+            SighashError::SighashComputationError(e) => {
+                write!(f, "Sighash computation error : {}", e)
+            }
+            SighashError::MissingWitnessScript => write!(f, "Missing Witness Script"),
+            SighashError::MissingRedeemScript => write!(f, "Missing Redeem Script"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl error::Error for SighashError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        use self::SighashError::*;
+
+        match self {
+            IndexOutOfBounds(_, _)
+            | MissingInputUtxo
+            | MissingSpendUtxos
+            | InvalidSighashType
+            | MissingWitnessScript
+            | MissingRedeemScript => None,
+            SighashComputationError(e) => Some(e),
+        }
+    }
+}
+
+impl From<bitcoin::util::sighash::Error> for SighashError {
+    fn from(e: bitcoin::util::sighash::Error) -> Self {
+        SighashError::SighashComputationError(e)
+    }
+}
+
+/// The sighash type the signatures of an input commit to, as reported by
+/// [`PsbtExt::derivation_summary`]
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum InputSighashType {
+    /// The sighash type of the ECDSA signatures of a pre-taproot input
+    Ecdsa(EcdsaSighashType),
+    /// The sighash type of the schnorr signatures of a taproot input
+    Schnorr(SchnorrSighashType),
+}
+
+impl fmt::Display for InputSighashType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InputSighashType::Ecdsa(hash_ty) => fmt::Display::fmt(hash_ty, f),
+            InputSighashType::Schnorr(hash_ty) => fmt::Display::fmt(hash_ty, f),
+        }
+    }
+}
+
+/// The keys an input is to be signed with and the sighash type requested, as
+/// returned by [`PsbtExt::derivation_summary`]
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct InputDerivations {
+    /// The master key fingerprint and derivation path of each key of the input,
+    /// from its `bip32_derivation` and then its `tap_key_origins`
+    pub keys: Vec<(
+        bip32::Fingerprint,
+        bip32::DerivationPath,
+        descriptor::SinglePubKey,
+    )>,
+    /// The sighash type of the requested signatures
+    pub sighash_type: InputSighashType,
+}
+
+/// Displays the sighash type on a first line, followed by one line per key in
+/// the key origin format of descriptors, i.e. `[fingerprint/path]key`
+impl fmt::Display for InputDerivations {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.sighash_type)?;
+        for (fingerprint, path, key) in &self.keys {
+            write!(f, "\n[{}", fingerprint)?;
+            for child in path {
+                write!(f, "/{}", child)?;
+            }
+            match key {
+                descriptor::SinglePubKey::FullKey(pk) => write!(f, "]{}", pk)?,
+                descriptor::SinglePubKey::XOnly(pk) => write!(f, "]{}", pk)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for InputDerivations {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// The digest of a hash fragment, whose preimage is reported missing by
+/// [`PsbtExt::required_hash_preimages`]
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+pub enum HashDigest {
+    /// A `sha256` digest, whose preimage goes in `sha256_preimages`
+    Sha256(sha256::Hash),
+    /// A `hash256` digest, whose preimage goes in `hash256_preimages`
+    Hash256(sha256d::Hash),
+    /// A `ripemd160` digest, whose preimage goes in `ripemd160_preimages`
+    Ripemd160(ripemd160::Hash),
+    /// A `hash160` digest, whose preimage goes in `hash160_preimages`
+    Hash160(hash160::Hash),
+}
+
+impl fmt::Display for HashDigest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HashDigest::Sha256(h) => write!(f, "sha256({})", h),
+            HashDigest::Hash256(h) => write!(f, "hash256({})", h),
+            HashDigest::Ripemd160(h) => write!(f, "ripemd160({})", h),
+            HashDigest::Hash160(h) => write!(f, "hash160({})", h),
+        }
+    }
+}
+
+/// The key of a signature checked by [`PsbtExt::verify_signatures`]
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+pub enum SignatureKey {
+    /// An ecdsa signature from `partial_sigs`
+    Ecdsa(bitcoin::PublicKey),
+    /// The taproot key spend signature, made with the output key
+    TapKey,
+    /// A taproot script spend signature from `tap_script_sigs`
+    TapScript(bitcoin::XOnlyPublicKey, TapLeafHash),
+}
+
+/// Return error type for a single signature checked by
+/// [`PsbtExt::verify_signatures`]
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+pub enum SignatureError {
+    /// Could not compute the sighash of the input
+    Sighash(SighashError),
+    /// The spent output of a taproot key spend is not a taproot output
+    InvalidOutputKey,
+    /// The signature is not valid for the key and sighash
+    InvalidSignature,
+}
+
+impl fmt::Display for SignatureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SignatureError::Sighash(e) => write!(f, "Sighash error: {}", e),
+            SignatureError::InvalidOutputKey => write!(f, "Spent output is not a taproot output"),
+            SignatureError::InvalidSignature => write!(f, "Invalid signature"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl error::Error for SignatureError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        use self::SignatureError::*;
+
+        match self {
+            Sighash(e) => Some(e),
+            InvalidOutputKey | InvalidSignature => None,
+        }
+    }
+}
+
+/// Sighash message(signing data) for a given psbt transaction input.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+pub enum PsbtSighashMsg {
+    /// Taproot Signature hash
+    TapSighash(taproot::TapSighashHash),
+    /// Ecdsa Sighash message (includes sighash for legacy/p2sh/segwitv0 outputs)
+    EcdsaSighash(bitcoin::Sighash),
+}
+
+/// A [`SighashCache`] for computing the sighash messages of a psbt with
+/// [`PsbtExt::sighash_msg_cached`].
+///
+/// Besides the midstate of the transaction kept by [`SighashCache`], it keeps
+/// the outputs spent by the psbt and every message it computed, keyed by
+/// input index, leaf hash and sighash type. It must therefore only be used
+/// with psbts having the same transaction, utxos and scripts: a cache made
+/// before any of them changes returns stale messages.
+#[derive(Debug)]
+pub struct PsbtSighashCache<T: Deref<Target = bitcoin::Transaction>> {
+    cache: SighashCache<T>,
+    prevouts: Option<Vec<bitcoin::TxOut>>,
+    msgs: BTreeMap<(usize, Option<TapLeafHash>, Option<psbt::PsbtSighashType>), PsbtSighashMsg>,
+}
+
+impl<T: Deref<Target = bitcoin::Transaction>> PsbtSighashCache<T> {
+    /// Create an empty cache for the unsigned transaction `tx` of a psbt
+    pub fn new(tx: T) -> Self {
+        PsbtSighashCache {
+            cache: SighashCache::new(tx),
+            prevouts: None,
+            msgs: BTreeMap::new(),
+        }
+    }
+
+    /// The underlying [`SighashCache`]
+    pub fn sighash_cache(&mut self) -> &mut SighashCache<T> {
+        &mut self.cache
+    }
+
+    // The sighash message of input `idx`, computed on the first request for
+    // the same leaf and sighash type
+    fn sighash_msg(
+        &mut self,
+        psbt: &Psbt,
+        idx: usize,
+        tapleaf_hash: Option<TapLeafHash>,
+        sighash_type: Option<psbt::PsbtSighashType>,
+    ) -> Result<PsbtSighashMsg, SighashError> {
+        let key = (idx, tapleaf_hash, sighash_type);
+        if let Some(msg) = self.msgs.get(&key) {
+            return Ok(*msg);
+        }
+        if self.prevouts.is_none() {
+            let prevouts =
+                finalizer::prevouts(psbt).map_err(|_e| SighashError::MissingSpendUtxos)?;
+            self.prevouts = Some(prevouts.into_iter().cloned().collect());
+        }
+        let prevouts = self.prevouts.as_ref().expect("Prevouts were just set");
+        let prevouts = bitcoin::util::sighash::Prevouts::All(&prevouts[..]);
+        let msg = sighash_msg_helper(
+            psbt,
+            idx,
+            &mut self.cache,
+            &prevouts,
+            tapleaf_hash,
+            sighash_type,
+        )?;
+        self.msgs.insert(key, msg);
+        Ok(msg)
+    }
+}
+
+impl PsbtSighashMsg {
+    /// Convert the message to a [`secp256k1::Message`].
+    pub fn to_secp_msg(&self) -> secp256k1::Message {
+        match *self {
+            PsbtSighashMsg::TapSighash(msg) => {
+                secp256k1::Message::from_slice(msg.as_ref()).expect("Sighashes are 32 bytes")
+            }
+            PsbtSighashMsg::EcdsaSighash(msg) => {
+                secp256k1::Message::from_slice(msg.as_ref()).expect("Sighashes are 32 bytes")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use bitcoin::consensus::encode::deserialize;
+    use bitcoin::hashes::hex::FromHex;
+    use bitcoin::hashes::{sha256, Hash};
+    use bitcoin::secp256k1::PublicKey;
     use bitcoin::util::bip32::{DerivationPath, ExtendedPubKey};
-    use bitcoin::{OutPoint, TxIn, TxOut, XOnlyPublicKey};
+    use bitcoin::{OutPoint, TxIn, TxOut, Witness, XOnlyPublicKey};
 
     use super::*;
+    use crate::descriptor::SinglePubKey;
     use crate::Miniscript;
 
     #[test]
@@ -1334,6 +3264,89 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_update_output_tr_tap_tree() {
+        use crate::descriptor::TapTree;
+
+        let xpub = "[73c5da0a/86'/0'/0']xpub6BgBgsespWvERF3LHQu6CnqdvfEvtMcQjYrcRzx53QJjSxarj2afYWcLteoGVky7D3UKDP9QyrLprQ3VCECoY49yfdDEHGCtMMj92pReUsQ";
+        let desc = format!(
+            "tr({}/0/0,{{pk({}/0/1),{{multi_a(2,{}/0/1,{}/1/0),leaf_ver(c2,pk({}/1/1))}}}})",
+            xpub, xpub, xpub, xpub, xpub
+        );
+        let desc = Descriptor::<DescriptorPublicKey>::from_str(&desc).unwrap();
+        let secp = Secp256k1::verification_only();
+        let derived = desc.derived_descriptor(&secp, 0).unwrap();
+
+        let tx = bitcoin::Transaction {
+            version: 1,
+            lock_time: 0,
+            input: vec![],
+            output: vec![TxOut {
+                value: 1_000,
+                script_pubkey: derived.script_pubkey(),
+            }],
+        };
+        let mut psbt = Psbt::from_unsigned_tx(tx).unwrap();
+        assert_eq!(
+            psbt.update_output_with_descriptor(1, &desc),
+            Err(OutputUpdateError::IndexOutOfBounds(1, 1))
+        );
+        let other = Descriptor::from_str(&format!("tr({}/0/0)", xpub)).unwrap();
+        assert_eq!(
+            psbt.update_output_with_descriptor(0, &other),
+            Err(OutputUpdateError::MismatchedScriptPubkey)
+        );
+        psbt.update_output_with_descriptor(0, &desc).unwrap();
+
+        let output = &psbt.outputs[0];
+        let internal_key = output.tap_internal_key.unwrap();
+        assert_eq!(output.tap_key_origins.len(), 4);
+        let tap_tree = output.tap_tree.as_ref().unwrap();
+        let mut leaves = tap_tree
+            .script_leaves()
+            .map(|leaf| (leaf.depth(), leaf.leaf_version()))
+            .collect::<Vec<_>>();
+        leaves.sort();
+        assert_eq!(
+            leaves,
+            vec![
+                (1, LeafVersion::TapScript),
+                (2, LeafVersion::TapScript),
+                (2, LeafVersion::from_consensus(0xc2).unwrap()),
+            ]
+        );
+
+        // The field survives serialization and gives back the same tree
+        let psbt: Psbt = deserialize(&bitcoin::consensus::encode::serialize(&psbt)).unwrap();
+        let tap_tree = psbt.outputs[0].tap_tree.as_ref().unwrap();
+        let tree = TapTree::from_psbt_tap_tree(tap_tree).unwrap();
+        assert_eq!(&tree.to_psbt_tap_tree().unwrap(), tap_tree);
+        let tr = descriptor::Tr::new(internal_key, Some(tree)).unwrap();
+        assert_eq!(tr.script_pubkey(), derived.script_pubkey());
+
+        // Hidden branches cannot be written
+        let hidden = TapTree::<XOnlyPublicKey>::Hidden(TapBranchHash::hash(b"hidden branch"));
+        let tree = TapTree::combine(
+            hidden,
+            TapTree::Leaf(crate::sync::Arc::new(
+                Miniscript::from_ast(Terminal::True).unwrap(),
+            )),
+        )
+        .unwrap();
+        tree.to_psbt_tap_tree().unwrap_err();
+        let mut output = psbt::Output::default();
+        let desc = Descriptor::from_str(&format!(
+            "tr({}/0/0,{{raw_node({}),pk({}/1/1)}})",
+            xpub,
+            sha256::Hash::hash(b"hidden branch"),
+            xpub
+        ))
+        .unwrap();
+        output.update_with_descriptor_unchecked(&desc).unwrap();
+        assert!(output.tap_internal_key.is_some());
+        assert_eq!(output.tap_tree, None);
+    }
+
     #[test]
     fn test_update_input_non_tr_multi() {
         // values taken from https://github.com/bitcoin/bips/blob/master/bip-0084.mediawiki (after removing zpub thingy)
@@ -1397,37 +3410,390 @@ mod tests {
     }
 
     #[test]
-    fn test_update_input_checks() {
-        let desc = format!("tr([73c5da0a/86'/0'/0']xpub6BgBgsespWvERF3LHQu6CnqdvfEvtMcQjYrcRzx53QJjSxarj2afYWcLteoGVky7D3UKDP9QyrLprQ3VCECoY49yfdDEHGCtMMj92pReUsQ/0/0)");
-        let desc = Descriptor::<DescriptorPublicKey>::from_str(&desc).unwrap();
-
-        let mut non_witness_utxo = bitcoin::Transaction {
+    fn test_derivation_summary() {
+        let xpub = "[73c5da0a/84'/0'/0']xpub6CatWdiZiodmUeTDp8LT5or8nmbKNcuyvz7WyksVFkKB4RHwCD3XyuvPEbvqAQY3rAPshWcMLoP2fMFMKHPJ4ZeZXYVUhLv1VMrjPC7PW6V";
+        let desc = format!("wsh(multi(1,{}/0/0,{}/0/1))", xpub, xpub);
+        let desc = Descriptor::from_str(&desc).unwrap();
+        let secp = Secp256k1::verification_only();
+        let tx = bitcoin::Transaction {
             version: 1,
             lock_time: 0,
-            input: vec![],
-            output: vec![TxOut {
-                value: 1_000,
-                script_pubkey: Script::from_str(
-                    "5120a60869f0dbcf1dc659c9cecbaf8050135ea9e8cdc487053f1dc6880949dc684c",
-                )
-                .unwrap(),
-            }],
+            input: vec![TxIn::default(), TxIn::default()],
+            output: vec![],
         };
+        let mut psbt = Psbt::from_unsigned_tx(tx).unwrap();
+        psbt.inputs[0]
+            .update_with_descriptor_unchecked(&desc)
+            .unwrap();
+        psbt.inputs[0].witness_utxo = Some(TxOut {
+            value: 1_000,
+            script_pubkey: desc.derived_descriptor(&secp, 0).unwrap().script_pubkey(),
+        });
+        psbt.inputs[0].sighash_type = Some(EcdsaSighashType::SinglePlusAnyoneCanPay.into());
+
+        let summary = psbt.derivation_summary();
+        assert_eq!(summary.len(), 2);
+        assert_eq!(summary[1], Err(SighashError::MissingInputUtxo));
+        let summary = summary[0].as_ref().unwrap();
+        assert_eq!(
+            summary.sighash_type,
+            InputSighashType::Ecdsa(EcdsaSighashType::SinglePlusAnyoneCanPay)
+        );
+        assert_eq!(summary.keys.len(), 2);
+        assert_eq!(
+            summary.to_string(),
+            "SIGHASH_SINGLE|SIGHASH_ANYONECANPAY\n\
+             [73c5da0a/84'/0'/0'/0/0]0330d54fd0dd420a6e5f8d3624f5f3482cae350f79d5f0753bf5beef9c2d91af3c\n\
+             [73c5da0a/84'/0'/0'/0/1]03e775fd51f0dfb8cd865d9ff1cca2a158cf651fe997fdc9fee9c1d3b5e995ea77"
+        );
+    }
 
+    #[test]
+    fn test_required_items() {
+        let secp = Secp256k1::new();
+        let sks = [[1; 32], [2; 32], [3; 32]]
+            .iter()
+            .map(|sk| secp256k1::SecretKey::from_slice(sk).unwrap())
+            .collect::<Vec<_>>();
+        let pks = sks
+            .iter()
+            .map(|sk| bitcoin::PublicKey::new(PublicKey::from_secret_key(&secp, sk)))
+            .collect::<Vec<_>>();
+        let preimage = [7; 32];
+        let hash = sha256::Hash::hash(&preimage);
+        // The multisig branch is more expensive than the key and preimage one
+        let desc = format!(
+            "wsh(or_d(multi(2,[00000001/0]{},[00000001/1]{}),and_v(v:pk([00000001/2]{}),sha256({}))))",
+            pks[0], pks[1], pks[2], hash
+        );
+        let desc = Descriptor::<DescriptorPublicKey>::from_str(&desc).unwrap();
         let tx = bitcoin::Transaction {
             version: 1,
             lock_time: 0,
-            input: vec![TxIn {
-                previous_output: OutPoint {
-                    txid: non_witness_utxo.txid(),
-                    vout: 0,
-                },
-                ..Default::default()
-            }],
+            input: vec![TxIn::default()],
             output: vec![],
         };
+        let mut psbt = Psbt::from_unsigned_tx(tx).unwrap();
+        psbt.inputs[0]
+            .update_with_descriptor_unchecked(&desc)
+            .unwrap();
+        psbt.inputs[0].witness_utxo = Some(TxOut {
+            value: 1_000,
+            script_pubkey: desc.derived_descriptor(&secp, 0).unwrap().script_pubkey(),
+        });
+        let msg = psbt
+            .sighash_msg(0, &mut SighashCache::new(&psbt.unsigned_tx), None)
+            .unwrap()
+            .to_secp_msg();
+        let sign = |sk| bitcoin::EcdsaSig::sighash_all(secp.sign_ecdsa(&msg, sk));
 
-        let mut psbt = Psbt::from_unsigned_tx(tx.clone()).unwrap();
+        let expected_sigs = vec![SinglePubKey::FullKey(pks[2])].into_iter().collect();
+        let expected_hashes = vec![HashDigest::Sha256(hash)].into_iter().collect();
+        assert_eq!(psbt.required_signatures(&secp, 0).unwrap(), expected_sigs);
+        assert_eq!(
+            psbt.required_hash_preimages(&secp, 0).unwrap(),
+            expected_hashes
+        );
+        match psbt.required_signatures(&secp, 1) {
+            Err(Error::InputIdxOutofBounds {
+                psbt_inp: 1,
+                index: 1,
+            }) => {}
+            res => panic!("unexpected {:?}", res),
+        }
+
+        // A signature that does not verify is still required
+        psbt.inputs[0].partial_sigs.insert(pks[2], sign(&sks[0]));
+        assert_eq!(psbt.required_signatures(&secp, 0).unwrap(), expected_sigs);
+        psbt.inputs[0].partial_sigs.insert(pks[2], sign(&sks[2]));
+        assert_eq!(psbt.required_signatures(&secp, 0).unwrap(), BTreeSet::new());
+        assert_eq!(
+            psbt.required_hash_preimages(&secp, 0).unwrap(),
+            expected_hashes
+        );
+
+        // Nothing is missing once the multisig branch is signed
+        psbt.inputs[0].partial_sigs.clear();
+        psbt.inputs[0].partial_sigs.insert(pks[0], sign(&sks[0]));
+        psbt.inputs[0].partial_sigs.insert(pks[1], sign(&sks[1]));
+        assert_eq!(psbt.required_signatures(&secp, 0).unwrap(), BTreeSet::new());
+        assert_eq!(
+            psbt.required_hash_preimages(&secp, 0).unwrap(),
+            BTreeSet::new()
+        );
+    }
+
+    #[test]
+    fn test_satisfier_verify_signatures() {
+        let secp = Secp256k1::new();
+        let sks = [[1; 32], [2; 32]]
+            .iter()
+            .map(|sk| secp256k1::SecretKey::from_slice(sk).unwrap())
+            .collect::<Vec<_>>();
+        let pks = sks
+            .iter()
+            .map(|sk| bitcoin::PublicKey::new(PublicKey::from_secret_key(&secp, sk)))
+            .collect::<Vec<_>>();
+        let desc = format!("wsh(or_d(pk({}),pk({})))", pks[0], pks[1]);
+        let desc = Descriptor::<bitcoin::PublicKey>::from_str(&desc).unwrap();
+        let tx = bitcoin::Transaction {
+            version: 1,
+            lock_time: 0,
+            input: vec![TxIn::default()],
+            output: vec![],
+        };
+        let mut psbt = Psbt::from_unsigned_tx(tx).unwrap();
+        psbt.inputs[0].witness_script = Some(desc.explicit_script().unwrap());
+        psbt.inputs[0].witness_utxo = Some(TxOut {
+            value: 1_000,
+            script_pubkey: desc.script_pubkey(),
+        });
+        let msg = psbt
+            .sighash_msg(0, &mut SighashCache::new(&psbt.unsigned_tx), None)
+            .unwrap()
+            .to_secp_msg();
+        let sign = |sk| bitcoin::EcdsaSig::sighash_all(secp.sign_ecdsa(&msg, sk));
+        // A stale signature for the cheapest path, signing another message
+        let stale = secp.sign_ecdsa(&secp256k1::Message::from_slice(&[1; 32]).unwrap(), &sks[0]);
+        psbt.inputs[0]
+            .partial_sigs
+            .insert(pks[0], bitcoin::EcdsaSig::sighash_all(stale));
+        psbt.inputs[0].partial_sigs.insert(pks[1], sign(&sks[1]));
+
+        let (witness, _) = desc
+            .get_satisfaction(PsbtInputSatisfier::new(&psbt, 0))
+            .unwrap();
+        assert_eq!(witness[0], bitcoin::EcdsaSig::sighash_all(stale).to_vec());
+
+        let sat = PsbtInputSatisfier::new(&psbt, 0).verify_signatures(&secp);
+        assert_eq!(
+            *sat.skipped_sigs(),
+            vec![(
+                SignatureKey::Ecdsa(pks[0]),
+                SignatureError::InvalidSignature
+            )]
+            .into_iter()
+            .collect()
+        );
+        let (witness, _) = desc.get_satisfaction(sat).unwrap();
+        assert_eq!(witness.len(), 3);
+        assert_eq!(witness[0], sign(&sks[1]).to_vec());
+        assert!(witness[1].is_empty());
+    }
+
+    #[test]
+    fn test_sighash_msg_cached() {
+        let desc = "wsh(pk(020000000000000000000000000000000000000000000000000000000000000002))";
+        let desc = Descriptor::<bitcoin::PublicKey>::from_str(desc).unwrap();
+        let tr = "tr(0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798)";
+        let tr = Descriptor::<bitcoin::PublicKey>::from_str(tr).unwrap();
+        let tx = bitcoin::Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn::default(), TxIn::default()],
+            output: vec![],
+        };
+        let mut psbt = Psbt::from_unsigned_tx(tx).unwrap();
+        psbt.inputs[0].witness_script = Some(desc.explicit_script().unwrap());
+        psbt.inputs[0].witness_utxo = Some(TxOut {
+            value: 1_000,
+            script_pubkey: desc.script_pubkey(),
+        });
+
+        // Every utxo is needed
+        let mut cache = PsbtSighashCache::new(&psbt.unsigned_tx);
+        assert_eq!(
+            psbt.sighash_msg_cached(0, &mut cache, None),
+            Err(SighashError::MissingSpendUtxos)
+        );
+        psbt.inputs[1].witness_utxo = Some(TxOut {
+            value: 2_000,
+            script_pubkey: tr.script_pubkey(),
+        });
+
+        let leaf_hash = TapLeafHash::from_script(&Script::new(), LeafVersion::TapScript);
+        let mut cache = PsbtSighashCache::new(&psbt.unsigned_tx);
+        for &(idx, tapleaf_hash) in &[(0, None), (1, None), (1, Some(leaf_hash))] {
+            let msg = psbt
+                .sighash_msg(idx, &mut SighashCache::new(&psbt.unsigned_tx), tapleaf_hash)
+                .unwrap();
+            assert_eq!(
+                psbt.sighash_msg_cached(idx, &mut cache, tapleaf_hash),
+                Ok(msg)
+            );
+            assert_eq!(
+                psbt.sighash_msg_cached(idx, &mut cache, tapleaf_hash),
+                Ok(msg)
+            );
+        }
+        assert_eq!(cache.msgs.len(), 3);
+
+        // The sighash type is part of the key
+        let mut sighash_none = psbt.clone();
+        sighash_none.inputs[0].sighash_type = Some(EcdsaSighashType::None.into());
+        let msg = sighash_none
+            .sighash_msg(0, &mut SighashCache::new(&psbt.unsigned_tx), None)
+            .unwrap();
+        assert_eq!(
+            sighash_none.sighash_msg_cached(0, &mut cache, None),
+            Ok(msg)
+        );
+        assert_eq!(cache.msgs.len(), 4);
+        assert_eq!(
+            psbt.sighash_msg_cached(2, &mut cache, None),
+            Err(SighashError::IndexOutOfBounds(2, 2))
+        );
+    }
+
+    #[test]
+    fn test_witness_builder() {
+        let secp = Secp256k1::new();
+        let sks = [[1; 32], [2; 32]]
+            .iter()
+            .map(|sk| secp256k1::SecretKey::from_slice(sk).unwrap())
+            .collect::<Vec<_>>();
+        let pks = sks
+            .iter()
+            .map(|sk| bitcoin::PublicKey::new(PublicKey::from_secret_key(&secp, sk)))
+            .collect::<Vec<_>>();
+        let preimage = [0xab; 32];
+        let hash = sha256::Hash::hash(&preimage);
+        let desc = format!(
+            "wsh(or_d(pk({}),and_v(v:pk({}),sha256({}))))",
+            pks[0], pks[1], hash
+        );
+        let desc = Descriptor::<bitcoin::PublicKey>::from_str(&desc).unwrap();
+        let tx = bitcoin::Transaction {
+            version: 1,
+            lock_time: 0,
+            input: vec![TxIn::default()],
+            output: vec![],
+        };
+        let mut psbt = Psbt::from_unsigned_tx(tx).unwrap();
+        psbt.inputs[0].witness_script = Some(desc.explicit_script().unwrap());
+        psbt.inputs[0].witness_utxo = Some(TxOut {
+            value: 1_000,
+            script_pubkey: desc.script_pubkey(),
+        });
+        let msg = psbt
+            .sighash_msg(0, &mut SighashCache::new(&psbt.unsigned_tx), None)
+            .unwrap()
+            .to_secp_msg();
+        let sign = |sk| bitcoin::EcdsaSig::sighash_all(secp.sign_ecdsa(&msg, sk));
+
+        // An external signature combined with the preimage
+        let builder = WitnessBuilder::new(0)
+            .ecdsa_sig(pks[1], sign(&sks[1]))
+            .preimage(preimage);
+        let (witness, script_sig) = builder.build(&psbt, &secp).unwrap();
+        assert!(script_sig.is_empty());
+        assert_eq!(
+            witness.to_vec()[..2],
+            [preimage.to_vec(), sign(&sks[1]).to_vec()]
+        );
+        assert!(psbt.inputs[0].partial_sigs.is_empty());
+
+        // The preimage alone does not satisfy any path
+        let res = WitnessBuilder::new(0)
+            .preimage(preimage)
+            .build(&psbt, &secp);
+        assert!(res.is_err());
+
+        // A stale signature for the cheapest path is caught by the interpreter
+        let stale = secp.sign_ecdsa(&secp256k1::Message::from_slice(&[1; 32]).unwrap(), &sks[0]);
+        let res = builder
+            .clone()
+            .ecdsa_sig(pks[0], bitcoin::EcdsaSig::sighash_all(stale))
+            .build(&psbt, &secp);
+        match res.unwrap_err() {
+            Error::InputError(InputError::Interpreter(_), 0) => {}
+            e => panic!("unexpected error {}", e),
+        }
+
+        // A complete stack is only checked
+        let stack = WitnessBuilder::new(0).stack(witness.to_vec(), Script::new());
+        assert_eq!(stack.build(&psbt, &secp).unwrap().0, witness);
+        let mut tampered = witness.to_vec();
+        tampered[0] = vec![0xcd; 32];
+        let res = WitnessBuilder::new(0)
+            .stack(tampered, Script::new())
+            .build(&psbt, &secp);
+        assert!(res.is_err());
+        match WitnessBuilder::new(1).build(&psbt, &secp).unwrap_err() {
+            Error::InputIdxOutofBounds {
+                psbt_inp: 1,
+                index: 1,
+            } => {}
+            e => panic!("unexpected error {}", e),
+        }
+
+        builder.finalize(&mut psbt, &secp).unwrap();
+        assert_eq!(psbt.inputs[0].final_script_witness, Some(witness));
+        assert!(psbt.inputs[0].witness_script.is_none());
+        interpreter_check(&psbt, &secp).unwrap();
+    }
+
+    #[test]
+    fn test_update_input_wv() {
+        let desc = Descriptor::<DescriptorPublicKey>::from_str("wv(1,4e73)").unwrap();
+        let tx = bitcoin::Transaction {
+            version: 1,
+            lock_time: 0,
+            input: vec![TxIn::default()],
+            output: vec![],
+        };
+        let mut psbt = Psbt::from_unsigned_tx(tx).unwrap();
+        psbt.inputs[0].witness_utxo = Some(TxOut {
+            value: 240,
+            script_pubkey: Script::from_str("51024e73").unwrap(),
+        });
+        psbt.update_input_with_descriptor(0, &desc).unwrap();
+        let expected = psbt::Input {
+            witness_utxo: psbt.inputs[0].witness_utxo.clone(),
+            ..Default::default()
+        };
+        assert_eq!(psbt.inputs[0], expected);
+
+        psbt.inputs[0].witness_utxo.as_mut().unwrap().script_pubkey =
+            Script::from_str("52024e73").unwrap();
+        assert_eq!(
+            psbt.update_input_with_descriptor(0, &desc),
+            Err(UtxoUpdateError::MismatchedScriptPubkey)
+        );
+    }
+
+    #[test]
+    fn test_update_input_checks() {
+        let desc = format!("tr([73c5da0a/86'/0'/0']xpub6BgBgsespWvERF3LHQu6CnqdvfEvtMcQjYrcRzx53QJjSxarj2afYWcLteoGVky7D3UKDP9QyrLprQ3VCECoY49yfdDEHGCtMMj92pReUsQ/0/0)");
+        let desc = Descriptor::<DescriptorPublicKey>::from_str(&desc).unwrap();
+
+        let mut non_witness_utxo = bitcoin::Transaction {
+            version: 1,
+            lock_time: 0,
+            input: vec![],
+            output: vec![TxOut {
+                value: 1_000,
+                script_pubkey: Script::from_str(
+                    "5120a60869f0dbcf1dc659c9cecbaf8050135ea9e8cdc487053f1dc6880949dc684c",
+                )
+                .unwrap(),
+            }],
+        };
+
+        let tx = bitcoin::Transaction {
+            version: 1,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint {
+                    txid: non_witness_utxo.txid(),
+                    vout: 0,
+                },
+                ..Default::default()
+            }],
+            output: vec![],
+        };
+
+        let mut psbt = Psbt::from_unsigned_tx(tx.clone()).unwrap();
         assert_eq!(
             psbt.update_input_with_descriptor(0, &desc),
             Err(UtxoUpdateError::UtxoCheck),
@@ -1460,4 +3826,1035 @@ mod tests {
             "non_witness_utxo no longer matches"
         );
     }
+
+    #[test]
+    fn test_global_xpubs() {
+        let secp = Secp256k1::verification_only();
+        let account = "xpub6CatWdiZiodmUeTDp8LT5or8nmbKNcuyvz7WyksVFkKB4RHwCD3XyuvPEbvqAQY3rAPshWcMLoP2fMFMKHPJ4ZeZXYVUhLv1VMrjPC7PW6V";
+        let cosigner = "xpub661MyMwAqRbcFkPHucMnrGNzDwb6teAX1RbKQmqtEF8kK3Z7LZ59qafCjB9eCRLiTVG3uxBxgKvRgbubRhqSKXnGGb1aoaqLrpMBDrVxga8";
+        let desc = Descriptor::<DescriptorPublicKey>::from_str(&format!(
+            "wsh(multi(2,[73c5da0a/84'/0'/0']{}/0/*,{}/0/*))",
+            account, cosigner
+        ))
+        .unwrap();
+        let derived = Descriptor::<DescriptorPublicKey>::from_str(&format!(
+            "wsh(multi(2,[73c5da0a/84'/0'/0']{}/0/7,{}/0/7))",
+            account, cosigner
+        ))
+        .unwrap();
+        let account = ExtendedPubKey::from_str(account).unwrap();
+        let cosigner = ExtendedPubKey::from_str(cosigner).unwrap();
+        let tx = bitcoin::Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn::default()],
+            output: vec![],
+        };
+
+        let mut psbt = Psbt::from_unsigned_tx(tx).unwrap();
+        psbt.update_global_xpubs(&desc).unwrap();
+        let account_origin = (
+            bip32::Fingerprint::from_str("73c5da0a").unwrap(),
+            DerivationPath::from_str("m/84'/0'/0'").unwrap(),
+        );
+        let cosigner_origin = (cosigner.fingerprint(), DerivationPath::master());
+        assert_eq!(psbt.xpub.len(), 2);
+        assert_eq!(psbt.xpub[&account], account_origin);
+        assert_eq!(psbt.xpub[&cosigner], cosigner_origin);
+        // Updating again is a no-op
+        psbt.update_global_xpubs(&desc).unwrap();
+        assert_eq!(psbt.xpub.len(), 2);
+
+        let mut other = psbt.clone();
+        other.xpub.insert(account, cosigner_origin.clone());
+        assert_eq!(
+            other.update_global_xpubs(&desc),
+            Err(GlobalXpubError::ConflictingOrigin(account))
+        );
+
+        // The derivations set by the updater agree with the global xpubs
+        psbt.inputs[0].witness_utxo = Some(TxOut {
+            value: 1_000,
+            script_pubkey: derived.derive(0).script_pubkey(),
+        });
+        psbt.update_input_with_descriptor(0, &derived).unwrap();
+        assert_eq!(psbt.inputs[0].bip32_derivation.len(), 2);
+        assert_eq!(psbt.verify_global_xpubs(&secp), Ok(()));
+
+        // An unrelated key is not checked, a key claiming to be below one of
+        // the global xpubs must be derived from it
+        let unrelated = (
+            bip32::Fingerprint::from_str("deadbeef").unwrap(),
+            DerivationPath::from_str("m/0/7").unwrap(),
+        );
+        let pk = *psbt.inputs[0].bip32_derivation.keys().next().unwrap();
+        psbt.inputs[0].bip32_derivation.insert(pk, unrelated);
+        assert_eq!(psbt.verify_global_xpubs(&secp), Ok(()));
+        let wrong_index = (
+            account_origin.0,
+            DerivationPath::from_str("m/84'/0'/0'/0/8").unwrap(),
+        );
+        let pk = cosigner
+            .derive_pub(&secp, &DerivationPath::from_str("m/0/7").unwrap())
+            .unwrap()
+            .public_key;
+        psbt.inputs[0].bip32_derivation.insert(pk, wrong_index);
+        assert_eq!(
+            psbt.verify_global_xpubs(&secp),
+            Err(GlobalXpubError::InconsistentDerivation(
+                0,
+                SinglePubKey::FullKey(bitcoin::PublicKey::new(pk))
+            ))
+        );
+    }
+
+    #[test]
+    fn test_verify_role() {
+        let desc = "tr([73c5da0a/86'/0'/0']xpub6BgBgsespWvERF3LHQu6CnqdvfEvtMcQjYrcRzx53QJjSxarj2afYWcLteoGVky7D3UKDP9QyrLprQ3VCECoY49yfdDEHGCtMMj92pReUsQ/0/0)";
+        let desc = Descriptor::<DescriptorPublicKey>::from_str(desc).unwrap();
+        let descs = [desc.clone()];
+        let utxo = TxOut {
+            value: 1_000,
+            script_pubkey: Script::from_str(
+                "5120a60869f0dbcf1dc659c9cecbaf8050135ea9e8cdc487053f1dc6880949dc684c",
+            )
+            .unwrap(),
+        };
+        let tx = bitcoin::Transaction {
+            version: 1,
+            lock_time: 0,
+            input: vec![TxIn::default()],
+            output: vec![],
+        };
+
+        let mut psbt = Psbt::from_unsigned_tx(tx).unwrap();
+        assert_eq!(psbt.verify_role(Role::Updater, &descs), Ok(()));
+        assert_eq!(
+            psbt.verify_role(Role::Signer, &[]),
+            Err(RoleError::DescriptorCountMismatch(0, 1))
+        );
+        assert_eq!(
+            psbt.verify_role(Role::Signer, &descs),
+            Err(RoleError::MissingUtxo(0))
+        );
+        psbt.inputs[0].witness_utxo = Some(utxo);
+        assert_eq!(
+            psbt.verify_role(Role::Signer, &descs),
+            Err(RoleError::MismatchedField(0, "tap_internal_key"))
+        );
+        psbt.update_input_with_descriptor(0, &desc).unwrap();
+        assert_eq!(psbt.verify_role(Role::Signer, &descs), Ok(()));
+        assert_eq!(psbt.verify_role(Role::Finalizer, &descs), Ok(()));
+
+        let sig = secp256k1::schnorr::Signature::from_slice(&[1; 64]).unwrap();
+        psbt.inputs[0].tap_key_sig = Some(bitcoin::SchnorrSig {
+            sig,
+            hash_ty: SchnorrSighashType::Default,
+        });
+        assert_eq!(
+            psbt.verify_role(Role::Updater, &descs),
+            Err(RoleError::UnexpectedSignature(0))
+        );
+        assert_eq!(psbt.verify_role(Role::Finalizer, &descs), Ok(()));
+
+        psbt.inputs[0].witness_utxo.as_mut().unwrap().script_pubkey = Script::default();
+        assert_eq!(
+            psbt.verify_role(Role::Finalizer, &descs),
+            Err(RoleError::MismatchedScriptPubkey(0))
+        );
+        psbt.inputs[0].final_script_witness = Some(Witness::default());
+        assert_eq!(
+            psbt.verify_role(Role::Finalizer, &descs),
+            Err(RoleError::Finalized(0))
+        );
+    }
+
+    #[test]
+    fn test_fee_and_estimated_weight() {
+        let pk = "02e96fe52ef0e22d2f131dd425ce1893073a3c6ad20e8cac36726393dfb4856a4c";
+        let wpkh = Descriptor::<DescriptorPublicKey>::from_str(&format!("wpkh({})", pk)).unwrap();
+        let bare = Descriptor::<DescriptorPublicKey>::from_str(&format!("pk({})", pk)).unwrap();
+        let pk = bitcoin::PublicKey::from_str(pk).unwrap();
+
+        let tx = bitcoin::Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn::default(), TxIn::default()],
+            output: vec![TxOut {
+                value: 15_000,
+                script_pubkey: Script::new_v0_p2wpkh(&pk.wpubkey_hash().unwrap()),
+            }],
+        };
+        let mut psbt = Psbt::from_unsigned_tx(tx.clone()).unwrap();
+        assert_eq!(psbt.fee(), Err(FeeError::MissingInputUtxo(0)));
+        psbt.inputs[0].witness_utxo = Some(TxOut {
+            value: 10_000,
+            script_pubkey: Script::new_v0_p2wpkh(&pk.wpubkey_hash().unwrap()),
+        });
+        psbt.inputs[1].witness_utxo = Some(TxOut {
+            value: 6_000,
+            script_pubkey: Script::new_p2pk(&pk),
+        });
+        assert_eq!(psbt.fee(), Ok(1_000));
+        psbt.unsigned_tx.output[0].value = 17_000;
+        assert_eq!(psbt.fee(), Err(FeeError::NegativeFee));
+
+        assert_eq!(
+            psbt.estimated_weight(core::slice::from_ref(&wpkh)),
+            Err(FeeError::DescriptorCountMismatch(1, 2))
+        );
+
+        // Satisfy both inputs with maximum size signatures and compare
+        let sig = vec![0u8; 72];
+        let mut satisfied = tx;
+        satisfied.input[0].witness = Witness::from_vec(vec![sig.clone(), pk.to_bytes()]);
+        satisfied.input[1].script_sig = bitcoin::blockdata::script::Builder::new()
+            .push_slice(&sig)
+            .into_script();
+        assert_eq!(psbt.estimated_weight(&[wpkh, bare]), Ok(satisfied.weight()));
+    }
+
+    #[test]
+    fn test_finalize_tr_reports_failed_leaves() {
+        let secp = Secp256k1::verification_only();
+        let internal_key = "cc8a4bc64d897bddc5fbc2f670f7a8ba0b386779106cf1223c6fc5d7cd6fc115";
+        let key = "83dfe85a3151d2517290da461fe2815591ef69f2b18a2ce63f01697a8b313145";
+        let hash = sha256::Hash::hash(&[0xab; 32]);
+        let desc = Descriptor::<DescriptorPublicKey>::from_str(&format!(
+            "tr({},{{and_v(v:pk({}),older(144)),and_v(v:sha256({}),pk({}))}})",
+            internal_key, key, hash, key
+        ))
+        .unwrap();
+
+        let tx = bitcoin::Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn::default()],
+            output: vec![],
+        };
+        let mut psbt = Psbt::from_unsigned_tx(tx).unwrap();
+        psbt.inputs[0].witness_utxo = Some(TxOut {
+            value: 10_000,
+            script_pubkey: desc.derived_descriptor(&secp, 0).unwrap().script_pubkey(),
+        });
+        psbt.inputs[0]
+            .update_with_descriptor_unchecked(&desc)
+            .unwrap();
+
+        let leaves = match psbt.finalize_inp_mut(&secp, 0) {
+            Err(Error::InputError(InputError::CouldNotSatisfyTr(leaves), 0)) => leaves,
+            res => panic!("unexpected result {:?}", res),
+        };
+        assert_eq!(leaves.len(), 2);
+        let mut missing: Vec<_> = leaves
+            .iter()
+            .map(|leaf| match leaf.reason {
+                TapLeafFailureReason::Unsatisfied(ref missing) => missing.clone(),
+                ref reason => panic!("unexpected reason {:?}", reason),
+            })
+            .collect();
+        missing.sort();
+        let key = XOnlyPublicKey::from_str(key).unwrap();
+        assert_eq!(
+            missing,
+            vec![
+                vec![
+                    MissingItem::Signature(key),
+                    MissingItem::Sha256Preimage(hash)
+                ],
+                vec![MissingItem::Signature(key), MissingItem::Older(144)],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_update_input_tr_with_leaf_version() {
+        use crate::Tap;
+
+        let desc = Descriptor::<DescriptorPublicKey>::from_str(
+            "tr(cc8a4bc64d897bddc5fbc2f670f7a8ba0b386779106cf1223c6fc5d7cd6fc115,{pk(83dfe85a3151d2517290da461fe2815591ef69f2b18a2ce63f01697a8b313145),leaf_ver(c2,pk(399f1b2f4393f29a18c937859c5dd8a77350103157eb880f02e8c08214277cef))})",
+        )
+        .unwrap();
+        let mut psbt_input = psbt::Input::default();
+        psbt_input.update_with_descriptor_unchecked(&desc).unwrap();
+        assert_eq!(psbt_input.tap_scripts.len(), 2);
+
+        let ver = LeafVersion::from_consensus(0xc2).unwrap();
+        let ms = Miniscript::<XOnlyPublicKey, Tap>::from_str(
+            "pk(399f1b2f4393f29a18c937859c5dd8a77350103157eb880f02e8c08214277cef)",
+        )
+        .unwrap();
+        let (control_block, _) = psbt_input
+            .tap_scripts
+            .iter()
+            .find(|&(_, leaf)| *leaf == (ms.encode(), ver))
+            .unwrap();
+        assert_eq!(control_block.leaf_version, ver);
+        let key = XOnlyPublicKey::from_str(
+            "399f1b2f4393f29a18c937859c5dd8a77350103157eb880f02e8c08214277cef",
+        )
+        .unwrap();
+        let (leaf_hashes, _) = &psbt_input.tap_key_origins[&key];
+        assert_eq!(
+            leaf_hashes,
+            &vec![TapLeafHash::from_script(&ms.encode(), ver)]
+        );
+    }
+
+    #[test]
+    fn test_finalize_inp_with_leaf() {
+        use bitcoin::util::schnorr::TapTweak;
+
+        let secp = Secp256k1::new();
+        let keypair = |b| {
+            let sk = secp256k1::SecretKey::from_slice(&[b; 32]).unwrap();
+            secp256k1::KeyPair::from_secret_key(&secp, sk)
+        };
+        let (internal, a, b) = (keypair(1), keypair(2), keypair(3));
+        let xonly = |kp: &secp256k1::KeyPair| XOnlyPublicKey::from_keypair(kp);
+        let desc = Descriptor::<DescriptorPublicKey>::from_str(&format!(
+            "tr({},{{pk({}),pk({})}})",
+            xonly(&internal),
+            xonly(&a),
+            xonly(&b)
+        ))
+        .unwrap();
+
+        let tx = bitcoin::Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn::default()],
+            output: vec![],
+        };
+        let mut psbt = Psbt::from_unsigned_tx(tx).unwrap();
+        psbt.inputs[0].witness_utxo = Some(TxOut {
+            value: 10_000,
+            script_pubkey: desc.derived_descriptor(&secp, 0).unwrap().script_pubkey(),
+        });
+        psbt.inputs[0]
+            .update_with_descriptor_unchecked(&desc)
+            .unwrap();
+
+        // Sign the key spend and both leaves
+        let mut cache = SighashCache::new(&psbt.unsigned_tx);
+        let msg = psbt.sighash_msg(0, &mut cache, None).unwrap().to_secp_msg();
+        let tweaked = internal
+            .tap_tweak(&secp, psbt.inputs[0].tap_merkle_root)
+            .into_inner();
+        psbt.inputs[0].tap_key_sig = Some(bitcoin::SchnorrSig {
+            sig: secp.sign_schnorr_no_aux_rand(&msg, &tweaked),
+            hash_ty: SchnorrSighashType::Default,
+        });
+        let leaf_hash = |kp: &secp256k1::KeyPair| {
+            let ms = Miniscript::<XOnlyPublicKey, Tap>::from_str(&format!("pk({})", xonly(kp)));
+            TapLeafHash::from_script(&ms.unwrap().encode(), LeafVersion::TapScript)
+        };
+        for kp in &[a, b] {
+            let leaf_hash = leaf_hash(kp);
+            let msg = psbt
+                .sighash_msg(0, &mut cache, Some(leaf_hash))
+                .unwrap()
+                .to_secp_msg();
+            psbt.inputs[0].tap_script_sigs.insert(
+                (xonly(kp), leaf_hash),
+                bitcoin::SchnorrSig {
+                    sig: secp.sign_schnorr_no_aux_rand(&msg, kp),
+                    hash_ty: SchnorrSighashType::Default,
+                },
+            );
+        }
+
+        // The forced leaf is used even though the key spend is cheaper
+        let mut leaf_psbt = psbt.clone();
+        leaf_psbt
+            .finalize_inp_with_leaf(&secp, 0, Some(leaf_hash(&b)))
+            .unwrap();
+        let witness = leaf_psbt.inputs[0].final_script_witness.clone().unwrap();
+        assert_eq!(witness.len(), 3);
+        let script = Script::from(witness.to_vec()[1].clone());
+        assert_eq!(
+            TapLeafHash::from_script(&script, LeafVersion::TapScript),
+            leaf_hash(&b)
+        );
+
+        let mut key_psbt = psbt.clone();
+        key_psbt.finalize_inp_with_leaf(&secp, 0, None).unwrap();
+        assert_eq!(
+            key_psbt.inputs[0]
+                .final_script_witness
+                .as_ref()
+                .unwrap()
+                .len(),
+            1
+        );
+
+        let unknown = TapLeafHash::hash(&[0xab; 32]);
+        match psbt.finalize_inp_with_leaf(&secp, 0, Some(unknown)) {
+            Err(Error::InputError(InputError::MissingTapLeaf(h), 0)) => assert_eq!(h, unknown),
+            res => panic!("unexpected result {:?}", res),
+        }
+        psbt.inputs[0].tap_key_sig = None;
+        match psbt.finalize_inp_with_leaf(&secp, 0, None) {
+            Err(Error::InputError(InputError::MissingTapKeySig, 0)) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+        assert!(psbt.inputs[0].final_script_witness.is_none());
+    }
+
+    #[test]
+    fn test_finalize_with_random_tap_leaf_selection() {
+        let secp = Secp256k1::new();
+        let keypair = |b| {
+            let sk = secp256k1::SecretKey::from_slice(&[b; 32]).unwrap();
+            secp256k1::KeyPair::from_secret_key(&secp, sk)
+        };
+        let (internal, a, b) = (keypair(1), keypair(2), keypair(3));
+        let xonly = |kp: &secp256k1::KeyPair| XOnlyPublicKey::from_keypair(kp);
+        let desc = Descriptor::<DescriptorPublicKey>::from_str(&format!(
+            "tr({},{{pk({}),pk({})}})",
+            xonly(&internal),
+            xonly(&a),
+            xonly(&b)
+        ))
+        .unwrap();
+
+        let tx = bitcoin::Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn::default()],
+            output: vec![],
+        };
+        let mut psbt = Psbt::from_unsigned_tx(tx).unwrap();
+        psbt.inputs[0].witness_utxo = Some(TxOut {
+            value: 10_000,
+            script_pubkey: desc.derived_descriptor(&secp, 0).unwrap().script_pubkey(),
+        });
+        psbt.inputs[0]
+            .update_with_descriptor_unchecked(&desc)
+            .unwrap();
+
+        // Both leaves are signed, and their witnesses have the same size
+        let mut cache = SighashCache::new(&psbt.unsigned_tx);
+        let leaf_hash = |kp: &secp256k1::KeyPair| {
+            let ms = Miniscript::<XOnlyPublicKey, Tap>::from_str(&format!("pk({})", xonly(kp)));
+            TapLeafHash::from_script(&ms.unwrap().encode(), LeafVersion::TapScript)
+        };
+        for kp in &[a, b] {
+            let leaf_hash = leaf_hash(kp);
+            let msg = psbt
+                .sighash_msg(0, &mut cache, Some(leaf_hash))
+                .unwrap()
+                .to_secp_msg();
+            psbt.inputs[0].tap_script_sigs.insert(
+                (xonly(kp), leaf_hash),
+                bitcoin::SchnorrSig {
+                    sig: secp.sign_schnorr_no_aux_rand(&msg, kp),
+                    hash_ty: SchnorrSighashType::Default,
+                },
+            );
+        }
+        let spent_leaf = |ctx: &mut FinalizationContext| {
+            let mut psbt = psbt.clone();
+            psbt.finalize_inp_mut_with_context(&secp, 0, ctx).unwrap();
+            let witness = psbt.inputs[0].final_script_witness.clone().unwrap();
+            let script = Script::from(witness.to_vec()[1].clone());
+            TapLeafHash::from_script(&script, LeafVersion::TapScript)
+        };
+
+        let mut ctx = FinalizationContext::new();
+        let first = spent_leaf(&mut ctx);
+        assert!((0..8).all(|_| spent_leaf(&mut ctx) == first));
+
+        let mut spent = BTreeSet::new();
+        let mut ctx = FinalizationContext::with_random_tap_leaf_selection(0, 42);
+        for _ in 0..32 {
+            spent.insert(spent_leaf(&mut ctx));
+        }
+        assert_eq!(spent.len(), 2);
+        // The same seed makes the same choices
+        let choices = |seed| {
+            let mut ctx = FinalizationContext::with_random_tap_leaf_selection(0, seed);
+            (0..8).map(|_| spent_leaf(&mut ctx)).collect::<Vec<_>>()
+        };
+        assert_eq!(choices(7), choices(7));
+    }
+
+    #[test]
+    fn test_combine_with() {
+        let secp = Secp256k1::verification_only();
+        let desc = Descriptor::<DescriptorPublicKey>::from_str(
+            "tr(cc8a4bc64d897bddc5fbc2f670f7a8ba0b386779106cf1223c6fc5d7cd6fc115,{pk(83dfe85a3151d2517290da461fe2815591ef69f2b18a2ce63f01697a8b313145),pk(399f1b2f4393f29a18c937859c5dd8a77350103157eb880f02e8c08214277cef)})",
+        )
+        .unwrap();
+        let tx = bitcoin::Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn::default()],
+            output: vec![],
+        };
+        let mut psbt = Psbt::from_unsigned_tx(tx).unwrap();
+        psbt.inputs[0].witness_utxo = Some(TxOut {
+            value: 10_000,
+            script_pubkey: desc.derived_descriptor(&secp, 0).unwrap().script_pubkey(),
+        });
+        psbt.inputs[0]
+            .update_with_descriptor_unchecked(&desc)
+            .unwrap();
+
+        // Each signer only knows one of the leaves of the key
+        let key = XOnlyPublicKey::from_str(
+            "83dfe85a3151d2517290da461fe2815591ef69f2b18a2ce63f01697a8b313145",
+        )
+        .unwrap();
+        let leaf_a = TapLeafHash::from_inner([1; 32]);
+        let leaf_b = TapLeafHash::from_inner([2; 32]);
+        let mut ours = psbt.clone();
+        let mut theirs = psbt.clone();
+        ours.inputs[0].tap_key_origins.get_mut(&key).unwrap().0 = vec![leaf_a];
+        theirs.inputs[0].tap_key_origins.get_mut(&key).unwrap().0 = vec![leaf_a, leaf_b];
+        ours.combine_with(theirs).unwrap();
+        assert_eq!(ours.inputs[0].tap_key_origins[&key].0, vec![leaf_a, leaf_b]);
+        assert_eq!(ours.inputs[0].tap_scripts, psbt.inputs[0].tap_scripts);
+
+        // A psbt for a different tree is rejected and leaves us unchanged
+        let other_desc = Descriptor::<DescriptorPublicKey>::from_str(
+            "tr(cc8a4bc64d897bddc5fbc2f670f7a8ba0b386779106cf1223c6fc5d7cd6fc115,pk(83dfe85a3151d2517290da461fe2815591ef69f2b18a2ce63f01697a8b313145))",
+        )
+        .unwrap();
+        let mut other = Psbt::from_unsigned_tx(psbt.unsigned_tx.clone()).unwrap();
+        other.inputs[0]
+            .update_with_descriptor_unchecked(&other_desc)
+            .unwrap();
+        let before = ours.clone();
+        assert_eq!(
+            ours.combine_with(other),
+            Err(CombineError::ConflictingTapTree(0))
+        );
+        assert_eq!(ours, before);
+
+        let mut other = psbt.clone();
+        other.unsigned_tx.version = 1;
+        assert_eq!(
+            ours.combine_with(other),
+            Err(CombineError::UnequalTransactions)
+        );
+    }
+
+    #[test]
+    fn test_update_input_with_preimages() {
+        let preimage = [0xab; 32];
+        let hash = sha256::Hash::hash(&preimage);
+        let other_hash = sha256::Hash::hash(&[0xcd; 32]);
+        let desc = Descriptor::<DescriptorPublicKey>::from_str(&format!(
+            "wsh(andor(pk(0283dfe85a3151d2517290da461fe2815591ef69f2b18a2ce63f01697a8b313145),sha256({}),and_v(v:pk(0231c7d3fc85c148717848033ce276ae2b464a4e2c367ed33886cc428b8af48ff8),sha256({}))))",
+            hash, other_hash
+        ))
+        .unwrap();
+        let mut input = psbt::Input::default();
+        input.update_with_descriptor_unchecked(&desc).unwrap();
+
+        let mut store = HashMap::new();
+        store.insert(hash, preimage);
+        input.update_with_preimages(&store).unwrap();
+        assert_eq!(input.sha256_preimages.len(), 1);
+        assert_eq!(input.sha256_preimages[&hash], preimage.to_vec());
+
+        // Not a miniscript
+        input.witness_script = Some(Script::from(vec![0x6a]));
+        assert!(input.update_with_preimages(&store).is_err());
+    }
+
+    #[test]
+    fn test_verify_signatures() {
+        use bitcoin::util::schnorr::TapTweak;
+
+        let secp = Secp256k1::new();
+        let sk = secp256k1::SecretKey::from_slice(&[1; 32]).unwrap();
+        let pk = bitcoin::PublicKey::new(secp256k1::PublicKey::from_secret_key(&secp, &sk));
+        let keypair = secp256k1::KeyPair::from_secret_key(&secp, sk);
+        let internal_key = XOnlyPublicKey::from_keypair(&keypair);
+
+        let tx = bitcoin::Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn::default(), TxIn::default()],
+            output: vec![],
+        };
+        let mut psbt = Psbt::from_unsigned_tx(tx).unwrap();
+        psbt.inputs[0].witness_utxo = Some(TxOut {
+            value: 10_000,
+            script_pubkey: Script::new_v0_p2wpkh(&pk.wpubkey_hash().unwrap()),
+        });
+        psbt.inputs[1].witness_utxo = Some(TxOut {
+            value: 20_000,
+            script_pubkey: Script::new_v1_p2tr(&secp, internal_key, None),
+        });
+
+        let mut cache = SighashCache::new(&psbt.unsigned_tx);
+        let msg = psbt.sighash_msg(0, &mut cache, None).unwrap().to_secp_msg();
+        psbt.inputs[0].partial_sigs.insert(
+            pk,
+            bitcoin::EcdsaSig::sighash_all(secp.sign_ecdsa(&msg, &sk)),
+        );
+        let msg = psbt.sighash_msg(1, &mut cache, None).unwrap().to_secp_msg();
+        let tweaked = keypair.tap_tweak(&secp, None).into_inner();
+        psbt.inputs[1].tap_key_sig = Some(bitcoin::SchnorrSig {
+            sig: secp.sign_schnorr_no_aux_rand(&msg, &tweaked),
+            hash_ty: SchnorrSighashType::Default,
+        });
+        // A signature for the wrong message
+        let leaf_hash = TapLeafHash::hash(&[0xab; 32]);
+        psbt.inputs[1].tap_script_sigs.insert(
+            (internal_key, leaf_hash),
+            bitcoin::SchnorrSig {
+                sig: secp.sign_schnorr_no_aux_rand(&msg, &keypair),
+                hash_ty: SchnorrSighashType::Default,
+            },
+        );
+
+        let results = psbt.verify_signatures(&secp);
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].len(), 1);
+        assert_eq!(results[0][&SignatureKey::Ecdsa(pk)], Ok(()));
+        assert_eq!(results[1].len(), 2);
+        assert_eq!(results[1][&SignatureKey::TapKey], Ok(()));
+        assert_eq!(
+            results[1][&SignatureKey::TapScript(internal_key, leaf_hash)],
+            Err(SignatureError::InvalidSignature)
+        );
+
+        // Without all spent utxos no sighash can be computed
+        psbt.inputs[1].witness_utxo = None;
+        let results = psbt.verify_signatures(&secp);
+        assert_eq!(
+            results[0][&SignatureKey::Ecdsa(pk)],
+            Err(SignatureError::Sighash(SighashError::MissingSpendUtxos))
+        );
+        assert_eq!(
+            results[1][&SignatureKey::TapKey],
+            Err(SignatureError::InvalidOutputKey)
+        );
+    }
+
+    #[test]
+    fn test_signers_for_input() {
+        use bitcoin::util::bip32::{DerivationPath, Fingerprint};
+
+        let secp = Secp256k1::new();
+        let keys: Vec<_> = (1..4)
+            .map(|i| {
+                let sk = secp256k1::SecretKey::from_slice(&[i; 32]).unwrap();
+                (sk, secp256k1::PublicKey::from_secret_key(&secp, &sk))
+            })
+            .collect();
+        let origin = |i: usize| {
+            (
+                Fingerprint::from(&[i as u8; 4][..]),
+                DerivationPath::from_str(&format!("m/48'/0'/0'/{}", i)).unwrap(),
+            )
+        };
+        let signer = |i: usize| {
+            let (fingerprint, path) = origin(i);
+            let pk = descriptor::SinglePubKey::FullKey(bitcoin::PublicKey::new(keys[i].1));
+            (fingerprint, path, pk)
+        };
+        let sig = |i: usize| {
+            let msg = secp256k1::Message::from_slice(&[0xab; 32]).unwrap();
+            bitcoin::EcdsaSig::sighash_all(secp.sign_ecdsa(&msg, &keys[i].0))
+        };
+
+        let tx = bitcoin::Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn::default(), TxIn::default()],
+            output: vec![],
+        };
+        let mut psbt = Psbt::from_unsigned_tx(tx).unwrap();
+
+        // 2-of-3 multisig with one signature
+        let ms = Miniscript::<bitcoin::PublicKey, Segwitv0>::from_str(&format!(
+            "multi(2,{},{},{})",
+            keys[0].1, keys[1].1, keys[2].1
+        ))
+        .unwrap();
+        psbt.inputs[0].witness_utxo = Some(TxOut {
+            value: 10_000,
+            script_pubkey: ms.encode().to_v0_p2wsh(),
+        });
+        psbt.inputs[0].witness_script = Some(ms.encode());
+        for (i, &(_, pk)) in keys.iter().enumerate() {
+            psbt.inputs[0].bip32_derivation.insert(pk, origin(i));
+        }
+        psbt.inputs[0]
+            .partial_sigs
+            .insert(bitcoin::PublicKey::new(keys[0].1), sig(0));
+        assert_eq!(
+            psbt.signers_for_input(0).unwrap(),
+            vec![signer(1), signer(2)].into_iter().collect()
+        );
+        psbt.inputs[0]
+            .partial_sigs
+            .insert(bitcoin::PublicKey::new(keys[2].1), sig(2));
+        assert!(psbt.signers_for_input(0).unwrap().is_empty());
+
+        // Without a signature the key of a wpkh is found from its origin
+        let wpkh = bitcoin::PublicKey::new(keys[1].1);
+        psbt.inputs[1].witness_utxo = Some(TxOut {
+            value: 10_000,
+            script_pubkey: Script::new_v0_p2wpkh(&wpkh.wpubkey_hash().unwrap()),
+        });
+        psbt.inputs[1].bip32_derivation.insert(keys[0].1, origin(0));
+        psbt.inputs[1].bip32_derivation.insert(keys[1].1, origin(1));
+        assert_eq!(
+            psbt.signers_for_input(1).unwrap(),
+            vec![signer(1)].into_iter().collect()
+        );
+
+        match psbt.signers_for_input(2) {
+            Err(Error::InputIdxOutofBounds {
+                psbt_inp: 2,
+                index: 2,
+            }) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+    }
+
+    #[test]
+    fn test_satisfier_at_chain_tip() {
+        let tx = bitcoin::Transaction {
+            version: 2,
+            lock_time: 700_000,
+            input: vec![TxIn {
+                sequence: 144,
+                ..TxIn::default()
+            }],
+            output: vec![],
+        };
+        let psbt = Psbt::from_unsigned_tx(tx).unwrap();
+        let check_after = |sat: &PsbtInputSatisfier, n| Satisfier::<PublicKey>::check_after(sat, n);
+        let check_older = |sat: &PsbtInputSatisfier, n| Satisfier::<PublicKey>::check_older(sat, n);
+
+        // Without a chain tip only the transaction fields are checked
+        let sat = PsbtInputSatisfier::new(&psbt, 0);
+        assert!(check_after(&sat, 700_000));
+        assert!(check_older(&sat, 144));
+
+        let tip = BlockTime {
+            height: 700_100,
+            mtp: 1_650_000_000,
+        };
+        let conf = BlockTime {
+            height: 700_000,
+            mtp: 1_649_900_000,
+        };
+        let sat = PsbtInputSatisfier::new(&psbt, 0).with_chain_tip(tip, Some(conf));
+        assert!(check_after(&sat, 700_000));
+        assert!(!check_after(&sat, 700_001));
+        // Only 101 confirmations in the next block
+        assert!(!check_older(&sat, 144));
+        assert!(!check_older(&sat, 100));
+        let sat = PsbtInputSatisfier::new(&psbt, 0).with_chain_tip(
+            BlockTime {
+                height: 700_143,
+                ..tip
+            },
+            Some(conf),
+        );
+        assert!(check_older(&sat, 144));
+        assert!(check_older(&sat, 100));
+        // An unconfirmed output never satisfies a relative timelock
+        let sat = PsbtInputSatisfier::new(&psbt, 0).with_chain_tip(tip, None);
+        assert!(!check_older(&sat, 1));
+
+        // The locktime itself is not yet reached
+        let sat = PsbtInputSatisfier::new(&psbt, 0).with_chain_tip(
+            BlockTime {
+                height: 699_999,
+                ..tip
+            },
+            Some(conf),
+        );
+        assert!(!check_after(&sat, 100));
+    }
+
+    #[test]
+    fn test_set_relative_timelock() {
+        let desc = Descriptor::<String>::from_str(
+            "wsh(or_d(pk(A),and_v(v:pk(B),and_v(v:older(144),after(700000)))))",
+        )
+        .unwrap();
+        let tx = bitcoin::Transaction {
+            version: 2,
+            lock_time: 700_000,
+            input: vec![TxIn::default()],
+            output: vec![],
+        };
+        let mut psbt = Psbt::from_unsigned_tx(tx).unwrap();
+        assert_eq!(
+            psbt.set_relative_timelock(1, 144, &desc),
+            Err(SequenceError::IndexOutOfBounds(1, 1))
+        );
+
+        // A final sequence allows no timelocked path, so enabling any is fine
+        psbt.set_relative_timelock(0, 144, &desc).unwrap();
+        assert_eq!(psbt.unsigned_tx.input[0].sequence, 144);
+        // The sequence already signals replaceability
+        psbt.enable_rbf(0, &desc).unwrap();
+        assert_eq!(psbt.unsigned_tx.input[0].sequence, 144);
+        psbt.set_relative_timelock(0, 200, &desc).unwrap();
+
+        // Lowering the relative timelock or disabling it breaks the CSV path
+        let csv_path = PathTimelocks {
+            after_height: Some(700_000),
+            older_height: Some(144),
+            ..PathTimelocks::default()
+        };
+        assert_eq!(
+            psbt.set_relative_timelock(0, 100, &desc),
+            Err(SequenceError::DisallowedPath(csv_path))
+        );
+        assert_eq!(
+            psbt.set_relative_timelock(0, 0xfffffffd, &desc),
+            Err(SequenceError::DisallowedPath(csv_path))
+        );
+        assert_eq!(psbt.unsigned_tx.input[0].sequence, 200);
+
+        let mut psbt = Psbt::from_unsigned_tx(bitcoin::Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn::default()],
+            output: vec![],
+        })
+        .unwrap();
+        psbt.enable_rbf(0, &desc).unwrap();
+        assert_eq!(psbt.unsigned_tx.input[0].sequence, 0xfffffffd);
+    }
+
+    #[test]
+    fn test_sanity_check_preimages() {
+        let tx = bitcoin::Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn::default()],
+            output: vec![],
+        };
+        let mut psbt = Psbt::from_unsigned_tx(tx).unwrap();
+        let preimage = vec![0xab; 32];
+        psbt.inputs[0]
+            .sha256_preimages
+            .insert(sha256::Hash::hash(&preimage), preimage.clone());
+        psbt.inputs[0]
+            .hash160_preimages
+            .insert(hash160::Hash::hash(&preimage), preimage.clone());
+        assert!(sanity_check(&psbt).is_ok());
+
+        // Wrong digest
+        let wrong = vec![0xcd; 32];
+        let digest = sha256d::Hash::hash(&preimage);
+        psbt.inputs[0]
+            .hash256_preimages
+            .insert(digest, wrong.clone());
+        match sanity_check(&psbt) {
+            Err(Error::InputError(InputError::InvalidPreimage { hash, preimage }, 0)) => {
+                assert_eq!(hash, PreimageDigest::Hash256(digest));
+                assert_eq!(preimage, wrong);
+            }
+            res => panic!("unexpected result {:?}", res),
+        }
+        psbt.inputs[0].hash256_preimages.clear();
+
+        // Correct digest of a preimage which is not 32 bytes long, allowed
+        // unless a script of the input uses the digest
+        let short = vec![0xab; 20];
+        let digest = ripemd160::Hash::hash(&short);
+        psbt.inputs[0].ripemd160_preimages.insert(digest, short);
+        assert!(sanity_check(&psbt).is_ok());
+        psbt.inputs[0].witness_script = Some(
+            bitcoin::blockdata::script::Builder::new()
+                .push_slice(&digest[..])
+                .into_script(),
+        );
+        match sanity_check(&psbt) {
+            Err(Error::InputError(InputError::InvalidPreimage { hash, .. }, 0)) => {
+                assert_eq!(hash, PreimageDigest::Ripemd160(digest))
+            }
+            res => panic!("unexpected result {:?}", res),
+        }
+    }
+
+    #[test]
+    fn test_finalize_with_unrelated_preimage() {
+        let secp = Secp256k1::new();
+        let sk = secp256k1::SecretKey::from_slice(&[1; 32]).unwrap();
+        let pk = bitcoin::PublicKey::new(PublicKey::from_secret_key(&secp, &sk));
+        let preimage = [0xab; 32];
+        let desc = Descriptor::<DescriptorPublicKey>::from_str(&format!(
+            "wsh(and_v(v:pk({}),sha256({})))",
+            pk,
+            sha256::Hash::hash(&preimage)
+        ))
+        .unwrap();
+
+        let tx = bitcoin::Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn::default()],
+            output: vec![],
+        };
+        let mut psbt = Psbt::from_unsigned_tx(tx).unwrap();
+        psbt.inputs[0].witness_utxo = Some(TxOut {
+            value: 10_000,
+            script_pubkey: desc.derived_descriptor(&secp, 0).unwrap().script_pubkey(),
+        });
+        psbt.inputs[0]
+            .update_with_descriptor_unchecked(&desc)
+            .unwrap();
+        let mut cache = SighashCache::new(&psbt.unsigned_tx);
+        let msg = psbt.sighash_msg(0, &mut cache, None).unwrap().to_secp_msg();
+        psbt.inputs[0].partial_sigs.insert(
+            pk,
+            bitcoin::EcdsaSig::sighash_all(secp.sign_ecdsa(&msg, &sk)),
+        );
+        psbt.inputs[0]
+            .sha256_preimages
+            .insert(sha256::Hash::hash(&preimage), preimage.to_vec());
+        // A preimage for another protocol, which the script does not use
+        let other = vec![0xcd; 20];
+        psbt.inputs[0]
+            .ripemd160_preimages
+            .insert(ripemd160::Hash::hash(&other), other);
+
+        psbt.finalize_mut(&secp).unwrap();
+        let witness = psbt.inputs[0].final_script_witness.as_ref().unwrap();
+        assert_eq!(witness.to_vec()[0], preimage.to_vec());
+    }
+
+    #[test]
+    fn test_sanity_check_stack_size() {
+        let secp = Secp256k1::signing_only();
+        let keys: Vec<_> = (1..52u8)
+            .map(|i| {
+                let sk = secp256k1::SecretKey::from_slice(&[i; 32]).unwrap();
+                bitcoin::PublicKey::new(PublicKey::from_secret_key(&secp, &sk))
+            })
+            .collect();
+        let tx = bitcoin::Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn::default()],
+            output: vec![],
+        };
+        let mut psbt = Psbt::from_unsigned_tx(tx).unwrap();
+        let mut set_witness_script = |s: &str| {
+            let ms = Miniscript::<bitcoin::PublicKey, Segwitv0>::from_str_insane(s).unwrap();
+            psbt.inputs[0].witness_script = Some(ms.encode());
+            sanity_check(&psbt)
+        };
+
+        // Each of the 51 `pkh` takes two elements, whether it is satisfied or
+        // dissatisfied
+        let subs: Vec<_> = keys
+            .iter()
+            .map(|pk| format!("a:pkh({})", pk.pubkey_hash()))
+            .collect();
+        let thresh = format!(
+            "thresh(1,pkh({}),{})",
+            keys[0].pubkey_hash(),
+            subs[1..].join(",")
+        );
+        match set_witness_script(&thresh) {
+            Err(Error::InputError(
+                InputError::MiniscriptError(crate::Error::ContextError(
+                    ScriptContextError::MaxWitnessItemssExceeded { actual, limit },
+                )),
+                0,
+            )) => assert_eq!((actual, limit), (102, MAX_STANDARD_P2WSH_STACK_ITEMS)),
+            res => panic!("unexpected result {:?}", res),
+        }
+
+        // A single path within the limit is enough
+        let either = format!("or_d(multi(1,{}),{})", keys[0], thresh);
+        assert!(set_witness_script(&either).is_ok());
+
+        // The limit is a standardness rule only
+        set_witness_script(&thresh).unwrap_err();
+        assert!(sanity_check_with(&psbt, &RuleSet::Consensus).is_ok());
+        let custom = |max| RuleSet::Custom {
+            limits: vec![(ResourceLimit::WitnessElements, max)]
+                .into_iter()
+                .collect(),
+        };
+        assert!(sanity_check_with(&psbt, &custom(102)).is_ok());
+        match sanity_check_with(&psbt, &custom(101)) {
+            Err(Error::InputError(
+                InputError::MiniscriptError(crate::Error::ContextError(
+                    ScriptContextError::MaxWitnessItemssExceeded { actual, limit },
+                )),
+                0,
+            )) => assert_eq!((actual, limit), (102, 101)),
+            res => panic!("unexpected result {:?}", res),
+        }
+    }
+
+    #[test]
+    fn test_finalize_with_rules() {
+        let secp = Secp256k1::new();
+        let sks: Vec<_> = (1..7u8)
+            .map(|i| secp256k1::SecretKey::from_slice(&[i; 32]).unwrap())
+            .collect();
+        let pks: Vec<_> = sks
+            .iter()
+            .map(|sk| bitcoin::PublicKey::new(PublicKey::from_secret_key(&secp, sk)))
+            .collect();
+        let ms = pks[1..].iter().fold(format!("pk({})", pks[0]), |ms, pk| {
+            format!("and_v(v:pk({}),{})", pk, ms)
+        });
+        let ms = Miniscript::<bitcoin::PublicKey, Segwitv0>::from_str(&ms).unwrap();
+        let tx = bitcoin::Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn::default()],
+            output: vec![],
+        };
+        let mut psbt = Psbt::from_unsigned_tx(tx).unwrap();
+        psbt.inputs[0].witness_script = Some(ms.encode());
+        psbt.inputs[0].witness_utxo = Some(TxOut {
+            value: 1_000,
+            script_pubkey: ms.encode().to_v0_p2wsh(),
+        });
+        let msg = psbt
+            .sighash_msg(0, &mut SighashCache::new(&psbt.unsigned_tx), None)
+            .unwrap()
+            .to_secp_msg();
+        for (sk, pk) in sks.iter().zip(&pks) {
+            let sig = bitcoin::EcdsaSig::sighash_all(secp.sign_ecdsa(&msg, sk));
+            psbt.inputs[0].partial_sigs.insert(*pk, sig);
+        }
+
+        // The six signatures are above a custom limit of five witness elements
+        let rules = RuleSet::Custom {
+            limits: vec![(ResourceLimit::WitnessElements, 5)]
+                .into_iter()
+                .collect(),
+        };
+        match psbt.clone().finalize_mut_with(&secp, &rules) {
+            Err(errors) => match errors[..] {
+                [Error::InputError(
+                    InputError::MiniscriptError(crate::Error::ResourceLimitExceeded(exceeded)),
+                    0,
+                )] => assert_eq!((exceeded.actual, exceeded.max), (6, 5)),
+                _ => panic!("unexpected errors {:?}", errors),
+            },
+            Ok(()) => panic!("finalized above the custom limit"),
+        }
+        psbt.clone()
+            .finalize_mall_mut_with(&secp, &rules)
+            .unwrap_err();
+        psbt.clone()
+            .finalize_mut_with(&secp, &RuleSet::Consensus)
+            .unwrap();
+        psbt.finalize_mut(&secp).unwrap();
+        let witness = psbt.inputs[0].final_script_witness.as_ref().unwrap();
+        assert_eq!(witness.len(), 7);
+    }
 }
@@ -0,0 +1,453 @@
+// Miniscript
+// Written in 2022 by
+//     The rust-miniscript developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Proof of Reserves
+//!
+//! Construction and verification of BIP 127 proof-of-reserves PSBTs. A proof
+//! is a transaction spending the outputs whose ownership is proven, along with
+//! a first "challenge" input spending a non-existent output committing to a
+//! message, so that the transaction can never be mined.
+
+use core::fmt;
+use core::ops::Range;
+#[cfg(feature = "std")]
+use std::error;
+
+use bitcoin::blockdata::opcodes;
+use bitcoin::blockdata::witness::Witness;
+use bitcoin::hashes::{sha256d, Hash, HashEngine};
+use bitcoin::secp256k1::{self, Secp256k1};
+use bitcoin::util::sighash::Prevouts;
+use bitcoin::{
+    self, EcdsaSighashType, OutPoint, SchnorrSighashType, Script, Transaction, TxIn, TxOut, Txid,
+};
+
+use super::{Error, Psbt, PsbtExt};
+use crate::prelude::*;
+use crate::{descriptor, interpreter, Descriptor, DescriptorPublicKey};
+
+/// The outpoint spent by the challenge input of a proof of reserves for
+/// `message`, whose txid is the double SHA256 of `"Proof-of-Reserves: "`
+/// followed by the message
+pub fn reserve_proof_challenge(message: &str) -> OutPoint {
+    let mut engine = sha256d::Hash::engine();
+    engine.input(b"Proof-of-Reserves: ");
+    engine.input(message.as_bytes());
+    OutPoint::new(Txid::from_hash(sha256d::Hash::from_engine(engine)), 0)
+}
+
+/// Create an unsigned proof of reserves for `message` spending `utxos`.
+///
+/// The challenge is the first input of the psbt, and the single output pays
+/// the total amount of `utxos` to `OP_TRUE`. The owned inputs should be
+/// updated with their descriptors and signed with `SIGHASH_ALL` as usual, and
+/// then finalized with [`finalize_reserve_proof`].
+pub fn reserve_proof_psbt(message: &str, utxos: &[(OutPoint, TxOut)]) -> Psbt {
+    let challenge = TxIn {
+        previous_output: reserve_proof_challenge(message),
+        ..Default::default()
+    };
+    let tx = Transaction {
+        version: 1,
+        lock_time: 0,
+        input: Some(challenge)
+            .into_iter()
+            .chain(utxos.iter().map(|&(outpoint, _)| TxIn {
+                previous_output: outpoint,
+                ..Default::default()
+            }))
+            .collect(),
+        output: vec![TxOut {
+            value: utxos.iter().map(|(_, utxo)| utxo.value).sum(),
+            script_pubkey: Script::from(vec![opcodes::OP_TRUE.into_u8()]),
+        }],
+    };
+    let mut psbt = Psbt::from_unsigned_tx(tx).expect("the transaction is unsigned");
+    // The challenge spends nothing, but taproot signatures commit to the
+    // spent outputs of every input
+    psbt.inputs[0].witness_utxo = Some(TxOut {
+        value: 0,
+        script_pubkey: Script::new(),
+    });
+    for (input, (_, utxo)) in psbt.inputs[1..].iter_mut().zip(utxos) {
+        input.witness_utxo = Some(utxo.clone());
+    }
+    psbt
+}
+
+/// Finalize every owned input of a proof of reserves, leaving the challenge
+/// input empty.
+///
+/// As for [`PsbtExt::finalize_mut`], the signatures of every finalized input
+/// are checked.
+///
+/// # Errors:
+///
+/// - A vector of errors, one of each of failed finalized input
+pub fn finalize_reserve_proof<C: secp256k1::Verification>(
+    psbt: &mut Psbt,
+    secp: &Secp256k1<C>,
+) -> Result<(), Vec<Error>> {
+    let errors: Vec<_> = (1..psbt.inputs.len())
+        .filter_map(|index| psbt.finalize_inp_mut(secp, index).err())
+        .collect();
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+/// Verify a finalized proof of reserves for `message`, returning the total
+/// amount proven.
+///
+/// The outputs spent by the owned inputs are looked up with `utxo`, and the
+/// utxos of the psbt, which are chosen by the prover, are ignored. `utxo`
+/// must only return outputs which the verifier has confirmed exist and are
+/// unspent, typically from its own UTXO set: the proof only shows that the
+/// prover could spend these outputs, not that they are still available.
+///
+/// Every owned input must spend a distinct output of one of `descriptors`,
+/// derived at an index in `range` for descriptors with wildcards, and be
+/// signed with `SIGHASH_ALL` so that its signatures commit to the challenge.
+pub fn verify_reserve_proof<C, F>(
+    psbt: &Psbt,
+    message: &str,
+    utxo: F,
+    descriptors: &[Descriptor<DescriptorPublicKey>],
+    range: Range<u32>,
+    secp: &Secp256k1<C>,
+) -> Result<u64, ReserveProofError>
+where
+    C: secp256k1::Verification,
+    F: Fn(&OutPoint) -> Option<TxOut>,
+{
+    let tx = &psbt.unsigned_tx;
+    match tx.input.first() {
+        Some(txin) if txin.previous_output == reserve_proof_challenge(message) => {}
+        _ => return Err(ReserveProofError::ChallengeMismatch),
+    }
+    if tx.input.len() < 2 {
+        return Err(ReserveProofError::NoReserves);
+    }
+    if tx.output.len() != 1 {
+        return Err(ReserveProofError::OutputCount(tx.output.len()));
+    }
+
+    // The challenge spends nothing, as when the proof was signed
+    let mut utxos = vec![TxOut {
+        value: 0,
+        script_pubkey: Script::new(),
+    }];
+    // An output spent twice would be counted twice
+    let mut spent = BTreeSet::new();
+    for (index, txin) in tx.input.iter().enumerate() {
+        if !spent.insert(txin.previous_output) {
+            return Err(ReserveProofError::DuplicateInput(index));
+        }
+        if index == 0 {
+            continue;
+        }
+        let utxo = utxo(&txin.previous_output).ok_or(ReserveProofError::UnknownUtxo(index))?;
+        utxos.push(utxo);
+    }
+    let prevouts = Prevouts::All(&utxos);
+    let mut total = 0u64;
+    for (index, utxo) in utxos.iter().enumerate().skip(1) {
+        let mut owned = false;
+        for desc in descriptors {
+            let found = desc
+                .find_derivation_index_for_spk(secp, &utxo.script_pubkey, range.clone())
+                .map_err(ReserveProofError::DerivationError)?;
+            if found.is_some() {
+                owned = true;
+                break;
+            }
+        }
+        if !owned {
+            return Err(ReserveProofError::UnknownInput(index));
+        }
+
+        let input = &psbt.inputs[index];
+        if input.final_script_sig.is_none() && input.final_script_witness.is_none() {
+            return Err(ReserveProofError::NotFinalized(index));
+        }
+        let empty_script_sig = Script::new();
+        let script_sig = input.final_script_sig.as_ref().unwrap_or(&empty_script_sig);
+        let witness = input
+            .final_script_witness
+            .as_ref()
+            .map(|wit_slice| Witness::from_vec(wit_slice.to_vec()))
+            .unwrap_or_default();
+        let interpreter = interpreter::Interpreter::from_txdata(
+            &utxo.script_pubkey,
+            script_sig,
+            &witness,
+            tx.lock_time,
+            tx.input[index].sequence,
+        )
+        .map_err(|e| ReserveProofError::Interpreter(index, e))?;
+        for constraint in interpreter.iter(secp, tx, index, &prevouts) {
+            let constraint = constraint.map_err(|e| ReserveProofError::Interpreter(index, e))?;
+            let key_sig = match constraint {
+                interpreter::SatisfiedConstraint::PublicKey { key_sig }
+                | interpreter::SatisfiedConstraint::PublicKeyHash { key_sig, .. } => key_sig,
+                _ => continue,
+            };
+            let sighash_all = match key_sig {
+                interpreter::KeySigPair::Ecdsa(_, sig) => sig.hash_ty == EcdsaSighashType::All,
+                interpreter::KeySigPair::Schnorr(_, sig) => {
+                    sig.hash_ty == SchnorrSighashType::All
+                        || sig.hash_ty == SchnorrSighashType::Default
+                }
+            };
+            if !sighash_all {
+                return Err(ReserveProofError::NonSighashAll(index));
+            }
+        }
+        total = total
+            .checked_add(utxo.value)
+            .ok_or(ReserveProofError::ValueOverflow)?;
+    }
+    Ok(total)
+}
+
+/// Return error type for [`verify_reserve_proof`]
+#[derive(Debug)]
+pub enum ReserveProofError {
+    /// The first input does not spend the challenge for the message
+    ChallengeMismatch,
+    /// The proof has no input besides the challenge
+    NoReserves,
+    /// The proof has this number of outputs instead of one
+    OutputCount(usize),
+    /// The input at this index spends an output the verifier does not know
+    UnknownUtxo(usize),
+    /// The input at this index spends the same output as an earlier input
+    DuplicateInput(usize),
+    /// Derivation error
+    DerivationError(descriptor::ConversionError),
+    /// The input at this index spends an output of none of the descriptors
+    UnknownInput(usize),
+    /// The input at this index is not finalized
+    NotFinalized(usize),
+    /// The input at this index is not validly signed
+    Interpreter(usize, interpreter::Error),
+    /// The input at this index has a signature not committing to every input
+    NonSighashAll(usize),
+    /// The sum of the input values overflowed
+    ValueOverflow,
+}
+
+impl fmt::Display for ReserveProofError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReserveProofError::ChallengeMismatch => {
+                write!(f, "First input does not spend the proof challenge")
+            }
+            ReserveProofError::NoReserves => write!(f, "Proof spends no reserves"),
+            ReserveProofError::OutputCount(n) => write!(f, "Proof has {} outputs, not 1", n),
+            ReserveProofError::UnknownUtxo(ind) => {
+                write!(f, "Psbt input {} spends an unknown output", ind)
+            }
+            ReserveProofError::DuplicateInput(ind) => {
+                write!(f, "Psbt input {} spends an output already spent", ind)
+            }
+            ReserveProofError::DerivationError(e) => write!(f, "Key derivation error {}", e),
+            ReserveProofError::UnknownInput(ind) => {
+                write!(f, "Psbt input {} matches none of the descriptors", ind)
+            }
+            ReserveProofError::NotFinalized(ind) => {
+                write!(f, "Psbt input {} is not finalized", ind)
+            }
+            ReserveProofError::Interpreter(ind, e) => write!(f, "{} at index {}", e, ind),
+            ReserveProofError::NonSighashAll(ind) => {
+                write!(f, "Psbt input {} is not signed with SIGHASH_ALL", ind)
+            }
+            ReserveProofError::ValueOverflow => write!(f, "Sum of psbt amounts overflowed"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl error::Error for ReserveProofError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        use self::ReserveProofError::*;
+
+        match self {
+            ChallengeMismatch | NoReserves | OutputCount(_) | UnknownUtxo(_)
+            | DuplicateInput(_) | UnknownInput(_) | NotFinalized(_) | NonSighashAll(_)
+            | ValueOverflow => None,
+            DerivationError(e) => Some(e),
+            Interpreter(_, e) => Some(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::str::FromStr;
+
+    use bitcoin::util::schnorr::TapTweak;
+    use bitcoin::util::sighash::SighashCache;
+    use bitcoin::XOnlyPublicKey;
+
+    use super::*;
+
+    #[test]
+    fn reserve_proof() {
+        let secp = Secp256k1::new();
+        let sk = secp256k1::SecretKey::from_slice(&[1; 32]).unwrap();
+        let pk = bitcoin::PublicKey::new(secp256k1::PublicKey::from_secret_key(&secp, &sk));
+        let keypair = secp256k1::KeyPair::from_secret_key(&secp, sk);
+        let internal_key = XOnlyPublicKey::from_keypair(&keypair);
+        let wpkh = Descriptor::<DescriptorPublicKey>::from_str(&format!("wpkh({})", pk)).unwrap();
+        let tr =
+            Descriptor::<DescriptorPublicKey>::from_str(&format!("tr({})", internal_key)).unwrap();
+        let utxo = |vout, value, desc: &Descriptor<DescriptorPublicKey>| {
+            let outpoint = OutPoint::new(Txid::hash(&[2; 32]), vout);
+            let script_pubkey = desc.derived_descriptor(&secp, 0).unwrap().script_pubkey();
+            (
+                outpoint,
+                TxOut {
+                    value,
+                    script_pubkey,
+                },
+            )
+        };
+
+        let message = "bitcoin exchange reserves";
+        let descriptors = [wpkh.clone(), tr.clone()];
+        let utxos = [utxo(0, 10_000, &wpkh), utxo(1, 20_000, &tr)];
+        let utxo_set = |outpoint: &OutPoint| {
+            utxos
+                .iter()
+                .find(|&&(o, _)| o == *outpoint)
+                .map(|(_, utxo)| utxo.clone())
+        };
+        let mut psbt = reserve_proof_psbt(message, &utxos);
+        assert_eq!(psbt.unsigned_tx.output[0].value, 30_000);
+        psbt.inputs[2].tap_internal_key = Some(internal_key);
+        let unsigned = psbt.clone();
+
+        let mut cache = SighashCache::new(&unsigned.unsigned_tx);
+        let msg = psbt.sighash_msg(1, &mut cache, None).unwrap().to_secp_msg();
+        psbt.inputs[1].partial_sigs.insert(
+            pk,
+            bitcoin::EcdsaSig::sighash_all(secp.sign_ecdsa(&msg, &sk)),
+        );
+        let msg = psbt.sighash_msg(2, &mut cache, None).unwrap().to_secp_msg();
+        let tweaked = keypair.tap_tweak(&secp, None).into_inner();
+        psbt.inputs[2].tap_key_sig = Some(bitcoin::SchnorrSig {
+            sig: secp.sign_schnorr_no_aux_rand(&msg, &tweaked),
+            hash_ty: SchnorrSighashType::Default,
+        });
+
+        match verify_reserve_proof(&psbt, message, utxo_set, &descriptors, 0..1, &secp) {
+            Err(ReserveProofError::NotFinalized(1)) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+        finalize_reserve_proof(&mut psbt, &secp).unwrap();
+        assert!(psbt.inputs[0].final_script_witness.is_none());
+        assert_eq!(
+            verify_reserve_proof(&psbt, message, utxo_set, &descriptors, 0..1, &secp).unwrap(),
+            30_000
+        );
+
+        // The challenge commits to the message
+        match verify_reserve_proof(
+            &psbt,
+            "another message",
+            utxo_set,
+            &descriptors,
+            0..1,
+            &secp,
+        ) {
+            Err(ReserveProofError::ChallengeMismatch) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+        // Every input must belong to one of the descriptors
+        match verify_reserve_proof(
+            &psbt,
+            message,
+            utxo_set,
+            core::slice::from_ref(&wpkh),
+            0..1,
+            &secp,
+        ) {
+            Err(ReserveProofError::UnknownInput(2)) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+        // Signatures are checked
+        let mut forged = unsigned;
+        forged.inputs[1].final_script_witness = psbt.inputs[1].final_script_witness.clone();
+        forged.inputs[2].final_script_witness = Some(Witness::from_vec(vec![vec![0x01; 64]]));
+        match verify_reserve_proof(&forged, message, utxo_set, &descriptors, 0..1, &secp) {
+            Err(ReserveProofError::Interpreter(2, _)) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+        // The spent outputs must be known to the verifier
+        match verify_reserve_proof(
+            &psbt,
+            message,
+            |_: &OutPoint| None,
+            &descriptors,
+            0..1,
+            &secp,
+        ) {
+            Err(ReserveProofError::UnknownUtxo(1)) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+        // Each output is only counted once
+        let twice = reserve_proof_psbt(message, &[utxos[0].clone(), utxos[0].clone()]);
+        match verify_reserve_proof(&twice, message, utxo_set, &descriptors, 0..1, &secp) {
+            Err(ReserveProofError::DuplicateInput(2)) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+
+        // The amounts in the psbt are not trusted: an inflated utxo is
+        // ignored, and a proof signed for it does not verify against the
+        // actual output
+        let mut tampered = psbt.clone();
+        tampered.inputs[1].witness_utxo.as_mut().unwrap().value = 10_000_000;
+        assert_eq!(
+            verify_reserve_proof(&tampered, message, utxo_set, &descriptors, 0..1, &secp).unwrap(),
+            30_000
+        );
+        let inflated = [utxo(0, 10_000_000, &wpkh)];
+        let mut psbt = reserve_proof_psbt(message, &inflated);
+        assert_eq!(psbt.unsigned_tx.output[0].value, 10_000_000);
+        let mut cache = SighashCache::new(&psbt.unsigned_tx);
+        let msg = psbt.sighash_msg(1, &mut cache, None).unwrap().to_secp_msg();
+        psbt.inputs[1].partial_sigs.insert(
+            pk,
+            bitcoin::EcdsaSig::sighash_all(secp.sign_ecdsa(&msg, &sk)),
+        );
+        finalize_reserve_proof(&mut psbt, &secp).unwrap();
+        let inflated_set = |outpoint: &OutPoint| {
+            inflated
+                .iter()
+                .find(|&&(o, _)| o == *outpoint)
+                .map(|(_, utxo)| utxo.clone())
+        };
+        assert_eq!(
+            verify_reserve_proof(&psbt, message, inflated_set, &descriptors, 0..1, &secp).unwrap(),
+            10_000_000
+        );
+        match verify_reserve_proof(&psbt, message, utxo_set, &descriptors, 0..1, &secp) {
+            Err(ReserveProofError::Interpreter(1, _)) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+    }
+}
@@ -0,0 +1,214 @@
+// Miniscript
+// Written in 2022 by
+//     The rust-miniscript developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # PSBT Roles
+//!
+//! Checks that a PSBT has the fields expected by one of the roles of BIP 174
+//! before it is handed to that role.
+
+use core::fmt;
+#[cfg(feature = "std")]
+use std::error;
+
+use bitcoin::util::psbt;
+
+use super::{update_item_with_descriptor_helper, utxo_script_pubkey, Psbt};
+use crate::{descriptor, Descriptor, DescriptorPublicKey};
+
+/// A role of BIP 174 a PSBT can be checked against with
+/// [`PsbtExt::verify_role`](super::PsbtExt::verify_role)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Role {
+    /// The PSBT is about to be updated: no input is signed or finalized
+    Updater,
+    /// The PSBT is about to be signed: no input is finalized, and every input
+    /// has the utxo and script fields of its descriptor
+    Signer,
+    /// The PSBT is about to be finalized, with the same requirements as
+    /// [`Role::Signer`]
+    Finalizer,
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Role::Updater => f.write_str("updater"),
+            Role::Signer => f.write_str("signer"),
+            Role::Finalizer => f.write_str("finalizer"),
+        }
+    }
+}
+
+/// Return error type for [`PsbtExt::verify_role`](super::PsbtExt::verify_role)
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+pub enum RoleError {
+    /// The psbt has this many inputs in its transaction but this many input maps
+    WrongInputCount(usize, usize),
+    /// The number of descriptors does not match the number of inputs
+    DescriptorCountMismatch(usize, usize),
+    /// The psbt input at this index already has a final scriptSig or witness
+    Finalized(usize),
+    /// The psbt input at this index has signatures, which the updater does
+    /// not expect
+    UnexpectedSignature(usize),
+    /// The psbt input at this index has neither a `witness_utxo` nor a
+    /// `non_witness_utxo`
+    MissingUtxo(usize),
+    /// The utxo of the psbt input at this index is inconsistent with the
+    /// transaction, or is not the utxo required by its descriptor: a
+    /// `witness_utxo` for segwit descriptors and a `non_witness_utxo` otherwise
+    UtxoCheck(usize),
+    /// The utxo of the psbt input at this index has a script pubkey that does
+    /// not match its descriptor
+    MismatchedScriptPubkey(usize),
+    /// The psbt input at this index is missing the named field of its
+    /// descriptor, or has a different value for it
+    MismatchedField(usize, &'static str),
+    /// Derivation error
+    DerivationError(descriptor::ConversionError),
+}
+
+impl fmt::Display for RoleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RoleError::WrongInputCount(in_tx, in_map) => write!(
+                f,
+                "PSBT had {} inputs in transaction but {} inputs in map",
+                in_tx, in_map
+            ),
+            RoleError::DescriptorCountMismatch(n_desc, n_inputs) => write!(
+                f,
+                "{} descriptors given for a psbt with {} inputs",
+                n_desc, n_inputs
+            ),
+            RoleError::Finalized(ind) => write!(f, "Psbt input {} is already finalized", ind),
+            RoleError::UnexpectedSignature(ind) => {
+                write!(f, "Psbt input {} is already signed", ind)
+            }
+            RoleError::MissingUtxo(ind) => write!(f, "Missing utxo for psbt input {}", ind),
+            RoleError::UtxoCheck(ind) => write!(
+                f,
+                "The witness_utxo and/or non_witness_utxo of psbt input {} are invalid",
+                ind
+            ),
+            RoleError::MismatchedScriptPubkey(ind) => write!(
+                f,
+                "The utxo of psbt input {} has a script pubkey that doesn't match the descriptor",
+                ind
+            ),
+            RoleError::MismatchedField(ind, field) => write!(
+                f,
+                "The {} of psbt input {} doesn't match the descriptor",
+                field, ind
+            ),
+            RoleError::DerivationError(e) => write!(f, "Key derivation error {}", e),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl error::Error for RoleError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        use self::RoleError::*;
+
+        match self {
+            WrongInputCount(_, _)
+            | DescriptorCountMismatch(_, _)
+            | Finalized(_)
+            | UnexpectedSignature(_)
+            | MissingUtxo(_)
+            | UtxoCheck(_)
+            | MismatchedScriptPubkey(_)
+            | MismatchedField(_, _) => None,
+            DerivationError(e) => Some(e),
+        }
+    }
+}
+
+pub(super) fn verify_role(
+    psbt: &Psbt,
+    role: Role,
+    descriptors: &[Descriptor<DescriptorPublicKey>],
+) -> Result<(), RoleError> {
+    let n_inputs = psbt.unsigned_tx.input.len();
+    if psbt.inputs.len() != n_inputs {
+        return Err(RoleError::WrongInputCount(n_inputs, psbt.inputs.len()));
+    }
+    if descriptors.len() != n_inputs {
+        return Err(RoleError::DescriptorCountMismatch(
+            descriptors.len(),
+            n_inputs,
+        ));
+    }
+
+    let inputs = psbt.inputs.iter().zip(&psbt.unsigned_tx.input);
+    for (index, ((input, txin), desc)) in inputs.zip(descriptors).enumerate() {
+        if input.final_script_sig.is_some() || input.final_script_witness.is_some() {
+            return Err(RoleError::Finalized(index));
+        }
+        match role {
+            Role::Updater => {
+                if !input.partial_sigs.is_empty()
+                    || input.tap_key_sig.is_some()
+                    || !input.tap_script_sigs.is_empty()
+                {
+                    return Err(RoleError::UnexpectedSignature(index));
+                }
+            }
+            Role::Signer | Role::Finalizer => {
+                if input.witness_utxo.is_none() && input.non_witness_utxo.is_none() {
+                    return Err(RoleError::MissingUtxo(index));
+                }
+                let spk = utxo_script_pubkey(input, txin, desc.desc_type())
+                    .map_err(|_| RoleError::UtxoCheck(index))?;
+                check_script_fields(index, input, desc, spk)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// Check that `input` has the script fields the updater sets for `desc`
+fn check_script_fields(
+    index: usize,
+    input: &psbt::Input,
+    desc: &Descriptor<DescriptorPublicKey>,
+    spk: bitcoin::Script,
+) -> Result<(), RoleError> {
+    let mut expected = psbt::Input::default();
+    let (_, spk_check_passed) = update_item_with_descriptor_helper(&mut expected, desc, Some(spk))
+        .map_err(RoleError::DerivationError)?;
+    if !spk_check_passed {
+        return Err(RoleError::MismatchedScriptPubkey(index));
+    }
+
+    if input.redeem_script != expected.redeem_script {
+        return Err(RoleError::MismatchedField(index, "redeem_script"));
+    }
+    if input.witness_script != expected.witness_script {
+        return Err(RoleError::MismatchedField(index, "witness_script"));
+    }
+    if input.tap_internal_key != expected.tap_internal_key {
+        return Err(RoleError::MismatchedField(index, "tap_internal_key"));
+    }
+    if input.tap_merkle_root != expected.tap_merkle_root {
+        return Err(RoleError::MismatchedField(index, "tap_merkle_root"));
+    }
+    for (control_block, leaf) in &expected.tap_scripts {
+        if input.tap_scripts.get(control_block) != Some(leaf) {
+            return Err(RoleError::MismatchedField(index, "tap_scripts"));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,629 @@
+// Miniscript
+// Written in 2022 by
+//     The rust-miniscript developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Lazy Signing
+//!
+//! A satisfier producing signatures on demand, only for the keys of the
+//! spend path chosen by the satisfaction algorithm.
+
+use core::cell::RefCell;
+
+use bitcoin::secp256k1::{KeyPair, Message, Secp256k1, SecretKey, Signing};
+use bitcoin::util::taproot::TapLeafHash;
+use bitcoin::{self, secp256k1, EcdsaSighashType, SchnorrSighashType, Script};
+
+use super::PsbtSighashMsg;
+use crate::prelude::*;
+use crate::{Descriptor, Error, MiniscriptKey, Satisfier, ToPublicKey};
+
+/// A signature produced by the signing callback of a [`SignerSatisfier`]
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Signature {
+    /// An ECDSA signature, for pre-taproot spends
+    Ecdsa(bitcoin::EcdsaSig),
+    /// A Schnorr signature, for taproot spends
+    Schnorr(bitcoin::SchnorrSig),
+}
+
+/// How signatures are produced from secret keys, e.g. in the signing callback
+/// of a [`SignerSatisfier`]
+///
+/// Signatures made with the same options, key and message are identical, so
+/// that transactions signed in tests are reproducible byte for byte.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct SigningOptions {
+    /// Grind ECDSA signatures until their R value is low, as Bitcoin Core
+    /// does, so that they are at most 71 bytes with the sighash type instead
+    /// of the 72 bytes satisfaction weights allow for. Enabled by default.
+    pub low_r: bool,
+    /// The auxiliary randomness of BIP 340 Schnorr signatures. `None`, the
+    /// default, signs without auxiliary randomness; pass fresh random bytes
+    /// for side-channel protection, or fixed ones to reproduce signatures.
+    pub aux_rand: Option<[u8; 32]>,
+}
+
+impl Default for SigningOptions {
+    fn default() -> Self {
+        SigningOptions {
+            low_r: true,
+            aux_rand: None,
+        }
+    }
+}
+
+impl SigningOptions {
+    /// Sign `msg` with `sk` in ECDSA, for pre-taproot spends
+    pub fn sign_ecdsa<C: Signing>(
+        &self,
+        secp: &Secp256k1<C>,
+        msg: &Message,
+        sk: &SecretKey,
+        hash_ty: EcdsaSighashType,
+    ) -> bitcoin::EcdsaSig {
+        let sig = if self.low_r {
+            secp.sign_ecdsa_low_r(msg, sk)
+        } else {
+            secp.sign_ecdsa(msg, sk)
+        };
+        bitcoin::EcdsaSig { sig, hash_ty }
+    }
+
+    /// Sign `msg` with `keypair` in Schnorr, for taproot spends. For key
+    /// spends, `keypair` must already be tweaked with the merkle root.
+    pub fn sign_schnorr<C: Signing>(
+        &self,
+        secp: &Secp256k1<C>,
+        msg: &Message,
+        keypair: &KeyPair,
+        hash_ty: SchnorrSighashType,
+    ) -> bitcoin::SchnorrSig {
+        let sig = match self.aux_rand {
+            Some(ref aux_rand) => secp.sign_schnorr_with_aux_rand(msg, keypair, aux_rand),
+            None => secp.sign_schnorr_no_aux_rand(msg, keypair),
+        };
+        bitcoin::SchnorrSig { sig, hash_ty }
+    }
+}
+
+// A signature the satisfaction algorithm asked for
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+enum SigRequest<Pk> {
+    Ecdsa(Pk),
+    TapKey(Pk),
+    TapLeaf(Pk, TapLeafHash),
+}
+
+// Every placeholder signature given out, encoded
+type Plan<Pk> = Vec<(SigRequest<Pk>, Vec<u8>)>;
+
+/// A [`Satisfier`] which obtains signatures from a signing callback, e.g.
+/// prompting a hardware signer, only for the keys it needs.
+///
+/// The satisfaction algorithm looks up a signature for every key of a script
+/// before picking a spend path, so [`SignerSatisfier::satisfy_descriptor`]
+/// first picks a path assuming every key can sign, and only then calls the
+/// signer for the keys of that path. If the signer declines to sign for some
+/// key, a new path is picked without it. Every key is signed for at most once.
+/// Callbacks holding secret keys can sign with [`SigningOptions`].
+///
+/// Keys of `pk_h` fragments are looked up by their hash and are not signed
+/// for lazily; their signatures must be given by the satisfier passed to
+/// `satisfy_descriptor`.
+pub struct SignerSatisfier<Pk: MiniscriptKey, F, S> {
+    signer: F,
+    sighash: S,
+    // The key signing for a taproot key spend
+    key_spend: RefCell<Option<Pk>>,
+    // The result of every call to the signer
+    sigs: RefCell<BTreeMap<SigRequest<Pk>, Option<Signature>>>,
+    // While picking a path, the placeholder signature given for every request
+    // the signer was not called for yet
+    plan: RefCell<Option<Plan<Pk>>>,
+}
+
+impl<Pk, F, S> SignerSatisfier<Pk, F, S>
+where
+    Pk: MiniscriptKey + ToPublicKey,
+    F: Fn(&Pk, &PsbtSighashMsg) -> Option<Signature>,
+    S: Fn(Option<TapLeafHash>) -> Option<PsbtSighashMsg>,
+{
+    /// Create a new satisfier signing with `signer`.
+    ///
+    /// `sighash` provides the message to sign, given the leaf hash for
+    /// taproot script spends, e.g. by calling
+    /// [`PsbtExt::sighash_msg`](super::PsbtExt::sighash_msg) for the input
+    /// being satisfied. Keys for which it returns `None` are not signed for.
+    pub fn new(signer: F, sighash: S) -> Self {
+        SignerSatisfier {
+            signer,
+            sighash,
+            key_spend: RefCell::new(None),
+            sigs: RefCell::new(BTreeMap::new()),
+            plan: RefCell::new(None),
+        }
+    }
+
+    /// Returns the satisfying non-malleable witness and scriptSig with minimum
+    /// weight for `descriptor`, as [`Descriptor::get_satisfaction`], calling
+    /// the signer for the keys of the chosen spend path only.
+    ///
+    /// Everything but the signatures, such as hash preimages and timelocks, is
+    /// looked up in `satisfier`; pass `()` if there is none.
+    pub fn satisfy_descriptor<T: Satisfier<Pk>>(
+        &self,
+        descriptor: &Descriptor<Pk>,
+        satisfier: T,
+    ) -> Result<(Vec<Vec<u8>>, Script), Error> {
+        *self.key_spend.borrow_mut() = match *descriptor {
+            Descriptor::Tr(ref tr) => Some(tr.internal_key().clone()),
+            Descriptor::RawTr(ref rawtr) => Some(rawtr.as_inner().clone()),
+            _ => None,
+        };
+        loop {
+            *self.plan.borrow_mut() = Some(vec![]);
+            let res = descriptor.get_satisfaction((self, &satisfier));
+            let plan = self.plan.borrow_mut().take().unwrap_or_default();
+            // Fails only if no path is satisfiable even with every key
+            let (witness, script_sig) = res?;
+
+            let needed: Vec<_> = plan
+                .into_iter()
+                .filter(|(_, placeholder)| witness.contains(placeholder))
+                .map(|(request, _)| request)
+                .collect();
+            if needed.is_empty() {
+                return Ok((witness, script_sig));
+            }
+            for request in needed {
+                let sig = self.sign(&request);
+                self.sigs.borrow_mut().insert(request, sig);
+            }
+        }
+    }
+
+    fn sign(&self, request: &SigRequest<Pk>) -> Option<Signature> {
+        let (pk, leaf_hash) = match *request {
+            SigRequest::Ecdsa(ref pk) | SigRequest::TapKey(ref pk) => (pk, None),
+            SigRequest::TapLeaf(ref pk, leaf_hash) => (pk, Some(leaf_hash)),
+        };
+        let msg = (self.sighash)(leaf_hash)?;
+        (self.signer)(pk, &msg)
+    }
+}
+
+impl<Pk: MiniscriptKey, F, S> SignerSatisfier<Pk, F, S> {
+    // The signature for `request`, or a placeholder while picking a path
+    fn lookup(&self, request: SigRequest<Pk>) -> Option<Signature> {
+        if let Some(sig) = self.sigs.borrow().get(&request) {
+            return *sig;
+        }
+        let mut plan = self.plan.borrow_mut();
+        let plan = plan.as_mut()?;
+        if let Some((_, placeholder)) = plan.iter().find(|(r, _)| *r == request) {
+            return Some(placeholder_sig(&request, placeholder));
+        }
+        // Placeholders of the largest signature size, made unique by their index
+        let mut bytes = [0x80; 64];
+        bytes[28..32].copy_from_slice(&(plan.len() as u32).to_be_bytes());
+        let sig = match request {
+            SigRequest::Ecdsa(_) => Signature::Ecdsa(bitcoin::EcdsaSig {
+                sig: secp256k1::ecdsa::Signature::from_compact(&bytes)
+                    .expect("valid compact signature"),
+                hash_ty: EcdsaSighashType::All,
+            }),
+            SigRequest::TapKey(_) | SigRequest::TapLeaf(..) => {
+                Signature::Schnorr(bitcoin::SchnorrSig {
+                    sig: secp256k1::schnorr::Signature::from_slice(&bytes)
+                        .expect("64 byte signature"),
+                    hash_ty: SchnorrSighashType::All,
+                })
+            }
+        };
+        let encoded = match sig {
+            Signature::Ecdsa(sig) => sig.to_vec(),
+            Signature::Schnorr(sig) => sig.to_vec(),
+        };
+        plan.push((request, encoded));
+        Some(sig)
+    }
+}
+
+// Decode a placeholder signature given out before
+fn placeholder_sig<Pk>(request: &SigRequest<Pk>, encoded: &[u8]) -> Signature {
+    match *request {
+        SigRequest::Ecdsa(_) => Signature::Ecdsa(
+            bitcoin::EcdsaSig::from_slice(encoded).expect("placeholder roundtrips"),
+        ),
+        SigRequest::TapKey(_) | SigRequest::TapLeaf(..) => Signature::Schnorr(
+            bitcoin::SchnorrSig::from_slice(encoded).expect("placeholder roundtrips"),
+        ),
+    }
+}
+
+impl<Pk: MiniscriptKey + ToPublicKey, F, S> Satisfier<Pk> for SignerSatisfier<Pk, F, S> {
+    fn lookup_ecdsa_sig(&self, pk: &Pk) -> Option<bitcoin::EcdsaSig> {
+        match self.lookup(SigRequest::Ecdsa(pk.clone())) {
+            Some(Signature::Ecdsa(sig)) => Some(sig),
+            _ => None,
+        }
+    }
+
+    fn lookup_tap_key_spend_sig(&self) -> Option<bitcoin::SchnorrSig> {
+        let pk = self.key_spend.borrow().clone()?;
+        match self.lookup(SigRequest::TapKey(pk)) {
+            Some(Signature::Schnorr(sig)) => Some(sig),
+            _ => None,
+        }
+    }
+
+    fn lookup_tap_leaf_script_sig(
+        &self,
+        pk: &Pk,
+        leaf_hash: &TapLeafHash,
+    ) -> Option<bitcoin::SchnorrSig> {
+        match self.lookup(SigRequest::TapLeaf(pk.clone(), *leaf_hash)) {
+            Some(Signature::Schnorr(sig)) => Some(sig),
+            _ => None,
+        }
+    }
+}
+
+/// A signing service for taproot spends, e.g. a remote signer or hardware
+/// wallet, plugged into finalization with a [`TapSignerSatisfier`]
+///
+/// The trait is object safe and its methods are blocking: services which sign
+/// asynchronously can wait on their result in the implementation. The message
+/// to sign is left to the service, e.g. with
+/// [`PsbtExt::sighash_msg`](super::PsbtExt::sighash_msg).
+pub trait TapSigner<Pk: MiniscriptKey> {
+    /// A signature for the key spend of the output with this internal key, or
+    /// `None` if the service does not sign for it
+    fn sign_key_spend(&self, internal_key: &Pk) -> Option<bitcoin::SchnorrSig>;
+
+    /// Signatures for the leaf `leaf_hash`, by as many of `keys` as the
+    /// service signs for. All keys of the leaf are requested at once so that
+    /// they can be signed for in a single round trip.
+    fn sign_leaf(&self, leaf_hash: &TapLeafHash, keys: &[Pk]) -> BTreeMap<Pk, bitcoin::SchnorrSig>;
+}
+
+/// A [`Satisfier`] looking up taproot signatures in a [`TapSigner`]
+///
+/// Only the key spend and leaf signatures are looked up, so that signing
+/// services need not implement the whole [`Satisfier`] trait. Anything else,
+/// such as ECDSA signatures or hash preimages, can be provided by another
+/// satisfier combined with this one in a tuple. The signer is called at most
+/// once for the key spend and once for each leaf, with every key of the leaf,
+/// and its signatures are kept for later lookups.
+pub struct TapSignerSatisfier<'a, Pk: MiniscriptKey> {
+    signer: &'a dyn TapSigner<Pk>,
+    internal_key: Option<Pk>,
+    // The keys of every leaf of the descriptor, in order of appearance
+    leaf_keys: BTreeMap<TapLeafHash, Vec<Pk>>,
+    key_spend: RefCell<Option<Option<bitcoin::SchnorrSig>>>,
+    leaf_sigs: RefCell<BTreeMap<TapLeafHash, BTreeMap<Pk, bitcoin::SchnorrSig>>>,
+}
+
+impl<'a, Pk: MiniscriptKey + ToPublicKey> TapSignerSatisfier<'a, Pk> {
+    /// Create a new satisfier signing the spends of `descriptor` with
+    /// `signer`. Only `tr` and `rawtr` descriptors have signatures looked up.
+    pub fn new(signer: &'a dyn TapSigner<Pk>, descriptor: &Descriptor<Pk>) -> Self {
+        let (internal_key, leaf_keys) = match *descriptor {
+            Descriptor::Tr(ref tr) => {
+                let leaf_keys = tr
+                    .iter_leaves()
+                    .map(|(_, ver, ms)| {
+                        let mut keys: Vec<Pk> = vec![];
+                        for pk in ms.iter_pk() {
+                            if !keys.contains(&pk) {
+                                keys.push(pk);
+                            }
+                        }
+                        (TapLeafHash::from_script(&ms.encode(), ver), keys)
+                    })
+                    .collect();
+                (Some(tr.internal_key().clone()), leaf_keys)
+            }
+            Descriptor::RawTr(ref rawtr) => (Some(rawtr.as_inner().clone()), BTreeMap::new()),
+            _ => (None, BTreeMap::new()),
+        };
+        TapSignerSatisfier {
+            signer,
+            internal_key,
+            leaf_keys,
+            key_spend: RefCell::new(None),
+            leaf_sigs: RefCell::new(BTreeMap::new()),
+        }
+    }
+}
+
+impl<'a, Pk: MiniscriptKey + ToPublicKey> Satisfier<Pk> for TapSignerSatisfier<'a, Pk> {
+    fn lookup_tap_key_spend_sig(&self) -> Option<bitcoin::SchnorrSig> {
+        let internal_key = self.internal_key.as_ref()?;
+        *self
+            .key_spend
+            .borrow_mut()
+            .get_or_insert_with(|| self.signer.sign_key_spend(internal_key))
+    }
+
+    fn lookup_tap_leaf_script_sig(
+        &self,
+        pk: &Pk,
+        leaf_hash: &TapLeafHash,
+    ) -> Option<bitcoin::SchnorrSig> {
+        let mut leaf_sigs = self.leaf_sigs.borrow_mut();
+        leaf_sigs
+            .entry(*leaf_hash)
+            .or_insert_with(|| match self.leaf_keys.get(leaf_hash) {
+                Some(keys) => self.signer.sign_leaf(leaf_hash, keys),
+                // A leaf which is not in the descriptor, e.g. when satisfying
+                // another one
+                None => BTreeMap::new(),
+            })
+            .get(pk)
+            .copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::cell::Cell;
+    use core::str::FromStr;
+
+    use bitcoin::hashes::Hash;
+    use bitcoin::secp256k1::Secp256k1;
+    use bitcoin::util::taproot::TapSighashHash;
+
+    use super::*;
+
+    #[test]
+    fn sign_lazily() {
+        let secp = Secp256k1::new();
+        let sks: Vec<_> = (1..5u8)
+            .map(|i| secp256k1::SecretKey::from_slice(&[i; 32]).unwrap())
+            .collect();
+        let pks: Vec<_> = sks
+            .iter()
+            .map(|sk| bitcoin::PublicKey::new(secp256k1::PublicKey::from_secret_key(&secp, sk)))
+            .collect();
+        let calls = Cell::new(0);
+        // Signs for every key but the first one
+        let signer = |pk: &bitcoin::PublicKey, msg: &PsbtSighashMsg| {
+            calls.set(calls.get() + 1);
+            let sk = sks[pks.iter().position(|k| k == pk).unwrap()];
+            if *pk == pks[0] {
+                return None;
+            }
+            let secp_msg = msg.to_secp_msg();
+            let options = SigningOptions::default();
+            Some(match *msg {
+                PsbtSighashMsg::EcdsaSighash(_) => Signature::Ecdsa(options.sign_ecdsa(
+                    &secp,
+                    &secp_msg,
+                    &sk,
+                    EcdsaSighashType::All,
+                )),
+                PsbtSighashMsg::TapSighash(_) => Signature::Schnorr(options.sign_schnorr(
+                    &secp,
+                    &secp_msg,
+                    &KeyPair::from_secret_key(&secp, sk),
+                    SchnorrSighashType::Default,
+                )),
+            })
+        };
+        let ecdsa_sighash = |_| Some(PsbtSighashMsg::EcdsaSighash(bitcoin::Sighash::hash(&[1])));
+        let tap_sighash = |_| Some(PsbtSighashMsg::TapSighash(TapSighashHash::hash(&[1])));
+
+        // The cheapest path needs the first key, which cannot sign
+        let desc = Descriptor::<bitcoin::PublicKey>::from_str(&format!(
+            "wsh(or_d(pk({}),pk({})))",
+            pks[0], pks[1]
+        ))
+        .unwrap();
+        let satisfier = SignerSatisfier::new(&signer, ecdsa_sighash);
+        let (witness, script_sig) = satisfier.satisfy_descriptor(&desc, ()).unwrap();
+        assert_eq!(calls.get(), 2);
+        assert!(script_sig.is_empty());
+        assert_eq!(witness.len(), 3);
+        assert!(witness[0].len() >= 70 && witness[1].is_empty());
+        // Signatures are kept, so no further signing is needed
+        assert_eq!(satisfier.satisfy_descriptor(&desc, ()).unwrap().0, witness);
+        assert_eq!(calls.get(), 2);
+
+        // Only as many keys as needed are signed for
+        calls.set(0);
+        let desc = Descriptor::<bitcoin::PublicKey>::from_str(&format!(
+            "wsh(multi(2,{},{},{}))",
+            pks[1], pks[2], pks[3]
+        ))
+        .unwrap();
+        let satisfier = SignerSatisfier::new(&signer, ecdsa_sighash);
+        let (witness, _) = satisfier.satisfy_descriptor(&desc, ()).unwrap();
+        assert_eq!(calls.get(), 2);
+        assert_eq!(witness.len(), 4);
+
+        // No path is left once the signer declines
+        calls.set(0);
+        let desc = Descriptor::<bitcoin::PublicKey>::from_str(&format!(
+            "wsh(and_v(v:pk({}),pk({})))",
+            pks[1], pks[0]
+        ))
+        .unwrap();
+        let satisfier = SignerSatisfier::new(&signer, ecdsa_sighash);
+        assert!(satisfier.satisfy_descriptor(&desc, ()).is_err());
+        assert_eq!(calls.get(), 2);
+
+        // A key spend with an unavailable internal key falls back to a leaf
+        calls.set(0);
+        let desc =
+            Descriptor::<bitcoin::PublicKey>::from_str(&format!("tr({},pk({}))", pks[0], pks[1]))
+                .unwrap();
+        let satisfier = SignerSatisfier::new(&signer, tap_sighash);
+        let (witness, _) = satisfier.satisfy_descriptor(&desc, ()).unwrap();
+        assert_eq!(calls.get(), 2);
+        assert_eq!(witness.len(), 3);
+        assert_eq!(witness[0].len(), 64);
+    }
+
+    // Signs for the keys it holds and records every request
+    struct RecordingSigner {
+        sks: BTreeMap<bitcoin::PublicKey, KeyPair>,
+        requests: RefCell<Vec<(Option<TapLeafHash>, Vec<bitcoin::PublicKey>)>>,
+    }
+
+    impl RecordingSigner {
+        fn sign(&self, pk: &bitcoin::PublicKey) -> Option<bitcoin::SchnorrSig> {
+            let keypair = self.sks.get(pk)?;
+            let msg = Message::from_slice(&[1; 32]).unwrap();
+            Some(SigningOptions::default().sign_schnorr(
+                &Secp256k1::new(),
+                &msg,
+                keypair,
+                SchnorrSighashType::Default,
+            ))
+        }
+    }
+
+    impl TapSigner<bitcoin::PublicKey> for RecordingSigner {
+        fn sign_key_spend(&self, internal_key: &bitcoin::PublicKey) -> Option<bitcoin::SchnorrSig> {
+            self.requests.borrow_mut().push((None, vec![*internal_key]));
+            self.sign(internal_key)
+        }
+
+        fn sign_leaf(
+            &self,
+            leaf_hash: &TapLeafHash,
+            keys: &[bitcoin::PublicKey],
+        ) -> BTreeMap<bitcoin::PublicKey, bitcoin::SchnorrSig> {
+            self.requests
+                .borrow_mut()
+                .push((Some(*leaf_hash), keys.to_vec()));
+            keys.iter()
+                .filter_map(|pk| self.sign(pk).map(|sig| (*pk, sig)))
+                .collect()
+        }
+    }
+
+    #[test]
+    fn tap_signer_satisfier() {
+        let secp = Secp256k1::new();
+        let keypairs: Vec<_> = (1..5u8)
+            .map(|i| KeyPair::from_secret_key(&secp, SecretKey::from_slice(&[i; 32]).unwrap()))
+            .collect();
+        let pks: Vec<_> = keypairs
+            .iter()
+            .map(|kp| bitcoin::PublicKey::new(secp256k1::PublicKey::from_keypair(kp)))
+            .collect();
+        // Holds every key but the internal key and the one of the first leaf
+        let signer = RecordingSigner {
+            sks: pks.iter().cloned().zip(keypairs).skip(2).collect(),
+            requests: RefCell::new(vec![]),
+        };
+        let desc = Descriptor::<bitcoin::PublicKey>::from_str(&format!(
+            "tr({},{{pk({}),and_v(v:pk({}),pk({}))}})",
+            pks[0], pks[1], pks[2], pks[3]
+        ))
+        .unwrap();
+        let leaf_hashes: Vec<_> = match desc {
+            Descriptor::Tr(ref tr) => tr
+                .iter_leaves()
+                .map(|(_, ver, ms)| TapLeafHash::from_script(&ms.encode(), ver))
+                .collect(),
+            _ => unreachable!(),
+        };
+
+        let satisfier = TapSignerSatisfier::new(&signer, &desc);
+        let (witness, script_sig) = desc.get_satisfaction(&satisfier).unwrap();
+        assert!(script_sig.is_empty());
+        // Two signatures, the script and the control block
+        assert_eq!(witness.len(), 4);
+        assert_eq!(witness[0].len(), 64);
+        assert_eq!(witness[1].len(), 64);
+
+        // The key spend once, and each leaf once with all of its keys
+        let requests = signer.requests.borrow().clone();
+        assert_eq!(requests.len(), 3);
+        assert_eq!(requests[0], (None, vec![pks[0]]));
+        for (leaf_hash, keys) in &requests[1..] {
+            if *leaf_hash == Some(leaf_hashes[0]) {
+                assert_eq!(*keys, vec![pks[1]]);
+            } else {
+                assert_eq!(*leaf_hash, Some(leaf_hashes[1]));
+                assert_eq!(*keys, vec![pks[2], pks[3]]);
+            }
+        }
+
+        // Signatures are kept, so the signer is not called again
+        assert_eq!(desc.get_satisfaction(&satisfier).unwrap().0, witness);
+        assert_eq!(signer.requests.borrow().len(), 3);
+
+        // Other descriptors have no taproot signatures
+        let desc =
+            Descriptor::<bitcoin::PublicKey>::from_str(&format!("wsh(pk({}))", pks[2])).unwrap();
+        let satisfier = TapSignerSatisfier::new(&signer, &desc);
+        assert!(desc.get_satisfaction(&satisfier).is_err());
+        assert_eq!(signer.requests.borrow().len(), 3);
+    }
+
+    #[test]
+    fn signing_options() {
+        let secp = Secp256k1::new();
+        let sk = SecretKey::from_slice(&[1; 32]).unwrap();
+        let keypair = KeyPair::from_secret_key(&secp, sk);
+        let pk = secp256k1::PublicKey::from_secret_key(&secp, &sk);
+
+        let high_r = SigningOptions {
+            low_r: false,
+            ..Default::default()
+        };
+        let mut ground = 0;
+        for i in 0..32u8 {
+            let msg = Message::from_slice(&[i; 32]).unwrap();
+            let sig = SigningOptions::default().sign_ecdsa(&secp, &msg, &sk, EcdsaSighashType::All);
+            secp.verify_ecdsa(&msg, &sig.sig, &pk).unwrap();
+            assert!(sig.to_vec().len() <= 71);
+            assert_eq!(sig.sig.serialize_compact()[0] & 0x80, 0);
+            let other = high_r.sign_ecdsa(&secp, &msg, &sk, EcdsaSighashType::All);
+            if other != sig {
+                ground += 1;
+            }
+            assert_eq!(
+                other,
+                high_r.sign_ecdsa(&secp, &msg, &sk, EcdsaSighashType::All)
+            );
+        }
+        // Half of the signatures have a high R without grinding
+        assert!(ground > 0);
+
+        let msg = Message::from_slice(&[1; 32]).unwrap();
+        let no_aux = SigningOptions::default().sign_schnorr(
+            &secp,
+            &msg,
+            &keypair,
+            SchnorrSighashType::Default,
+        );
+        let aux = SigningOptions {
+            aux_rand: Some([7; 32]),
+            ..Default::default()
+        };
+        let sig = aux.sign_schnorr(&secp, &msg, &keypair, SchnorrSighashType::All);
+        assert_ne!(sig.sig, no_aux.sig);
+        assert_eq!(
+            sig,
+            aux.sign_schnorr(&secp, &msg, &keypair, SchnorrSighashType::All)
+        );
+        secp.verify_schnorr(&sig.sig, &msg, &keypair.public_key())
+            .unwrap();
+        assert_eq!(sig.hash_ty, SchnorrSighashType::All);
+    }
+}
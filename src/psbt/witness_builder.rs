@@ -0,0 +1,180 @@
+// Miniscript
+// Written in 2022 by
+//     The rust-miniscript developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Witness Builder
+//!
+//! Manual finalization of a single PSBT input, from signatures and preimages
+//! produced outside of the PSBT or from a complete witness stack.
+
+use bitcoin::hashes::{hash160, ripemd160, sha256, sha256d, Hash};
+use bitcoin::secp256k1::{self, Secp256k1, XOnlyPublicKey};
+use bitcoin::util::sighash::Prevouts;
+use bitcoin::util::taproot::TapLeafHash;
+use bitcoin::{EcdsaSig, PublicKey, SchnorrSig, Script, Witness};
+
+use super::finalizer::{
+    finalize_input_helper, interpreter_inp_check, prevouts, set_final_fields, TapSpendPath,
+};
+use super::{Error, FinalizationContext, Psbt};
+use crate::prelude::*;
+use crate::Preimage32;
+
+/// Builder of the final scriptSig and witness of a psbt input.
+///
+/// The satisfaction is assembled by the finalizer, as in
+/// [`PsbtExt::finalize_inp`](super::PsbtExt::finalize_inp), from the
+/// signatures and preimages of the input along with the ones added to the
+/// builder, such as a signature produced by an external signing service.
+/// Alternatively, the whole witness stack can be given with
+/// [`WitnessBuilder::stack`]. Either way the result is checked with the
+/// miniscript interpreter before it is returned or inserted into the psbt.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WitnessBuilder {
+    index: usize,
+    ecdsa_sigs: BTreeMap<PublicKey, EcdsaSig>,
+    tap_key_sig: Option<SchnorrSig>,
+    tap_script_sigs: BTreeMap<(XOnlyPublicKey, TapLeafHash), SchnorrSig>,
+    preimages: Vec<Preimage32>,
+    stack: Option<(Vec<Vec<u8>>, Script)>,
+    allow_mall: bool,
+}
+
+impl WitnessBuilder {
+    /// Create a builder for the psbt input at `index`
+    pub fn new(index: usize) -> Self {
+        WitnessBuilder {
+            index,
+            ecdsa_sigs: BTreeMap::new(),
+            tap_key_sig: None,
+            tap_script_sigs: BTreeMap::new(),
+            preimages: vec![],
+            stack: None,
+            allow_mall: false,
+        }
+    }
+
+    /// Add an ECDSA signature by `pk`, taking precedence over the one of the
+    /// input for the same key
+    pub fn ecdsa_sig(mut self, pk: PublicKey, sig: EcdsaSig) -> Self {
+        self.ecdsa_sigs.insert(pk, sig);
+        self
+    }
+
+    /// Add a taproot key spend signature, taking precedence over the one of
+    /// the input
+    pub fn tap_key_sig(mut self, sig: SchnorrSig) -> Self {
+        self.tap_key_sig = Some(sig);
+        self
+    }
+
+    /// Add a signature by `pk` for the taproot leaf `leaf_hash`, taking
+    /// precedence over the one of the input for the same key and leaf
+    pub fn tap_script_sig(
+        mut self,
+        pk: XOnlyPublicKey,
+        leaf_hash: TapLeafHash,
+        sig: SchnorrSig,
+    ) -> Self {
+        self.tap_script_sigs.insert((pk, leaf_hash), sig);
+        self
+    }
+
+    /// Add a preimage, usable by any hashlock of one of its four hashes
+    pub fn preimage(mut self, preimage: Preimage32) -> Self {
+        self.preimages.push(preimage);
+        self
+    }
+
+    /// Use `witness` and `script_sig` as they are instead of assembling a
+    /// satisfaction. The signatures and preimages added to the builder are
+    /// then ignored.
+    pub fn stack(mut self, witness: Vec<Vec<u8>>, script_sig: Script) -> Self {
+        self.stack = Some((witness, script_sig));
+        self
+    }
+
+    /// Allow malleable satisfactions, as [`PsbtExt::finalize_inp_mall`]
+    ///
+    /// [`PsbtExt::finalize_inp_mall`]: super::PsbtExt::finalize_inp_mall
+    pub fn allow_malleable(mut self) -> Self {
+        self.allow_mall = true;
+        self
+    }
+
+    /// The final witness and scriptSig of the input, checked by the
+    /// interpreter against the transaction and the utxos of `psbt`
+    pub fn build<C: secp256k1::Verification>(
+        &self,
+        psbt: &Psbt,
+        secp: &Secp256k1<C>,
+    ) -> Result<(Witness, Script), Error> {
+        if self.index >= psbt.inputs.len() {
+            return Err(Error::InputIdxOutofBounds {
+                psbt_inp: psbt.inputs.len(),
+                index: self.index,
+            });
+        }
+        if let Some((ref witness, ref script_sig)) = self.stack {
+            let witness = Witness::from_vec(witness.clone());
+            let utxos = prevouts(psbt)?;
+            let utxos = &Prevouts::All(&utxos);
+            interpreter_inp_check(psbt, secp, self.index, utxos, &witness, script_sig)?;
+            return Ok((witness, script_sig.clone()));
+        }
+
+        let mut psbt = psbt.clone();
+        let input = &mut psbt.inputs[self.index];
+        input.partial_sigs.extend(self.ecdsa_sigs.clone());
+        if self.tap_key_sig.is_some() {
+            input.tap_key_sig = self.tap_key_sig;
+        }
+        input.tap_script_sigs.extend(self.tap_script_sigs.clone());
+        for preimage in &self.preimages {
+            let vec = preimage.to_vec();
+            input
+                .sha256_preimages
+                .insert(sha256::Hash::hash(preimage), vec.clone());
+            input
+                .hash256_preimages
+                .insert(sha256d::Hash::hash(preimage), vec.clone());
+            input
+                .ripemd160_preimages
+                .insert(ripemd160::Hash::hash(preimage), vec.clone());
+            input
+                .hash160_preimages
+                .insert(hash160::Hash::hash(preimage), vec);
+        }
+        finalize_input_helper(
+            &psbt,
+            self.index,
+            secp,
+            self.allow_mall,
+            None,
+            TapSpendPath::Any,
+            &mut FinalizationContext::new(),
+        )
+    }
+
+    /// Build the final witness and scriptSig as [`WitnessBuilder::build`] and
+    /// finalize the input of `psbt` with them
+    pub fn finalize<C: secp256k1::Verification>(
+        &self,
+        psbt: &mut Psbt,
+        secp: &Secp256k1<C>,
+    ) -> Result<(), Error> {
+        let (witness, script_sig) = self.build(psbt, secp)?;
+        set_final_fields(&mut psbt.inputs[self.index], witness, script_sig);
+        Ok(())
+    }
+}
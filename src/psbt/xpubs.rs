@@ -0,0 +1,163 @@
+// Miniscript
+// Written in 2022 by
+//     The rust-miniscript developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Global Xpubs
+//!
+//! Population of the global xpubs of a PSBT from descriptors, and checks that
+//! the key origins of its inputs agree with them.
+
+use core::fmt;
+#[cfg(feature = "std")]
+use std::error;
+
+use bitcoin::secp256k1::{self, Secp256k1};
+use bitcoin::util::bip32::{self, ExtendedPubKey, KeySource};
+
+use super::Psbt;
+use crate::descriptor::SinglePubKey;
+use crate::prelude::*;
+use crate::{Descriptor, DescriptorPublicKey, ForEachKey};
+
+/// Return error type for [`PsbtExt::update_global_xpubs`] and
+/// [`PsbtExt::verify_global_xpubs`]
+///
+/// [`PsbtExt::update_global_xpubs`]: super::PsbtExt::update_global_xpubs
+/// [`PsbtExt::verify_global_xpubs`]: super::PsbtExt::verify_global_xpubs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GlobalXpubError {
+    /// The psbt already has this global xpub, with a different origin than
+    /// the one of the descriptor
+    ConflictingOrigin(ExtendedPubKey),
+    /// The origin of this key of the psbt input at this index is below one
+    /// of the global xpubs, which does not derive the key
+    InconsistentDerivation(usize, SinglePubKey),
+    /// Derivation error
+    Bip32(bip32::Error),
+}
+
+impl fmt::Display for GlobalXpubError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            GlobalXpubError::ConflictingOrigin(ref xpub) => write!(
+                f,
+                "The psbt already has global xpub {} with a different origin",
+                xpub
+            ),
+            GlobalXpubError::InconsistentDerivation(ind, ref key) => {
+                write!(f, "The origin of key ")?;
+                match *key {
+                    SinglePubKey::FullKey(ref pk) => write!(f, "{}", pk)?,
+                    SinglePubKey::XOnly(ref pk) => write!(f, "{}", pk)?,
+                }
+                write!(
+                    f,
+                    " of psbt input {} is inconsistent with the global xpubs",
+                    ind
+                )
+            }
+            GlobalXpubError::Bip32(ref e) => write!(f, "Key derivation error {}", e),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl error::Error for GlobalXpubError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            GlobalXpubError::ConflictingOrigin(_)
+            | GlobalXpubError::InconsistentDerivation(_, _) => None,
+            GlobalXpubError::Bip32(ref e) => Some(e),
+        }
+    }
+}
+
+pub(super) fn update_global_xpubs(
+    psbt: &mut Psbt,
+    descriptor: &Descriptor<DescriptorPublicKey>,
+) -> Result<(), GlobalXpubError> {
+    let mut xpubs = BTreeMap::new();
+    descriptor.for_each_key(|key| {
+        if let DescriptorPublicKey::XPub(ref xkey) = *key.as_key() {
+            // An xpub without origin is its own master key
+            let origin = xkey
+                .origin
+                .clone()
+                .unwrap_or_else(|| (xkey.xkey.fingerprint(), bip32::DerivationPath::master()));
+            xpubs.insert(xkey.xkey, origin);
+        }
+        true
+    });
+
+    for (xpub, origin) in &xpubs {
+        match psbt.xpub.get(xpub) {
+            Some(existing) if existing != origin => {
+                return Err(GlobalXpubError::ConflictingOrigin(*xpub))
+            }
+            _ => {}
+        }
+    }
+    psbt.xpub.extend(xpubs);
+    Ok(())
+}
+
+pub(super) fn verify_global_xpubs<C: secp256k1::Verification>(
+    psbt: &Psbt,
+    secp: &Secp256k1<C>,
+) -> Result<(), GlobalXpubError> {
+    for (index, input) in psbt.inputs.iter().enumerate() {
+        for (pk, source) in &input.bip32_derivation {
+            let key = SinglePubKey::FullKey(bitcoin::PublicKey::new(*pk));
+            if let Some(derived) = derive_from_globals(psbt, secp, source)? {
+                if derived.public_key != *pk {
+                    return Err(GlobalXpubError::InconsistentDerivation(index, key));
+                }
+            }
+        }
+        for (pk, (_, source)) in &input.tap_key_origins {
+            if let Some(derived) = derive_from_globals(psbt, secp, source)? {
+                if derived.to_x_only_pub() != *pk {
+                    let key = SinglePubKey::XOnly(*pk);
+                    return Err(GlobalXpubError::InconsistentDerivation(index, key));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+// The key at `source`, derived from the first global xpub whose origin it is
+// below, if any
+fn derive_from_globals<C: secp256k1::Verification>(
+    psbt: &Psbt,
+    secp: &Secp256k1<C>,
+    source: &KeySource,
+) -> Result<Option<ExtendedPubKey>, GlobalXpubError> {
+    let (fingerprint, ref path) = *source;
+    for (xpub, (xpub_fingerprint, xpub_path)) in &psbt.xpub {
+        if *xpub_fingerprint != fingerprint || !path.as_ref().starts_with(xpub_path.as_ref()) {
+            continue;
+        }
+        let rest = &path.as_ref()[xpub_path.as_ref().len()..];
+        // Hardened steps cannot be derived from an xpub, so the key may be
+        // below another global xpub
+        if rest.iter().any(|child| child.is_hardened()) {
+            continue;
+        }
+        return xpub
+            .derive_pub(secp, &rest)
+            .map(Some)
+            .map_err(GlobalXpubError::Bip32);
+    }
+    Ok(None)
+}
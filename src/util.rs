@@ -13,6 +13,14 @@ pub(crate) fn witness_size(wit: &[Vec<u8>]) -> usize {
     wit.iter().map(Vec::len).sum::<usize>() + varint_len(wit.len())
 }
 
+// Sort the keys of a `sortedmulti_a` by their x-only serialization, as done by
+// Bitcoin Core
+pub(crate) fn sort_xonly_keys<Pk: ToPublicKey>(keys: &[Pk]) -> Vec<Pk> {
+    let mut keys = keys.to_vec();
+    keys.sort_by_key(|pk| pk.to_x_only_pubkey().serialize());
+    keys
+}
+
 pub(crate) fn witness_to_scriptsig(witness: &[Vec<u8>]) -> Script {
     let mut b = script::Builder::new();
     for wit in witness {
@@ -25,6 +33,38 @@ pub(crate) fn witness_to_scriptsig(witness: &[Vec<u8>]) -> Script {
     b.into_script()
 }
 
+// Size of the push of `elem` in the script_sig built by `witness_to_scriptsig`
+pub(crate) fn script_sig_push_len(elem: &[u8]) -> usize {
+    match script::read_scriptint(elem) {
+        // Pushed with a single opcode
+        Ok(n) if n == -1 || (0..=16).contains(&n) => 1,
+        Ok(n) => {
+            // Minimally encoded, with a sign bit
+            let mut abs = if n < 0 { -n } else { n };
+            let mut len = 0;
+            let mut last = 0;
+            while abs > 0 {
+                last = abs & 0xff;
+                abs >>= 8;
+                len += 1;
+            }
+            if last & 0x80 != 0 {
+                len += 1;
+            }
+            1 + len
+        }
+        Err(_) => {
+            let prefix = match elem.len() {
+                n if n < 0x4c => 1,
+                n if n < 0x100 => 2,
+                n if n < 0x10000 => 3,
+                _ => 5,
+            };
+            prefix + elem.len()
+        }
+    }
+}
+
 // trait for pushing key that depend on context
 pub(crate) trait MsKeyBuilder {
     /// Serialize the key as bytes based on script context. Used when encoding miniscript into bitcoin script
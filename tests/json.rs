@@ -0,0 +1,97 @@
+//! # rust-miniscript JSON tests
+//!
+//! Check the JSON of the types with serde support. `serde_json` is only used
+//! here rather than in the library tests: its `PartialEq` impls for integers
+//! would make the empty vectors compared there ambiguous.
+//!
+
+#![cfg(feature = "serde")]
+
+use std::str::FromStr;
+
+use miniscript::descriptor::core_import::{ImportDescriptor, Timestamp};
+use miniscript::{Descriptor, DescriptorPublicKey};
+
+const DESC: &str = "wpkh([d34db33f/84'/0'/0']xpub6ERApfZwUNrhLCkDtcHTcxd75RbzS1ed54G1LkBUHQVHQKqhMkhgbmJbZRkrgZw4koxb5JaHWkY4ALHY2grBGRjaDMzQLcgJvLJuZZvRcEL/0/*)";
+
+#[test]
+fn descriptor_public_key() {
+    let key_str = "[78412e3a/44'/0'/0']xpub6ERApfZwUNrhLCkDtcHTcxd75RbzS1ed54G1LkBUHQVHQKqhMkhgbmJbZRkrgZw4koxb5JaHWkY4ALHY2grBGRjaDMzQLcgJvLJuZZvRcEL/1/*";
+    let key = DescriptorPublicKey::from_str(key_str).unwrap();
+    let json = serde_json::to_string(&key).unwrap();
+    assert_eq!(json, format!("\"{}\"", key_str));
+    assert_eq!(
+        serde_json::from_str::<DescriptorPublicKey>(&json).unwrap(),
+        key
+    );
+    serde_json::from_str::<DescriptorPublicKey>("\"xpub\"").unwrap_err();
+    serde_json::from_str::<DescriptorPublicKey>("42").unwrap_err();
+}
+
+fn request() -> ImportDescriptor {
+    ImportDescriptor::new(Descriptor::from_str(DESC).unwrap(), Timestamp::Now)
+}
+
+#[test]
+fn json_roundtrip() {
+    let mut req = request();
+    req.active = true;
+    req.range = Some((10, 100));
+    req.next_index = Some(20);
+    req.label = Some("savings".to_string());
+    let json = serde_json::to_string(&req).unwrap();
+    assert_eq!(
+        json,
+        format!(
+            "{{\"desc\":\"{}\",\"active\":true,\"range\":[10,100],\"next_index\":20,\"timestamp\":\"now\",\"internal\":false,\"label\":\"savings\"}}",
+            req.descriptor
+        )
+    );
+    assert_eq!(
+        serde_json::from_str::<ImportDescriptor>(&json).unwrap(),
+        req
+    );
+
+    let mut req = request();
+    req.timestamp = Timestamp::Time(1_455_191_478);
+    req.internal = true;
+    let json = serde_json::to_string(&vec![req.clone()]).unwrap();
+    assert_eq!(
+        serde_json::from_str::<Vec<ImportDescriptor>>(&json).unwrap(),
+        vec![req]
+    );
+
+    // Core also accepts the end of the range alone, and unknown fields are
+    // ignored
+    let json = format!(
+        "{{\"desc\":\"{}\",\"range\":5,\"timestamp\":0,\"watchonly\":true}}",
+        DESC
+    );
+    let req = serde_json::from_str::<ImportDescriptor>(&json).unwrap();
+    assert_eq!(req.range, Some((0, 5)));
+    assert_eq!(req.timestamp, Timestamp::Time(0));
+    assert!(!req.active && !req.internal);
+}
+
+#[test]
+fn json_rejected() {
+    let mut req = request();
+    req.internal = true;
+    req.label = Some("change".to_string());
+    serde_json::to_string(&req).unwrap_err();
+
+    let parse = |fields: &str| {
+        serde_json::from_str::<ImportDescriptor>(&format!("{{\"desc\":\"{}\",{}}}", DESC, fields))
+    };
+    parse("\"timestamp\":\"now\"").unwrap();
+    parse("\"timestamp\":\"now\",\"internal\":true,\"label\":\"change\"").unwrap_err();
+    parse("\"active\":true").unwrap_err();
+    parse("\"timestamp\":\"later\"").unwrap_err();
+    parse("\"timestamp\":-1").unwrap_err();
+    parse("\"timestamp\":\"now\",\"range\":[5,1]").unwrap_err();
+    parse("\"timestamp\":\"now\",\"range\":[1,2,3]").unwrap_err();
+    parse("\"timestamp\":\"now\",\"range\":4294967296").unwrap_err();
+    serde_json::from_str::<ImportDescriptor>("{\"timestamp\":\"now\"}").unwrap_err();
+    serde_json::from_str::<ImportDescriptor>("{\"desc\":\"wpkh(xpub)\",\"timestamp\":\"now\"}")
+        .unwrap_err();
+}
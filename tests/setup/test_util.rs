@@ -24,7 +24,9 @@ use bitcoin::hashes::hex::ToHex;
 use bitcoin::hashes::{hash160, ripemd160, sha256, sha256d, Hash};
 use bitcoin::secp256k1;
 use miniscript::descriptor::{SinglePub, SinglePubKey};
-use miniscript::{Descriptor, DescriptorPublicKey, Miniscript, ScriptContext, TranslatePk};
+use miniscript::{
+    hash256, Descriptor, DescriptorPublicKey, Miniscript, ScriptContext, TranslatePk, Translator,
+};
 use rand::RngCore;
 
 #[derive(Clone, Debug)]
@@ -143,6 +145,86 @@ pub fn random_pk(mut seed: u8) -> bitcoin::PublicKey {
     }
 }
 
+#[derive(Debug, Clone)]
+struct StrDescPubKeyTranslator<'a> {
+    pk_index: usize,
+    pkh_index: usize,
+    pubdata: &'a PubData,
+    // Whether keys not starting with K or X are parsed as known full keys
+    // instead of panicking
+    lenient: bool,
+}
+
+impl<'a> StrDescPubKeyTranslator<'a> {
+    fn new(pubdata: &'a PubData, lenient: bool) -> Self {
+        StrDescPubKeyTranslator {
+            pk_index: 0,
+            pkh_index: pubdata.pks.len(),
+            pubdata,
+            lenient,
+        }
+    }
+
+    fn key(&self, pk_str: &str, index: usize) -> DescriptorPublicKey {
+        let key = if pk_str.starts_with("K") {
+            SinglePubKey::FullKey(self.pubdata.pks[index])
+        } else if pk_str.starts_with("X") {
+            SinglePubKey::XOnly(self.pubdata.x_only_pks[index])
+        } else if self.lenient {
+            // Parse any other keys as known to allow compatibility with existing tests
+            SinglePubKey::FullKey(self.pubdata.pks[index])
+        } else {
+            panic!("Key must start with either K or X")
+        };
+        DescriptorPublicKey::Single(SinglePub { origin: None, key })
+    }
+}
+
+impl<'a> Translator<String, DescriptorPublicKey, ()> for StrDescPubKeyTranslator<'a> {
+    fn pk(&mut self, pk_str: &String) -> Result<DescriptorPublicKey, ()> {
+        let avail = !pk_str.ends_with("!");
+        if avail {
+            self.pk_index += 1;
+            Ok(self.key(pk_str, self.pk_index))
+        } else {
+            Ok(DescriptorPublicKey::Single(SinglePub {
+                origin: None,
+                key: SinglePubKey::FullKey(random_pk(59)),
+            }))
+        }
+    }
+
+    fn pkh(&mut self, pkh_str: &String) -> Result<DescriptorPublicKey, ()> {
+        let avail = !pkh_str.ends_with("!");
+        if avail {
+            self.pkh_index -= 1;
+            Ok(self.key(pkh_str, self.pkh_index))
+        } else {
+            let seed = if self.lenient { 59 } else { 61 };
+            Ok(DescriptorPublicKey::Single(SinglePub {
+                origin: None,
+                key: SinglePubKey::FullKey(random_pk(seed)),
+            }))
+        }
+    }
+
+    fn sha256(&mut self, sha256: &String) -> Result<sha256::Hash, ()> {
+        Ok(sha256::Hash::from_str(sha256).expect("hashes are substituted"))
+    }
+
+    fn hash256(&mut self, hash256: &String) -> Result<hash256::Hash, ()> {
+        Ok(hash256::Hash::from_str(hash256).expect("hashes are substituted"))
+    }
+
+    fn ripemd160(&mut self, ripemd160: &String) -> Result<ripemd160::Hash, ()> {
+        Ok(ripemd160::Hash::from_str(ripemd160).expect("hashes are substituted"))
+    }
+
+    fn hash160(&mut self, hash160: &String) -> Result<hash160::Hash, ()> {
+        Ok(hash160::Hash::from_str(hash160).expect("hashes are substituted"))
+    }
+}
+
 #[allow(dead_code)]
 // https://github.com/rust-lang/rust/issues/46379. The code is pub fn and integration test, but still shows warnings
 /// Parse an insane miniscript into a miniscript with the format described above at file header
@@ -153,67 +235,9 @@ pub fn parse_insane_ms<Ctx: ScriptContext>(
     let ms = subs_hash_frag(ms, pubdata);
     let ms =
         Miniscript::<String, Ctx>::from_str_insane(&ms).expect("only parsing valid minsicripts");
-    let mut i = 0;
-    let mut j = pubdata.pks.len();
-    let ms = ms.translate_pk_infallible(
-        &mut |pk_str: &String| {
-            let avail = !pk_str.ends_with("!");
-            if avail {
-                i = i + 1;
-                if pk_str.starts_with("K") {
-                    DescriptorPublicKey::Single(SinglePub {
-                        origin: None,
-                        key: SinglePubKey::FullKey(pubdata.pks[i]),
-                    })
-                } else if pk_str.starts_with("X") {
-                    DescriptorPublicKey::Single(SinglePub {
-                        origin: None,
-                        key: SinglePubKey::XOnly(pubdata.x_only_pks[i]),
-                    })
-                } else {
-                    // Parse any other keys as known to allow compatibility with existing tests
-                    DescriptorPublicKey::Single(SinglePub {
-                        origin: None,
-                        key: SinglePubKey::FullKey(pubdata.pks[i]),
-                    })
-                }
-            } else {
-                DescriptorPublicKey::Single(SinglePub {
-                    origin: None,
-                    key: SinglePubKey::FullKey(random_pk(59)),
-                })
-            }
-        },
-        &mut |pk_str: &String| {
-            let avail = !pk_str.ends_with("!");
-            if avail {
-                j = j - 1;
-                if pk_str.starts_with("K") {
-                    DescriptorPublicKey::Single(SinglePub {
-                        origin: None,
-                        key: SinglePubKey::FullKey(pubdata.pks[j]),
-                    })
-                } else if pk_str.starts_with("X") {
-                    DescriptorPublicKey::Single(SinglePub {
-                        origin: None,
-                        key: SinglePubKey::XOnly(pubdata.x_only_pks[j]),
-                    })
-                } else {
-                    // Parse any other keys as known to allow compatibility with existing tests
-                    DescriptorPublicKey::Single(SinglePub {
-                        origin: None,
-                        key: SinglePubKey::FullKey(pubdata.pks[j]),
-                    })
-                }
-            } else {
-                DescriptorPublicKey::Single(SinglePub {
-                    origin: None,
-                    key: SinglePubKey::FullKey(random_pk(59)),
-                })
-            }
-        },
-    );
-    ms
+    let mut translator = StrDescPubKeyTranslator::new(pubdata, true);
+    ms.translate_pk(&mut translator)
+        .expect("Translation must succeed")
 }
 
 #[allow(dead_code)]
@@ -222,59 +246,9 @@ pub fn parse_test_desc(desc: &str, pubdata: &PubData) -> Descriptor<DescriptorPu
     let desc = subs_hash_frag(desc, pubdata);
     let desc =
         Descriptor::<String>::from_str(&desc).expect("only parsing valid and sane descriptors");
-    let mut i = 0;
-    let mut j = pubdata.pks.len();
-    let desc: Result<_, ()> = desc.translate_pk(
-        &mut |pk_str: &'_ String| {
-            let avail = !pk_str.ends_with("!");
-            if avail {
-                i = i + 1;
-                if pk_str.starts_with("K") {
-                    Ok(DescriptorPublicKey::Single(SinglePub {
-                        origin: None,
-                        key: SinglePubKey::FullKey(pubdata.pks[i]),
-                    }))
-                } else if pk_str.starts_with("X") {
-                    Ok(DescriptorPublicKey::Single(SinglePub {
-                        origin: None,
-                        key: SinglePubKey::XOnly(pubdata.x_only_pks[i]),
-                    }))
-                } else {
-                    panic!("Key must start with either K or X")
-                }
-            } else {
-                Ok(DescriptorPublicKey::Single(SinglePub {
-                    origin: None,
-                    key: SinglePubKey::FullKey(random_pk(59)),
-                }))
-            }
-        },
-        &mut |pkh_str: &'_ String| {
-            let avail = !pkh_str.ends_with("!");
-            if avail {
-                j = j - 1;
-                if pkh_str.starts_with("K") {
-                    Ok(DescriptorPublicKey::Single(SinglePub {
-                        origin: None,
-                        key: SinglePubKey::FullKey(pubdata.pks[j]),
-                    }))
-                } else if pkh_str.starts_with("X") {
-                    Ok(DescriptorPublicKey::Single(SinglePub {
-                        origin: None,
-                        key: SinglePubKey::XOnly(pubdata.x_only_pks[j]),
-                    }))
-                } else {
-                    panic!("Key must start with either K or X")
-                }
-            } else {
-                Ok(DescriptorPublicKey::Single(SinglePub {
-                    origin: None,
-                    key: SinglePubKey::FullKey(random_pk(61)),
-                }))
-            }
-        },
-    );
-    desc.expect("Translate must succeed")
+    let mut translator = StrDescPubKeyTranslator::new(pubdata, false);
+    desc.translate_pk(&mut translator)
+        .expect("Translate must succeed")
 }
 
 // substitute hash fragments in the string as the per rules
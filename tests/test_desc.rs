@@ -229,6 +229,12 @@ pub fn test_desc_satisfy(cl: &Client, testdata: &TestData, desc: &str) -> Witnes
                     miniscript::descriptor::WshInner::Ms(ref ms) => find_sks_ms(&ms, testdata),
                 },
                 Descriptor::Tr(_tr) => unreachable!("Tr checked earlier"),
+                Descriptor::RawTr(_)
+                | Descriptor::Raw(_)
+                | Descriptor::Addr(_)
+                | Descriptor::Wv(_) => {
+                    unreachable!("Only tr descriptors are tested")
+                }
             };
             let msg = psbt
                 .sighash_msg(0, &mut sighash_cache, None)